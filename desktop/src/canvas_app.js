@@ -26962,8 +26962,9 @@ async function createRun() {
   updateEmptyCanvasHint();
   renderFilmstrip();
   chooseSpawnNodes();
-  scheduleVisualPromptWrite({ immediate: true });
+  // The engine starts on the empty run dir before the desktop writes to it.
   await spawnEngine();
+  scheduleVisualPromptWrite({ immediate: true });
   await startEventsPolling();
   if (state.ptySpawned) setStatus("Engine: ready");
   finalizeRunTransition("new", { engineReady: state.ptySpawned });
@@ -27134,6 +27135,17 @@ async function loadExistingArtifacts() {
   return restored;
 }
 
+async function runDirHasEngineState(runDir) {
+  for (const name of ["events.jsonl", "thread.json"]) {
+    try {
+      if (await exists(`${runDir}/${name}`)) return true;
+    } catch (_) {
+      // Treat an unreadable path as missing.
+    }
+  }
+  return false;
+}
+
 async function spawnEngine() {
   if (!state.runDir || !state.eventsPath) return;
   if (state.ptySpawning) return;
@@ -27143,6 +27155,9 @@ async function spawnEngine() {
   const preferredMode = "native";
   const baseEnv = { BROOD_MEMORY: settings.memory ? "1" : "0" };
   const broodArgs = ["chat", "--out", state.runDir, "--events", state.eventsPath];
+  // Reopened runs and engine restarts continue the run already on disk; the
+  // engine refuses a non-empty run dir otherwise.
+  if (await runDirHasEngineState(state.runDir)) broodArgs.push("--resume");
   try {
     let spawned = false;
     let lastErr = null;
@@ -0,0 +1,45 @@
+use std::io::Write;
+
+use clap::CommandFactory;
+pub(crate) use clap_complete::Shell;
+
+use crate::Cli;
+
+const BIN_NAME: &str = "brood-rs";
+
+/// Writes the completion script for `shell`, generated by `clap_complete`
+/// from the CLI definition, so nested subcommands, flags and value enums
+/// are picked up without touching the scripts.
+pub(crate) fn write_completions(shell: Shell, out: &mut dyn Write) -> std::io::Result<()> {
+    clap_complete::generate(shell, &mut Cli::command(), BIN_NAME, out);
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_completions, Shell};
+
+    fn script(shell: Shell) -> String {
+        let mut out = Vec::new();
+        write_completions(shell, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn scripts_cover_nested_subcommands_and_value_enums() {
+        let bash = script(Shell::Bash);
+        assert!(bash.contains("complete -F _brood__rs"));
+        assert!(bash.contains("brood__rs__subcmd__auth__subcmd__set"));
+        assert!(bash.contains("brood__rs__subcmd__queue__subcmd__work"));
+        assert!(bash.contains("compgen -W \"priority deadline fifo\""));
+
+        let zsh = script(Shell::Zsh);
+        assert!(zsh.starts_with("#compdef brood-rs\n"));
+        assert!(zsh.contains("'serve:Serve runs over an HTTP API'"));
+        assert!(zsh.contains("--policy=[Which job runs next]"));
+
+        let fish = script(Shell::Fish);
+        assert!(fish.contains("__fish_brood_rs_using_subcommand pricing"));
+        assert!(fish.contains("-a \"update\""));
+    }
+}
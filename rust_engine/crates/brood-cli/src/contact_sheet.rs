@@ -0,0 +1,377 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+
+use super::gallery::{load_run_versions, RunArtifact};
+
+// A4 portrait in PDF points.
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 36.0;
+const HEADER_HEIGHT: f64 = 28.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 3;
+const GUTTER: f64 = 12.0;
+const CAPTION_LINES: usize = 5;
+const CAPTION_SIZE: f64 = 7.5;
+const CAPTION_LEADING: f64 = 9.5;
+const THUMB_EDGE: u32 = 600;
+
+struct SheetCell<'a> {
+    version_id: &'a str,
+    prompt: &'a str,
+    artifact: &'a RunArtifact,
+}
+
+/// Lays the run's artifacts out as a paginated A4 contact sheet (3x3 per
+/// page) with prompt, model, seed and cost under each image. Returns the
+/// number of artifacts placed.
+pub(crate) fn export_contact_sheet(run_dir: &Path, out_path: &Path) -> Result<usize> {
+    let versions = load_run_versions(run_dir)?;
+    let cells: Vec<SheetCell> = versions
+        .iter()
+        .flat_map(|version| {
+            version.artifacts.iter().map(|artifact| SheetCell {
+                version_id: &version.version_id,
+                prompt: &version.prompt,
+                artifact,
+            })
+        })
+        .collect();
+    if cells.is_empty() {
+        bail!("{} has no artifacts to export", run_dir.display());
+    }
+    let run_label = run_dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("run");
+
+    let mut pdf = PdfWriter::new();
+    let pages_id = pdf.reserve();
+    let font_id = pdf.add(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    let bold_id = pdf.add(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+
+    let cell_width = (PAGE_WIDTH - 2.0 * MARGIN - GUTTER * (COLUMNS as f64 - 1.0)) / COLUMNS as f64;
+    let cell_height =
+        (PAGE_HEIGHT - 2.0 * MARGIN - HEADER_HEIGHT - GUTTER * (ROWS as f64 - 1.0)) / ROWS as f64;
+    let image_box_height = cell_height - CAPTION_LINES as f64 * CAPTION_LEADING - 4.0;
+    let per_page = COLUMNS * ROWS;
+    let page_count = cells.len().div_ceil(per_page);
+
+    let mut page_ids = Vec::new();
+    for (page_idx, page_cells) in cells.chunks(per_page).enumerate() {
+        let mut content = String::new();
+        let header = format!(
+            "Brood contact sheet - {run_label} - page {} of {page_count}",
+            page_idx + 1
+        );
+        push_text(
+            &mut content,
+            "F2",
+            11.0,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN - 12.0,
+            &header,
+        );
+
+        let mut xobjects = String::new();
+        for (slot, cell) in page_cells.iter().enumerate() {
+            let col = slot % COLUMNS;
+            let row = slot / COLUMNS;
+            let left = MARGIN + col as f64 * (cell_width + GUTTER);
+            let top = PAGE_HEIGHT - MARGIN - HEADER_HEIGHT - row as f64 * (cell_height + GUTTER);
+            let image_bottom = top - image_box_height;
+
+            match jpeg_thumbnail(&cell.artifact.image_path) {
+                Some((jpeg, width, height)) => {
+                    let image_id = pdf.add(image_object(&jpeg, width, height));
+                    let name = format!("Im{slot}");
+                    let _ = write!(xobjects, "/{name} {image_id} 0 R ");
+                    let scale = (cell_width / width as f64).min(image_box_height / height as f64);
+                    let (draw_w, draw_h) = (width as f64 * scale, height as f64 * scale);
+                    let x = left + (cell_width - draw_w) / 2.0;
+                    let y = image_bottom + (image_box_height - draw_h) / 2.0;
+                    let _ = writeln!(
+                        content,
+                        "q {draw_w:.2} 0 0 {draw_h:.2} {x:.2} {y:.2} cm /{name} Do Q"
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        content,
+                        "q 0.85 g {left:.2} {image_bottom:.2} {cell_width:.2} {image_box_height:.2} re f Q"
+                    );
+                    push_text(
+                        &mut content,
+                        "F1",
+                        CAPTION_SIZE,
+                        left + 4.0,
+                        image_bottom + image_box_height / 2.0,
+                        "no preview",
+                    );
+                }
+            }
+
+            let max_chars = (cell_width / (CAPTION_SIZE * 0.5)) as usize;
+            let mut lines = vec![(
+                "F2",
+                format!(
+                    "{}{}  ({})",
+                    cell.artifact.artifact_id,
+                    if cell.artifact.selected { " *" } else { "" },
+                    cell.version_id
+                ),
+            )];
+            for line in wrap_text(cell.prompt, max_chars, 2) {
+                lines.push(("F1", line));
+            }
+            let mut model_line = cell
+                .artifact
+                .model
+                .clone()
+                .unwrap_or_else(|| "model unknown".to_string());
+            if let Some(seed) = cell.artifact.seed {
+                let _ = write!(model_line, "  seed {seed}");
+            }
+            lines.push(("F1", model_line));
+            lines.push((
+                "F1",
+                cell.artifact
+                    .cost_usd
+                    .map(|cost| format!("${cost:.4}"))
+                    .unwrap_or_else(|| "cost n/a".to_string()),
+            ));
+            for (idx, (font, text)) in lines.iter().enumerate().take(CAPTION_LINES) {
+                let text: String = text.chars().take(max_chars).collect();
+                push_text(
+                    &mut content,
+                    font,
+                    CAPTION_SIZE,
+                    left,
+                    image_bottom - CAPTION_LEADING * (idx as f64 + 1.0),
+                    &text,
+                );
+            }
+        }
+
+        let content_id = pdf.add(stream_object("", content.as_bytes()));
+        let page_id = pdf.add(
+            format!(
+                "<< /Type /Page /Parent {pages_id} 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                 /Resources << /Font << /F1 {font_id} 0 R /F2 {bold_id} 0 R >> /XObject << {xobjects}>> >> \
+                 /Contents {content_id} 0 R >>"
+            )
+            .into_bytes(),
+        );
+        page_ids.push(page_id);
+    }
+
+    let kids = page_ids
+        .iter()
+        .map(|id| format!("{id} 0 R"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    pdf.set(
+        pages_id,
+        format!(
+            "<< /Type /Pages /Kids [{kids}] /Count {} >>",
+            page_ids.len()
+        )
+        .into_bytes(),
+    );
+    let catalog_id = pdf.add(format!("<< /Type /Catalog /Pages {pages_id} 0 R >>").into_bytes());
+
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out_path, pdf.finish(catalog_id))
+        .with_context(|| format!("failed to write {}", out_path.display()))?;
+    Ok(cells.len())
+}
+
+/// Minimal PDF object table: ids are 1-based positions, reserved slots are
+/// filled with [`PdfWriter::set`] before [`PdfWriter::finish`].
+struct PdfWriter {
+    objects: Vec<Vec<u8>>,
+}
+
+impl PdfWriter {
+    fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+        }
+    }
+
+    fn reserve(&mut self) -> usize {
+        self.add(Vec::new())
+    }
+
+    fn add(&mut self, body: Vec<u8>) -> usize {
+        self.objects.push(body);
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, body: Vec<u8>) {
+        self.objects[id - 1] = body;
+    }
+
+    fn finish(self, root_id: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (idx, body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", idx + 1).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref_offset = out.len();
+        let mut xref = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(xref, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            xref,
+            "trailer\n<< /Size {} /Root {root_id} 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+            self.objects.len() + 1
+        );
+        out.extend_from_slice(xref.as_bytes());
+        out
+    }
+}
+
+fn stream_object(dict_extra: &str, data: &[u8]) -> Vec<u8> {
+    let mut out = format!("<< {dict_extra}/Length {} >>\nstream\n", data.len()).into_bytes();
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\nendstream");
+    out
+}
+
+fn image_object(jpeg: &[u8], width: u32, height: u32) -> Vec<u8> {
+    stream_object(
+        &format!(
+            "/Type /XObject /Subtype /Image /Width {width} /Height {height} \
+             /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode "
+        ),
+        jpeg,
+    )
+}
+
+fn jpeg_thumbnail(path: &Path) -> Option<(Vec<u8>, u32, u32)> {
+    let image = image::open(path).ok()?;
+    let thumb = image.thumbnail(THUMB_EDGE, THUMB_EDGE).to_rgb8();
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(Cursor::new(&mut bytes), 85)
+        .encode_image(&thumb)
+        .ok()?;
+    Some((bytes, thumb.width(), thumb.height()))
+}
+
+fn push_text(content: &mut String, font: &str, size: f64, x: f64, y: f64, text: &str) {
+    let _ = writeln!(
+        content,
+        "BT /{font} {size} Tf {x:.2} {y:.2} Td ({}) Tj ET",
+        pdf_string(text)
+    );
+}
+
+/// Escapes a PDF literal string; characters outside printable ASCII become
+/// `?` since the standard fonts are used without embedding.
+fn pdf_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '\\' | '(' | ')' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            ' '..='~' => out.push(ch),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+fn wrap_text(text: &str, max_chars: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut current));
+            if lines.len() == max_lines {
+                break;
+            }
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if lines.len() < max_lines && !current.is_empty() {
+        lines.push(current);
+    } else if let Some(last) = lines.last_mut() {
+        let keep = max_chars.saturating_sub(3);
+        *last = format!("{}...", last.chars().take(keep).collect::<String>());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use brood_engine::NativeEngine;
+    use serde_json::{json, Map};
+
+    use super::{export_contact_sheet, pdf_string, wrap_text};
+
+    #[test]
+    fn contact_sheet_paginates_artifacts_into_valid_pdf() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("64x48"));
+        settings.insert("n".to_string(), json!(5));
+        engine.generate("harbor (dawn)", settings.clone(), Map::new())?;
+        engine.generate("harbor at noon", settings, Map::new())?;
+
+        let out_path = temp.path().join("sheet.pdf");
+        assert_eq!(export_contact_sheet(&run_dir, &out_path)?, 10);
+        let bytes = std::fs::read(&out_path)?;
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Type /Pages /Kids [") && text.contains("/Count 2"));
+        assert!(text.contains("(harbor \\(dawn\\))"));
+        assert!(text.contains("dryrun-image-1"));
+        assert_eq!(text.matches("/Subtype /Image").count(), 10);
+
+        let startxref = text.rfind("startxref\n").expect("startxref");
+        let offset: usize = text[startxref + 10..]
+            .lines()
+            .next()
+            .unwrap_or("")
+            .parse()?;
+        assert!(bytes[offset..].starts_with(b"xref"));
+        Ok(())
+    }
+
+    #[test]
+    fn caption_helpers_escape_and_truncate() {
+        assert_eq!(pdf_string("a(b)\\ é"), "a\\(b\\)\\\\ ?");
+        let lines = wrap_text("one two three four five six", 9, 2);
+        assert_eq!(lines, vec!["one two", "three..."]);
+    }
+}
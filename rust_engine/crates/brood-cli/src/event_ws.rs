@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use brood_contracts::events::EventSink;
+use serde_json::Value;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use super::ws_connect::websocket_connect;
+
+/// Events waiting for the socket; the oldest are dropped past this.
+const WS_EVENT_BUFFER: usize = 1024;
+const WS_BACKOFF_INITIAL: Duration = Duration::from_millis(500);
+const WS_BACKOFF_MAX: Duration = Duration::from_secs(30);
+/// How long dropping the sink waits for queued events to go out.
+const WS_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+/// A stalled endpoint counts as disconnected after this long.
+const WS_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type EventSocket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Mirrors events to a WebSocket endpoint (`BROOD_EVENT_WS_URL`) as one text
+/// message per event.
+///
+/// `send` never blocks the engine: events are queued for a worker thread
+/// that owns the connection, reconnecting with exponential backoff. While
+/// disconnected the newest [`WS_EVENT_BUFFER`] events are kept and replayed.
+pub(crate) struct WebSocketEventSink {
+    queue: Mutex<Option<SyncSender<String>>>,
+    done: Mutex<Receiver<()>>,
+}
+
+impl WebSocketEventSink {
+    pub(crate) fn new(url: &str) -> Self {
+        let (queue, events) = mpsc::sync_channel(WS_EVENT_BUFFER);
+        let (finished, done) = mpsc::channel();
+        let url = url.to_string();
+        thread::spawn(move || {
+            forward_events(&url, &events);
+            let _ = finished.send(());
+        });
+        Self {
+            queue: Mutex::new(Some(queue)),
+            done: Mutex::new(done),
+        }
+    }
+}
+
+impl EventSink for WebSocketEventSink {
+    fn send(&self, event: &Value) -> Result<()> {
+        let queue = self
+            .queue
+            .lock()
+            .map_err(|_| anyhow::anyhow!("websocket sink lock poisoned"))?;
+        if let Some(queue) = queue.as_ref() {
+            match queue.try_send(serde_json::to_string(event)?) {
+                Ok(()) | Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => {
+                    anyhow::bail!("websocket event sink stopped")
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WebSocketEventSink {
+    fn drop(&mut self) {
+        // Closing the queue lets the worker flush and exit; wait briefly so
+        // the final events of a short CLI run are not lost.
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.take();
+        }
+        if let Ok(done) = self.done.lock() {
+            let _ = done.recv_timeout(WS_FLUSH_TIMEOUT);
+        }
+    }
+}
+
+fn forward_events(url: &str, events: &Receiver<String>) {
+    let mut pending: VecDeque<String> = VecDeque::new();
+    let mut socket: Option<EventSocket> = None;
+    let mut backoff = WS_BACKOFF_INITIAL;
+    let mut retry_at = Instant::now();
+    let mut closing = false;
+    loop {
+        if !closing {
+            let wait = if pending.is_empty() || socket.is_some() {
+                WS_BACKOFF_MAX
+            } else {
+                retry_at.saturating_duration_since(Instant::now())
+            };
+            match events.recv_timeout(wait) {
+                Ok(event) => push_bounded(&mut pending, event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => closing = true,
+            }
+            while let Ok(event) = events.try_recv() {
+                push_bounded(&mut pending, event);
+            }
+        }
+        if pending.is_empty() {
+            if closing {
+                break;
+            }
+            continue;
+        }
+        if socket.is_none() {
+            if Instant::now() < retry_at {
+                if closing {
+                    // No connection to flush to; give up on the remainder.
+                    break;
+                }
+                continue;
+            }
+            match websocket_connect(url) {
+                Ok((mut connected, _)) => {
+                    set_write_timeout(&mut connected, WS_WRITE_TIMEOUT);
+                    socket = Some(connected);
+                    backoff = WS_BACKOFF_INITIAL;
+                }
+                Err(err) => {
+                    eprintln!("brood-rs: event websocket {url} unavailable ({err}); retrying");
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(WS_BACKOFF_MAX);
+                    continue;
+                }
+            }
+        }
+        if let Some(active) = socket.as_mut() {
+            while let Some(event) = pending.front() {
+                if active.send(WsMessage::text(event.clone())).is_err() {
+                    socket = None;
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(WS_BACKOFF_MAX);
+                    break;
+                }
+                pending.pop_front();
+            }
+        }
+    }
+    if let Some(mut active) = socket {
+        let _ = active.close(None);
+        let _ = active.flush();
+    }
+}
+
+fn set_write_timeout(socket: &mut EventSocket, timeout: Duration) {
+    match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => {
+            let _ = stream.set_write_timeout(Some(timeout));
+        }
+        MaybeTlsStream::Rustls(stream) => {
+            let _ = stream.get_mut().set_write_timeout(Some(timeout));
+        }
+        _ => {}
+    }
+}
+
+fn push_bounded(pending: &mut VecDeque<String>, event: String) {
+    if pending.len() >= WS_EVENT_BUFFER {
+        pending.pop_front();
+    }
+    pending.push_back(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use brood_contracts::event_schema::{ArtifactCreatedEvent, RunStartedEvent};
+    use brood_contracts::events::{EventFilter, EventWriter};
+    use serde_json::{json, Map, Value};
+    use tungstenite::{accept, Message};
+
+    use super::WebSocketEventSink;
+
+    #[test]
+    fn mirrors_events_and_reconnects_after_a_drop() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("ws://{}", listener.local_addr()?);
+        let (received, messages) = mpsc::channel();
+        thread::spawn(move || {
+            // First connection is dropped after one event to force a reconnect.
+            for limit in [1, usize::MAX] {
+                let Ok((stream, _)) = listener.accept() else {
+                    return;
+                };
+                let Ok(mut socket) = accept(stream) else {
+                    return;
+                };
+                let mut seen = 0;
+                while seen < limit {
+                    match socket.read() {
+                        Ok(Message::Text(text)) => {
+                            let _ = received.send(text.to_string());
+                            seen += 1;
+                        }
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+
+        let temp = tempfile::tempdir()?;
+        let writer = EventWriter::new(temp.path().join("events.jsonl"), "run-ws");
+        writer.add_sink(
+            Box::new(WebSocketEventSink::new(&url)),
+            EventFilter::default(),
+        )?;
+        writer.emit_typed(&RunStartedEvent {
+            out_dir: temp.path().display().to_string(),
+        })?;
+        let first: Value = serde_json::from_str(&messages.recv_timeout(Duration::from_secs(10))?)?;
+        assert_eq!(first["type"], json!("run_started"));
+        assert_eq!(first["run_id"], json!("run-ws"));
+
+        // The server has hung up; keep emitting until the sink reconnects.
+        let artifact = ArtifactCreatedEvent {
+            version_id: "v1".to_string(),
+            artifact_id: "a1".to_string(),
+            image_path: "/tmp/a1.png".to_string(),
+            receipt_path: None,
+            thumbnail_path: None,
+            metrics: Map::new(),
+            warnings: Vec::new(),
+            warning_details: Vec::new(),
+        };
+        let mut second = None;
+        for _ in 0..40 {
+            writer.emit_typed(&artifact)?;
+            if let Ok(raw) = messages.recv_timeout(Duration::from_millis(250)) {
+                second = Some(serde_json::from_str::<Value>(&raw)?);
+                break;
+            }
+        }
+        assert_eq!(
+            second.map(|event| event["type"].clone()),
+            Some(json!("artifact_created"))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use brood_contracts::runs::thread_manifest::{artifact_tags, ThreadManifest};
+use image::codecs::jpeg::JpegEncoder;
+use serde_json::{Map, Value};
+
+use super::escape_html;
+
+const THUMB_EDGE: u32 = 360;
+
+/// One artifact with the annotations shared by the gallery and contact-sheet
+/// exporters. Model/provider/seed come from the receipt when it is readable.
+#[derive(Debug, Clone)]
+pub(crate) struct RunArtifact {
+    pub artifact_id: String,
+    pub image_path: PathBuf,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub seed: Option<i64>,
+    pub cost_usd: Option<f64>,
+    pub latency_s: Option<f64>,
+    pub selected: bool,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RunVersion {
+    pub version_id: String,
+    pub parent_version_id: Option<String>,
+    pub prompt: String,
+    pub settings: Map<String, Value>,
+    pub artifacts: Vec<RunArtifact>,
+}
+
+/// Live (not soft-deleted) versions of a run, in thread order.
+pub(crate) fn load_run_versions(run_dir: &Path) -> Result<Vec<RunVersion>> {
+    let thread_path = run_dir.join("thread.json");
+    if !thread_path.is_file() {
+        anyhow::bail!("{} has no thread.json", run_dir.display());
+    }
+    let thread = ThreadManifest::load(&thread_path)?;
+    Ok(thread
+        .live_versions()
+        .map(|version| RunVersion {
+            version_id: version.version_id.clone(),
+            parent_version_id: version.parent_version_id.clone(),
+            prompt: version.prompt.clone(),
+            settings: version.settings.clone(),
+            artifacts: version
+                .artifacts
+                .iter()
+                .map(|artifact| {
+                    let artifact_id = str_field(artifact, "artifact_id").unwrap_or_default();
+                    let receipt = str_field(artifact, "receipt_path")
+                        .and_then(|path| fs::read_to_string(path).ok())
+                        .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+                        .unwrap_or(Value::Null);
+                    let resolved = receipt.get("resolved").unwrap_or(&Value::Null);
+                    let metrics = artifact.get("metrics").unwrap_or(&Value::Null);
+                    RunArtifact {
+                        selected: version.selected_artifact_id.as_deref()
+                            == Some(artifact_id.as_str()),
+                        image_path: PathBuf::from(
+                            str_field(artifact, "image_path").unwrap_or_default(),
+                        ),
+                        provider: resolved
+                            .get("provider")
+                            .and_then(Value::as_str)
+                            .map(str::to_string),
+                        model: resolved
+                            .get("model")
+                            .and_then(Value::as_str)
+                            .map(str::to_string),
+                        seed: resolved.get("seed").and_then(Value::as_i64),
+                        cost_usd: metrics.get("cost_total_usd").and_then(Value::as_f64),
+                        latency_s: metrics.get("latency_per_image_s").and_then(Value::as_f64),
+                        tags: artifact_tags(artifact),
+                        artifact_id,
+                    }
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Writes a single self-contained HTML file: embedded JPEG thumbnails,
+/// prompts, settings, cost metrics and the version tree. Returns the number
+/// of artifacts rendered.
+pub(crate) fn export_gallery(run_dir: &Path, out_path: &Path) -> Result<usize> {
+    let versions = load_run_versions(run_dir)?;
+    let artifact_count: usize = versions.iter().map(|version| version.artifacts.len()).sum();
+    let total_cost: f64 = versions
+        .iter()
+        .flat_map(|version| &version.artifacts)
+        .filter_map(|artifact| artifact.cost_usd)
+        .sum();
+
+    let mut sections = String::new();
+    for version in &versions {
+        let _ = write!(
+            sections,
+            "<section id='{id}'><h2>{id}{parent}</h2><p class='prompt'>{prompt}</p>",
+            id = escape_html(&version.version_id),
+            parent = version
+                .parent_version_id
+                .as_deref()
+                .map(|parent| format!(
+                    " <span class='parent'>from <a href='#{0}'>{0}</a></span>",
+                    escape_html(parent)
+                ))
+                .unwrap_or_default(),
+            prompt = escape_html(&version.prompt),
+        );
+        if !version.settings.is_empty() {
+            let settings = serde_json::to_string_pretty(&version.settings).unwrap_or_default();
+            let _ = write!(
+                sections,
+                "<details><summary>settings</summary><pre>{}</pre></details>",
+                escape_html(&settings)
+            );
+        }
+        sections.push_str("<div class='grid'>");
+        for artifact in &version.artifacts {
+            let thumb = thumbnail_data_uri(&artifact.image_path)
+                .map(|uri| {
+                    format!(
+                        "<img src='{uri}' alt='{}'>",
+                        escape_html(&artifact.artifact_id)
+                    )
+                })
+                .unwrap_or_else(|| "<span class='missing'>no preview</span>".to_string());
+            let mut meta = Vec::new();
+            match (&artifact.provider, &artifact.model) {
+                (Some(provider), Some(model)) => {
+                    meta.push(escape_html(&format!("{provider}/{model}")))
+                }
+                (None, Some(label)) | (Some(label), None) => meta.push(escape_html(label)),
+                (None, None) => {}
+            }
+            if let Some(seed) = artifact.seed {
+                meta.push(format!("seed {seed}"));
+            }
+            if let Some(cost) = artifact.cost_usd {
+                meta.push(format!("${cost:.4}"));
+            }
+            if let Some(latency) = artifact.latency_s {
+                meta.push(format!("{latency:.1}s"));
+            }
+            if !artifact.tags.is_empty() {
+                meta.push(format!(
+                    "<span class='tags'>#{}</span>",
+                    escape_html(&artifact.tags.join(" #"))
+                ));
+            }
+            let _ = write!(
+                sections,
+                "<figure class='card{winner}'><div class='thumb'>{thumb}</div><figcaption><b>{id}</b>{badge}<br>{meta}</figcaption></figure>",
+                winner = if artifact.selected { " winner" } else { "" },
+                id = escape_html(&artifact.artifact_id),
+                badge = if artifact.selected { " &#9733;" } else { "" },
+                meta = meta.join(" &middot; "),
+            );
+        }
+        sections.push_str("</div></section>\n");
+    }
+
+    let html_doc = format!(
+        "<!doctype html>\n<html>\n<head>\n<meta charset='utf-8'>\n<title>Brood Gallery</title>\n<style>\n\
+body {{ font-family: Arial, sans-serif; background: #f6f6f6; margin: 0; padding: 20px; color: #222; }}\n\
+header {{ margin-bottom: 16px; }}\n\
+nav ul {{ list-style: none; padding-left: 16px; margin: 4px 0; }}\n\
+nav > ul {{ padding-left: 0; }}\n\
+nav a {{ color: #0066cc; text-decoration: none; }}\n\
+section {{ background: white; border-radius: 10px; padding: 12px 16px; margin: 16px 0; box-shadow: 0 2px 8px rgba(0,0,0,0.08); }}\n\
+h2 {{ font-size: 16px; margin: 0 0 6px; }}\n\
+.parent {{ font-weight: normal; font-size: 12px; color: #666; }}\n\
+.prompt {{ font-size: 13px; margin: 4px 0 8px; }}\n\
+pre {{ font-size: 11px; background: #f3f3f3; padding: 8px; overflow-x: auto; }}\n\
+.grid {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(220px, 1fr)); gap: 12px; }}\n\
+.card {{ margin: 0; border: 1px solid #e4e4e4; border-radius: 8px; overflow: hidden; }}\n\
+.card.winner {{ border-color: #d4a106; }}\n\
+.thumb {{ height: 200px; background: #eee; display: flex; align-items: center; justify-content: center; }}\n\
+.thumb img {{ max-width: 100%; max-height: 100%; }}\n\
+figcaption {{ font-size: 12px; padding: 6px 8px; color: #444; }}\n\
+.missing {{ font-size: 12px; color: #999; }}\n\
+.tags {{ color: #0066cc; }}\n\
+</style>\n</head>\n<body>\n<header><h1>Brood Run Gallery</h1><p>{version_count} versions &middot; {artifact_count} artifacts &middot; ${total_cost:.4} estimated</p>\n<nav>{tree}</nav></header>\n{sections}</body>\n</html>\n",
+        version_count = versions.len(),
+        tree = render_version_tree(&versions),
+    );
+    if let Some(parent) = out_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(out_path, html_doc)
+        .with_context(|| format!("failed to write {}", out_path.display()))?;
+    Ok(artifact_count)
+}
+
+/// Nested list of versions by `parent_version_id`; versions whose parent is
+/// missing (or deleted) are shown as roots.
+fn render_version_tree(versions: &[RunVersion]) -> String {
+    let known: Vec<&str> = versions
+        .iter()
+        .map(|version| version.version_id.as_str())
+        .collect();
+    let mut children: BTreeMap<Option<&str>, Vec<&RunVersion>> = BTreeMap::new();
+    for version in versions {
+        let parent = version
+            .parent_version_id
+            .as_deref()
+            .filter(|parent| known.contains(parent));
+        children.entry(parent).or_default().push(version);
+    }
+    fn render(
+        parent: Option<&str>,
+        children: &BTreeMap<Option<&str>, Vec<&RunVersion>>,
+        out: &mut String,
+    ) {
+        let Some(rows) = children.get(&parent) else {
+            return;
+        };
+        out.push_str("<ul>");
+        for version in rows {
+            let prompt: String = version.prompt.chars().take(60).collect();
+            let _ = write!(
+                out,
+                "<li><a href='#{id}'>{id}</a> {prompt}",
+                id = escape_html(&version.version_id),
+                prompt = escape_html(&prompt),
+            );
+            render(Some(version.version_id.as_str()), children, out);
+            out.push_str("</li>");
+        }
+        out.push_str("</ul>");
+    }
+    let mut out = String::new();
+    render(None, &children, &mut out);
+    out
+}
+
+fn thumbnail_data_uri(path: &Path) -> Option<String> {
+    let image = image::open(path).ok()?;
+    let thumb = image.thumbnail(THUMB_EDGE, THUMB_EDGE).to_rgb8();
+    let mut bytes = Vec::new();
+    JpegEncoder::new_with_quality(Cursor::new(&mut bytes), 80)
+        .encode_image(&thumb)
+        .ok()?;
+    Some(format!("data:image/jpeg;base64,{}", BASE64.encode(bytes)))
+}
+
+fn str_field(map: &Map<String, Value>, key: &str) -> Option<String> {
+    map.get(key).and_then(Value::as_str).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use brood_engine::NativeEngine;
+    use serde_json::{json, Map};
+
+    use super::export_gallery;
+
+    #[test]
+    fn gallery_embeds_thumbnails_and_version_tree() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("64x64"));
+        engine.generate("red <kite>", settings.clone(), Map::new())?;
+        let mut intent = Map::new();
+        intent.insert("parent_version_id".to_string(), json!("v1"));
+        engine.generate("red kite at dusk", settings, intent)?;
+        let (_, tags) = engine.tag_artifact(None, &["Hero".to_string()])?;
+        assert_eq!(tags, vec!["hero"]);
+
+        let out_path = temp.path().join("gallery.html");
+        assert_eq!(export_gallery(&run_dir, &out_path)?, 2);
+        let html = std::fs::read_to_string(&out_path)?;
+        assert!(html.contains("data:image/jpeg;base64,"));
+        assert!(html.contains("red &lt;kite&gt;"));
+        assert!(html.contains("<li><a href='#v1'>v1</a> red &lt;kite&gt;<ul><li><a href='#v2'>"));
+        assert!(html.contains("dryrun-image-1"));
+        assert!(html.contains("<span class='tags'>#hero</span>"));
+        assert!(!html.contains(&run_dir.to_string_lossy().to_string()));
+        Ok(())
+    }
+}
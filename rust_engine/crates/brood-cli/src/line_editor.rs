@@ -0,0 +1,181 @@
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+
+use rustyline::config::Config;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+/// Entries kept in memory and in the history file.
+const HISTORY_LIMIT: usize = 1000;
+const CONTINUATION_PROMPT: &str = ". ";
+
+/// Chat input through rustyline: arrow keys and Emacs bindings move and
+/// edit, Up/Down walk the history, Ctrl-R searches it. A trailing `\`
+/// continues the entry on the next line. Without a terminal rustyline reads
+/// plain lines.
+pub(crate) struct LineEditor {
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// `history_path` is loaded now and appended to as entries are read.
+    pub(crate) fn open(history_path: Option<PathBuf>) -> io::Result<Self> {
+        let config = Config::builder()
+            .max_history_size(HISTORY_LIMIT)
+            .map_err(readline_io_error)?
+            .history_ignore_dups(true)
+            .map_err(readline_io_error)?
+            .auto_add_history(false)
+            .build();
+        let mut editor = DefaultEditor::with_config(config).map_err(readline_io_error)?;
+        if let Some(path) = history_path.as_ref().filter(|path| path.is_file()) {
+            if let Err(err) = editor.load_history(path) {
+                eprintln!(
+                    "brood-rs: chat history {} not readable ({err}); starting without it",
+                    path.display()
+                );
+            }
+        }
+        Ok(Self {
+            editor,
+            history_path,
+        })
+    }
+
+    /// Next complete entry, with continuation lines joined by `\n`. `None`
+    /// at end of input; Ctrl-C yields an empty entry.
+    pub(crate) fn read_entry(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let editor = &mut self.editor;
+        let entry = read_continued(prompt, |prompt| editor.readline(prompt))?;
+        if let Some(entry) = entry.as_deref() {
+            self.add_history(entry);
+        }
+        Ok(entry)
+    }
+
+    fn add_history(&mut self, entry: &str) {
+        if entry.trim().is_empty() {
+            return;
+        }
+        if !matches!(self.editor.add_history_entry(entry), Ok(true)) {
+            return;
+        }
+        let Some(path) = self.history_path.as_ref() else {
+            return;
+        };
+        if let Err(err) = self.editor.append_history(path) {
+            eprintln!(
+                "brood-rs: chat history {} not writable ({err}); history is kept in memory only",
+                path.display()
+            );
+            self.history_path = None;
+        }
+    }
+}
+
+/// Reads lines through `read_line` until one does not end in `\`.
+fn read_continued(
+    prompt: &str,
+    mut read_line: impl FnMut(&str) -> Result<String, ReadlineError>,
+) -> io::Result<Option<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    loop {
+        let prompt = if lines.is_empty() {
+            prompt
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match read_line(prompt) {
+            Ok(line) => match line.strip_suffix('\\') {
+                Some(head) => lines.push(head.to_string()),
+                None => {
+                    lines.push(line);
+                    break;
+                }
+            },
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) if lines.is_empty() => return Ok(None),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(readline_io_error(err)),
+        }
+    }
+    Ok(Some(lines.join("\n")))
+}
+
+fn readline_io_error(err: ReadlineError) -> io::Error {
+    match err {
+        ReadlineError::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// A secret typed without echo when stdin is a terminal, else the first
+/// line of stdin, so it never lands in shell history.
+pub(crate) fn read_secret(prompt: &str) -> io::Result<String> {
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        let mut line = String::new();
+        stdin.lock().read_line(&mut line)?;
+        return Ok(line.trim().to_string());
+    }
+    Ok(rpassword::prompt_password(prompt)?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use rustyline::error::ReadlineError;
+
+    use super::{read_continued, LineEditor};
+
+    fn read(script: Vec<Result<&str, ReadlineError>>) -> Option<String> {
+        let mut script = script.into_iter();
+        let mut prompts = Vec::new();
+        let entry = read_continued("> ", |prompt| {
+            prompts.push(prompt.to_string());
+            script
+                .next()
+                .unwrap_or(Err(ReadlineError::Eof))
+                .map(str::to_string)
+        })
+        .unwrap();
+        assert!(prompts.iter().skip(1).all(|prompt| prompt == ". "));
+        entry
+    }
+
+    #[test]
+    fn joins_continuation_lines() {
+        assert_eq!(
+            read(vec![Ok("a red chair")]).as_deref(),
+            Some("a red chair")
+        );
+        assert_eq!(
+            read(vec![Ok("a red chair\\"), Ok("by a window")]).as_deref(),
+            Some("a red chair\nby a window")
+        );
+        assert_eq!(
+            read(vec![Ok("half\\"), Err(ReadlineError::Eof)]).as_deref(),
+            Some("half")
+        );
+        assert_eq!(
+            read(vec![Ok("half\\"), Err(ReadlineError::Interrupted)]).as_deref(),
+            Some("")
+        );
+        assert_eq!(read(vec![Err(ReadlineError::Eof)]), None);
+    }
+
+    #[test]
+    fn history_persists_in_the_run_dir() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("chat_history.txt");
+        let mut editor = LineEditor::open(Some(path.clone()))?;
+        editor.add_history("first");
+        editor.add_history("first");
+        editor.add_history("two\nlines");
+        editor.add_history("  ");
+        let reloaded = LineEditor::open(Some(path))?;
+        let entries: Vec<&String> = reloaded.editor.history().iter().collect();
+        assert_eq!(entries, ["first", "two\nlines"]);
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::hash::{Hash, Hasher};
-use std::io::{self, ErrorKind, Write};
+use std::io::{self, ErrorKind};
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -10,23 +10,57 @@ use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+mod completions;
+mod contact_sheet;
+mod event_ws;
+mod gallery;
+mod line_editor;
+mod metrics;
+mod notify;
+mod serve;
+mod worker_pool;
+mod ws_connect;
+
 use anyhow::{bail, Context, Result};
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine as _;
-use brood_contracts::chat::{parse_intent, CHAT_HELP_COMMANDS};
-use brood_contracts::events::EventWriter;
-use brood_engine::NativeEngine;
-use clap::{Parser, Subcommand};
+use brood_contracts::chat::{command_palette, parse_intent, ChatCommandHelp};
+use brood_contracts::event_schema::event_schema_bundle;
+use brood_contracts::events::{EventFilter, EventWriter, JsonLineSink};
+use brood_contracts::prompt_template::parse_variable_assignment;
+use brood_contracts::runs::gc::{collect_garbage, RetentionPolicy};
+use brood_contracts::runs::layout::{migrate_to_per_version, receipt_files, RunLayout};
+use brood_contracts::runs::lock::{force_unlock as force_unlock_run_dir, RunLock};
+use brood_contracts::runs::migrate::migrate_run_dir;
+use brood_contracts::runs::receipt_diff::{diff_receipts, load_receipt, ReceiptDiff};
+use brood_contracts::runs::run_dir::{create_unique_run_dir, prepare_run_dir, RunDirReuse};
+use brood_contracts::runs::session::SessionState;
+use brood_contracts::runs::thread_manifest::ThreadManifest;
+use brood_contracts::runs::verify::verify_run;
+use brood_engine::{
+    api_key_source, artifact_store_from_env, artifact_store_from_url, check_http_config,
+    deferred_artifact_store_from_env, http_client_builder, install_tracing_from_env,
+    load_batch_manifest, parse_deadline, parse_ledger_date, remote_pricing_path,
+    remove_keychain_key, run_batch, store_keychain_key, summarize_costs, sync_run, update_pricing,
+    verify_api_key, BatchConfig, BatchRow, CostBudget, CostGroupBy, CostLedger, CostReportRow,
+    CredentialsProvider, EditRegion, GlobalCache, Hooks, JobQueue, JobStatus, KeyringCredentials,
+    NativeEngine, PlanPreview, PricingSource, PromptVariant, ProviderConfig, QueueJob, QueuePolicy,
+    RecreateOptions, SettingsPreset, StyleProfile, ARTIFACT_STORE_ENV, HOOKS_ENV,
+    JOB_QUEUE_DIR_ENV, KEYRING_ENV, PALETTE_DEFAULT_COLORS, PRICING_PUBLIC_KEY_ENV,
+    PRICING_URL_ENV,
+};
+use clap::{Parser, Subcommand, ValueEnum};
 use image::codecs::jpeg::JpegEncoder;
 use image::imageops::FilterType;
 use image::{DynamicImage, Rgba, RgbaImage};
-use reqwest::blocking::Client as HttpClient;
+use line_editor::LineEditor;
 use reqwest::header::CONTENT_TYPE;
 use serde_json::{json, Map, Value};
 use tungstenite::client::IntoClientRequest;
 use tungstenite::http::{HeaderValue, Request};
 use tungstenite::stream::MaybeTlsStream;
-use tungstenite::{connect as websocket_connect, Message as WsMessage, WebSocket};
+use tungstenite::{Message as WsMessage, WebSocket};
+use ws_connect::websocket_connect;
 
 #[derive(Debug, Parser)]
 #[command(name = "brood-rs", version, about = "Brood Rust CLI scaffold")]
@@ -37,50 +71,182 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum Command {
+    /// Interactive chat session (or `--exec` lines) against a run.
     Chat(ChatArgs),
+    /// Generate images for a single prompt.
     Run(RunArgs),
+    /// Recreate a reference image.
     Recreate(RecreateArgs),
+    /// Export a run as HTML, a gallery or a PDF.
     Export(ExportArgs),
+    /// Generate every prompt in a JSONL manifest.
+    Batch(BatchArgs),
+    /// Queue generations with priorities and deadlines, and work through
+    /// them across restarts.
+    Queue(QueueArgs),
+    /// Check a run dir's receipts and artifacts.
+    Verify(VerifyArgs),
+    /// Explain why two outputs differ by diffing their receipts' requests.
+    DiffReceipts(DiffReceiptsArgs),
+    /// Re-run the exact request recorded in a receipt.
+    Reproduce(ReproduceArgs),
+    /// A/B prompt variants within one run.
+    Experiment(ExperimentArgs),
+    /// Delete old artifacts from run dirs.
+    Gc(GcArgs),
+    /// Report spend across runs from the cost ledger.
+    Costs(CostsArgs),
+    /// Manage the pricing tables used for cost estimates.
+    Pricing(PricingArgs),
+    /// Store, list, check and remove provider keys in the OS keychain.
+    Auth(AuthArgs),
+    /// Upgrade a run dir to the current layout.
+    Migrate(MigrateArgs),
+    /// Upload a run's artifacts and receipts to the artifact store.
+    Sync(SyncArgs),
+    /// Serve runs over an HTTP API.
+    Serve(ServeArgs),
+    /// Print a shell completion script.
+    Completions(CompletionsArgs),
+    /// Print the JSON Schema bundle for `events.jsonl`.
+    EventSchema,
+}
+
+#[derive(Debug, Parser)]
+struct CompletionsArgs {
+    #[arg(value_enum)]
+    shell: completions::Shell,
 }
 
 #[derive(Debug, Parser)]
 struct ChatArgs {
+    #[arg(
+        long,
+        required_unless_present = "out_root",
+        conflicts_with = "out_root"
+    )]
+    out: Option<PathBuf>,
+    /// Create a fresh timestamped run dir under this root instead of `--out`.
     #[arg(long)]
-    out: PathBuf,
+    out_root: Option<PathBuf>,
+    /// Continue an existing run dir under its run id, recovering state left
+    /// by a crash.
+    #[arg(long)]
+    resume: bool,
+    /// Reuse a non-empty run dir even if it does not look like a run.
+    #[arg(long)]
+    force: bool,
+    /// Remove the run dir's lock before opening it. Only for when the
+    /// process named in the "in use" error is known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
     #[arg(long)]
     events: Option<PathBuf>,
     #[arg(long, default_value = "gpt-5.2")]
     text_model: String,
     #[arg(long)]
     image_model: Option<String>,
+    /// Mirror events to stderr as JSON lines, filtered by a spec such as
+    /// `exclude=context_*;sample=progress:10` (empty string for everything).
+    #[arg(long)]
+    events_stderr: Option<String>,
+    /// Run this chat line (e.g. `/generate a red chair --n 2`) instead of
+    /// reading stdin, then exit. Repeatable; lines run in order.
+    #[arg(long = "exec", value_name = "COMMAND")]
+    exec: Vec<String>,
+    /// Fail instead of snapping or guessing settings: seeds are always set,
+    /// model versions pinned, and `reproducibility.json` lists providers
+    /// that cannot promise identical output.
+    #[arg(long)]
+    deterministic: bool,
 }
 
 #[derive(Debug, Parser)]
 struct RunArgs {
     #[arg(long)]
     prompt: String,
+    #[arg(
+        long,
+        required_unless_present = "out_root",
+        conflicts_with = "out_root"
+    )]
+    out: Option<PathBuf>,
+    /// Create a fresh timestamped run dir under this root instead of `--out`.
     #[arg(long)]
-    out: PathBuf,
+    out_root: Option<PathBuf>,
+    /// Continue an existing run dir under its run id, recovering state left
+    /// by a crash.
+    #[arg(long)]
+    resume: bool,
+    /// Reuse a non-empty run dir even if it does not look like a run.
+    #[arg(long)]
+    force: bool,
+    /// Remove the run dir's lock before opening it. Only for when the
+    /// process named in the "in use" error is known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
     #[arg(long)]
     events: Option<PathBuf>,
     #[arg(long, default_value = "gpt-5.2")]
     text_model: String,
     #[arg(long)]
     image_model: Option<String>,
+    /// Mirror events to stderr as JSON lines, filtered by a spec such as
+    /// `exclude=context_*;sample=progress:10` (empty string for everything).
+    #[arg(long)]
+    events_stderr: Option<String>,
+    /// Template variable for `{{name}}` placeholders in the prompt, as
+    /// `name=value` or `name=a,b` (one version per combination). Repeatable.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    vars: Vec<String>,
+    /// Rewrite the prompt with the text model before generating.
+    #[arg(long)]
+    enhance_prompt: bool,
+    /// Fail instead of snapping or guessing settings: seeds are always set,
+    /// model versions pinned, and `reproducibility.json` lists providers
+    /// that cannot promise identical output.
+    #[arg(long)]
+    deterministic: bool,
+    /// Apply a preset saved with `/preset save` (size, n, image model,
+    /// quality preset, provider options). `--image-model` still wins.
+    #[arg(long)]
+    preset: Option<String>,
 }
 
 #[derive(Debug, Parser)]
 struct RecreateArgs {
     #[arg(long)]
     reference: PathBuf,
+    #[arg(
+        long,
+        required_unless_present = "out_root",
+        conflicts_with = "out_root"
+    )]
+    out: Option<PathBuf>,
+    /// Create a fresh timestamped run dir under this root instead of `--out`.
     #[arg(long)]
-    out: PathBuf,
+    out_root: Option<PathBuf>,
+    /// Continue an existing run dir under its run id, recovering state left
+    /// by a crash.
+    #[arg(long)]
+    resume: bool,
+    /// Reuse a non-empty run dir even if it does not look like a run.
+    #[arg(long)]
+    force: bool,
+    /// Remove the run dir's lock before opening it. Only for when the
+    /// process named in the "in use" error is known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
     #[arg(long)]
     events: Option<PathBuf>,
     #[arg(long, default_value = "gpt-5.2")]
     text_model: String,
     #[arg(long)]
     image_model: Option<String>,
+    /// Mirror events to stderr as JSON lines, filtered by a spec such as
+    /// `exclude=context_*;sample=progress:10` (empty string for everything).
+    #[arg(long)]
+    events_stderr: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -89,12 +255,344 @@ struct ExportArgs {
     run: PathBuf,
     #[arg(long)]
     out: PathBuf,
+    /// `html` (plain report linking run files), `gallery` (single
+    /// self-contained HTML file with thumbnails, settings, costs and the
+    /// version tree) or `pdf` (paginated contact sheet).
+    #[arg(long, default_value = "html")]
+    format: String,
+    /// Instead of a report, write the rendition set (`social` or `web`) of
+    /// the run's winner into `--out`: one smart-cropped image and receipt
+    /// per target size.
+    #[arg(long)]
+    renditions: Option<String>,
+    /// Artifact to render instead of the winner (with `--renditions`).
+    #[arg(long, requires = "renditions")]
+    artifact: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct BatchArgs {
+    /// JSONL file with one `{"prompt": ..., ...settings}` object per line.
+    #[arg(long)]
+    manifest: PathBuf,
+    #[arg(long)]
+    out: PathBuf,
+    #[arg(long, default_value_t = 2)]
+    concurrency: usize,
+    /// Stop starting new rows once this much (USD) has been spent.
+    #[arg(long)]
+    budget: Option<f64>,
+    #[arg(long, default_value = "gpt-5.2")]
+    text_model: String,
+    #[arg(long)]
+    image_model: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct QueueArgs {
+    /// Queue dir instead of `BROOD_QUEUE_DIR` / `~/.brood/queue`.
+    #[arg(long, global = true)]
+    queue: Option<PathBuf>,
+    #[command(subcommand)]
+    action: QueueAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum QueueAction {
+    /// Add a prompt, or every prompt in a JSONL manifest.
+    Add(QueueAddArgs),
+    /// List jobs with their status.
+    List(QueueListArgs),
+    /// Cancel a job that has not started.
+    Cancel(QueueJobArgs),
+    /// Queue a failed, cancelled or expired job again.
+    Retry(QueueJobArgs),
+    /// Run queued jobs one at a time until none is left.
+    Work(QueueWorkArgs),
+}
+
+#[derive(Debug, Parser)]
+struct QueueAddArgs {
+    #[arg(
+        long,
+        required_unless_present = "manifest",
+        conflicts_with = "manifest"
+    )]
+    prompt: Option<String>,
+    /// JSONL file in the `batch` format. Rows may set their own `priority`
+    /// and `deadline`.
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Higher runs first.
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    priority: i64,
+    /// RFC 3339 time or delay (`30m`, `2h`, `1d`) after which the job expires
+    /// instead of starting.
+    #[arg(long)]
+    deadline: Option<String>,
+    #[arg(long)]
+    image_model: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct QueueListArgs {
+    /// Only jobs with this status.
+    #[arg(long, value_enum)]
+    status: Option<JobStatus>,
+    /// Print the job records as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Parser)]
+struct QueueJobArgs {
+    job_id: String,
+}
+
+#[derive(Debug, Parser)]
+struct QueueWorkArgs {
+    /// Which job runs next.
+    #[arg(long, value_enum, default_value_t = QueuePolicy::Priority)]
+    policy: QueuePolicy,
+    /// Keep waiting for new jobs once the queue is empty.
+    #[arg(long)]
+    follow: bool,
+    #[arg(long, default_value = "gpt-5.2")]
+    text_model: String,
+    #[arg(long)]
+    image_model: Option<String>,
+    /// Take over the queue from a worker known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
+}
+
+#[derive(Debug, Parser)]
+struct VerifyArgs {
+    /// Run dir whose receipts and artifacts should be checked.
+    #[arg(long)]
+    run: PathBuf,
+}
+
+#[derive(Debug, Parser)]
+struct DiffReceiptsArgs {
+    left: PathBuf,
+    right: PathBuf,
+    /// Print the diff as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Parser)]
+struct ReproduceArgs {
+    /// Image receipt whose provider request should be sent again.
+    #[arg(long)]
+    receipt: PathBuf,
+    /// Run dir to add the reproduction to; defaults to the receipt's run dir.
+    #[arg(long)]
+    out: Option<PathBuf>,
+    /// Remove the run dir's lock before opening it. Only for when the
+    /// process named in the "in use" error is known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
+}
+
+#[derive(Debug, Parser)]
+struct GcArgs {
+    /// Directory holding run dirs (or a single run dir).
+    #[arg(long)]
+    runs_dir: PathBuf,
+    /// Keep artifacts written within this many days.
+    #[arg(long, default_value_t = 30)]
+    keep_days: u64,
+    /// Keep selected and winner-rated artifacts regardless of age.
+    #[arg(long)]
+    keep_winners: bool,
+    /// Report what would be removed and how many bytes it frees.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+struct CostsArgs {
+    /// Only spend on or after this UTC date (YYYY-MM-DD).
+    #[arg(long)]
+    since: Option<String>,
+    /// Group spend by provider, model or day.
+    #[arg(long, value_enum, default_value_t = CostGroupBy::Provider)]
+    group_by: CostGroupBy,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = CostReportFormat::Table)]
+    format: CostReportFormat,
+    /// Ledger to read instead of `BROOD_COST_LEDGER` / `~/.brood/cost_ledger.jsonl`.
+    #[arg(long)]
+    ledger: Option<PathBuf>,
+}
+
+/// `table` for people, `csv` for spreadsheets, `json` for scripts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum CostReportFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Parser)]
+struct PricingArgs {
+    #[command(subcommand)]
+    action: PricingAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum PricingAction {
+    /// Fetch, verify and cache the signed remote pricing manifest.
+    Update(PricingUpdateArgs),
+}
+
+#[derive(Debug, Parser)]
+struct PricingUpdateArgs {
+    /// Manifest URL instead of `BROOD_PRICING_URL`.
+    #[arg(long)]
+    url: Option<String>,
+    /// Ed25519 public key (hex or base64) instead of `BROOD_PRICING_PUBLIC_KEY`.
+    #[arg(long)]
+    public_key: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct AuthArgs {
+    #[command(subcommand)]
+    action: AuthAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum AuthAction {
+    /// Store a provider's key, typed at a hidden prompt or piped on stdin.
+    Set(AuthProviderArgs),
+    /// List providers and where each one's key comes from.
+    List,
+    /// Check a provider's key with one read-only API call.
+    Test(AuthProviderArgs),
+    /// Remove a provider's key from the keychain.
+    Remove(AuthProviderArgs),
+}
+
+#[derive(Debug, Parser)]
+struct AuthProviderArgs {
+    /// Provider name, e.g. `openai`, `flux` or a custom endpoint.
+    provider: String,
+}
+
+#[derive(Debug, Parser)]
+struct MigrateArgs {
+    /// Run dir whose thread, cache, summary and receipt files are upgraded.
+    #[arg(long)]
+    run: PathBuf,
+    /// List the files that would be upgraded without rewriting them.
+    #[arg(long)]
+    dry_run: bool,
+    /// `per-version` also moves a flat run's artifacts and receipts into
+    /// `v-NNNN/` subdirectories.
+    #[arg(long, value_enum)]
+    layout: Option<RunLayout>,
+}
+
+#[derive(Debug, Parser)]
+struct SyncArgs {
+    /// Run dir to upload.
+    #[arg(long)]
+    run: PathBuf,
+    /// `s3://bucket/prefix`, `gs://bucket/prefix` or `file:///dir`;
+    /// defaults to `BROOD_ARTIFACT_STORE`.
+    #[arg(long)]
+    store: Option<String>,
+    /// Upload artifacts again even if their receipts record an upload to
+    /// this store.
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Parser)]
+struct ServeArgs {
+    /// Address for the HTTP API, e.g. `127.0.0.1:8787`.
+    #[arg(long, default_value = "127.0.0.1:8787")]
+    http: String,
+    /// Directory new runs are created under; existing run dirs in it can be
+    /// addressed by name.
+    #[arg(long, default_value = "runs")]
+    runs_dir: PathBuf,
+    #[arg(long, default_value = "gpt-5.2")]
+    text_model: String,
+    #[arg(long)]
+    image_model: Option<String>,
+    /// URL this server is reachable at from the internet. When set, queued
+    /// Fal requests register a webhook under it.
+    #[arg(long)]
+    public_url: Option<String>,
+    /// Generations run at once, each on its own engine. Jobs beyond that
+    /// queue, taking turns across clients.
+    #[arg(long, default_value_t = 4)]
+    workers: usize,
+    /// Bearer token API requests must send. Falls back to
+    /// `BROOD_SERVE_TOKEN`, else one is generated and printed at startup.
+    #[arg(long)]
+    token: Option<String>,
+    /// JSON file mapping client names to their own bearer tokens, e.g.
+    /// `{"studio": "..."}`. Generations sent with a client's token are
+    /// queued fairly and limited as that client's.
+    #[arg(long, value_name = "FILE")]
+    client_tokens: Option<PathBuf>,
+    /// Generations one client may have queued or running (default 64);
+    /// more get a 429.
+    #[arg(long)]
+    queue_limit: Option<usize>,
+    /// Remove the locks of the run dirs under `--runs-dir` before serving.
+    /// Only for when the processes that held them are known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
+}
+
+#[derive(Debug, Parser)]
+struct ExperimentArgs {
+    /// Prompt variant as `LABEL=PROMPT`. Repeat for each arm of the A/B.
+    #[arg(long = "variant", value_name = "LABEL=PROMPT", required = true)]
+    variants: Vec<String>,
+    #[arg(
+        long,
+        required_unless_present = "out_root",
+        conflicts_with = "out_root"
+    )]
+    out: Option<PathBuf>,
+    /// Create a fresh timestamped run dir under this root instead of `--out`.
+    #[arg(long)]
+    out_root: Option<PathBuf>,
+    /// Continue an existing run dir under its run id, recovering state left
+    /// by a crash.
+    #[arg(long)]
+    resume: bool,
+    /// Reuse a non-empty run dir even if it does not look like a run.
+    #[arg(long)]
+    force: bool,
+    /// Remove the run dir's lock before opening it. Only for when the
+    /// process named in the "in use" error is known to be gone.
+    #[arg(long)]
+    force_unlock: bool,
+    #[arg(long)]
+    events: Option<PathBuf>,
+    #[arg(long, default_value = "gpt-5.2")]
+    text_model: String,
+    #[arg(long)]
+    image_model: Option<String>,
+    /// Images per variant.
+    #[arg(long, default_value_t = 1)]
+    n: u64,
 }
 
 const REALTIME_DESCRIPTION_MAX_CHARS: usize = 40;
 const OPENAI_VISION_FALLBACK_MODEL: &str = "gpt-5.2";
 const OPENAI_VISION_SECONDARY_MODEL: &str = "gpt-5-nano";
 const OPENROUTER_OPENAI_VISION_FALLBACK_MODEL: &str = "openai/gpt-5.2";
+/// How often `queue work --follow` looks for new jobs once the queue is empty.
+const QUEUE_FOLLOW_POLL: Duration = Duration::from_secs(2);
 
 fn main() {
     match run() {
@@ -108,36 +606,91 @@ fn main() {
 
 fn run() -> Result<i32> {
     let cli = Cli::parse();
+    // Held until run returns: main exits the process, which skips drops.
+    let _telemetry = install_tracing_from_env()?;
     match cli.command {
-        Command::Chat(args) => {
-            run_chat_native(args)?;
-            Ok(0)
-        }
+        Command::Chat(args) => run_chat_native(args),
         Command::Run(args) => run_run_native(args),
         Command::Recreate(args) => run_recreate_native(args),
         Command::Export(args) => run_export_native(args),
+        Command::Batch(args) => run_batch_native(args),
+        Command::Queue(args) => run_queue_native(args),
+        Command::Verify(args) => run_verify_native(args),
+        Command::DiffReceipts(args) => run_diff_receipts_native(args),
+        Command::Reproduce(args) => run_reproduce_native(args),
+        Command::Experiment(args) => run_experiment_native(args),
+        Command::Gc(args) => run_gc_native(args),
+        Command::Costs(args) => run_costs_native(args),
+        Command::Pricing(args) => match args.action {
+            PricingAction::Update(args) => run_pricing_update_native(args),
+        },
+        Command::Auth(args) => run_auth_native(args.action),
+        Command::Migrate(args) => run_migrate_native(args),
+        Command::Sync(args) => run_sync_native(args),
+        Command::Serve(args) => run_serve_native(args),
+        Command::Completions(args) => {
+            completions::write_completions(args.shell, &mut io::stdout())?;
+            Ok(0)
+        }
+        Command::EventSchema => {
+            println!("{}", serde_json::to_string_pretty(&event_schema_bundle())?);
+            Ok(0)
+        }
     }
 }
 
-fn run_chat_native(args: ChatArgs) -> Result<()> {
-    let run_out_dir = args.out.clone();
+/// Interactive chat over stdin, or with `--exec` the given lines only;
+/// exits 1 when an `--exec` line fails to generate or is not a command.
+fn run_chat_native(args: ChatArgs) -> Result<i32> {
+    let run_out_dir = resolve_run_dir(
+        args.out.as_deref(),
+        args.out_root.as_deref(),
+        "chat",
+        RunDirReuse::from_flags(args.resume, args.force),
+    )?;
     let events_path = args
         .events
         .clone()
-        .unwrap_or_else(|| args.out.join("events.jsonl"));
-    let mut engine = NativeEngine::new(
-        &args.out,
+        .unwrap_or_else(|| run_out_dir.join("events.jsonl"));
+    let mut engine = open_engine(
+        &run_out_dir,
         &events_path,
         Some(args.text_model.clone()),
         args.image_model.clone(),
+        args.resume,
+        args.force_unlock,
     )?;
-
-    let stdin = io::stdin();
+    attach_event_sinks(&engine, args.events_stderr.as_deref())?;
+    apply_cost_budget_env(&mut engine)?;
+    engine.set_global_cache(global_cache_from_env());
+    engine.set_upscale_provider(first_non_empty_env(&["BROOD_UPSCALE_PROVIDER"]));
+    engine.set_video_provider(first_non_empty_env(&["BROOD_VIDEO_PROVIDER"]));
+
+    let mut saved_memory = if args.resume {
+        ChatMemory::restore(&engine.session_state())
+    } else {
+        ChatMemory::default()
+    };
+    if args.resume && saved_memory != ChatMemory::default() {
+        println!("{}", saved_memory.describe());
+    }
     let mut line = String::new();
-    let mut profile = "default".to_string();
-    let mut quality_preset = "quality".to_string();
-    let mut last_prompt: Option<String> = None;
-    let mut last_artifact_path: Option<String> = None;
+    let mut profile = saved_memory.profile.clone();
+    if profile != "default" {
+        println!("{}", load_style_profile(&mut engine, &profile));
+    }
+    let mut quality_preset = saved_memory.quality_preset.clone();
+    let mut template_variables: Map<String, Value> = Map::new();
+    // `/palette lock` pins the last extracted palette; pinned colors go into
+    // `settings.palette` for every generation.
+    let mut extracted_palette: Vec<String> = Vec::new();
+    let mut pinned_palette: Vec<String> = Vec::new();
+    // Settings recalled by `/preset use`, and the last generation's flags so
+    // `/preset save` snapshots what was actually used.
+    let mut preset_settings: Map<String, Value> = Map::new();
+    let mut last_settings_update: Map<String, Value> = Map::new();
+    let mut last_prompt = saved_memory.last_prompt.clone();
+    let mut last_artifact_path = saved_memory.active_image.clone();
     let shared_events = engine.event_writer();
     let mut canvas_context_rt: Option<CanvasContextRealtimeSession> = None;
     let mut intent_rt: Option<IntentIconsRealtimeSession> = None;
@@ -145,20 +698,36 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
     let canvas_rt_source = || canvas_context_realtime_provider().as_str().to_string();
     let intent_rt_source = |mother: bool| intent_realtime_provider(mother).as_str().to_string();
 
-    println!("Brood chat started. Type /help for commands.");
+    let mut exec_lines = args.exec.iter();
+    let interactive = args.exec.is_empty();
+    let mut exec_failures = 0usize;
+    let mut editor = interactive
+        .then(|| LineEditor::open(Some(run_out_dir.join("chat_history.txt"))))
+        .transpose()?;
+    if interactive {
+        println!("Brood chat started. Type /help for commands.");
+    }
 
     loop {
-        print!("> ");
-        io::stdout().flush()?;
-
+        ChatMemory {
+            profile: profile.clone(),
+            quality_preset: quality_preset.clone(),
+            last_prompt: last_prompt.clone(),
+            active_image: last_artifact_path.clone(),
+        }
+        .save_if_changed(&engine, &mut saved_memory)?;
         line.clear();
-        let read = match stdin.read_line(&mut line) {
-            Ok(read) => read,
-            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
-            Err(err) => return Err(err.into()),
-        };
-        if read == 0 {
-            break;
+        if let Some(editor) = editor.as_mut() {
+            let Some(entry) = editor.read_entry("> ")? else {
+                break;
+            };
+            line.push_str(&entry);
+        } else {
+            let Some(command) = exec_lines.next() else {
+                break;
+            };
+            println!("> {command}");
+            line.push_str(command);
         }
 
         let input = line.trim_end_matches(['\n', '\r']);
@@ -169,12 +738,20 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
 
         match intent.action.as_str() {
             "help" => {
-                println!("Commands: {}", CHAT_HELP_COMMANDS.join(" "));
+                let query =
+                    value_as_non_empty_string(intent.command_args.get("query")).unwrap_or_default();
+                let matches = command_palette(&query);
+                if matches.is_empty() {
+                    println!("No commands match {query:?}");
+                } else {
+                    print!("{}", format_command_palette(&matches));
+                }
             }
             "set_profile" => {
                 profile = value_as_non_empty_string(intent.command_args.get("profile"))
                     .unwrap_or_else(|| "default".to_string());
                 println!("Profile set to {profile}");
+                println!("{}", load_style_profile(&mut engine, &profile));
             }
             "set_text_model" => {
                 let current = engine.text_model().unwrap_or("gpt-5.2").to_string();
@@ -567,6 +1144,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     plan.cached,
                     request.source_images.len()
                 );
+                print_plan_limits(&plan);
 
                 let (artifacts, error_message) =
                     match engine.generate(&request.prompt, request.settings, request.intent) {
@@ -636,7 +1214,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                             "source": canvas_context_rt
                                 .as_ref()
                                 .map(|session| session.source().to_string())
-                                .unwrap_or_else(|| canvas_rt_source()),
+                                .unwrap_or_else(&canvas_rt_source),
                             "model": model,
                             "fatal": true,
                         })),
@@ -662,7 +1240,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                             "source": canvas_context_rt
                                 .as_ref()
                                 .map(|session| session.source().to_string())
-                                .unwrap_or_else(|| canvas_rt_source()),
+                                .unwrap_or_else(&canvas_rt_source),
                             "model": model,
                             "fatal": true,
                         })),
@@ -1001,6 +1579,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     "Plan: {} images via {}:{} size={} cached={}",
                     plan.images, plan.provider, plan.model, plan.size, plan.cached
                 );
+                print_plan_limits(&plan);
                 let (artifacts, error_message) =
                     match engine.generate(prompt, settings, generation_intent) {
                         Ok(artifacts) => (artifacts, None),
@@ -1058,6 +1637,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     "Plan: {} images via {}:{} size={} cached={}",
                     plan.images, plan.provider, plan.model, plan.size, plan.cached
                 );
+                print_plan_limits(&plan);
                 let (artifacts, error_message) =
                     match engine.generate(prompt, settings, generation_intent) {
                         Ok(artifacts) => (artifacts, None),
@@ -1172,6 +1752,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     "Plan: {} images via {}:{} size={} cached={}",
                     plan.images, plan.provider, plan.model, plan.size, plan.cached
                 );
+                print_plan_limits(&plan);
                 let (artifacts, error_message) =
                     match engine.generate(prompt, settings, generation_intent) {
                         Ok(artifacts) => (artifacts, None),
@@ -1230,6 +1811,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     "Plan: {} images via {}:{} size={} cached={}",
                     plan.images, plan.provider, plan.model, plan.size, plan.cached
                 );
+                print_plan_limits(&plan);
                 let (artifacts, error_message) =
                     match engine.generate(prompt, settings, generation_intent) {
                         Ok(artifacts) => (artifacts, None),
@@ -1298,6 +1880,7 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
                     "Plan: {} images via {}:{} size={} cached={}",
                     plan.images, plan.provider, plan.model, plan.size, plan.cached
                 );
+                print_plan_limits(&plan);
                 let (artifacts, error_message) =
                     match engine.generate(prompt, settings, generation_intent) {
                         Ok(artifacts) => (artifacts, None),
@@ -1678,7 +2261,53 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
             "export" => {
                 let format = value_as_non_empty_string(intent.command_args.get("format"))
                     .unwrap_or_else(|| "html".to_string());
-                if format.to_ascii_lowercase() != "html" {
+                if format == "files" {
+                    let selector = value_as_non_empty_string(intent.command_args.get("selector"))
+                        .unwrap_or_else(|| "all".to_string());
+                    let profile = value_as_non_empty_string(intent.command_args.get("profile"))
+                        .unwrap_or_else(|| "web".to_string());
+                    match engine.export_selection(&selector, &profile) {
+                        Ok(files) => {
+                            println!("Exported {} artifact(s) ({profile}):", files.len());
+                            for file in files {
+                                println!(
+                                    "  {} {}x{} {} KB",
+                                    file.path.display(),
+                                    file.width,
+                                    file.height,
+                                    file.bytes.div_ceil(1024)
+                                );
+                            }
+                        }
+                        Err(err) => println!("Export failed: {err}"),
+                    }
+                    continue;
+                }
+                if format == "gallery" {
+                    let out_path =
+                        run_out_dir.join(format!("gallery-{}.html", compact_timestamp()));
+                    match gallery::export_gallery(&run_out_dir, &out_path) {
+                        Ok(count) => println!(
+                            "Exported gallery ({count} artifacts) to {}",
+                            out_path.display()
+                        ),
+                        Err(err) => println!("Export failed: {err}"),
+                    }
+                    continue;
+                }
+                if format == "pdf" {
+                    let out_path =
+                        run_out_dir.join(format!("contact-sheet-{}.pdf", compact_timestamp()));
+                    match contact_sheet::export_contact_sheet(&run_out_dir, &out_path) {
+                        Ok(count) => println!(
+                            "Exported contact sheet ({count} artifacts) to {}",
+                            out_path.display()
+                        ),
+                        Err(err) => println!("Export failed: {err}"),
+                    }
+                    continue;
+                }
+                if !format.eq_ignore_ascii_case("html") {
                     println!("Export format '{format}' is not supported in native mode.");
                     continue;
                 }
@@ -1854,79 +2483,728 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
 
                 println!("Optimize loop complete.");
             }
-            "unknown" => {
-                let command = value_as_non_empty_string(intent.command_args.get("command"))
-                    .unwrap_or_else(|| "unknown".to_string());
-                println!("Unknown command: {command}");
-            }
-            "generate" => {
-                let mut prompt = intent.prompt.clone().unwrap_or_default();
-                if prompt.trim().is_empty() {
-                    if let Some(previous) = &last_prompt {
-                        prompt = previous.clone();
+            "provider" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "list".to_string());
+                let names = value_as_string_list(intent.command_args.get("names"));
+                let result = match op.as_str() {
+                    "enable" | "disable" if !names.is_empty() => names
+                        .iter()
+                        .try_for_each(|name| engine.set_provider_enabled(name, op == "enable")),
+                    "priority" => engine.set_provider_priority(names),
+                    "list" => Ok(()),
+                    _ => {
+                        println!("Usage: /provider [enable|disable <name>] [priority <a,b,...>]");
+                        continue;
                     }
-                }
-                if prompt.trim().is_empty() {
+                };
+                if let Err(err) = result {
+                    println!("Provider update failed: {err}");
                     continue;
                 }
-                last_prompt = Some(prompt.clone());
-
-                let usage = engine.track_context(&prompt, "")?;
-                let pct = (usage.pct * 100.0).round() as i64;
-                if usage.alert_level != "none" {
-                    println!("Context usage: {pct}% (alert {})", usage.alert_level);
-                } else {
-                    println!("Context usage: {pct}%");
-                }
-
-                let mut settings = chat_settings(&quality_preset);
-                let mut generation_intent = Map::new();
-                generation_intent
-                    .insert("action".to_string(), Value::String("generate".to_string()));
-                generation_intent.insert("profile".to_string(), Value::String(profile.clone()));
-                if let Some(init_image) =
-                    active_image_for_edit_prompt(&prompt, last_artifact_path.as_deref())
-                {
-                    settings.insert("init_image".to_string(), Value::String(init_image.clone()));
-                    generation_intent.insert(
-                        "source_images".to_string(),
-                        Value::Array(vec![Value::String(init_image)]),
-                    );
+                let registry = engine.provider_registry();
+                println!("Providers enabled: {}", registry.names().join(", "));
+                let disabled = registry.disabled();
+                if !disabled.is_empty() {
+                    println!("Providers disabled: {}", disabled.join(", "));
                 }
-
-                let plan = engine.preview_plan(&prompt, &settings, &generation_intent)?;
-                println!(
-                    "Plan: {} images via {}:{} size={} cached={}",
-                    plan.images, plan.provider, plan.model, plan.size, plan.cached
-                );
-
-                let (artifacts, error_message) =
-                    match engine.generate(&prompt, settings, generation_intent) {
-                        Ok(artifacts) => (artifacts, None),
-                        Err(err) => (Vec::new(), Some(err.to_string())),
-                    };
-                update_last_artifact_path(&artifacts, &mut last_artifact_path);
-
-                if let Some(reason) = engine.last_fallback_reason() {
-                    println!("Model fallback: {reason}");
+                if !registry.priority().is_empty() {
+                    println!("Provider priority: {}", registry.priority().join(" > "));
                 }
-                print_generation_cost_latency(&engine);
-
-                if let Some(error) = error_message {
-                    println!("Generation failed: {error}");
-                } else {
-                    println!("Generation complete.");
+            }
+            "upscale" => {
+                let artifact_id = value_as_non_empty_string(intent.command_args.get("artifact_id"))
+                    .or_else(|| latest_thread_artifact_id(&run_out_dir));
+                let Some(artifact_id) = artifact_id else {
+                    println!("/upscale requires an artifact id (or a prior generation)");
+                    continue;
+                };
+                let factor = intent
+                    .command_args
+                    .get("factor")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(2) as u32;
+                match engine.upscale(&artifact_id, factor) {
+                    Ok(artifact) => {
+                        update_last_artifact_path(
+                            std::slice::from_ref(&artifact),
+                            &mut last_artifact_path,
+                        );
+                        println!(
+                            "Upscaled {artifact_id} {factor}x -> {}",
+                            artifact
+                                .get("image_path")
+                                .and_then(Value::as_str)
+                                .unwrap_or("")
+                        );
+                    }
+                    Err(err) => println!("Upscale failed: {err}"),
                 }
             }
-            _ => {
-                println!(
-                    "Unknown command: {}",
-                    action_to_command_name(&intent.action).unwrap_or_else(|| intent.action.clone())
-                );
+            "video" => {
+                let prompt = intent
+                    .prompt
+                    .clone()
+                    .or_else(|| last_prompt.clone())
+                    .unwrap_or_default();
+                let mut settings = Map::new();
+                if let Some(path) = &last_artifact_path {
+                    settings.insert("init_image".to_string(), Value::String(path.clone()));
+                }
+                match engine.generate_video(&prompt, settings) {
+                    Ok(clips) => {
+                        for clip in clips {
+                            println!(
+                                "Video saved to {}",
+                                clip.get("video_path").and_then(Value::as_str).unwrap_or("")
+                            );
+                        }
+                    }
+                    Err(err) => println!("Video generation failed: {err:#}"),
+                }
+            }
+            "delete_version" | "restore_version" => {
+                let Some(version_id) =
+                    value_as_non_empty_string(intent.command_args.get("version_id"))
+                else {
+                    println!("Usage: /delete <version_id> | /restore <version_id>");
+                    continue;
+                };
+                let result = if intent.action == "delete_version" {
+                    engine.delete_version(&version_id)
+                } else {
+                    engine.restore_version(&version_id)
+                };
+                match result {
+                    Ok(()) if intent.action == "delete_version" => {
+                        println!("Deleted {version_id} (files kept; /restore {version_id} to undo)")
+                    }
+                    Ok(()) => println!("Restored {version_id}"),
+                    Err(err) => println!("{err}"),
+                }
+            }
+            "tag_artifact" => {
+                let labels: Vec<String> = intent
+                    .command_args
+                    .get("labels")
+                    .and_then(Value::as_array)
+                    .map(|labels| {
+                        labels
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if labels.is_empty() {
+                    println!(
+                        "Usage: /tag <artifact_id> <label> [-label ...] | /favorite [artifact_id]"
+                    );
+                    continue;
+                }
+                let artifact_id = value_as_non_empty_string(intent.command_args.get("artifact_id"));
+                match engine.tag_artifact(artifact_id.as_deref(), &labels) {
+                    Ok((artifact_id, tags)) if tags.is_empty() => {
+                        println!("{artifact_id} has no tags")
+                    }
+                    Ok((artifact_id, tags)) => println!("{artifact_id} tags: {}", tags.join(", ")),
+                    Err(err) => println!("{err}"),
+                }
+            }
+            "compare" => {
+                let refs: Vec<String> = intent
+                    .command_args
+                    .get("artifacts")
+                    .and_then(Value::as_array)
+                    .map(|refs| {
+                        refs.iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let [left, right] = refs.as_slice() else {
+                    println!("Usage: /compare <artifact_a> <artifact_b> [--diff]");
+                    continue;
+                };
+                let heatmap = intent
+                    .command_args
+                    .get("heatmap")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                match engine.compare_artifacts(left, right, heatmap) {
+                    Ok(comparison) => {
+                        println!(
+                            "SSIM {:.4}, mean abs diff {:.4} at {}x{}{}",
+                            comparison.ssim,
+                            comparison.mean_abs_diff,
+                            comparison.width,
+                            comparison.height,
+                            if comparison.resized {
+                                " (right image resized)"
+                            } else {
+                                ""
+                            }
+                        );
+                        println!("Comparison: {}", comparison.composite_path.display());
+                    }
+                    Err(err) => println!("Compare failed: {err}"),
+                }
+            }
+            "why_different" => {
+                let refs: Vec<String> = intent
+                    .command_args
+                    .get("artifacts")
+                    .and_then(Value::as_array)
+                    .map(|refs| {
+                        refs.iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let [left, right] = refs.as_slice() else {
+                    println!("Usage: /why-different <artifact_a> <artifact_b>");
+                    continue;
+                };
+                match engine.diff_artifact_receipts(left, right) {
+                    Ok(diff) => print_receipt_diff(&diff),
+                    Err(err) => println!("Diff failed: {err}"),
+                }
+            }
+            "grid" => {
+                let mut artifact_ids: Vec<String> = intent
+                    .command_args
+                    .get("artifact_ids")
+                    .and_then(Value::as_array)
+                    .map(|ids| {
+                        ids.iter()
+                            .filter_map(Value::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                if artifact_ids.is_empty() {
+                    artifact_ids = engine
+                        .thread()
+                        .live_versions()
+                        .last()
+                        .map(|version| {
+                            version
+                                .artifacts
+                                .iter()
+                                .filter_map(|artifact| artifact.get("artifact_id"))
+                                .filter_map(Value::as_str)
+                                .map(str::to_string)
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                }
+                if artifact_ids.is_empty() {
+                    println!("Usage: /grid <artifact_id...> [cols=N] [cell=PX]");
+                    continue;
+                }
+                let cols = intent
+                    .command_args
+                    .get("cols")
+                    .and_then(Value::as_u64)
+                    .map(|cols| cols as u32)
+                    .unwrap_or_else(|| (artifact_ids.len() as f64).sqrt().ceil() as u32);
+                let cell_size = intent
+                    .command_args
+                    .get("cell_size")
+                    .and_then(Value::as_u64)
+                    .map(|size| size as u32)
+                    .unwrap_or(256);
+                match engine.compose_grid(&artifact_ids, cols, cell_size) {
+                    Ok(artifact) => {
+                        last_artifact_path = artifact
+                            .get("image_path")
+                            .and_then(Value::as_str)
+                            .map(str::to_string);
+                        println!(
+                            "Grid of {} artifact(s): {}",
+                            artifact_ids.len(),
+                            last_artifact_path.as_deref().unwrap_or_default()
+                        );
+                    }
+                    Err(err) => println!("Grid failed: {err}"),
+                }
+            }
+            "revert_version" => {
+                let Some(version_id) =
+                    value_as_non_empty_string(intent.command_args.get("version_id"))
+                        .or_else(|| latest_thread_version_id(&run_out_dir))
+                else {
+                    println!("Nothing to undo.");
+                    continue;
+                };
+                match engine.revert_version(&version_id) {
+                    Ok(restored) => {
+                        last_artifact_path = restored
+                            .as_ref()
+                            .and_then(|artifact| artifact.get("image_path"))
+                            .and_then(Value::as_str)
+                            .map(str::to_string);
+                        println!(
+                            "Reverted {version_id} (files kept; /restore {version_id} to redo)."
+                        );
+                        if let Some(path) = &last_artifact_path {
+                            println!("Active image: {path}");
+                        }
+                    }
+                    Err(err) => println!("{err}"),
+                }
+            }
+            "branch" => {
+                let Some(version_id) =
+                    value_as_non_empty_string(intent.command_args.get("version_id"))
+                else {
+                    println!("Usage: /branch <version_id>");
+                    continue;
+                };
+                match engine.branch_from(&version_id) {
+                    Ok(()) => println!("Next generation branches from {version_id}."),
+                    Err(err) => println!("{err}"),
+                }
+            }
+            "select_region" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "show".to_string());
+                let number = |key: &str| {
+                    intent
+                        .command_args
+                        .get(key)
+                        .and_then(Value::as_u64)
+                        .unwrap_or(0)
+                };
+                let region = match op.as_str() {
+                    "show" => {
+                        match engine.active_selection() {
+                            Some(selection) => println!(
+                                "Selection: {:.0}% of {} (mask {})",
+                                selection.coverage * 100.0,
+                                selection.image_path.display(),
+                                selection.mask_path.display()
+                            ),
+                            None => println!("No selection."),
+                        }
+                        continue;
+                    }
+                    "clear" => {
+                        if engine.clear_selection()? {
+                            println!("Selection cleared.");
+                        } else {
+                            println!("No selection.");
+                        }
+                        continue;
+                    }
+                    "invalid" => {
+                        println!(
+                            "{}",
+                            value_as_non_empty_string(intent.command_args.get("error"))
+                                .unwrap_or_else(|| "Usage: /select rect X,Y WxH".to_string())
+                        );
+                        continue;
+                    }
+                    "rect" => Some(EditRegion::Rect {
+                        x: number("x") as u32,
+                        y: number("y") as u32,
+                        width: number("width") as u32,
+                        height: number("height") as u32,
+                    }),
+                    "ellipse" => Some(EditRegion::Ellipse {
+                        cx: number("cx") as f64,
+                        cy: number("cy") as f64,
+                        rx: number("rx") as f64,
+                        ry: number("ry") as f64,
+                    }),
+                    _ => None,
+                };
+                let Some(image_path) = last_artifact_path.clone() else {
+                    println!("No active image to select on.");
+                    continue;
+                };
+                let selected = match region {
+                    Some(region) => engine.select_region(Path::new(&image_path), &region),
+                    None => {
+                        let description =
+                            value_as_non_empty_string(intent.command_args.get("description"))
+                                .unwrap_or_default();
+                        engine.segment_region(Path::new(&image_path), &description, None)
+                    }
+                };
+                match selected {
+                    Ok(selection) => println!(
+                        "Selected {:.0}% of the image; the next prompt edits only that area (mask {}).",
+                        selection.coverage * 100.0,
+                        selection.mask_path.display()
+                    ),
+                    Err(err) => println!("Select failed: {err}"),
+                }
+            }
+            "preset" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "list".to_string());
+                let name =
+                    value_as_non_empty_string(intent.command_args.get("name")).unwrap_or_default();
+                match op.as_str() {
+                    "save" => {
+                        let mut snapshot = chat_settings(&quality_preset);
+                        snapshot.extend(preset_settings.clone());
+                        snapshot.extend(last_settings_update.clone());
+                        let saved = SettingsPreset::capture(&name, engine.image_model(), &snapshot)
+                            .and_then(|preset| preset.save());
+                        match saved {
+                            Ok(path) => println!("Saved preset {name} to {}", path.display()),
+                            Err(err) => println!("Preset save failed: {err:#}"),
+                        }
+                    }
+                    "use" => match SettingsPreset::load(&name) {
+                        Ok(preset) => {
+                            if let Some(preset_quality) = preset.quality_preset() {
+                                quality_preset = preset_quality.to_string();
+                            }
+                            if let Some(model) = &preset.image_model {
+                                engine.set_image_model(Some(model.clone()));
+                            }
+                            preset_settings = preset.settings.clone();
+                            preset_settings.remove("quality_preset");
+                            println!("Using preset {name}: {}", preset.to_value());
+                        }
+                        Err(err) => println!("Preset failed: {err:#}"),
+                    },
+                    "list" => {
+                        let available = SettingsPreset::default_dir()
+                            .map(|dir| SettingsPreset::available(&dir))
+                            .unwrap_or_default();
+                        if available.is_empty() {
+                            println!("No presets saved yet; use /preset save <name>.");
+                        } else {
+                            println!("Presets: {}", available.join(", "));
+                        }
+                    }
+                    _ => println!(
+                        "{}",
+                        value_as_non_empty_string(intent.command_args.get("error")).unwrap_or_else(
+                            || "usage: /preset save <name> | /preset use <name>".to_string()
+                        )
+                    ),
+                }
+            }
+            "palette" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "extract".to_string());
+                match op.as_str() {
+                    "extract" => {
+                        let Some(path) = value_as_non_empty_string(intent.command_args.get("path"))
+                            .or_else(|| last_artifact_path.clone())
+                        else {
+                            println!("No active image; use /palette <path>.");
+                            continue;
+                        };
+                        match engine.extract_palette(Path::new(&path), PALETTE_DEFAULT_COLORS) {
+                            Ok(colors) => {
+                                println!("Palette of {path}:");
+                                for color in &colors {
+                                    println!(
+                                        "  {} {:>3.0}% {}",
+                                        color.hex,
+                                        color.share * 100.0,
+                                        brood_engine::color_name(&color.hex).unwrap_or_default()
+                                    );
+                                }
+                                println!("Use /palette lock to generate with these colors.");
+                                extracted_palette =
+                                    colors.into_iter().map(|color| color.hex).collect();
+                            }
+                            Err(err) => println!("Palette failed: {err}"),
+                        }
+                        continue;
+                    }
+                    "lock" => {
+                        if extracted_palette.is_empty() {
+                            println!("No palette extracted yet; run /palette first.");
+                            continue;
+                        }
+                        pinned_palette = extracted_palette.clone();
+                    }
+                    "set" => {
+                        pinned_palette = intent
+                            .command_args
+                            .get("colors")
+                            .and_then(Value::as_array)
+                            .map(|colors| {
+                                colors
+                                    .iter()
+                                    .filter_map(Value::as_str)
+                                    .map(str::to_string)
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                    }
+                    "clear" => pinned_palette.clear(),
+                    _ => {
+                        let error = value_as_non_empty_string(intent.command_args.get("error"))
+                            .unwrap_or_default();
+                        println!("Usage: /palette [<path>|lock|#rrggbb ...|clear] ({error})");
+                        continue;
+                    }
+                }
+                if pinned_palette.is_empty() {
+                    println!("Palette: none");
+                } else {
+                    println!("Palette pinned: {}", pinned_palette.join(" "));
+                }
+            }
+            "history" => {
+                let thread = engine.thread();
+                let active = engine
+                    .active_parent_version_id()
+                    .or_else(|| thread.live_versions().last().map(|v| v.version_id.as_str()));
+                let tree = thread.version_tree();
+                if tree.is_empty() {
+                    println!("No versions yet.");
+                }
+                for (depth, version) in tree {
+                    let marker = if Some(version.version_id.as_str()) == active {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    let winner = version
+                        .selected_artifact_id
+                        .as_deref()
+                        .map(|id| format!(" winner={id}"))
+                        .unwrap_or_default();
+                    println!(
+                        "{marker} {}{} ({} artifact(s){winner}) {}",
+                        "  ".repeat(depth),
+                        version.version_id,
+                        version.artifacts.len(),
+                        truncate_for_describe(version.prompt.clone(), 60)
+                    );
+                }
+            }
+            "auto_select" => {
+                let Some(version_id) =
+                    value_as_non_empty_string(intent.command_args.get("version_id"))
+                        .or_else(|| latest_thread_version_id(&run_out_dir))
+                else {
+                    println!("No versions to pick from yet.");
+                    continue;
+                };
+                match engine.auto_select(&version_id) {
+                    Ok(best) => println!(
+                        "Selected {} for {version_id} (score {:.3}, sharpness {:.1}, entropy {:.2})",
+                        best.artifact_id, best.score, best.sharpness, best.entropy
+                    ),
+                    Err(err) => println!("Auto-select failed: {err}"),
+                }
+            }
+            "budget" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "show".to_string());
+                let scope = value_as_non_empty_string(intent.command_args.get("scope"))
+                    .unwrap_or_else(|| "all".to_string());
+                let usd = intent.command_args.get("usd").and_then(Value::as_f64);
+                let mut budget = engine.cost_budget();
+                match op.as_str() {
+                    "show" => {}
+                    "force" => {
+                        engine.allow_next_over_budget();
+                        println!("Next generation may exceed the budget.");
+                    }
+                    "set" | "off" => {
+                        let value = if op == "set" { usd } else { None };
+                        if scope != "session" {
+                            budget.run_usd = value;
+                        }
+                        if scope != "run" {
+                            budget.session_usd = value;
+                        }
+                        if let Err(err) = engine.set_cost_budget(budget) {
+                            println!("Budget update failed: {err}");
+                            continue;
+                        }
+                    }
+                    _ => {
+                        println!("Usage: /budget [run|session] <usd|off> | /budget force");
+                        continue;
+                    }
+                }
+                print_cost_budget(&engine);
+            }
+            "set_variables" => {
+                let op = value_as_non_empty_string(intent.command_args.get("op"))
+                    .unwrap_or_else(|| "show".to_string());
+                match op.as_str() {
+                    "set" => {
+                        if let Some(variables) = intent
+                            .command_args
+                            .get("variables")
+                            .and_then(Value::as_object)
+                        {
+                            template_variables.extend(variables.clone());
+                        }
+                    }
+                    "clear" => template_variables.clear(),
+                    "show" => {}
+                    _ => {
+                        let error = value_as_non_empty_string(intent.command_args.get("error"))
+                            .unwrap_or_default();
+                        println!("Usage: /vars name=value [name=a,b ...] | /vars clear ({error})");
+                        continue;
+                    }
+                }
+                if template_variables.is_empty() {
+                    println!("Template variables: none");
+                } else {
+                    println!(
+                        "Template variables: {}",
+                        Value::Object(template_variables.clone())
+                    );
+                }
+            }
+            "unknown" => {
+                let command = value_as_non_empty_string(intent.command_args.get("command"))
+                    .unwrap_or_else(|| "unknown".to_string());
+                println!("Unknown command: {command}");
+                let suggestions: Vec<&str> = command_palette(&command)
+                    .into_iter()
+                    .take(3)
+                    .map(|entry| entry.command)
+                    .collect();
+                if !suggestions.is_empty() {
+                    println!("Did you mean: {}", suggestions.join(", "));
+                }
+                exec_failures += 1;
+            }
+            "generate" => {
+                let mut prompt = intent.prompt.clone().unwrap_or_default();
+                if prompt.trim().is_empty() {
+                    if let Some(previous) = &last_prompt {
+                        prompt = previous.clone();
+                    }
+                }
+                if prompt.trim().is_empty() {
+                    continue;
+                }
+                last_prompt = Some(prompt.clone());
+
+                let usage = engine.track_context(&prompt, "")?;
+                if let Some(compaction) = &usage.compaction {
+                    println!(
+                        "Context compacted: {} earlier turns summarized ({} -> {} tokens)",
+                        compaction.turns_summarized,
+                        compaction.tokens_before,
+                        compaction.tokens_after
+                    );
+                }
+                let pct = (usage.pct * 100.0).round() as i64;
+                if usage.alert_level != "none" {
+                    println!("Context usage: {pct}% (alert {})", usage.alert_level);
+                } else {
+                    println!("Context usage: {pct}%");
+                }
+
+                let mut settings = chat_settings(&quality_preset);
+                settings.extend(preset_settings.clone());
+                if args.deterministic {
+                    settings.insert("deterministic".to_string(), Value::Bool(true));
+                }
+                settings.extend(intent.settings_update.clone());
+                last_settings_update = intent.settings_update.clone().into_iter().collect();
+                if !template_variables.is_empty() {
+                    settings.insert(
+                        "variables".to_string(),
+                        Value::Object(template_variables.clone()),
+                    );
+                }
+                if !pinned_palette.is_empty() {
+                    settings.insert("palette".to_string(), json!(pinned_palette));
+                }
+                let mut generation_intent = Map::new();
+                generation_intent
+                    .insert("action".to_string(), Value::String("generate".to_string()));
+                generation_intent.insert("profile".to_string(), Value::String(profile.clone()));
+                // A selection on the active image turns any prompt into an
+                // edit of just that area.
+                let selection = engine
+                    .active_selection()
+                    .filter(|selection| {
+                        last_artifact_path.as_deref().map(Path::new)
+                            == Some(selection.image_path.as_path())
+                    })
+                    .cloned();
+                // `/generate <prompt>` always starts a new image; only plain
+                // prompts are read as follow-up edits.
+                let explicit_generate = intent.raw.trim_start().starts_with('/');
+                if let Some(selection) = &selection {
+                    let init_image = selection.image_path.to_string_lossy().to_string();
+                    println!("Editing the selection on {init_image}");
+                    settings.insert("init_image".to_string(), Value::String(init_image));
+                } else if !explicit_generate {
+                    if let Some(init_image) = engine.chain_edit(
+                        &prompt,
+                        last_artifact_path.as_deref(),
+                        &mut settings,
+                        &mut generation_intent,
+                    ) {
+                        println!("Editing {init_image}");
+                    }
+                }
+
+                let plan = engine.preview_plan(&prompt, &settings, &generation_intent)?;
+                println!(
+                    "Plan: {} images via {}:{} size={} cached={}",
+                    plan.images, plan.provider, plan.model, plan.size, plan.cached
+                );
+                print_plan_limits(&plan);
+
+                let result = match &selection {
+                    Some(selection) => engine.edit(
+                        &prompt,
+                        &EditRegion::Mask(selection.mask_path.clone()),
+                        settings,
+                    ),
+                    None => engine.generate(&prompt, settings, generation_intent),
+                };
+                let (artifacts, error_message) = match result {
+                    Ok(artifacts) => (artifacts, None),
+                    Err(err) => (Vec::new(), Some(err.to_string())),
+                };
+                update_last_artifact_path(&artifacts, &mut last_artifact_path);
+
+                if let Some(reason) = engine.last_fallback_reason() {
+                    println!("Model fallback: {reason}");
+                }
+                print_generation_cost_latency(&engine);
+
+                if let Some(error) = error_message {
+                    println!("Generation failed: {error}");
+                    exec_failures += 1;
+                } else {
+                    println!("Generation complete.");
+                }
+            }
+            _ => {
+                println!(
+                    "Unknown command: {}",
+                    action_to_command_name(&intent.action).unwrap_or_else(|| intent.action.clone())
+                );
+                exec_failures += 1;
             }
         }
     }
 
+    ChatMemory {
+        profile,
+        quality_preset,
+        last_prompt,
+        active_image: last_artifact_path,
+    }
+    .save_if_changed(&engine, &mut saved_memory)?;
     if let Some(session) = intent_rt.as_mut() {
         session.stop();
     }
@@ -1937,20 +3215,114 @@ fn run_chat_native(args: ChatArgs) -> Result<()> {
         session.stop();
     }
     engine.finish()?;
-    Ok(())
+    Ok(if !interactive && exec_failures > 0 {
+        1
+    } else {
+        0
+    })
+}
+
+/// The chat loop's own state, kept in `session.json` after every command so
+/// `chat --resume` restores it.
+#[derive(Debug, Clone, PartialEq)]
+struct ChatMemory {
+    profile: String,
+    quality_preset: String,
+    last_prompt: Option<String>,
+    active_image: Option<String>,
+}
+
+impl Default for ChatMemory {
+    fn default() -> Self {
+        Self {
+            profile: "default".to_string(),
+            quality_preset: "quality".to_string(),
+            last_prompt: None,
+            active_image: None,
+        }
+    }
+}
+
+impl ChatMemory {
+    /// An active image whose file is gone is dropped rather than restored.
+    fn restore(session: &SessionState) -> Self {
+        let defaults = Self::default();
+        Self {
+            profile: session.profile.clone().unwrap_or(defaults.profile),
+            quality_preset: session
+                .quality_preset
+                .clone()
+                .unwrap_or(defaults.quality_preset),
+            last_prompt: session.last_prompt.clone(),
+            active_image: session
+                .active_image
+                .clone()
+                .filter(|path| Path::new(path).is_file()),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mut line = format!(
+            "Restored chat: profile {}, quality {}",
+            self.profile, self.quality_preset
+        );
+        if let Some(path) = &self.active_image {
+            line.push_str(&format!(", active image {path}"));
+        }
+        if let Some(prompt) = &self.last_prompt {
+            line.push_str(&format!(", last prompt {prompt:?}"));
+        }
+        line
+    }
+
+    fn save_if_changed(self, engine: &NativeEngine, saved: &mut Self) -> Result<()> {
+        if self == *saved {
+            return Ok(());
+        }
+        engine.update_session(|session| {
+            session.profile = Some(self.profile.clone());
+            session.quality_preset = Some(self.quality_preset.clone());
+            session.last_prompt = self.last_prompt.clone();
+            session.active_image = self.active_image.clone();
+        })?;
+        *saved = self;
+        Ok(())
+    }
 }
 
 fn run_run_native(args: RunArgs) -> Result<i32> {
+    // Before the run dir exists, so a typo does not leave an empty run behind.
+    let preset = args
+        .preset
+        .as_deref()
+        .map(SettingsPreset::load)
+        .transpose()?;
+    let run_dir = resolve_run_dir(
+        args.out.as_deref(),
+        args.out_root.as_deref(),
+        &args.prompt,
+        RunDirReuse::from_flags(args.resume, args.force),
+    )?;
     let events_path = args
         .events
         .clone()
-        .unwrap_or_else(|| args.out.join("events.jsonl"));
-    let mut engine = NativeEngine::new(
-        &args.out,
+        .unwrap_or_else(|| run_dir.join("events.jsonl"));
+    let image_model = args.image_model.clone().or_else(|| {
+        preset
+            .as_ref()
+            .and_then(|preset| preset.image_model.clone())
+    });
+    let mut engine = open_engine(
+        &run_dir,
         &events_path,
         Some(args.text_model.clone()),
-        args.image_model.clone(),
+        image_model,
+        args.resume,
+        args.force_unlock,
     )?;
+    attach_event_sinks(&engine, args.events_stderr.as_deref())?;
+    apply_cost_budget_env(&mut engine)?;
+    engine.set_global_cache(global_cache_from_env());
     let mut settings = Map::new();
     settings.insert("size".to_string(), Value::String("1024x1024".to_string()));
     settings.insert("n".to_string(), json!(1));
@@ -1958,6 +3330,23 @@ fn run_run_native(args: RunArgs) -> Result<i32> {
         "quality_preset".to_string(),
         Value::String("quality".to_string()),
     );
+    if let Some(preset) = &preset {
+        preset.apply(&mut settings);
+    }
+    if !args.vars.is_empty() {
+        let mut variables = Map::new();
+        for assignment in &args.vars {
+            let (name, value) = parse_variable_assignment(assignment)?;
+            variables.insert(name, value);
+        }
+        settings.insert("variables".to_string(), Value::Object(variables));
+    }
+    if args.enhance_prompt {
+        settings.insert("enhance_prompt".to_string(), Value::Bool(true));
+    }
+    if args.deterministic {
+        settings.insert("deterministic".to_string(), Value::Bool(true));
+    }
     let mut intent = Map::new();
     intent.insert("action".to_string(), Value::String("generate".to_string()));
     engine.generate(&args.prompt, settings, intent)?;
@@ -1966,28 +3355,932 @@ fn run_run_native(args: RunArgs) -> Result<i32> {
 }
 
 fn run_recreate_native(args: RecreateArgs) -> Result<i32> {
+    let label = args
+        .reference
+        .file_stem()
+        .and_then(|value| value.to_str())
+        .unwrap_or("recreate")
+        .to_string();
+    let run_dir = resolve_run_dir(
+        args.out.as_deref(),
+        args.out_root.as_deref(),
+        &label,
+        RunDirReuse::from_flags(args.resume, args.force),
+    )?;
     let events_path = args
         .events
         .clone()
-        .unwrap_or_else(|| args.out.join("events.jsonl"));
-    let mut engine = NativeEngine::new(
-        &args.out,
+        .unwrap_or_else(|| run_dir.join("events.jsonl"));
+    let mut engine = open_engine(
+        &run_dir,
         &events_path,
         Some(args.text_model.clone()),
         args.image_model.clone(),
+        args.resume,
+        args.force_unlock,
     )?;
+    attach_event_sinks(&engine, args.events_stderr.as_deref())?;
+    apply_cost_budget_env(&mut engine)?;
+    engine.set_global_cache(global_cache_from_env());
     let result = run_native_recreate_loop(&mut engine, &args.reference, "quality", 2);
     engine.finish()?;
     result?;
     Ok(0)
 }
 
+/// The `BROOD_KEYRING` file, if set, as the engine's credentials provider.
+fn keyring_from_env() -> Result<Option<Arc<dyn CredentialsProvider>>> {
+    Ok(KeyringCredentials::from_env()?.map(|keyring| Arc::new(keyring) as _))
+}
+
+/// `NativeEngine::resume` for `--resume` on an existing run (printing what
+/// crash recovery found), `NativeEngine::new` otherwise. `force_unlock`
+/// removes the run dir's lock first.
+fn open_engine(
+    run_dir: &Path,
+    events_path: &Path,
+    text_model: Option<String>,
+    image_model: Option<String>,
+    resume: bool,
+    force_unlock: bool,
+) -> Result<NativeEngine> {
+    check_http_config()?;
+    if force_unlock {
+        if let Some(pid) = force_unlock_run_dir(run_dir)? {
+            println!(
+                "Removed the lock held by pid {pid} on {}.",
+                run_dir.display()
+            );
+        }
+    }
+    if !resume || !(run_dir.join("thread.json").is_file() || events_path.is_file()) {
+        let mut engine = NativeEngine::new(run_dir, events_path, text_model, image_model)?;
+        if let Some(layout) = first_non_empty_env(&["BROOD_RUN_LAYOUT"]) {
+            engine.set_run_layout(RunLayout::parse(&layout)?)?;
+        }
+        engine.set_cost_ledger(CostLedger::from_env());
+        engine.set_artifact_store(deferred_artifact_store_from_env())?;
+        engine.set_credentials_provider(keyring_from_env()?);
+        engine.set_hooks(Hooks::from_env()?);
+        return Ok(engine);
+    }
+    let mut engine = NativeEngine::resume(run_dir, events_path, text_model, image_model)?;
+    engine.set_cost_ledger(CostLedger::from_env());
+    engine.set_artifact_store(deferred_artifact_store_from_env())?;
+    engine.set_credentials_provider(keyring_from_env()?);
+    engine.set_hooks(Hooks::from_env()?);
+    if let Some(report) = engine.resume_report() {
+        println!(
+            "Resumed run started {}{}.",
+            report.started_at,
+            if report.previously_finished {
+                ""
+            } else {
+                " (previous session did not finish)"
+            }
+        );
+        if !report.recovered_artifacts.is_empty() {
+            println!(
+                "Recovered artifacts: {}",
+                report.recovered_artifacts.join(", ")
+            );
+        }
+        if !report.incomplete_versions.is_empty() {
+            println!(
+                "Incomplete versions (no artifacts): {}",
+                report.incomplete_versions.join(", ")
+            );
+        }
+        if report.pruned_cache_entries > 0 {
+            println!(
+                "Dropped {} cache entries with missing files.",
+                report.pruned_cache_entries
+            );
+        }
+    }
+    Ok(engine)
+}
+
+/// Picks the run dir for a command: a fresh `<out_root>/run-<stamp>-<slug>`
+/// (printed so callers can find it) or the explicit `--out`, which must be
+/// empty unless `--resume`/`--force` was given.
+fn resolve_run_dir(
+    out: Option<&Path>,
+    out_root: Option<&Path>,
+    label: &str,
+    reuse: RunDirReuse,
+) -> Result<PathBuf> {
+    if let Some(root) = out_root {
+        let run_dir = create_unique_run_dir(root, label)?;
+        println!("Run dir: {}", run_dir.display());
+        return Ok(run_dir);
+    }
+    let Some(out) = out else {
+        bail!("either --out or --out-root is required");
+    };
+    prepare_run_dir(out, reuse)?;
+    Ok(out.to_path_buf())
+}
+
+fn run_batch_native(args: BatchArgs) -> Result<i32> {
+    let rows = load_batch_manifest(&args.manifest)?;
+    if rows.is_empty() {
+        bail!("batch manifest {} has no prompts", args.manifest.display());
+    }
+    let config = BatchConfig {
+        out_dir: args.out.clone(),
+        concurrency: args.concurrency,
+        budget_usd: args.budget,
+        text_model: Some(args.text_model.clone()),
+        image_model: args.image_model.clone(),
+        base_settings: batch_base_settings(),
+        global_cache_dir: global_cache_from_env().map(|cache| cache.root().to_path_buf()),
+        cost_ledger: CostLedger::from_env(),
+        artifact_store: first_non_empty_env(&[ARTIFACT_STORE_ENV]),
+        keyring: first_non_empty_env(&[KEYRING_ENV]).map(PathBuf::from),
+        hooks: first_non_empty_env(&[HOOKS_ENV]).map(PathBuf::from),
+    };
+    let summary = run_batch(&rows, &config)?;
+    for row in &summary.rows {
+        match &row.error {
+            Some(error) => println!("[{}] {} {}: {error}", row.id, row.status, row.prompt),
+            None => println!("[{}] {} {}", row.id, row.status, row.prompt),
+        }
+    }
+    println!(
+        "Batch: {} ok, {} failed, {} skipped (budget); ${:.4} total. Summary: {}",
+        summary.count("ok"),
+        summary.count("failed"),
+        summary.count("skipped_budget"),
+        summary.cost_total_usd,
+        summary.summary_path.display()
+    );
+    Ok(if summary.count("failed") > 0 { 1 } else { 0 })
+}
+
+/// Settings every `batch` row and queued job starts from.
+fn batch_base_settings() -> Map<String, Value> {
+    let mut settings = Map::new();
+    settings.insert("size".to_string(), Value::String("1024x1024".to_string()));
+    settings.insert("n".to_string(), json!(1));
+    settings.insert(
+        "quality_preset".to_string(),
+        Value::String("quality".to_string()),
+    );
+    settings
+}
+
+fn run_queue_native(args: QueueArgs) -> Result<i32> {
+    let Some(queue) = args.queue.map(JobQueue::new).or_else(JobQueue::from_env) else {
+        bail!("no queue dir: pass --queue or set {JOB_QUEUE_DIR_ENV}");
+    };
+    match args.action {
+        QueueAction::Add(args) => {
+            let now = chrono::Utc::now();
+            let deadline = args
+                .deadline
+                .as_deref()
+                .map(|raw| parse_deadline(raw, now))
+                .transpose()?;
+            let rows = match (&args.manifest, args.prompt) {
+                (Some(manifest), _) => load_batch_manifest(manifest)?,
+                (None, prompt) => vec![BatchRow {
+                    id: String::new(),
+                    prompt: prompt.unwrap_or_default(),
+                    image_model: None,
+                    settings: Map::new(),
+                }],
+            };
+            for mut row in rows {
+                // Manifest rows carry their own scheduling next to their settings.
+                let priority = match row.settings.remove("priority") {
+                    Some(value) => value.as_i64().ok_or_else(|| {
+                        anyhow::anyhow!("priority must be an integer, got {value}")
+                    })?,
+                    None => args.priority,
+                };
+                let row_deadline = match row.settings.remove("deadline") {
+                    Some(Value::String(raw)) => Some(parse_deadline(&raw, now)?),
+                    Some(value) => bail!("deadline must be a string, got {value}"),
+                    None => deadline,
+                };
+                let mut settings = batch_base_settings();
+                settings.append(&mut row.settings);
+                row.settings = settings;
+                row.image_model = row.image_model.or_else(|| args.image_model.clone());
+                let job = queue.enqueue(row, priority, row_deadline)?;
+                println!("queued {} {}", job.job_id, job.prompt);
+            }
+        }
+        QueueAction::List(args) => {
+            let jobs: Vec<QueueJob> = queue
+                .jobs()?
+                .into_iter()
+                .filter(|job| args.status.is_none_or(|status| job.status == status))
+                .collect();
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&jobs)?);
+            } else {
+                for job in &jobs {
+                    print_queue_job(job);
+                }
+            }
+        }
+        QueueAction::Cancel(args) => {
+            let job = queue.cancel(&args.job_id)?;
+            println!("cancelled {} {}", job.job_id, job.prompt);
+        }
+        QueueAction::Retry(args) => {
+            let job = queue.retry(&args.job_id)?;
+            println!("queued {} {} again", job.job_id, job.prompt);
+        }
+        QueueAction::Work(args) => return run_queue_work(&queue, args),
+    }
+    Ok(0)
+}
+
+fn run_queue_work(queue: &JobQueue, args: QueueWorkArgs) -> Result<i32> {
+    let policy = args.policy;
+    if args.force_unlock {
+        if let Some(pid) = force_unlock_run_dir(queue.dir())? {
+            println!(
+                "Removed the lock held by pid {pid} on {}.",
+                queue.dir().display()
+            );
+        }
+    }
+    let config = BatchConfig {
+        out_dir: queue.dir().to_path_buf(),
+        concurrency: 1,
+        budget_usd: None,
+        text_model: Some(args.text_model),
+        image_model: args.image_model,
+        base_settings: Map::new(),
+        global_cache_dir: global_cache_from_env().map(|cache| cache.root().to_path_buf()),
+        cost_ledger: CostLedger::from_env(),
+        artifact_store: first_non_empty_env(&[ARTIFACT_STORE_ENV]),
+        keyring: first_non_empty_env(&[KEYRING_ENV]).map(PathBuf::from),
+        hooks: first_non_empty_env(&[HOOKS_ENV]).map(PathBuf::from),
+    };
+    let mut failed = 0;
+    loop {
+        let done = queue.work(&config, policy, print_queue_job)?;
+        failed += done
+            .iter()
+            .filter(|job| job.status == JobStatus::Failed)
+            .count();
+        if !args.follow {
+            break;
+        }
+        thread::sleep(QUEUE_FOLLOW_POLL);
+    }
+    Ok(if failed > 0 { 1 } else { 0 })
+}
+
+fn print_queue_job(job: &QueueJob) {
+    let deadline = job
+        .deadline
+        .map(|deadline| deadline.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_else(|| "-".to_string());
+    print!(
+        "{}  {:<9}  priority {:<3}  deadline {deadline}  {}",
+        job.job_id,
+        job.status.label(),
+        job.priority,
+        job.prompt
+    );
+    match &job.error {
+        Some(error) => println!(": {error}"),
+        None => println!(),
+    }
+}
+
+fn run_experiment_native(args: ExperimentArgs) -> Result<i32> {
+    let mut variants = Vec::new();
+    for raw in &args.variants {
+        let Some((label, prompt)) = raw.split_once('=') else {
+            bail!("--variant expects LABEL=PROMPT, got '{raw}'");
+        };
+        variants.push(PromptVariant::new(label.trim(), prompt.trim()));
+    }
+    let run_dir = resolve_run_dir(
+        args.out.as_deref(),
+        args.out_root.as_deref(),
+        "experiment",
+        RunDirReuse::from_flags(args.resume, args.force),
+    )?;
+    let events_path = args
+        .events
+        .clone()
+        .unwrap_or_else(|| run_dir.join("events.jsonl"));
+    let mut engine = open_engine(
+        &run_dir,
+        &events_path,
+        Some(args.text_model.clone()),
+        args.image_model.clone(),
+        args.resume,
+        args.force_unlock,
+    )?;
+    apply_cost_budget_env(&mut engine)?;
+    engine.set_global_cache(global_cache_from_env());
+    let mut settings = Map::new();
+    settings.insert("size".to_string(), Value::String("1024x1024".to_string()));
+    settings.insert("n".to_string(), json!(args.n.max(1)));
+    settings.insert(
+        "quality_preset".to_string(),
+        Value::String("quality".to_string()),
+    );
+    let summary = engine.experiment(variants, settings)?;
+    engine.finish()?;
+    for variant in &summary.variants {
+        let latency = variant
+            .latency_per_image_s
+            .map(|value| format!("{value:.2}s/img"))
+            .unwrap_or_else(|| "-".to_string());
+        let quality = variant
+            .quality_score
+            .map(|value| format!("quality {value:.3}"))
+            .unwrap_or_else(|| "quality -".to_string());
+        match &variant.error {
+            Some(error) => println!("[{}] failed: {error}", variant.label),
+            None => println!(
+                "[{}] {} artifact(s) ${:.4} {latency} {quality}",
+                variant.label,
+                variant.artifact_ids.len(),
+                variant.cost_usd
+            ),
+        }
+    }
+    println!(
+        "Experiment {}: cheapest {}, fastest {}, best quality {}. Summary: {}",
+        summary.experiment_id,
+        summary
+            .cheapest()
+            .map(|variant| variant.label.as_str())
+            .unwrap_or("-"),
+        summary
+            .fastest()
+            .map(|variant| variant.label.as_str())
+            .unwrap_or("-"),
+        summary
+            .best_quality()
+            .map(|variant| variant.label.as_str())
+            .unwrap_or("-"),
+        summary.summary_path.display()
+    );
+    Ok(
+        if summary
+            .variants
+            .iter()
+            .any(|variant| variant.error.is_some())
+        {
+            1
+        } else {
+            0
+        },
+    )
+}
+
+fn run_verify_native(args: VerifyArgs) -> Result<i32> {
+    if !args.run.is_dir() {
+        bail!("run dir {} does not exist", args.run.display());
+    }
+    let report = verify_run(&args.run);
+    for issue in &report.errors {
+        println!(
+            "FAIL [{}] {}: {}",
+            issue.code,
+            issue.path.display(),
+            issue.message
+        );
+    }
+    for issue in &report.notes {
+        println!(
+            "note [{}] {}: {}",
+            issue.code,
+            issue.path.display(),
+            issue.message
+        );
+    }
+    println!(
+        "Verified {} receipt(s), {} artifact(s): {} mismatch(es), {} note(s).",
+        report.receipts_checked,
+        report.artifacts_checked,
+        report.errors.len(),
+        report.notes.len()
+    );
+    Ok(if report.is_ok() { 0 } else { 1 })
+}
+
+fn run_diff_receipts_native(args: DiffReceiptsArgs) -> Result<i32> {
+    let diff = diff_receipts(&load_receipt(&args.left)?, &load_receipt(&args.right)?);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff.to_value())?);
+    } else {
+        print_receipt_diff(&diff);
+    }
+    Ok(0)
+}
+
+fn run_reproduce_native(args: ReproduceArgs) -> Result<i32> {
+    let run_dir = match args.out {
+        Some(out) => out,
+        None => args
+            .receipt
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf),
+    };
+    let events_path = run_dir.join("events.jsonl");
+    let mut engine = open_engine(&run_dir, &events_path, None, None, true, args.force_unlock)?;
+    apply_cost_budget_env(&mut engine)?;
+    let reproduction = engine.reproduce_receipt(&args.receipt)?;
+    for artifact in &reproduction.artifacts {
+        match artifact.delta {
+            Some(delta) => println!(
+                "{}: pixel delta {:.4}, dHash distance {}/64",
+                artifact.artifact_id, delta.pixel_delta, delta.dhash_distance
+            ),
+            None => println!("{}: original image missing, no delta", artifact.artifact_id),
+        }
+    }
+    match reproduction.closest() {
+        Some(artifact) if artifact.delta.is_some_and(|delta| delta.pixel_delta == 0.0) => {
+            println!(
+                "Reproduced {} exactly as {}.",
+                reproduction.reproduction_of, artifact.artifact_id
+            )
+        }
+        Some(artifact) => println!(
+            "Closest reproduction of {}: {}",
+            reproduction.reproduction_of,
+            artifact.image_path.display()
+        ),
+        None => println!(
+            "Reproduced {} as version {}.",
+            reproduction.reproduction_of, reproduction.version_id
+        ),
+    }
+    engine.finish()?;
+    Ok(0)
+}
+
+fn print_receipt_diff(diff: &ReceiptDiff) {
+    let side = |value: &Option<Value>| {
+        value
+            .as_ref()
+            .map_or_else(|| "(unset)".to_string(), Value::to_string)
+    };
+    for change in &diff.changes {
+        println!(
+            "{}: {} -> {}",
+            change.path,
+            side(&change.left),
+            side(&change.right)
+        );
+    }
+    if diff.is_identical() {
+        println!("Requests are identical.");
+    }
+    if diff.unseeded {
+        println!("Neither request set a seed; provider sampling alone can change the output.");
+    }
+}
+
+fn run_gc_native(args: GcArgs) -> Result<i32> {
+    let policy = RetentionPolicy {
+        keep_days: args.keep_days,
+        keep_winners: args.keep_winners,
+    };
+    let report = collect_garbage(&args.runs_dir, policy, SystemTime::now(), args.dry_run)?;
+    let verb = if report.dry_run {
+        "would prune"
+    } else {
+        "pruned"
+    };
+    for artifact in &report.pruned {
+        println!(
+            "{verb} {} {}/{} ({} bytes, {} file(s))",
+            artifact.run_dir.display(),
+            artifact.version_id,
+            artifact.artifact_id,
+            artifact.bytes,
+            artifact.files.len()
+        );
+    }
+    println!(
+        "Scanned {} run(s): {} {} artifact(s), {} bytes reclaimable; kept {} winner(s).",
+        report.runs_scanned,
+        verb,
+        report.pruned.len(),
+        report.reclaimable_bytes(),
+        report.winners_kept
+    );
+    Ok(0)
+}
+
+fn run_costs_native(args: CostsArgs) -> Result<i32> {
+    let group_by = args.group_by;
+    let since = args.since.as_deref().map(parse_ledger_date).transpose()?;
+    let Some(ledger) = args
+        .ledger
+        .map(CostLedger::new)
+        .or_else(CostLedger::from_env)
+    else {
+        bail!("no cost ledger: pass --ledger or set BROOD_COST_LEDGER");
+    };
+    let rows = summarize_costs(&ledger.entries()?, since, group_by);
+    print!(
+        "{}",
+        render_cost_report(&rows, group_by, args.since.as_deref(), args.format)?
+    );
+    Ok(0)
+}
+
+fn run_auth_native(action: AuthAction) -> Result<i32> {
+    let config = ProviderConfig::load()?;
+    let known = config.provider_names();
+    let provider = |args: &AuthProviderArgs| {
+        let name = args.provider.trim().to_ascii_lowercase();
+        if !known.contains(&name) {
+            bail!(
+                "unknown provider '{}' (known: {})",
+                args.provider,
+                known.join(", ")
+            );
+        }
+        Ok(name)
+    };
+    match action {
+        AuthAction::Set(args) => {
+            let name = provider(&args)?;
+            let key = line_editor::read_secret(&format!("{name} API key: "))?;
+            store_keychain_key(&name, &key)?;
+            println!("stored the {name} key in the keychain");
+        }
+        AuthAction::List => {
+            for name in &known {
+                let source = api_key_source(&config.settings(name));
+                println!("{name:<12} {}", source.as_deref().unwrap_or("-"));
+            }
+        }
+        AuthAction::Test(args) => {
+            let name = provider(&args)?;
+            let settings = config.settings(&name);
+            let source = api_key_source(&settings).unwrap_or_default();
+            if verify_api_key(&settings)? {
+                println!("{name} key from {source} works");
+            } else {
+                println!("{name} key from {source} found; {name} has no check endpoint");
+            }
+        }
+        AuthAction::Remove(args) => {
+            let name = provider(&args)?;
+            if remove_keychain_key(&name)? {
+                println!("removed the {name} key from the keychain");
+            } else {
+                println!("no {name} key in the keychain");
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn run_pricing_update_native(args: PricingUpdateArgs) -> Result<i32> {
+    let env = |key: &str| {
+        std::env::var(key)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+    };
+    let Some(url) = args.url.or_else(|| env(PRICING_URL_ENV)) else {
+        bail!("no pricing manifest URL: pass --url or set {PRICING_URL_ENV}");
+    };
+    let Some(public_key) = args.public_key.or_else(|| env(PRICING_PUBLIC_KEY_ENV)) else {
+        bail!("no pricing public key: pass --public-key or set {PRICING_PUBLIC_KEY_ENV}");
+    };
+    let Some(path) = remote_pricing_path() else {
+        bail!("HOME is not set; cannot cache remote pricing");
+    };
+    let update = update_pricing(&PricingSource::new(&url, &public_key)?, &path)?;
+    println!(
+        "pricing manifest v{}{} ({} models){} -> {}",
+        update.version,
+        update
+            .issued_at
+            .as_deref()
+            .map(|issued_at| format!(" issued {issued_at}"))
+            .unwrap_or_default(),
+        update.models,
+        if update.changed { "" } else { ", unchanged" },
+        update.path.display()
+    );
+    Ok(0)
+}
+
+/// `table` for people, `csv` for spreadsheets, `json` for scripts; each
+/// ends with the total.
+fn render_cost_report(
+    rows: &[CostReportRow],
+    group_by: CostGroupBy,
+    since: Option<&str>,
+    format: CostReportFormat,
+) -> Result<String> {
+    let generations: u64 = rows.iter().map(|row| row.generations).sum();
+    let cost_usd: f64 = rows.iter().map(|row| row.cost_usd).sum();
+    let label = group_by.label();
+    let mut out = String::new();
+    match format {
+        CostReportFormat::Table => {
+            let width = rows
+                .iter()
+                .map(|row| row.key.chars().count())
+                .chain([label.len(), "total".len()])
+                .max()
+                .unwrap_or(0);
+            out.push_str(&format!(
+                "{label:<width$}  {:>11}  {:>12}\n",
+                "generations", "cost_usd"
+            ));
+            for row in rows {
+                out.push_str(&format!(
+                    "{:<width$}  {:>11}  {:>12}\n",
+                    row.key,
+                    row.generations,
+                    format!("${:.4}", row.cost_usd)
+                ));
+            }
+            out.push_str(&format!(
+                "{:<width$}  {generations:>11}  {:>12}\n",
+                "total",
+                format!("${cost_usd:.4}")
+            ));
+        }
+        CostReportFormat::Csv => {
+            let quote = |field: &str| {
+                if field.contains([',', '"', '\n']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            };
+            out.push_str(&format!("{label},generations,cost_usd\n"));
+            for row in rows {
+                out.push_str(&format!(
+                    "{},{},{:.6}\n",
+                    quote(&row.key),
+                    row.generations,
+                    row.cost_usd
+                ));
+            }
+            out.push_str(&format!("total,{generations},{cost_usd:.6}\n"));
+        }
+        CostReportFormat::Json => {
+            let rows: Vec<Value> = rows
+                .iter()
+                .map(|row| {
+                    json!({
+                        label: row.key,
+                        "generations": row.generations,
+                        "cost_usd": row.cost_usd,
+                    })
+                })
+                .collect();
+            out.push_str(&serde_json::to_string_pretty(&json!({
+                "group_by": label,
+                "since": since,
+                "rows": rows,
+                "total_generations": generations,
+                "total_cost_usd": cost_usd,
+            }))?);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+fn run_serve_native(args: ServeArgs) -> Result<i32> {
+    if args.force_unlock && args.runs_dir.is_dir() {
+        for entry in fs::read_dir(&args.runs_dir)?.flatten() {
+            let run_dir = entry.path();
+            if !run_dir.is_dir() {
+                continue;
+            }
+            if let Some(pid) = force_unlock_run_dir(&run_dir)? {
+                println!(
+                    "Removed the lock held by pid {pid} on {}.",
+                    run_dir.display()
+                );
+            }
+        }
+    }
+    let token_configured =
+        args.token.is_some() || first_non_empty_env(&[serve::TOKEN_ENV]).is_some();
+    let server = serve::HttpServer::bind(
+        &args.http,
+        &args.runs_dir,
+        serve::ServeOptions {
+            text_model: Some(args.text_model),
+            image_model: args.image_model,
+            workers: args.workers,
+            token: args.token,
+            public_url: args.public_url.clone(),
+            client_tokens: match &args.client_tokens {
+                Some(path) => serve::load_client_tokens(path)?,
+                None => Default::default(),
+            },
+            queue_limit: args.queue_limit,
+        },
+    )?;
+    println!(
+        "Serving {} on http://{}",
+        args.runs_dir.display(),
+        server.local_addr()?
+    );
+    if !token_configured {
+        println!("API token: {}", server.token());
+    }
+    if let Some(public_url) = &args.public_url {
+        server.register_fal_webhook(public_url);
+        println!(
+            "Fal webhooks: {}/webhooks/fal",
+            public_url.trim_end_matches('/')
+        );
+    }
+    server.run()?;
+    Ok(0)
+}
+
+fn run_migrate_native(args: MigrateArgs) -> Result<i32> {
+    // Held across both steps: a session writing the run meanwhile would
+    // lose its writes or read half-moved files. A dry run writes nothing.
+    let _lock =
+        if args.dry_run || !args.run.is_dir() {
+            None
+        } else {
+            Some(RunLock::acquire(&args.run).with_context(|| {
+                format!("cannot migrate {} while it is open", args.run.display())
+            })?)
+        };
+    let report = migrate_run_dir(&args.run, args.dry_run)?;
+    let verb = if report.dry_run {
+        "would upgrade"
+    } else {
+        "upgraded"
+    };
+    for step in &report.upgraded {
+        println!(
+            "{verb} {} {} (schema {} -> {})",
+            step.kind.label(),
+            step.path.display(),
+            step.from,
+            step.to
+        );
+    }
+    println!(
+        "{} file(s) {verb}, {} already current.",
+        report.upgraded.len(),
+        report.up_to_date
+    );
+    match args.layout {
+        None => {}
+        Some(RunLayout::Flat) => {
+            let thread = ThreadManifest::load(args.run.join("thread.json"))?;
+            if thread.layout != RunLayout::Flat {
+                bail!("per-version runs cannot be moved back to the flat layout");
+            }
+        }
+        Some(RunLayout::PerVersion) => {
+            let moved = migrate_to_per_version(&args.run, args.dry_run)?;
+            let verb = if moved.dry_run { "would move" } else { "moved" };
+            for (from, to) in &moved.moved {
+                println!("{verb} {} -> {}", from.display(), to.display());
+            }
+            println!(
+                "{} file(s) {verb} into version directories, {} path record(s) rewritten.",
+                moved.moved.len(),
+                moved.rewritten.len()
+            );
+        }
+    }
+    Ok(0)
+}
+
+fn run_sync_native(args: SyncArgs) -> Result<i32> {
+    let store = match &args.store {
+        Some(url) => artifact_store_from_url(url)?,
+        None => match artifact_store_from_env()? {
+            Some(store) => store,
+            None => bail!("no artifact store: pass --store or set {ARTIFACT_STORE_ENV}"),
+        },
+    };
+    let summary = sync_run(&args.run, store.as_ref(), args.force)?;
+    for (artifact_id, error) in &summary.failed {
+        println!("failed {artifact_id}: {error}");
+    }
+    println!(
+        "Uploaded {} artifact(s) to {}, {} already synced, {} failed.",
+        summary.uploaded.len(),
+        store.location(),
+        summary.skipped.len(),
+        summary.failed.len()
+    );
+    Ok(if summary.failed.is_empty() { 0 } else { 1 })
+}
+
 fn run_export_native(args: ExportArgs) -> Result<i32> {
-    export_html_native(&args.run, &args.out)?;
+    if let Some(set) = &args.renditions {
+        let events_path = args.run.join("events.jsonl");
+        let mut engine = NativeEngine::resume(&args.run, &events_path, None, None)?;
+        let renditions =
+            engine.export_renditions(args.artifact.as_deref(), set, Some(&args.out))?;
+        for rendition in &renditions {
+            println!(
+                "{} {}x{} from {} ({} crop): {}",
+                rendition.target.name,
+                rendition.target.width,
+                rendition.target.height,
+                rendition.source_artifact_id,
+                rendition.focus,
+                rendition.path.display()
+            );
+        }
+        println!(
+            "Exported {} rendition(s) to {}",
+            renditions.len(),
+            args.out.display()
+        );
+        return Ok(0);
+    }
+    match args.format.trim().to_ascii_lowercase().as_str() {
+        "html" => export_html_native(&args.run, &args.out)?,
+        "gallery" => {
+            let count = gallery::export_gallery(&args.run, &args.out)?;
+            println!("Gallery includes {count} artifact(s).");
+        }
+        "pdf" => {
+            let count = contact_sheet::export_contact_sheet(&args.run, &args.out)?;
+            println!("Contact sheet includes {count} artifact(s).");
+        }
+        other => bail!("unknown export format '{other}' (expected html, gallery or pdf)"),
+    }
     println!("Exported to {}", args.out.display());
     Ok(0)
 }
 
+/// Makes the style profile file for `name` the engine's active profile (or
+/// clears it when there is none) and describes the outcome.
+fn load_style_profile(engine: &mut NativeEngine, name: &str) -> String {
+    match StyleProfile::load(name) {
+        Ok(Some(style)) => {
+            let mut parts = Vec::new();
+            if !style.palette.is_empty() {
+                parts.push(format!("palette {}", style.palette.join(" ")));
+            }
+            if let Some(logo) = &style.logo {
+                parts.push(format!("logo {}", logo.display()));
+            }
+            if !style.tone_words.is_empty() {
+                parts.push(format!("tone {}", style.tone_words.join(", ")));
+            }
+            if !style.negative_prompts.is_empty() {
+                parts.push(format!("avoid {}", style.negative_prompts.join(", ")));
+            }
+            if !style.provider_options.is_empty() {
+                parts.push(format!(
+                    "provider options {}",
+                    Value::Object(style.provider_options.clone())
+                ));
+            }
+            let line = format!(
+                "Style profile {} applies to every generation: {}",
+                style.path.display(),
+                if parts.is_empty() {
+                    "nothing set".to_string()
+                } else {
+                    parts.join("; ")
+                }
+            );
+            engine.set_style_profile(Some(style));
+            line
+        }
+        Ok(None) => {
+            engine.set_style_profile(None);
+            match StyleProfile::default_dir() {
+                Some(dir) => format!(
+                    "No style profile {name}.json in {} (available: {})",
+                    dir.display(),
+                    StyleProfile::available(&dir).join(", ")
+                ),
+                None => "No style profile directory (HOME is not set)".to_string(),
+            }
+        }
+        Err(err) => {
+            engine.set_style_profile(None);
+            format!("Style profile not applied: {err:#}")
+        }
+    }
+}
+
 fn chat_settings(quality_preset: &str) -> Map<String, Value> {
     let mut settings = Map::new();
     settings.insert("size".to_string(), Value::String("1024x1024".to_string()));
@@ -2008,8 +4301,7 @@ fn describe_local_image(path: &Path, max_chars: usize) -> String {
         .file_stem()
         .and_then(|value| value.to_str())
         .unwrap_or("image")
-        .replace('_', " ")
-        .replace('-', " ");
+        .replace(['_', '-'], " ");
     let base = if stem.trim().is_empty() {
         "image".to_string()
     } else {
@@ -2046,6 +4338,12 @@ fn format_latency(value: Option<f64>) -> String {
     }
 }
 
+fn print_plan_limits(plan: &PlanPreview) {
+    for reason in &plan.unsupported {
+        println!("Cannot honor: {reason}");
+    }
+}
+
 fn print_generation_cost_latency(engine: &NativeEngine) {
     let cost = engine
         .last_cost_latency()
@@ -2074,27 +4372,6 @@ fn update_last_artifact_path(
     }
 }
 
-fn active_image_for_edit_prompt(prompt: &str, active_image_path: Option<&str>) -> Option<String> {
-    if !is_edit_style_prompt(prompt) {
-        return None;
-    }
-    let path = active_image_path
-        .map(str::trim)
-        .filter(|value| !value.is_empty())?;
-    let candidate = PathBuf::from(path);
-    if candidate.exists() && candidate.is_file() {
-        Some(path.to_string())
-    } else {
-        None
-    }
-}
-
-fn is_edit_style_prompt(prompt: &str) -> bool {
-    let mut tokens = prompt.split_whitespace();
-    let head = tokens.next().unwrap_or("").trim().to_ascii_lowercase();
-    matches!(head.as_str(), "edit" | "replace")
-}
-
 fn value_as_string_list(value: Option<&Value>) -> Vec<String> {
     value
         .and_then(Value::as_array)
@@ -2106,13 +4383,21 @@ fn value_as_string_list(value: Option<&Value>) -> Vec<String> {
         .collect()
 }
 
+/// Soft-deleted or reverted versions (`deleted_at` / `reverted_at` set) stay
+/// in thread.json for audit but are skipped by history lookups and exports.
+fn is_deleted_version(version: &Value) -> bool {
+    ["deleted_at", "reverted_at"]
+        .iter()
+        .any(|key| version.get(*key).is_some_and(|value| !value.is_null()))
+}
+
 fn latest_thread_version(run_dir: &Path) -> Option<Map<String, Value>> {
     let thread_path = run_dir.join("thread.json");
     let payload = read_json_object(&thread_path)?;
     payload
         .get("versions")
         .and_then(Value::as_array)
-        .and_then(|rows| rows.last())
+        .and_then(|rows| rows.iter().rev().find(|row| !is_deleted_version(row)))
         .and_then(Value::as_object)
         .cloned()
 }
@@ -2135,6 +4420,24 @@ fn latest_thread_version_id(run_dir: &Path) -> Option<String> {
         .map(str::to_string)
 }
 
+fn latest_thread_artifact_id(run_dir: &Path) -> Option<String> {
+    let payload = read_json_object(&run_dir.join("thread.json"))?;
+    payload
+        .get("versions")
+        .and_then(Value::as_array)?
+        .iter()
+        .rev()
+        .filter(|version| !is_deleted_version(version))
+        .filter_map(|version| version.get("artifacts").and_then(Value::as_array))
+        .find_map(|artifacts| {
+            artifacts
+                .last()
+                .and_then(|artifact| artifact.get("artifact_id"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+}
+
 fn latest_thread_settings(run_dir: &Path) -> Option<Map<String, Value>> {
     latest_thread_version(run_dir)?
         .get("settings")
@@ -2162,18 +4465,8 @@ fn latest_receipt_path(run_dir: &Path) -> Option<PathBuf> {
     }
 
     let mut newest: Option<(SystemTime, PathBuf)> = None;
-    let entries = fs::read_dir(run_dir).ok()?;
-    for entry in entries.flatten() {
-        let path = entry.path();
-        let name = path
-            .file_name()
-            .and_then(|row| row.to_str())
-            .unwrap_or_default();
-        if !(name.starts_with("receipt-") && name.ends_with(".json")) {
-            continue;
-        }
-        let modified = entry
-            .metadata()
+    for path in receipt_files(run_dir).ok()? {
+        let modified = fs::metadata(&path)
             .ok()
             .and_then(|meta| meta.modified().ok())
             .unwrap_or(UNIX_EPOCH);
@@ -2453,15 +4746,6 @@ fn read_json_value(path: &Path) -> Option<Value> {
     serde_json::from_str(&raw).ok()
 }
 
-fn write_json_value(path: &Path, value: &Value) -> Result<()> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-    let encoded = serde_json::to_string_pretty(value)?;
-    fs::write(path, encoded)?;
-    Ok(())
-}
-
 fn snapshot_sidecar_path(snapshot_path: &Path) -> PathBuf {
     let mut out = snapshot_path.to_path_buf();
     if let Some(stem) = snapshot_path.file_stem().and_then(|value| value.to_str()) {
@@ -2716,8 +5000,7 @@ fn humanize_file_name(file: &str) -> String {
         .file_stem()
         .and_then(|value| value.to_str())
         .unwrap_or(file)
-        .replace('_', " ")
-        .replace('-', " ");
+        .replace(['_', '-'], " ");
     let cleaned = stem
         .split_whitespace()
         .collect::<Vec<&str>>()
@@ -3896,11 +6179,14 @@ impl RealtimeWorker {
             "generationConfig": Value::Object(generation_config),
         });
         let endpoint = gemini_generate_content_endpoint(&self.model);
-        let client = HttpClient::builder()
-            .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
-            .build()
+        let client = http_client_builder()
+            .and_then(|builder| {
+                Ok(builder
+                    .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
+                    .build()?)
+            })
             .map_err(|err| {
-                RealtimeJobError::terminal(format!("failed to build realtime http client: {err}"))
+                RealtimeJobError::terminal(format!("failed to build realtime http client: {err:#}"))
             })?;
         let response = client
             .post(&endpoint)
@@ -4034,6 +6320,7 @@ impl RealtimeWorker {
         self.request_openrouter_chat_completion_realtime(chat_content)
     }
 
+    #[allow(clippy::type_complexity)]
     fn try_openrouter_responses_realtime(
         &self,
         chat_content: &[Value],
@@ -4053,11 +6340,14 @@ impl RealtimeWorker {
             "max_output_tokens": self.kind.max_output_tokens(),
             "stream": false,
         });
-        let client = HttpClient::builder()
-            .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
-            .build()
+        let client = http_client_builder()
+            .and_then(|builder| {
+                Ok(builder
+                    .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
+                    .build()?)
+            })
             .map_err(|err| {
-                RealtimeJobError::terminal(format!("failed to build realtime http client: {err}"))
+                RealtimeJobError::terminal(format!("failed to build realtime http client: {err:#}"))
             })?;
         let request = client
             .post(&endpoint)
@@ -4134,11 +6424,14 @@ impl RealtimeWorker {
             "max_tokens": self.kind.max_output_tokens(),
             "stream": false,
         });
-        let client = HttpClient::builder()
-            .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
-            .build()
+        let client = http_client_builder()
+            .and_then(|builder| {
+                Ok(builder
+                    .timeout(Duration::from_secs_f64(REALTIME_TIMEOUT_SECONDS))
+                    .build()?)
+            })
             .map_err(|err| {
-                RealtimeJobError::terminal(format!("failed to build realtime http client: {err}"))
+                RealtimeJobError::terminal(format!("failed to build realtime http client: {err:#}"))
             })?;
         let request = client
             .post(&endpoint)
@@ -6404,7 +8697,7 @@ fn read_basic_image_stats(path: &Path) -> Option<BasicImageStats> {
     }
 
     let mut dominant: Vec<((u8, u8, u8), u64)> = bins.into_iter().collect();
-    dominant.sort_by(|left, right| right.1.cmp(&left.1));
+    dominant.sort_by_key(|entry| std::cmp::Reverse(entry.1));
     let mut palette: Vec<String> = dominant
         .into_iter()
         .take(6)
@@ -6681,7 +8974,7 @@ fn infer_triplet_odd_one_out(
     path_b: &Path,
     path_c: &Path,
 ) -> TripletOddOneOutOutput {
-    let stats = vec![
+    let stats = [
         read_basic_image_stats(path_a),
         read_basic_image_stats(path_b),
         read_basic_image_stats(path_c),
@@ -6811,6 +9104,91 @@ struct IntentIconsVisionInference {
     output_tokens: Option<i64>,
 }
 
+/// Mirrors events to stderr (`--events-stderr`) and to `BROOD_EVENT_WS_URL`,
+/// filtered by `BROOD_EVENT_WS_FILTER` (same spec syntax), and notifies on
+/// run completion or failure when `BROOD_NOTIFY_DESKTOP` or
+/// `BROOD_NOTIFY_WEBHOOK_URL` is set.
+fn attach_event_sinks(engine: &NativeEngine, stderr_spec: Option<&str>) -> Result<()> {
+    if let Some(spec) = stderr_spec {
+        let filter = EventFilter::parse(spec)?;
+        engine
+            .events()
+            .add_sink(Box::new(JsonLineSink::new(io::stderr())), filter)?;
+    }
+    if let Some(url) = first_non_empty_env(&["BROOD_EVENT_WS_URL"]) {
+        let filter = EventFilter::parse(
+            &first_non_empty_env(&["BROOD_EVENT_WS_FILTER"]).unwrap_or_default(),
+        )?;
+        engine
+            .events()
+            .add_sink(Box::new(event_ws::WebSocketEventSink::new(&url)), filter)?;
+    }
+    let desktop = first_non_empty_env(&["BROOD_NOTIFY_DESKTOP"]).is_some_and(|raw| {
+        matches!(
+            raw.to_ascii_lowercase().as_str(),
+            "1" | "true" | "on" | "yes"
+        )
+    });
+    let webhook_url = first_non_empty_env(&["BROOD_NOTIFY_WEBHOOK_URL"]);
+    if desktop || webhook_url.is_some() {
+        engine.events().add_sink(
+            Box::new(notify::RunNotifier::new(desktop, webhook_url)?),
+            notify::RunNotifier::filter(),
+        )?;
+    }
+    Ok(())
+}
+
+/// `BROOD_RUN_BUDGET_USD` / `BROOD_SESSION_BUDGET_USD` override the caps; a
+/// run budget already stored in the run dir is kept when the env is unset.
+fn apply_cost_budget_env(engine: &mut NativeEngine) -> Result<()> {
+    let parse = |key: &str| -> Result<Option<f64>> {
+        first_non_empty_env(&[key])
+            .map(|raw| {
+                raw.trim_start_matches('$')
+                    .parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("{key} must be a USD amount, got '{raw}'"))
+            })
+            .transpose()
+    };
+    let run_usd = parse("BROOD_RUN_BUDGET_USD")?;
+    let session_usd = parse("BROOD_SESSION_BUDGET_USD")?;
+    if run_usd.is_none() && session_usd.is_none() {
+        return Ok(());
+    }
+    let current = engine.cost_budget();
+    engine.set_cost_budget(CostBudget {
+        run_usd: run_usd.or(current.run_usd),
+        session_usd: session_usd.or(current.session_usd),
+    })
+}
+
+/// `BROOD_GLOBAL_CACHE=1` enables the cross-run cache at `~/.brood/cache`;
+/// any other non-boolean value is used as the cache directory.
+fn global_cache_from_env() -> Option<GlobalCache> {
+    let raw = first_non_empty_env(&["BROOD_GLOBAL_CACHE"])?;
+    match raw.to_ascii_lowercase().as_str() {
+        "0" | "false" | "off" | "no" => None,
+        "1" | "true" | "on" | "yes" => GlobalCache::default_root().map(GlobalCache::new),
+        _ => Some(GlobalCache::new(raw)),
+    }
+}
+
+fn print_cost_budget(engine: &NativeEngine) {
+    let budget = engine.cost_budget();
+    let (run_spent, session_spent) = engine.cost_spent_usd();
+    let cap = |value: Option<f64>| {
+        value
+            .map(|usd| format!("${usd:.2}"))
+            .unwrap_or_else(|| "none".to_string())
+    };
+    println!(
+        "Budget: run ${run_spent:.4} / {}, session ${session_spent:.4} / {}",
+        cap(budget.run_usd),
+        cap(budget.session_usd)
+    );
+}
+
 fn first_non_empty_env(keys: &[&str]) -> Option<String> {
     for key in keys {
         if let Ok(value) = env::var(key) {
@@ -6899,7 +9277,7 @@ fn openai_vision_request(
     timeout: Duration,
 ) -> Option<(String, Option<i64>, Option<i64>, String)> {
     let request_model = sanitize_openai_responses_model(model, OPENAI_VISION_FALLBACK_MODEL);
-    let client = HttpClient::builder().timeout(timeout).build().ok()?;
+    let client = http_client_builder().ok()?.timeout(timeout).build().ok()?;
     if let Some(api_key) = openai_api_key() {
         let endpoint = format!("{}/responses", openai_api_base());
         let payload = json!({
@@ -7539,9 +9917,10 @@ fn compact_caption_phrase(text: &str) -> String {
             .enumerate()
             .filter_map(|(idx, token)| {
                 let lower = token.to_ascii_lowercase();
-                if idx > 0 && idx < last_idx && is_aux_verb_token(lower.as_str()) {
-                    None
-                } else if idx > 0 && is_article_token(lower.as_str()) {
+                if idx > 0
+                    && ((idx < last_idx && is_aux_verb_token(lower.as_str()))
+                        || is_article_token(lower.as_str()))
+                {
                     None
                 } else {
                     Some(token)
@@ -7582,7 +9961,7 @@ fn clean_description(text: &str, max_chars: usize) -> String {
     cleaned = cleaned
         .trim()
         .trim_matches(|ch: char| matches!(ch, '"' | '\''))
-        .trim_end_matches(|ch: char| matches!(ch, '.' | ',' | ':' | ';'))
+        .trim_end_matches(['.', ',', ':', ';'])
         .trim()
         .to_string();
     if cleaned.is_empty() {
@@ -7727,6 +10106,7 @@ fn normalize_hex_color(value: &str) -> Option<String> {
     Some(format!("#{}", body.to_ascii_uppercase()))
 }
 
+#[allow(clippy::type_complexity)]
 fn parse_dna_payload(
     payload: &Map<String, Value>,
 ) -> Option<(Vec<String>, Vec<String>, Vec<String>, String)> {
@@ -8735,330 +11115,38 @@ fn run_native_recreate_loop(
     quality_preset: &str,
     images_per_iteration: u64,
 ) -> Result<Map<String, Value>> {
-    if !reference_path.exists() {
-        bail!("reference file not found ({})", reference_path.display());
-    }
-
-    let (base_prompt, prompt_source, caption_model) = infer_recreate_prompt(reference_path);
-    engine.emit_event(
-        "recreate_prompt_inferred",
-        json_object(json!({
-            "reference": reference_path.to_string_lossy().to_string(),
-            "prompt": base_prompt,
-            "source": prompt_source,
-            "model": caption_model,
-        })),
-    )?;
-
-    let mut prompt = base_prompt.clone();
-    let mut best_artifact: Option<Map<String, Value>> = None;
-    let mut best_score = 0.0f64;
-    let mut iterations_run = 0i64;
-    let mut failure: Option<String> = None;
-
-    for iteration in 1..=3 {
-        iterations_run = iteration;
-        let mut settings = chat_settings(quality_preset);
-        settings.insert(
-            "n".to_string(),
-            Value::Number(images_per_iteration.max(1).into()),
-        );
-        settings.insert(
-            "reference_images".to_string(),
-            Value::Array(vec![Value::String(
-                reference_path.to_string_lossy().to_string(),
-            )]),
-        );
-
-        let mut recreate_intent = Map::new();
-        recreate_intent.insert("action".to_string(), Value::String("recreate".to_string()));
-        recreate_intent.insert(
-            "reference".to_string(),
-            Value::String(reference_path.to_string_lossy().to_string()),
-        );
-        recreate_intent.insert("iteration".to_string(), Value::Number(iteration.into()));
-        recreate_intent.insert(
-            "base_prompt".to_string(),
-            Value::String(base_prompt.clone()),
-        );
-        recreate_intent.insert(
-            "prompt_source".to_string(),
-            Value::String(prompt_source.clone()),
-        );
-        recreate_intent.insert(
-            "caption_model".to_string(),
-            caption_model
-                .as_ref()
-                .map(|value| Value::String(value.clone()))
-                .unwrap_or(Value::Null),
-        );
-
-        let artifacts = match engine.generate(&prompt, settings, recreate_intent) {
-            Ok(artifacts) => artifacts,
-            Err(err) => {
-                failure = Some(err.to_string());
-                break;
-            }
-        };
-
-        for artifact in artifacts {
-            let image_path = artifact
-                .get("image_path")
-                .and_then(Value::as_str)
-                .map(PathBuf::from);
-            let receipt_path = artifact
-                .get("receipt_path")
-                .and_then(Value::as_str)
-                .map(PathBuf::from);
-            let Some(image_path) = image_path else {
-                continue;
-            };
-
-            let similarity = match compare_similarity(reference_path, &image_path) {
-                Ok(similarity) => similarity,
-                Err(err) => {
-                    failure = Some(err.to_string());
-                    break;
-                }
-            };
-
-            if let Some(receipt_path) = receipt_path {
-                if let Err(err) = write_similarity_to_receipt(&receipt_path, &similarity) {
-                    failure = Some(err.to_string());
-                    break;
-                }
-            }
-
-            let overall = similarity
-                .get("overall")
-                .and_then(Value::as_f64)
-                .unwrap_or(0.0);
-            let mut enriched = artifact.clone();
-            enriched.insert("similarity".to_string(), Value::Object(similarity));
-            if best_artifact.is_none() || overall > best_score {
-                best_score = overall;
-                best_artifact = Some(enriched);
-            }
-        }
-
-        if failure.is_some() {
-            break;
-        }
-
-        let best_artifact_id = best_artifact
-            .as_ref()
-            .and_then(|artifact| artifact.get("artifact_id"))
-            .cloned()
-            .unwrap_or(Value::Null);
-        engine.emit_event(
-            "recreate_iteration_update",
-            json_object(json!({
-                "iteration": iteration,
-                "similarity": best_score,
-                "best_artifact_id": best_artifact_id,
-            })),
-        )?;
-
-        if best_score >= 0.8 {
-            break;
-        }
-        prompt = format!("{prompt} Refine to better match the reference image.")
-            .trim()
-            .to_string();
-    }
-
-    let best_artifact_id = best_artifact
-        .as_ref()
-        .and_then(|artifact| artifact.get("artifact_id"))
-        .cloned()
-        .unwrap_or(Value::Null);
-    engine.emit_event(
-        "recreate_done",
-        json_object(json!({
-            "reference": reference_path.to_string_lossy().to_string(),
-            "best_artifact_id": best_artifact_id,
-            "best_score": best_score,
-            "iterations": iterations_run,
-            "success": failure.is_none(),
-            "error": failure,
-        })),
+    let outcome = engine.recreate(
+        reference_path,
+        &RecreateOptions {
+            settings: chat_settings(quality_preset),
+            images_per_iteration,
+            ..RecreateOptions::default()
+        },
     )?;
-
-    if let Some(err) = failure {
-        bail!(err);
-    }
-
     let mut out = Map::new();
     out.insert(
         "best".to_string(),
-        best_artifact.map(Value::Object).unwrap_or(Value::Null),
+        outcome.best.map(Value::Object).unwrap_or(Value::Null),
+    );
+    out.insert("best_score".to_string(), json!(outcome.best_score));
+    out.insert("inferred_prompt".to_string(), Value::String(outcome.prompt));
+    out.insert(
+        "prompt_source".to_string(),
+        Value::String(outcome.spec.source.clone()),
     );
-    out.insert("best_score".to_string(), json!(best_score));
-    out.insert("inferred_prompt".to_string(), Value::String(base_prompt));
-    out.insert("prompt_source".to_string(), Value::String(prompt_source));
     out.insert(
         "caption_model".to_string(),
-        caption_model.map(Value::String).unwrap_or(Value::Null),
+        outcome
+            .spec
+            .model
+            .clone()
+            .map(Value::String)
+            .unwrap_or(Value::Null),
     );
+    out.insert("spec".to_string(), outcome.spec.to_value());
     Ok(out)
 }
 
-fn infer_recreate_prompt(reference_path: &Path) -> (String, String, Option<String>) {
-    if let Some((prompt, model)) = infer_prompt_from_receipts(reference_path) {
-        return (prompt, "receipt".to_string(), model);
-    }
-    let file_name = reference_path
-        .file_name()
-        .and_then(|value| value.to_str())
-        .map(str::trim)
-        .filter(|value| !value.is_empty())
-        .unwrap_or("reference image")
-        .to_string();
-    (
-        format!("Recreate an image similar to {file_name}."),
-        "fallback".to_string(),
-        None,
-    )
-}
-
-fn infer_prompt_from_receipts(reference_path: &Path) -> Option<(String, Option<String>)> {
-    let parent = reference_path.parent()?;
-    let target = reference_path.to_string_lossy().to_string();
-    let canonical_target = fs::canonicalize(reference_path).ok();
-
-    let entries = fs::read_dir(parent).ok()?;
-    for entry in entries {
-        let entry = entry.ok()?;
-        let path = entry.path();
-        if path.extension().and_then(|value| value.to_str()) != Some("json") {
-            continue;
-        }
-        let file_name = path
-            .file_name()
-            .and_then(|value| value.to_str())
-            .unwrap_or("");
-        if !file_name.starts_with("receipt-") {
-            continue;
-        }
-        let payload = read_json_object(&path)?;
-        let image_path = payload
-            .get("artifacts")
-            .and_then(Value::as_object)
-            .and_then(|row| row.get("image_path"))
-            .and_then(Value::as_str)
-            .unwrap_or_default()
-            .to_string();
-        let same_path = if image_path == target {
-            true
-        } else if let (Some(expected), Ok(actual)) =
-            (canonical_target.as_ref(), fs::canonicalize(&image_path))
-        {
-            actual == *expected
-        } else {
-            false
-        };
-        if !same_path {
-            continue;
-        }
-        let prompt = payload
-            .get("request")
-            .and_then(Value::as_object)
-            .and_then(|row| row.get("prompt"))
-            .and_then(Value::as_str)
-            .map(str::trim)
-            .filter(|value| !value.is_empty())
-            .map(str::to_string)?;
-        let model = payload
-            .get("resolved")
-            .and_then(Value::as_object)
-            .and_then(|row| row.get("model"))
-            .and_then(Value::as_str)
-            .map(str::trim)
-            .filter(|value| !value.is_empty())
-            .map(str::to_string);
-        return Some((prompt, model));
-    }
-    None
-}
-
-fn compare_similarity(reference: &Path, candidate: &Path) -> Result<Map<String, Value>> {
-    let dh_ref = dhash64(reference)?;
-    let dh_can = dhash64(candidate)?;
-    let dh_score = score_hash(dh_ref, dh_can, 64);
-
-    let ph_ref = ahash64(reference)?;
-    let ph_can = ahash64(candidate)?;
-    let ph_score = score_hash(ph_ref, ph_can, 64);
-
-    let overall = ((dh_score + ph_score) / 2.0).clamp(0.0, 1.0);
-    Ok(json_object(json!({
-        "dhash": dh_score,
-        "phash": ph_score,
-        "overall": overall,
-    })))
-}
-
-fn dhash64(path: &Path) -> Result<u64> {
-    let resized = image::open(path)
-        .with_context(|| format!("failed to read image for dhash ({})", path.display()))?
-        .resize_exact(9, 8, FilterType::Triangle)
-        .to_luma8();
-    let mut value = 0u64;
-    for y in 0..8u32 {
-        for x in 0..8u32 {
-            let left = resized.get_pixel(x, y)[0];
-            let right = resized.get_pixel(x + 1, y)[0];
-            value = (value << 1) | if left > right { 1 } else { 0 };
-        }
-    }
-    Ok(value)
-}
-
-fn ahash64(path: &Path) -> Result<u64> {
-    let resized = image::open(path)
-        .with_context(|| format!("failed to read image for ahash ({})", path.display()))?
-        .resize_exact(8, 8, FilterType::Triangle)
-        .to_luma8();
-    let mut sum = 0u64;
-    for pixel in resized.pixels() {
-        sum += pixel[0] as u64;
-    }
-    let avg = (sum as f64 / 64.0).clamp(0.0, 255.0);
-    let mut value = 0u64;
-    for y in 0..8u32 {
-        for x in 0..8u32 {
-            let sample = resized.get_pixel(x, y)[0] as f64;
-            value = (value << 1) | if sample > avg { 1 } else { 0 };
-        }
-    }
-    Ok(value)
-}
-
-fn score_hash(left: u64, right: u64, bits: u32) -> f64 {
-    let distance = (left ^ right).count_ones() as f64;
-    (1.0 - (distance / bits as f64)).clamp(0.0, 1.0)
-}
-
-fn write_similarity_to_receipt(receipt_path: &Path, similarity: &Map<String, Value>) -> Result<()> {
-    let Some(mut payload) = read_json_value(receipt_path) else {
-        return Ok(());
-    };
-    let Some(root) = payload.as_object_mut() else {
-        return Ok(());
-    };
-    let meta = root
-        .entry("result_metadata".to_string())
-        .or_insert_with(|| Value::Object(Map::new()));
-    if !meta.is_object() {
-        *meta = Value::Object(Map::new());
-    }
-    if let Some(meta_obj) = meta.as_object_mut() {
-        meta_obj.insert("similarity".to_string(), Value::Object(similarity.clone()));
-    }
-    write_json_value(receipt_path, &payload)?;
-    Ok(())
-}
-
 fn export_html_native(run_dir: &Path, out_path: &Path) -> Result<()> {
     let thread_path = run_dir.join("thread.json");
     let versions = read_json_value(&thread_path)
@@ -9073,6 +11161,9 @@ fn export_html_native(run_dir: &Path, out_path: &Path) -> Result<()> {
 
     let mut cards = String::new();
     for version in versions {
+        if is_deleted_version(&version) {
+            continue;
+        }
         let Some(version_obj) = version.as_object() else {
             continue;
         };
@@ -9149,6 +11240,24 @@ fn value_as_non_empty_string(value: Option<&Value>) -> Option<String> {
     }
 }
 
+/// Aligned `command args  summary` rows for `/help` and the bare `/` palette.
+fn format_command_palette(entries: &[&ChatCommandHelp]) -> String {
+    let usage: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!("{} {}", entry.command, entry.args)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+    let width = usage.iter().map(String::len).max().unwrap_or(0);
+    let mut out = String::new();
+    for (usage, entry) in usage.iter().zip(entries) {
+        out.push_str(&format!("  {usage:<width$}  {}\n", entry.summary));
+    }
+    out
+}
+
 fn action_to_command_name(action: &str) -> Option<String> {
     match action {
         "set_profile" => Some("profile".to_string()),
@@ -9168,24 +11277,119 @@ fn json_object(value: Value) -> Map<String, Value> {
 #[cfg(test)]
 mod tests {
     use super::{
-        active_image_for_edit_prompt, build_realtime_websocket_request, clean_description,
-        default_realtime_model, description_realtime_instruction, extract_gemini_finish_reason,
-        extract_gemini_output_text, extract_gemini_token_usage_pair,
-        extract_openrouter_chat_output_text, intent_icons_instruction,
-        intent_realtime_reference_image_limit, is_anyhow_realtime_transport_error,
-        is_edit_style_prompt, openrouter_chat_content_to_responses_input,
-        openrouter_responses_content_to_chat_content, pseudo_random_seed,
+        build_realtime_websocket_request, clean_description, default_realtime_model,
+        description_realtime_instruction, extract_gemini_finish_reason, extract_gemini_output_text,
+        extract_gemini_token_usage_pair, extract_openrouter_chat_output_text,
+        format_command_palette, intent_icons_instruction, intent_realtime_reference_image_limit,
+        is_anyhow_realtime_transport_error, openrouter_chat_content_to_responses_input,
+        openrouter_responses_content_to_chat_content, pseudo_random_seed, render_cost_report,
         resolve_realtime_gemini_model_for_transport, resolve_streamed_response_text,
-        sanitize_gemini_generate_content_model, sanitize_openrouter_gemini_model,
-        sanitize_openrouter_model, should_fallback_openrouter_responses,
-        vision_description_model_candidates_for, RealtimeJobError, RealtimeJobErrorKind,
-        RealtimeProvider, RealtimeSessionKind, REALTIME_BETA_HEADER_VALUE,
+        run_chat_native, run_migrate_native, sanitize_gemini_generate_content_model,
+        sanitize_openrouter_gemini_model, sanitize_openrouter_model,
+        should_fallback_openrouter_responses, vision_description_model_candidates_for, ChatArgs,
+        ChatMemory, CostReportFormat, MigrateArgs, RealtimeJobError, RealtimeJobErrorKind,
+        RealtimeProvider, RealtimeSessionKind, SessionState, REALTIME_BETA_HEADER_VALUE,
         REALTIME_INTENT_REFERENCE_IMAGE_LIMIT_MAX,
     };
+    use brood_contracts::chat::command_palette;
+    use brood_contracts::runs::lock::RunLock;
+    use brood_engine::{CostGroupBy, CostReportRow};
     use serde_json::json;
+    use std::fs;
     use std::io;
-    use std::time::{SystemTime, UNIX_EPOCH};
-    use std::{env, fs};
+
+    #[test]
+    fn chat_exec_runs_commands_and_reports_failures() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let args = |out: &std::path::Path, resume: bool, exec: &[&str]| ChatArgs {
+            out: Some(out.to_path_buf()),
+            out_root: None,
+            resume,
+            force: false,
+            force_unlock: false,
+            events: None,
+            text_model: "dryrun-text-1".to_string(),
+            image_model: Some("dryrun-image-1".to_string()),
+            events_stderr: None,
+            exec: exec.iter().map(|line| line.to_string()).collect(),
+            deterministic: false,
+        };
+        let code = run_chat_native(args(
+            &run_dir,
+            false,
+            &["/generate a red chair --n 2 --size 32x32"],
+        ))?;
+        assert_eq!(code, 0);
+        let thread: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run_dir.join("thread.json"))?)?;
+        assert_eq!(thread["versions"][0]["prompt"], json!("a red chair"));
+        assert_eq!(
+            thread["versions"][0]["artifacts"].as_array().map(Vec::len),
+            Some(2)
+        );
+
+        let code = run_chat_native(args(&run_dir, true, &["/no_such_command"]))?;
+        assert_eq!(code, 1);
+
+        // Profile, quality, last prompt, active image and the conversation
+        // survive into a resumed chat.
+        let code = run_chat_native(args(&run_dir, true, &["/profile web", "/fast"]))?;
+        assert_eq!(code, 0);
+        let session: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run_dir.join("session.json"))?)?;
+        assert_eq!(session["profile"], json!("web"));
+        assert_eq!(session["quality_preset"], json!("fast"));
+        assert_eq!(session["last_prompt"], json!("a red chair"));
+        assert!(session["active_image"]
+            .as_str()
+            .is_some_and(|path| std::path::Path::new(path).is_file()));
+        assert_eq!(session["conversation"][0]["text"], json!("a red chair"));
+        let restored = ChatMemory::restore(&SessionState::load(&run_dir.join("session.json")));
+        assert_eq!(restored.profile, "web");
+        assert_eq!(restored.quality_preset, "fast");
+
+        // A selection on the active image limits the next prompt to a
+        // masked edit of it.
+        let code = run_chat_native(args(
+            &run_dir,
+            true,
+            &["/select rect 0,0 16x16", "a red balloon"],
+        ))?;
+        assert_eq!(code, 0);
+        let thread: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run_dir.join("thread.json"))?)?;
+        assert_eq!(thread["versions"][1]["intent"]["mode"], json!("inpaint"));
+        assert_eq!(thread["versions"][1]["parent_version_id"], json!("v1"));
+        assert!(thread["versions"][1]["intent"]["region"]["path"]
+            .as_str()
+            .is_some_and(|path| path.contains("select-")));
+
+        // A plain follow-up edits the active image; `/generate` opts out.
+        let code = run_chat_native(args(
+            &run_dir,
+            true,
+            &["make it darker", "/generate make it darker"],
+        ))?;
+        assert_eq!(code, 0);
+        let thread: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(run_dir.join("thread.json"))?)?;
+        assert_eq!(thread["versions"][2]["intent"]["action"], json!("edit"));
+        assert_eq!(thread["versions"][2]["parent_version_id"], json!("v2"));
+        assert_eq!(thread["versions"][3]["intent"]["action"], json!("generate"));
+        assert!(thread["versions"][3]["intent"]
+            .get("source_images")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn command_palette_rows_align_usage_and_summary() {
+        let rows = format_command_palette(&command_palette("model"));
+        let lines: Vec<&str> = rows.lines().collect();
+        assert_eq!(lines[0], "  /text_model <model>   Switch the text model");
+        assert_eq!(lines[1], "  /image_model <model>  Switch the image model");
+    }
 
     #[test]
     fn pseudo_random_seed_stays_in_range_and_is_not_pinned_to_max() {
@@ -9597,44 +11801,58 @@ mod tests {
     }
 
     #[test]
-    fn edit_style_prompt_detection_only_matches_edit_and_replace_heads() {
-        assert!(is_edit_style_prompt(
-            "edit the image: isolate subject and keep dimensions"
-        ));
-        assert!(is_edit_style_prompt(
-            "  REPLACE the background with pure white and preserve logos"
-        ));
-        assert!(!is_edit_style_prompt("editors choice cinematic portrait"));
-        assert!(!is_edit_style_prompt("generate a brand new scene"));
+    fn migrate_refuses_a_run_dir_in_use() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let args = |dry_run: bool| MigrateArgs {
+            run: temp.path().to_path_buf(),
+            dry_run,
+            layout: None,
+        };
+        let lock = RunLock::acquire(temp.path())?;
+        let err = run_migrate_native(args(false)).expect_err("run dir is locked");
+        assert!(format!("{err:#}").contains("in use by pid"), "{err:#}");
+        assert_eq!(run_migrate_native(args(true))?, 0);
+        drop(lock);
+        assert_eq!(run_migrate_native(args(false))?, 0);
+        Ok(())
     }
 
     #[test]
-    fn active_image_for_edit_prompt_requires_existing_file() {
-        let stamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|value| value.as_nanos())
-            .unwrap_or(0);
-        let test_path = env::temp_dir().join(format!("brood-cli-edit-path-{stamp}.png"));
-        fs::write(&test_path, b"test").unwrap();
-        let test_path_text = test_path.to_string_lossy().to_string();
-
-        let matched = active_image_for_edit_prompt(
-            "replace the background with seamless white",
-            Some(&test_path_text),
-        );
-        assert_eq!(matched, Some(test_path_text.clone()));
-        assert_eq!(
-            active_image_for_edit_prompt("generate a city skyline at dusk", Some(&test_path_text)),
-            None
-        );
+    fn cost_report_renders_table_csv_and_json() -> anyhow::Result<()> {
+        let rows = [
+            CostReportRow {
+                key: "openai".to_string(),
+                generations: 2,
+                cost_usd: 0.12,
+            },
+            CostReportRow {
+                key: "flux, dev".to_string(),
+                generations: 1,
+                cost_usd: 0.025,
+            },
+        ];
+        let table =
+            render_cost_report(&rows, CostGroupBy::Provider, None, CostReportFormat::Table)?;
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "provider   generations      cost_usd");
+        assert_eq!(lines[1], "openai               2       $0.1200");
+        assert_eq!(lines[3], "total                3       $0.1450");
+
+        let csv = render_cost_report(&rows, CostGroupBy::Model, None, CostReportFormat::Csv)?;
         assert_eq!(
-            active_image_for_edit_prompt(
-                "edit the image: remove people",
-                Some("/tmp/does-not-exist-brood-cli.png")
-            ),
-            None
+            csv,
+            "model,generations,cost_usd\nopenai,2,0.120000\n\"flux, dev\",1,0.025000\ntotal,3,0.145000\n"
         );
 
-        let _ = fs::remove_file(test_path);
+        let report: serde_json::Value = serde_json::from_str(&render_cost_report(
+            &rows,
+            CostGroupBy::Day,
+            Some("2024-06-01"),
+            CostReportFormat::Json,
+        )?)?;
+        assert_eq!(report["since"], json!("2024-06-01"));
+        assert_eq!(report["rows"][0]["day"], json!("openai"));
+        assert_eq!(report["total_generations"], json!(3));
+        Ok(())
     }
 }
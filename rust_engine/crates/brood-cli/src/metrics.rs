@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use brood_contracts::events::{EventFilter, EventSink};
+use serde_json::Value;
+
+/// Events the metrics sink reads.
+const METRICS_EVENT_FILTER: &str = "include=plan_preview,cost_latency_update,generation_failed";
+/// Upper bounds, in seconds, of the per-image latency histogram buckets.
+const LATENCY_BUCKETS_S: [f64; 10] = [0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0];
+
+/// `(provider, model)`.
+type ModelKey = (String, String);
+
+#[derive(Default)]
+struct Histogram {
+    /// Cumulative counts per bucket of [`LATENCY_BUCKETS_S`].
+    buckets: [u64; LATENCY_BUCKETS_S.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS_S) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Default)]
+struct MetricsState {
+    generations: BTreeMap<ModelKey, u64>,
+    failures: BTreeMap<(ModelKey, &'static str), u64>,
+    cost_usd: BTreeMap<ModelKey, f64>,
+    latency: BTreeMap<ModelKey, Histogram>,
+    cache_hits: u64,
+    cache_misses: u64,
+}
+
+/// Fleet-wide counters for `brood-rs serve`, fed by an event sink on every
+/// engine the server opens and rendered for `GET /metrics` in the
+/// Prometheus text format.
+#[derive(Default)]
+pub(crate) struct ServerMetrics {
+    state: Mutex<MetricsState>,
+}
+
+impl ServerMetrics {
+    pub(crate) fn filter() -> EventFilter {
+        EventFilter::parse(METRICS_EVENT_FILTER).unwrap_or_default()
+    }
+
+    fn record(&self, event: &Value) {
+        let field = |value: &Value, key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string()
+        };
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match event.get("type").and_then(Value::as_str) {
+            Some("plan_preview") => {
+                let plan = event.get("plan").unwrap_or(&Value::Null);
+                let key = (field(plan, "provider"), field(plan, "model"));
+                *state.generations.entry(key).or_default() += 1;
+                if plan.get("cached").and_then(Value::as_bool) == Some(true) {
+                    state.cache_hits += 1;
+                } else {
+                    state.cache_misses += 1;
+                }
+            }
+            Some("cost_latency_update") => {
+                let key = (field(event, "provider"), field(event, "model"));
+                let number = |name: &str| event.get(name).and_then(Value::as_f64).unwrap_or(0.0);
+                *state.cost_usd.entry(key.clone()).or_default() += number("cost_total_usd");
+                // Cache hits and requests that never reached a provider
+                // report zero latency; they would only drag the buckets down.
+                let latency = number("latency_per_image_s");
+                if latency > 0.0 {
+                    state.latency.entry(key).or_default().observe(latency);
+                }
+            }
+            Some("generation_failed") => {
+                let key = (field(event, "provider"), field(event, "model"));
+                let class = error_class(&field(event, "error"));
+                *state.failures.entry((key, class)).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Prometheus text exposition; `queue` is the current job count per
+    /// status, which the server knows and events do not.
+    pub(crate) fn render(&self, queue: &[(&str, usize)]) -> String {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut out = String::new();
+        let model_labels = |(provider, model): &ModelKey| {
+            format!(
+                "provider=\"{}\",model=\"{}\"",
+                escape_label(provider),
+                escape_label(model)
+            )
+        };
+
+        header(
+            &mut out,
+            "brood_generations_total",
+            "counter",
+            "Generation requests by provider and model, cache hits included.",
+        );
+        for (key, count) in &state.generations {
+            let _ = writeln!(
+                out,
+                "brood_generations_total{{{}}} {count}",
+                model_labels(key)
+            );
+        }
+        header(
+            &mut out,
+            "brood_provider_failures_total",
+            "counter",
+            "Failed generations by provider, model and error class.",
+        );
+        for ((key, class), count) in &state.failures {
+            let _ = writeln!(
+                out,
+                "brood_provider_failures_total{{{},error_class=\"{class}\"}} {count}",
+                model_labels(key)
+            );
+        }
+        header(
+            &mut out,
+            "brood_cost_usd_total",
+            "counter",
+            "Estimated provider spend in USD.",
+        );
+        for (key, cost) in &state.cost_usd {
+            let _ = writeln!(out, "brood_cost_usd_total{{{}}} {cost}", model_labels(key));
+        }
+        header(
+            &mut out,
+            "brood_latency_per_image_seconds",
+            "histogram",
+            "Provider latency per generated image.",
+        );
+        for (key, histogram) in &state.latency {
+            let labels = model_labels(key);
+            for (bound, count) in LATENCY_BUCKETS_S.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "brood_latency_per_image_seconds_bucket{{{labels},le=\"{bound}\"}} {count}"
+                );
+            }
+            let _ = writeln!(
+                out,
+                "brood_latency_per_image_seconds_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "brood_latency_per_image_seconds_sum{{{labels}}} {}",
+                histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "brood_latency_per_image_seconds_count{{{labels}}} {}",
+                histogram.count
+            );
+        }
+        header(
+            &mut out,
+            "brood_cache_requests_total",
+            "counter",
+            "Generation cache lookups; hit rate is hit over all.",
+        );
+        let _ = writeln!(
+            out,
+            "brood_cache_requests_total{{result=\"hit\"}} {}",
+            state.cache_hits
+        );
+        let _ = writeln!(
+            out,
+            "brood_cache_requests_total{{result=\"miss\"}} {}",
+            state.cache_misses
+        );
+        header(
+            &mut out,
+            "brood_generation_queue_depth",
+            "gauge",
+            "Generation jobs waiting or running.",
+        );
+        for (status, count) in queue {
+            let _ = writeln!(
+                out,
+                "brood_generation_queue_depth{{status=\"{status}\"}} {count}"
+            );
+        }
+        out
+    }
+}
+
+/// Forwards engine events into the shared [`ServerMetrics`].
+pub(crate) struct MetricsSink(pub(crate) Arc<ServerMetrics>);
+
+impl EventSink for MetricsSink {
+    fn send(&self, event: &Value) -> anyhow::Result<()> {
+        self.0.record(event);
+        Ok(())
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Buckets a `generation_failed` error into a small, fixed set of label
+/// values so failure counters stay low-cardinality.
+fn error_class(error: &str) -> &'static str {
+    let lowered = error.to_ascii_lowercase();
+    let mentions = |needles: &[&str]| needles.iter().any(|needle| lowered.contains(needle));
+    if mentions(&["moderat", "nsfw", "safety", "content policy", "blocked"]) {
+        return "moderation";
+    }
+    if mentions(&["timed out", "timeout"]) {
+        return "timeout";
+    }
+    if mentions(&["budget"]) {
+        return "budget";
+    }
+    match http_status(&lowered) {
+        Some(401 | 403) => return "auth",
+        Some(429) => return "rate_limited",
+        Some(500..=599) => return "provider_unavailable",
+        Some(400..=499) => return "invalid_request",
+        _ => {}
+    }
+    if mentions(&["not set", "api key", "unauthorized"]) {
+        "auth"
+    } else if mentions(&["rate limit", "quota"]) {
+        "rate_limited"
+    } else if mentions(&["not registered", "connection", "dns", "unavailable"]) {
+        "provider_unavailable"
+    } else {
+        "other"
+    }
+}
+
+/// The status in provider errors shaped like `... request failed (429): ...`.
+fn http_status(error: &str) -> Option<u16> {
+    error.match_indices('(').find_map(|(start, _)| {
+        let digits = error.get(start + 1..start + 4)?;
+        (error.get(start + 4..start + 5) == Some(")"))
+            .then(|| digits.parse().ok())
+            .flatten()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{error_class, ServerMetrics};
+
+    #[test]
+    fn records_events_and_renders_prometheus_text() {
+        let metrics = ServerMetrics::default();
+        for event in [
+            json!({"type": "plan_preview", "plan": {"provider": "replicate", "model": "flux \"dev\"", "cached": false}}),
+            json!({"type": "plan_preview", "plan": {"provider": "replicate", "model": "flux \"dev\"", "cached": true}}),
+            json!({"type": "cost_latency_update", "provider": "replicate", "model": "flux \"dev\"", "cost_total_usd": 0.03, "latency_per_image_s": 4.2}),
+            json!({"type": "cost_latency_update", "provider": "replicate", "model": "flux \"dev\"", "cost_total_usd": 0.0, "latency_per_image_s": 0.0}),
+            json!({"type": "generation_failed", "provider": "openai", "model": "gpt-image-1", "error": "OpenAI request failed (429): slow down"}),
+        ] {
+            metrics.record(&event);
+        }
+        let text = metrics.render(&[("queued", 2), ("running", 1)]);
+        let labels = "provider=\"replicate\",model=\"flux \\\"dev\\\"\"";
+        assert!(text.contains(&format!("brood_generations_total{{{labels}}} 2\n")));
+        assert!(text.contains(&format!("brood_cost_usd_total{{{labels}}} 0.03\n")));
+        assert!(text.contains(&format!(
+            "brood_latency_per_image_seconds_bucket{{{labels},le=\"2.5\"}} 0\n"
+        )));
+        assert!(text.contains(&format!(
+            "brood_latency_per_image_seconds_bucket{{{labels},le=\"5\"}} 1\n"
+        )));
+        assert!(text.contains(&format!(
+            "brood_latency_per_image_seconds_count{{{labels}}} 1\n"
+        )));
+        assert!(text.contains(
+            "brood_provider_failures_total{provider=\"openai\",model=\"gpt-image-1\",error_class=\"rate_limited\"} 1\n"
+        ));
+        assert!(text.contains("brood_cache_requests_total{result=\"hit\"} 1\n"));
+        assert!(text.contains("brood_cache_requests_total{result=\"miss\"} 1\n"));
+        assert!(text.contains("brood_generation_queue_depth{status=\"queued\"} 2\n"));
+        assert!(text.contains("# TYPE brood_latency_per_image_seconds histogram\n"));
+
+        assert_eq!(
+            error_class("Replicate prediction failed: NSFW content detected"),
+            "moderation"
+        );
+        assert_eq!(
+            error_class("Stability request failed (503): busy"),
+            "provider_unavailable"
+        );
+        assert_eq!(error_class("OpenAI request failed (401): bad key"), "auth");
+        assert_eq!(error_class("REPLICATE_API_TOKEN not set"), "auth");
+        assert_eq!(error_class("Flux polling timed out after 30.0s"), "timeout");
+        assert_eq!(error_class("something odd (v2)"), "other");
+    }
+}
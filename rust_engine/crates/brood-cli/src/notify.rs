@@ -0,0 +1,302 @@
+use std::fs;
+use std::process::{Command as ProcessCommand, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use brood_contracts::events::{EventFilter, EventSink};
+use brood_engine::http_client_builder;
+use reqwest::blocking::Client as HttpClient;
+use serde_json::{json, Map, Value};
+
+/// Events that trigger a notification.
+const NOTIFY_EVENT_FILTER: &str = "include=run_finished,generation_failed";
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Provider errors can be long; chat clients truncate anyway.
+const ERROR_TEXT_MAX_CHARS: usize = 300;
+
+/// Tells the user a run finished or a generation failed, via a desktop
+/// notification (`BROOD_NOTIFY_DESKTOP=1`) and/or a webhook POST
+/// (`BROOD_NOTIFY_WEBHOOK_URL`).
+///
+/// Both are sent synchronously: `run_finished` is usually the last event
+/// before the CLI exits, so a background thread would be cut off.
+pub(crate) struct RunNotifier {
+    desktop: bool,
+    webhook: Option<(String, HttpClient)>,
+}
+
+impl RunNotifier {
+    pub(crate) fn new(desktop: bool, webhook_url: Option<String>) -> Result<Self> {
+        let webhook = match webhook_url {
+            Some(url) => {
+                let client = http_client_builder()?
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .build()
+                    .context("failed to build webhook client")?;
+                Some((url, client))
+            }
+            None => None,
+        };
+        Ok(Self { desktop, webhook })
+    }
+
+    pub(crate) fn filter() -> EventFilter {
+        EventFilter::parse(NOTIFY_EVENT_FILTER).unwrap_or_default()
+    }
+}
+
+impl EventSink for RunNotifier {
+    fn send(&self, event: &Value) -> Result<()> {
+        let Some(notice) = Notice::from_event(event) else {
+            return Ok(());
+        };
+        if self.desktop {
+            if let Err(err) = show_desktop_notification(&notice) {
+                eprintln!("brood-rs: desktop notification failed: {err:#}");
+            }
+        }
+        if let Some((url, client)) = self.webhook.as_ref() {
+            let posted = client
+                .post(url)
+                .json(&notice.webhook_payload())
+                .send()
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = posted {
+                eprintln!("brood-rs: notification webhook failed: {err}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a notification says about one event.
+#[derive(Debug, PartialEq)]
+struct Notice {
+    title: String,
+    body: String,
+    details: Map<String, Value>,
+}
+
+impl Notice {
+    fn from_event(event: &Value) -> Option<Self> {
+        let event_type = event.get("type").and_then(Value::as_str)?;
+        let run_id = event.get("run_id").and_then(Value::as_str).unwrap_or("run");
+        let mut details = Map::new();
+        details.insert("event".to_string(), json!(event_type));
+        details.insert("run_id".to_string(), json!(run_id));
+        match event_type {
+            "run_finished" => {
+                let summary = event
+                    .get("summary_path")
+                    .and_then(Value::as_str)
+                    .and_then(|path| fs::read_to_string(path).ok())
+                    .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+                    .unwrap_or(Value::Null);
+                let count = |key: &str| summary.get(key).and_then(Value::as_u64).unwrap_or(0);
+                let body = format!(
+                    "{} version(s), {} artifact(s)",
+                    count("total_versions"),
+                    count("total_artifacts")
+                );
+                details.insert("summary".to_string(), summary);
+                Some(Self {
+                    title: format!("Brood run {run_id} finished"),
+                    body,
+                    details,
+                })
+            }
+            "generation_failed" => {
+                let field = |key: &str| event.get(key).and_then(Value::as_str).unwrap_or("");
+                let mut error: String = field("error").chars().take(ERROR_TEXT_MAX_CHARS).collect();
+                if field("error").chars().count() > ERROR_TEXT_MAX_CHARS {
+                    error.push('…');
+                }
+                for key in ["version_id", "provider", "model"] {
+                    details.insert(key.to_string(), json!(field(key)));
+                }
+                details.insert("error".to_string(), json!(error));
+                Some(Self {
+                    title: format!("Brood generation failed in {run_id}"),
+                    body: format!("{}/{}: {error}", field("provider"), field("model")),
+                    details,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// `text` is what Slack renders and `content` what Discord renders; the
+    /// structured fields ride along under `brood` for custom receivers.
+    fn webhook_payload(&self) -> Value {
+        let text = format!("{}: {}", self.title, self.body);
+        json!({
+            "text": text,
+            "content": text,
+            "brood": self.details,
+        })
+    }
+}
+
+fn show_desktop_notification(notice: &Notice) -> Result<()> {
+    let mut command = desktop_command(&notice.title, &notice.body);
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("failed to run {:?}", command.get_program()))?;
+    if !status.success() {
+        anyhow::bail!("{:?} exited with {status}", command.get_program());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn desktop_command(title: &str, body: &str) -> ProcessCommand {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut command = ProcessCommand::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(title)
+    ));
+    command
+}
+
+#[cfg(windows)]
+fn desktop_command(title: &str, body: &str) -> ProcessCommand {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let script = format!(
+        "[reflection.assembly]::LoadWithPartialName('System.Windows.Forms') | Out-Null; \
+         $n = New-Object System.Windows.Forms.NotifyIcon; \
+         $n.Icon = [System.Drawing.SystemIcons]::Information; $n.Visible = $true; \
+         $n.ShowBalloonTip(10000, {}, {}, 'Info'); Start-Sleep -Seconds 5; $n.Dispose()",
+        quote(title),
+        quote(body)
+    );
+    let mut command = ProcessCommand::new("powershell");
+    command.args(["-NoProfile", "-Command", &script]);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn desktop_command(title: &str, body: &str) -> ProcessCommand {
+    let mut command = ProcessCommand::new("notify-send");
+    command.args(["--app-name=Brood", title, body]);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use brood_contracts::event_schema::ArtifactCreatedEvent;
+    use brood_contracts::events::EventWriter;
+    use serde_json::{json, Map, Value};
+
+    use super::{Notice, RunNotifier};
+
+    #[test]
+    fn notices_summarize_finished_runs_and_truncate_errors() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let summary_path = temp.path().join("summary.json");
+        std::fs::write(
+            &summary_path,
+            r#"{"run_id": "run-7", "total_versions": 2, "total_artifacts": 5}"#,
+        )?;
+        let finished = Notice::from_event(&json!({
+            "type": "run_finished",
+            "run_id": "run-7",
+            "summary_path": summary_path.to_string_lossy(),
+        }))
+        .expect("notice");
+        assert_eq!(finished.title, "Brood run run-7 finished");
+        assert_eq!(finished.body, "2 version(s), 5 artifact(s)");
+        assert_eq!(
+            finished.webhook_payload()["text"],
+            json!("Brood run run-7 finished: 2 version(s), 5 artifact(s)")
+        );
+
+        let failed = Notice::from_event(&json!({
+            "type": "generation_failed",
+            "run_id": "run-7",
+            "provider": "replicate",
+            "model": "flux-pro",
+            "error": "x".repeat(400),
+        }))
+        .expect("notice");
+        assert_eq!(
+            failed.details["error"]
+                .as_str()
+                .map(|text| text.chars().count()),
+            Some(301)
+        );
+        assert!(failed.body.starts_with("replicate/flux-pro: xxx"));
+        assert!(Notice::from_event(&json!({"type": "artifact_created"})).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn posts_slack_and_discord_compatible_payload() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/hook", listener.local_addr()?);
+        let (received, bodies) = mpsc::channel();
+        thread::spawn(move || {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            let _ = reader.read_exact(&mut body);
+            let _ = reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+            let _ = received.send(String::from_utf8_lossy(&body).to_string());
+        });
+
+        let temp = tempfile::tempdir()?;
+        let writer = EventWriter::new(temp.path().join("events.jsonl"), "run-hook");
+        writer.add_sink(
+            Box::new(RunNotifier::new(false, Some(url))?),
+            RunNotifier::filter(),
+        )?;
+        writer.emit_typed(&ArtifactCreatedEvent {
+            version_id: "v1".to_string(),
+            artifact_id: "a1".to_string(),
+            image_path: "/tmp/a1.png".to_string(),
+            receipt_path: None,
+            thumbnail_path: None,
+            metrics: Map::new(),
+            warnings: Vec::new(),
+            warning_details: Vec::new(),
+        })?;
+        let mut payload = Map::new();
+        payload.insert("provider".to_string(), json!("dryrun"));
+        payload.insert("model".to_string(), json!("dryrun-image-1"));
+        payload.insert("error".to_string(), json!("boom"));
+        writer.emit("generation_failed", payload)?;
+
+        let body: Value = serde_json::from_str(&bodies.recv_timeout(Duration::from_secs(10))?)?;
+        let text = json!("Brood generation failed in run-hook: dryrun/dryrun-image-1: boom");
+        assert_eq!(body["text"], text);
+        assert_eq!(body["content"], text);
+        assert_eq!(body["brood"]["event"], json!("generation_failed"));
+        Ok(())
+    }
+}
@@ -0,0 +1,1821 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::net::TcpListener;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use brood_contracts::events::{EventFilter, EventSink};
+use brood_contracts::runs::atomic::{read_json, write_json_atomic};
+use brood_contracts::runs::run_dir::create_unique_run_dir;
+use brood_contracts::runs::thread_manifest::ThreadManifest;
+use brood_engine::{deliver_fal_webhook, set_fal_webhook_url, THUMBNAIL_EDGE};
+use image::ImageFormat;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Map, Value};
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::metrics::{MetricsSink, ServerMetrics};
+use super::worker_pool::{QueuedJob, WorkerPool};
+use super::{
+    apply_cost_budget_env, attach_event_sinks, global_cache_from_env, guess_image_mime, open_engine,
+};
+
+/// Largest request body accepted (generation requests are small JSON).
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Finished jobs kept for `GET /jobs`; older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 1000;
+/// Queued and running jobs across all clients; more get a 429.
+const MAX_PENDING_JOBS: usize = 1024;
+/// Queued and running jobs one client may have unless `--queue-limit`
+/// says otherwise.
+const DEFAULT_CLIENT_QUEUE_LIMIT: usize = 64;
+/// An SSE stream wakes when an engine in this server emits an event, and
+/// otherwise re-checks events.jsonl this often for writers outside it.
+const SSE_FALLBACK_POLL: Duration = Duration::from_secs(2);
+/// A comment line is sent this often so dead clients are noticed.
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+/// Key for signed asset URLs. Set it when several servers share one host
+/// name; otherwise each server signs with a random key and its URLs stop
+/// working when it restarts.
+pub(crate) const SIGNING_KEY_ENV: &str = "BROOD_SERVE_SIGNING_KEY";
+/// Bearer token API requests must carry. Without it a token is generated
+/// at startup and printed.
+pub(crate) const TOKEN_ENV: &str = "BROOD_SERVE_TOKEN";
+const ASSET_URL_DEFAULT_TTL_S: u64 = 300;
+const ASSET_URL_MAX_TTL_S: u64 = 24 * 60 * 60;
+const ASSET_VARIANTS: &[&str] = &["original", "thumb", "webp"];
+/// Re-encoded asset variants kept in memory, so a page full of thumbnails
+/// does not decode every original again.
+const VARIANT_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+/// `Host` names accepted besides the host of `--public-url`. Anything else
+/// is a DNS-rebinding attempt or a misrouted request.
+const LOOPBACK_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+/// Records, in a run dir, the client whose token created the run. Only that
+/// client and the operator can reach the run; runs without it are the
+/// operator's.
+const RUN_CLIENT_FILENAME: &str = "serve_client.json";
+
+/// How `brood-rs serve` is set up.
+#[derive(Debug, Default)]
+pub(crate) struct ServeOptions {
+    pub(crate) text_model: Option<String>,
+    pub(crate) image_model: Option<String>,
+    pub(crate) workers: usize,
+    /// Bearer token for the API; generated when unset.
+    pub(crate) token: Option<String>,
+    /// URL this server is reachable at from outside. Its host is accepted
+    /// alongside the loopback names, and signed asset URLs are built on it.
+    pub(crate) public_url: Option<String>,
+    /// Client name to bearer token. Generations sent with a client's token
+    /// are scheduled and limited as that client's.
+    pub(crate) client_tokens: BTreeMap<String, String>,
+    /// Queued and running jobs per client; [`DEFAULT_CLIENT_QUEUE_LIMIT`]
+    /// when unset.
+    pub(crate) queue_limit: Option<usize>,
+}
+
+/// Reads `--client-tokens`: a JSON object of client names to tokens.
+pub(crate) fn load_client_tokens(path: &FsPath) -> Result<BTreeMap<String, String>> {
+    let raw =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let tokens: BTreeMap<String, String> = serde_json::from_str(&raw).with_context(|| {
+        format!(
+            "{} must map client names to tokens, e.g. {{\"studio\": \"...\"}}",
+            path.display()
+        )
+    })?;
+    Ok(tokens)
+}
+
+/// Who sent an authorized request.
+#[derive(Debug, Clone, PartialEq)]
+enum Caller {
+    /// The server token: the operator, whose runs each count as a client.
+    Operator,
+    /// A token from `--client-tokens`.
+    Client(String),
+}
+
+impl Caller {
+    /// Whether this caller may reach the run in `run_dir`.
+    fn owns(&self, run_dir: &FsPath) -> bool {
+        match self {
+            Self::Operator => true,
+            Self::Client(client) => run_client(run_dir).as_deref() == Some(client.as_str()),
+        }
+    }
+}
+
+/// The client recorded as the creator of the run in `run_dir`, if any.
+fn run_client(run_dir: &FsPath) -> Option<String> {
+    read_json(&run_dir.join(RUN_CLIENT_FILENAME))
+        .ok()
+        .flatten()?
+        .get("client")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Locks `mutex` even if a handler panicked while holding it: every update
+/// leaves the job, model and variant tables whole, so one panic must not
+/// fail every later request.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Models a run's generations use; set when the run is created.
+#[derive(Debug, Clone)]
+struct RunModels {
+    text_model: Option<String>,
+    image_model: Option<String>,
+}
+
+/// What a worker needs to run one `POST /runs/{id}/generations` job.
+struct GenerationJob {
+    run_dir: PathBuf,
+    prompt: String,
+    settings: Map<String, Value>,
+    intent: Map<String, Value>,
+}
+
+/// An API failure, mapped to its status code.
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden(String),
+    NotFound(String),
+    TooManyRequests(String),
+    Internal(anyhow::Error),
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Internal(err)
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "missing or invalid bearer token".to_string(),
+            ),
+            Self::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            Self::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            Self::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
+            Self::Internal(err) => {
+                eprintln!("brood-rs serve: {err:#}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal server error".to_string(),
+                )
+            }
+        };
+        (status, Json(json!({ "error": message }))).into_response()
+    }
+}
+
+/// Jobs by sequence number, so listing is oldest first and eviction drops
+/// the oldest finished jobs.
+#[derive(Default)]
+struct JobTable {
+    records: BTreeMap<u64, Map<String, Value>>,
+}
+
+impl JobTable {
+    fn insert(&mut self, seq: u64, record: Map<String, Value>) {
+        self.records.insert(seq, record);
+    }
+
+    fn get(&self, job_id: &str) -> Option<&Map<String, Value>> {
+        self.records.get(&job_seq(job_id)?)
+    }
+
+    fn get_mut(&mut self, job_id: &str) -> Option<&mut Map<String, Value>> {
+        self.records.get_mut(&job_seq(job_id)?)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Map<String, Value>> {
+        self.records.values()
+    }
+
+    /// Refuses a new job for `client` once the server or that client has
+    /// too many jobs queued or running.
+    fn admit(&self, client: &str, client_limit: usize) -> ApiResult<()> {
+        let pending: Vec<&Map<String, Value>> = self
+            .values()
+            .filter(|job| {
+                matches!(
+                    job.get("status").and_then(Value::as_str),
+                    Some("queued" | "running")
+                )
+            })
+            .collect();
+        if pending.len() >= MAX_PENDING_JOBS {
+            return Err(ApiError::TooManyRequests(format!(
+                "{MAX_PENDING_JOBS} generations are already pending; retry later"
+            )));
+        }
+        let mine = pending
+            .iter()
+            .filter(|job| job.get("client").and_then(Value::as_str) == Some(client))
+            .count();
+        if mine >= client_limit {
+            return Err(ApiError::TooManyRequests(format!(
+                "client '{client}' already has {mine} generations pending; retry later"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Forgets the oldest finished jobs beyond [`MAX_FINISHED_JOBS`].
+    /// Queued and running jobs are always kept.
+    fn evict_finished(&mut self) {
+        let finished: Vec<u64> = self
+            .records
+            .iter()
+            .filter(|(_, job)| {
+                matches!(
+                    job.get("status").and_then(Value::as_str),
+                    Some("succeeded" | "failed")
+                )
+            })
+            .map(|(seq, _)| *seq)
+            .collect();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_JOBS);
+        for seq in &finished[..excess] {
+            self.records.remove(seq);
+        }
+    }
+}
+
+fn job_seq(job_id: &str) -> Option<u64> {
+    job_id.strip_prefix("job-")?.parse().ok()
+}
+
+/// Source file, its mtime, the variant and its content type.
+type VariantKey = (PathBuf, SystemTime, &'static str, &'static str);
+
+/// Encoded asset variants, evicted oldest first past
+/// [`VARIANT_CACHE_MAX_BYTES`]. Keys include the file's mtime, so a
+/// rewritten artifact is encoded afresh.
+#[derive(Default)]
+struct VariantCache {
+    entries: HashMap<VariantKey, Bytes>,
+    order: VecDeque<VariantKey>,
+    bytes: usize,
+}
+
+impl VariantCache {
+    fn get(&self, key: &VariantKey) -> Option<Bytes> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: VariantKey, body: Bytes) {
+        if body.len() > VARIANT_CACHE_MAX_BYTES || self.entries.contains_key(&key) {
+            return;
+        }
+        self.bytes += body.len();
+        self.order.push_back(key.clone());
+        self.entries.insert(key, body);
+        while self.bytes > VARIANT_CACHE_MAX_BYTES {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+    }
+}
+
+/// `brood-rs serve --http`. Runs live on disk under `runs_dir`; only
+/// generation jobs are tracked in memory.
+pub(crate) struct HttpServer {
+    listener: TcpListener,
+    state: Arc<ServerState>,
+}
+
+struct ServerState {
+    runs_dir: PathBuf,
+    defaults: RunModels,
+    models: Mutex<HashMap<String, RunModels>>,
+    jobs: Mutex<JobTable>,
+    /// Generations run on a fixed set of workers, each opening its own
+    /// engine. Jobs for the same run are serialized, since an engine locks
+    /// its run dir.
+    pool: WorkerPool<GenerationJob>,
+    next_job: AtomicU64,
+    metrics: Arc<ServerMetrics>,
+    signing_key: hmac::Key,
+    token: String,
+    /// `hmac(signing_key, token)`, so bearer tokens are compared in
+    /// constant time.
+    token_tag: hmac::Tag,
+    /// Client names and the tags of their tokens.
+    client_tags: Vec<(String, hmac::Tag)>,
+    queue_limit: usize,
+    allowed_hosts: Vec<String>,
+    /// Origin signed asset URLs are built on: `--public-url`, else the
+    /// listen address.
+    base_url: String,
+    variants: Mutex<VariantCache>,
+    /// Bumped after every event an engine in this server writes.
+    events_changed: Arc<watch::Sender<u64>>,
+}
+
+impl HttpServer {
+    pub(crate) fn bind(addr: &str, runs_dir: &FsPath, options: ServeOptions) -> Result<Self> {
+        fs::create_dir_all(runs_dir)
+            .with_context(|| format!("failed to create {}", runs_dir.display()))?;
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}"))?;
+        listener.set_nonblocking(true)?;
+        let secret = match std::env::var(SIGNING_KEY_ENV) {
+            Ok(secret) if !secret.trim().is_empty() => secret.trim().as_bytes().to_vec(),
+            _ => random_bytes(32)?,
+        };
+        let signing_key = hmac::Key::new(hmac::HMAC_SHA256, &secret);
+        let token = match options
+            .token
+            .or_else(|| std::env::var(TOKEN_ENV).ok())
+            .map(|token| token.trim().to_string())
+        {
+            Some(token) if !token.is_empty() => token,
+            _ => hex::encode(random_bytes(32)?),
+        };
+        let token_tag = hmac::sign(&signing_key, token.as_bytes());
+        let mut client_tags = Vec::new();
+        for (client, client_token) in &options.client_tokens {
+            let client_token = client_token.trim();
+            if client.trim().is_empty() || client_token.is_empty() {
+                bail!("client tokens need a client name and a non-empty token");
+            }
+            if client_token == token
+                || options.client_tokens.iter().any(|(other, other_token)| {
+                    other != client && other_token.trim() == client_token
+                })
+            {
+                bail!("client '{client}' shares its token with another caller");
+            }
+            client_tags.push((
+                client.trim().to_string(),
+                hmac::sign(&signing_key, client_token.as_bytes()),
+            ));
+        }
+        let mut allowed_hosts: Vec<String> =
+            LOOPBACK_HOSTS.iter().map(|host| host.to_string()).collect();
+        let base_url = match &options.public_url {
+            Some(public_url) => public_url.trim().trim_end_matches('/').to_string(),
+            None => format!("http://{}", listener.local_addr()?),
+        };
+        if let Some(public_url) = &options.public_url {
+            let url = reqwest::Url::parse(public_url.trim())
+                .with_context(|| format!("invalid public URL '{public_url}'"))?;
+            if let Some(host) = url.host_str() {
+                allowed_hosts.push(host.to_ascii_lowercase());
+            }
+        }
+        let (events_changed, _) = watch::channel(0);
+        Ok(Self {
+            listener,
+            state: Arc::new_cyclic(|state: &Weak<ServerState>| {
+                let state = state.clone();
+                ServerState {
+                    runs_dir: runs_dir.to_path_buf(),
+                    defaults: RunModels {
+                        text_model: options.text_model,
+                        image_model: options.image_model,
+                    },
+                    models: Mutex::new(HashMap::new()),
+                    jobs: Mutex::new(JobTable::default()),
+                    pool: {
+                        let panicked = state.clone();
+                        WorkerPool::new(
+                            options.workers,
+                            move |worker, job| {
+                                if let Some(state) = state.upgrade() {
+                                    state.run_generation(worker, job);
+                                }
+                            },
+                            move |job_id, message| {
+                                if let Some(state) = panicked.upgrade() {
+                                    state.fail_panicked_job(job_id, message);
+                                }
+                            },
+                        )
+                    },
+                    next_job: AtomicU64::new(1),
+                    metrics: Arc::new(ServerMetrics::default()),
+                    signing_key,
+                    token,
+                    token_tag,
+                    client_tags,
+                    queue_limit: options.queue_limit.unwrap_or(DEFAULT_CLIENT_QUEUE_LIMIT),
+                    allowed_hosts,
+                    base_url,
+                    variants: Mutex::new(VariantCache::default()),
+                    events_changed: Arc::new(events_changed),
+                }
+            }),
+        })
+    }
+
+    /// Registers `{public_url}/webhooks/fal` with queued Fal requests so
+    /// results arrive without waiting for the next status poll. The URL
+    /// carries a token derived from the signing key; callbacks without it
+    /// are rejected.
+    pub(crate) fn register_fal_webhook(&self, public_url: &str) -> String {
+        let url = format!(
+            "{}/webhooks/fal?token={}",
+            public_url.trim().trim_end_matches('/'),
+            self.state.fal_webhook_token()
+        );
+        set_fal_webhook_url(Some(url.clone()));
+        url
+    }
+
+    pub(crate) fn local_addr(&self) -> Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    /// The bearer token API requests must send.
+    pub(crate) fn token(&self) -> &str {
+        &self.state.token
+    }
+
+    /// Serves requests until the process exits.
+    pub(crate) fn run(self) -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .context("failed to start the HTTP runtime")?;
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(self.listener)?;
+            axum::serve(listener, router(self.state)).await?;
+            Ok(())
+        })
+    }
+}
+
+fn router(state: Arc<ServerState>) -> Router {
+    Router::new()
+        .route("/health", get(|| async { Json(json!({ "ok": true })) }))
+        .route("/metrics", get(metrics))
+        .route("/runs", get(list_runs).post(create_run))
+        .route("/runs/{run_id}", get(run_status))
+        .route("/runs/{run_id}/events", get(run_events))
+        .route("/runs/{run_id}/generations", post(submit_generation))
+        .route("/runs/{run_id}/generations/{job_id}", get(run_job_status))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{job_id}", get(job_status))
+        .route("/runs/{run_id}/artifacts/{artifact_id}", get(artifact_file))
+        .route(
+            "/runs/{run_id}/artifacts/{artifact_id}/url",
+            post(sign_asset_url),
+        )
+        .route("/assets/{run_id}/{artifact_id}", get(signed_asset))
+        .route("/webhooks/fal", post(fal_webhook))
+        .fallback(|| async { ApiError::NotFound("route not found".to_string()) })
+        .layer(middleware::from_fn_with_state(Arc::clone(&state), guard))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(state)
+}
+
+/// Rejects requests for a foreign `Host`, and API requests without the
+/// bearer token. `/health`, signed `/assets` URLs and the Fal webhook carry
+/// their own checks.
+async fn guard(
+    State(state): State<Arc<ServerState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(host_name)
+        .unwrap_or_default();
+    if !state.allowed_hosts.contains(&host) {
+        return ApiError::Forbidden(format!("host '{host}' is not allowed")).into_response();
+    }
+    let path = request.uri().path();
+    let public = path == "/health" || path.starts_with("/assets/") || path == "/webhooks/fal";
+    if !public {
+        let Some(caller) = state.authenticate(request.headers()) else {
+            return ApiError::Unauthorized.into_response();
+        };
+        request.extensions_mut().insert(caller);
+    }
+    next.run(request).await
+}
+
+/// `Host` without its port, lowercased. IPv6 literals keep their brackets.
+fn host_name(raw: &str) -> String {
+    let raw = raw.trim().to_ascii_lowercase();
+    let host = match raw.rfind(':') {
+        Some(idx) if !raw[idx..].contains(']') => &raw[..idx],
+        _ => &raw,
+    };
+    host.to_string()
+}
+
+/// Runs blocking work (disk reads, image encoding) off the async workers.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> ApiResult<T> + Send + 'static,
+) -> ApiResult<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| ApiError::Internal(anyhow::anyhow!("request handler failed: {err}")))?
+}
+
+type AppState = State<Arc<ServerState>>;
+
+async fn metrics(State(state): AppState) -> Response {
+    state.metrics_response()
+}
+
+async fn list_runs(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+) -> ApiResult<Response> {
+    blocking(move || Ok(Json(json!({ "runs": state.list_runs(&caller)? })).into_response())).await
+}
+
+async fn create_run(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    blocking(move || state.create_run(&caller, &request_json(&body)?)).await
+}
+
+async fn run_status(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path(run_id): Path<String>,
+) -> ApiResult<Response> {
+    blocking(move || state.run_status(&caller, &run_id)).await
+}
+
+async fn submit_generation(
+    State(state): AppState,
+    Path(run_id): Path<String>,
+    Extension(caller): Extension<Caller>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    blocking(move || state.submit_generation(&run_id, &caller, request_json(&body)?)).await
+}
+
+async fn run_job_status(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path((run_id, job_id)): Path<(String, String)>,
+) -> ApiResult<Response> {
+    blocking(move || state.job_status(&caller, Some(&run_id), &job_id)).await
+}
+
+async fn job_status(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path(job_id): Path<String>,
+) -> ApiResult<Response> {
+    blocking(move || state.job_status(&caller, None, &job_id)).await
+}
+
+async fn list_jobs(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Query(query): Query<HashMap<String, String>>,
+) -> ApiResult<Response> {
+    blocking(move || Ok(state.list_jobs(&caller, query.get("client")))).await
+}
+
+async fn artifact_file(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path((run_id, artifact_id)): Path<(String, String)>,
+) -> ApiResult<Response> {
+    blocking(move || state.artifact_file(&caller, &run_id, &artifact_id)).await
+}
+
+async fn sign_asset_url(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path((run_id, artifact_id)): Path<(String, String)>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    blocking(move || state.sign_asset_url(&caller, &run_id, &artifact_id, &request_json(&body)?))
+        .await
+}
+
+async fn signed_asset(
+    State(state): AppState,
+    Path((run_id, artifact_id)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    blocking(move || state.signed_asset(&headers, &query, &run_id, &artifact_id)).await
+}
+
+async fn fal_webhook(
+    State(state): AppState,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> ApiResult<Response> {
+    blocking(move || state.fal_webhook(&query, &body)).await
+}
+
+/// Streams events.jsonl as server-sent events, one event per line with the
+/// line number as its id. `Last-Event-ID` (or `?from=N`) resumes after a
+/// line; `?follow=false` closes once the existing lines are sent.
+async fn run_events(
+    State(state): AppState,
+    Extension(caller): Extension<Caller>,
+    Path(run_id): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let run_dir = {
+        let state = Arc::clone(&state);
+        blocking(move || state.caller_run_dir(&caller, &run_id)).await?
+    };
+    let skip = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| query.get("from").map(String::as_str))
+        .and_then(|raw| raw.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let follow = !matches!(query.get("follow").map(String::as_str), Some("false" | "0"));
+    let (events, stream) = mpsc::channel(64);
+    let changed = state.events_changed.subscribe();
+    tokio::spawn(tail_events(
+        EventTail::new(run_dir.join("events.jsonl"), skip),
+        follow,
+        events,
+        changed,
+    ));
+    Ok(Sse::new(ReceiverStream::new(stream))
+        .keep_alive(KeepAlive::new().interval(SSE_KEEPALIVE))
+        .into_response())
+}
+
+async fn tail_events(
+    mut tail: EventTail,
+    follow: bool,
+    events: mpsc::Sender<std::result::Result<Event, Infallible>>,
+    mut changed: watch::Receiver<u64>,
+) {
+    loop {
+        for (line_no, line) in tail.read_new() {
+            let event = Event::default().id(line_no.to_string()).data(line);
+            if events.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+        if !follow {
+            return;
+        }
+        tokio::select! {
+            _ = changed.changed() => {}
+            _ = tokio::time::sleep(SSE_FALLBACK_POLL) => {}
+            () = events.closed() => return,
+        }
+    }
+}
+
+/// Reads lines appended to events.jsonl since the last call, keeping a
+/// trailing partial line (the writer may be mid-append) for the next one.
+struct EventTail {
+    path: PathBuf,
+    offset: u64,
+    line_no: usize,
+    skip: usize,
+    partial: Vec<u8>,
+}
+
+impl EventTail {
+    fn new(path: PathBuf, skip: usize) -> Self {
+        Self {
+            path,
+            offset: 0,
+            line_no: 0,
+            skip,
+            partial: Vec::new(),
+        }
+    }
+
+    fn read_new(&mut self) -> Vec<(usize, String)> {
+        let mut appended = Vec::new();
+        let read = File::open(&self.path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(self.offset))?;
+            file.read_to_end(&mut appended)
+        });
+        if read.is_err() {
+            return Vec::new();
+        }
+        self.offset += appended.len() as u64;
+        self.partial.extend(appended);
+        let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        let mut lines = Vec::new();
+        for line in String::from_utf8_lossy(&complete).lines() {
+            self.line_no += 1;
+            if self.line_no > self.skip && !line.trim().is_empty() {
+                lines.push((self.line_no, line.to_string()));
+            }
+        }
+        lines
+    }
+}
+
+/// Wakes SSE streams after an engine in this server writes an event.
+struct EventsChangedSink(Arc<watch::Sender<u64>>);
+
+impl EventSink for EventsChangedSink {
+    fn send(&self, _event: &Value) -> anyhow::Result<()> {
+        self.0.send_modify(|count| *count += 1);
+        Ok(())
+    }
+}
+
+impl ServerState {
+    /// The caller whose bearer token the request carries, if any.
+    fn authenticate(&self, headers: &HeaderMap) -> Option<Caller> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))?
+            .trim()
+            .as_bytes();
+        if hmac::verify(&self.signing_key, token, self.token_tag.as_ref()).is_ok() {
+            return Some(Caller::Operator);
+        }
+        self.client_tags
+            .iter()
+            .find(|(_, tag)| hmac::verify(&self.signing_key, token, tag.as_ref()).is_ok())
+            .map(|(client, _)| Caller::Client(client.clone()))
+    }
+
+    /// Run ids are directory names under `runs_dir`; anything that could
+    /// escape it is rejected.
+    fn run_dir(&self, run_id: &str) -> ApiResult<PathBuf> {
+        let not_found = || ApiError::NotFound(format!("run '{run_id}' not found"));
+        if run_id.is_empty() || run_id.starts_with('.') || run_id.contains(['/', '\\']) {
+            return Err(not_found());
+        }
+        let run_dir = self.runs_dir.join(run_id);
+        if !run_dir.is_dir() {
+            return Err(not_found());
+        }
+        Ok(run_dir)
+    }
+
+    /// [`Self::run_dir`] for a run `caller` may reach. Other clients' runs
+    /// are reported missing, so their ids are not confirmed either.
+    fn caller_run_dir(&self, caller: &Caller, run_id: &str) -> ApiResult<PathBuf> {
+        let run_dir = self.run_dir(run_id)?;
+        if !caller.owns(&run_dir) {
+            return Err(ApiError::NotFound(format!("run '{run_id}' not found")));
+        }
+        Ok(run_dir)
+    }
+
+    /// Whether `caller` may see `job`, which it may when it may reach the
+    /// job's run.
+    fn caller_sees_job(&self, caller: &Caller, job: &Map<String, Value>) -> bool {
+        job.get("run_id")
+            .and_then(Value::as_str)
+            .is_some_and(|run_id| caller.owns(&self.runs_dir.join(run_id)))
+    }
+
+    fn list_runs(&self, caller: &Caller) -> ApiResult<Vec<String>> {
+        let mut runs = Vec::new();
+        for entry in fs::read_dir(&self.runs_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && caller.owns(&entry.path()) {
+                runs.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        runs.sort();
+        Ok(runs)
+    }
+
+    fn create_run(&self, caller: &Caller, body: &Map<String, Value>) -> ApiResult<Response> {
+        let label = body.get("label").and_then(Value::as_str).unwrap_or("http");
+        let run_dir = create_unique_run_dir(&self.runs_dir, label)?;
+        if let Caller::Client(client) = caller {
+            write_json_atomic(
+                &run_dir.join(RUN_CLIENT_FILENAME),
+                &json!({ "client": client }),
+            )?;
+        }
+        let run_id = run_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let models = RunModels {
+            text_model: string_field(body, "text_model").or(self.defaults.text_model.clone()),
+            image_model: string_field(body, "image_model").or(self.defaults.image_model.clone()),
+        };
+        lock(&self.models).insert(run_id.clone(), models.clone());
+        Ok((
+            StatusCode::CREATED,
+            Json(json!({
+                "run_id": run_id,
+                "run_dir": run_dir.to_string_lossy(),
+                "text_model": models.text_model,
+                "image_model": models.image_model,
+            })),
+        )
+            .into_response())
+    }
+
+    fn run_status(&self, caller: &Caller, run_id: &str) -> ApiResult<Response> {
+        let run_dir = self.caller_run_dir(caller, run_id)?;
+        let thread = ThreadManifest::load(run_dir.join("thread.json"))?;
+        let versions: Vec<Value> = thread
+            .live_versions()
+            .map(|version| {
+                json!({
+                    "version_id": version.version_id,
+                    "prompt": version.prompt,
+                    "artifact_ids": version
+                        .artifacts
+                        .iter()
+                        .filter_map(|artifact| artifact.get("artifact_id").cloned())
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        let jobs: Vec<Value> = lock(&self.jobs)
+            .values()
+            .filter(|job| job.get("run_id").and_then(Value::as_str) == Some(run_id))
+            .map(|job| {
+                json!({
+                    "job_id": job.get("job_id"),
+                    "status": job.get("status"),
+                })
+            })
+            .collect();
+        let summary = fs::read_to_string(run_dir.join("summary.json"))
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Value>(&raw).ok());
+        Ok(Json(json!({
+            "run_id": run_id,
+            "versions": versions,
+            "jobs": jobs,
+            "summary": summary,
+        }))
+        .into_response())
+    }
+
+    fn submit_generation(
+        &self,
+        run_id: &str,
+        caller: &Caller,
+        body: Map<String, Value>,
+    ) -> ApiResult<Response> {
+        let run_dir = self.caller_run_dir(caller, run_id)?;
+        let Some(prompt) = string_field(&body, "prompt") else {
+            return Err(ApiError::BadRequest("prompt is required".to_string()));
+        };
+        let settings = object_field(&body, "settings")?;
+        let mut intent = object_field(&body, "intent")?;
+        intent
+            .entry("action".to_string())
+            .or_insert_with(|| json!("generate"));
+        let client = match caller {
+            Caller::Client(client) => client.clone(),
+            Caller::Operator => run_id.to_string(),
+        };
+        let mut jobs = lock(&self.jobs);
+        jobs.admit(&client, self.queue_limit)?;
+        let seq = self.next_job.fetch_add(1, Ordering::Relaxed);
+        let job_id = format!("job-{seq}");
+        let job = map_from(json!({
+            "job_id": job_id,
+            "run_id": run_id,
+            "client": client,
+            "status": "queued",
+            "prompt": prompt,
+            "queued_at": unix_now(),
+            "artifacts": [],
+            "error": null,
+        }));
+        jobs.insert(seq, job);
+        drop(jobs);
+        self.pool.submit(QueuedJob {
+            job_id: job_id.clone(),
+            run_id: run_id.to_string(),
+            client,
+            payload: GenerationJob {
+                run_dir,
+                prompt,
+                settings,
+                intent,
+            },
+        });
+        let mut response = self.job_status(caller, Some(run_id), &job_id)?;
+        *response.status_mut() = StatusCode::ACCEPTED;
+        Ok(response)
+    }
+
+    /// Runs on a pool worker once the job reaches the front of the queue.
+    fn run_generation(&self, worker: usize, job: QueuedJob<GenerationJob>) {
+        self.update_job(&job.job_id, |record| {
+            record.insert("status".to_string(), json!("running"));
+            record.insert("worker".to_string(), json!(worker));
+            record.insert("started_at".to_string(), json!(unix_now()));
+        });
+        let models = self.run_models(&job.run_id);
+        let GenerationJob {
+            run_dir,
+            prompt,
+            settings,
+            intent,
+        } = job.payload;
+        let outcome = generate_in_run(&run_dir, &models, self, &prompt, settings, intent);
+        self.update_job(&job.job_id, |record| {
+            record.insert("finished_at".to_string(), json!(unix_now()));
+            match outcome {
+                Ok(artifacts) => {
+                    record.insert("status".to_string(), json!("succeeded"));
+                    record.insert("artifacts".to_string(), json!(artifacts));
+                }
+                Err(err) => {
+                    record.insert("status".to_string(), json!("failed"));
+                    record.insert("error".to_string(), json!(format!("{err:#}")));
+                }
+            }
+        });
+        lock(&self.jobs).evict_finished();
+    }
+
+    /// Marks a job whose worker panicked as failed with the panic message.
+    fn fail_panicked_job(&self, job_id: &str, message: &str) {
+        self.update_job(job_id, |record| {
+            record.insert("status".to_string(), json!("failed"));
+            record.insert("finished_at".to_string(), json!(unix_now()));
+            record.insert(
+                "error".to_string(),
+                json!(format!("generation panicked: {message}")),
+            );
+        });
+        lock(&self.jobs).evict_finished();
+    }
+
+    /// `GET /jobs/{id}` (or under its run): the job record, plus its place
+    /// in the queue while it waits for a worker. Jobs in runs `caller` may
+    /// not reach are reported missing.
+    fn job_status(
+        &self,
+        caller: &Caller,
+        run_id: Option<&str>,
+        job_id: &str,
+    ) -> ApiResult<Response> {
+        let job = lock(&self.jobs)
+            .get(job_id)
+            .filter(|job| {
+                run_id
+                    .is_none_or(|run_id| job.get("run_id").and_then(Value::as_str) == Some(run_id))
+            })
+            .cloned();
+        let Some(mut job) = job.filter(|job| self.caller_sees_job(caller, job)) else {
+            return Err(ApiError::NotFound(format!(
+                "generation '{job_id}' not found"
+            )));
+        };
+        if job.get("status").and_then(Value::as_str) == Some("queued") {
+            job.insert(
+                "queue_position".to_string(),
+                json!(self.pool.queue_position(job_id)),
+            );
+        }
+        Ok(Json(Value::Object(job)).into_response())
+    }
+
+    /// `GET /jobs[?client=]`: every job the server remembers in runs
+    /// `caller` may reach, oldest first.
+    fn list_jobs(&self, caller: &Caller, client: Option<&String>) -> Response {
+        let remembered: Vec<Map<String, Value>> = lock(&self.jobs)
+            .values()
+            .filter(|job| {
+                client.is_none_or(|client| {
+                    job.get("client").and_then(Value::as_str) == Some(client.as_str())
+                })
+            })
+            .cloned()
+            .collect();
+        let jobs: Vec<Value> = remembered
+            .iter()
+            .filter(|job| self.caller_sees_job(caller, job))
+            .map(|job| {
+                json!({
+                    "job_id": job.get("job_id"),
+                    "run_id": job.get("run_id"),
+                    "client": job.get("client"),
+                    "status": job.get("status"),
+                })
+            })
+            .collect();
+        Json(json!({
+            "workers": self.pool.workers(),
+            "jobs": jobs,
+        }))
+        .into_response()
+    }
+
+    fn artifact_file(
+        &self,
+        caller: &Caller,
+        run_id: &str,
+        artifact_id: &str,
+    ) -> ApiResult<Response> {
+        let path = self.artifact_path(&self.caller_run_dir(caller, run_id)?, artifact_id)?;
+        Ok(file_response(
+            artifact_content_type(&path),
+            fs::read(&path)?,
+        ))
+    }
+
+    /// The artifact's file, which must lie inside its run dir.
+    fn artifact_path(&self, run_dir: &FsPath, artifact_id: &str) -> ApiResult<PathBuf> {
+        let not_found = || ApiError::NotFound(format!("artifact '{artifact_id}' not found"));
+        let thread = ThreadManifest::load(run_dir.join("thread.json"))?;
+        let Some((_, artifact)) = thread.find_artifact(artifact_id) else {
+            return Err(not_found());
+        };
+        let Some(path) = ["image_path", "video_path"]
+            .iter()
+            .find_map(|key| artifact.get(*key).and_then(Value::as_str))
+            .map(PathBuf::from)
+        else {
+            return Err(ApiError::NotFound(format!(
+                "artifact '{artifact_id}' has no file"
+            )));
+        };
+        // Artifacts are always written inside the run dir.
+        let canonical = path.canonicalize().map_err(|_| not_found())?;
+        if !canonical.starts_with(run_dir.canonicalize()?) {
+            return Err(not_found());
+        }
+        Ok(canonical)
+    }
+
+    /// `POST /runs/{id}/artifacts/{id}/url` with optional
+    /// `{"ttl_s": 300, "variant": "thumb"}`: a URL under `/assets` that
+    /// serves the artifact without further auth until it expires.
+    fn sign_asset_url(
+        &self,
+        caller: &Caller,
+        run_id: &str,
+        artifact_id: &str,
+        body: &Map<String, Value>,
+    ) -> ApiResult<Response> {
+        self.artifact_path(&self.caller_run_dir(caller, run_id)?, artifact_id)?;
+        let ttl_s = match body.get("ttl_s") {
+            None | Some(Value::Null) => ASSET_URL_DEFAULT_TTL_S,
+            Some(value) => value
+                .as_u64()
+                .filter(|ttl| (1..=ASSET_URL_MAX_TTL_S).contains(ttl))
+                .ok_or_else(|| {
+                    ApiError::BadRequest(format!(
+                        "ttl_s must be 1..={ASSET_URL_MAX_TTL_S} seconds (got {value})"
+                    ))
+                })?,
+        };
+        let variant = string_field(body, "variant");
+        if let Some(variant) = &variant {
+            asset_variant(variant)?;
+        }
+        let expires = unix_now() + ttl_s;
+        let signature = hex::encode(hmac::sign(
+            &self.signing_key,
+            asset_signature_payload(run_id, artifact_id, expires).as_bytes(),
+        ));
+        let mut path = format!("/assets/{run_id}/{artifact_id}?expires={expires}&sig={signature}");
+        if let Some(variant) = &variant {
+            path.push_str(&format!("&variant={variant}"));
+        }
+        Ok(Json(json!({
+            "url": format!("{}{path}", self.base_url),
+            "path": path,
+            "expires_at": expires,
+            "variants": ASSET_VARIANTS,
+        }))
+        .into_response())
+    }
+
+    /// `GET /assets/{run}/{artifact}?expires=&sig=[&variant=]`. Without a
+    /// variant, clients that accept `image/webp` get WebP.
+    fn signed_asset(
+        &self,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>,
+        run_id: &str,
+        artifact_id: &str,
+    ) -> ApiResult<Response> {
+        let expires = query.get("expires").and_then(|raw| raw.parse::<u64>().ok());
+        let signature = query.get("sig").and_then(|raw| hex::decode(raw).ok());
+        let (Some(expires), Some(signature)) = (expires, signature) else {
+            return Err(ApiError::Forbidden("asset URL is not signed".to_string()));
+        };
+        let payload = asset_signature_payload(run_id, artifact_id, expires);
+        if hmac::verify(&self.signing_key, payload.as_bytes(), &signature).is_err() {
+            return Err(ApiError::Forbidden(
+                "asset URL signature is invalid".to_string(),
+            ));
+        }
+        if expires < unix_now() {
+            return Err(ApiError::Forbidden("asset URL has expired".to_string()));
+        }
+        // The signature stands in for the bearer token here.
+        let path = self.artifact_path(&self.run_dir(run_id)?, artifact_id)?;
+        let accepts_webp = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("image/webp"));
+        let content_type = artifact_content_type(&path);
+        let variant = match query.get("variant") {
+            Some(variant) => asset_variant(variant)?,
+            None if accepts_webp && is_raster(content_type) && content_type != "image/webp" => {
+                "webp"
+            }
+            None => "original",
+        };
+        if variant == "original" {
+            return Ok(file_response(content_type, fs::read(&path)?));
+        }
+        if !is_raster(content_type) {
+            return Err(ApiError::BadRequest(format!(
+                "variant '{variant}' needs a raster image artifact"
+            )));
+        }
+        let (format, content_type) = if variant == "webp" || accepts_webp {
+            (ImageFormat::WebP, "image/webp")
+        } else {
+            (ImageFormat::Png, "image/png")
+        };
+        let modified = fs::metadata(&path)?.modified()?;
+        let key = (path.clone(), modified, variant, content_type);
+        if let Some(body) = lock(&self.variants).get(&key) {
+            return Ok(([(header::CONTENT_TYPE, content_type)], body).into_response());
+        }
+        let mut image =
+            image::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        if variant == "thumb" {
+            image = image.thumbnail(THUMBNAIL_EDGE, THUMBNAIL_EDGE);
+        }
+        // The WebP encoder only takes 8-bit RGB(A).
+        let image = if image.color().has_alpha() {
+            image::DynamicImage::ImageRgba8(image.to_rgba8())
+        } else {
+            image::DynamicImage::ImageRgb8(image.to_rgb8())
+        };
+        let mut body = Vec::new();
+        image
+            .write_to(&mut std::io::Cursor::new(&mut body), format)
+            .context("failed to encode the asset variant")?;
+        let body = Bytes::from(body);
+        lock(&self.variants).insert(key, body.clone());
+        Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+    }
+
+    fn fal_webhook_token(&self) -> String {
+        hex::encode(hmac::sign(&self.signing_key, b"fal-webhook"))
+    }
+
+    /// `POST /webhooks/fal?token=`: a queued Fal request finished. The
+    /// generation polling it picks the result up from here.
+    fn fal_webhook(&self, query: &HashMap<String, String>, body: &[u8]) -> ApiResult<Response> {
+        let token = query
+            .get("token")
+            .and_then(|raw| hex::decode(raw).ok())
+            .unwrap_or_default();
+        if hmac::verify(&self.signing_key, b"fal-webhook", &token).is_err() {
+            return Err(ApiError::Forbidden("webhook token is invalid".to_string()));
+        }
+        let body = Value::Object(request_json(body)?);
+        let delivered =
+            deliver_fal_webhook(&body).map_err(|err| ApiError::BadRequest(format!("{err:#}")))?;
+        Ok(Json(json!({ "delivered": delivered })).into_response())
+    }
+
+    fn run_models(&self, run_id: &str) -> RunModels {
+        lock(&self.models)
+            .get(run_id)
+            .cloned()
+            .unwrap_or_else(|| self.defaults.clone())
+    }
+
+    /// `GET /metrics`: event-fed counters plus the live job queue.
+    fn metrics_response(&self) -> Response {
+        let mut queued = 0;
+        let mut running = 0;
+        for job in lock(&self.jobs).values() {
+            match job.get("status").and_then(Value::as_str) {
+                Some("queued") => queued += 1,
+                Some("running") => running += 1,
+                _ => {}
+            }
+        }
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            self.metrics
+                .render(&[("queued", queued), ("running", running)]),
+        )
+            .into_response()
+    }
+
+    fn update_job(&self, job_id: &str, update: impl FnOnce(&mut Map<String, Value>)) {
+        if let Some(job) = lock(&self.jobs).get_mut(job_id) {
+            update(job);
+        }
+    }
+}
+
+/// Opens the run's engine (resuming when it already has state), generates,
+/// and finishes so the summary is current for `GET /runs/{id}`.
+fn generate_in_run(
+    run_dir: &FsPath,
+    models: &RunModels,
+    state: &ServerState,
+    prompt: &str,
+    settings: Map<String, Value>,
+    intent: Map<String, Value>,
+) -> Result<Vec<Map<String, Value>>> {
+    let events_path = run_dir.join("events.jsonl");
+    let mut engine = open_engine(
+        run_dir,
+        &events_path,
+        models.text_model.clone(),
+        models.image_model.clone(),
+        true,
+        false,
+    )?;
+    attach_event_sinks(&engine, None)?;
+    engine.events().add_sink(
+        Box::new(MetricsSink(Arc::clone(&state.metrics))),
+        ServerMetrics::filter(),
+    )?;
+    engine.events().add_sink(
+        Box::new(EventsChangedSink(Arc::clone(&state.events_changed))),
+        EventFilter::default(),
+    )?;
+    apply_cost_budget_env(&mut engine)?;
+    engine.set_global_cache(global_cache_from_env());
+    let mut request_settings = map_from(json!({
+        "size": "1024x1024",
+        "n": 1,
+        "quality_preset": "quality",
+    }));
+    request_settings.extend(settings);
+    let result = engine.generate(prompt, request_settings, intent);
+    engine.finish()?;
+    result
+}
+
+fn file_response(content_type: &'static str, body: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+fn request_json(body: &[u8]) -> ApiResult<Map<String, Value>> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Map::new());
+    }
+    match serde_json::from_slice(body) {
+        Ok(Value::Object(body)) => Ok(body),
+        Ok(_) => Err(ApiError::BadRequest(
+            "request body must be a JSON object".to_string(),
+        )),
+        Err(err) => Err(ApiError::BadRequest(format!(
+            "request body is not valid JSON: {err}"
+        ))),
+    }
+}
+
+fn string_field(body: &Map<String, Value>, key: &str) -> Option<String> {
+    body.get(key)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn object_field(body: &Map<String, Value>, key: &str) -> ApiResult<Map<String, Value>> {
+    match body.get(key) {
+        None | Some(Value::Null) => Ok(Map::new()),
+        Some(Value::Object(value)) => Ok(value.clone()),
+        Some(other) => Err(ApiError::BadRequest(format!(
+            "{key} must be an object (got {other})"
+        ))),
+    }
+}
+
+fn map_from(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("failed to generate a server secret"))?;
+    Ok(bytes)
+}
+
+fn artifact_content_type(path: &FsPath) -> &'static str {
+    match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("svg") => "image/svg+xml",
+        Some("gif") => "image/gif",
+        Some("avif") => "image/avif",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => guess_image_mime(path),
+    }
+}
+
+fn asset_variant(raw: &str) -> ApiResult<&'static str> {
+    let raw = raw.trim().to_ascii_lowercase();
+    ASSET_VARIANTS
+        .iter()
+        .find(|variant| **variant == raw)
+        .copied()
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "unknown asset variant '{raw}' (expected {})",
+                ASSET_VARIANTS.join(", ")
+            ))
+        })
+}
+
+fn asset_signature_payload(run_id: &str, artifact_id: &str, expires: u64) -> String {
+    format!("{run_id}/{artifact_id}/{expires}")
+}
+
+fn is_raster(content_type: &str) -> bool {
+    content_type.starts_with("image/") && content_type != "image/svg+xml"
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use brood_engine::NativeEngine;
+    use reqwest::blocking::Client;
+    use serde_json::{json, Value};
+
+    use serde_json::Map;
+
+    use super::{lock, ApiError, HttpServer, JobTable, ServeOptions};
+
+    /// Starts `server` on its own thread; returns its base URL and a client
+    /// that sends its bearer token.
+    fn start(server: HttpServer) -> anyhow::Result<(String, Client)> {
+        let base = format!("http://{}", server.local_addr()?);
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            format!("Bearer {}", server.token()).parse()?,
+        );
+        thread::spawn(move || server.run());
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok((base, client))
+    }
+
+    fn options(workers: usize) -> ServeOptions {
+        ServeOptions {
+            workers,
+            ..ServeOptions::default()
+        }
+    }
+
+    #[test]
+    fn serves_runs_generations_events_and_artifacts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let server = HttpServer::bind(
+            "127.0.0.1:0",
+            &temp.path().join("runs"),
+            ServeOptions {
+                text_model: Some("dryrun-text-1".to_string()),
+                image_model: Some("dryrun-image-1".to_string()),
+                client_tokens: [("studio".to_string(), "studio-token".to_string())].into(),
+                ..options(2)
+            },
+        )?;
+        let (base, client) = start(server)?;
+
+        let created: Value = client
+            .post(format!("{base}/runs"))
+            .bearer_auth("studio-token")
+            .json(&json!({"label": "api"}))
+            .send()?
+            .json()?;
+        let run_id = created["run_id"].as_str().unwrap_or_default().to_string();
+        assert!(run_id.ends_with("-api"));
+
+        let submitted = client
+            .post(format!("{base}/runs/{run_id}/generations"))
+            .bearer_auth("studio-token")
+            .json(&json!({"prompt": "a lighthouse", "settings": {"size": "32x32"}}))
+            .send()?;
+        assert_eq!(submitted.status().as_u16(), 202);
+        let job_id = submitted.json::<Value>()?["job_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let job = loop {
+            let job: Value = client
+                .get(format!("{base}/runs/{run_id}/generations/{job_id}"))
+                .send()?
+                .json()?;
+            if job["status"] == json!("succeeded") || job["status"] == json!("failed") {
+                break job;
+            }
+            assert!(Instant::now() < deadline, "generation did not finish");
+            thread::sleep(Duration::from_millis(50));
+        };
+        assert_eq!(job["status"], json!("succeeded"), "{job}");
+        assert_eq!(job["client"], json!("studio"));
+        assert!(job["worker"].as_u64().is_some_and(|worker| worker < 2));
+        assert!(job.get("queue_position").is_none());
+        let jobs: Value = client
+            .get(format!("{base}/jobs?client=studio"))
+            .send()?
+            .json()?;
+        assert_eq!(jobs["workers"], json!(2));
+        assert_eq!(jobs["jobs"][0]["job_id"], json!(job_id));
+        let by_id: Value = client.get(format!("{base}/jobs/{job_id}")).send()?.json()?;
+        assert_eq!(by_id["run_id"], json!(run_id));
+        let artifact_id = job["artifacts"][0]["artifact_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let artifact = client
+            .get(format!("{base}/runs/{run_id}/artifacts/{artifact_id}"))
+            .send()?;
+        assert_eq!(artifact.status().as_u16(), 200);
+        assert_eq!(
+            artifact
+                .headers()
+                .get("content-type")
+                .and_then(|value| value.to_str().ok()),
+            Some("image/png")
+        );
+        assert!(!artifact.bytes()?.is_empty());
+
+        let status: Value = client.get(format!("{base}/runs/{run_id}")).send()?.json()?;
+        assert_eq!(status["versions"][0]["artifact_ids"][0], json!(artifact_id));
+
+        let events = client
+            .get(format!("{base}/runs/{run_id}/events?follow=false"))
+            .send()?
+            .text()?;
+        assert!(events.starts_with("id: 1\ndata: {"), "{events}");
+        assert!(events.contains("\"type\":\"artifact_created\""));
+        let tail = client
+            .get(format!("{base}/runs/{run_id}/events?follow=false"))
+            .header("Last-Event-ID", "1")
+            .send()?
+            .text()?;
+        assert!(tail.starts_with("id: 2\n"));
+
+        let metrics = client.get(format!("{base}/metrics")).send()?.text()?;
+        assert!(metrics
+            .contains("brood_generations_total{provider=\"dryrun\",model=\"dryrun-image-1\"} 1\n"));
+        assert!(metrics.contains("brood_cache_requests_total{result=\"miss\"} 1\n"));
+        assert!(metrics.contains("brood_generation_queue_depth{status=\"running\"} 0\n"));
+
+        let missing = client.get(format!("{base}/runs/..%2Fetc")).send()?;
+        assert_eq!(missing.status().as_u16(), 404);
+        let unknown = client.get(format!("{base}/nope")).send()?;
+        assert_eq!(unknown.status().as_u16(), 404);
+        Ok(())
+    }
+
+    #[test]
+    fn admission_caps_pending_jobs_per_client_and_overall() {
+        let job = |client: &str, status: &str| -> Map<String, Value> {
+            json!({"client": client, "status": status})
+                .as_object()
+                .cloned()
+                .unwrap_or_default()
+        };
+        let mut jobs = JobTable::default();
+        jobs.insert(1, job("studio", "queued"));
+        jobs.insert(2, job("studio", "running"));
+        jobs.insert(3, job("studio", "succeeded"));
+        assert!(jobs.admit("studio", 3).is_ok());
+        assert!(matches!(
+            jobs.admit("studio", 2),
+            Err(ApiError::TooManyRequests(_))
+        ));
+        assert!(jobs.admit("other", 2).is_ok());
+
+        for seq in 10..10 + super::MAX_PENDING_JOBS as u64 {
+            jobs.insert(seq, job("crowd", "queued"));
+        }
+        assert!(matches!(
+            jobs.admit("other", usize::MAX),
+            Err(ApiError::TooManyRequests(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_requests_without_the_token_or_for_foreign_hosts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let server = HttpServer::bind(
+            "127.0.0.1:0",
+            &temp.path().join("runs"),
+            ServeOptions {
+                token: Some("s3cret".to_string()),
+                ..options(1)
+            },
+        )?;
+        let (base, client) = start(server)?;
+        let anonymous = Client::new();
+
+        assert_eq!(anonymous.get(format!("{base}/runs")).send()?.status(), 401);
+        assert_eq!(
+            anonymous.get(format!("{base}/metrics")).send()?.status(),
+            401
+        );
+        assert_eq!(anonymous.get(format!("{base}/jobs")).send()?.status(), 401);
+        let wrong = anonymous
+            .get(format!("{base}/runs"))
+            .bearer_auth("guess")
+            .send()?;
+        assert_eq!(wrong.status(), 401);
+        assert_eq!(
+            anonymous.get(format!("{base}/health")).send()?.status(),
+            200
+        );
+        assert_eq!(client.get(format!("{base}/runs")).send()?.status(), 200);
+
+        let rebound = client
+            .get(format!("{base}/runs"))
+            .header("Host", "attacker.example")
+            .send()?;
+        assert_eq!(rebound.status(), 403);
+        let localhost = client
+            .get(format!("{base}/runs"))
+            .header("Host", "localhost:8787")
+            .send()?;
+        assert_eq!(localhost.status(), 200);
+        assert!(localhost
+            .headers()
+            .get("access-control-allow-origin")
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn fal_webhooks_need_the_registered_token() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let server = HttpServer::bind("127.0.0.1:0", &temp.path().join("runs"), options(2))?;
+        let base = format!("http://{}", server.local_addr()?);
+        let webhook_url = server.register_fal_webhook(&format!("{base}/"));
+        brood_engine::set_fal_webhook_url(None);
+        assert!(webhook_url.starts_with(&format!("{base}/webhooks/fal?token=")));
+        let (_, client) = start(server)?;
+
+        let body = json!({"request_id": "q-unknown", "status": "OK", "payload": {}});
+        let accepted = client.post(&webhook_url).json(&body).send()?;
+        assert_eq!(accepted.status().as_u16(), 200);
+        assert_eq!(accepted.json::<Value>()?["delivered"], json!(false));
+        let forged = client
+            .post(format!("{base}/webhooks/fal?token=00"))
+            .json(&body)
+            .send()?;
+        assert_eq!(forged.status().as_u16(), 403);
+        let wrong_method = client.get(&webhook_url).send()?;
+        assert_eq!(wrong_method.status().as_u16(), 405);
+        Ok(())
+    }
+
+    #[test]
+    fn signed_asset_urls_serve_variants_until_tampered() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let runs_dir = temp.path().join("runs");
+        let run_dir = runs_dir.join("run-assets");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let mut settings = serde_json::Map::new();
+        settings.insert("size".to_string(), json!("640x320"));
+        let artifacts = engine.generate("a harbor", settings, serde_json::Map::new())?;
+        let artifact_id = artifacts[0]["artifact_id"].as_str().unwrap_or_default();
+
+        let server = HttpServer::bind("127.0.0.1:0", &runs_dir, options(2))?;
+        let (base, client) = start(server)?;
+
+        let signed: Value = client
+            .post(format!(
+                "{base}/runs/run-assets/artifacts/{artifact_id}/url"
+            ))
+            .json(&json!({"ttl_s": 60}))
+            .send()?
+            .json()?;
+        let path = signed["path"].as_str().unwrap_or_default().to_string();
+        assert!(path.starts_with(&format!("/assets/run-assets/{artifact_id}?expires=")));
+        assert_eq!(signed["url"], json!(format!("{base}{path}")));
+
+        let anonymous = Client::new();
+        let original = anonymous.get(format!("{base}{path}")).send()?;
+        assert_eq!(original.status().as_u16(), 200);
+        assert_eq!(original.headers()["content-type"].to_str()?, "image/png");
+        let negotiated = anonymous
+            .get(format!("{base}{path}"))
+            .header("Accept", "image/webp,image/*")
+            .send()?;
+        assert_eq!(negotiated.headers()["content-type"].to_str()?, "image/webp");
+        let thumb = anonymous
+            .get(format!("{base}{path}&variant=thumb"))
+            .send()?
+            .bytes()?;
+        let thumb = image::load_from_memory(&thumb)?;
+        assert_eq!((thumb.width(), thumb.height()), (256, 128));
+        let cached = anonymous
+            .get(format!("{base}{path}&variant=thumb"))
+            .send()?
+            .bytes()?;
+        assert_eq!(image::load_from_memory(&cached)?.width(), 256);
+        let direct = anonymous
+            .get(format!("{base}/runs/run-assets/artifacts/{artifact_id}"))
+            .send()?;
+        assert_eq!(direct.status().as_u16(), 401);
+        let minted = anonymous
+            .post(format!(
+                "{base}/runs/run-assets/artifacts/{artifact_id}/url"
+            ))
+            .send()?;
+        assert_eq!(minted.status().as_u16(), 401);
+
+        let tampered = path.replace("run-assets/", "run-assets/x");
+        let forbidden = anonymous.get(format!("{base}{tampered}")).send()?;
+        assert_eq!(forbidden.status().as_u16(), 403);
+        let unsigned = anonymous
+            .get(format!("{base}/assets/run-assets/{artifact_id}"))
+            .send()?;
+        assert_eq!(unsigned.status().as_u16(), 403);
+        let invalid = client
+            .post(format!(
+                "{base}/runs/run-assets/artifacts/{artifact_id}/url"
+            ))
+            .json(&json!({"ttl_s": 0, "variant": "gif"}))
+            .send()?;
+        assert_eq!(invalid.status().as_u16(), 400);
+        Ok(())
+    }
+
+    #[test]
+    fn client_tokens_only_reach_their_own_runs() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let server = HttpServer::bind(
+            "127.0.0.1:0",
+            &temp.path().join("runs"),
+            ServeOptions {
+                image_model: Some("dryrun-image-1".to_string()),
+                client_tokens: [
+                    ("studio".to_string(), "studio-token".to_string()),
+                    ("rival".to_string(), "rival-token".to_string()),
+                ]
+                .into(),
+                ..options(1)
+            },
+        )?;
+        let (base, operator) = start(server)?;
+        let create = |token: &str| -> anyhow::Result<String> {
+            let created: Value = operator
+                .post(format!("{base}/runs"))
+                .bearer_auth(token)
+                .json(&json!({}))
+                .send()?
+                .json()?;
+            Ok(created["run_id"].as_str().unwrap_or_default().to_string())
+        };
+        let studio_run = create("studio-token")?;
+        let rival_run = create("rival-token")?;
+        let submitted: Value = operator
+            .post(format!("{base}/runs/{studio_run}/generations"))
+            .bearer_auth("studio-token")
+            .json(&json!({"prompt": "a lighthouse", "settings": {"size": "32x32"}}))
+            .send()?
+            .json()?;
+        let job_id = submitted["job_id"].as_str().unwrap_or_default().to_string();
+
+        for path in [
+            format!("/runs/{studio_run}"),
+            format!("/runs/{studio_run}/events?follow=false"),
+            format!("/runs/{studio_run}/generations/{job_id}"),
+            format!("/jobs/{job_id}"),
+        ] {
+            let rival = operator
+                .get(format!("{base}{path}"))
+                .bearer_auth("rival-token")
+                .send()?;
+            assert_eq!(rival.status().as_u16(), 404, "{path}");
+            let studio = operator
+                .get(format!("{base}{path}"))
+                .bearer_auth("studio-token")
+                .send()?;
+            assert_eq!(studio.status().as_u16(), 200, "{path}");
+            assert_eq!(
+                operator.get(format!("{base}{path}")).send()?.status(),
+                200,
+                "{path}"
+            );
+        }
+        let hijack = operator
+            .post(format!("{base}/runs/{studio_run}/generations"))
+            .bearer_auth("rival-token")
+            .json(&json!({"prompt": "mine now"}))
+            .send()?;
+        assert_eq!(hijack.status().as_u16(), 404);
+
+        let runs = |token: Option<&str>| -> anyhow::Result<Value> {
+            let request = operator.get(format!("{base}/runs"));
+            let request = match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            };
+            Ok(request.send()?.json::<Value>()?["runs"].clone())
+        };
+        assert_eq!(runs(Some("rival-token"))?, json!([rival_run]));
+        assert_eq!(runs(Some("studio-token"))?, json!([studio_run]));
+        assert_eq!(runs(None)?.as_array().map(Vec::len), Some(2));
+        let rival_jobs: Value = operator
+            .get(format!("{base}/jobs"))
+            .bearer_auth("rival-token")
+            .send()?
+            .json()?;
+        assert_eq!(rival_jobs["jobs"], json!([]));
+        Ok(())
+    }
+
+    #[test]
+    fn a_panic_while_holding_a_table_does_not_poison_later_requests() {
+        let jobs = std::sync::Mutex::new(JobTable::default());
+        let _ = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _held = jobs.lock();
+                    panic!("handler failed");
+                })
+                .join()
+        });
+        assert!(jobs.is_poisoned());
+        assert!(lock(&jobs).admit("studio", 1).is_ok());
+    }
+}
@@ -0,0 +1,313 @@
+use std::any::Any;
+use std::collections::{HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+/// A unit of work for [`WorkerPool`]: the run dir it needs to itself and
+/// the client it is scheduled fairly against.
+pub(crate) struct QueuedJob<T> {
+    pub(crate) job_id: String,
+    pub(crate) run_id: String,
+    pub(crate) client: String,
+    pub(crate) payload: T,
+}
+
+/// Jobs waiting for a worker, one FIFO per client. Clients take turns, so
+/// one client's burst cannot starve the others, and a job whose run is
+/// already being generated waits without blocking jobs for other runs.
+struct FairQueue<T> {
+    clients: VecDeque<(String, VecDeque<QueuedJob<T>>)>,
+    busy_runs: HashSet<String>,
+    closed: bool,
+}
+
+impl<T> FairQueue<T> {
+    fn push(&mut self, job: QueuedJob<T>) {
+        match self
+            .clients
+            .iter_mut()
+            .find(|(client, _)| *client == job.client)
+        {
+            Some((_, jobs)) => jobs.push_back(job),
+            None => self
+                .clients
+                .push_back((job.client.clone(), VecDeque::from([job]))),
+        }
+    }
+
+    /// The next runnable job, taking clients round-robin. Its run is marked
+    /// busy until [`FairQueue::finish`].
+    fn next(&mut self) -> Option<QueuedJob<T>> {
+        for _ in 0..self.clients.len() {
+            let (client, mut jobs) = self.clients.pop_front()?;
+            let runnable = jobs
+                .iter()
+                .position(|job| !self.busy_runs.contains(&job.run_id));
+            let job = runnable.and_then(|idx| jobs.remove(idx));
+            if !jobs.is_empty() {
+                self.clients.push_back((client, jobs));
+            }
+            if let Some(job) = job {
+                self.busy_runs.insert(job.run_id.clone());
+                return Some(job);
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self, run_id: &str) {
+        self.busy_runs.remove(run_id);
+    }
+
+    /// 0-based place of `job_id` in the order workers would take the queue
+    /// if every run were free.
+    fn position(&self, job_id: &str) -> Option<usize> {
+        let mut cursors: Vec<_> = self.clients.iter().map(|(_, jobs)| jobs.iter()).collect();
+        let mut position = 0;
+        loop {
+            let mut any = false;
+            for cursor in &mut cursors {
+                if let Some(job) = cursor.next() {
+                    if job.job_id == job_id {
+                        return Some(position);
+                    }
+                    position += 1;
+                    any = true;
+                }
+            }
+            if !any {
+                return None;
+            }
+        }
+    }
+}
+
+struct Shared<T> {
+    queue: Mutex<FairQueue<T>>,
+    ready: Condvar,
+}
+
+/// A fixed set of worker threads draining a [`FairQueue`]. `handler` runs
+/// each job on a worker, with that worker's index; if it panics,
+/// `panicked` gets the job id and the panic message. Dropping the pool lets
+/// the workers finish what is queued and exit.
+pub(crate) struct WorkerPool<T> {
+    shared: Arc<Shared<T>>,
+    workers: usize,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    pub(crate) fn new(
+        workers: usize,
+        handler: impl Fn(usize, QueuedJob<T>) + Send + Sync + 'static,
+        panicked: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> Self {
+        let workers = workers.max(1);
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(FairQueue {
+                clients: VecDeque::new(),
+                busy_runs: HashSet::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        });
+        let handler = Arc::new(handler);
+        let panicked = Arc::new(panicked);
+        for worker in 0..workers {
+            let shared = Arc::clone(&shared);
+            let handler = Arc::clone(&handler);
+            let panicked = Arc::clone(&panicked);
+            thread::spawn(move || work(worker, &shared, handler.as_ref(), panicked.as_ref()));
+        }
+        Self { shared, workers }
+    }
+
+    pub(crate) fn submit(&self, job: QueuedJob<T>) {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(job);
+        self.shared.ready.notify_one();
+    }
+
+    pub(crate) fn queue_position(&self, job_id: &str) -> Option<usize> {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .position(job_id)
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        self.workers
+    }
+}
+
+impl<T> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+fn work<T>(
+    worker: usize,
+    shared: &Shared<T>,
+    handler: &dyn Fn(usize, QueuedJob<T>),
+    panicked: &dyn Fn(&str, &str),
+) {
+    loop {
+        let job = {
+            let mut queue = shared.queue.lock().unwrap_or_else(PoisonError::into_inner);
+            loop {
+                if let Some(job) = queue.next() {
+                    break job;
+                }
+                if queue.closed && queue.clients.is_empty() {
+                    return;
+                }
+                queue = shared
+                    .ready
+                    .wait(queue)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+        let run_id = job.run_id.clone();
+        let job_id = job.job_id.clone();
+        // A panicking job must not leave its run marked busy forever.
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| handler(worker, job))) {
+            panicked(&job_id, &panic_message(payload.as_ref()));
+        }
+        shared
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .finish(&run_id);
+        // A job held back by this run may be runnable now.
+        shared.ready.notify_all();
+    }
+}
+
+/// The text a panic was raised with, when it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{FairQueue, QueuedJob, WorkerPool};
+
+    fn job(job_id: &str, run_id: &str, client: &str) -> QueuedJob<()> {
+        QueuedJob {
+            job_id: job_id.to_string(),
+            run_id: run_id.to_string(),
+            client: client.to_string(),
+            payload: (),
+        }
+    }
+
+    #[test]
+    fn clients_take_turns_and_busy_runs_wait() {
+        let mut queue = FairQueue {
+            clients: VecDeque::new(),
+            busy_runs: HashSet::new(),
+            closed: false,
+        };
+        for id in ["a1", "a2", "a3"] {
+            queue.push(job(id, &format!("run-{id}"), "alice"));
+        }
+        queue.push(job("b1", "run-b", "bob"));
+        queue.push(job("b2", "run-b", "bob"));
+        assert_eq!(queue.position("b1"), Some(1));
+        assert_eq!(queue.position("a3"), Some(4));
+        assert_eq!(queue.position("zz"), None);
+
+        let mut order = Vec::new();
+        while let Some(next) = queue.next() {
+            order.push(next.job_id);
+        }
+        // b2 shares bob's run with b1, which is still running.
+        assert_eq!(order, ["a1", "b1", "a2", "a3"]);
+        queue.finish("run-b");
+        assert_eq!(queue.next().map(|next| next.job_id), Some("b2".to_string()));
+        assert!(queue.next().is_none());
+    }
+
+    #[test]
+    fn pool_runs_jobs_for_different_runs_in_parallel() -> anyhow::Result<()> {
+        let (started, started_rx) = mpsc::channel();
+        let gate = Arc::new(Mutex::new(()));
+        let held = gate.lock().expect("gate");
+        let pool = {
+            let gate = Arc::clone(&gate);
+            WorkerPool::new(
+                2,
+                move |worker, job: QueuedJob<()>| {
+                    started.send((worker, job.job_id)).expect("started channel");
+                    drop(gate.lock().expect("gate"));
+                },
+                |_, _| {},
+            )
+        };
+        assert_eq!(pool.workers(), 2);
+        pool.submit(job("j1", "run-1", "alice"));
+        pool.submit(job("j2", "run-1", "alice"));
+        pool.submit(job("j3", "run-2", "bob"));
+
+        let mut first: Vec<_> = (0..2)
+            .map(|_| started_rx.recv_timeout(Duration::from_secs(5)))
+            .collect::<Result<_, _>>()?;
+        first.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(first[0].1, "j1");
+        assert_eq!(first[1].1, "j3");
+        assert_ne!(first[0].0, first[1].0);
+        assert_eq!(pool.queue_position("j2"), Some(0));
+
+        drop(held);
+        let (_, last) = started_rx.recv_timeout(Duration::from_secs(5))?;
+        assert_eq!(last, "j2");
+        Ok(())
+    }
+
+    #[test]
+    fn panicking_jobs_are_reported_and_free_their_run() -> anyhow::Result<()> {
+        let (done, done_rx) = mpsc::channel();
+        let (panicked, panicked_rx) = mpsc::channel();
+        let pool = WorkerPool::new(
+            1,
+            move |_, job: QueuedJob<()>| {
+                if job.job_id == "j1" {
+                    panic!("engine blew up");
+                }
+                done.send(job.job_id).expect("done channel");
+            },
+            move |job_id, message| {
+                panicked
+                    .send((job_id.to_string(), message.to_string()))
+                    .expect("panicked channel");
+            },
+        );
+        pool.submit(job("j1", "run-1", "alice"));
+        pool.submit(job("j2", "run-1", "alice"));
+        assert_eq!(
+            panicked_rx.recv_timeout(Duration::from_secs(5))?,
+            ("j1".to_string(), "engine blew up".to_string())
+        );
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5))?, "j2");
+        Ok(())
+    }
+}
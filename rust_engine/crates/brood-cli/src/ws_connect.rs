@@ -0,0 +1,245 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use brood_engine::HttpConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::handshake::client::Response;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{client_tls_with_config, Connector, HandshakeError, WebSocket};
+
+use super::first_non_empty_env;
+
+/// Longest proxy `CONNECT` response head we read before giving up.
+const MAX_PROXY_RESPONSE: usize = 8 * 1024;
+
+/// Opens a websocket with the same network settings as Brood's HTTP
+/// clients (see [`HttpConfig`]): the proxy, tunnelled with `CONNECT`, the
+/// extra root CAs and the client certificate.
+pub(crate) fn websocket_connect(
+    request: impl IntoClientRequest,
+) -> tungstenite::Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
+    connect_with(request, &HttpConfig::from_env())
+}
+
+fn connect_with(
+    request: impl IntoClientRequest,
+    config: &HttpConfig,
+) -> tungstenite::Result<(WebSocket<MaybeTlsStream<TcpStream>>, Response)> {
+    let request = request.into_client_request()?;
+    let secure = request.uri().scheme_str() == Some("wss");
+    let Some(host) = request.uri().host().map(str::to_string) else {
+        return Err(tungstenite::Error::Url(
+            tungstenite::error::UrlError::NoHostName,
+        ));
+    };
+    let port = request
+        .uri()
+        .port_u16()
+        .unwrap_or(if secure { 443 } else { 80 });
+    let stream = match proxy_for(config, secure, &host) {
+        Some(proxy) => tunnel(&proxy, &host, port).map_err(io_error)?,
+        None => TcpStream::connect((host.as_str(), port))?,
+    };
+    let connector = if secure {
+        Some(Connector::Rustls(Arc::new(
+            tls_config(config).map_err(io_error)?,
+        )))
+    } else {
+        None
+    };
+    client_tls_with_config(request, stream, None, connector).map_err(|err| match err {
+        HandshakeError::Failure(err) => err,
+        HandshakeError::Interrupted(_) => {
+            io_error(anyhow::anyhow!("websocket handshake was interrupted"))
+        }
+    })
+}
+
+fn io_error(err: anyhow::Error) -> tungstenite::Error {
+    tungstenite::Error::Io(io::Error::other(format!("{err:#}")))
+}
+
+/// The proxy for `host`, as reqwest picks it: `BROOD_PROXY`, then the
+/// scheme's `HTTPS_PROXY`/`HTTP_PROXY`, then `ALL_PROXY`, unless `NO_PROXY`
+/// names the host.
+fn proxy_for(config: &HttpConfig, secure: bool, host: &str) -> Option<String> {
+    let proxy = config.proxy.clone().or_else(|| {
+        let scheme_keys: &[&str] = if secure {
+            &["HTTPS_PROXY", "https_proxy"]
+        } else {
+            &["HTTP_PROXY", "http_proxy"]
+        };
+        first_non_empty_env(scheme_keys)
+            .or_else(|| first_non_empty_env(&["ALL_PROXY", "all_proxy"]))
+    })?;
+    let no_proxy = first_non_empty_env(&["NO_PROXY", "no_proxy"]).unwrap_or_default();
+    (!bypasses_proxy(&no_proxy, host)).then_some(proxy)
+}
+
+/// Whether a `NO_PROXY` list names `host`: `*`, the host itself or one of
+/// its parent domains.
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+        })
+}
+
+/// A TCP connection to `host:port` through the HTTP proxy at `proxy`.
+fn tunnel(proxy: &str, host: &str, port: u16) -> Result<TcpStream> {
+    let url = reqwest::Url::parse(proxy).with_context(|| format!("Invalid proxy URL '{proxy}'"))?;
+    if url.scheme() != "http" {
+        bail!(
+            "websockets can only use http:// proxies (got '{}')",
+            url.scheme()
+        );
+    }
+    let proxy_host = url
+        .host_str()
+        .with_context(|| format!("proxy URL '{proxy}' has no host"))?;
+    let proxy_port = url.port_or_known_default().unwrap_or(80);
+    let mut stream = TcpStream::connect((proxy_host, proxy_port))
+        .with_context(|| format!("failed to connect to proxy {proxy_host}:{proxy_port}"))?;
+    let target = if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
+    let mut head = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if !url.username().is_empty() {
+        let credentials = format!("{}:{}", url.username(), url.password().unwrap_or_default());
+        head.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        ));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_PROXY_RESPONSE {
+            bail!("proxy {proxy_host} sent an oversized CONNECT response");
+        }
+        if stream.read(&mut byte)? == 0 {
+            bail!("proxy {proxy_host} closed the connection during CONNECT");
+        }
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        bail!("proxy {proxy_host} refused CONNECT to {target}: {status_line}");
+    }
+    Ok(stream)
+}
+
+/// Webpki roots plus the configured CA bundle, and the client certificate
+/// when one is set.
+fn tls_config(config: &HttpConfig) -> Result<ClientConfig> {
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(path) = &config.ca_bundle {
+        let pem = fs::read(path)
+            .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+        for cert in CertificateDer::pem_slice_iter(&pem) {
+            let cert = cert.with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+            roots
+                .add(cert)
+                .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+        }
+    }
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let Some(cert_path) = &config.client_cert else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let cert_pem = fs::read(cert_path)
+        .with_context(|| format!("Failed to read client certificate {}", cert_path.display()))?;
+    let certs = CertificateDer::pem_slice_iter(&cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid client certificate {}", cert_path.display()))?;
+    let key = match &config.client_key {
+        Some(key_path) => PrivateKeyDer::from_pem_file(key_path)
+            .with_context(|| format!("Invalid client key {}", key_path.display()))?,
+        None => PrivateKeyDer::from_pem_slice(&cert_pem)
+            .with_context(|| format!("Invalid client certificate {}", cert_path.display()))?,
+    };
+    builder
+        .with_client_auth_cert(certs, key)
+        .with_context(|| format!("Invalid client certificate {}", cert_path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use brood_engine::HttpConfig;
+    use tungstenite::{accept, Message};
+
+    use super::{bypasses_proxy, connect_with};
+
+    #[test]
+    fn websockets_tunnel_through_the_configured_proxy() -> anyhow::Result<()> {
+        let proxy = TcpListener::bind("127.0.0.1:0")?;
+        let proxy_url = format!("http://user:secret@{}", proxy.local_addr()?);
+        let server = thread::spawn(move || -> anyhow::Result<(String, String)> {
+            let (stream, _) = proxy.accept()?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                head.push(line.trim_end().to_string());
+            }
+            (&stream).write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+            let mut socket = accept(stream)?;
+            let message = socket.read()?.into_text()?.to_string();
+            Ok((head.join("\n"), message))
+        });
+
+        let config = HttpConfig {
+            proxy: Some(proxy_url),
+            ..HttpConfig::default()
+        };
+        let (mut socket, _) = connect_with("ws://events.example.invalid:9000/feed", &config)?;
+        socket.send(Message::text("hello"))?;
+        let (head, message) = server.join().expect("proxy thread")?;
+        assert!(head.starts_with("CONNECT events.example.invalid:9000 HTTP/1.1"));
+        assert!(head.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ="));
+        assert_eq!(message, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn no_proxy_matches_hosts_and_parent_domains() {
+        assert!(bypasses_proxy(
+            "localhost, .internal.example",
+            "api.internal.example"
+        ));
+        assert!(bypasses_proxy("*", "anything.example"));
+        assert!(!bypasses_proxy("internal.example", "notinternal.example"));
+        assert!(!bypasses_proxy("", "api.example"));
+    }
+}
@@ -136,6 +136,10 @@ pub(crate) const NO_ARG_COMMANDS: &[CommandSpec] = &[
         command: "intent_rt_mother_stop",
         action: "intent_rt_mother_stop",
     },
+    CommandSpec {
+        command: "history",
+        action: "history",
+    },
     CommandSpec {
         command: "help",
         action: "help",
@@ -147,41 +151,396 @@ pub(crate) const EXPORT_COMMAND: CommandSpec = CommandSpec {
     action: "export",
 };
 
-pub const CHAT_HELP_COMMANDS: &[&str] = &[
-    "/profile",
-    "/text_model",
-    "/image_model",
-    "/fast",
-    "/quality",
-    "/cheaper",
-    "/better",
-    "/optimize",
-    "/recreate",
-    "/describe",
-    "/canvas_context",
-    "/intent_infer",
-    "/prompt_compile",
-    "/mother_generate",
-    "/diagnose",
-    "/recast",
-    "/use",
-    "/canvas_context_rt_start",
-    "/canvas_context_rt_stop",
-    "/canvas_context_rt",
-    "/intent_rt_start",
-    "/intent_rt_stop",
-    "/intent_rt",
-    "/intent_rt_mother_start",
-    "/intent_rt_mother_stop",
-    "/intent_rt_mother",
-    "/blend",
-    "/swap_dna",
-    "/argue",
-    "/bridge",
-    "/extract_dna",
-    "/soul_leech",
-    "/extract_rule",
-    "/odd_one_out",
-    "/triforce",
-    "/export",
+pub(crate) const UPSCALE_COMMAND: CommandSpec = CommandSpec {
+    command: "upscale",
+    action: "upscale",
+};
+
+pub(crate) const PROVIDER_COMMAND: CommandSpec = CommandSpec {
+    command: "provider",
+    action: "provider",
+};
+
+pub(crate) const VIDEO_COMMAND: CommandSpec = CommandSpec {
+    command: "video",
+    action: "video",
+};
+
+pub(crate) const BUDGET_COMMAND: CommandSpec = CommandSpec {
+    command: "budget",
+    action: "budget",
+};
+
+pub(crate) const DELETE_COMMAND: CommandSpec = CommandSpec {
+    command: "delete",
+    action: "delete_version",
+};
+
+pub(crate) const RESTORE_COMMAND: CommandSpec = CommandSpec {
+    command: "restore",
+    action: "restore_version",
+};
+
+pub(crate) const COMPARE_COMMAND: CommandSpec = CommandSpec {
+    command: "compare",
+    action: "compare",
+};
+
+pub(crate) const WHY_DIFFERENT_COMMAND: CommandSpec = CommandSpec {
+    command: "why-different",
+    action: "why_different",
+};
+
+pub(crate) const GENERATE_COMMAND: CommandSpec = CommandSpec {
+    command: "generate",
+    action: "generate",
+};
+
+pub(crate) const GRID_COMMAND: CommandSpec = CommandSpec {
+    command: "grid",
+    action: "grid",
+};
+
+pub(crate) const UNDO_COMMAND: CommandSpec = CommandSpec {
+    command: "undo",
+    action: "revert_version",
+};
+
+pub(crate) const BRANCH_COMMAND: CommandSpec = CommandSpec {
+    command: "branch",
+    action: "branch",
+};
+
+pub(crate) const TAG_COMMAND: CommandSpec = CommandSpec {
+    command: "tag",
+    action: "tag_artifact",
+};
+
+pub(crate) const FAVORITE_COMMAND: CommandSpec = CommandSpec {
+    command: "favorite",
+    action: "tag_artifact",
+};
+
+pub(crate) const VARS_COMMAND: CommandSpec = CommandSpec {
+    command: "vars",
+    action: "set_variables",
+};
+
+pub(crate) const AUTOPICK_COMMAND: CommandSpec = CommandSpec {
+    command: "autopick",
+    action: "auto_select",
+};
+
+pub(crate) const SELECT_COMMAND: CommandSpec = CommandSpec {
+    command: "select",
+    action: "select_region",
+};
+
+pub(crate) const PALETTE_COMMAND: CommandSpec = CommandSpec {
+    command: "palette",
+    action: "palette",
+};
+
+pub(crate) const PRESET_COMMAND: CommandSpec = CommandSpec {
+    command: "preset",
+    action: "preset",
+};
+
+/// Usage hint and one-line summary for a chat command; drives `/help`,
+/// the `/` palette and suggestions for mistyped commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChatCommandHelp {
+    /// Command with its leading slash, e.g. `/generate`.
+    pub command: &'static str,
+    /// Argument hint; empty for commands without arguments.
+    pub args: &'static str,
+    pub summary: &'static str,
+}
+
+pub const CHAT_HELP_COMMANDS: &[ChatCommandHelp] = &[
+    ChatCommandHelp {
+        command: "/generate",
+        args: "<prompt> [--n N] [--seed N] [--size WxH]",
+        summary: "Generate images (plain text does the same)",
+    },
+    ChatCommandHelp {
+        command: "/profile",
+        args: "<name>",
+        summary: "Set the request profile and apply its style profile",
+    },
+    ChatCommandHelp {
+        command: "/text_model",
+        args: "<model>",
+        summary: "Switch the text model",
+    },
+    ChatCommandHelp {
+        command: "/image_model",
+        args: "<model>",
+        summary: "Switch the image model",
+    },
+    ChatCommandHelp {
+        command: "/fast",
+        args: "",
+        summary: "Quality preset: fast",
+    },
+    ChatCommandHelp {
+        command: "/quality",
+        args: "",
+        summary: "Quality preset: quality",
+    },
+    ChatCommandHelp {
+        command: "/cheaper",
+        args: "",
+        summary: "Quality preset: cheaper",
+    },
+    ChatCommandHelp {
+        command: "/better",
+        args: "",
+        summary: "Quality preset: better",
+    },
+    ChatCommandHelp {
+        command: "/optimize",
+        args: "[review|auto] <goals>",
+        summary: "Tune settings toward goals",
+    },
+    ChatCommandHelp {
+        command: "/recreate",
+        args: "<path>",
+        summary: "Recreate a reference image",
+    },
+    ChatCommandHelp {
+        command: "/describe",
+        args: "[path]",
+        summary: "Describe an image (default: active image)",
+    },
+    ChatCommandHelp {
+        command: "/canvas_context",
+        args: "[path]",
+        summary: "Describe the canvas context of an image",
+    },
+    ChatCommandHelp {
+        command: "/intent_infer",
+        args: "<payload.json>",
+        summary: "Infer intent from a canvas payload",
+    },
+    ChatCommandHelp {
+        command: "/prompt_compile",
+        args: "<payload.json>",
+        summary: "Compile a prompt from a payload",
+    },
+    ChatCommandHelp {
+        command: "/mother_generate",
+        args: "<payload.json>",
+        summary: "Generate from a Mother payload",
+    },
+    ChatCommandHelp {
+        command: "/diagnose",
+        args: "[path]",
+        summary: "Critique an image (default: active image)",
+    },
+    ChatCommandHelp {
+        command: "/recast",
+        args: "[path]",
+        summary: "Reimagine an image in a new medium (default: active image)",
+    },
+    ChatCommandHelp {
+        command: "/use",
+        args: "<path>",
+        summary: "Set the active image for edits",
+    },
+    ChatCommandHelp {
+        command: "/canvas_context_rt_start",
+        args: "",
+        summary: "Start realtime canvas context",
+    },
+    ChatCommandHelp {
+        command: "/canvas_context_rt_stop",
+        args: "",
+        summary: "Stop realtime canvas context",
+    },
+    ChatCommandHelp {
+        command: "/canvas_context_rt",
+        args: "<path>",
+        summary: "Send a snapshot to realtime canvas context",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt_start",
+        args: "",
+        summary: "Start realtime intent inference",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt_stop",
+        args: "",
+        summary: "Stop realtime intent inference",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt",
+        args: "<path>",
+        summary: "Send a snapshot to realtime intent inference",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt_mother_start",
+        args: "",
+        summary: "Start realtime Mother intent inference",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt_mother_stop",
+        args: "",
+        summary: "Stop realtime Mother intent inference",
+    },
+    ChatCommandHelp {
+        command: "/intent_rt_mother",
+        args: "<path>",
+        summary: "Send a snapshot to realtime Mother intent inference",
+    },
+    ChatCommandHelp {
+        command: "/blend",
+        args: "<image_a> <image_b>",
+        summary: "Blend two images",
+    },
+    ChatCommandHelp {
+        command: "/swap_dna",
+        args: "<image_a> <image_b>",
+        summary: "Swap structure and surface between two images",
+    },
+    ChatCommandHelp {
+        command: "/argue",
+        args: "<image_a> <image_b>",
+        summary: "Compare two directions",
+    },
+    ChatCommandHelp {
+        command: "/bridge",
+        args: "<image_a> <image_b>",
+        summary: "Find the aesthetic midpoint of two images",
+    },
+    ChatCommandHelp {
+        command: "/extract_dna",
+        args: "<image_a> [image_b ...]",
+        summary: "Extract palette and material DNA",
+    },
+    ChatCommandHelp {
+        command: "/soul_leech",
+        args: "<image_a> [image_b ...]",
+        summary: "Extract the emotional read of images",
+    },
+    ChatCommandHelp {
+        command: "/extract_rule",
+        args: "<image_a> <image_b> <image_c>",
+        summary: "Infer the rule shared by three images",
+    },
+    ChatCommandHelp {
+        command: "/odd_one_out",
+        args: "<image_a> <image_b> <image_c>",
+        summary: "Find the image that breaks the pattern",
+    },
+    ChatCommandHelp {
+        command: "/triforce",
+        args: "<image_a> <image_b> <image_c>",
+        summary: "Find the centroid of three images",
+    },
+    ChatCommandHelp {
+        command: "/export",
+        args: "[html|gallery|pdf|<selector> <profile>]",
+        summary: "Export the run or selected files",
+    },
+    ChatCommandHelp {
+        command: "/upscale",
+        args: "[artifact_id] [factor]",
+        summary: "Upscale an artifact (default: latest, 2x)",
+    },
+    ChatCommandHelp {
+        command: "/provider",
+        args: "[list|enable|disable|priority] <names>",
+        summary: "Show or change image providers",
+    },
+    ChatCommandHelp {
+        command: "/video",
+        args: "<prompt>",
+        summary: "Generate a video",
+    },
+    ChatCommandHelp {
+        command: "/budget",
+        args: "[run|session] <usd|off> | force",
+        summary: "Show or set cost caps",
+    },
+    ChatCommandHelp {
+        command: "/delete",
+        args: "<version_id>",
+        summary: "Delete a version",
+    },
+    ChatCommandHelp {
+        command: "/restore",
+        args: "<version_id>",
+        summary: "Restore a deleted version",
+    },
+    ChatCommandHelp {
+        command: "/undo",
+        args: "[version_id]",
+        summary: "Revert the latest (or given) version",
+    },
+    ChatCommandHelp {
+        command: "/branch",
+        args: "<version_id>",
+        summary: "Continue from an earlier version",
+    },
+    ChatCommandHelp {
+        command: "/history",
+        args: "",
+        summary: "Show the version tree",
+    },
+    ChatCommandHelp {
+        command: "/tag",
+        args: "<artifact_id> <label>...",
+        summary: "Tag an artifact",
+    },
+    ChatCommandHelp {
+        command: "/select",
+        args: "[rect X,Y WxH|ellipse CX,CY RXxRY|\"<what>\"|clear]",
+        summary: "Select the region the next edit may change",
+    },
+    ChatCommandHelp {
+        command: "/palette",
+        args: "[<path>|lock|#rrggbb...|clear]",
+        summary: "Extract a palette or pin one for generations",
+    },
+    ChatCommandHelp {
+        command: "/preset",
+        args: "[save <name>|use <name>]",
+        summary: "Save or recall generation settings",
+    },
+    ChatCommandHelp {
+        command: "/compare",
+        args: "<a> <b> [--diff]",
+        summary: "Side-by-side comparison with SSIM",
+    },
+    ChatCommandHelp {
+        command: "/why-different",
+        args: "<a> <b>",
+        summary: "Diff the requests behind two artifacts",
+    },
+    ChatCommandHelp {
+        command: "/grid",
+        args: "[artifact_id...] [cols=N] [cell=N]",
+        summary: "Contact sheet of artifacts",
+    },
+    ChatCommandHelp {
+        command: "/favorite",
+        args: "<artifact_id>",
+        summary: "Tag an artifact as favorite",
+    },
+    ChatCommandHelp {
+        command: "/vars",
+        args: "[show|clear|name=value...]",
+        summary: "Set prompt template variables",
+    },
+    ChatCommandHelp {
+        command: "/autopick",
+        args: "[version_id]",
+        summary: "Score artifacts and select the best",
+    },
+    ChatCommandHelp {
+        command: "/help",
+        args: "[query]",
+        summary: "List commands matching a query",
+    },
 ];
@@ -0,0 +1,142 @@
+/// Words that only ever open an edit of the current image.
+const EDIT_VERBS: &[&str] = &[
+    "edit",
+    "replace",
+    "retouch",
+    "recolor",
+    "recolour",
+    "brighten",
+    "darken",
+    "desaturate",
+    "erase",
+];
+
+/// Words that edit only when they point back at the image ("make it
+/// darker", "add a hat to it", "more contrast in the sky"), not when they
+/// start a new one ("make a poster of ...", "add a moon over a desert",
+/// "lower Manhattan at night").
+const REFERRING_VERBS: &[&str] = &[
+    "make", "turn", "change", "give", "put", "swap", "keep", "fix", "tweak", "adjust", "move",
+    "zoom", "rotate", "flip", "shift", "add", "remove", "crop", "blur", "sharpen", "lighten",
+    "saturate", "more", "less", "closer", "higher", "lower",
+];
+
+const REFERENCES: &[&str] = &[
+    "it",
+    "its",
+    "it's",
+    "this",
+    "that",
+    "them",
+    "the image",
+    "the picture",
+    "the photo",
+    "the background",
+    "the subject",
+    "the colors",
+    "the colours",
+    "the lighting",
+    "the sky",
+];
+
+/// Openers like `now`, `ok` or `can you` that do not change the request.
+const FILLERS: &[&str] = &[
+    "now", "ok", "okay", "please", "and", "then", "also", "but", "so", "can", "could", "would",
+    "you", "just", "maybe", "let's", "lets",
+];
+
+/// Comparative nudges that only make sense against a previous image
+/// (`darker`, `a bit warmer`).
+const NUDGES: &[&str] = &[
+    "darker", "brighter", "lighter", "warmer", "cooler", "bigger", "smaller", "sharper", "softer",
+    "bolder", "simpler", "busier", "calmer", "wider", "redder", "bluer", "greener",
+];
+
+/// Whether a chat prompt reads as a change to the active image rather than
+/// a new picture: "make it darker", "now add a hat to it", "a bit warmer".
+pub fn is_edit_followup(prompt: &str) -> bool {
+    let lowered = prompt.trim().to_ascii_lowercase();
+    let words: Vec<&str> = lowered
+        .split_whitespace()
+        .map(|word| word.trim_matches(|ch: char| ch.is_ascii_punctuation() && ch != '\''))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let mut rest = words.as_slice();
+    while let Some((head, tail)) = rest.split_first() {
+        if FILLERS.contains(head) {
+            rest = tail;
+        } else {
+            break;
+        }
+    }
+    // "a bit darker", "a little more contrast", "slightly warmer".
+    if let ["a", "bit" | "little" | "lot" | "touch", tail @ ..] | ["slightly" | "much", tail @ ..] =
+        rest
+    {
+        rest = tail;
+    }
+    let Some((head, tail)) = rest.split_first() else {
+        return false;
+    };
+    if EDIT_VERBS.contains(head) || NUDGES.contains(head) {
+        return true;
+    }
+    if !REFERRING_VERBS.contains(head) {
+        return false;
+    }
+    let tail = format!(" {} ", tail.join(" "));
+    REFERENCES
+        .iter()
+        .any(|reference| tail.contains(&format!(" {reference} ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_edit_followup;
+
+    #[test]
+    fn recognizes_followup_edits_but_not_new_prompts() {
+        for prompt in [
+            "make it darker",
+            "Now make it darker.",
+            "ok, add a red hat to it",
+            "remove the people from the image",
+            "a bit warmer",
+            "more contrast in the sky",
+            "can you turn the sky purple",
+            "change the background to white",
+            "replace the background with seamless white",
+            "edit the image: remove people",
+        ] {
+            assert!(is_edit_followup(prompt), "{prompt}");
+        }
+        for prompt in [
+            "a red fox at dusk",
+            "make a poster of a lighthouse",
+            "turn of the century street scene",
+            "generate a city skyline at dusk",
+            "add a moon over a desert",
+            "remove background noise from a studio portrait",
+            "more cats in a sunny garden",
+            "less is more poster",
+            "lower manhattan at night",
+            "higher ground, a lone hiker",
+            "closer look at a honeybee",
+            "",
+        ] {
+            assert!(!is_edit_followup(prompt), "{prompt}");
+        }
+    }
+
+    #[test]
+    fn edit_and_replace_heads_match_whole_words() {
+        assert!(is_edit_followup(
+            "edit the image: isolate subject and keep dimensions"
+        ));
+        assert!(is_edit_followup(
+            "  REPLACE the background with pure white and preserve logos"
+        ));
+        assert!(!is_edit_followup("editors choice cinematic portrait"));
+        assert!(!is_edit_followup("generate a brand new scene"));
+    }
+}
@@ -1,10 +1,15 @@
 use std::collections::BTreeMap;
 
-use serde_json::Value;
+use serde_json::{Map, Value};
+
+use crate::prompt_template::parse_variable_assignment;
 
 use super::command_registry::{
-    CommandSpec, EXPORT_COMMAND, MULTI_PATH_COMMANDS, NO_ARG_COMMANDS, QUALITY_PRESET_COMMANDS,
-    RAW_ARG_COMMANDS, SINGLE_PATH_COMMANDS,
+    CommandSpec, AUTOPICK_COMMAND, BRANCH_COMMAND, BUDGET_COMMAND, COMPARE_COMMAND, DELETE_COMMAND,
+    EXPORT_COMMAND, FAVORITE_COMMAND, GENERATE_COMMAND, GRID_COMMAND, MULTI_PATH_COMMANDS,
+    NO_ARG_COMMANDS, PALETTE_COMMAND, PRESET_COMMAND, PROVIDER_COMMAND, QUALITY_PRESET_COMMANDS,
+    RAW_ARG_COMMANDS, RESTORE_COMMAND, SELECT_COMMAND, SINGLE_PATH_COMMANDS, TAG_COMMAND,
+    UNDO_COMMAND, UPSCALE_COMMAND, VARS_COMMAND, VIDEO_COMMAND, WHY_DIFFERENT_COMMAND,
 };
 
 #[derive(Debug, Clone, PartialEq)]
@@ -115,6 +120,222 @@ fn parse_path_args(arg: &str) -> Vec<String> {
     }
 }
 
+/// Splits `/export <selector> --profile <name>` into its selector and profile.
+fn parse_export_args(arg: &str) -> (Option<String>, Option<String>) {
+    let mut selector: Vec<&str> = Vec::new();
+    let mut profile: Option<String> = None;
+    let mut parts = arg.split_whitespace();
+    while let Some(part) = parts.next() {
+        if let Some(value) = part.strip_prefix("--profile=") {
+            profile = Some(value.to_ascii_lowercase());
+        } else if part == "--profile" {
+            profile = parts.next().map(str::to_ascii_lowercase);
+        } else {
+            selector.push(part);
+        }
+    }
+    let selector = (!selector.is_empty()).then(|| selector.join(" "));
+    (selector, profile)
+}
+
+fn parse_upscale_args(arg: &str) -> (Option<String>, Option<u64>) {
+    let mut artifact_id: Option<String> = None;
+    let mut factor: Option<u64> = None;
+    for part in arg.split_whitespace() {
+        let lower = part.to_ascii_lowercase();
+        let numeric = lower.strip_suffix('x').unwrap_or(&lower);
+        if factor.is_none() {
+            if let Ok(value) = numeric.parse::<u64>() {
+                factor = Some(value);
+                continue;
+            }
+        }
+        if artifact_id.is_none() {
+            artifact_id = Some(part.to_string());
+        }
+    }
+    (artifact_id, factor)
+}
+
+fn parse_provider_args(arg: &str) -> (String, Vec<String>) {
+    let mut parts = arg.split_whitespace();
+    let op = parts
+        .next()
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_else(|| "list".to_string());
+    let names = parts
+        .flat_map(|part| part.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    (op, names)
+}
+
+/// `/budget` forms: (empty) show, `force`, `off`, `<usd>` (run cap),
+/// `run|session <usd>` and `run|session off`.
+fn parse_budget_args(arg: &str) -> (String, String, Option<f64>) {
+    let lowered = arg.trim().to_ascii_lowercase();
+    let mut parts = lowered.split_whitespace();
+    let first = parts.next().unwrap_or("");
+    let (scope, value) = match first {
+        "" | "show" => return ("show".to_string(), "all".to_string(), None),
+        "force" => return ("force".to_string(), "all".to_string(), None),
+        "off" => return ("off".to_string(), "all".to_string(), None),
+        "run" | "session" => (first, parts.next().unwrap_or("")),
+        _ => ("run", first),
+    };
+    if value == "off" {
+        return ("off".to_string(), scope.to_string(), None);
+    }
+    match value.trim_start_matches('$').parse::<f64>() {
+        Ok(usd) if usd.is_finite() && usd >= 0.0 => {
+            ("set".to_string(), scope.to_string(), Some(usd))
+        }
+        _ => ("invalid".to_string(), scope.to_string(), None),
+    }
+}
+
+/// `/vars` forms: (empty) show, `clear`, or `name=value name=a,b ...`
+/// where words without `=` continue the previous value (`subject=red fox`).
+fn parse_vars_args(arg: &str) -> (String, Map<String, Value>, Option<String>) {
+    let trimmed = arg.trim();
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("show") {
+        return ("show".to_string(), Map::new(), None);
+    }
+    if trimmed.eq_ignore_ascii_case("clear") {
+        return ("clear".to_string(), Map::new(), None);
+    }
+    let mut assignments: Vec<String> = Vec::new();
+    for word in trimmed.split_whitespace() {
+        match assignments.last_mut() {
+            Some(last) if !word.contains('=') => {
+                last.push(' ');
+                last.push_str(word);
+            }
+            _ => assignments.push(word.to_string()),
+        }
+    }
+    let mut variables = Map::new();
+    for assignment in assignments {
+        match parse_variable_assignment(&assignment) {
+            Ok((name, value)) => {
+                variables.insert(name, value);
+            }
+            Err(err) => return ("invalid".to_string(), Map::new(), Some(err.to_string())),
+        }
+    }
+    ("set".to_string(), variables, None)
+}
+
+/// `/select` forms: (empty) show, `clear`, `rect X,Y WxH`,
+/// `ellipse CX,CY RXxRY`, or a description to segment (`"the sky"`).
+fn parse_select_args(arg: &str) -> (String, Map<String, Value>, Option<String>) {
+    let trimmed = arg.trim();
+    let mut words = trimmed.split_whitespace();
+    let shape = words.next().unwrap_or("").to_ascii_lowercase();
+    let mut args = Map::new();
+    match shape.as_str() {
+        "" | "show" => return ("show".to_string(), args, None),
+        "clear" | "none" | "off" => return ("clear".to_string(), args, None),
+        "rect" | "ellipse" => {}
+        _ => {
+            let description = trimmed.trim_matches(|ch| ch == '"' || ch == '\'').trim();
+            args.insert(
+                "description".to_string(),
+                Value::String(description.to_string()),
+            );
+            return ("segment".to_string(), args, None);
+        }
+    }
+    let usage = if shape == "rect" {
+        "usage: /select rect X,Y WxH"
+    } else {
+        "usage: /select ellipse CX,CY RXxRY"
+    };
+    let pair = |text: Option<&str>, separator: char| {
+        let (a, b) = text?.split_once(separator)?;
+        let a = a.trim().parse::<u32>().ok()?;
+        let b = b.trim().parse::<u32>().ok()?;
+        Some((a, b))
+    };
+    let (Some(origin), Some(extent), None) = (
+        pair(words.next(), ','),
+        pair(words.next().map(str::to_ascii_lowercase).as_deref(), 'x'),
+        words.next(),
+    ) else {
+        return ("invalid".to_string(), args, Some(usage.to_string()));
+    };
+    if extent.0 == 0 || extent.1 == 0 {
+        return ("invalid".to_string(), args, Some(usage.to_string()));
+    }
+    let keys = if shape == "rect" {
+        ["x", "y", "width", "height"]
+    } else {
+        ["cx", "cy", "rx", "ry"]
+    };
+    for (key, value) in keys
+        .into_iter()
+        .zip([origin.0, origin.1, extent.0, extent.1])
+    {
+        args.insert(key.to_string(), Value::from(value));
+    }
+    (shape, args, None)
+}
+
+/// `/palette` forms: (empty) or a path to extract, `lock` to pin the last
+/// extracted palette, `#rrggbb ...` to pin colors, or `clear`.
+fn parse_palette_args(arg: &str) -> (String, Map<String, Value>, Option<String>) {
+    let trimmed = arg.trim();
+    let mut args = Map::new();
+    match trimmed.to_ascii_lowercase().as_str() {
+        "lock" | "pin" => return ("lock".to_string(), args, None),
+        "clear" | "none" | "off" => return ("clear".to_string(), args, None),
+        _ => {}
+    }
+    if !trimmed.starts_with('#') {
+        let path = parse_single_path_arg(trimmed);
+        let path = if path.is_empty() {
+            Value::Null
+        } else {
+            Value::String(path)
+        };
+        args.insert("path".to_string(), path);
+        return ("extract".to_string(), args, None);
+    }
+    let mut colors = Vec::new();
+    for word in trimmed.split(|ch: char| ch.is_whitespace() || ch == ',') {
+        if word.is_empty() {
+            continue;
+        }
+        match normalize_hex_color(word) {
+            Some(color) => colors.push(Value::String(color)),
+            None => {
+                return (
+                    "invalid".to_string(),
+                    args,
+                    Some(format!("'{word}' is not a #rgb or #rrggbb color")),
+                )
+            }
+        }
+    }
+    args.insert("colors".to_string(), Value::Array(colors));
+    ("set".to_string(), args, None)
+}
+
+/// `#abc` or `#AABBCC` as lowercase `#aabbcc`.
+pub fn normalize_hex_color(text: &str) -> Option<String> {
+    let digits = text.trim().strip_prefix('#')?;
+    if !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    let expanded = match digits.len() {
+        3 => digits.chars().flat_map(|ch| [ch, ch]).collect(),
+        6 => digits.to_string(),
+        _ => return None,
+    };
+    Some(format!("#{}", expanded.to_ascii_lowercase()))
+}
+
 fn parse_single_path_arg(arg: &str) -> String {
     let parts = parse_path_args(arg);
     match parts.len() {
@@ -130,10 +351,14 @@ pub fn parse_intent(text: &str) -> Intent {
         return Intent::new("noop", text);
     }
 
+    if raw_trimmed == "/" {
+        return Intent::new("help", text);
+    }
+
     if let Some(slash_tail) = raw_trimmed.strip_prefix('/') {
         let command_len = slash_tail
             .chars()
-            .take_while(|ch| ch.is_ascii_alphanumeric() || *ch == '_')
+            .take_while(|ch| ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '-')
             .count();
         if command_len > 0 {
             let command = slash_tail[..command_len].to_ascii_lowercase();
@@ -206,19 +431,267 @@ pub fn parse_intent(text: &str) -> Intent {
             }
 
             if let Some(action) = find_action(&command, NO_ARG_COMMANDS) {
-                return Intent::new(action, text);
+                let mut intent = Intent::new(action, text);
+                if action == "help" && !arg.is_empty() {
+                    intent
+                        .command_args
+                        .insert("query".to_string(), Value::String(arg.to_string()));
+                }
+                return intent;
             }
 
             if command == EXPORT_COMMAND.command {
                 let mut intent = Intent::new(EXPORT_COMMAND.action, text);
+                let (selector, profile) = parse_export_args(arg);
+                let format = match (&selector, &profile) {
+                    (None, None) => "html".to_string(),
+                    (Some(value), None) if value.eq_ignore_ascii_case("html") => "html".to_string(),
+                    (Some(value), None) if value.eq_ignore_ascii_case("gallery") => {
+                        "gallery".to_string()
+                    }
+                    (Some(value), None) if value.eq_ignore_ascii_case("pdf") => "pdf".to_string(),
+                    _ => "files".to_string(),
+                };
+                intent
+                    .command_args
+                    .insert("format".to_string(), Value::String(format));
+                intent.command_args.insert(
+                    "selector".to_string(),
+                    selector.map(Value::String).unwrap_or(Value::Null),
+                );
+                intent.command_args.insert(
+                    "profile".to_string(),
+                    Value::String(profile.unwrap_or_else(|| "web".to_string())),
+                );
+                return intent;
+            }
+
+            if command == UPSCALE_COMMAND.command {
+                let (artifact_id, factor) = parse_upscale_args(arg);
+                let mut intent = Intent::new(UPSCALE_COMMAND.action, text);
+                intent.command_args.insert(
+                    "artifact_id".to_string(),
+                    artifact_id.map(Value::String).unwrap_or(Value::Null),
+                );
+                intent.command_args.insert(
+                    "factor".to_string(),
+                    Value::Number(factor.unwrap_or(2).into()),
+                );
+                return intent;
+            }
+
+            if command == PROVIDER_COMMAND.command {
+                let (op, names) = parse_provider_args(arg);
+                let mut intent = Intent::new(PROVIDER_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("op".to_string(), Value::String(op));
+                intent.command_args.insert(
+                    "names".to_string(),
+                    Value::Array(names.into_iter().map(Value::String).collect()),
+                );
+                return intent;
+            }
+
+            if command == VIDEO_COMMAND.command {
+                let mut intent = Intent::new(VIDEO_COMMAND.action, text);
+                intent.prompt = (!arg.is_empty()).then(|| arg.to_string());
+                return intent;
+            }
+
+            if command == BUDGET_COMMAND.command {
+                let (op, scope, usd) = parse_budget_args(arg);
+                let mut intent = Intent::new(BUDGET_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("op".to_string(), Value::String(op));
+                intent
+                    .command_args
+                    .insert("scope".to_string(), Value::String(scope));
+                intent.command_args.insert(
+                    "usd".to_string(),
+                    usd.map(Value::from).unwrap_or(Value::Null),
+                );
+                return intent;
+            }
+
+            if command == VARS_COMMAND.command {
+                let (op, variables, error) = parse_vars_args(arg);
+                let mut intent = Intent::new(VARS_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("op".to_string(), Value::String(op));
+                intent
+                    .command_args
+                    .insert("variables".to_string(), Value::Object(variables));
+                intent.command_args.insert(
+                    "error".to_string(),
+                    error.map(Value::String).unwrap_or(Value::Null),
+                );
+                return intent;
+            }
+
+            if command == COMPARE_COMMAND.command {
+                let mut heatmap = false;
+                let mut refs = Vec::new();
+                for word in arg.split_whitespace() {
+                    match word.to_ascii_lowercase().as_str() {
+                        "--diff" | "--heatmap" | "diff" | "heatmap" => heatmap = true,
+                        _ => refs.push(Value::String(word.to_string())),
+                    }
+                }
+                let mut intent = Intent::new(COMPARE_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("artifacts".to_string(), Value::Array(refs));
+                intent
+                    .command_args
+                    .insert("heatmap".to_string(), Value::Bool(heatmap));
+                return intent;
+            }
+
+            if command == WHY_DIFFERENT_COMMAND.command {
+                let mut intent = Intent::new(WHY_DIFFERENT_COMMAND.action, text);
+                intent.command_args.insert(
+                    "artifacts".to_string(),
+                    Value::Array(
+                        parse_path_args(arg)
+                            .into_iter()
+                            .map(Value::String)
+                            .collect(),
+                    ),
+                );
+                return intent;
+            }
+
+            if command == GENERATE_COMMAND.command {
+                let mut intent = Intent::new(GENERATE_COMMAND.action, text);
+                let (prompt, settings) = parse_generate_args(arg);
+                intent.prompt = (!prompt.is_empty()).then_some(prompt);
+                intent.settings_update = settings;
+                return intent;
+            }
+
+            if command == GRID_COMMAND.command {
+                let mut intent = Intent::new(GRID_COMMAND.action, text);
+                let mut ids = Vec::new();
+                for word in arg.split_whitespace() {
+                    let (key, value) = word.split_once('=').unwrap_or((word, ""));
+                    let key = match key.to_ascii_lowercase().as_str() {
+                        "cols" | "columns" => "cols",
+                        "cell" | "cell_size" | "size" => "cell_size",
+                        _ => "",
+                    };
+                    match value.parse::<u64>() {
+                        Ok(value) if !key.is_empty() => {
+                            intent
+                                .command_args
+                                .insert(key.to_string(), Value::from(value));
+                        }
+                        _ => ids.push(Value::String(word.to_string())),
+                    }
+                }
+                intent
+                    .command_args
+                    .insert("artifact_ids".to_string(), Value::Array(ids));
+                return intent;
+            }
+
+            if command == SELECT_COMMAND.command {
+                let (op, args, error) = parse_select_args(arg);
+                let mut intent = Intent::new(SELECT_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("op".to_string(), Value::String(op));
+                intent.command_args.extend(args);
+                intent.command_args.insert(
+                    "error".to_string(),
+                    error.map(Value::String).unwrap_or(Value::Null),
+                );
+                return intent;
+            }
+
+            if command == PRESET_COMMAND.command {
+                let mut words = arg.split_whitespace();
+                let op = words.next().unwrap_or("list").to_ascii_lowercase();
+                let name = words.next().map(str::to_string);
+                let error = match op.as_str() {
+                    "list" => None,
+                    "save" | "use" if name.is_some() && words.next().is_none() => None,
+                    _ => Some("usage: /preset save <name> | /preset use <name>".to_string()),
+                };
+                let mut intent = Intent::new(PRESET_COMMAND.action, text);
                 intent.command_args.insert(
-                    "format".to_string(),
-                    Value::String(if arg.is_empty() {
-                        "html".to_string()
+                    "op".to_string(),
+                    Value::String(if error.is_some() {
+                        "invalid".to_string()
                     } else {
-                        arg.to_string()
+                        op
                     }),
                 );
+                intent.command_args.insert(
+                    "name".to_string(),
+                    name.map(Value::String).unwrap_or(Value::Null),
+                );
+                intent.command_args.insert(
+                    "error".to_string(),
+                    error.map(Value::String).unwrap_or(Value::Null),
+                );
+                return intent;
+            }
+
+            if command == PALETTE_COMMAND.command {
+                let (op, args, error) = parse_palette_args(arg);
+                let mut intent = Intent::new(PALETTE_COMMAND.action, text);
+                intent
+                    .command_args
+                    .insert("op".to_string(), Value::String(op));
+                intent.command_args.extend(args);
+                intent.command_args.insert(
+                    "error".to_string(),
+                    error.map(Value::String).unwrap_or(Value::Null),
+                );
+                return intent;
+            }
+
+            if command == TAG_COMMAND.command || command == FAVORITE_COMMAND.command {
+                let mut words = arg.split_whitespace();
+                let artifact_id = words.next().map(str::to_string);
+                let labels: Vec<String> = if command == FAVORITE_COMMAND.command {
+                    vec!["favorite".to_string()]
+                } else {
+                    words.map(str::to_string).collect()
+                };
+                let mut intent = Intent::new(TAG_COMMAND.action, text);
+                intent.command_args.insert(
+                    "artifact_id".to_string(),
+                    artifact_id.map(Value::String).unwrap_or(Value::Null),
+                );
+                intent.command_args.insert(
+                    "labels".to_string(),
+                    Value::Array(labels.into_iter().map(Value::String).collect()),
+                );
+                return intent;
+            }
+
+            if let Some(spec) = [
+                DELETE_COMMAND,
+                RESTORE_COMMAND,
+                AUTOPICK_COMMAND,
+                BRANCH_COMMAND,
+                UNDO_COMMAND,
+            ]
+            .iter()
+            .find(|spec| spec.command == command)
+            {
+                let mut intent = Intent::new(spec.action, text);
+                intent.command_args.insert(
+                    "version_id".to_string(),
+                    arg.split_whitespace()
+                        .next()
+                        .map(|id| Value::String(id.to_string()))
+                        .unwrap_or(Value::Null),
+                );
                 return intent;
             }
 
@@ -238,12 +711,76 @@ pub fn parse_intent(text: &str) -> Intent {
     intent
 }
 
+/// Splits `/generate` arguments into the prompt and `--n N`, `--seed N` and
+/// `--size WxH` settings (also `--n=N`). Unknown flags stay in the prompt.
+fn parse_generate_args(arg: &str) -> (String, BTreeMap<String, Value>) {
+    let mut settings = BTreeMap::new();
+    let mut prompt: Vec<String> = Vec::new();
+    let mut words = arg.split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        let (flag, inline) = match word.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (word, None),
+        };
+        let key = match flag.to_ascii_lowercase().as_str() {
+            "--n" => "n",
+            "--seed" => "seed",
+            "--size" => "size",
+            _ => {
+                prompt.push(word.to_string());
+                continue;
+            }
+        };
+        let separate = inline.is_none();
+        let Some(raw) = inline.or_else(|| {
+            words
+                .next_if(|next| !next.starts_with("--"))
+                .map(str::to_string)
+        }) else {
+            prompt.push(word.to_string());
+            continue;
+        };
+        let value = match key {
+            "size" => Some(Value::String(raw.clone())),
+            _ => raw.parse::<i64>().ok().map(Value::from),
+        };
+        match value {
+            Some(value) => {
+                settings.insert(key.to_string(), value);
+            }
+            None => {
+                prompt.push(word.to_string());
+                if separate {
+                    prompt.push(raw);
+                }
+            }
+        }
+    }
+    (prompt.join(" "), settings)
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+    use proptest::sample::select;
     use serde_json::json;
 
     use super::parse_intent;
 
+    #[test]
+    fn parse_generate_with_flags() {
+        let intent = parse_intent("/generate a red chair --n 2 --size=512x512");
+        assert_eq!(intent.action, "generate");
+        assert_eq!(intent.prompt.as_deref(), Some("a red chair"));
+        assert_eq!(intent.settings_update["n"], json!(2));
+        assert_eq!(intent.settings_update["size"], json!("512x512"));
+
+        let plain = parse_intent("/generate --seed x a chair");
+        assert_eq!(plain.prompt.as_deref(), Some("--seed x a chair"));
+        assert!(plain.settings_update.is_empty());
+        assert_eq!(parse_intent("/generate").prompt, None);
+    }
+
     #[test]
     fn parse_blend_basic() {
         let intent = parse_intent("/blend a.png b.png");
@@ -392,6 +929,260 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_upscale_artifact_and_factor() {
+        let intent = parse_intent("/upscale v1-01-abc 4x");
+        assert_eq!(intent.action, "upscale");
+        assert_eq!(intent.command_args["artifact_id"], json!("v1-01-abc"));
+        assert_eq!(intent.command_args["factor"], json!(4));
+
+        let bare = parse_intent("/upscale");
+        assert_eq!(bare.command_args["artifact_id"], json!(null));
+        assert_eq!(bare.command_args["factor"], json!(2));
+    }
+
+    #[test]
+    fn parse_provider_toggle_and_priority() {
+        let disable = parse_intent("/provider disable Replicate");
+        assert_eq!(disable.action, "provider");
+        assert_eq!(disable.command_args["op"], json!("disable"));
+        assert_eq!(disable.command_args["names"], json!(["replicate"]));
+
+        let priority = parse_intent("/provider priority flux, openai gemini");
+        assert_eq!(priority.command_args["op"], json!("priority"));
+        assert_eq!(
+            priority.command_args["names"],
+            json!(["flux", "openai", "gemini"])
+        );
+
+        assert_eq!(parse_intent("/provider").command_args["op"], json!("list"));
+    }
+
+    #[test]
+    fn parse_export_selector_and_profile() {
+        let legacy = parse_intent("/export");
+        assert_eq!(legacy.command_args["format"], json!("html"));
+        assert_eq!(
+            parse_intent("/export html").command_args["format"],
+            json!("html")
+        );
+        assert_eq!(
+            parse_intent("/export gallery").command_args["format"],
+            json!("gallery")
+        );
+        assert_eq!(
+            parse_intent("/export pdf").command_args["format"],
+            json!("pdf")
+        );
+
+        let ranged = parse_intent("/export v3..v7 --profile web");
+        assert_eq!(ranged.command_args["format"], json!("files"));
+        assert_eq!(ranged.command_args["selector"], json!("v3..v7"));
+        assert_eq!(ranged.command_args["profile"], json!("web"));
+
+        let last = parse_intent("/export last 4 --profile=Thumb");
+        assert_eq!(last.command_args["selector"], json!("last 4"));
+        assert_eq!(last.command_args["profile"], json!("thumb"));
+    }
+
+    #[test]
+    fn parse_video_prompt() {
+        let intent = parse_intent("/video slow dolly zoom");
+        assert_eq!(intent.action, "video");
+        assert_eq!(intent.prompt.as_deref(), Some("slow dolly zoom"));
+        assert_eq!(parse_intent("/video").prompt, None);
+    }
+
+    #[test]
+    fn parse_budget_forms() {
+        let intent = parse_intent("/budget session $2.50");
+        assert_eq!(intent.action, "budget");
+        assert_eq!(intent.command_args["op"], json!("set"));
+        assert_eq!(intent.command_args["scope"], json!("session"));
+        assert_eq!(intent.command_args["usd"], json!(2.5));
+        assert_eq!(
+            parse_intent("/budget 4").command_args["scope"],
+            json!("run")
+        );
+        assert_eq!(parse_intent("/budget").command_args["op"], json!("show"));
+        assert_eq!(
+            parse_intent("/budget run off").command_args["op"],
+            json!("off")
+        );
+        assert_eq!(
+            parse_intent("/budget force").command_args["op"],
+            json!("force")
+        );
+        assert_eq!(
+            parse_intent("/budget run lots").command_args["op"],
+            json!("invalid")
+        );
+    }
+
+    #[test]
+    fn parse_vars_forms() {
+        let intent = parse_intent("/vars style=noir,pastel subject=red fox");
+        assert_eq!(intent.action, "set_variables");
+        assert_eq!(intent.command_args["op"], json!("set"));
+        assert_eq!(
+            intent.command_args["variables"],
+            json!({"style": ["noir", "pastel"], "subject": "red fox"})
+        );
+        assert_eq!(parse_intent("/vars").command_args["op"], json!("show"));
+        assert_eq!(
+            parse_intent("/vars clear").command_args["op"],
+            json!("clear")
+        );
+        let invalid = parse_intent("/vars noir");
+        assert_eq!(invalid.command_args["op"], json!("invalid"));
+        assert!(invalid.command_args["error"].is_string());
+    }
+
+    #[test]
+    fn parse_delete_and_restore() {
+        let intent = parse_intent("/delete v3");
+        assert_eq!(intent.action, "delete_version");
+        assert_eq!(intent.command_args["version_id"], json!("v3"));
+        assert_eq!(parse_intent("/restore v3").action, "restore_version");
+        assert_eq!(parse_intent("/autopick v2").action, "auto_select");
+        let branch = parse_intent("/branch v2");
+        assert_eq!(branch.action, "branch");
+        assert_eq!(branch.command_args["version_id"], json!("v2"));
+        assert_eq!(parse_intent("/history").action, "history");
+        let undo = parse_intent("/undo");
+        assert_eq!(undo.action, "revert_version");
+        assert_eq!(undo.command_args["version_id"], json!(null));
+        assert_eq!(
+            parse_intent("/delete").command_args["version_id"],
+            json!(null)
+        );
+    }
+
+    #[test]
+    fn parse_tag_and_favorite() {
+        let intent = parse_intent("/tag v1-01-abc hero -draft");
+        assert_eq!(intent.action, "tag_artifact");
+        assert_eq!(intent.command_args["artifact_id"], json!("v1-01-abc"));
+        assert_eq!(intent.command_args["labels"], json!(["hero", "-draft"]));
+        let favorite = parse_intent("/favorite");
+        assert_eq!(favorite.action, "tag_artifact");
+        assert_eq!(favorite.command_args["artifact_id"], json!(null));
+        assert_eq!(favorite.command_args["labels"], json!(["favorite"]));
+    }
+
+    #[test]
+    fn parse_select_shapes_descriptions_and_clear() {
+        let rect = parse_intent("/select rect 100,120 400x300");
+        assert_eq!(rect.action, "select_region");
+        assert_eq!(rect.command_args["op"], json!("rect"));
+        assert_eq!(rect.command_args["x"], json!(100));
+        assert_eq!(rect.command_args["y"], json!(120));
+        assert_eq!(rect.command_args["width"], json!(400));
+        assert_eq!(rect.command_args["height"], json!(300));
+        let ellipse = parse_intent("/select ellipse 256,256 64X32");
+        assert_eq!(ellipse.command_args["op"], json!("ellipse"));
+        assert_eq!(ellipse.command_args["rx"], json!(64));
+        assert_eq!(ellipse.command_args["ry"], json!(32));
+
+        let segment = parse_intent("/select \"the sky\"");
+        assert_eq!(segment.command_args["op"], json!("segment"));
+        assert_eq!(segment.command_args["description"], json!("the sky"));
+        assert_eq!(
+            parse_intent("/select clear").command_args["op"],
+            json!("clear")
+        );
+        assert_eq!(parse_intent("/select").command_args["op"], json!("show"));
+        for invalid in [
+            "/select rect 100,120",
+            "/select rect 1,2 0x5",
+            "/select ellipse a,b 3x4",
+        ] {
+            let intent = parse_intent(invalid);
+            assert_eq!(intent.command_args["op"], json!("invalid"), "{invalid}");
+            assert!(intent.command_args["error"].is_string());
+        }
+    }
+
+    #[test]
+    fn parse_palette_extract_pin_and_clear() {
+        let bare = parse_intent("/palette");
+        assert_eq!(bare.action, "palette");
+        assert_eq!(bare.command_args["op"], json!("extract"));
+        assert_eq!(bare.command_args["path"], json!(null));
+        let path = parse_intent("/palette brand/logo.png");
+        assert_eq!(path.command_args["path"], json!("brand/logo.png"));
+        assert_eq!(
+            parse_intent("/palette lock").command_args["op"],
+            json!("lock")
+        );
+        let set = parse_intent("/palette #1428A0, #fff");
+        assert_eq!(set.command_args["op"], json!("set"));
+        assert_eq!(set.command_args["colors"], json!(["#1428a0", "#ffffff"]));
+        let invalid = parse_intent("/palette #12345");
+        assert_eq!(invalid.command_args["op"], json!("invalid"));
+        assert!(invalid.command_args["error"].is_string());
+        assert_eq!(
+            parse_intent("/palette clear").command_args["op"],
+            json!("clear")
+        );
+    }
+
+    #[test]
+    fn parse_preset_save_use_and_list() {
+        let save = parse_intent("/preset save hero-shots");
+        assert_eq!(save.action, "preset");
+        assert_eq!(save.command_args["op"], json!("save"));
+        assert_eq!(save.command_args["name"], json!("hero-shots"));
+        let recall = parse_intent("/preset USE hero-shots");
+        assert_eq!(recall.command_args["op"], json!("use"));
+        assert_eq!(parse_intent("/preset").command_args["op"], json!("list"));
+        for invalid in ["/preset save", "/preset use a b", "/preset drop a"] {
+            let intent = parse_intent(invalid);
+            assert_eq!(intent.command_args["op"], json!("invalid"), "{invalid}");
+            assert!(intent.command_args["error"].is_string());
+        }
+    }
+
+    #[test]
+    fn parse_grid_ids_and_layout_options() {
+        let intent = parse_intent("/grid v1-01-a v1-02-b v2-01-c cols=2 cell=128");
+        assert_eq!(intent.action, "grid");
+        assert_eq!(
+            intent.command_args["artifact_ids"],
+            json!(["v1-01-a", "v1-02-b", "v2-01-c"])
+        );
+        assert_eq!(intent.command_args["cols"], json!(2));
+        assert_eq!(intent.command_args["cell_size"], json!(128));
+        let bare = parse_intent("/grid");
+        assert_eq!(bare.command_args["artifact_ids"], json!([]));
+        assert!(!bare.command_args.contains_key("cols"));
+    }
+
+    #[test]
+    fn parse_why_different_refs() {
+        let intent = parse_intent("/why-different v1-01-a \"/tmp/run b/receipt-2.json\"");
+        assert_eq!(intent.action, "why_different");
+        assert_eq!(
+            intent.command_args["artifacts"],
+            json!(["v1-01-a", "/tmp/run b/receipt-2.json"])
+        );
+    }
+
+    #[test]
+    fn parse_compare_with_heatmap_flag() {
+        let intent = parse_intent("/compare v1-01-a v2-01-b --diff");
+        assert_eq!(intent.action, "compare");
+        assert_eq!(
+            intent.command_args["artifacts"],
+            json!(["v1-01-a", "v2-01-b"])
+        );
+        assert_eq!(intent.command_args["heatmap"], json!(true));
+        assert_eq!(
+            parse_intent("/compare a b").command_args["heatmap"],
+            json!(false)
+        );
+    }
+
     #[test]
     fn parse_unknown_command() {
         let intent = parse_intent("/magic foo bar");
@@ -399,4 +1190,66 @@ mod tests {
         assert_eq!(intent.command_args["command"], json!("magic"));
         assert_eq!(intent.command_args["arg"], json!("foo bar"));
     }
+
+    #[test]
+    fn parse_help_query_and_bare_slash() {
+        let intent = parse_intent("/help canvas");
+        assert_eq!(intent.action, "help");
+        assert_eq!(intent.command_args["query"], json!("canvas"));
+        assert!(!parse_intent("/help").command_args.contains_key("query"));
+        assert_eq!(parse_intent("/").action, "help");
+    }
+
+    /// Command lines built from real commands, flags and hostile fragments.
+    /// Failing lines are shrunk before they are reported; the `fuzz/`
+    /// target covers the same ground unbounded.
+    fn arbitrary_command_line() -> impl Strategy<Value = String> {
+        const FRAGMENTS: [&str; 16] = [
+            "--n",
+            "--size=",
+            "--seed",
+            "=",
+            "\"",
+            "'",
+            "\\",
+            "-",
+            "..",
+            "/",
+            "18446744073709551616",
+            "-1",
+            "1e309",
+            "\u{1f5bc}",
+            "\u{0}",
+            "\t",
+        ];
+        let commands: Vec<&'static str> = crate::chat::CHAT_HELP_COMMANDS
+            .iter()
+            .map(|help| help.command)
+            .collect();
+        let part = (
+            select(&[" ", "", "  "][..]),
+            prop_oneof![
+                select(&FRAGMENTS[..]).prop_map(str::to_string),
+                any::<i64>().prop_map(|value| value.to_string()),
+                "[a-zA-Z0-9]{1,5}",
+            ],
+        )
+            .prop_map(|(separator, part)| format!("{separator}{part}"));
+        (
+            prop::option::of(select(commands)),
+            prop::collection::vec(part, 0..6),
+        )
+            .prop_map(|(command, parts)| format!("{}{}", command.unwrap_or(""), parts.concat()))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(4000))]
+
+        #[test]
+        fn parse_intent_is_total_and_deterministic(line in arbitrary_command_line()) {
+            let intent = parse_intent(&line);
+            prop_assert!(!intent.action.is_empty(), "{:?}", line);
+            prop_assert_eq!(parse_intent(&line), intent, "{:?}", line);
+        }
+    }
 }
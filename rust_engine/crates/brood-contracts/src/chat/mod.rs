@@ -1,5 +1,9 @@
 mod command_registry;
+mod edit_followup;
 mod intent_parser;
+mod palette;
 
-pub use command_registry::CHAT_HELP_COMMANDS;
-pub use intent_parser::{parse_intent, Intent};
+pub use command_registry::{ChatCommandHelp, CHAT_HELP_COMMANDS};
+pub use edit_followup::is_edit_followup;
+pub use intent_parser::{normalize_hex_color, parse_intent, Intent};
+pub use palette::command_palette;
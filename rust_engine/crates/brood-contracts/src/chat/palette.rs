@@ -0,0 +1,78 @@
+use super::command_registry::{ChatCommandHelp, CHAT_HELP_COMMANDS};
+
+/// Chat commands matching `query`, best first: a command-name prefix beats a
+/// substring, which beats an in-order subsequence (`/cc` finds
+/// `/canvas_context`); a summary mention ranks last. Ties keep registry
+/// order. An empty query (or a bare `/`) lists every command.
+pub fn command_palette(query: &str) -> Vec<&'static ChatCommandHelp> {
+    let needle = query.trim().trim_start_matches('/').to_ascii_lowercase();
+    let mut ranked: Vec<(u8, usize, &'static ChatCommandHelp)> = CHAT_HELP_COMMANDS
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| match_rank(&needle, entry).map(|rank| (rank, index, entry)))
+        .collect();
+    ranked.sort_by_key(|(rank, index, _)| (*rank, *index));
+    ranked.into_iter().map(|(_, _, entry)| entry).collect()
+}
+
+fn match_rank(needle: &str, entry: &ChatCommandHelp) -> Option<u8> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    let name = entry.command.trim_start_matches('/');
+    if name.starts_with(needle) {
+        Some(0)
+    } else if name.contains(needle) {
+        Some(1)
+    } else if is_subsequence(needle, name) {
+        Some(2)
+    } else if entry.summary.to_ascii_lowercase().contains(needle) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut remaining = haystack.chars();
+    needle
+        .chars()
+        .all(|wanted| remaining.by_ref().any(|ch| ch == wanted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command_palette;
+    use crate::chat::{parse_intent, CHAT_HELP_COMMANDS};
+
+    fn names(query: &str) -> Vec<&'static str> {
+        command_palette(query)
+            .into_iter()
+            .map(|entry| entry.command)
+            .collect()
+    }
+
+    #[test]
+    fn palette_ranks_prefix_then_substring_then_subsequence() {
+        assert_eq!(names("").len(), CHAT_HELP_COMMANDS.len());
+        assert_eq!(names("/"), names(""));
+
+        let canvas = names("canvas");
+        assert_eq!(canvas[0], "/canvas_context");
+        assert!(canvas.contains(&"/canvas_context_rt_start"));
+
+        let model = names("model");
+        assert_eq!(&model[..2], &["/text_model", "/image_model"]);
+
+        assert_eq!(names("/cmpr").first(), Some(&"/compare"));
+        assert!(names("zzzz").is_empty());
+    }
+
+    #[test]
+    fn every_help_entry_parses_to_a_known_action() {
+        for entry in CHAT_HELP_COMMANDS {
+            let intent = parse_intent(entry.command);
+            assert_ne!(intent.action, "unknown", "{} is not parsed", entry.command);
+        }
+    }
+}
@@ -0,0 +1,1482 @@
+//! Typed payloads for every `events.jsonl` event type.
+//!
+//! Each struct is the payload an [`EventWriter`](crate::events::EventWriter)
+//! merges under the `type`, `run_id` and `ts` envelope. Writers check
+//! every event against its type here and log an unknown type or a
+//! misspelled or mistyped field, so drift shows up before it breaks
+//! consumers. The event is still written. Extra fields are allowed; the
+//! structs list what consumers may rely on.
+
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// `$id` of the bundle checked in as `docs/events.schema.json`.
+pub const EVENT_SCHEMA_ID: &str = "https://brood.sh/spec/events.schema.json";
+
+/// An event payload with a fixed `type`.
+pub trait TypedEvent: Serialize + DeserializeOwned + JsonSchema {
+    const TYPE: &'static str;
+}
+
+/// A run dir was opened for a new run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RunStartedEvent {
+    pub out_dir: String,
+}
+
+impl TypedEvent for RunStartedEvent {
+    const TYPE: &'static str = "run_started";
+}
+
+/// What a generation is about to do, before any provider is called.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PlanPreviewEvent {
+    pub plan: GenerationPlan,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationPlan {
+    pub images: u64,
+    pub model: String,
+    pub provider: String,
+    pub size: String,
+    pub cached: bool,
+    /// `run` or `global` when the result comes from a cache.
+    pub cache_source: Option<String>,
+    /// Why the requested model was swapped for `model`, if it was.
+    pub fallback_reason: Option<String>,
+}
+
+impl TypedEvent for PlanPreviewEvent {
+    const TYPE: &'static str = "plan_preview";
+}
+
+/// A version was added to the thread.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionCreatedEvent {
+    pub version_id: String,
+    pub parent_version_id: Option<String>,
+    pub settings: Map<String, Value>,
+    pub prompt: String,
+}
+
+impl TypedEvent for VersionCreatedEvent {
+    const TYPE: &'static str = "version_created";
+}
+
+/// An image artifact and its receipt were written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactCreatedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    pub image_path: String,
+    pub receipt_path: Option<String>,
+    pub thumbnail_path: Option<String>,
+    #[serde(default)]
+    pub metrics: Map<String, Value>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// `warnings` with their stable codes; see `docs/warning_codes.md`.
+    #[serde(default)]
+    pub warning_details: Vec<Value>,
+}
+
+impl TypedEvent for ArtifactCreatedEvent {
+    const TYPE: &'static str = "artifact_created";
+}
+
+/// A generation produced nothing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationFailedEvent {
+    /// `null` when the request failed before a version was created.
+    pub version_id: Option<String>,
+    pub provider: String,
+    pub model: Option<String>,
+    pub error: String,
+    /// The HTTP trace of the failed call, when tracing was on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_trace: Option<String>,
+}
+
+impl TypedEvent for GenerationFailedEvent {
+    const TYPE: &'static str = "generation_failed";
+}
+
+/// Spend and latency of the latest generation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CostLatencyUpdateEvent {
+    pub provider: String,
+    pub model: String,
+    /// Image spend plus `text_cost_usd`.
+    pub cost_total_usd: f64,
+    pub cost_per_1k_images_usd: f64,
+    pub latency_per_image_s: f64,
+    pub text_input_tokens: u64,
+    pub text_output_tokens: u64,
+    pub text_cost_usd: f64,
+}
+
+impl TypedEvent for CostLatencyUpdateEvent {
+    const TYPE: &'static str = "cost_latency_update";
+}
+
+/// Context window usage of the chat session's text model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ContextWindowUpdateEvent {
+    pub model: String,
+    pub used_tokens: u64,
+    pub max_tokens: u64,
+    pub pct: f64,
+    /// `none`, `medium`, `high` or `critical`.
+    pub alert_level: String,
+    pub estimator: String,
+}
+
+impl TypedEvent for ContextWindowUpdateEvent {
+    const TYPE: &'static str = "context_window_update";
+}
+
+/// The run's summary was written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RunFinishedEvent {
+    pub summary_path: String,
+}
+
+impl TypedEvent for RunFinishedEvent {
+    const TYPE: &'static str = "run_finished";
+}
+
+/// A run dir was reopened by `resume`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RunResumedEvent {
+    pub out_dir: String,
+    pub started_at: String,
+    pub previously_finished: bool,
+    pub recovered_artifacts: Vec<String>,
+    pub incomplete_versions: Vec<String>,
+    pub pruned_cache_entries: u64,
+}
+
+impl TypedEvent for RunResumedEvent {
+    const TYPE: &'static str = "run_resumed";
+}
+
+/// A torn run file was set aside when the run dir was opened.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RunFileQuarantinedEvent {
+    pub path: String,
+    pub moved_to: String,
+    pub reason: String,
+    /// Versions recovered into a rebuilt `thread.json`.
+    pub rebuilt_versions: Option<u64>,
+}
+
+impl TypedEvent for RunFileQuarantinedEvent {
+    const TYPE: &'static str = "run_file_quarantined";
+}
+
+/// A provider reported progress on a long-running generation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationProgressEvent {
+    pub version_id: String,
+    pub provider: String,
+    pub model: String,
+    pub status: String,
+    pub elapsed_s: f64,
+    pub percent: Option<f64>,
+    /// The last lines of the provider's logs, when it sends any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+}
+
+impl TypedEvent for GenerationProgressEvent {
+    const TYPE: &'static str = "generation_progress";
+}
+
+/// Some images of a generation were delivered before a later call failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationPartialEvent {
+    pub version_id: String,
+    pub provider: String,
+    pub model: String,
+    pub requested: u64,
+    pub delivered: u64,
+    pub calls: u64,
+    pub error: String,
+}
+
+impl TypedEvent for GenerationPartialEvent {
+    const TYPE: &'static str = "generation_partial";
+}
+
+/// A generation would exceed, or cannot be checked against, a spend cap.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BudgetExceededEvent {
+    /// `run` or `session`.
+    pub scope: String,
+    pub cap_usd: f64,
+    pub spent_usd: f64,
+    /// `null` when the model has no pricing.
+    pub estimated_usd: Option<f64>,
+    #[serde(default)]
+    pub projected_usd: Option<f64>,
+    /// The model without pricing that made the cap unenforceable.
+    #[serde(default)]
+    pub unpriced_model: Option<String>,
+    /// The generation went ahead anyway.
+    pub forced: bool,
+}
+
+impl TypedEvent for BudgetExceededEvent {
+    const TYPE: &'static str = "budget_exceeded";
+}
+
+/// A video artifact and its receipt were written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VideoArtifactCreatedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    pub video_path: String,
+    pub receipt_path: Option<String>,
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub metrics: Map<String, Value>,
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    #[serde(default)]
+    pub warning_details: Vec<Value>,
+}
+
+impl TypedEvent for VideoArtifactCreatedEvent {
+    const TYPE: &'static str = "video_artifact_created";
+}
+
+/// An artifact was picked as its version's winner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactSelectedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    pub reason: String,
+    #[serde(default)]
+    pub score: Option<f64>,
+}
+
+impl TypedEvent for ArtifactSelectedEvent {
+    const TYPE: &'static str = "artifact_selected";
+}
+
+/// A hook rejected an artifact.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactRejectedEvent {
+    pub artifact_id: String,
+    pub hook: String,
+    pub reason: String,
+}
+
+impl TypedEvent for ArtifactRejectedEvent {
+    const TYPE: &'static str = "artifact_rejected";
+}
+
+/// The safety check flagged an artifact.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactFlaggedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    pub image_path: Option<String>,
+    pub backend: String,
+    pub score: f64,
+    pub categories: Vec<String>,
+    pub quarantined: bool,
+}
+
+impl TypedEvent for ArtifactFlaggedEvent {
+    const TYPE: &'static str = "artifact_flagged";
+}
+
+/// A new image is within the dedup distance of an earlier artifact.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactNearDuplicateEvent {
+    pub version_id: String,
+    pub image_path: String,
+    pub duplicate_of: String,
+    pub distance: u32,
+    /// `kept` or `skipped`.
+    pub action: String,
+}
+
+impl TypedEvent for ArtifactNearDuplicateEvent {
+    const TYPE: &'static str = "artifact_near_duplicate";
+}
+
+/// Labels were added to an artifact.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactTaggedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    pub labels: Vec<String>,
+    /// Every tag the artifact now has.
+    pub tags: Vec<String>,
+}
+
+impl TypedEvent for ArtifactTaggedEvent {
+    const TYPE: &'static str = "artifact_tagged";
+}
+
+/// An artifact and its receipt reached the artifact store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactUploadedEvent {
+    pub artifact_id: String,
+    pub store: String,
+    pub url: String,
+    pub receipt_url: String,
+    pub uploaded_at: String,
+}
+
+impl TypedEvent for ArtifactUploadedEvent {
+    const TYPE: &'static str = "artifact_uploaded";
+}
+
+/// Uploading an artifact failed; the local files are kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactUploadFailedEvent {
+    pub artifact_id: String,
+    pub store: String,
+    pub error: String,
+}
+
+impl TypedEvent for ArtifactUploadFailedEvent {
+    const TYPE: &'static str = "artifact_upload_failed";
+}
+
+/// Finished uploads could not be recorded in receipts and `thread.json`
+/// when the engine shut down; the objects may be in the store regardless.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UploadFailedEvent {
+    pub error: String,
+}
+
+impl TypedEvent for UploadFailedEvent {
+    const TYPE: &'static str = "upload_failed";
+}
+
+/// A version was soft-deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionDeletedEvent {
+    pub version_id: String,
+    pub deleted_at: Option<String>,
+    pub artifact_ids: Vec<String>,
+}
+
+impl TypedEvent for VersionDeletedEvent {
+    const TYPE: &'static str = "version_deleted";
+}
+
+/// A deleted or reverted version was brought back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionRestoredEvent {
+    pub version_id: String,
+}
+
+impl TypedEvent for VersionRestoredEvent {
+    const TYPE: &'static str = "version_restored";
+}
+
+/// A version was undone, making an earlier one current again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionRevertedEvent {
+    pub version_id: String,
+    pub reverted_at: Option<String>,
+    pub restored_version_id: Option<String>,
+    pub restored_artifact_id: Option<String>,
+}
+
+impl TypedEvent for VersionRevertedEvent {
+    const TYPE: &'static str = "version_reverted";
+}
+
+/// A version's artifacts were scored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct VersionScoredEvent {
+    pub version_id: String,
+    /// One row per artifact: `artifact_id`, `score` and its components.
+    pub scores: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for VersionScoredEvent {
+    const TYPE: &'static str = "version_scored";
+}
+
+/// The next generation will branch from `version_id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ThreadBranchedEvent {
+    pub version_id: String,
+    pub previous_version_id: Option<String>,
+}
+
+impl TypedEvent for ThreadBranchedEvent {
+    const TYPE: &'static str = "thread_branched";
+}
+
+/// Older chat turns were summarized to free context.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ContextCompactedEvent {
+    pub model: String,
+    pub transport: String,
+    pub turns_summarized: u64,
+    pub turns_kept: u64,
+    pub tokens_before: u64,
+    pub tokens_after: u64,
+    pub cost_usd: f64,
+}
+
+impl TypedEvent for ContextCompactedEvent {
+    const TYPE: &'static str = "context_compacted";
+}
+
+/// Summarizing older chat turns failed; the turns are kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ContextCompactionFailedEvent {
+    pub model: String,
+    pub used_tokens: u64,
+    pub error: String,
+}
+
+impl TypedEvent for ContextCompactionFailedEvent {
+    const TYPE: &'static str = "context_compaction_failed";
+}
+
+/// The text model rewrote the prompt before generation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PromptEnhancedEvent {
+    pub original_prompt: String,
+    pub enhanced_prompt: String,
+    pub model: String,
+    pub transport: String,
+    pub latency_s: f64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl TypedEvent for PromptEnhancedEvent {
+    const TYPE: &'static str = "prompt_enhanced";
+}
+
+/// Prompt enhancement failed; the original prompt is used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PromptEnhanceFailedEvent {
+    pub prompt: String,
+    pub model: String,
+    pub error: String,
+}
+
+impl TypedEvent for PromptEnhanceFailedEvent {
+    const TYPE: &'static str = "prompt_enhance_failed";
+}
+
+/// A hook failed or timed out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct HookFailedEvent {
+    pub hook: String,
+    /// The event type the hook ran for.
+    pub event: String,
+    pub artifact_id: Option<String>,
+    pub error: String,
+}
+
+impl TypedEvent for HookFailedEvent {
+    const TYPE: &'static str = "hook_failed";
+}
+
+/// Artifacts were written out under an export profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExportCompletedEvent {
+    pub profile: String,
+    pub out_dir: String,
+    /// One entry per file: `artifact_id`, `path`, `width`, `height`,
+    /// `bytes` and `tags`.
+    pub files: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for ExportCompletedEvent {
+    const TYPE: &'static str = "export_completed";
+}
+
+/// A receipt was generated again and compared with the original.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReproductionCompletedEvent {
+    pub version_id: String,
+    pub reproduction_of: String,
+    pub source_receipt: String,
+    /// One entry per artifact: `artifact_id` and its `delta`.
+    pub artifacts: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for ReproductionCompletedEvent {
+    const TYPE: &'static str = "reproduction_completed";
+}
+
+/// A vision model's critique of one artifact.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArtifactCritiquedEvent {
+    pub version_id: String,
+    pub artifact_id: String,
+    /// Prompt adherence and quality, 0 to 100.
+    pub score: f64,
+    pub notes: String,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+}
+
+impl TypedEvent for ArtifactCritiquedEvent {
+    const TYPE: &'static str = "artifact_critiqued";
+}
+
+/// The critic could not score a version; its retry loop stops.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CriticFailedEvent {
+    pub version_id: String,
+    pub error: String,
+}
+
+impl TypedEvent for CriticFailedEvent {
+    const TYPE: &'static str = "critic_failed";
+}
+
+/// A version scored under the critic threshold and is generated again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CriticRetryEvent {
+    pub version_id: String,
+    pub attempt: u64,
+    pub max_attempts: u64,
+    pub score: f64,
+    pub threshold: f64,
+    pub prompt: String,
+}
+
+impl TypedEvent for CriticRetryEvent {
+    const TYPE: &'static str = "critic_retry";
+}
+
+/// Picking the best artifact of a version failed; nothing was selected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AutoSelectFailedEvent {
+    pub version_id: String,
+    pub error: String,
+}
+
+impl TypedEvent for AutoSelectFailedEvent {
+    const TYPE: &'static str = "auto_select_failed";
+}
+
+/// A prompt template was expanded into one prompt per combination.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PromptTemplateExpandedEvent {
+    pub template: String,
+    pub count: u64,
+    pub prompts: Vec<String>,
+}
+
+impl TypedEvent for PromptTemplateExpandedEvent {
+    const TYPE: &'static str = "prompt_template_expanded";
+}
+
+/// Storing artifacts in the global cache failed; the run is unaffected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GlobalCacheStoreFailedEvent {
+    pub error: String,
+}
+
+impl TypedEvent for GlobalCacheStoreFailedEvent {
+    const TYPE: &'static str = "global_cache_store_failed";
+}
+
+/// The enabled providers or their order changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ProviderPolicyUpdatedEvent {
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+    pub priority: Vec<String>,
+}
+
+impl TypedEvent for ProviderPolicyUpdatedEvent {
+    const TYPE: &'static str = "provider_policy_updated";
+}
+
+/// Dominant colours of an image, most common first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PaletteExtractedEvent {
+    pub image_path: String,
+    /// `null` when the image is not an artifact of the run.
+    pub artifact_id: Option<String>,
+    /// One entry per colour: `hex`, `name` and `share`.
+    pub colors: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for PaletteExtractedEvent {
+    const TYPE: &'static str = "palette_extracted";
+}
+
+/// Two images were composited side by side, with their similarity.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ComparisonCreatedEvent {
+    pub left: String,
+    pub right: String,
+    pub left_path: String,
+    pub right_path: String,
+    pub composite_path: String,
+    pub heatmap: bool,
+    pub ssim: f64,
+    pub mean_abs_diff: f64,
+    pub width: u64,
+    pub height: u64,
+    /// The right image was resized to the left one's size.
+    pub resized: bool,
+}
+
+impl TypedEvent for ComparisonCreatedEvent {
+    const TYPE: &'static str = "comparison_created";
+}
+
+/// The requests recorded in two receipts were compared.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReceiptsDiffedEvent {
+    pub left: String,
+    pub right: String,
+    pub left_receipt: String,
+    pub right_receipt: String,
+    pub identical: bool,
+    pub unseeded: bool,
+    /// One entry per differing field: `path`, `left` and `right`.
+    pub changes: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for ReceiptsDiffedEvent {
+    const TYPE: &'static str = "receipts_diffed";
+}
+
+/// A deterministic generation was added to `reproducibility.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ReproducibilityReportEvent {
+    pub version_id: String,
+    pub provider: String,
+    pub model: String,
+    pub seeds: Vec<Option<i64>>,
+    pub pinned_options: Map<String, Value>,
+    pub deterministic: bool,
+    pub reasons: Vec<String>,
+    pub report_path: String,
+    /// Providers in the run that could not reproduce an image, with reasons.
+    pub nondeterministic_providers: Vec<Value>,
+}
+
+impl TypedEvent for ReproducibilityReportEvent {
+    const TYPE: &'static str = "reproducibility_report";
+}
+
+/// What a reference image shows, before it is recreated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreateAnalysisEvent {
+    pub reference: String,
+    pub subject: String,
+    pub style: String,
+    pub medium: String,
+    pub composition: String,
+    pub lighting: String,
+    pub palette: Vec<String>,
+    /// `vision`, `receipt` or `local`.
+    pub source: String,
+    pub model: Option<String>,
+}
+
+impl TypedEvent for RecreateAnalysisEvent {
+    const TYPE: &'static str = "recreate_analysis";
+}
+
+/// The vision model could not describe a reference; the local analysis is
+/// used.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreateAnalysisFailedEvent {
+    pub reference: String,
+    pub model: String,
+    pub error: String,
+}
+
+impl TypedEvent for RecreateAnalysisFailedEvent {
+    const TYPE: &'static str = "recreate_analysis_failed";
+}
+
+/// The prompt compiled from a reference analysis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreatePromptInferredEvent {
+    pub reference: String,
+    pub prompt: String,
+    pub source: String,
+    pub model: Option<String>,
+}
+
+impl TypedEvent for RecreatePromptInferredEvent {
+    const TYPE: &'static str = "recreate_prompt_inferred";
+}
+
+/// How close one recreate candidate is to the reference, each part 0 to 1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreateScoreEvent {
+    pub iteration: u64,
+    pub artifact_id: Option<String>,
+    pub structure: f64,
+    pub ssim: f64,
+    pub palette: f64,
+    pub overall: f64,
+}
+
+impl TypedEvent for RecreateScoreEvent {
+    const TYPE: &'static str = "recreate_score";
+}
+
+/// A recreate round finished.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreateIterationUpdateEvent {
+    pub iteration: u64,
+    /// Best overall score so far.
+    pub similarity: f64,
+    pub best_artifact_id: Option<String>,
+}
+
+impl TypedEvent for RecreateIterationUpdateEvent {
+    const TYPE: &'static str = "recreate_iteration_update";
+}
+
+/// A recreate stopped at the target score, the round limit or an error.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RecreateDoneEvent {
+    pub reference: String,
+    pub best_artifact_id: Option<String>,
+    pub best_score: f64,
+    pub iterations: u64,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl TypedEvent for RecreateDoneEvent {
+    const TYPE: &'static str = "recreate_done";
+}
+
+/// A prompt experiment is about to run its variants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExperimentStartedEvent {
+    pub experiment_id: String,
+    /// One entry per variant: `label` and `prompt`.
+    pub variants: Vec<Map<String, Value>>,
+    pub settings: Map<String, Value>,
+}
+
+impl TypedEvent for ExperimentStartedEvent {
+    const TYPE: &'static str = "experiment_started";
+}
+
+/// One variant of a prompt experiment finished.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExperimentVariantCompletedEvent {
+    pub experiment_id: String,
+    pub label: String,
+    pub status: String,
+    pub version_ids: Vec<String>,
+    pub artifact_ids: Vec<String>,
+    pub cost_usd: f64,
+    pub latency_per_image_s: Option<f64>,
+    pub elapsed_s: f64,
+    pub error: Option<String>,
+}
+
+impl TypedEvent for ExperimentVariantCompletedEvent {
+    const TYPE: &'static str = "experiment_variant_completed";
+}
+
+/// Scoring the variants of an experiment failed; the summary has no
+/// quality scores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExperimentScoringFailedEvent {
+    pub experiment_id: String,
+    pub error: String,
+}
+
+impl TypedEvent for ExperimentScoringFailedEvent {
+    const TYPE: &'static str = "experiment_scoring_failed";
+}
+
+/// A prompt experiment's summary was written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ExperimentFinishedEvent {
+    pub experiment_id: String,
+    pub summary_path: String,
+    /// Label of the variant with the best quality score.
+    pub best_quality: Option<String>,
+}
+
+impl TypedEvent for ExperimentFinishedEvent {
+    const TYPE: &'static str = "experiment_finished";
+}
+
+/// Faces were found in an init image and masked out of the edit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FacesDetectedEvent {
+    pub init_image: String,
+    /// `local` or `vision`.
+    pub detector: String,
+    /// One box per face: `x`, `y`, `width` and `height`.
+    pub faces: Vec<Map<String, Value>>,
+    pub padding: f64,
+    pub mask_path: String,
+}
+
+impl TypedEvent for FacesDetectedEvent {
+    const TYPE: &'static str = "faces_detected";
+}
+
+/// No face was found in an init image; the edit runs unmasked.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FacesNotFoundEvent {
+    pub init_image: String,
+    pub detector: String,
+}
+
+impl TypedEvent for FacesNotFoundEvent {
+    const TYPE: &'static str = "faces_not_found";
+}
+
+/// Crops of one artifact were written for a rendition set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RenditionsExportedEvent {
+    pub artifact_id: String,
+    pub set: String,
+    /// `faces` or `saliency`: what the crops are centred on.
+    pub focus: String,
+    pub faces: Vec<Map<String, Value>>,
+    pub out_dir: String,
+    /// One entry per file: `name`, `width`, `height`, `crop`, `path` and
+    /// `receipt_path`.
+    pub renditions: Vec<Map<String, Value>>,
+}
+
+impl TypedEvent for RenditionsExportedEvent {
+    const TYPE: &'static str = "renditions_exported";
+}
+
+/// A region of an image was selected for the next edit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RegionSelectedEvent {
+    pub image_path: String,
+    pub mask_path: String,
+    pub region: Value,
+    pub description: Option<String>,
+    pub model: Option<String>,
+    /// Share of the image the mask covers, 0 to 1.
+    pub coverage: f64,
+    pub cost_usd: f64,
+}
+
+impl TypedEvent for RegionSelectedEvent {
+    const TYPE: &'static str = "region_selected";
+}
+
+/// The selected region was dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RegionClearedEvent {
+    pub image_path: String,
+    pub mask_path: String,
+}
+
+impl TypedEvent for RegionClearedEvent {
+    const TYPE: &'static str = "region_cleared";
+}
+
+/// `/describe` produced a caption for an image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageDescriptionEvent {
+    pub image_path: String,
+    pub description: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub max_chars: Option<u64>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for ImageDescriptionEvent {
+    const TYPE: &'static str = "image_description";
+}
+
+/// Text a vision model read from the canvas, from `/canvas_context` or its
+/// realtime session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CanvasContextEvent {
+    pub image_path: String,
+    pub text: String,
+    pub source: String,
+    pub model: Option<String>,
+    /// Set on streamed text that is not final yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial: Option<bool>,
+}
+
+impl TypedEvent for CanvasContextEvent {
+    const TYPE: &'static str = "canvas_context";
+}
+
+/// Reading the canvas failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CanvasContextFailedEvent {
+    pub image_path: Option<String>,
+    pub error: String,
+    pub source: String,
+    pub model: Option<String>,
+    /// The realtime session stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fatal: Option<bool>,
+}
+
+impl TypedEvent for CanvasContextFailedEvent {
+    const TYPE: &'static str = "canvas_context_failed";
+}
+
+/// Intent icons streamed by the realtime intent session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IntentIconsEvent {
+    pub image_path: String,
+    /// The model's JSON reply, as text.
+    pub text: String,
+    pub source: String,
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial: Option<bool>,
+}
+
+impl TypedEvent for IntentIconsEvent {
+    const TYPE: &'static str = "intent_icons";
+}
+
+/// The realtime intent session failed to read a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IntentIconsFailedEvent {
+    pub image_path: Option<String>,
+    pub error: String,
+    pub source: String,
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fatal: Option<bool>,
+}
+
+impl TypedEvent for IntentIconsFailedEvent {
+    const TYPE: &'static str = "intent_icons_failed";
+}
+
+/// `/diagnose` critiqued an image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageDiagnosisEvent {
+    pub image_path: String,
+    pub text: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for ImageDiagnosisEvent {
+    const TYPE: &'static str = "image_diagnosis";
+}
+
+/// `/argue` weighed two images against each other.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageArgumentEvent {
+    pub image_paths: Vec<String>,
+    pub text: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for ImageArgumentEvent {
+    const TYPE: &'static str = "image_argument";
+}
+
+/// The visual DNA (palette, colours, materials) of an image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageDnaExtractedEvent {
+    pub image_path: String,
+    pub palette: Vec<String>,
+    pub colors: Vec<String>,
+    pub materials: Vec<String>,
+    pub summary: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for ImageDnaExtractedEvent {
+    const TYPE: &'static str = "image_dna_extracted";
+}
+
+/// Extracting an image's DNA failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageDnaExtractedFailedEvent {
+    pub image_path: String,
+    pub error: String,
+}
+
+impl TypedEvent for ImageDnaExtractedFailedEvent {
+    const TYPE: &'static str = "image_dna_extracted_failed";
+}
+
+/// The emotional read of an image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageSoulExtractedEvent {
+    pub image_path: String,
+    pub emotion: String,
+    pub summary: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for ImageSoulExtractedEvent {
+    const TYPE: &'static str = "image_soul_extracted";
+}
+
+/// Extracting an image's soul failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ImageSoulExtractedFailedEvent {
+    pub image_path: String,
+    pub error: String,
+}
+
+impl TypedEvent for ImageSoulExtractedFailedEvent {
+    const TYPE: &'static str = "image_soul_extracted_failed";
+}
+
+/// The design rule three images share.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TripletRuleEvent {
+    pub image_paths: Vec<String>,
+    pub principle: String,
+    pub evidence: Vec<Value>,
+    pub annotations: Vec<Value>,
+    pub source: String,
+    pub model: Option<String>,
+    pub confidence: f64,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for TripletRuleEvent {
+    const TYPE: &'static str = "triplet_rule";
+}
+
+/// The image of three that breaks the pattern of the other two.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TripletOddOneOutEvent {
+    pub image_paths: Vec<String>,
+    pub odd_image: String,
+    pub odd_index: i64,
+    pub pattern: String,
+    pub explanation: String,
+    pub source: String,
+    pub model: Option<String>,
+    pub confidence: f64,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+}
+
+impl TypedEvent for TripletOddOneOutEvent {
+    const TYPE: &'static str = "triplet_odd_one_out";
+}
+
+/// A structured intent was inferred from a Mother payload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MotherIntentInferredEvent {
+    pub payload_path: String,
+    pub action_version: i64,
+    pub intent: Value,
+    pub source: String,
+    pub model: String,
+}
+
+impl TypedEvent for MotherIntentInferredEvent {
+    const TYPE: &'static str = "mother_intent_inferred";
+}
+
+/// A Mother payload could not be read for intent inference.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MotherIntentInferFailedEvent {
+    pub error: String,
+    pub payload_path: Option<String>,
+}
+
+impl TypedEvent for MotherIntentInferFailedEvent {
+    const TYPE: &'static str = "mother_intent_infer_failed";
+}
+
+/// A Mother payload was compiled into generation prompts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MotherPromptCompiledEvent {
+    pub payload_path: String,
+    pub action_version: i64,
+    pub compiled: Value,
+    pub source: String,
+    pub model: String,
+}
+
+impl TypedEvent for MotherPromptCompiledEvent {
+    const TYPE: &'static str = "mother_prompt_compiled";
+}
+
+/// A Mother payload could not be read for prompt compilation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MotherPromptCompileFailedEvent {
+    pub error: String,
+    pub payload_path: Option<String>,
+}
+
+impl TypedEvent for MotherPromptCompileFailedEvent {
+    const TYPE: &'static str = "mother_prompt_compile_failed";
+}
+
+/// `/optimize` analysed the latest receipt against its goals.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnalysisReadyEvent {
+    pub analysis_excerpt: String,
+    pub recommendations: Vec<Map<String, Value>>,
+    pub analysis_elapsed_s: f64,
+    pub goals: Vec<String>,
+    /// `auto` or `review`.
+    pub mode: String,
+    /// Set in `auto` mode, which runs several rounds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round_total: Option<u64>,
+}
+
+impl TypedEvent for AnalysisReadyEvent {
+    const TYPE: &'static str = "analysis_ready";
+}
+
+/// One `/optimize` round's generation finished.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OptimizeGenerationDoneEvent {
+    pub round: u64,
+    pub round_total: u64,
+    pub elapsed_s: f64,
+    pub goals: Vec<String>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl TypedEvent for OptimizeGenerationDoneEvent {
+    const TYPE: &'static str = "optimize_generation_done";
+}
+
+struct Registered {
+    event_type: &'static str,
+    validate: fn(&Value) -> serde_json::Result<()>,
+    schema: fn() -> Value,
+}
+
+const fn register<E: TypedEvent>() -> Registered {
+    Registered {
+        event_type: E::TYPE,
+        validate: validate_as::<E>,
+        schema: payload_schema::<E>,
+    }
+}
+
+const REGISTRY: &[Registered] = &[
+    register::<RunStartedEvent>(),
+    register::<PlanPreviewEvent>(),
+    register::<VersionCreatedEvent>(),
+    register::<ArtifactCreatedEvent>(),
+    register::<GenerationFailedEvent>(),
+    register::<CostLatencyUpdateEvent>(),
+    register::<ContextWindowUpdateEvent>(),
+    register::<RunFinishedEvent>(),
+    register::<RunResumedEvent>(),
+    register::<RunFileQuarantinedEvent>(),
+    register::<GenerationProgressEvent>(),
+    register::<GenerationPartialEvent>(),
+    register::<BudgetExceededEvent>(),
+    register::<VideoArtifactCreatedEvent>(),
+    register::<ArtifactSelectedEvent>(),
+    register::<ArtifactRejectedEvent>(),
+    register::<ArtifactFlaggedEvent>(),
+    register::<ArtifactNearDuplicateEvent>(),
+    register::<ArtifactTaggedEvent>(),
+    register::<ArtifactUploadedEvent>(),
+    register::<ArtifactUploadFailedEvent>(),
+    register::<UploadFailedEvent>(),
+    register::<VersionDeletedEvent>(),
+    register::<VersionRestoredEvent>(),
+    register::<VersionRevertedEvent>(),
+    register::<VersionScoredEvent>(),
+    register::<ThreadBranchedEvent>(),
+    register::<ContextCompactedEvent>(),
+    register::<ContextCompactionFailedEvent>(),
+    register::<PromptEnhancedEvent>(),
+    register::<PromptEnhanceFailedEvent>(),
+    register::<HookFailedEvent>(),
+    register::<ExportCompletedEvent>(),
+    register::<ReproductionCompletedEvent>(),
+    register::<ArtifactCritiquedEvent>(),
+    register::<CriticFailedEvent>(),
+    register::<CriticRetryEvent>(),
+    register::<AutoSelectFailedEvent>(),
+    register::<PromptTemplateExpandedEvent>(),
+    register::<GlobalCacheStoreFailedEvent>(),
+    register::<ProviderPolicyUpdatedEvent>(),
+    register::<PaletteExtractedEvent>(),
+    register::<ComparisonCreatedEvent>(),
+    register::<ReceiptsDiffedEvent>(),
+    register::<ReproducibilityReportEvent>(),
+    register::<RecreateAnalysisEvent>(),
+    register::<RecreateAnalysisFailedEvent>(),
+    register::<RecreatePromptInferredEvent>(),
+    register::<RecreateScoreEvent>(),
+    register::<RecreateIterationUpdateEvent>(),
+    register::<RecreateDoneEvent>(),
+    register::<ExperimentStartedEvent>(),
+    register::<ExperimentVariantCompletedEvent>(),
+    register::<ExperimentScoringFailedEvent>(),
+    register::<ExperimentFinishedEvent>(),
+    register::<FacesDetectedEvent>(),
+    register::<FacesNotFoundEvent>(),
+    register::<RenditionsExportedEvent>(),
+    register::<RegionSelectedEvent>(),
+    register::<RegionClearedEvent>(),
+    register::<ImageDescriptionEvent>(),
+    register::<CanvasContextEvent>(),
+    register::<CanvasContextFailedEvent>(),
+    register::<IntentIconsEvent>(),
+    register::<IntentIconsFailedEvent>(),
+    register::<ImageDiagnosisEvent>(),
+    register::<ImageArgumentEvent>(),
+    register::<ImageDnaExtractedEvent>(),
+    register::<ImageDnaExtractedFailedEvent>(),
+    register::<ImageSoulExtractedEvent>(),
+    register::<ImageSoulExtractedFailedEvent>(),
+    register::<TripletRuleEvent>(),
+    register::<TripletOddOneOutEvent>(),
+    register::<MotherIntentInferredEvent>(),
+    register::<MotherIntentInferFailedEvent>(),
+    register::<MotherPromptCompiledEvent>(),
+    register::<MotherPromptCompileFailedEvent>(),
+    register::<AnalysisReadyEvent>(),
+    register::<OptimizeGenerationDoneEvent>(),
+];
+
+fn validate_as<E: TypedEvent>(event: &Value) -> serde_json::Result<()> {
+    E::deserialize(event).map(drop)
+}
+
+fn payload_schema<E: TypedEvent>() -> Value {
+    let generator = SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    generator.into_root_schema_for::<E>().to_value()
+}
+
+/// Event types with a registered schema, in bundle order.
+pub fn event_types() -> impl Iterator<Item = &'static str> {
+    REGISTRY.iter().map(|entry| entry.event_type)
+}
+
+/// Checks a full event (envelope included) against its type's schema.
+/// An event type without a schema is an error too.
+pub fn validate_event(event: &Value) -> anyhow::Result<()> {
+    let event_type = event.get("type").and_then(Value::as_str).unwrap_or("");
+    let Some(entry) = REGISTRY.iter().find(|entry| entry.event_type == event_type) else {
+        anyhow::bail!("unknown event type {event_type:?}");
+    };
+    (entry.validate)(event).map_err(|err| anyhow::anyhow!("invalid {event_type} event: {err}"))
+}
+
+/// The schema of one registered event type, envelope included.
+pub fn event_schema(event_type: &str) -> Option<Value> {
+    let entry = REGISTRY
+        .iter()
+        .find(|entry| entry.event_type == event_type)?;
+    let mut schema = (entry.schema)();
+    let object = schema.as_object_mut()?;
+    object.remove("$schema");
+    object.insert("title".to_string(), json!(event_type));
+    let properties = object
+        .entry("properties")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()?;
+    properties.insert("type".to_string(), json!({ "const": event_type }));
+    properties.insert("run_id".to_string(), json!({ "type": "string" }));
+    properties.insert(
+        "ts".to_string(),
+        json!({ "type": "string", "format": "date-time" }),
+    );
+    let required = object
+        .entry("required")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()?;
+    for (idx, key) in ["type", "run_id", "ts"].into_iter().enumerate() {
+        required.insert(idx, json!(key));
+    }
+    Some(schema)
+}
+
+/// One JSON Schema document covering every registered event type, as
+/// published in `docs/events.schema.json`.
+pub fn event_schema_bundle() -> Value {
+    let defs: Map<String, Value> = event_types()
+        .filter_map(|event_type| Some((event_type.to_string(), event_schema(event_type)?)))
+        .collect();
+    let one_of: Vec<Value> = defs
+        .keys()
+        .map(|event_type| json!({ "$ref": format!("#/$defs/{event_type}") }))
+        .collect();
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": EVENT_SCHEMA_ID,
+        "title": "Brood events.jsonl",
+        "description": "One line of events.jsonl. Every event may carry fields beyond those listed.",
+        "oneOf": one_of,
+        "$defs": defs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Types the workspace emits through a variable rather than a literal.
+    const NON_LITERAL_EVENT_TYPES: &[&str] = &[
+        "intent_icons",
+        "artifact_uploaded",
+        "artifact_upload_failed",
+    ];
+
+    /// Every string literal passed to `emit` or `emit_event` outside the
+    /// workspace crates' test modules.
+    fn emitted_event_types() -> std::io::Result<BTreeSet<String>> {
+        let crates = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(crates)? {
+            dirs.push(entry?.path().join("src"));
+        }
+        let mut types = BTreeSet::new();
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                if path.extension().and_then(|ext| ext.to_str()) != Some("rs") {
+                    continue;
+                }
+                let source = std::fs::read_to_string(&path)?;
+                let source = source.split("#[cfg(test)]").next().unwrap_or_default();
+                for call in [".emit(", ".emit_event("] {
+                    for (start, _) in source.match_indices(call) {
+                        let rest = source[start + call.len()..].trim_start();
+                        let Some(literal) = rest.strip_prefix('"') else {
+                            continue;
+                        };
+                        if let Some(end) = literal.find('"') {
+                            types.insert(literal[..end].to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(types)
+    }
+
+    #[test]
+    fn every_emitted_event_type_is_registered() -> std::io::Result<()> {
+        let mut emitted = emitted_event_types()?;
+        assert!(emitted.contains("artifact_flagged"), "{emitted:?}");
+        assert!(emitted.contains("triplet_rule"), "{emitted:?}");
+        emitted.extend(NON_LITERAL_EVENT_TYPES.iter().map(|name| name.to_string()));
+        let registered: BTreeSet<&str> = event_types().collect();
+        let missing: Vec<&String> = emitted
+            .iter()
+            .filter(|name| !registered.contains(name.as_str()))
+            .collect();
+        assert!(missing.is_empty(), "unregistered event types: {missing:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn validation_catches_missing_and_mistyped_fields() {
+        let event = |payload: Value| {
+            let mut event = json!({"type": "plan_preview", "run_id": "r", "ts": "t"});
+            event["plan"] = payload;
+            event
+        };
+        let plan = json!({
+            "images": 2, "model": "m", "provider": "p", "size": "1024x1024",
+            "cached": false, "cache_source": null,
+        });
+        assert!(validate_event(&event(plan.clone())).is_ok());
+
+        let typo = json!({
+            "imgaes": 2, "model": "m", "provider": "p", "size": "1024x1024", "cached": false,
+        });
+        let err = validate_event(&event(typo)).expect_err("missing images");
+        assert!(err.to_string().contains("images"), "{err}");
+
+        let mut mistyped = plan;
+        mistyped["cached"] = json!("no");
+        assert!(validate_event(&event(mistyped)).is_err());
+
+        let err = validate_event(&json!({"type": "something_else", "run_id": "r", "ts": "t"}))
+            .expect_err("unknown type");
+        assert!(err.to_string().contains("something_else"), "{err}");
+        assert!(validate_event(&json!({"run_id": "r", "ts": "t"})).is_err());
+    }
+
+    #[test]
+    fn published_bundle_matches_the_registry() -> anyhow::Result<()> {
+        let bundle = event_schema_bundle();
+        let defs = bundle["$defs"].as_object().expect("defs");
+        assert_eq!(defs.len(), REGISTRY.len());
+        let artifact = &defs["artifact_created"];
+        assert_eq!(artifact["properties"]["type"]["const"], "artifact_created");
+        assert!(artifact["required"]
+            .as_array()
+            .expect("required")
+            .contains(&json!("image_path")));
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../docs/events.schema.json"
+        );
+        let published: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        assert_eq!(
+            published, bundle,
+            "docs/events.schema.json is stale; regenerate it with `brood-rs event-schema`"
+        );
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -6,6 +7,9 @@ use std::sync::{Arc, Mutex};
 use chrono::{SecondsFormat, Utc};
 use serde_json::{Map, Value};
 
+use crate::event_schema::{validate_event, TypedEvent};
+use crate::redaction::{redact_map, MAX_PERSISTED_STRING_CHARS};
+
 pub type EventPayload = Map<String, Value>;
 
 /// Append-only writer for `events.jsonl`.
@@ -14,6 +18,10 @@ pub type EventPayload = Map<String, Value>;
 /// - default fields are `type`, `run_id`, `ts`
 /// - caller payload is merged last and can override defaults
 /// - one compact JSON object per line
+///
+/// Events of a type registered in [`crate::event_schema`] are validated
+/// before they are written. An invalid one is logged and written anyway,
+/// so a schema drift never fails the caller.
 #[derive(Debug, Clone)]
 pub struct EventWriter {
     inner: Arc<EventWriterInner>,
@@ -24,6 +32,145 @@ struct EventWriterInner {
     path: PathBuf,
     run_id: String,
     lock: Mutex<()>,
+    sinks: Mutex<Vec<FilteredSink>>,
+}
+
+/// Secondary destination for events (stderr, sockets, UIs).
+///
+/// `events.jsonl` always receives every event; sinks only see what their
+/// [`EventFilter`] admits.
+pub trait EventSink: Send + Sync {
+    fn send(&self, event: &Value) -> anyhow::Result<()>;
+}
+
+/// Include/exclude rules plus per-type sampling for a single sink.
+///
+/// Type patterns match exactly, or by prefix when they end in `*`.
+/// `sample_every` keeps one out of every N events of a matching type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub sample_every: BTreeMap<String, u64>,
+}
+
+impl EventFilter {
+    /// Parses `include=a,b;exclude=c*;sample=progress:10` style specs.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut filter = Self::default();
+        for clause in spec.split(';').map(str::trim).filter(|row| !row.is_empty()) {
+            let Some((key, value)) = clause.split_once('=') else {
+                anyhow::bail!("invalid event filter clause '{clause}'");
+            };
+            let items = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string);
+            match key.trim().to_ascii_lowercase().as_str() {
+                "include" => filter.include.extend(items),
+                "exclude" => filter.exclude.extend(items),
+                "sample" => {
+                    for item in items {
+                        let Some((pattern, every)) = item.rsplit_once(':') else {
+                            anyhow::bail!("invalid sample rule '{item}' (expected type:N)");
+                        };
+                        let every = every
+                            .trim()
+                            .parse::<u64>()
+                            .map_err(|_| anyhow::anyhow!("invalid sample rate in '{item}'"))?;
+                        filter
+                            .sample_every
+                            .insert(pattern.trim().to_string(), every.max(1));
+                    }
+                }
+                other => anyhow::bail!("unknown event filter key '{other}'"),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn admits_type(&self, event_type: &str) -> bool {
+        if !self.include.is_empty()
+            && !self
+                .include
+                .iter()
+                .any(|pattern| type_matches(pattern, event_type))
+        {
+            return false;
+        }
+        !self
+            .exclude
+            .iter()
+            .any(|pattern| type_matches(pattern, event_type))
+    }
+
+    fn sample_rate(&self, event_type: &str) -> Option<(&str, u64)> {
+        self.sample_every
+            .iter()
+            .find(|(pattern, _)| type_matches(pattern, event_type))
+            .map(|(pattern, every)| (pattern.as_str(), *every))
+    }
+}
+
+fn type_matches(pattern: &str, event_type: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => event_type.starts_with(prefix),
+        None => pattern == event_type,
+    }
+}
+
+struct FilteredSink {
+    sink: Box<dyn EventSink>,
+    filter: EventFilter,
+    seen: BTreeMap<String, u64>,
+}
+
+impl std::fmt::Debug for FilteredSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilteredSink")
+            .field("filter", &self.filter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FilteredSink {
+    fn admit(&mut self, event_type: &str) -> bool {
+        if !self.filter.admits_type(event_type) {
+            return false;
+        }
+        let Some((pattern, every)) = self.filter.sample_rate(event_type) else {
+            return true;
+        };
+        let counter = self.seen.entry(pattern.to_string()).or_insert(0);
+        *counter += 1;
+        (*counter - 1).is_multiple_of(every)
+    }
+}
+
+/// Writes each admitted event as one JSON line to any `Write` target.
+pub struct JsonLineSink<W: Write + Send> {
+    out: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonLineSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonLineSink<W> {
+    fn send(&self, event: &Value) -> anyhow::Result<()> {
+        let mut out = self
+            .out
+            .lock()
+            .map_err(|_| anyhow::anyhow!("event sink lock poisoned"))?;
+        writeln!(out, "{}", serde_json::to_string(event)?)?;
+        out.flush()?;
+        Ok(())
+    }
 }
 
 impl EventWriter {
@@ -33,6 +180,7 @@ impl EventWriter {
                 path: path.into(),
                 run_id: run_id.into(),
                 lock: Mutex::new(()),
+                sinks: Mutex::new(Vec::new()),
             }),
         }
     }
@@ -45,6 +193,20 @@ impl EventWriter {
         &self.inner.run_id
     }
 
+    /// Attaches a filtered sink; it applies to every clone of this writer.
+    pub fn add_sink(&self, sink: Box<dyn EventSink>, filter: EventFilter) -> anyhow::Result<()> {
+        self.inner
+            .sinks
+            .lock()
+            .map_err(|_| anyhow::anyhow!("event sink registry lock poisoned"))?
+            .push(FilteredSink {
+                sink,
+                filter,
+                seen: BTreeMap::new(),
+            });
+        Ok(())
+    }
+
     pub fn emit(&self, event_type: &str, payload: EventPayload) -> anyhow::Result<Value> {
         let mut event = Map::new();
         event.insert("type".to_string(), Value::String(event_type.to_string()));
@@ -53,10 +215,17 @@ impl EventWriter {
             Value::String(self.inner.run_id.clone()),
         );
         event.insert("ts".to_string(), Value::String(now_utc_iso()));
-        for (key, value) in payload {
+        // Payloads can carry provider options and URLs; keys never hit disk.
+        for (key, value) in redact_map(&payload, MAX_PERSISTED_STRING_CHARS) {
             event.insert(key, value);
         }
 
+        let event = Value::Object(event);
+        // Written anyway: the event describes work that already happened.
+        if let Err(err) = validate_event(&event) {
+            tracing::warn!(event_type, error = %err, "event does not match its schema");
+        }
+
         if let Some(parent) = self.inner.path.parent() {
             std::fs::create_dir_all(parent)?;
         }
@@ -73,8 +242,31 @@ impl EventWriter {
             .open(&self.inner.path)?;
         file.write_all(line.as_bytes())?;
         file.write_all(b"\n")?;
+        drop(_guard);
+
+        self.dispatch_to_sinks(&event);
+        Ok(event)
+    }
 
-        Ok(Value::Object(event))
+    /// Emits a typed payload under its event type.
+    pub fn emit_typed<E: TypedEvent>(&self, event: &E) -> anyhow::Result<Value> {
+        let Value::Object(payload) = serde_json::to_value(event)? else {
+            anyhow::bail!("{} payload must serialize to an object", E::TYPE);
+        };
+        self.emit(E::TYPE, payload)
+    }
+
+    fn dispatch_to_sinks(&self, event: &Value) {
+        let Ok(mut sinks) = self.inner.sinks.lock() else {
+            return;
+        };
+        let event_type = event.get("type").and_then(Value::as_str).unwrap_or("");
+        for entry in sinks.iter_mut() {
+            if entry.admit(event_type) {
+                // Sinks are best-effort views; events.jsonl stays the source of truth.
+                let _ = entry.sink.send(event);
+            }
+        }
     }
 }
 
@@ -89,6 +281,7 @@ mod tests {
     use chrono::DateTime;
 
     use super::*;
+    use crate::event_schema::RunFinishedEvent;
 
     #[test]
     fn emit_writes_compact_jsonl_line() -> anyhow::Result<()> {
@@ -133,6 +326,120 @@ mod tests {
         Ok(())
     }
 
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sinks_receive_filtered_and_sampled_events() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("events.jsonl");
+        let writer = EventWriter::new(&path, "run-123");
+        let buffer = SharedBuffer::default();
+        let filter = EventFilter::parse("exclude=context_*;sample=progress:3")?;
+        writer.add_sink(Box::new(JsonLineSink::new(buffer.clone())), filter)?;
+
+        for _ in 0..7 {
+            writer.emit("progress", EventPayload::new())?;
+        }
+        writer.emit("context_window_update", EventPayload::new())?;
+        writer.emit("artifact_created", EventPayload::new())?;
+
+        let full = fs::read_to_string(&path)?;
+        assert_eq!(full.lines().count(), 9);
+
+        let streamed = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let types: Vec<String> = streamed
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter_map(|row| row["type"].as_str().map(str::to_string))
+            .collect();
+        assert_eq!(
+            types,
+            vec!["progress", "progress", "progress", "artifact_created"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn event_filter_parse_handles_include_and_errors() -> anyhow::Result<()> {
+        let filter = EventFilter::parse("include=artifact_*,run_finished")?;
+        assert!(filter.admits_type("artifact_created"));
+        assert!(filter.admits_type("run_finished"));
+        assert!(!filter.admits_type("plan_preview"));
+        assert!(EventFilter::parse("bogus").is_err());
+        assert!(EventFilter::parse("sample=progress").is_err());
+        assert!(EventFilter::parse("colour=red").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn emit_redacts_credentials_before_writing() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("events.jsonl");
+        let writer = EventWriter::new(&path, "run-123");
+
+        let mut payload = EventPayload::new();
+        payload.insert(
+            "settings".to_string(),
+            serde_json::json!({ "provider_options": { "api_key": "sk-secret-123", "steps": 4 } }),
+        );
+        payload.insert(
+            "image_url".to_string(),
+            Value::String(
+                "https://cdn.example.com/a.png?X-Amz-Signature=sk-secret-123".to_string(),
+            ),
+        );
+        let emitted = writer.emit("version_created", payload)?;
+
+        let content = fs::read_to_string(&path)?;
+        assert!(!content.contains("sk-secret-123"));
+        assert_eq!(
+            emitted["settings"]["provider_options"]["api_key"],
+            "<redacted>"
+        );
+        assert_eq!(emitted["settings"]["provider_options"]["steps"], 4);
+        assert_eq!(
+            emitted["image_url"],
+            "https://cdn.example.com/a.png?X-Amz-Signature=<redacted>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn emit_typed_writes_registered_events_and_invalid_ones_still_land() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("events.jsonl");
+        let writer = EventWriter::new(&path, "run-123");
+
+        let emitted = writer.emit_typed(&RunFinishedEvent {
+            summary_path: "/tmp/run/summary.json".to_string(),
+        })?;
+        assert_eq!(emitted["type"], "run_finished");
+        assert_eq!(emitted["summary_path"], "/tmp/run/summary.json");
+
+        // A misspelled field is logged, not fatal: the emit still writes.
+        let mut payload = EventPayload::new();
+        payload.insert(
+            "summary_pth".to_string(),
+            Value::from("/tmp/run/summary.json"),
+        );
+        let emitted = writer.emit("run_finished", payload)?;
+        assert!(validate_event(&emitted).is_err());
+        assert_eq!(fs::read_to_string(&path)?.lines().count(), 2);
+        Ok(())
+    }
+
     #[test]
     fn emit_appends_lines() -> anyhow::Result<()> {
         let temp = tempfile::tempdir()?;
@@ -1,5 +1,9 @@
 pub mod chat;
+pub mod context_scrub;
+pub mod event_schema;
 pub mod events;
 pub mod models;
+pub mod prompt_template;
 pub mod providers;
+pub mod redaction;
 pub mod runs;
@@ -1,5 +1,5 @@
 mod registry;
 mod selectors;
 
-pub use registry::{ModelRegistry, ModelSpec};
+pub use registry::{ModelRegistry, ModelSpec, OPENROUTER_MODEL_PREFIX};
 pub use selectors::{ModelSelection, ModelSelector};
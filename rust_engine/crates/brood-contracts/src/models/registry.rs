@@ -1,5 +1,9 @@
 use indexmap::IndexMap;
 
+/// Prefix of OpenRouter model slugs (`openrouter/google/gemini-2.5-flash-image`);
+/// any such slug is accepted as an image model, registered or not.
+pub const OPENROUTER_MODEL_PREFIX: &str = "openrouter/";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ModelSpec {
     pub name: String,
@@ -28,6 +32,11 @@ impl ModelRegistry {
         }
     }
 
+    /// Adds `spec`, replacing any model with the same name.
+    pub fn register(&mut self, spec: ModelSpec) {
+        self.models.insert(spec.name.clone(), spec);
+    }
+
     pub fn get(&self, name: &str) -> Option<&ModelSpec> {
         self.models.get(name)
     }
@@ -81,7 +90,7 @@ fn default_models() -> IndexMap<String, ModelSpec> {
     insert(
         "dryrun-text-1",
         "dryrun",
-        &["text"],
+        &["text", "vision"],
         Some(8192),
         Some("dryrun-text"),
         Some("dryrun-text"),
@@ -238,6 +247,38 @@ fn default_models() -> IndexMap<String, ModelSpec> {
         Some("stability-sd3-large"),
         Some("stability-sd3-large"),
     );
+    insert(
+        "stable-image-ultra",
+        "stability",
+        &["image", "edit"],
+        None,
+        Some("stability-ultra"),
+        Some("stability-ultra"),
+    );
+    insert(
+        "stable-image-core",
+        "stability",
+        &["image"],
+        None,
+        Some("stability-core"),
+        Some("stability-core"),
+    );
+    insert(
+        "openrouter/google/gemini-2.5-flash-image",
+        "openrouter",
+        &["image"],
+        None,
+        Some("google-gemini-2.5-flash-image"),
+        Some("google-gemini-2.5-flash-image"),
+    );
+    insert(
+        "openrouter/google/gemini-3-pro-image-preview",
+        "openrouter",
+        &["image"],
+        None,
+        Some("google-gemini-3-pro-image-preview"),
+        Some("google-gemini-3-pro-image-preview"),
+    );
     insert(
         "fal-ai/fast-sdxl",
         "fal",
@@ -246,6 +287,22 @@ fn default_models() -> IndexMap<String, ModelSpec> {
         Some("fal-fast-sdxl"),
         Some("fal-fast-sdxl"),
     );
+    insert(
+        "recraft-v3",
+        "recraft",
+        &["image"],
+        None,
+        Some("recraft-v3"),
+        Some("recraft-v3"),
+    );
+    insert(
+        "recraft-v3-svg",
+        "recraft",
+        &["image"],
+        None,
+        Some("recraft-v3-svg"),
+        Some("recraft-v3-svg"),
+    );
 
     map
 }
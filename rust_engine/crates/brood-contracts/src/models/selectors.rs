@@ -1,4 +1,4 @@
-use super::registry::{ModelRegistry, ModelSpec};
+use super::registry::{ModelRegistry, ModelSpec, OPENROUTER_MODEL_PREFIX};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ModelSelection {
@@ -32,6 +32,13 @@ impl ModelSelector {
                     fallback_reason: None,
                 });
             }
+            if let Some(model) = openrouter_image_model(requested_value, capability) {
+                return Ok(ModelSelection {
+                    model,
+                    requested: Some(requested_value.to_string()),
+                    fallback_reason: None,
+                });
+            }
             (
                 Some(format!(
                     "Requested model '{requested_value}' unavailable for capability '{capability}'."
@@ -55,3 +62,20 @@ impl ModelSelector {
         })
     }
 }
+
+/// An unregistered `openrouter/<slug>` image model, served by the
+/// `openrouter` provider without pricing or latency data.
+fn openrouter_image_model(requested: &str, capability: &str) -> Option<ModelSpec> {
+    let slug = requested.trim().strip_prefix(OPENROUTER_MODEL_PREFIX)?;
+    if capability != "image" || slug.trim().is_empty() {
+        return None;
+    }
+    Some(ModelSpec {
+        name: requested.trim().to_string(),
+        provider: "openrouter".to_string(),
+        capabilities: vec!["image".to_string()],
+        context_window: None,
+        pricing_key: None,
+        latency_key: None,
+    })
+}
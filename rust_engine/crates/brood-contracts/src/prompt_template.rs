@@ -0,0 +1,239 @@
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
+
+/// Upper bound on combinations produced by [`expand_prompt_template`], so a
+/// few list-valued variables cannot fan out into hundreds of paid requests.
+pub const MAX_TEMPLATE_EXPANSIONS: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Variable(String),
+}
+
+/// A prompt with `{{name}}` placeholders. Whitespace inside the braces is
+/// ignored; there is no escaping or nesting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    segments: Vec<Segment>,
+}
+
+impl PromptTemplate {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let Some(end) = after.find("}}") else {
+                bail!("unterminated '{{{{' in prompt template");
+            };
+            let name = after[..end].trim();
+            if !is_variable_name(name) {
+                bail!("invalid template variable '{{{{{}}}}}'", &after[..end]);
+            }
+            segments.push(Segment::Variable(name.to_string()));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Variable names in order of first use.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for segment in &self.segments {
+            if let Segment::Variable(name) = segment {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    pub fn render(&self, values: &Map<String, Value>) -> Result<String> {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Variable(name) => match values.get(name) {
+                    Some(value) => out.push_str(&scalar_text(name, value)?),
+                    None => bail!("template variable '{name}' has no value"),
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// One rendered prompt and the variable values that produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptExpansion {
+    pub prompt: String,
+    pub bindings: Map<String, Value>,
+}
+
+/// Renders `template` once per combination of list-valued variables (the
+/// cartesian product, earlier variables varying slowest). Scalar variables
+/// apply to every combination; variables the template does not use are
+/// ignored.
+pub fn expand_prompt_template(
+    template: &str,
+    variables: &Map<String, Value>,
+) -> Result<Vec<PromptExpansion>> {
+    let parsed = PromptTemplate::parse(template)?;
+    let mut axes: Vec<(&str, Vec<Value>)> = Vec::new();
+    for name in parsed.variables() {
+        let options = match variables.get(name) {
+            Some(Value::Array(options)) if options.is_empty() => {
+                bail!("template variable '{name}' has an empty list of values")
+            }
+            Some(Value::Array(options)) => options.clone(),
+            Some(value) => vec![value.clone()],
+            None => bail!("template variable '{name}' has no value"),
+        };
+        axes.push((name, options));
+    }
+    let total = axes
+        .iter()
+        .try_fold(1usize, |acc, (_, options)| acc.checked_mul(options.len()))
+        .filter(|total| *total <= MAX_TEMPLATE_EXPANSIONS);
+    let Some(total) = total else {
+        bail!("prompt template expands to more than {MAX_TEMPLATE_EXPANSIONS} combinations");
+    };
+
+    let mut expansions = Vec::with_capacity(total);
+    for index in 0..total {
+        let mut bindings = Map::new();
+        let mut remainder = index;
+        for (name, options) in axes.iter().rev() {
+            bindings.insert(name.to_string(), options[remainder % options.len()].clone());
+            remainder /= options.len();
+        }
+        expansions.push(PromptExpansion {
+            prompt: parsed.render(&bindings)?,
+            bindings,
+        });
+    }
+    Ok(expansions)
+}
+
+/// Parses `name=value` or `name=a,b,c` (a list, for combinatorial
+/// expansion). Used by `--var` and `/vars`.
+pub fn parse_variable_assignment(text: &str) -> Result<(String, Value)> {
+    let Some((name, raw)) = text.split_once('=') else {
+        bail!("expected name=value, got '{text}'");
+    };
+    let name = name.trim();
+    if !is_variable_name(name) {
+        bail!("invalid template variable name '{name}'");
+    }
+    let options: Vec<Value> = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|option| !option.is_empty())
+        .map(|option| Value::String(option.to_string()))
+        .collect();
+    let value = match options.len() {
+        0 => bail!("template variable '{name}' has no value"),
+        1 => options.into_iter().next().unwrap_or(Value::Null),
+        _ => Value::Array(options),
+    };
+    Ok((name.to_string(), value))
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+}
+
+fn scalar_text(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(flag) => Ok(flag.to_string()),
+        _ => bail!("template variable '{name}' must be a string, number or bool"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::{expand_prompt_template, parse_variable_assignment, PromptTemplate};
+
+    fn vars(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn template_renders_and_expands_combinations() -> anyhow::Result<()> {
+        let template = PromptTemplate::parse("a {{ style }} photo of {{subject}}, {{style}}")?;
+        assert_eq!(template.variables(), vec!["style", "subject"]);
+        assert_eq!(
+            template.render(&vars(json!({"style": "noir", "subject": "a fox"})))?,
+            "a noir photo of a fox, noir"
+        );
+
+        let expansions = expand_prompt_template(
+            "a {{style}} photo of {{subject}} #{{n}}",
+            &vars(json!({
+                "style": ["noir", "pastel"],
+                "subject": ["cat", "dog", "owl"],
+                "n": 3,
+                "unused": ["x", "y"],
+            })),
+        )?;
+        let prompts: Vec<&str> = expansions.iter().map(|row| row.prompt.as_str()).collect();
+        assert_eq!(prompts.len(), 6);
+        assert_eq!(prompts[0], "a noir photo of cat #3");
+        assert_eq!(prompts[1], "a noir photo of dog #3");
+        assert_eq!(prompts[5], "a pastel photo of owl #3");
+        assert_eq!(
+            Value::Object(expansions[4].bindings.clone()),
+            json!({"style": "pastel", "subject": "dog", "n": 3})
+        );
+
+        let plain = expand_prompt_template("no placeholders", &Map::new())?;
+        assert_eq!(plain.len(), 1);
+        assert_eq!(plain[0].prompt, "no placeholders");
+        Ok(())
+    }
+
+    #[test]
+    fn template_errors_are_reported() {
+        assert!(PromptTemplate::parse("a {{style photo").is_err());
+        assert!(PromptTemplate::parse("a {{}} photo").is_err());
+        assert!(expand_prompt_template("a {{style}}", &Map::new()).is_err());
+        assert!(expand_prompt_template("a {{style}}", &vars(json!({"style": []}))).is_err());
+        assert!(expand_prompt_template("a {{style}}", &vars(json!({"style": {"k": 1}}))).is_err());
+        let wide: Vec<Value> = (0..9).map(|idx| json!(idx)).collect();
+        assert!(expand_prompt_template(
+            "{{a}} {{b}}",
+            &vars(json!({"a": wide.clone(), "b": wide}))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn variable_assignments_parse_lists() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_variable_assignment("style=noir, pastel")?,
+            ("style".to_string(), json!(["noir", "pastel"]))
+        );
+        assert_eq!(
+            parse_variable_assignment("subject = red fox")?,
+            ("subject".to_string(), json!("red fox"))
+        );
+        assert!(parse_variable_assignment("style").is_err());
+        assert!(parse_variable_assignment("bad name=x").is_err());
+        Ok(())
+    }
+}
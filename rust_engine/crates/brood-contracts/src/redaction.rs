@@ -0,0 +1,216 @@
+use serde_json::{Map, Value};
+
+/// Replacement for credential values.
+pub const REDACTED: &str = "<redacted>";
+
+/// Strings longer than this are cut when written to receipts and events.
+pub const MAX_PERSISTED_STRING_CHARS: usize = 8192;
+
+/// Base64 runs at least this long are treated as inline binary.
+const MIN_INLINE_BINARY_CHARS: usize = 1024;
+
+/// Object keys whose values are credentials. Bare `key` is only treated as a
+/// secret in query strings, since manifests use it as a field label.
+pub fn is_secret_key(key: &str) -> bool {
+    let lowered = key.trim().to_ascii_lowercase().replace('-', "_");
+    matches!(
+        lowered.as_str(),
+        "token" | "authorization" | "proxy_authorization" | "cookie" | "set_cookie"
+    ) || lowered.ends_with("api_key")
+        || lowered.ends_with("apikey")
+        || lowered.ends_with("_token")
+        || lowered.contains("secret")
+        || lowered.contains("password")
+}
+
+fn is_secret_query_param(key: &str) -> bool {
+    let lowered = key.trim().to_ascii_lowercase();
+    matches!(lowered.as_str(), "key" | "sig" | "signature")
+        || lowered.ends_with("-signature")
+        || is_secret_key(&lowered)
+}
+
+/// Masks credential query parameters in an `http(s)` URL.
+pub fn redact_url(url: &str) -> String {
+    let trimmed = url.trim_start();
+    if !(trimmed.starts_with("http://") || trimmed.starts_with("https://")) {
+        return url.to_string();
+    }
+    let Some((base, rest)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let (query, fragment) = match rest.split_once('#') {
+        Some((query, fragment)) => (query, Some(fragment)),
+        None => (rest, None),
+    };
+    let query = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if is_secret_query_param(key) => format!("{key}={REDACTED}"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    match fragment {
+        Some(fragment) => format!("{base}?{query}#{fragment}"),
+        None => format!("{base}?{query}"),
+    }
+}
+
+/// Masks credential query parameters (`?key=...`, `&token=...`) wherever
+/// they appear in free text, such as an error message quoting a URL.
+pub fn redact_query_secrets(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find(['?', '&']) {
+        out.push_str(&rest[..=idx]);
+        rest = &rest[idx + 1..];
+        let name_len = rest
+            .find(|ch: char| !(ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.')))
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(name_len);
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        if name.is_empty() || !is_secret_query_param(name) || value.starts_with(REDACTED) {
+            continue;
+        }
+        let value_len = value
+            .find(|ch: char| ch.is_whitespace() || matches!(ch, '&' | '#' | '"' | '\'' | ')' | '>'))
+            .unwrap_or(value.len());
+        out.push_str(name);
+        out.push('=');
+        out.push_str(REDACTED);
+        rest = &value[value_len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Applies [`redact_secrets_capped`] with [`MAX_PERSISTED_STRING_CHARS`].
+pub fn redact_secrets(value: &Value) -> Value {
+    redact_secrets_capped(value, MAX_PERSISTED_STRING_CHARS)
+}
+
+/// Redacts credential keys and URL query secrets, replaces data URLs and
+/// long base64 runs with a size note, and cuts any other string to
+/// `max_string_chars`.
+pub fn redact_secrets_capped(value: &Value, max_string_chars: usize) -> Value {
+    match value {
+        Value::String(text) => Value::String(redact_string(text, max_string_chars)),
+        Value::Array(rows) => Value::Array(
+            rows.iter()
+                .map(|row| redact_secrets_capped(row, max_string_chars))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(redact_map(map, max_string_chars)),
+        other => other.clone(),
+    }
+}
+
+/// [`redact_secrets`] over an event or receipt payload map.
+pub fn redact_map(map: &Map<String, Value>, max_string_chars: usize) -> Map<String, Value> {
+    map.iter()
+        .map(|(key, row)| {
+            let row = if is_secret_key(key) && !row.is_null() {
+                Value::String(REDACTED.to_string())
+            } else {
+                redact_secrets_capped(row, max_string_chars)
+            };
+            (key.clone(), row)
+        })
+        .collect()
+}
+
+fn redact_string(text: &str, max_chars: usize) -> String {
+    if let Some(rest) = text.strip_prefix("data:") {
+        if let Some((header, payload)) = rest.split_once(',') {
+            if header.ends_with(";base64") {
+                return format!(
+                    "<data:{} omitted, {} chars>",
+                    header.trim_end_matches(";base64"),
+                    payload.len()
+                );
+            }
+        }
+    }
+    if text.len() >= MIN_INLINE_BINARY_CHARS && looks_like_base64(text) {
+        return format!("<base64 omitted, {} chars>", text.len());
+    }
+    let text = redact_query_secrets(text);
+    let chars = text.chars().count();
+    if chars <= max_chars {
+        return text;
+    }
+    let head: String = text.chars().take(max_chars).collect();
+    format!("{head}<{} more chars cut>", chars - max_chars)
+}
+
+fn looks_like_base64(text: &str) -> bool {
+    text.bytes().all(|byte| {
+        byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'/' | b'=' | b'-' | b'_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{
+        redact_query_secrets, redact_secrets, redact_secrets_capped, redact_url, REDACTED,
+    };
+
+    #[test]
+    fn secrets_binary_and_oversized_strings_are_scrubbed() {
+        let scrubbed = redact_secrets(&json!({
+            "endpoint": "https://proxy.example.com/v1/images?api-version=2&api_key=sk-live#frag",
+            "headers": { "X-API-Key": "sk-live", "Authorization": "Bearer sk-live" },
+            "access_token": "tok",
+            "key": "image",
+            "prompt_tokens": 12,
+            "init_image": format!("data:image/png;base64,{}", "iVBOR".repeat(40)),
+            "raw": "QUJD".repeat(400),
+            "prompt": "dunes at dusk ".repeat(650),
+            "error": "request failed (https://g.example.com/x?alt=json&key=AIza-live): timeout",
+        }));
+        assert_eq!(
+            scrubbed["endpoint"],
+            json!("https://proxy.example.com/v1/images?api-version=2&api_key=<redacted>#frag")
+        );
+        assert_eq!(scrubbed["headers"]["X-API-Key"], json!(REDACTED));
+        assert_eq!(scrubbed["headers"]["Authorization"], json!(REDACTED));
+        assert_eq!(scrubbed["access_token"], json!(REDACTED));
+        assert_eq!(scrubbed["key"], json!("image"));
+        assert_eq!(scrubbed["prompt_tokens"], json!(12));
+        assert_eq!(
+            scrubbed["init_image"],
+            json!("<data:image/png omitted, 200 chars>")
+        );
+        assert_eq!(scrubbed["raw"], json!("<base64 omitted, 1600 chars>"));
+        assert!(scrubbed["prompt"]
+            .as_str()
+            .unwrap_or_default()
+            .ends_with("<908 more chars cut>"));
+        assert!(!scrubbed.to_string().contains("sk-live"));
+        assert_eq!(
+            scrubbed["error"],
+            json!("request failed (https://g.example.com/x?alt=json&key=<redacted>): timeout")
+        );
+        assert_eq!(
+            redact_query_secrets("retry with ?token=abc123 or &monkey=1 and key=plain"),
+            "retry with ?token=<redacted> or &monkey=1 and key=plain"
+        );
+        let once = redact_query_secrets("https://x.example/?sig=abc");
+        assert_eq!(redact_query_secrets(&once), once);
+
+        assert_eq!(
+            redact_url("https://g.example.com/m:generate?key=AIza&alt=json"),
+            "https://g.example.com/m:generate?key=<redacted>&alt=json"
+        );
+        assert_eq!(redact_url("/tmp/out.png?key=1"), "/tmp/out.png?key=1");
+        assert_eq!(
+            redact_secrets_capped(&json!("abcdef"), 3),
+            json!("abc<3 more chars cut>")
+        );
+    }
+}
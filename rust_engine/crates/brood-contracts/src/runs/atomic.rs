@@ -0,0 +1,248 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::Value;
+
+use super::layout::is_version_dir_name;
+use super::thread_manifest::ThreadManifest;
+
+/// Run-dir JSON files that can be rebuilt or started over, so
+/// [`recover_run_dir`] may move a torn one aside. Receipts and
+/// `thread.json` are the run's record and are never quarantined blindly.
+const DISPOSABLE_JSON_FILES: [&str; 3] = ["cache.json", "summary.json", "session.json"];
+
+/// Writes `bytes` to a staging file next to `path`, fsyncs it, then renames
+/// it over `path`. A crash leaves either the old file or the new one, never
+/// a torn mix; the leftover staging file is ignored by every reader.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+    let staging = staging_path(path);
+    let written = File::create(&staging).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|()| fs::rename(&staging, path)) {
+        let _ = fs::remove_file(&staging);
+        anyhow::bail!("failed to write {}: {err}", path.display());
+    }
+    sync_dir(parent);
+    Ok(())
+}
+
+/// [`write_atomic`] for pretty-printed JSON, the format every run file uses.
+pub fn write_json_atomic(path: &Path, payload: &Value) -> anyhow::Result<()> {
+    write_atomic(path, serde_json::to_string_pretty(payload)?.as_bytes())
+}
+
+/// Reads a JSON run file. A missing file is `None`; one that no longer
+/// parses (torn by a crash under an older build, or edited by hand) is an
+/// error. Reading never moves anything; see [`recover_run_dir`].
+pub fn read_json(path: &Path) -> anyhow::Result<Option<Value>> {
+    let raw = match fs::read(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => anyhow::bail!("failed to read {}: {err}", path.display()),
+    };
+    serde_json::from_slice(&raw)
+        .map(Some)
+        .map_err(|err| anyhow::anyhow!("{} is not valid JSON: {err}", path.display()))
+}
+
+/// Renames `path` to `<name>.corrupt-<timestamp>` beside it.
+pub fn quarantine(path: &Path) -> anyhow::Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".corrupt-{}",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%3fZ")
+    ));
+    let target = path.with_file_name(name);
+    fs::rename(path, &target)
+        .map_err(|err| anyhow::anyhow!("failed to quarantine {}: {err}", path.display()))?;
+    Ok(target)
+}
+
+/// A run file that failed to parse and was moved aside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedFile {
+    pub path: PathBuf,
+    pub moved_to: PathBuf,
+    /// For `thread.json`: how many versions the replacement was rebuilt
+    /// with.
+    pub rebuilt_versions: Option<usize>,
+}
+
+/// Repairs `run_dir` before anything loads it. Must run under the run lock.
+///
+/// A torn `thread.json` is rebuilt with [`ThreadManifest::rebuild`] and the
+/// original moved aside; if nothing survives to rebuild it from, the run
+/// dir is refused rather than reopened empty. Torn cache, summary and
+/// session files are quarantined. Staging files left by a crashed writer
+/// are removed. Torn receipts are left in place for `verify` to report.
+pub fn recover_run_dir(run_dir: &Path) -> anyhow::Result<Vec<QuarantinedFile>> {
+    remove_stale_staging_files(run_dir)?;
+    let mut quarantined = Vec::new();
+    let thread_path = run_dir.join("thread.json");
+    if read_json(&thread_path).is_err() {
+        let Some(rebuilt) = ThreadManifest::rebuild(&thread_path)? else {
+            anyhow::bail!(
+                "{} is not valid JSON and no version.json files or receipts are left to rebuild it from; restore it from a backup",
+                thread_path.display()
+            );
+        };
+        let moved_to = quarantine(&thread_path)?;
+        rebuilt.save()?;
+        quarantined.push(QuarantinedFile {
+            path: thread_path,
+            moved_to,
+            rebuilt_versions: Some(rebuilt.versions.len()),
+        });
+    }
+    for name in DISPOSABLE_JSON_FILES {
+        let path = run_dir.join(name);
+        if read_json(&path).is_ok() {
+            continue;
+        }
+        let moved_to = quarantine(&path)?;
+        quarantined.push(QuarantinedFile {
+            path,
+            moved_to,
+            rebuilt_versions: None,
+        });
+    }
+    Ok(quarantined)
+}
+
+/// Removes staging files [`write_atomic`] left behind in `run_dir` and its
+/// version subdirectories when a process died mid-write. Files staged by
+/// this process may still be in flight and are kept.
+fn remove_stale_staging_files(run_dir: &Path) -> anyhow::Result<()> {
+    let own_suffix = format!(".tmp-{}-", std::process::id());
+    let mut dirs = vec![run_dir.to_path_buf()];
+    for entry in fs::read_dir(run_dir)?.flatten() {
+        let name = entry.file_name();
+        if is_version_dir_name(&name.to_string_lossy()) && entry.path().is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    for dir in dirs {
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if is_staging_name(&name) && !name.contains(&own_suffix) {
+                fs::remove_file(entry.path()).map_err(|err| {
+                    anyhow::anyhow!("failed to remove {}: {err}", entry.path().display())
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Matches the names [`staging_path`] hands out.
+fn is_staging_name(name: &str) -> bool {
+    let Some((_, suffix)) = name
+        .strip_prefix('.')
+        .and_then(|rest| rest.rsplit_once(".tmp-"))
+    else {
+        return false;
+    };
+    suffix
+        .split_once('-')
+        .is_some_and(|(pid, n)| is_digits(pid) && is_digits(n))
+}
+
+fn is_digits(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `.<name>.tmp-<pid>-<n>`: hidden, so listings and receipt scans skip it,
+/// and unique per write, so threads sharing a file (batch rows share one
+/// `cache.json`) never write into each other's staging file.
+fn staging_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(
+        ".tmp-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+/// Makes the rename itself durable. Directories cannot be opened for sync
+/// on Windows, where the rename is already journaled.
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(handle) = File::open(dir) {
+        let _ = handle.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::{read_json, recover_run_dir, write_json_atomic};
+    use crate::runs::thread_manifest::ThreadManifest;
+
+    #[test]
+    fn atomic_writes_replace_whole_files_and_recovery_only_moves_disposable_files(
+    ) -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let thread_path = run_dir.join("thread.json");
+        write_json_atomic(&thread_path, &json!({"thread_id": "t1", "versions": []}))?;
+        write_json_atomic(&thread_path, &json!({"thread_id": "t2", "versions": []}))?;
+        assert_eq!(ThreadManifest::load(&thread_path)?.thread_id, "t2");
+        let names: Vec<String> = fs::read_dir(&run_dir)?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, ["thread.json"]);
+
+        // Reads report a torn file without touching it.
+        let session = run_dir.join("session.json");
+        fs::write(&session, "{\"disabled")?;
+        assert!(read_json(&session).is_err());
+        assert!(session.is_file());
+        assert_eq!(read_json(&run_dir.join("missing.json"))?, None);
+
+        let receipt = run_dir.join("receipt-v1-01.json");
+        fs::write(&receipt, "{")?;
+        fs::write(run_dir.join("cache.json"), "{}")?;
+        fs::write(run_dir.join(".cache.json.tmp-999999999-0"), "{")?;
+        let quarantined = recover_run_dir(&run_dir)?;
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].path, session);
+        assert!(quarantined[0]
+            .moved_to
+            .to_string_lossy()
+            .contains("session.json.corrupt-"));
+        assert!(quarantined[0].moved_to.is_file());
+        assert!(receipt.is_file());
+        assert!(run_dir.join("cache.json").is_file());
+        assert!(!run_dir.join(".cache.json.tmp-999999999-0").exists());
+        assert!(recover_run_dir(&run_dir)?.is_empty());
+
+        // A torn thread.json with nothing to rebuild it from is refused.
+        fs::remove_file(&receipt)?;
+        fs::write(&thread_path, r#"{"thread_id": "t2", "vers"#)?;
+        let err = recover_run_dir(&run_dir).expect_err("torn thread");
+        assert!(
+            err.to_string().contains("restore it from a backup"),
+            "{err}"
+        );
+        assert!(thread_path.is_file());
+        Ok(())
+    }
+}
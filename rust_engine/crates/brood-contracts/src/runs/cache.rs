@@ -2,6 +2,13 @@ use std::path::{Path, PathBuf};
 
 use serde_json::{Map, Value};
 
+use super::atomic::{read_json, write_json_atomic};
+
+/// Layout version stamped into `cache.json` under `schema_version`, the one
+/// top-level key that is not a cache entry.
+pub const CACHE_SCHEMA_VERSION: u64 = 1;
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
 #[derive(Debug, Clone)]
 pub struct CacheStore {
     path: PathBuf,
@@ -22,11 +29,7 @@ impl CacheStore {
 
     pub fn get(&mut self, key: &str) -> Option<Map<String, Value>> {
         let payload = self.ensure_loaded(true);
-        payload
-            .get(key)
-            .and_then(Value::as_object)
-            .cloned()
-            .map(|value| value.clone())
+        payload.get(key).and_then(Value::as_object).cloned()
     }
 
     pub fn set(&mut self, key: &str, value: Map<String, Value>) -> anyhow::Result<()> {
@@ -43,12 +46,40 @@ impl CacheStore {
         self.flush()
     }
 
+    /// Drops on-disk entries for which `keep` returns false and returns their
+    /// keys. Pending writes are flushed first so they are filtered too.
+    pub fn retain(
+        &mut self,
+        mut keep: impl FnMut(&str, &Map<String, Value>) -> bool,
+    ) -> anyhow::Result<Vec<String>> {
+        self.flush()?;
+        let mut on_disk = read_json_object(&self.path).unwrap_or_default();
+        let removed: Vec<String> = on_disk
+            .iter()
+            .filter(|(key, _)| key.as_str() != SCHEMA_VERSION_KEY)
+            .filter(|(key, value)| !value.as_object().is_some_and(|entry| keep(key, entry)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !removed.is_empty() {
+            for key in &removed {
+                on_disk.remove(key);
+            }
+            write_json_object(&self.path, &on_disk)?;
+        }
+        self.payload = Some(on_disk);
+        Ok(removed)
+    }
+
     pub fn flush(&mut self) -> anyhow::Result<()> {
         if self.payload.is_none() || !self.dirty || self.dirty_keys.is_empty() {
             return Ok(());
         }
 
         let mut on_disk = read_json_object(&self.path).unwrap_or_default();
+        on_disk.insert(
+            SCHEMA_VERSION_KEY.to_string(),
+            Value::Number(CACHE_SCHEMA_VERSION.into()),
+        );
         if let Some(payload) = &self.payload {
             for key in &self.dirty_keys {
                 if let Some(value) = payload.get(key) {
@@ -72,20 +103,11 @@ impl CacheStore {
 }
 
 fn read_json_object(path: &Path) -> Option<Map<String, Value>> {
-    let raw = std::fs::read_to_string(path).ok()?;
-    let parsed: Value = serde_json::from_str(&raw).ok()?;
-    parsed.as_object().cloned()
+    read_json(path).ok()??.as_object().cloned()
 }
 
 fn write_json_object(path: &Path, payload: &Map<String, Value>) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    std::fs::write(
-        path,
-        serde_json::to_string_pretty(&Value::Object(payload.clone()))?,
-    )?;
-    Ok(())
+    write_json_atomic(path, &Value::Object(payload.clone()))
 }
 
 #[cfg(test)]
@@ -194,4 +216,20 @@ mod tests {
         assert_eq!(reloaded.get("key"), Some(obj(json!({"value": 1}))));
         Ok(())
     }
+
+    #[test]
+    fn cache_retain_removes_rejected_entries_on_disk() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("cache.json");
+        let mut cache = CacheStore::new(&path);
+        cache.set("keep", obj(json!({"value": 1})))?;
+        cache.set("drop", obj(json!({"value": 2})))?;
+
+        let removed = cache.retain(|_, entry| entry.get("value") == Some(&json!(1)))?;
+        assert_eq!(removed, vec!["drop".to_string()]);
+        let mut reloaded = CacheStore::new(path);
+        assert_eq!(reloaded.get("drop"), None);
+        assert_eq!(reloaded.get("keep"), Some(obj(json!({"value": 1}))));
+        Ok(())
+    }
 }
@@ -0,0 +1,305 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+
+use super::lock::RunLock;
+use super::thread_manifest::{ThreadManifest, VersionEntry};
+
+/// Which artifacts [`collect_garbage`] may delete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Artifacts whose files were written within this many days are kept.
+    pub keep_days: u64,
+    /// Keep selected artifacts and artifacts rated `winner`, however old.
+    pub keep_winners: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrunedArtifact {
+    pub run_dir: PathBuf,
+    pub version_id: String,
+    pub artifact_id: String,
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub runs_scanned: usize,
+    pub pruned: Vec<PrunedArtifact>,
+    /// Artifacts past the cutoff that were kept because they are winners.
+    pub winners_kept: usize,
+    pub dry_run: bool,
+}
+
+impl GcReport {
+    /// Bytes freed, or that would be freed in a dry run.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.pruned.iter().map(|artifact| artifact.bytes).sum()
+    }
+}
+
+/// Applies `policy` to every run dir directly under `runs_dir` (or to
+/// `runs_dir` itself when it holds a `thread.json`).
+///
+/// Pruning deletes an artifact's media file, its receipt and any HTTP trace
+/// the receipt links, then marks the artifact in `thread.json` with
+/// `pruned_at` so history stays intact. Each run is locked while it is
+/// pruned, and a run another process has open is an error. A dry run only
+/// reports.
+pub fn collect_garbage(
+    runs_dir: &Path,
+    policy: RetentionPolicy,
+    now: SystemTime,
+    dry_run: bool,
+) -> anyhow::Result<GcReport> {
+    if !runs_dir.is_dir() {
+        anyhow::bail!("runs dir {} does not exist", runs_dir.display());
+    }
+    let mut run_dirs: Vec<PathBuf> = if runs_dir.join("thread.json").is_file() {
+        vec![runs_dir.to_path_buf()]
+    } else {
+        std::fs::read_dir(runs_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join("thread.json").is_file())
+            .collect()
+    };
+    run_dirs.sort();
+
+    let cutoff = now
+        .checked_sub(Duration::from_secs(policy.keep_days.saturating_mul(86_400)))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut report = GcReport {
+        dry_run,
+        ..GcReport::default()
+    };
+    for run_dir in run_dirs {
+        report.runs_scanned += 1;
+        gc_run(&run_dir, policy, cutoff, dry_run, &mut report)?;
+    }
+    Ok(report)
+}
+
+pub fn is_pruned(artifact: &Map<String, Value>) -> bool {
+    artifact
+        .get("pruned_at")
+        .is_some_and(|value| !value.is_null())
+}
+
+fn gc_run(
+    run_dir: &Path,
+    policy: RetentionPolicy,
+    cutoff: SystemTime,
+    dry_run: bool,
+    report: &mut GcReport,
+) -> anyhow::Result<()> {
+    // An open engine would save its own thread.json over the pruned marks.
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(
+            RunLock::acquire(run_dir)
+                .with_context(|| format!("cannot prune {} while it is open", run_dir.display()))?,
+        )
+    };
+    let mut thread = ThreadManifest::load(run_dir.join("thread.json"))?;
+    let pruned_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, false);
+    let mut changed = false;
+    for version in &mut thread.versions {
+        let winners = winner_ids(version);
+        for artifact in &mut version.artifacts {
+            if is_pruned(artifact) {
+                continue;
+            }
+            let files = artifact_files(artifact);
+            let newest = files
+                .iter()
+                .filter_map(|path| path.metadata().and_then(|meta| meta.modified()).ok())
+                .max();
+            if newest.is_none_or(|modified| modified >= cutoff) {
+                continue;
+            }
+            let artifact_id = artifact
+                .get("artifact_id")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            if policy.keep_winners && winners.contains(&artifact_id) {
+                report.winners_kept += 1;
+                continue;
+            }
+            let mut bytes = 0;
+            for path in &files {
+                let Ok(meta) = path.metadata() else {
+                    continue;
+                };
+                bytes += meta.len();
+                if !dry_run {
+                    std::fs::remove_file(path).map_err(|err| {
+                        anyhow::anyhow!("failed to remove {}: {err}", path.display())
+                    })?;
+                }
+            }
+            if !dry_run {
+                artifact.insert("pruned_at".to_string(), Value::String(pruned_at.clone()));
+                artifact.insert("pruned_bytes".to_string(), Value::from(bytes));
+                changed = true;
+            }
+            report.pruned.push(PrunedArtifact {
+                run_dir: run_dir.to_path_buf(),
+                version_id: version.version_id.clone(),
+                artifact_id,
+                files,
+                bytes,
+            });
+        }
+    }
+    if changed {
+        thread.save()?;
+    }
+    Ok(())
+}
+
+fn winner_ids(version: &VersionEntry) -> Vec<String> {
+    version
+        .selected_artifact_id
+        .iter()
+        .cloned()
+        .chain(version.feedback.iter().filter_map(|feedback| {
+            (feedback.get("rating").and_then(Value::as_str) == Some("winner"))
+                .then(|| feedback.get("artifact_id").and_then(Value::as_str))
+                .flatten()
+                .map(str::to_string)
+        }))
+        .collect()
+}
+
+/// Media file, thumbnail, receipt and the HTTP trace the receipt links, if
+/// present.
+fn artifact_files(artifact: &Map<String, Value>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = ["image_path", "video_path", "thumbnail_path", "receipt_path"]
+        .iter()
+        .filter_map(|key| artifact.get(*key).and_then(Value::as_str))
+        .map(PathBuf::from)
+        .filter(|path| path.is_file())
+        .collect();
+    let trace = artifact
+        .get("receipt_path")
+        .and_then(Value::as_str)
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+        .and_then(|receipt| {
+            receipt
+                .pointer("/artifacts/http_trace")
+                .and_then(Value::as_str)
+                .map(PathBuf::from)
+        });
+    files.extend(trace.filter(|path| path.is_file()));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    use serde_json::{json, Map, Value};
+
+    use super::{collect_garbage, is_pruned, RetentionPolicy};
+    use crate::runs::lock::RunLock;
+    use crate::runs::thread_manifest::ThreadManifest;
+    use crate::runs::verify::verify_run;
+
+    #[test]
+    fn gc_prunes_old_artifacts_but_keeps_winners_and_recent_files() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run-a");
+        fs::create_dir_all(&run_dir)?;
+        let now = SystemTime::now();
+        let old = now - Duration::from_secs(40 * 86_400);
+
+        let mut thread = ThreadManifest::new(run_dir.join("thread.json"));
+        let version = thread.add_version(Map::new(), Map::new(), "dunes".to_string(), None);
+        for (id, modified) in [("old", old), ("winner", old), ("fresh", now)] {
+            let image = run_dir.join(format!("artifact-{id}.png"));
+            let receipt = run_dir.join(format!("receipt-{id}.json"));
+            fs::write(&image, vec![0_u8; 100])?;
+            fs::write(&receipt, "{}")?;
+            for path in [&image, &receipt] {
+                File::options()
+                    .write(true)
+                    .open(path)?
+                    .set_modified(modified)?;
+            }
+            let artifact = json!({
+                "artifact_id": id,
+                "image_path": image.to_string_lossy(),
+                "receipt_path": receipt.to_string_lossy(),
+            });
+            if let Value::Object(artifact) = artifact {
+                thread.add_artifact(&version.version_id, artifact);
+            }
+        }
+        thread.select_artifact(&version.version_id, "winner", Some("best dunes"));
+        thread.save()?;
+        let policy = RetentionPolicy {
+            keep_days: 30,
+            keep_winners: true,
+        };
+
+        let preview = collect_garbage(temp.path(), policy, now, true)?;
+        assert_eq!(preview.runs_scanned, 1);
+        assert_eq!(preview.pruned.len(), 1);
+        assert_eq!(preview.pruned[0].artifact_id, "old");
+        assert_eq!(preview.reclaimable_bytes(), 102);
+        assert_eq!(preview.winners_kept, 1);
+        assert!(run_dir.join("artifact-old.png").is_file());
+
+        let report = collect_garbage(temp.path(), policy, now, false)?;
+        assert_eq!(report.reclaimable_bytes(), 102);
+        assert!(!run_dir.join("artifact-old.png").exists());
+        assert!(!run_dir.join("receipt-old.json").exists());
+        assert!(run_dir.join("artifact-winner.png").is_file());
+        assert!(run_dir.join("artifact-fresh.png").is_file());
+
+        let reloaded = ThreadManifest::load(run_dir.join("thread.json"))?;
+        let pruned: Vec<bool> = reloaded.versions[0]
+            .artifacts
+            .iter()
+            .map(is_pruned)
+            .collect();
+        assert_eq!(pruned, vec![true, false, false]);
+        let verified = verify_run(&run_dir);
+        assert!(verified
+            .errors
+            .iter()
+            .all(|issue| !issue.message.contains("artifact old")));
+        assert!(verified.notes.iter().any(|issue| issue.code == "pruned"));
+
+        let again = collect_garbage(temp.path(), policy, now, false)?;
+        assert!(again.pruned.is_empty());
+
+        let lock = RunLock::acquire(&run_dir)?;
+        let err = collect_garbage(temp.path(), policy, now, false).expect_err("run is open");
+        assert!(format!("{err:#}").contains("in use by pid"), "{err:#}");
+        assert!(collect_garbage(temp.path(), policy, now, true).is_ok());
+        drop(lock);
+
+        let without_winners = collect_garbage(
+            temp.path(),
+            RetentionPolicy {
+                keep_days: 30,
+                keep_winners: false,
+            },
+            now,
+            false,
+        )?;
+        assert_eq!(without_winners.pruned.len(), 1);
+        assert_eq!(without_winners.pruned[0].artifact_id, "winner");
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use serde_json::{json, Value};
+
+use super::atomic::{read_json, write_json_atomic};
+use super::run_dir::slugify;
+use super::thread_manifest::ThreadManifest;
+
+/// Written into each version's subdirectory under [`RunLayout::PerVersion`]:
+/// that version's `thread.json` entry on its own.
+pub const VERSION_FILENAME: &str = "version.json";
+
+/// Written in the run dir while [`migrate_to_per_version`] moves files, so
+/// an interrupted migration is undone, or finished once `thread.json` has
+/// been saved, on the next run.
+pub const MIGRATION_JOURNAL: &str = "layout-migration.json";
+
+/// Where a run dir keeps its artifacts and receipts. Recorded in
+/// `thread.json` as `layout`; runs without one are flat.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum RunLayout {
+    /// Everything directly in the run dir.
+    #[default]
+    Flat,
+    /// Each version's files in its own `v-NNNN/` subdirectory.
+    PerVersion,
+}
+
+impl RunLayout {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        match value.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "flat" => Ok(Self::Flat),
+            "per-version" => Ok(Self::PerVersion),
+            other => anyhow::bail!("unknown run layout '{other}' (expected flat or per-version)"),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::PerVersion => "per-version",
+        }
+    }
+
+    /// The directory `version_id`'s artifacts and receipts go in.
+    pub fn version_dir(self, run_dir: &Path, version_id: &str) -> PathBuf {
+        match self {
+            Self::Flat => run_dir.to_path_buf(),
+            Self::PerVersion => run_dir.join(version_dir_name(version_id)),
+        }
+    }
+}
+
+/// `v3` -> `v-0003`, so directory listings sort in version order.
+pub fn version_dir_name(version_id: &str) -> String {
+    match version_id
+        .strip_prefix('v')
+        .and_then(|number| number.parse::<u64>().ok())
+    {
+        Some(number) => format!("v-{number:04}"),
+        None => slugify(version_id),
+    }
+}
+
+pub(crate) fn is_version_dir_name(name: &str) -> bool {
+    name.strip_prefix("v-")
+        .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Every `receipt-*.json` in `run_dir` and its version subdirectories,
+/// sorted, whichever layout wrote them.
+pub fn receipt_files(run_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let is_receipt = |path: &Path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("receipt-") && name.ends_with(".json"))
+    };
+    let mut receipts = Vec::new();
+    for entry in fs::read_dir(run_dir)?.flatten() {
+        let path = entry.path();
+        if is_receipt(&path) {
+            receipts.push(path);
+        } else if path.is_dir()
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_version_dir_name)
+        {
+            receipts.extend(
+                fs::read_dir(&path)?
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| is_receipt(path)),
+            );
+        }
+    }
+    receipts.sort();
+    Ok(receipts)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayoutMigration {
+    /// Files that were (or, in a dry run, would be) moved, old and new path.
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// JSON files whose recorded paths were rewritten.
+    pub rewritten: Vec<PathBuf>,
+    pub dry_run: bool,
+}
+
+/// Moves a flat run's artifacts, thumbnails and receipts into per-version
+/// subdirectories and rewrites the paths `thread.json`, `cache.json`,
+/// `summary.json` and the receipts record. Files no artifact references
+/// (edit masks, exports, traces) stay where they are; `events.jsonl` is
+/// history and keeps the old paths.
+///
+/// The moves are journaled in [`MIGRATION_JOURNAL`]. A failure before
+/// `thread.json` is saved moves every file back; one after it leaves the
+/// journal for the next run to finish rewriting the other files.
+pub fn migrate_to_per_version(run_dir: &Path, dry_run: bool) -> anyhow::Result<LayoutMigration> {
+    let thread_path = run_dir.join("thread.json");
+    if !thread_path.is_file() {
+        anyhow::bail!("no thread.json in {}", run_dir.display());
+    }
+    let journal_path = run_dir.join(MIGRATION_JOURNAL);
+    if journal_path.is_file() {
+        if dry_run {
+            anyhow::bail!(
+                "{} records an interrupted migration; run without --dry-run to recover it",
+                journal_path.display()
+            );
+        }
+        recover_migration(run_dir)?;
+    }
+    let root = fs::canonicalize(run_dir)?;
+    let mut thread = ThreadManifest::load(&thread_path)?;
+    let mut renames: BTreeMap<String, String> = BTreeMap::new();
+    let mut report = LayoutMigration {
+        dry_run,
+        ..LayoutMigration::default()
+    };
+    for version in &thread.versions {
+        let dir_name = version_dir_name(&version.version_id);
+        let mut paths = Vec::new();
+        for artifact in &version.artifacts {
+            collect_strings(&Value::Object(artifact.clone()), &mut paths);
+        }
+        for text in paths {
+            let path = Path::new(&text);
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+            let in_root = fs::canonicalize(parent).is_ok_and(|parent| parent == root);
+            if !in_root || !path.is_file() || renames.contains_key(&text) {
+                continue;
+            }
+            let target = parent.join(&dir_name).join(name);
+            report.moved.push((path.to_path_buf(), target.clone()));
+            renames.insert(text, target.to_string_lossy().to_string());
+        }
+    }
+    if dry_run || report.moved.is_empty() {
+        return Ok(report);
+    }
+
+    write_journal(&journal_path, &report.moved, false)?;
+    let moved = move_files(&report.moved).and_then(|()| {
+        for version in &mut thread.versions {
+            for artifact in &mut version.artifacts {
+                for value in artifact.values_mut() {
+                    replace_strings(value, &renames);
+                }
+            }
+        }
+        thread.layout = RunLayout::PerVersion;
+        thread.save()
+    });
+    if let Err(err) = moved {
+        return Err(match undo_moves(&report.moved) {
+            Ok(()) => {
+                fs::remove_file(&journal_path)?;
+                err.context("layout migration rolled back")
+            }
+            Err(undo) => err.context(format!(
+                "rolling back failed too ({undo}); {} lists the moves",
+                journal_path.display()
+            )),
+        });
+    }
+    report.rewritten.push(thread_path);
+    write_journal(&journal_path, &report.moved, true)?;
+    report
+        .rewritten
+        .extend(rewrite_recorded_paths(run_dir, &renames)?);
+    fs::remove_file(&journal_path)?;
+    Ok(report)
+}
+
+fn write_journal(path: &Path, moves: &[(PathBuf, PathBuf)], committed: bool) -> anyhow::Result<()> {
+    let moves: Vec<[String; 2]> = moves
+        .iter()
+        .map(|(from, to)| {
+            [
+                from.to_string_lossy().to_string(),
+                to.to_string_lossy().to_string(),
+            ]
+        })
+        .collect();
+    write_json_atomic(path, &json!({ "moves": moves, "committed": committed }))
+}
+
+fn move_files(moves: &[(PathBuf, PathBuf)]) -> anyhow::Result<()> {
+    for (from, to) in moves {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(from, to)
+            .with_context(|| format!("failed to move {} to {}", from.display(), to.display()))?;
+    }
+    Ok(())
+}
+
+/// Moves back every file that reached its new path.
+fn undo_moves(moves: &[(PathBuf, PathBuf)]) -> anyhow::Result<()> {
+    for (from, to) in moves.iter().rev() {
+        if to.is_file() && !from.exists() {
+            fs::rename(to, from).with_context(|| {
+                format!("failed to move {} back to {}", to.display(), from.display())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites moved paths in `cache.json`, `summary.json` and the receipts;
+/// returns the files changed.
+fn rewrite_recorded_paths(
+    run_dir: &Path,
+    renames: &BTreeMap<String, String>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut rewritten = Vec::new();
+    let mut others = vec![run_dir.join("cache.json"), run_dir.join("summary.json")];
+    others.extend(receipt_files(run_dir)?);
+    for path in others {
+        let Some(mut payload) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+        else {
+            continue;
+        };
+        if replace_strings(&mut payload, renames) {
+            write_json_atomic(&path, &payload)?;
+            rewritten.push(path);
+        }
+    }
+    Ok(rewritten)
+}
+
+/// Undoes a migration [`MIGRATION_JOURNAL`] shows was interrupted before
+/// `thread.json` was saved, or finishes one interrupted after.
+fn recover_migration(run_dir: &Path) -> anyhow::Result<()> {
+    let journal_path = run_dir.join(MIGRATION_JOURNAL);
+    let journal = read_json(&journal_path)?.unwrap_or(Value::Null);
+    let moves: Vec<(PathBuf, PathBuf)> = journal["moves"]
+        .as_array()
+        .with_context(|| format!("{} lists no moves", journal_path.display()))?
+        .iter()
+        .filter_map(|pair| Some((pair[0].as_str()?.into(), pair[1].as_str()?.into())))
+        .collect();
+    if journal["committed"].as_bool() == Some(true) {
+        let renames = moves
+            .iter()
+            .map(|(from, to)| {
+                (
+                    from.to_string_lossy().to_string(),
+                    to.to_string_lossy().to_string(),
+                )
+            })
+            .collect();
+        rewrite_recorded_paths(run_dir, &renames)?;
+    } else {
+        undo_moves(&moves)?;
+    }
+    fs::remove_file(&journal_path)?;
+    Ok(())
+}
+
+fn collect_strings(value: &Value, out: &mut Vec<String>) {
+    match value {
+        Value::String(text) => out.push(text.clone()),
+        Value::Array(items) => items.iter().for_each(|item| collect_strings(item, out)),
+        Value::Object(map) => map.values().for_each(|item| collect_strings(item, out)),
+        _ => {}
+    }
+}
+
+/// Replaces every string equal to a key of `renames`; true when any was.
+fn replace_strings(value: &mut Value, renames: &BTreeMap<String, String>) -> bool {
+    match value {
+        Value::String(text) => match renames.get(text.as_str()) {
+            Some(renamed) => {
+                *text = renamed.clone();
+                true
+            }
+            None => false,
+        },
+        Value::Array(items) => items.iter_mut().fold(false, |changed, item| {
+            replace_strings(item, renames) | changed
+        }),
+        Value::Object(map) => map.values_mut().fold(false, |changed, item| {
+            replace_strings(item, renames) | changed
+        }),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Map, Value};
+
+    use super::{
+        migrate_to_per_version, receipt_files, version_dir_name, write_journal, RunLayout,
+        MIGRATION_JOURNAL, VERSION_FILENAME,
+    };
+    use crate::runs::thread_manifest::ThreadManifest;
+    use crate::runs::verify::verify_run;
+
+    #[test]
+    fn flat_runs_migrate_into_version_directories() -> anyhow::Result<()> {
+        assert_eq!(version_dir_name("v3"), "v-0003");
+        assert_eq!(version_dir_name("v12345"), "v-12345");
+        assert_eq!(RunLayout::parse("per_version")?, RunLayout::PerVersion);
+        assert!(RunLayout::parse("nested").is_err());
+
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        fs::create_dir_all(&run_dir)?;
+        let mut thread = ThreadManifest::new(run_dir.join("thread.json"));
+        for prompt in ["boat", "dune"] {
+            let version = thread.add_version(Map::new(), Map::new(), prompt.to_string(), None);
+            let id = format!("{}-01", version.version_id);
+            let image = run_dir.join(format!("artifact-{id}.png"));
+            let receipt = run_dir.join(format!("receipt-{id}.json"));
+            fs::write(&image, b"png")?;
+            fs::write(
+                &receipt,
+                serde_json::to_string(&json!({
+                    "artifacts": {
+                        "image_path": image.to_string_lossy(),
+                        "receipt_path": receipt.to_string_lossy(),
+                    },
+                }))?,
+            )?;
+            let artifact = json!({
+                "artifact_id": id,
+                "image_path": image.to_string_lossy(),
+                "receipt_path": receipt.to_string_lossy(),
+            });
+            let Value::Object(artifact) = artifact else {
+                unreachable!()
+            };
+            thread.add_artifact(&version.version_id, artifact);
+        }
+        thread.save()?;
+        fs::write(run_dir.join("mask-1.png"), b"mask")?;
+
+        let preview = migrate_to_per_version(&run_dir, true)?;
+        assert_eq!(preview.moved.len(), 4);
+        assert!(run_dir.join("artifact-v1-01.png").is_file());
+
+        // A move that fails part way puts every file back.
+        let blocker = run_dir.join("v-0002").join("artifact-v2-01.png");
+        fs::create_dir_all(blocker.join("occupied"))?;
+        let err = migrate_to_per_version(&run_dir, false).expect_err("blocked move");
+        assert!(format!("{err:#}").contains("rolled back"), "{err:#}");
+        assert!(run_dir.join("artifact-v1-01.png").is_file());
+        assert!(run_dir.join("receipt-v1-01.json").is_file());
+        assert!(!run_dir.join(MIGRATION_JOURNAL).exists());
+        assert_eq!(
+            ThreadManifest::load(run_dir.join("thread.json"))?.layout,
+            RunLayout::Flat
+        );
+        fs::remove_dir_all(&blocker)?;
+
+        // A migration killed after its first move is undone by the next.
+        let first = (
+            run_dir.join("artifact-v1-01.png"),
+            run_dir.join("v-0001").join("artifact-v1-01.png"),
+        );
+        write_journal(&run_dir.join(MIGRATION_JOURNAL), &preview.moved, false)?;
+        fs::create_dir_all(run_dir.join("v-0001"))?;
+        fs::rename(&first.0, &first.1)?;
+        assert!(migrate_to_per_version(&run_dir, true).is_err());
+
+        let report = migrate_to_per_version(&run_dir, false)?;
+        assert_eq!(report.moved.len(), 4);
+        let v2 = run_dir.join("v-0002");
+        assert!(v2.join("artifact-v2-01.png").is_file());
+        assert!(v2.join(VERSION_FILENAME).is_file());
+        assert!(run_dir.join("mask-1.png").is_file());
+        assert!(!run_dir.join("receipt-v1-01.json").exists());
+        assert_eq!(
+            receipt_files(&run_dir)?,
+            [
+                run_dir.join("v-0001").join("receipt-v1-01.json"),
+                v2.join("receipt-v2-01.json"),
+            ]
+        );
+
+        let thread = ThreadManifest::load(run_dir.join("thread.json"))?;
+        assert_eq!(thread.layout, RunLayout::PerVersion);
+        assert_eq!(
+            thread.versions[1].artifacts[0]["image_path"],
+            json!(v2.join("artifact-v2-01.png").to_string_lossy())
+        );
+        let version: Value = serde_json::from_str(&fs::read_to_string(v2.join(VERSION_FILENAME))?)?;
+        assert_eq!(version["prompt"], "dune");
+        // The stub receipts are incomplete, but every path they and the
+        // manifest record must resolve after the move.
+        let report = verify_run(&run_dir);
+        assert_eq!(report.receipts_checked, 2);
+        assert!(
+            !report
+                .errors
+                .iter()
+                .any(|issue| matches!(issue.code, "missing_file" | "path_mismatch")),
+            "{report:?}"
+        );
+
+        // Already migrated: nothing left to move.
+        assert!(migrate_to_per_version(&run_dir, false)?.moved.is_empty());
+
+        let unknown = temp.path().join("unknown.json");
+        fs::write(&unknown, r#"{"layout": "nested", "versions": []}"#)?;
+        let err = ThreadManifest::load(&unknown).expect_err("unknown layout");
+        assert!(format!("{err:#}").contains("nested"), "{err:#}");
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+/// Lock file in the run dir held by the engine that has it open.
+pub const LOCK_FILENAME: &str = "run.lock";
+
+/// Exclusive lock on a run dir: an OS advisory lock (`flock`/`LockFileEx`)
+/// on `run.lock`, held for as long as this value lives. The OS releases it
+/// when the holder exits or crashes, so there is no heartbeat to go stale
+/// and a suspended holder keeps its lock. The file records the holder's pid
+/// for the "in use" error and is left in place on release: deleting it
+/// would let a waiter lock the unlinked file while a newcomer locks a new
+/// one.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl RunLock {
+    /// Takes the lock on `run_dir`, or fails naming the process that holds
+    /// it.
+    pub fn acquire(run_dir: &Path) -> anyhow::Result<Self> {
+        let path = run_dir.join(LOCK_FILENAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|err| anyhow::anyhow!("failed to lock {}: {err}", run_dir.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = match LockHolder::read(&path) {
+                    Some(holder) => format!("pid {} (since {})", holder.pid, holder.acquired_at),
+                    None => "another process".to_string(),
+                };
+                anyhow::bail!(
+                    "run dir {} is in use by {holder}; if no such process is running (a lock stuck on a network filesystem), pass --force-unlock",
+                    run_dir.display()
+                );
+            }
+            Err(TryLockError::Error(err)) => {
+                anyhow::bail!("failed to lock {}: {err}", run_dir.display())
+            }
+        }
+        let payload = json!({
+            "pid": std::process::id(),
+            "acquired_at": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        });
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string_pretty(&payload)?.as_bytes())?;
+        file.sync_all()?;
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Clear the holder before the OS lock goes with the handle.
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+/// Exclusive lock on a shared file, waited for rather than refused: the
+/// same OS advisory lock as [`RunLock`], taken around a short
+/// read-modify-write of a file several processes update, such as the global
+/// cache index. Released when dropped; the lock file stays in place.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Blocks until `path` is locked, creating it when missing.
+    pub fn acquire(path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|err| anyhow::anyhow!("failed to lock {}: {err}", path.display()))?;
+        file.lock()
+            .map_err(|err| anyhow::anyhow!("failed to lock {}: {err}", path.display()))?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Removes `run_dir`'s lock file whoever holds it: `--force-unlock`, for a
+/// lock the OS did not release, such as one held through a network
+/// filesystem whose client is gone. A live holder keeps its lock on the
+/// removed file, so this breaks exclusion if it is still running. Returns
+/// the pid the file recorded.
+pub fn force_unlock(run_dir: &Path) -> anyhow::Result<Option<u32>> {
+    let path = run_dir.join(LOCK_FILENAME);
+    let holder = LockHolder::read(&path);
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(holder.map(|holder| holder.pid)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => anyhow::bail!("failed to remove {}: {err}", path.display()),
+    }
+}
+
+#[derive(Debug)]
+struct LockHolder {
+    pid: u32,
+    acquired_at: String,
+}
+
+impl LockHolder {
+    /// `None` when the file is missing, released or still being written.
+    fn read(path: &Path) -> Option<Self> {
+        let payload: Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+        Some(Self {
+            pid: payload.get("pid")?.as_u64()? as u32,
+            acquired_at: payload.get("acquired_at")?.as_str()?.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{force_unlock, FileLock, RunLock, LOCK_FILENAME};
+
+    #[test]
+    fn run_lock_excludes_second_holder_until_released() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path();
+        let lock = RunLock::acquire(run_dir)?;
+        assert!(lock.path().is_file());
+        let err = RunLock::acquire(run_dir)
+            .err()
+            .map(|err| err.to_string())
+            .unwrap_or_default();
+        assert!(
+            err.contains(&format!("in use by pid {}", std::process::id())),
+            "{err}"
+        );
+        assert!(err.contains("--force-unlock"), "{err}");
+
+        // Released on drop; the file stays, empty.
+        drop(lock);
+        assert_eq!(fs::read_to_string(run_dir.join(LOCK_FILENAME))?, "");
+        let lock = RunLock::acquire(run_dir)?;
+
+        // Forcing the lock away lets a new holder in.
+        assert_eq!(force_unlock(run_dir)?, Some(std::process::id()));
+        let next = RunLock::acquire(run_dir)?;
+        drop(lock);
+        assert!(RunLock::acquire(run_dir).is_err());
+        drop(next);
+        assert!(force_unlock(run_dir)?.is_none());
+        assert_eq!(force_unlock(run_dir)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn a_leftover_lock_file_without_a_holder_is_taken() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join(LOCK_FILENAME);
+        // What a crashed holder leaves: its pid, but no OS lock.
+        fs::write(
+            &path,
+            r#"{"pid": 1, "acquired_at": "2020-01-01T00:00:00Z"}"#,
+        )?;
+        let lock = RunLock::acquire(temp.path())?;
+        assert!(fs::read_to_string(&path)?.contains(&std::process::id().to_string()));
+        drop(lock);
+        Ok(())
+    }
+
+    #[test]
+    fn file_lock_waits_for_the_holder() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("index.lock");
+        let lock = FileLock::acquire(&path)?;
+        let waiter = std::thread::spawn({
+            let path = path.clone();
+            move || FileLock::acquire(&path).map(drop)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        drop(lock);
+        waiter.join().expect("waiter thread")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::atomic::write_json_atomic;
+use super::cache::CACHE_SCHEMA_VERSION;
+use super::layout::receipt_files;
+use super::receipts::{RECEIPT_SCHEMA_VERSION, VIDEO_RECEIPT_SCHEMA_VERSION};
+use super::summary::SUMMARY_SCHEMA_VERSION;
+use super::thread_manifest::THREAD_SCHEMA_VERSION;
+
+/// Versioned files in a run dir. A file without `schema_version` predates
+/// versioning and counts as version 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunFileKind {
+    Thread,
+    Cache,
+    Summary,
+    Receipt,
+    VideoReceipt,
+}
+
+impl RunFileKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Thread => "thread",
+            Self::Cache => "cache",
+            Self::Summary => "summary",
+            Self::Receipt => "receipt",
+            Self::VideoReceipt => "video receipt",
+        }
+    }
+
+    /// Version this build writes.
+    pub fn current(self) -> u64 {
+        match self {
+            Self::Thread => THREAD_SCHEMA_VERSION,
+            Self::Cache => CACHE_SCHEMA_VERSION,
+            Self::Summary => SUMMARY_SCHEMA_VERSION,
+            Self::Receipt => RECEIPT_SCHEMA_VERSION,
+            Self::VideoReceipt => VIDEO_RECEIPT_SCHEMA_VERSION,
+        }
+    }
+
+    /// Oldest version this build can still read without migrating.
+    pub fn min_readable(self) -> u64 {
+        0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStep {
+    pub path: PathBuf,
+    pub kind: RunFileKind,
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Files that were (or, in a dry run, would be) rewritten.
+    pub upgraded: Vec<MigrationStep>,
+    pub up_to_date: usize,
+    pub dry_run: bool,
+}
+
+/// Upgrades every versioned file in `run_dir` to the version this build
+/// writes. Files written by a newer build are an error and nothing is
+/// rewritten.
+pub fn migrate_run_dir(run_dir: &Path, dry_run: bool) -> anyhow::Result<MigrationReport> {
+    if !run_dir.is_dir() {
+        anyhow::bail!("run dir {} does not exist", run_dir.display());
+    }
+    let files = versioned_files(run_dir)?;
+    for VersionedFile {
+        path,
+        kind,
+        payload,
+    } in &files
+    {
+        let found = schema_version_of(payload);
+        if found > kind.current() {
+            anyhow::bail!(
+                "{} {} has schema_version {found}, newer than this build supports ({})",
+                kind.label(),
+                path.display(),
+                kind.current()
+            );
+        }
+    }
+
+    let mut report = MigrationReport {
+        dry_run,
+        ..MigrationReport::default()
+    };
+    for VersionedFile {
+        path,
+        kind,
+        mut payload,
+    } in files
+    {
+        let from = schema_version_of(&payload);
+        if from == kind.current() {
+            report.up_to_date += 1;
+            continue;
+        }
+        upgrade(kind, &mut payload);
+        if !dry_run {
+            write_json_atomic(&path, &Value::Object(payload))?;
+        }
+        report.upgraded.push(MigrationStep {
+            path,
+            kind,
+            from,
+            to: kind.current(),
+        });
+    }
+    Ok(report)
+}
+
+/// Fails when `run_dir` holds a thread, cache or summary file this build
+/// cannot read, pointing at `migrate` for outdated ones.
+pub fn check_run_dir(run_dir: &Path) -> anyhow::Result<()> {
+    for (path, kind) in [
+        ("thread.json", RunFileKind::Thread),
+        ("cache.json", RunFileKind::Cache),
+        ("summary.json", RunFileKind::Summary),
+    ] {
+        let path = run_dir.join(path);
+        let Some(payload) = read_object(&path) else {
+            continue;
+        };
+        let found = schema_version_of(&payload);
+        if found > kind.current() {
+            anyhow::bail!(
+                "{} has schema_version {found}; this build only reads up to {}",
+                path.display(),
+                kind.current()
+            );
+        }
+        if found < kind.min_readable() {
+            anyhow::bail!(
+                "{} has schema_version {found}; run `brood-rs migrate --run {}` first",
+                path.display(),
+                run_dir.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn schema_version_of(payload: &Map<String, Value>) -> u64 {
+    payload
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0)
+}
+
+/// Rewrites `payload` into the current layout. Version 1 only introduced the
+/// `schema_version` field itself; later format changes add their rewrite
+/// here, keyed on the version they upgrade from.
+fn upgrade(kind: RunFileKind, payload: &mut Map<String, Value>) {
+    payload.insert(
+        "schema_version".to_string(),
+        Value::Number(kind.current().into()),
+    );
+}
+
+struct VersionedFile {
+    path: PathBuf,
+    kind: RunFileKind,
+    payload: Map<String, Value>,
+}
+
+fn versioned_files(run_dir: &Path) -> anyhow::Result<Vec<VersionedFile>> {
+    let mut files = Vec::new();
+    for (name, kind) in [
+        ("thread.json", RunFileKind::Thread),
+        ("cache.json", RunFileKind::Cache),
+        ("summary.json", RunFileKind::Summary),
+    ] {
+        let path = run_dir.join(name);
+        if let Some(payload) = read_object(&path) {
+            files.push(VersionedFile {
+                path,
+                kind,
+                payload,
+            });
+        }
+    }
+    for path in receipt_files(run_dir)? {
+        let Some(payload) = read_object(&path) else {
+            continue;
+        };
+        let kind = if payload.get("kind").and_then(Value::as_str) == Some("video") {
+            RunFileKind::VideoReceipt
+        } else {
+            RunFileKind::Receipt
+        };
+        files.push(VersionedFile {
+            path,
+            kind,
+            payload,
+        });
+    }
+    Ok(files)
+}
+
+fn read_object(path: &Path) -> Option<Map<String, Value>> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str::<Value>(&raw)
+        .ok()?
+        .as_object()
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Value};
+
+    use super::{check_run_dir, migrate_run_dir, RunFileKind};
+    use crate::runs::cache::CacheStore;
+    use crate::runs::thread_manifest::ThreadManifest;
+
+    #[test]
+    fn migrate_stamps_legacy_files_and_rejects_newer_ones() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path();
+        fs::write(
+            run_dir.join("thread.json"),
+            r#"{"thread_id": "t1", "created_at": "2025-01-01T00:00:00Z", "versions": []}"#,
+        )?;
+        fs::write(
+            run_dir.join("cache.json"),
+            r#"{"abc": {"image_path": "/tmp/a.png"}}"#,
+        )?;
+        fs::write(run_dir.join("summary.json"), r#"{"run_id": "r1"}"#)?;
+        fs::write(
+            run_dir.join("receipt-a1.json"),
+            r#"{"schema_version": 1, "request": {}}"#,
+        )?;
+
+        let preview = migrate_run_dir(run_dir, true)?;
+        assert_eq!(preview.upgraded.len(), 3);
+        assert_eq!(preview.up_to_date, 1);
+        assert!(!fs::read_to_string(run_dir.join("cache.json"))?.contains("schema_version"));
+
+        let report = migrate_run_dir(run_dir, false)?;
+        let kinds: Vec<RunFileKind> = report.upgraded.iter().map(|step| step.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                RunFileKind::Thread,
+                RunFileKind::Cache,
+                RunFileKind::Summary
+            ]
+        );
+        assert!(report.upgraded.iter().all(|step| step.from == 0));
+        let cache: Value = serde_json::from_str(&fs::read_to_string(run_dir.join("cache.json"))?)?;
+        assert_eq!(cache["schema_version"], json!(1));
+        assert_eq!(cache["abc"]["image_path"], json!("/tmp/a.png"));
+        assert_eq!(
+            CacheStore::new(run_dir.join("cache.json"))
+                .get("abc")
+                .map(|e| e.len()),
+            Some(1)
+        );
+        assert_eq!(
+            ThreadManifest::load(run_dir.join("thread.json"))?.thread_id,
+            "t1"
+        );
+        assert!(migrate_run_dir(run_dir, false)?.upgraded.is_empty());
+        check_run_dir(run_dir)?;
+
+        fs::write(run_dir.join("summary.json"), r#"{"schema_version": 99}"#)?;
+        assert!(check_run_dir(run_dir).is_err());
+        assert!(migrate_run_dir(run_dir, false).is_err());
+        Ok(())
+    }
+}
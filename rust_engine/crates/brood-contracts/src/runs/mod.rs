@@ -1,5 +1,16 @@
+pub mod atomic;
 pub mod cache;
 pub mod feedback;
+pub mod gc;
+pub mod layout;
+pub mod lock;
+pub mod migrate;
+pub mod receipt_diff;
 pub mod receipts;
+pub mod run_dir;
+pub mod selection;
+pub mod session;
 pub mod summary;
 pub mod thread_manifest;
+pub mod verify;
+pub mod warnings;
@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+/// Image receipt fields that decide what a provider produced, in the order
+/// [`diff_receipts`] reports them. Object fields are compared key by key.
+pub const IMAGE_DIFF_FIELDS: &[&str] = &[
+    "resolved.provider",
+    "resolved.model",
+    "request.mode",
+    "request.prompt",
+    "resolved.prompt",
+    "resolved.seed",
+    "resolved.size",
+    "resolved.width",
+    "resolved.height",
+    "resolved.n",
+    "resolved.output_format",
+    "resolved.background",
+    "resolved.inputs",
+    "resolved.provider_params",
+    "resolved.safety",
+];
+
+/// Video receipts have no `resolved` block; the request is what was sent.
+pub const VIDEO_DIFF_FIELDS: &[&str] = &[
+    "request.provider",
+    "request.model",
+    "request.prompt",
+    "request.seed",
+    "request.init_image",
+    "request.duration_s",
+    "request.aspect_ratio",
+    "request.provider_options",
+];
+
+/// One field that differs between two receipts. `path` is dotted from the
+/// receipt root (e.g. `resolved.provider_params.guidance`); a side is
+/// `None` when the field is absent or null there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiptChange {
+    pub path: String,
+    pub left: Option<Value>,
+    pub right: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReceiptDiff {
+    pub changes: Vec<ReceiptChange>,
+    /// Neither request pinned a seed, so matching requests can still
+    /// produce different images.
+    pub unseeded: bool,
+}
+
+impl ReceiptDiff {
+    pub fn is_identical(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn to_value(&self) -> Value {
+        json!({
+            "identical": self.is_identical(),
+            "unseeded": self.unseeded,
+            "changes": self.changes.iter().map(|change| json!({
+                "path": change.path,
+                "left": change.left,
+                "right": change.right,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+pub fn load_receipt(path: &Path) -> Result<Value> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read receipt {}", path.display()))?;
+    let receipt: Value = serde_json::from_str(&raw)
+        .with_context(|| format!("invalid receipt {}", path.display()))?;
+    if !receipt.is_object() {
+        bail!("invalid receipt {}: expected a JSON object", path.display());
+    }
+    Ok(receipt)
+}
+
+/// Compares the requests behind two receipts to explain why their outputs
+/// differ. Media paths, hashes and provider responses are ignored.
+pub fn diff_receipts(left: &Value, right: &Value) -> ReceiptDiff {
+    let mut diff = ReceiptDiff::default();
+    let (left_kind, right_kind) = (receipt_kind(left), receipt_kind(right));
+    if left_kind != right_kind {
+        diff.changes.push(ReceiptChange {
+            path: "kind".to_string(),
+            left: Some(Value::String(left_kind.to_string())),
+            right: Some(Value::String(right_kind.to_string())),
+        });
+    }
+    let video = left_kind == "video" && right_kind == "video";
+    let fields = if video {
+        VIDEO_DIFF_FIELDS
+    } else {
+        IMAGE_DIFF_FIELDS
+    };
+    for field in fields {
+        diff_field(
+            field,
+            lookup(left, field),
+            lookup(right, field),
+            &mut diff.changes,
+        );
+    }
+    let seed_field = if video {
+        "request.seed"
+    } else {
+        "resolved.seed"
+    };
+    diff.unseeded = lookup(left, seed_field).is_none() && lookup(right, seed_field).is_none();
+    diff
+}
+
+fn receipt_kind(receipt: &Value) -> &str {
+    receipt
+        .get("kind")
+        .and_then(Value::as_str)
+        .unwrap_or("image")
+}
+
+fn lookup<'a>(receipt: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(receipt, |value, key| value.get(key))
+        .filter(|value| !value.is_null())
+}
+
+fn diff_field(
+    path: &str,
+    left: Option<&Value>,
+    right: Option<&Value>,
+    changes: &mut Vec<ReceiptChange>,
+) {
+    if let (Some(Value::Object(left)), Some(Value::Object(right))) = (left, right) {
+        diff_objects(path, left, right, changes);
+        return;
+    }
+    if left != right {
+        changes.push(ReceiptChange {
+            path: path.to_string(),
+            left: left.cloned(),
+            right: right.cloned(),
+        });
+    }
+}
+
+fn diff_objects(
+    path: &str,
+    left: &Map<String, Value>,
+    right: &Map<String, Value>,
+    changes: &mut Vec<ReceiptChange>,
+) {
+    let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        diff_field(
+            &format!("{path}.{key}"),
+            left.get(key).filter(|value| !value.is_null()),
+            right.get(key).filter(|value| !value.is_null()),
+            changes,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::diff_receipts;
+
+    #[test]
+    fn diffs_resolved_requests_field_by_field() {
+        let left = json!({
+            "request": {"prompt": "a red chair", "mode": "generate"},
+            "resolved": {
+                "provider": "replicate", "model": "flux-2-pro", "prompt": "a red chair",
+                "seed": 7, "size": "1024x1024", "n": 1,
+                "provider_params": {"guidance": 3.5, "steps": 28},
+                "warnings": ["ignored"],
+            },
+            "artifacts": {"image_path": "/runs/a/artifact-1.png"},
+        });
+        let right = json!({
+            "request": {"prompt": "a red chair", "mode": "generate"},
+            "resolved": {
+                "provider": "replicate", "model": "flux-2-pro", "prompt": "a red chair, studio",
+                "seed": 8, "size": "1024x1024", "n": 1,
+                "provider_params": {"guidance": 3.5, "steps": 40, "scheduler": null},
+            },
+            "artifacts": {"image_path": "/runs/b/artifact-2.png"},
+        });
+        let diff = diff_receipts(&left, &right);
+        let paths: Vec<&str> = diff
+            .changes
+            .iter()
+            .map(|change| change.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "resolved.prompt",
+                "resolved.seed",
+                "resolved.provider_params.steps"
+            ]
+        );
+        assert_eq!(diff.changes[1].left, Some(json!(7)));
+        assert_eq!(diff.changes[1].right, Some(json!(8)));
+        assert!(!diff.unseeded);
+
+        let unseeded = json!({"resolved": {"provider": "openai", "seed": null}});
+        let same = diff_receipts(&unseeded, &unseeded);
+        assert!(same.is_identical());
+        assert!(same.unseeded);
+        assert_eq!(same.to_value()["identical"], json!(true));
+
+        let video = json!({"kind": "video", "request": {"provider": "runway", "seed": 1}});
+        let kinds = diff_receipts(&video, &left);
+        assert_eq!(kinds.changes[0].path, "kind");
+    }
+}
@@ -2,8 +2,14 @@ use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::atomic::write_json_atomic;
+use super::warnings::{coded_warnings, warning_messages, Warning};
+use crate::redaction::redact_secrets;
 
 pub const RECEIPT_SCHEMA_VERSION: u64 = 1;
+pub const VIDEO_RECEIPT_SCHEMA_VERSION: u64 = 1;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub struct ImageInputs {
@@ -11,6 +17,48 @@ pub struct ImageInputs {
     pub mask: Option<String>,
     #[serde(default)]
     pub reference_images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<ControlInput>,
+}
+
+/// Kind of structure conditioning a control image carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlKind {
+    Canny,
+    Depth,
+    Pose,
+}
+
+impl ControlKind {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "canny" | "edges" => Some(Self::Canny),
+            "depth" => Some(Self::Depth),
+            "pose" | "openpose" => Some(Self::Pose),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Canny => "canny",
+            Self::Depth => "depth",
+            Self::Pose => "pose",
+        }
+    }
+}
+
+/// ControlNet-style conditioning input (`settings.control`):
+/// `{"type": "canny", "image": "edges.png", "strength": 0.7}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlInput {
+    #[serde(rename = "type")]
+    pub kind: ControlKind,
+    pub image: String,
+    /// Conditioning strength in `0.0..=1.0`; providers pick their own default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strength: Option<f64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -62,15 +110,39 @@ pub struct ResolvedRequest {
     pub provider_params: Map<String, Value>,
     #[serde(default)]
     pub warnings: Vec<String>,
+    /// `{level, applied}` from `settings.safety_level`; empty when the
+    /// request did not go through the safety mapping (e.g. upscales).
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub safety: Map<String, Value>,
 }
 
+/// Request shape recorded in video receipts; video generations do not share
+/// the image size/n/output-format contract.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoRequest {
+    pub prompt: String,
+    pub provider: String,
+    pub model: Option<String>,
+    pub init_image: Option<String>,
+    #[serde(default = "default_video_duration")]
+    pub duration_s: f64,
+    pub aspect_ratio: Option<String>,
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub provider_options: Map<String, Value>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn build_receipt(
     request: &ImageRequest,
     resolved: &ResolvedRequest,
     provider_request: &Map<String, Value>,
     provider_response: &Map<String, Value>,
-    warnings: &[String],
+    warnings: &[Warning],
     image_path: &Path,
+    image_sha256: Option<String>,
     receipt_path: &Path,
     result_metadata: &Map<String, Value>,
 ) -> Value {
@@ -97,14 +169,26 @@ pub fn build_receipt(
     );
     root.insert(
         "warnings".to_string(),
-        Value::Array(warnings.iter().cloned().map(Value::String).collect()),
+        Value::Array(
+            warning_messages(warnings)
+                .into_iter()
+                .map(Value::String)
+                .collect(),
+        ),
     );
+    root.insert("warning_details".to_string(), coded_warnings(warnings));
 
     let mut artifacts = Map::new();
     artifacts.insert(
         "image_path".to_string(),
         Value::String(image_path.to_string_lossy().to_string()),
     );
+    if let Some(digest) = image_sha256.or_else(|| file_sha256(image_path)) {
+        artifacts.insert("image_sha256".to_string(), Value::String(digest));
+    }
+    if let Ok(metadata) = std::fs::metadata(image_path) {
+        artifacts.insert("image_bytes".to_string(), Value::from(metadata.len()));
+    }
     artifacts.insert(
         "receipt_path".to_string(),
         Value::String(receipt_path.to_string_lossy().to_string()),
@@ -117,19 +201,115 @@ pub fn build_receipt(
     Value::Object(root)
 }
 
+#[allow(clippy::too_many_arguments)]
+pub fn build_video_receipt(
+    request: &VideoRequest,
+    provider_request: &Map<String, Value>,
+    provider_response: &Map<String, Value>,
+    warnings: &[Warning],
+    video_path: &Path,
+    video_sha256: Option<String>,
+    receipt_path: &Path,
+    result_metadata: &Map<String, Value>,
+) -> Value {
+    let mut root = Map::new();
+    root.insert(
+        "schema_version".to_string(),
+        Value::Number(VIDEO_RECEIPT_SCHEMA_VERSION.into()),
+    );
+    root.insert("kind".to_string(), Value::String("video".to_string()));
+    root.insert(
+        "request".to_string(),
+        sanitize_payload(&serde_json::to_value(request).unwrap_or(Value::Null)),
+    );
+    root.insert(
+        "provider_request".to_string(),
+        sanitize_payload(&Value::Object(provider_request.clone())),
+    );
+    root.insert(
+        "provider_response".to_string(),
+        sanitize_payload(&Value::Object(provider_response.clone())),
+    );
+    root.insert(
+        "warnings".to_string(),
+        Value::Array(
+            warning_messages(warnings)
+                .into_iter()
+                .map(Value::String)
+                .collect(),
+        ),
+    );
+    root.insert("warning_details".to_string(), coded_warnings(warnings));
+
+    let mut artifacts = Map::new();
+    artifacts.insert(
+        "video_path".to_string(),
+        Value::String(video_path.to_string_lossy().to_string()),
+    );
+    if let Some(digest) = video_sha256.or_else(|| file_sha256(video_path)) {
+        artifacts.insert("video_sha256".to_string(), Value::String(digest));
+    }
+    if let Ok(metadata) = std::fs::metadata(video_path) {
+        artifacts.insert("video_bytes".to_string(), Value::from(metadata.len()));
+    }
+    artifacts.insert(
+        "receipt_path".to_string(),
+        Value::String(receipt_path.to_string_lossy().to_string()),
+    );
+    root.insert("artifacts".to_string(), Value::Object(artifacts));
+    root.insert(
+        "result_metadata".to_string(),
+        sanitize_payload(&Value::Object(result_metadata.clone())),
+    );
+    Value::Object(root)
+}
+
+/// Hex sha256 of a file's bytes; receipts record it so `verify` can detect
+/// artifacts that changed after the fact.
+pub fn file_sha256(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    Some(hex::encode(Sha256::digest(bytes)))
+}
+
 pub fn write_receipt(path: &Path, payload: &Value) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+    write_json_atomic(path, payload)
+}
+
+/// Sets `result_metadata.<key>` in the receipt at `path`. A missing or
+/// unreadable receipt is left alone.
+pub fn store_result_metadata(path: &Path, key: &str, value: Value) -> anyhow::Result<()> {
+    let Some(mut payload) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+    else {
+        return Ok(());
+    };
+    let Some(root) = payload.as_object_mut() else {
+        return Ok(());
+    };
+    let metadata = root
+        .entry("result_metadata".to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Map::new());
     }
-    std::fs::write(path, serde_json::to_string_pretty(payload)?)?;
-    Ok(())
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert(key.to_string(), value);
+    }
+    write_receipt(path, &payload)
 }
 
+/// Everything a receipt embeds goes through here: inline image fields are
+/// dropped, then credentials and oversized strings are scrubbed.
 fn sanitize_payload(value: &Value) -> Value {
+    redact_secrets(&strip_binary_fields(value))
+}
+
+fn strip_binary_fields(value: &Value) -> Value {
     match value {
         Value::Null => Value::Null,
         Value::Bool(_) | Value::Number(_) | Value::String(_) => value.clone(),
-        Value::Array(rows) => Value::Array(rows.iter().map(sanitize_payload).collect()),
+        Value::Array(rows) => Value::Array(rows.iter().map(strip_binary_fields).collect()),
         Value::Object(map) => {
             let mut out = Map::new();
             for (key, row) in map {
@@ -141,7 +321,7 @@ fn sanitize_payload(value: &Value) -> Value {
                     out.insert(key.clone(), Value::String("<omitted>".to_string()));
                     continue;
                 }
-                out.insert(key.clone(), sanitize_payload(row));
+                out.insert(key.clone(), strip_binary_fields(row));
             }
             Value::Object(out)
         }
@@ -152,6 +332,10 @@ fn default_mode() -> String {
     "generate".to_string()
 }
 
+fn default_video_duration() -> f64 {
+    5.0
+}
+
 fn default_size() -> String {
     "1024x1024".to_string()
 }
@@ -162,10 +346,12 @@ fn default_n() -> u64 {
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+
     use serde_json::{json, Map, Value};
 
     use super::{
-        build_receipt, write_receipt, ImageInputs, ImageRequest, ResolvedRequest,
+        build_receipt, write_receipt, ImageInputs, ImageRequest, ResolvedRequest, Warning,
         RECEIPT_SCHEMA_VERSION,
     };
 
@@ -176,47 +362,13 @@ mod tests {
         let image_path = temp.path().join("image.png");
         std::fs::write(&image_path, b"png")?;
 
-        let request = ImageRequest {
-            prompt: "hello".to_string(),
-            mode: "generate".to_string(),
-            size: "1024x1024".to_string(),
-            n: 1,
-            seed: Some(7),
-            output_format: Some("png".to_string()),
-            background: None,
-            inputs: ImageInputs::default(),
-            provider: Some("dryrun".to_string()),
-            provider_options: Map::new(),
-            user: None,
-            out_dir: Some(temp.path().to_string_lossy().to_string()),
-            stream: false,
-            partial_images: None,
-            model: Some("dryrun-image-1".to_string()),
-            metadata: Map::new(),
-        };
-        let resolved = ResolvedRequest {
-            provider: "dryrun".to_string(),
-            model: Some("dryrun-image-1".to_string()),
-            size: "1024x1024".to_string(),
-            width: Some(1024),
-            height: Some(1024),
-            output_format: "png".to_string(),
-            background: None,
-            seed: Some(7),
-            n: 1,
-            user: None,
-            prompt: "hello".to_string(),
-            inputs: ImageInputs::default(),
-            stream: false,
-            partial_images: None,
-            provider_params: Map::new(),
-            warnings: Vec::new(),
-        };
+        let request = image_request_for_test(temp.path());
+        let resolved = resolved_request_for_test();
         let mut provider_request = Map::new();
         provider_request.insert("endpoint".to_string(), json!("dryrun"));
         let mut provider_response = Map::new();
         provider_response.insert("status".to_string(), json!("ok"));
-        let warnings = vec!["note".to_string()];
+        let warnings = vec![Warning::note("note")];
         let mut result_metadata = Map::new();
         result_metadata.insert("cost_total_usd".to_string(), Value::Null);
         result_metadata.insert("latency_per_image_s".to_string(), json!(0.01));
@@ -228,6 +380,7 @@ mod tests {
             &provider_response,
             &warnings,
             &image_path,
+            None,
             &receipt_path,
             &result_metadata,
         );
@@ -237,6 +390,8 @@ mod tests {
         let parsed: Value = serde_json::from_str(&raw)?;
         assert_eq!(parsed["schema_version"], json!(RECEIPT_SCHEMA_VERSION));
         assert_eq!(parsed["request"]["prompt"], json!("hello"));
+        assert_eq!(parsed["warning_details"][0]["code"], json!("provider_note"));
+        assert_eq!(parsed["warning_details"][0]["message"], json!("note"));
         assert_eq!(parsed["resolved"]["provider"], json!("dryrun"));
         assert_eq!(
             parsed["artifacts"]["image_path"],
@@ -248,4 +403,92 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn receipts_never_persist_credentials_or_inline_images() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let receipt_path = temp.path().join("receipt-1.json");
+        let image_path = temp.path().join("image.png");
+        std::fs::write(&image_path, b"png")?;
+        let mut request = image_request_for_test(temp.path());
+        request
+            .provider_options
+            .insert("api_key".to_string(), json!("sk-secret-123"));
+        let provider_request = json!({
+            "endpoint": "https://proxy.example.com/v1/images?api_key=sk-secret-123&v=2",
+            "headers": { "Authorization": "Bearer sk-secret-123" },
+            "payload": {
+                "image_url": format!("data:image/png;base64,{}", "iVBOR".repeat(300)),
+            },
+        });
+        let payload = build_receipt(
+            &request,
+            &resolved_request_for_test(),
+            provider_request.as_object().expect("object"),
+            &Map::new(),
+            &[],
+            &image_path,
+            None,
+            &receipt_path,
+            &Map::new(),
+        );
+        write_receipt(&receipt_path, &payload)?;
+
+        let raw = std::fs::read_to_string(&receipt_path)?;
+        assert!(!raw.contains("sk-secret-123"));
+        assert!(!raw.contains("iVBOR"));
+        let parsed: Value = serde_json::from_str(&raw)?;
+        assert_eq!(
+            parsed["provider_request"]["endpoint"],
+            json!("https://proxy.example.com/v1/images?api_key=<redacted>&v=2")
+        );
+        assert_eq!(
+            parsed["provider_request"]["payload"]["image_url"],
+            json!("<data:image/png omitted, 1500 chars>")
+        );
+        Ok(())
+    }
+
+    fn image_request_for_test(out_dir: &Path) -> ImageRequest {
+        ImageRequest {
+            prompt: "hello".to_string(),
+            mode: "generate".to_string(),
+            size: "1024x1024".to_string(),
+            n: 1,
+            seed: Some(7),
+            output_format: Some("png".to_string()),
+            background: None,
+            inputs: ImageInputs::default(),
+            provider: Some("dryrun".to_string()),
+            provider_options: Map::new(),
+            user: None,
+            out_dir: Some(out_dir.to_string_lossy().to_string()),
+            stream: false,
+            partial_images: None,
+            model: Some("dryrun-image-1".to_string()),
+            metadata: Map::new(),
+        }
+    }
+
+    fn resolved_request_for_test() -> ResolvedRequest {
+        ResolvedRequest {
+            provider: "dryrun".to_string(),
+            model: Some("dryrun-image-1".to_string()),
+            size: "1024x1024".to_string(),
+            width: Some(1024),
+            height: Some(1024),
+            output_format: "png".to_string(),
+            background: None,
+            seed: Some(7),
+            n: 1,
+            user: None,
+            prompt: "hello".to_string(),
+            inputs: ImageInputs::default(),
+            stream: false,
+            partial_images: None,
+            provider_params: Map::new(),
+            warnings: Vec::new(),
+            safety: Map::new(),
+        }
+    }
 }
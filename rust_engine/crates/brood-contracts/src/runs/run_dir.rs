@@ -0,0 +1,143 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const SLUG_MAX_CHARS: usize = 40;
+
+/// How an existing, non-empty run dir may be reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunDirReuse {
+    Refuse,
+    Resume,
+    Force,
+}
+
+impl RunDirReuse {
+    pub fn from_flags(resume: bool, force: bool) -> Self {
+        if force {
+            Self::Force
+        } else if resume {
+            Self::Resume
+        } else {
+            Self::Refuse
+        }
+    }
+}
+
+/// Lowercase ASCII slug of `text`, words joined by `-`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_alphanumeric() {
+            if slug.len() >= SLUG_MAX_CHARS {
+                break;
+            }
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-').to_string();
+    if slug.is_empty() {
+        "run".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Creates `<root>/run-<UTC timestamp>-<slug>`, adding a numeric suffix when
+/// another process already claimed the name.
+pub fn create_unique_run_dir(root: &Path, label: &str) -> anyhow::Result<PathBuf> {
+    fs::create_dir_all(root)?;
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+    let base = format!("run-{stamp}-{}", slugify(label));
+    for attempt in 1..1000 {
+        let name = if attempt == 1 {
+            base.clone()
+        } else {
+            format!("{base}-{attempt}")
+        };
+        let candidate = root.join(name);
+        match fs::create_dir(&candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    anyhow::bail!(
+        "could not allocate a unique run dir under {}",
+        root.display()
+    )
+}
+
+/// Validates that `path` may be used as a run dir under `reuse`, creating it
+/// when missing.
+pub fn prepare_run_dir(path: &Path, reuse: RunDirReuse) -> anyhow::Result<()> {
+    let has_entries = match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(err) if err.kind() == ErrorKind::NotFound => false,
+        Err(err) => return Err(err.into()),
+    };
+    match reuse {
+        RunDirReuse::Force => {}
+        RunDirReuse::Resume => {
+            if !path.join("thread.json").is_file() && !path.join("events.jsonl").is_file() {
+                anyhow::bail!(
+                    "nothing to resume in {} (no thread.json or events.jsonl)",
+                    path.display()
+                );
+            }
+        }
+        RunDirReuse::Refuse => {
+            if has_entries {
+                anyhow::bail!(
+                    "run dir {} is not empty; pass --resume to continue it or --force to reuse it",
+                    path.display()
+                );
+            }
+        }
+    }
+    fs::create_dir_all(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_unique_run_dir, prepare_run_dir, slugify, RunDirReuse};
+
+    #[test]
+    fn slugify_collapses_punctuation_and_caps_length() {
+        assert_eq!(slugify("  A Red Boat, at dawn!! "), "a-red-boat-at-dawn");
+        assert_eq!(slugify("!!!"), "run");
+        assert!(slugify(&"x".repeat(200)).len() <= 40);
+    }
+
+    #[test]
+    fn unique_run_dirs_do_not_collide() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let first = create_unique_run_dir(temp.path(), "red boat")?;
+        let second = create_unique_run_dir(temp.path(), "red boat")?;
+        assert_ne!(first, second);
+        assert!(first.is_dir() && second.is_dir());
+        assert!(first
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("")
+            .ends_with("-red-boat"));
+        Ok(())
+    }
+
+    #[test]
+    fn prepare_refuses_non_empty_dirs_without_flags() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        prepare_run_dir(&run_dir, RunDirReuse::Refuse)?;
+        std::fs::write(run_dir.join("notes.txt"), "x")?;
+        assert!(prepare_run_dir(&run_dir, RunDirReuse::Refuse).is_err());
+        assert!(prepare_run_dir(&run_dir, RunDirReuse::Resume).is_err());
+        prepare_run_dir(&run_dir, RunDirReuse::Force)?;
+        std::fs::write(run_dir.join("thread.json"), "{}")?;
+        prepare_run_dir(&run_dir, RunDirReuse::Resume)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use serde_json::{Map, Value};
+
+use super::gc::is_pruned;
+use super::thread_manifest::{ThreadManifest, VersionEntry};
+
+/// Which thread artifacts a command (e.g. `/export`) should act on.
+///
+/// Accepted forms: `all`, `winners`, `tag:<name>` / `#<name>`, `v3..v7`,
+/// `v4`, `last 4`, or a comma-separated list of artifact ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtifactSelector {
+    All,
+    Winners,
+    Tag(String),
+    VersionRange { start: u64, end: u64 },
+    Last(usize),
+    Ids(Vec<String>),
+}
+
+impl ArtifactSelector {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let text = raw.trim();
+        let lowered = text.to_ascii_lowercase();
+        if lowered.is_empty() || lowered == "all" {
+            return Ok(Self::All);
+        }
+        if matches!(lowered.as_str(), "winners" | "winner" | "selected") {
+            return Ok(Self::Winners);
+        }
+        if let Some(tag) = lowered
+            .strip_prefix("tag:")
+            .or_else(|| lowered.strip_prefix('#'))
+        {
+            let tag = tag.trim();
+            if tag.is_empty() {
+                anyhow::bail!("empty tag selector");
+            }
+            return Ok(Self::Tag(tag.to_string()));
+        }
+        if let Some(count) = lowered
+            .strip_prefix("last")
+            .map(|rest| rest.trim_start_matches([' ', ':']).trim())
+        {
+            let count = if count.is_empty() {
+                1
+            } else {
+                count
+                    .parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("invalid count in selector '{text}'"))?
+            };
+            return Ok(Self::Last(count.max(1)));
+        }
+        if let Some((start, end)) = lowered.split_once("..") {
+            let start = parse_version_number(start)
+                .ok_or_else(|| anyhow::anyhow!("invalid version range '{text}'"))?;
+            let end = parse_version_number(end)
+                .ok_or_else(|| anyhow::anyhow!("invalid version range '{text}'"))?;
+            return Ok(Self::VersionRange {
+                start: start.min(end),
+                end: start.max(end),
+            });
+        }
+        if let Some(version) = parse_version_number(&lowered) {
+            return Ok(Self::VersionRange {
+                start: version,
+                end: version,
+            });
+        }
+        let ids: Vec<String> = text
+            .split(|ch: char| ch == ',' || ch.is_whitespace())
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(Self::Ids(ids))
+    }
+
+    /// Matching artifacts in thread order; soft-deleted versions and
+    /// artifacts removed by gc are skipped.
+    pub fn select<'a>(
+        &self,
+        thread: &'a ThreadManifest,
+    ) -> Vec<(&'a VersionEntry, &'a Map<String, Value>)> {
+        let all = thread.live_versions().flat_map(|version| {
+            version
+                .artifacts
+                .iter()
+                .filter(|artifact| !is_pruned(artifact))
+                .map(move |artifact| (version, artifact))
+        });
+        match self {
+            Self::All => all.collect(),
+            Self::Winners => all
+                .filter(|(version, artifact)| {
+                    version.selected_artifact_id.as_deref() == artifact_id(artifact)
+                        && artifact_id(artifact).is_some()
+                })
+                .collect(),
+            Self::Tag(tag) => all
+                .filter(|(_, artifact)| {
+                    artifact
+                        .get("tags")
+                        .and_then(Value::as_array)
+                        .is_some_and(|tags| {
+                            tags.iter()
+                                .filter_map(Value::as_str)
+                                .any(|value| value.eq_ignore_ascii_case(tag))
+                        })
+                })
+                .collect(),
+            Self::VersionRange { start, end } => all
+                .filter(|(version, _)| {
+                    parse_version_number(&version.version_id)
+                        .is_some_and(|number| (*start..=*end).contains(&number))
+                })
+                .collect(),
+            Self::Last(count) => {
+                let rows: Vec<_> = all.collect();
+                let skip = rows.len().saturating_sub(*count);
+                rows.into_iter().skip(skip).collect()
+            }
+            Self::Ids(ids) => all
+                .filter(|(_, artifact)| {
+                    artifact_id(artifact).is_some_and(|id| ids.iter().any(|want| want == id))
+                })
+                .collect(),
+        }
+    }
+}
+
+fn artifact_id(artifact: &Map<String, Value>) -> Option<&str> {
+    artifact.get("artifact_id").and_then(Value::as_str)
+}
+
+fn parse_version_number(raw: &str) -> Option<u64> {
+    let trimmed = raw.trim();
+    let digits = trimmed
+        .strip_prefix('v')
+        .or_else(|| trimmed.strip_prefix('V'))
+        .unwrap_or(trimmed);
+    digits.parse::<u64>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::ArtifactSelector;
+    use crate::runs::thread_manifest::ThreadManifest;
+
+    fn artifact(id: &str, tags: &[&str]) -> Map<String, Value> {
+        let mut row = Map::new();
+        row.insert("artifact_id".to_string(), json!(id));
+        row.insert("tags".to_string(), json!(tags));
+        row
+    }
+
+    #[test]
+    fn selectors_parse_and_filter_thread_artifacts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let mut thread = ThreadManifest::new(temp.path().join("thread.json"));
+        for idx in 1..=4 {
+            let version = thread.add_version(Map::new(), Map::new(), format!("p{idx}"), None);
+            thread.add_artifact(&version.version_id, artifact(&format!("a{idx}"), &[]));
+            thread.add_artifact(
+                &version.version_id,
+                artifact(&format!("b{idx}"), if idx == 2 { &["Hero"] } else { &[] }),
+            );
+        }
+        thread.select_artifact("v3", "b3", None);
+        let version = thread.add_version(Map::new(), Map::new(), "gone".to_string(), None);
+        thread.add_artifact(&version.version_id, artifact("z5", &[]));
+        thread.delete_version("v5")?;
+
+        let ids = |selector: &str| -> anyhow::Result<Vec<String>> {
+            Ok(ArtifactSelector::parse(selector)?
+                .select(&thread)
+                .into_iter()
+                .filter_map(|(_, artifact)| artifact["artifact_id"].as_str().map(str::to_string))
+                .collect())
+        };
+        assert_eq!(ids("winners")?, vec!["b3"]);
+        assert_eq!(ids("tag:hero")?, vec!["b2"]);
+        assert_eq!(ids("v2..v3")?, vec!["a2", "b2", "a3", "b3"]);
+        assert_eq!(ids("v4")?, vec!["a4", "b4"]);
+        assert_eq!(ids("last 3")?, vec!["b3", "a4", "b4"]);
+        assert_eq!(ids("a1, b4")?, vec!["a1", "b4"]);
+        assert_eq!(ids("")?.len(), 8);
+        assert!(ArtifactSelector::parse("last many").is_err());
+        assert!(ArtifactSelector::parse("v3..vx").is_err());
+        Ok(())
+    }
+}
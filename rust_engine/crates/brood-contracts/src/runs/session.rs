@@ -0,0 +1,99 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::atomic::{read_json, write_json_atomic};
+
+/// Per-run chat session state persisted to `session.json`.
+///
+/// Holds policy that users change mid-session (provider toggles, ordering,
+/// the run budget), the run's cumulative spend, and the chat's conversation
+/// and settings, so a resumed chat picks up where it left off.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionState {
+    #[serde(default)]
+    pub disabled_providers: Vec<String>,
+    #[serde(default)]
+    pub provider_priority: Vec<String>,
+    #[serde(default)]
+    pub run_budget_usd: Option<f64>,
+    #[serde(default)]
+    pub run_cost_usd: f64,
+    /// Version the next chat generation branches from (set by `/branch`).
+    #[serde(default)]
+    pub active_parent_version_id: Option<String>,
+    /// Conversational context, oldest first; compaction folds older turns
+    /// into one `summary` turn.
+    #[serde(default)]
+    pub conversation: Vec<ContextTurn>,
+    /// Prompt an empty chat `generate` repeats.
+    #[serde(default)]
+    pub last_prompt: Option<String>,
+    /// Image edits and `/describe` apply to (set by `/use` or the latest
+    /// generation).
+    #[serde(default)]
+    pub active_image: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub quality_preset: Option<String>,
+}
+
+/// One entry of a chat's conversational context.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextTurn {
+    /// `user`, `assistant`, or `summary` for compacted older turns.
+    pub role: String,
+    pub text: String,
+}
+
+impl SessionState {
+    pub fn load(path: &Path) -> Self {
+        read_json(path)
+            .ok()
+            .flatten()
+            .and_then(|payload| serde_json::from_value(payload).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        write_json_atomic(path, &serde_json::to_value(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ContextTurn, SessionState};
+
+    #[test]
+    fn session_state_roundtrips_and_tolerates_missing_file() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("session.json");
+        assert_eq!(SessionState::load(&path), SessionState::default());
+
+        let state = SessionState {
+            disabled_providers: vec!["replicate".to_string()],
+            provider_priority: vec!["flux".to_string(), "openai".to_string()],
+            run_budget_usd: Some(5.0),
+            run_cost_usd: 1.25,
+            active_parent_version_id: Some("v2".to_string()),
+            conversation: vec![ContextTurn {
+                role: "user".to_string(),
+                text: "a red fox".to_string(),
+            }],
+            last_prompt: Some("a red fox".to_string()),
+            active_image: Some("/tmp/run/artifact.png".to_string()),
+            profile: Some("web".to_string()),
+            quality_preset: Some("fast".to_string()),
+        };
+        state.save(&path)?;
+        assert_eq!(SessionState::load(&path), state);
+
+        std::fs::write(&path, "{\"disabled_providers\": [\"fal\"]}")?;
+        assert_eq!(
+            SessionState::load(&path).disabled_providers,
+            vec!["fal".to_string()]
+        );
+        Ok(())
+    }
+}
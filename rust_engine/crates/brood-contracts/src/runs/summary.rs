@@ -4,6 +4,11 @@ use chrono::{SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
+use super::atomic::write_json_atomic;
+
+/// Layout version written to `summary.json`; see [`super::migrate`].
+pub const SUMMARY_SCHEMA_VERSION: u64 = 1;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RunSummary {
     pub run_id: String,
@@ -20,6 +25,10 @@ pub fn write_summary(
     extra: Option<&Map<String, Value>>,
 ) -> anyhow::Result<()> {
     let mut payload = Map::new();
+    payload.insert(
+        "schema_version".to_string(),
+        Value::Number(SUMMARY_SCHEMA_VERSION.into()),
+    );
     payload.insert("run_id".to_string(), Value::String(summary.run_id.clone()));
     payload.insert(
         "started_at".to_string(),
@@ -48,11 +57,7 @@ pub fn write_summary(
         }
     }
 
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    std::fs::write(path, serde_json::to_string_pretty(&Value::Object(payload))?)?;
-    Ok(())
+    write_json_atomic(path, &Value::Object(payload))
 }
 
 fn now_utc_iso() -> String {
@@ -63,7 +68,7 @@ fn now_utc_iso() -> String {
 mod tests {
     use serde_json::{json, Map, Value};
 
-    use super::{write_summary, RunSummary};
+    use super::{write_summary, RunSummary, SUMMARY_SCHEMA_VERSION};
 
     #[test]
     fn write_summary_generates_expected_payload() -> anyhow::Result<()> {
@@ -86,6 +91,7 @@ mod tests {
         write_summary(&path, &summary, Some(&extra))?;
 
         let parsed: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        assert_eq!(parsed["schema_version"], json!(SUMMARY_SCHEMA_VERSION));
         assert_eq!(parsed["run_id"], json!("run-123"));
         assert_eq!(parsed["total_versions"], json!(2));
         assert_eq!(parsed["winners"][0]["artifact_id"], json!("a-1"));
@@ -1,12 +1,19 @@
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+use anyhow::Context;
 use chrono::{SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use similar::TextDiff;
 use uuid::Uuid;
 
+use super::atomic::{read_json, write_atomic, write_json_atomic};
+use super::layout::{is_version_dir_name, receipt_files, RunLayout, VERSION_FILENAME};
+
+/// Layout version written to `thread.json`; see [`super::migrate`].
+pub const THREAD_SCHEMA_VERSION: u64 = 1;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VersionEntry {
     pub version_id: String,
@@ -19,6 +26,29 @@ pub struct VersionEntry {
     pub artifacts: Vec<Map<String, Value>>,
     pub selected_artifact_id: Option<String>,
     pub feedback: Vec<Map<String, Value>>,
+    /// Set by soft-delete; deleted versions keep their files but are hidden
+    /// from history, exports and summaries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    /// Set by undo; like soft-delete, the version keeps its files but drops
+    /// out of history until restored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverted_at: Option<String>,
+}
+
+impl VersionEntry {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    pub fn is_reverted(&self) -> bool {
+        self.reverted_at.is_some()
+    }
+
+    /// Neither soft-deleted nor reverted.
+    pub fn is_live(&self) -> bool {
+        !self.is_deleted() && !self.is_reverted()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -33,6 +63,7 @@ pub struct ThreadManifest {
     pub schema_version: u64,
     pub thread_id: String,
     pub created_at: String,
+    pub layout: RunLayout,
     pub versions: Vec<VersionEntry>,
     pub context_summary: ContextSummary,
 }
@@ -41,9 +72,10 @@ impl ThreadManifest {
     pub fn new(path: impl Into<PathBuf>) -> Self {
         Self {
             path: path.into(),
-            schema_version: 1,
+            schema_version: THREAD_SCHEMA_VERSION,
             thread_id: Uuid::new_v4().to_string(),
             created_at: now_utc_iso(),
+            layout: RunLayout::default(),
             versions: Vec::new(),
             context_summary: ContextSummary {
                 text: String::new(),
@@ -52,12 +84,16 @@ impl ThreadManifest {
         }
     }
 
-    pub fn load(path: impl Into<PathBuf>) -> Self {
+    /// Loads `path`, or a fresh manifest when it does not exist. A file
+    /// that is not valid JSON is an error: saving over it would lose the
+    /// run's history, so it must go through
+    /// [`recover_run_dir`](super::atomic::recover_run_dir) first.
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
         let path = path.into();
         let mut manifest = Self::new(path.clone());
-        let payload = read_json(&path).unwrap_or(Value::Object(Map::new()));
+        let payload = read_json(&path)?.unwrap_or(Value::Object(Map::new()));
         let Some(obj) = payload.as_object() else {
-            return manifest;
+            return Ok(manifest);
         };
 
         manifest.schema_version = obj
@@ -75,6 +111,16 @@ impl ThreadManifest {
             .map(str::to_string)
             .unwrap_or(manifest.created_at);
 
+        manifest.layout = match obj.get("layout") {
+            None | Some(Value::Null) => RunLayout::default(),
+            Some(Value::String(layout)) => RunLayout::parse(layout)
+                .with_context(|| format!("{} records an unknown layout", path.display()))?,
+            Some(other) => anyhow::bail!(
+                "{} records layout {other}, expected a string",
+                path.display()
+            ),
+        };
+
         if let Some(summary) = obj.get("context_summary").and_then(Value::as_object) {
             manifest.context_summary = ContextSummary {
                 text: summary
@@ -96,7 +142,112 @@ impl ThreadManifest {
                 }
             }
         }
-        manifest
+        Ok(manifest)
+    }
+
+    /// Rebuilds the manifest at `path` from what a lost `thread.json`
+    /// leaves behind: each per-version `version.json` as written, and for
+    /// versions without one, their receipts. A version rebuilt from receipts
+    /// keeps its prompt, model, size, seed and artifacts, but not its parent,
+    /// intent, selection or feedback. `None` when neither source names a
+    /// version.
+    pub fn rebuild(path: impl Into<PathBuf>) -> anyhow::Result<Option<Self>> {
+        let mut manifest = Self::new(path);
+        let run_dir = manifest
+            .path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf();
+        let mut versions: BTreeMap<(u64, String), VersionEntry> = BTreeMap::new();
+        for entry in std::fs::read_dir(&run_dir)?.flatten() {
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            if !is_version_dir_name(&dir_name) {
+                continue;
+            }
+            let Ok(Some(payload)) = read_json(&entry.path().join(VERSION_FILENAME)) else {
+                continue;
+            };
+            if let Ok(version) = serde_json::from_value::<VersionEntry>(payload) {
+                manifest.layout = RunLayout::PerVersion;
+                versions.insert(version_order(&version.version_id), version);
+            }
+        }
+        let recorded: Vec<(u64, String)> = versions.keys().cloned().collect();
+        for receipt_path in receipt_files(&run_dir)? {
+            let Some(artifact_id) = receipt_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix("receipt-"))
+            else {
+                continue;
+            };
+            let version_id = artifact_id.split('-').next().unwrap_or(artifact_id);
+            let key = version_order(version_id);
+            if recorded.contains(&key) {
+                continue;
+            }
+            let Ok(Some(Value::Object(receipt))) = read_json(&receipt_path) else {
+                continue;
+            };
+            let request = receipt
+                .get("request")
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default();
+            let version = versions.entry(key).or_insert_with(|| {
+                let mut intent = Map::new();
+                intent.insert(
+                    "rebuilt_from".to_string(),
+                    Value::String("receipts".to_string()),
+                );
+                VersionEntry {
+                    version_id: version_id.to_string(),
+                    parent_version_id: None,
+                    intent,
+                    settings: ["model", "size", "seed", "output_format"]
+                        .into_iter()
+                        .filter_map(|key| {
+                            let value = request.get(key).filter(|value| !value.is_null())?;
+                            Some((key.to_string(), value.clone()))
+                        })
+                        .collect(),
+                    prompt: request
+                        .get("prompt")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    prompt_diff: None,
+                    settings_diff: None,
+                    artifacts: Vec::new(),
+                    selected_artifact_id: None,
+                    feedback: Vec::new(),
+                    deleted_at: None,
+                    reverted_at: None,
+                }
+            });
+            let mut artifact = Map::new();
+            artifact.insert(
+                "artifact_id".to_string(),
+                Value::String(artifact_id.to_string()),
+            );
+            if let Some(files) = receipt.get("artifacts").and_then(Value::as_object) {
+                for key in ["image_path", "video_path"] {
+                    if let Some(path) = files.get(key) {
+                        artifact.insert(key.to_string(), path.clone());
+                    }
+                }
+            }
+            artifact.insert(
+                "receipt_path".to_string(),
+                Value::String(receipt_path.to_string_lossy().to_string()),
+            );
+            version.artifacts.push(artifact);
+        }
+        if versions.is_empty() {
+            return Ok(None);
+        }
+        manifest.versions = versions.into_values().collect();
+        Ok(Some(manifest))
     }
 
     pub fn add_version(
@@ -120,6 +271,8 @@ impl ThreadManifest {
             artifacts: Vec::new(),
             selected_artifact_id: None,
             feedback: Vec::new(),
+            deleted_at: None,
+            reverted_at: None,
         };
         self.versions.push(version.clone());
         version
@@ -153,6 +306,143 @@ impl ThreadManifest {
         }
     }
 
+    /// Versions that have not been soft-deleted or reverted, in thread order.
+    pub fn live_versions(&self) -> impl Iterator<Item = &VersionEntry> {
+        self.versions.iter().filter(|version| version.is_live())
+    }
+
+    /// Live versions depth-first by `parent_version_id`, with their depth.
+    /// Versions whose parent is missing or deleted are roots.
+    pub fn version_tree(&self) -> Vec<(usize, &VersionEntry)> {
+        let live: Vec<&VersionEntry> = self.live_versions().collect();
+        let is_root = |version: &VersionEntry| {
+            version
+                .parent_version_id
+                .as_deref()
+                .is_none_or(|parent| !live.iter().any(|candidate| candidate.version_id == parent))
+        };
+        let mut rows = Vec::new();
+        let mut stack: Vec<(usize, &VersionEntry)> = live
+            .iter()
+            .rev()
+            .filter(|version| is_root(version))
+            .map(|version| (0, *version))
+            .collect();
+        while let Some((depth, version)) = stack.pop() {
+            rows.push((depth, version));
+            stack.extend(
+                live.iter()
+                    .rev()
+                    .filter(|child| {
+                        child.parent_version_id.as_deref() == Some(version.version_id.as_str())
+                    })
+                    .map(|child| (depth + 1, *child)),
+            );
+        }
+        rows
+    }
+
+    pub fn delete_version(&mut self, version_id: &str) -> anyhow::Result<&VersionEntry> {
+        let Some(version) = self.get_version_mut(Some(version_id)) else {
+            anyhow::bail!("version '{version_id}' not found");
+        };
+        if version.is_deleted() {
+            anyhow::bail!("version '{version_id}' is already deleted");
+        }
+        version.deleted_at = Some(now_utc_iso());
+        Ok(version)
+    }
+
+    /// Marks a live version as reverted (undo).
+    pub fn revert_version(&mut self, version_id: &str) -> anyhow::Result<&VersionEntry> {
+        let Some(version) = self.get_version_mut(Some(version_id)) else {
+            anyhow::bail!("version '{version_id}' not found");
+        };
+        if !version.is_live() {
+            anyhow::bail!("version '{version_id}' is already deleted or reverted");
+        }
+        version.reverted_at = Some(now_utc_iso());
+        Ok(version)
+    }
+
+    /// Undoes a soft-delete or a revert.
+    pub fn restore_version(&mut self, version_id: &str) -> anyhow::Result<&VersionEntry> {
+        let Some(version) = self.get_version_mut(Some(version_id)) else {
+            anyhow::bail!("version '{version_id}' not found");
+        };
+        if version.is_live() {
+            anyhow::bail!("version '{version_id}' is not deleted");
+        }
+        version.deleted_at = None;
+        version.reverted_at = None;
+        Ok(version)
+    }
+
+    pub fn find_artifact(&self, artifact_id: &str) -> Option<(&VersionEntry, &Map<String, Value>)> {
+        self.versions.iter().find_map(|version| {
+            version
+                .artifacts
+                .iter()
+                .find(|artifact| {
+                    artifact.get("artifact_id").and_then(Value::as_str) == Some(artifact_id)
+                })
+                .map(|artifact| (version, artifact))
+        })
+    }
+
+    /// Applies `labels` to an artifact's `tags`: a leading `-` removes the
+    /// label, anything else adds it. Labels are lowercased; returns the
+    /// owning version id and the resulting tags.
+    pub fn tag_artifact(
+        &mut self,
+        artifact_id: &str,
+        labels: &[String],
+    ) -> anyhow::Result<(String, Vec<String>)> {
+        let Some((version_id, artifact)) = self.versions.iter_mut().find_map(|version| {
+            let version_id = &version.version_id;
+            version
+                .artifacts
+                .iter_mut()
+                .find(|artifact| {
+                    artifact.get("artifact_id").and_then(Value::as_str) == Some(artifact_id)
+                })
+                .map(|artifact| (version_id.clone(), artifact))
+        }) else {
+            anyhow::bail!("artifact '{artifact_id}' not found");
+        };
+        let mut tags = artifact_tags(artifact);
+        for label in labels {
+            let (remove, label) = match label.trim().strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, label.trim()),
+            };
+            let label = label.trim().to_ascii_lowercase();
+            if label.is_empty() {
+                continue;
+            }
+            if remove {
+                tags.retain(|tag| *tag != label);
+            } else if !tags.contains(&label) {
+                tags.push(label);
+            }
+        }
+        artifact.insert(
+            "tags".to_string(),
+            Value::Array(tags.iter().cloned().map(Value::String).collect()),
+        );
+        Ok((version_id, tags))
+    }
+
+    /// Id of the newest artifact in a live version.
+    pub fn latest_artifact_id(&self) -> Option<&str> {
+        self.versions
+            .iter()
+            .rev()
+            .filter(|version| version.is_live())
+            .flat_map(|version| version.artifacts.iter().rev())
+            .find_map(|artifact| artifact.get("artifact_id").and_then(Value::as_str))
+    }
+
     pub fn update_context_summary(&mut self, text: &str) {
         self.context_summary = ContextSummary {
             text: text.to_string(),
@@ -174,24 +464,41 @@ impl ThreadManifest {
             "created_at".to_string(),
             Value::String(self.created_at.clone()),
         );
-        payload.insert(
-            "versions".to_string(),
-            Value::Array(
-                self.versions
-                    .iter()
-                    .map(|entry| serde_json::to_value(entry).unwrap_or(Value::Null))
-                    .collect(),
-            ),
-        );
+        if self.layout != RunLayout::Flat {
+            payload.insert(
+                "layout".to_string(),
+                Value::String(self.layout.label().to_string()),
+            );
+        }
+        let versions: Vec<Value> = self
+            .versions
+            .iter()
+            .map(|entry| serde_json::to_value(entry).unwrap_or(Value::Null))
+            .collect();
+        if self.layout == RunLayout::PerVersion {
+            let run_dir = self.path.parent().unwrap_or(Path::new("."));
+            for (entry, value) in self.versions.iter().zip(&versions) {
+                write_version_file(
+                    &self
+                        .layout
+                        .version_dir(run_dir, &entry.version_id)
+                        .join(VERSION_FILENAME),
+                    value,
+                )?;
+            }
+        }
+        payload.insert("versions".to_string(), Value::Array(versions));
         payload.insert(
             "context_summary".to_string(),
             serde_json::to_value(&self.context_summary).unwrap_or(Value::Null),
         );
 
-        write_json(&self.path, Value::Object(payload))
+        write_json_atomic(&self.path, &Value::Object(payload))
     }
 
-    fn next_version_id(&self) -> String {
+    /// The id [`ThreadManifest::add_version`] hands out next, for placing
+    /// files before the version is recorded.
+    pub fn next_version_id(&self) -> String {
         format!("v{}", self.versions.len() + 1)
     }
 
@@ -208,6 +515,20 @@ impl ThreadManifest {
     }
 }
 
+/// Tags recorded on a thread artifact, in the order they were added.
+pub fn artifact_tags(artifact: &Map<String, Value>) -> Vec<String> {
+    artifact
+        .get("tags")
+        .and_then(Value::as_array)
+        .map(|tags| {
+            tags.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 fn prompt_diff(prev: Option<&str>, curr: &str) -> Option<Vec<String>> {
     let prev = prev?;
     let diff = TextDiff::from_lines(prev, curr);
@@ -248,21 +569,27 @@ fn settings_diff(
     Some(diff)
 }
 
-fn now_utc_iso() -> String {
-    Utc::now().to_rfc3339_opts(SecondsFormat::Micros, false)
+/// Sorts `v2` before `v10`; ids that are not `v<number>` go last.
+fn version_order(version_id: &str) -> (u64, String) {
+    let number = version_id
+        .strip_prefix('v')
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(u64::MAX);
+    (number, version_id.to_string())
 }
 
-fn read_json(path: &Path) -> anyhow::Result<Value> {
-    let raw = std::fs::read_to_string(path)?;
-    Ok(serde_json::from_str(&raw)?)
+fn now_utc_iso() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Micros, false)
 }
 
-fn write_json(path: &Path, payload: Value) -> anyhow::Result<()> {
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+/// Skips unchanged files, so saving a long thread only touches the
+/// versions that moved.
+fn write_version_file(path: &Path, payload: &Value) -> anyhow::Result<()> {
+    let text = serde_json::to_string_pretty(payload)?;
+    if std::fs::read_to_string(path).is_ok_and(|existing| existing == text) {
+        return Ok(());
     }
-    std::fs::write(path, serde_json::to_string_pretty(&payload)?)?;
-    Ok(())
+    write_atomic(path, text.as_bytes())
 }
 
 #[cfg(test)]
@@ -299,7 +626,7 @@ mod tests {
         manifest.add_artifact(&v2.version_id, artifact);
         manifest.save()?;
 
-        let loaded = ThreadManifest::load(&path);
+        let loaded = ThreadManifest::load(&path)?;
         assert_eq!(loaded.versions.len(), 2);
         assert_eq!(
             loaded.versions[1].parent_version_id.as_deref(),
@@ -311,6 +638,146 @@ mod tests {
             loaded.versions[1].artifacts[0].get("artifact_id"),
             Some(&json!("a1"))
         );
+        let (owner, _) = loaded.find_artifact("a1").expect("artifact a1");
+        assert_eq!(owner.version_id, v2.version_id);
+        assert!(loaded.find_artifact("missing").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn tag_artifact_adds_removes_and_dedupes_labels() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("thread.json");
+        let mut manifest = ThreadManifest::new(&path);
+        let version = manifest.add_version(Map::new(), Map::new(), "A".to_string(), None);
+        let mut artifact = Map::new();
+        artifact.insert("artifact_id".to_string(), json!("a1"));
+        manifest.add_artifact(&version.version_id, artifact);
+        assert_eq!(manifest.latest_artifact_id(), Some("a1"));
+
+        let labels = |raw: &[&str]| {
+            raw.iter()
+                .map(|label| label.to_string())
+                .collect::<Vec<_>>()
+        };
+        let (owner, tags) = manifest.tag_artifact("a1", &labels(&["Hero", "night", "hero"]))?;
+        assert_eq!(owner, "v1");
+        assert_eq!(tags, vec!["hero", "night"]);
+        let (_, tags) = manifest.tag_artifact("a1", &labels(&["-night", "favorite"]))?;
+        assert_eq!(tags, vec!["hero", "favorite"]);
+        assert!(manifest.tag_artifact("missing", &labels(&["x"])).is_err());
+        manifest.save()?;
+
+        let loaded = ThreadManifest::load(&path)?;
+        let (_, artifact) = loaded.find_artifact("a1").expect("artifact a1");
+        assert_eq!(super::artifact_tags(artifact), vec!["hero", "favorite"]);
+        Ok(())
+    }
+
+    #[test]
+    fn version_tree_nests_branches_under_their_parent() {
+        let mut manifest = ThreadManifest::new("thread.json");
+        manifest.add_version(Map::new(), Map::new(), "A".to_string(), None);
+        manifest.add_version(
+            Map::new(),
+            Map::new(),
+            "B".to_string(),
+            Some("v1".to_string()),
+        );
+        manifest.add_version(
+            Map::new(),
+            Map::new(),
+            "C".to_string(),
+            Some("v2".to_string()),
+        );
+        manifest.add_version(
+            Map::new(),
+            Map::new(),
+            "D".to_string(),
+            Some("v1".to_string()),
+        );
+        manifest.add_version(
+            Map::new(),
+            Map::new(),
+            "E".to_string(),
+            Some("v9".to_string()),
+        );
+        let tree: Vec<(usize, &str)> = manifest
+            .version_tree()
+            .into_iter()
+            .map(|(depth, version)| (depth, version.version_id.as_str()))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![(0, "v1"), (1, "v2"), (2, "v3"), (1, "v4"), (0, "v5")]
+        );
+    }
+
+    #[test]
+    fn soft_delete_hides_version_until_restored() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("thread.json");
+        let mut manifest = ThreadManifest::new(&path);
+        manifest.add_version(Map::new(), Map::new(), "A".to_string(), None);
+        manifest.add_version(Map::new(), Map::new(), "B".to_string(), None);
+
+        manifest.delete_version("v1")?;
+        assert!(manifest.delete_version("v1").is_err());
+        assert!(manifest.delete_version("v9").is_err());
+        manifest.save()?;
+
+        let mut loaded = ThreadManifest::load(&path)?;
+        assert!(loaded.versions[0].is_deleted());
+        let live: Vec<&str> = loaded
+            .live_versions()
+            .map(|version| version.version_id.as_str())
+            .collect();
+        assert_eq!(live, vec!["v2"]);
+        assert!(
+            loaded
+                .add_version(Map::new(), Map::new(), "C".to_string(), None)
+                .version_id
+                == "v3"
+        );
+
+        loaded.restore_version("v1")?;
+        assert!(loaded.restore_version("v1").is_err());
+        assert_eq!(loaded.live_versions().count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_prefers_version_files_and_falls_back_to_receipts() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let path = tmp.path().join("thread.json");
+        let mut manifest = ThreadManifest::new(&path);
+        manifest.layout = crate::runs::layout::RunLayout::PerVersion;
+        manifest.add_version(Map::new(), Map::new(), "A".to_string(), None);
+        manifest.add_version(Map::new(), Map::new(), "B".to_string(), Some("v1".into()));
+        manifest.save()?;
+        std::fs::remove_file(tmp.path().join("v-0002").join("version.json"))?;
+        std::fs::write(
+            tmp.path().join("v-0002").join("receipt-v2-01-abc.json"),
+            json!({
+                "request": {"prompt": "B", "size": "64x64", "seed": null},
+                "artifacts": {"image_path": "/runs/x/v2-01.png"},
+            })
+            .to_string(),
+        )?;
+        std::fs::write(&path, "{")?;
+
+        let rebuilt = ThreadManifest::rebuild(&path)?.expect("versions left");
+        assert_eq!(rebuilt.versions.len(), 2);
+        assert_eq!(rebuilt.versions[0], manifest.versions[0]);
+        let v2 = &rebuilt.versions[1];
+        assert_eq!((v2.version_id.as_str(), v2.prompt.as_str()), ("v2", "B"));
+        assert_eq!(v2.parent_version_id, None);
+        assert_eq!(Value::Object(v2.settings.clone()), json!({"size": "64x64"}));
+        assert_eq!(v2.artifacts[0]["artifact_id"], json!("v2-01-abc"));
+        assert_eq!(v2.artifacts[0]["image_path"], json!("/runs/x/v2-01.png"));
+
+        let empty = tempfile::tempdir()?;
+        assert!(ThreadManifest::rebuild(empty.path().join("thread.json"))?.is_none());
         Ok(())
     }
 }
@@ -0,0 +1,369 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use super::gc::is_pruned;
+use super::layout::receipt_files;
+use super::receipts::{file_sha256, RECEIPT_SCHEMA_VERSION, VIDEO_RECEIPT_SCHEMA_VERSION};
+use super::thread_manifest::ThreadManifest;
+
+/// One finding from [`verify_run`]. `code` is stable for scripting:
+/// `missing_file`, `hash_mismatch`, `unreadable_receipt`, `schema_version`,
+/// `missing_field`, `inconsistent_request`, `path_mismatch`, `no_hash`,
+/// `pruned`, `unreadable_thread`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyIssue {
+    pub path: PathBuf,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub receipts_checked: usize,
+    pub artifacts_checked: usize,
+    /// Mismatches that make the run fail verification.
+    pub errors: Vec<VerifyIssue>,
+    /// Gaps that cannot be checked (e.g. receipts written before hashes
+    /// were recorded) but do not indicate tampering.
+    pub notes: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, path: &Path, code: &'static str, message: impl Into<String>) {
+        self.errors.push(VerifyIssue {
+            path: path.to_path_buf(),
+            code,
+            message: message.into(),
+        });
+    }
+
+    fn note(&mut self, path: &Path, code: &'static str, message: impl Into<String>) {
+        self.notes.push(VerifyIssue {
+            path: path.to_path_buf(),
+            code,
+            message: message.into(),
+        });
+    }
+}
+
+/// Checks every receipt in `run_dir` (plus any receipt the thread manifest
+/// points at elsewhere) and every artifact the manifest references.
+pub fn verify_run(run_dir: &Path) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    let mut receipts: BTreeSet<PathBuf> = receipt_files(run_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+
+    let thread_path = run_dir.join("thread.json");
+    let thread = match ThreadManifest::load(&thread_path) {
+        Ok(thread) => Some(thread),
+        Err(err) => {
+            report.error(&thread_path, "unreadable_thread", format!("{err:#}"));
+            None
+        }
+    };
+    if let Some(thread) = thread.filter(|_| thread_path.is_file()) {
+        for version in &thread.versions {
+            for artifact in &version.artifacts {
+                report.artifacts_checked += 1;
+                let label = artifact
+                    .get("artifact_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or("?");
+                if is_pruned(artifact) {
+                    report.note(
+                        &thread_path,
+                        "pruned",
+                        format!("artifact {label} was removed by gc"),
+                    );
+                    continue;
+                }
+                let media_path = ["image_path", "video_path"]
+                    .iter()
+                    .find_map(|key| artifact.get(*key).and_then(Value::as_str));
+                match media_path {
+                    Some(path) if Path::new(path).is_file() => {}
+                    Some(path) => report.error(
+                        Path::new(path),
+                        "missing_file",
+                        format!("artifact {label} ({}) file is missing", version.version_id),
+                    ),
+                    None => report.error(
+                        &thread_path,
+                        "missing_field",
+                        format!("artifact {label} has no image_path/video_path"),
+                    ),
+                }
+                let Some(receipt_path) = artifact.get("receipt_path").and_then(Value::as_str)
+                else {
+                    continue;
+                };
+                let receipt_path = PathBuf::from(receipt_path);
+                if !receipt_path.is_file() {
+                    report.error(
+                        &receipt_path,
+                        "missing_file",
+                        format!("receipt for artifact {label} is missing"),
+                    );
+                    continue;
+                }
+                if let (Some(expected), Some(receipt)) = (media_path, read_object(&receipt_path)) {
+                    let recorded = receipt
+                        .get("artifacts")
+                        .and_then(Value::as_object)
+                        .and_then(|artifacts| {
+                            ["image_path", "video_path"]
+                                .iter()
+                                .find_map(|key| artifacts.get(*key).and_then(Value::as_str))
+                        });
+                    if recorded.is_some_and(|recorded| recorded != expected) {
+                        report.error(
+                            &receipt_path,
+                            "path_mismatch",
+                            format!(
+                                "thread artifact {label} points at {expected} but the receipt records {}",
+                                recorded.unwrap_or_default()
+                            ),
+                        );
+                    }
+                }
+                receipts.insert(receipt_path);
+            }
+        }
+    }
+
+    for receipt_path in receipts {
+        report.receipts_checked += 1;
+        verify_receipt(&receipt_path, &mut report);
+    }
+    report
+}
+
+fn verify_receipt(path: &Path, report: &mut VerifyReport) {
+    let Some(receipt) = read_object(path) else {
+        report.error(path, "unreadable_receipt", "receipt is not a JSON object");
+        return;
+    };
+    let is_video = receipt.get("kind").and_then(Value::as_str) == Some("video");
+    let expected_schema = if is_video {
+        VIDEO_RECEIPT_SCHEMA_VERSION
+    } else {
+        RECEIPT_SCHEMA_VERSION
+    };
+    match receipt.get("schema_version").and_then(Value::as_u64) {
+        Some(version) if version == expected_schema => {}
+        Some(version) => report.error(
+            path,
+            "schema_version",
+            format!("schema_version {version} (expected {expected_schema})"),
+        ),
+        None => report.error(path, "schema_version", "schema_version missing"),
+    }
+
+    let mut required = vec![
+        "request",
+        "provider_request",
+        "provider_response",
+        "artifacts",
+    ];
+    if !is_video {
+        required.push("resolved");
+    }
+    for key in required {
+        if !receipt.get(key).is_some_and(Value::is_object) {
+            report.error(
+                path,
+                "missing_field",
+                format!("'{key}' missing or not an object"),
+            );
+        }
+    }
+    if receipt
+        .get("provider_request")
+        .and_then(Value::as_object)
+        .is_some_and(Map::is_empty)
+    {
+        report.error(
+            path,
+            "inconsistent_request",
+            "provider_request is empty but a provider_response was recorded",
+        );
+    }
+    if let (Some(request), Some(resolved)) = (
+        receipt.get("request").and_then(Value::as_object),
+        receipt.get("resolved").and_then(Value::as_object),
+    ) {
+        for key in ["provider", "model", "n"] {
+            let (Some(left), Some(right)) = (request.get(key), resolved.get(key)) else {
+                continue;
+            };
+            if !left.is_null() && !right.is_null() && left != right {
+                report.error(
+                    path,
+                    "inconsistent_request",
+                    format!("request.{key} {left} does not match resolved.{key} {right}"),
+                );
+            }
+        }
+    }
+    if let Some(response_model) = receipt
+        .get("provider_response")
+        .and_then(|response| response.get("model"))
+        .and_then(Value::as_str)
+    {
+        let request_model = receipt
+            .get("request")
+            .and_then(|request| request.get("model"))
+            .and_then(Value::as_str);
+        if request_model.is_some_and(|model| model != response_model) {
+            report.error(
+                path,
+                "inconsistent_request",
+                format!(
+                    "provider_response.model '{response_model}' does not match request.model '{}'",
+                    request_model.unwrap_or_default()
+                ),
+            );
+        }
+    }
+
+    let Some(artifacts) = receipt.get("artifacts").and_then(Value::as_object) else {
+        return;
+    };
+    for (path_key, hash_key) in [
+        ("image_path", "image_sha256"),
+        ("video_path", "video_sha256"),
+    ] {
+        let Some(media) = artifacts.get(path_key).and_then(Value::as_str) else {
+            continue;
+        };
+        let media = Path::new(media);
+        let Some(actual) = file_sha256(media) else {
+            report.error(
+                media,
+                "missing_file",
+                format!("{path_key} in receipt is missing"),
+            );
+            continue;
+        };
+        match artifacts.get(hash_key).and_then(Value::as_str) {
+            Some(recorded) if recorded == actual => {}
+            Some(recorded) => report.error(
+                media,
+                "hash_mismatch",
+                format!("sha256 {actual} does not match recorded {recorded}"),
+            ),
+            None => report.note(path, "no_hash", format!("receipt records no {hash_key}")),
+        }
+    }
+}
+
+fn read_object(path: &Path) -> Option<Map<String, Value>> {
+    let raw = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str::<Value>(&raw)
+        .ok()?
+        .as_object()
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::verify_run;
+    use crate::runs::receipts::{
+        build_receipt, write_receipt, ImageInputs, ImageRequest, ResolvedRequest,
+    };
+    use crate::runs::thread_manifest::ThreadManifest;
+
+    #[test]
+    fn verify_detects_tampered_artifacts_and_receipts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path();
+        let image_path = run_dir.join("artifact-1.png");
+        let receipt_path = run_dir.join("receipt-a1.json");
+        std::fs::write(&image_path, b"pixels")?;
+        let request: ImageRequest = serde_json::from_value(json!({
+            "prompt": "boat",
+            "provider": "dryrun",
+            "model": "dryrun-image-1",
+        }))?;
+        let resolved = ResolvedRequest {
+            provider: "dryrun".to_string(),
+            model: Some("dryrun-image-1".to_string()),
+            size: "1024x1024".to_string(),
+            width: None,
+            height: None,
+            output_format: "png".to_string(),
+            background: None,
+            seed: None,
+            n: 1,
+            user: None,
+            prompt: "boat".to_string(),
+            inputs: ImageInputs::default(),
+            stream: false,
+            partial_images: None,
+            provider_params: Map::new(),
+            warnings: Vec::new(),
+            safety: Map::new(),
+        };
+        let provider_request = json!({"endpoint": "dryrun"})
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        let provider_response = json!({"status": "ok", "model": "dryrun-image-1"})
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        let receipt = build_receipt(
+            &request,
+            &resolved,
+            &provider_request,
+            &provider_response,
+            &[],
+            &image_path,
+            None,
+            &receipt_path,
+            &Map::new(),
+        );
+        write_receipt(&receipt_path, &receipt)?;
+        let mut thread = ThreadManifest::new(run_dir.join("thread.json"));
+        let version = thread.add_version(Map::new(), Map::new(), "boat".to_string(), None);
+        let mut artifact = Map::new();
+        artifact.insert("artifact_id".to_string(), json!("a1"));
+        artifact.insert(
+            "image_path".to_string(),
+            json!(image_path.to_string_lossy()),
+        );
+        artifact.insert(
+            "receipt_path".to_string(),
+            json!(receipt_path.to_string_lossy()),
+        );
+        thread.add_artifact(&version.version_id, artifact);
+        thread.save()?;
+
+        let report = verify_run(run_dir);
+        assert!(report.is_ok(), "{:?}", report.errors);
+        assert_eq!((report.receipts_checked, report.artifacts_checked), (1, 1));
+
+        std::fs::write(&image_path, b"edited")?;
+        let mut tampered: Value = serde_json::from_str(&std::fs::read_to_string(&receipt_path)?)?;
+        tampered["schema_version"] = json!(99);
+        tampered["resolved"]["model"] = json!("other-model");
+        std::fs::write(&receipt_path, serde_json::to_string(&tampered)?)?;
+
+        let report = verify_run(run_dir);
+        let codes: Vec<&str> = report.errors.iter().map(|issue| issue.code).collect();
+        assert!(codes.contains(&"hash_mismatch"));
+        assert!(codes.contains(&"schema_version"));
+        assert!(codes.contains(&"inconsistent_request"));
+        Ok(())
+    }
+}
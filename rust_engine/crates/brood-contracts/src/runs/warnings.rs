@@ -0,0 +1,187 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const WARNING_CODES_DOC: &str = "docs/warning_codes.md";
+
+/// Stable warning codes, documented in `docs/warning_codes.md`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    ParamSnapped,
+    ParamClamped,
+    ValueReplaced,
+    ValueDropped,
+    ModelRemapped,
+    TransportRetry,
+    TransportFallback,
+    BackendFallback,
+    PartialResult,
+    FormatConverted,
+    NearDuplicate,
+    ArtifactMismatch,
+    ContextScrubbed,
+    ProviderNote,
+}
+
+impl WarningCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ParamSnapped => "param_snapped",
+            Self::ParamClamped => "param_clamped",
+            Self::ValueReplaced => "value_replaced",
+            Self::ValueDropped => "value_dropped",
+            Self::ModelRemapped => "model_remapped",
+            Self::TransportRetry => "transport_retry",
+            Self::TransportFallback => "transport_fallback",
+            Self::BackendFallback => "backend_fallback",
+            Self::PartialResult => "partial_result",
+            Self::FormatConverted => "format_converted",
+            Self::NearDuplicate => "near_duplicate",
+            Self::ArtifactMismatch => "artifact_mismatch",
+            Self::ContextScrubbed => "context_scrubbed",
+            Self::ProviderNote => "provider_note",
+        }
+    }
+}
+
+/// A provider/engine warning, coded where it is raised. `message` is the
+/// human text receipts and events show; the other fields name what changed
+/// when the warning is about one parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub applied: Option<String>,
+}
+
+impl Warning {
+    pub fn new(code: WarningCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            parameter: None,
+            requested: None,
+            applied: None,
+        }
+    }
+
+    /// Free text from a provider or plugin, which has no code of its own.
+    pub fn note(message: impl Into<String>) -> Self {
+        Self::new(WarningCode::ProviderNote, message)
+    }
+
+    pub fn parameter(mut self, parameter: impl Into<String>) -> Self {
+        self.parameter = Some(parameter.into());
+        self
+    }
+
+    pub fn requested(mut self, requested: impl ToString) -> Self {
+        self.requested = Some(requested.to_string());
+        self
+    }
+
+    pub fn applied(mut self, applied: impl ToString) -> Self {
+        self.applied = Some(applied.to_string());
+        self
+    }
+
+    /// The `warning_details` entry for this warning.
+    pub fn coded(&self) -> CodedWarning {
+        CodedWarning {
+            code: self.code.as_str().to_string(),
+            parameter: self.parameter.clone(),
+            requested: self.requested.clone(),
+            applied: self.applied.clone(),
+            docs_hint: format!("{WARNING_CODES_DOC}#{}", self.code.as_str()),
+            message: self.message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Machine-readable form of a warning, as receipts and events carry it.
+/// `message` keeps the original human text; the other fields let UIs group
+/// and link remediation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CodedWarning {
+    pub code: String,
+    pub parameter: Option<String>,
+    pub requested: Option<String>,
+    pub applied: Option<String>,
+    pub docs_hint: String,
+    pub message: String,
+}
+
+/// `warning_details` for receipts and events.
+pub fn coded_warnings(warnings: &[Warning]) -> Value {
+    Value::Array(
+        warnings
+            .iter()
+            .map(|warning| serde_json::to_value(warning.coded()).unwrap_or(Value::Null))
+            .collect(),
+    )
+}
+
+/// The human text of each warning, for the `warnings` lists of receipts
+/// and events.
+pub fn warning_messages(warnings: &[Warning]) -> Vec<String> {
+    warnings
+        .iter()
+        .map(|warning| warning.message.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{coded_warnings, warning_messages, Warning, WarningCode};
+
+    #[test]
+    fn warnings_carry_the_code_they_were_raised_with() {
+        let snapped = Warning::new(
+            WarningCode::ParamSnapped,
+            "OpenAI size snapped to 1536x1024.",
+        )
+        .parameter("size")
+        .requested("1500x1000")
+        .applied("1536x1024");
+        let coded = snapped.coded();
+        assert_eq!(coded.code, "param_snapped");
+        assert_eq!(coded.parameter.as_deref(), Some("size"));
+        assert_eq!(coded.requested.as_deref(), Some("1500x1000"));
+        assert_eq!(coded.applied.as_deref(), Some("1536x1024"));
+        assert_eq!(coded.docs_hint, "docs/warning_codes.md#param_snapped");
+        assert_eq!(snapped.to_string(), "OpenAI size snapped to 1536x1024.");
+
+        // Rewording a message never changes its code.
+        let note = Warning::note("Size snapped, but differently worded.");
+        assert_eq!(note.coded().code, "provider_note");
+
+        let warnings = [snapped, note];
+        assert_eq!(
+            warning_messages(&warnings),
+            [
+                "OpenAI size snapped to 1536x1024.",
+                "Size snapped, but differently worded."
+            ]
+        );
+        assert_eq!(coded_warnings(&warnings)[1]["code"], json!("provider_note"));
+        assert_eq!(
+            serde_json::to_value(WarningCode::ContextScrubbed).ok(),
+            Some(json!("context_scrubbed"))
+        );
+    }
+}
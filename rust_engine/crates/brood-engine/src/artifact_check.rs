@@ -0,0 +1,257 @@
+use std::fs;
+use std::path::Path;
+
+use brood_contracts::runs::receipts::file_sha256;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use image::GenericImageView;
+use serde_json::{json, Value};
+
+use super::output_format::{is_svg, open_image, svg_dimensions, OutputFormat};
+
+/// How far, as a fraction of the requested side, either side of the
+/// returned image may be off before it is reported.
+pub(crate) const DIMENSION_TOLERANCE: f64 = 0.05;
+
+/// The exact `WxH` a `size` setting asks for; `None` for `auto`, aspect
+/// ratios and resolution hints such as `2K`.
+pub(crate) fn requested_dims(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size
+        .trim()
+        .to_ascii_lowercase()
+        .split_once('x')
+        .map(|(w, h)| (w.trim().parse::<u32>().ok(), h.trim().parse::<u32>().ok()))?;
+    Some((width?, height?)).filter(|(width, height)| *width > 0 && *height > 0)
+}
+
+fn within_tolerance(actual: (u32, u32), requested: (u32, u32)) -> bool {
+    let close = |actual: u32, requested: u32| {
+        (f64::from(actual) - f64::from(requested)).abs()
+            <= f64::from(requested) * DIMENSION_TOLERANCE
+    };
+    close(actual.0, requested.0) && close(actual.1, requested.1)
+}
+
+/// What checking a provider's file found, before any local step rewrites
+/// it. Mismatches become warnings rather than errors: the artifact is kept
+/// so the receipt can say what went wrong.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ArtifactCheck {
+    pub(crate) sha256: Option<String>,
+    pub(crate) bytes: u64,
+    /// What the bytes are (`png`, `jpg`, `webp`, `avif`, `svg`), whatever
+    /// the extension says.
+    pub(crate) format: Option<String>,
+    /// The exact size the request asked for, if it named one.
+    pub(crate) requested: Option<(u32, u32)>,
+    /// Decoded size, or an SVG's declared one; `None` for AVIF and images
+    /// that fail to decode.
+    pub(crate) dims: Option<(u32, u32)>,
+    pub(crate) warnings: Vec<Warning>,
+}
+
+impl ArtifactCheck {
+    /// Checks the file at `path` decodes as the format its extension
+    /// claims and is within [`DIMENSION_TOLERANCE`] of the `requested` size.
+    /// `sha256` is the digest taken while the file was written, if any;
+    /// otherwise the file is hashed.
+    pub(crate) fn run(path: &Path, sha256: Option<&str>, requested: Option<(u32, u32)>) -> Self {
+        let mut check = Self {
+            sha256: sha256.map(str::to_string).or_else(|| file_sha256(path)),
+            requested,
+            bytes: fs::metadata(path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0),
+            ..Self::default()
+        };
+        if check.bytes == 0 {
+            check.warnings.push(Warning::new(
+                WarningCode::ArtifactMismatch,
+                "Artifact check: the file is empty.",
+            ));
+            return check;
+        }
+        // Vectors have no pixel size to hold to the request.
+        if is_svg(path) {
+            check.format = Some("svg".to_string());
+            check.dims = fs::read(path)
+                .ok()
+                .and_then(|document| svg_dimensions(&document));
+            return check;
+        }
+        let Some(format) = OutputFormat::sniff(path) else {
+            check.warnings.push(Warning::new(
+                WarningCode::ArtifactMismatch,
+                "Artifact check: the bytes are not a recognized image format.",
+            ));
+            return check;
+        };
+        check.format = Some(format.extension().to_string());
+        if let Some(claimed) = OutputFormat::from_path(path).filter(|claimed| *claimed != format) {
+            check.warnings.push(
+                Warning::new(
+                    WarningCode::ArtifactMismatch,
+                    format!(
+                        "Artifact check: the bytes are {}, not the {} its extension claims.",
+                        format.extension().to_ascii_uppercase(),
+                        claimed.extension().to_ascii_uppercase()
+                    ),
+                )
+                .parameter("format")
+                .requested(claimed.extension())
+                .applied(format.extension()),
+            );
+        }
+        // AVIF cannot be decoded locally.
+        if format == OutputFormat::Avif {
+            return check;
+        }
+        match open_image(path) {
+            Ok(image) => check.dims = Some(image.dimensions()),
+            Err(err) => {
+                check.warnings.push(Warning::new(
+                    WarningCode::ArtifactMismatch,
+                    format!(
+                        "Artifact check: the {} does not decode ({}); the download may be truncated.",
+                        format.extension().to_ascii_uppercase(),
+                        err.root_cause()
+                    ),
+                ));
+                return check;
+            }
+        }
+        if let (Some(actual), Some(requested)) = (check.dims, requested) {
+            if !within_tolerance(actual, requested) {
+                check.warnings.push(
+                    Warning::new(
+                        WarningCode::ArtifactMismatch,
+                        format!(
+                            "Artifact check: the image is {}x{}, not the requested {}x{}.",
+                            actual.0, actual.1, requested.0, requested.1
+                        ),
+                    )
+                    .parameter("size")
+                    .requested(format!("{}x{}", requested.0, requested.1))
+                    .applied(format!("{}x{}", actual.0, actual.1)),
+                );
+            }
+        }
+        check
+    }
+
+    /// Whether the file is empty, unrecognized or fails to decode, so no
+    /// local step can read its pixels.
+    pub(crate) fn is_unreadable(&self) -> bool {
+        match self.format.as_deref() {
+            None => true,
+            Some("svg" | "avif") => false,
+            Some(_) => self.dims.is_none(),
+        }
+    }
+
+    /// `result_metadata.provider_artifact` in receipts and artifact metrics.
+    pub(crate) fn to_value(&self) -> Value {
+        let mut value = json!({
+            "sha256": self.sha256,
+            "bytes": self.bytes,
+            "format": self.format,
+            "valid": self.warnings.is_empty(),
+        });
+        if let Some((width, height)) = self.dims {
+            value["width"] = json!(width);
+            value["height"] = json!(height);
+        }
+        value
+    }
+
+    /// `result_metadata.dimensions`: the requested and actual `WxH`, `null`
+    /// when unknown.
+    pub(crate) fn dimensions_value(&self) -> Value {
+        let text =
+            |dims: Option<(u32, u32)>| dims.map(|(width, height)| format!("{width}x{height}"));
+        json!({
+            "requested": text(self.requested),
+            "actual": text(self.dims),
+            "within_tolerance": match (self.dims, self.requested) {
+                (Some(actual), Some(requested)) => Some(within_tolerance(actual, requested)),
+                _ => None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use brood_contracts::runs::warnings::{warning_messages, WarningCode};
+    use serde_json::json;
+
+    use super::{requested_dims, ArtifactCheck};
+    use crate::test_support::canned;
+
+    #[test]
+    fn truncated_mislabeled_and_resized_artifacts_warn() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let png = canned::png(8, 6);
+
+        let good = dir.path().join("good.png");
+        fs::write(&good, &png)?;
+        let check = ArtifactCheck::run(&good, None, Some((8, 6)));
+        assert!(check.warnings.is_empty(), "{:?}", check.warnings);
+        assert_eq!(check.dims, Some((8, 6)));
+        assert_eq!(check.bytes, png.len() as u64);
+        assert_eq!(check.to_value()["format"], "png");
+        assert_eq!(check.to_value()["valid"], true);
+        assert_eq!(check.sha256.as_ref().map(String::len), Some(64));
+
+        let resized = ArtifactCheck::run(&good, None, Some((16, 16)));
+        assert_eq!(
+            warning_messages(&resized.warnings),
+            ["Artifact check: the image is 8x6, not the requested 16x16."]
+        );
+        assert_eq!(resized.warnings[0].code, WarningCode::ArtifactMismatch);
+        assert_eq!(resized.warnings[0].applied.as_deref(), Some("8x6"));
+        assert_eq!(
+            resized.dimensions_value(),
+            json!({"requested": "16x16", "actual": "8x6", "within_tolerance": false})
+        );
+
+        // Within 5% per side is close enough.
+        let wide = dir.path().join("wide.png");
+        fs::write(&wide, canned::png(1000, 1024))?;
+        let check = ArtifactCheck::run(&wide, None, requested_dims("1024x1024"));
+        assert!(check.warnings.is_empty(), "{:?}", check.warnings);
+        assert_eq!(check.dimensions_value()["within_tolerance"], true);
+        assert_eq!(requested_dims("auto"), None);
+        assert_eq!(requested_dims("2K"), None);
+        assert_eq!(
+            ArtifactCheck::run(&wide, None, None).dimensions_value(),
+            json!({"requested": null, "actual": "1000x1024", "within_tolerance": null})
+        );
+
+        let mislabeled = dir.path().join("mislabeled.jpg");
+        fs::write(&mislabeled, &png)?;
+        let check = ArtifactCheck::run(&mislabeled, None, None);
+        assert_eq!(
+            warning_messages(&check.warnings),
+            ["Artifact check: the bytes are PNG, not the JPG its extension claims."]
+        );
+
+        let truncated = dir.path().join("truncated.png");
+        fs::write(&truncated, &png[..png.len() / 2])?;
+        let check = ArtifactCheck::run(&truncated, None, Some((8, 6)));
+        assert_eq!(check.dims, None);
+        assert!(check.warnings[0].message.contains("does not decode"));
+        assert_eq!(check.to_value()["valid"], false);
+        assert!(check.is_unreadable());
+        assert!(!ArtifactCheck::run(&good, None, Some((16, 16))).is_unreadable());
+
+        let empty = dir.path().join("empty.png");
+        fs::write(&empty, b"")?;
+        assert_eq!(
+            warning_messages(&ArtifactCheck::run(&empty, None, Some((8, 6))).warnings),
+            ["Artifact check: the file is empty."]
+        );
+        Ok(())
+    }
+}
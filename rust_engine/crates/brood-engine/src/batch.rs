@@ -0,0 +1,369 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_json_atomic;
+use brood_contracts::runs::run_dir::slugify;
+use serde_json::{json, Map, Value};
+
+use super::{
+    artifact_store_from_url, error_chain_text, map_object, now_utc_iso, CostBudget, CostLedger,
+    GlobalCache, Hooks, KeyringCredentials, NativeEngine,
+};
+
+pub const BATCH_SUMMARY_FILENAME: &str = "batch-summary.json";
+
+/// One prompt from a batch manifest. Keys other than `prompt`, `id`, `model`
+/// and `settings` are treated as settings overrides as well.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRow {
+    pub id: String,
+    pub prompt: String,
+    pub image_model: Option<String>,
+    pub settings: Map<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    pub out_dir: PathBuf,
+    pub concurrency: usize,
+    pub budget_usd: Option<f64>,
+    pub text_model: Option<String>,
+    pub image_model: Option<String>,
+    pub base_settings: Map<String, Value>,
+    pub global_cache_dir: Option<PathBuf>,
+    /// Cost ledger every row's engine appends to.
+    pub cost_ledger: Option<CostLedger>,
+    /// Artifact store URL (see [`crate::artifact_store_from_url`]) every
+    /// row uploads to.
+    pub artifact_store: Option<String>,
+    /// Keyring file (see [`KeyringCredentials`]) every row's engine takes
+    /// provider keys from.
+    pub keyring: Option<PathBuf>,
+    /// Hooks file (see [`Hooks`]) every row's engine runs.
+    pub hooks: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchRowOutcome {
+    pub id: String,
+    pub prompt: String,
+    pub status: String,
+    pub run_dir: PathBuf,
+    pub artifacts: Vec<String>,
+    pub cost_usd: f64,
+    pub latency_s: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchSummary {
+    pub rows: Vec<BatchRowOutcome>,
+    pub cost_total_usd: f64,
+    pub summary_path: PathBuf,
+}
+
+impl BatchSummary {
+    pub fn count(&self, status: &str) -> usize {
+        self.rows.iter().filter(|row| row.status == status).count()
+    }
+}
+
+pub fn load_batch_manifest(path: &Path) -> Result<Vec<BatchRow>> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("failed reading batch manifest {}", path.display()))?;
+    let mut rows = Vec::new();
+    for (line_no, line) in raw.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let parsed: Value = serde_json::from_str(trimmed)
+            .with_context(|| format!("invalid JSON on manifest line {}", line_no + 1))?;
+        let Some(obj) = parsed.as_object() else {
+            bail!("manifest line {} is not a JSON object", line_no + 1);
+        };
+        let prompt = obj
+            .get("prompt")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("manifest line {} has no prompt", line_no + 1))?
+            .to_string();
+        let id = obj
+            .get("id")
+            .and_then(|value| match value {
+                Value::String(text) => Some(text.trim().to_string()),
+                Value::Number(num) => Some(num.to_string()),
+                _ => None,
+            })
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| format!("{:03}", rows.len() + 1));
+        let mut settings = Map::new();
+        for (key, value) in obj {
+            if !matches!(key.as_str(), "prompt" | "id" | "model" | "settings") {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+        if let Some(overrides) = obj.get("settings").and_then(Value::as_object) {
+            for (key, value) in overrides {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+        rows.push(BatchRow {
+            id,
+            prompt,
+            image_model: obj
+                .get("model")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string),
+            settings,
+        });
+    }
+    Ok(rows)
+}
+
+/// Runs every manifest row through its own [`NativeEngine`] (one run dir per
+/// row, one shared cache), at most `concurrency` rows at a time. Rows that
+/// would start after the budget is spent are recorded as `skipped_budget`;
+/// a row whose estimate exceeds what is left, or whose model has no
+/// pricing, fails.
+pub fn run_batch(rows: &[BatchRow], config: &BatchConfig) -> Result<BatchSummary> {
+    fs::create_dir_all(&config.out_dir)?;
+    let started_at = now_utc_iso();
+    let cache_path = config.out_dir.join("cache.json");
+    let next = AtomicUsize::new(0);
+    let spent = Mutex::new(0.0f64);
+    let outcomes: Mutex<Vec<Option<BatchRowOutcome>>> = Mutex::new(vec![None; rows.len()]);
+    let workers = config.concurrency.clamp(1, rows.len().max(1));
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                let Some(row) = rows.get(idx) else {
+                    break;
+                };
+                let run_dir =
+                    config
+                        .out_dir
+                        .join(format!("{:03}-{}", idx + 1, slugify(&row.prompt)));
+                let budget_left = config
+                    .budget_usd
+                    .map(|budget| spent.lock().map(|value| budget - *value).unwrap_or(0.0));
+                let outcome = if budget_left.is_some_and(|left| left <= 0.0) {
+                    BatchRowOutcome {
+                        id: row.id.clone(),
+                        prompt: row.prompt.clone(),
+                        status: "skipped_budget".to_string(),
+                        run_dir,
+                        artifacts: Vec::new(),
+                        cost_usd: 0.0,
+                        latency_s: 0.0,
+                        error: None,
+                    }
+                } else {
+                    let outcome = run_batch_row(row, &run_dir, &cache_path, config, budget_left);
+                    if let Ok(mut total) = spent.lock() {
+                        *total += outcome.cost_usd;
+                    }
+                    outcome
+                };
+                if let Ok(mut slots) = outcomes.lock() {
+                    slots[idx] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let rows_out: Vec<BatchRowOutcome> = outcomes
+        .into_inner()
+        .map_err(|_| anyhow::anyhow!("batch results lock poisoned"))?
+        .into_iter()
+        .flatten()
+        .collect();
+    let cost_total_usd = rows_out.iter().map(|row| row.cost_usd).sum::<f64>();
+    let summary = BatchSummary {
+        rows: rows_out,
+        cost_total_usd,
+        summary_path: config.out_dir.join(BATCH_SUMMARY_FILENAME),
+    };
+    write_batch_summary(&summary, config, &started_at)?;
+    Ok(summary)
+}
+
+pub(crate) fn run_batch_row(
+    row: &BatchRow,
+    run_dir: &Path,
+    cache_path: &Path,
+    config: &BatchConfig,
+    budget_left: Option<f64>,
+) -> BatchRowOutcome {
+    let started = Instant::now();
+    let mut outcome = BatchRowOutcome {
+        id: row.id.clone(),
+        prompt: row.prompt.clone(),
+        status: "ok".to_string(),
+        run_dir: run_dir.to_path_buf(),
+        artifacts: Vec::new(),
+        cost_usd: 0.0,
+        latency_s: 0.0,
+        error: None,
+    };
+    let result = (|| -> Result<()> {
+        let mut engine = NativeEngine::new(
+            run_dir,
+            run_dir.join("events.jsonl"),
+            config.text_model.clone(),
+            row.image_model
+                .clone()
+                .or_else(|| config.image_model.clone()),
+        )?;
+        engine.set_cache_path(cache_path);
+        engine.set_global_cache(config.global_cache_dir.as_ref().map(GlobalCache::new));
+        engine.set_cost_ledger(config.cost_ledger.clone());
+        if let Some(left) = budget_left {
+            engine.set_cost_budget(CostBudget {
+                run_usd: Some(left),
+                session_usd: None,
+            })?;
+        }
+        engine.set_artifact_store(
+            config
+                .artifact_store
+                .as_deref()
+                .map(artifact_store_from_url)
+                .transpose()?,
+        )?;
+        if let Some(path) = &config.keyring {
+            engine.set_credentials_provider(Some(Arc::new(KeyringCredentials::load(path)?)));
+        }
+        engine.set_hooks(config.hooks.as_deref().map(Hooks::load).transpose()?);
+        let mut settings = config.base_settings.clone();
+        for (key, value) in &row.settings {
+            settings.insert(key.clone(), value.clone());
+        }
+        // Row ids stay out of the intent so identical rows share cache entries.
+        let intent = map_object(json!({ "action": "generate" }));
+        let generated = engine.generate(&row.prompt, settings, intent);
+        if let Some(metrics) = engine.last_cost_latency() {
+            outcome.cost_usd = metrics.cost_total_usd;
+        }
+        engine.finish()?;
+        outcome.artifacts = generated?
+            .iter()
+            .filter_map(|artifact| artifact.get("image_path").and_then(Value::as_str))
+            .map(str::to_string)
+            .collect();
+        Ok(())
+    })();
+    if let Err(err) = result {
+        outcome.status = "failed".to_string();
+        outcome.error = Some(error_chain_text(&err, 1024));
+    }
+    outcome.latency_s = started.elapsed().as_secs_f64();
+    outcome
+}
+
+fn write_batch_summary(
+    summary: &BatchSummary,
+    config: &BatchConfig,
+    started_at: &str,
+) -> Result<()> {
+    let rows: Vec<Value> = summary
+        .rows
+        .iter()
+        .map(|row| {
+            json!({
+                "id": row.id,
+                "prompt": row.prompt,
+                "status": row.status,
+                "run_dir": row.run_dir.to_string_lossy(),
+                "artifacts": row.artifacts,
+                "cost_usd": row.cost_usd,
+                "latency_s": row.latency_s,
+                "error": row.error,
+            })
+        })
+        .collect();
+    let payload = json!({
+        "started_at": started_at,
+        "finished_at": now_utc_iso(),
+        "concurrency": config.concurrency,
+        "budget_usd": config.budget_usd,
+        "total_rows": summary.rows.len(),
+        "succeeded": summary.count("ok"),
+        "failed": summary.count("failed"),
+        "skipped_budget": summary.count("skipped_budget"),
+        "cost_total_usd": summary.cost_total_usd,
+        "rows": rows,
+    });
+    write_json_atomic(&summary.summary_path, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::{load_batch_manifest, run_batch, BatchConfig};
+
+    #[test]
+    fn batch_runs_rows_and_writes_summary() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let manifest = temp.path().join("prompts.jsonl");
+        std::fs::write(
+            &manifest,
+            concat!(
+                "{\"prompt\": \"red boat\", \"size\": \"64x64\"}\n",
+                "# comment\n",
+                "{\"id\": \"b\", \"prompt\": \"blue kite\", \"settings\": {\"n\": 2}}\n",
+                "{\"prompt\": \"green hill\"}\n",
+            ),
+        )?;
+        let rows = load_batch_manifest(&manifest)?;
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].settings["size"], json!("64x64"));
+        assert_eq!(rows[1].id, "b");
+
+        let mut base_settings = Map::new();
+        base_settings.insert("size".to_string(), json!("32x32"));
+        let config = BatchConfig {
+            out_dir: temp.path().join("out"),
+            concurrency: 2,
+            budget_usd: None,
+            text_model: Some("dryrun-text-1".to_string()),
+            image_model: Some("dryrun-image-1".to_string()),
+            base_settings,
+            global_cache_dir: None,
+            cost_ledger: None,
+            artifact_store: None,
+            keyring: None,
+            hooks: None,
+        };
+        let summary = run_batch(&rows, &config)?;
+        assert_eq!(summary.count("ok"), 3);
+        assert_eq!(summary.rows[1].artifacts.len(), 2);
+        assert!(summary.rows[0].run_dir.ends_with("001-red-boat"));
+
+        let written: Value =
+            serde_json::from_str(&std::fs::read_to_string(&summary.summary_path)?)?;
+        assert_eq!(written["succeeded"], json!(3));
+        assert_eq!(written["rows"][2]["prompt"], json!("green hill"));
+        Ok(())
+    }
+
+    #[test]
+    fn batch_manifest_rejects_rows_without_prompt() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let manifest = temp.path().join("prompts.jsonl");
+        std::fs::write(&manifest, "{\"size\": \"64x64\"}\n")?;
+        assert!(load_batch_manifest(&manifest).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use serde_json::{Map, Value};
+
+use super::output_format::OutputFormat;
+
+/// What a provider can honor. Requests outside these limits still run, but
+/// the provider clamps, snaps or drops the difference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    pub supports_mask: bool,
+    pub supports_reference_images: bool,
+    pub supports_seed: bool,
+    /// The same request, seed and model version give the same image. Off
+    /// by default: a provider has to claim it.
+    pub deterministic: bool,
+    /// Images per request; `None` when the provider loops without a cap.
+    pub max_n: Option<u64>,
+    /// Exact `WxH` sizes the provider accepts; empty means any size.
+    pub supported_sizes: Vec<String>,
+    /// Extensions the provider can return natively (`png`, `jpg`, ...).
+    pub supported_output_formats: Vec<String>,
+}
+
+impl Default for ProviderCapabilities {
+    fn default() -> Self {
+        Self {
+            supports_mask: true,
+            supports_reference_images: true,
+            supports_seed: true,
+            deterministic: false,
+            max_n: None,
+            supported_sizes: Vec::new(),
+            supported_output_formats: strings(&["png", "jpg", "webp"]),
+        }
+    }
+}
+
+impl ProviderCapabilities {
+    /// One sentence per requested setting `provider` cannot honor as given.
+    /// `n` is the image count of a single provider call.
+    pub fn unmet_settings(
+        &self,
+        provider: &str,
+        settings: &Map<String, Value>,
+        size: &str,
+        n: u64,
+    ) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let present = |key: &str| match settings.get(key) {
+            Some(Value::String(text)) => !text.trim().is_empty(),
+            Some(Value::Array(rows)) => !rows.is_empty(),
+            Some(Value::Null) | None => false,
+            Some(_) => true,
+        };
+        if present("mask") && !self.supports_mask {
+            reasons.push(format!(
+                "{provider} cannot apply a mask; it would be ignored."
+            ));
+        }
+        if present("reference_images") && !self.supports_reference_images {
+            reasons.push(format!("{provider} does not accept reference images."));
+        }
+        if (present("seed") || present("seed_sweep")) && !self.supports_seed {
+            reasons.push(format!(
+                "{provider} does not honor seeds; results will not be reproducible."
+            ));
+        }
+        if let Some(max_n) = self.max_n.filter(|max_n| n > *max_n) {
+            reasons.push(format!(
+                "{provider} returns at most {max_n} images per request; n={n} would be clamped."
+            ));
+        }
+        let size = size.trim().to_ascii_lowercase();
+        if !self.supported_sizes.is_empty() && !self.supported_sizes.contains(&size) {
+            reasons.push(format!(
+                "{provider} does not offer size {size} (supported: {}); it would be snapped.",
+                self.supported_sizes.join(", ")
+            ));
+        }
+        if let Some(format) = settings.get("output_format").and_then(Value::as_str) {
+            let wanted = OutputFormat::parse(format)
+                .map(|format| format.extension().to_string())
+                .unwrap_or_else(|| format.trim().to_ascii_lowercase());
+            if !self.supported_output_formats.contains(&wanted) {
+                let fallback = if OutputFormat::parse(format).is_some() {
+                    "it would be re-encoded locally"
+                } else {
+                    "it would fall back to png"
+                };
+                reasons.push(format!("{provider} cannot return {wanted}; {fallback}."));
+            }
+        }
+        reasons
+    }
+}
+
+pub(crate) fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{strings, ProviderCapabilities};
+    use crate::map_object;
+
+    #[test]
+    fn unmet_settings_explain_each_limit() {
+        let capabilities = ProviderCapabilities {
+            supports_mask: false,
+            supports_reference_images: false,
+            supports_seed: false,
+            deterministic: false,
+            max_n: Some(4),
+            supported_sizes: strings(&["1024x1024"]),
+            supported_output_formats: strings(&["png", "jpg"]),
+        };
+        let settings = map_object(json!({
+            "mask": "/tmp/mask.png",
+            "reference_images": ["/tmp/ref.png"],
+            "seed": 7,
+            "output_format": "webp",
+        }));
+        let reasons = capabilities.unmet_settings("imagen", &settings, "512x512", 6);
+        assert_eq!(reasons.len(), 6);
+        assert!(reasons[3].contains("at most 4 images"));
+        assert!(reasons[4].contains("size 512x512"));
+        assert_eq!(
+            reasons[5],
+            "imagen cannot return webp; it would be re-encoded locally."
+        );
+        assert!(ProviderCapabilities::default()
+            .unmet_settings("dryrun", &settings, "512x512", 6)
+            .is_empty());
+    }
+}
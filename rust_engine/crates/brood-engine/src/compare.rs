@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::receipt_diff::{diff_receipts, load_receipt, ReceiptDiff};
+use image::imageops::FilterType;
+use image::{GrayImage, Rgb, RgbImage};
+use serde_json::{json, Value};
+
+use super::{map_object, timestamp_millis, NativeEngine};
+
+/// Run-dir subdirectory holding comparison composites.
+pub const COMPARISONS_DIR: &str = "comparisons";
+
+/// Gap in pixels between panels of a comparison composite.
+const PANEL_GAP: u32 = 8;
+
+/// SSIM window edge in pixels.
+const SSIM_WINDOW: u32 = 8;
+
+/// Result of [`NativeEngine::compare_artifacts`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub left: String,
+    pub right: String,
+    pub composite_path: PathBuf,
+    /// Mean structural similarity of the luma channels (1.0 = identical).
+    pub ssim: f64,
+    /// Mean absolute per-channel difference in `0.0..=1.0`.
+    pub mean_abs_diff: f64,
+    /// Panel size both images were compared at (the left image's size).
+    pub width: u32,
+    pub height: u32,
+    /// Whether `right` was resized to match `left` before comparing.
+    pub resized: bool,
+}
+
+impl NativeEngine {
+    /// Writes a side-by-side composite of two artifacts (plus a difference
+    /// heatmap panel when `heatmap` is set) under [`COMPARISONS_DIR`] and
+    /// returns SSIM and mean absolute difference. `left` and `right` are
+    /// artifact ids or image paths.
+    pub fn compare_artifacts(
+        &mut self,
+        left: &str,
+        right: &str,
+        heatmap: bool,
+    ) -> Result<Comparison> {
+        let left_path = self.resolve_comparison_image(left)?;
+        let right_path = self.resolve_comparison_image(right)?;
+        let left_image = image::open(&left_path)
+            .with_context(|| format!("failed to read {}", left_path.display()))?
+            .to_rgb8();
+        let mut right_image = image::open(&right_path)
+            .with_context(|| format!("failed to read {}", right_path.display()))?
+            .to_rgb8();
+        let (width, height) = left_image.dimensions();
+        let resized = right_image.dimensions() != (width, height);
+        if resized {
+            right_image =
+                image::imageops::resize(&right_image, width, height, FilterType::Triangle);
+        }
+
+        let ssim = mean_ssim(
+            &image::imageops::grayscale(&left_image),
+            &image::imageops::grayscale(&right_image),
+        );
+        let mean_abs_diff = mean_abs_diff(&left_image, &right_image);
+        let mut panels = vec![&left_image, &right_image];
+        let diff = heatmap.then(|| diff_heatmap(&left_image, &right_image));
+        if let Some(diff) = &diff {
+            panels.push(diff);
+        }
+        let composite = side_by_side(&panels);
+
+        let dir = self.run_dir.join(COMPARISONS_DIR);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let composite_path = dir.join(format!(
+            "compare-{}-{}-{}.png",
+            file_label(left),
+            file_label(right),
+            timestamp_millis()
+        ));
+        composite
+            .save(&composite_path)
+            .with_context(|| format!("failed to write {}", composite_path.display()))?;
+
+        let comparison = Comparison {
+            left: left.to_string(),
+            right: right.to_string(),
+            composite_path,
+            ssim,
+            mean_abs_diff,
+            width,
+            height,
+            resized,
+        };
+        self.events.emit(
+            "comparison_created",
+            map_object(json!({
+                "left": comparison.left,
+                "right": comparison.right,
+                "left_path": left_path.to_string_lossy().to_string(),
+                "right_path": right_path.to_string_lossy().to_string(),
+                "composite_path": comparison.composite_path.to_string_lossy().to_string(),
+                "heatmap": heatmap,
+                "ssim": comparison.ssim,
+                "mean_abs_diff": comparison.mean_abs_diff,
+                "width": width,
+                "height": height,
+                "resized": resized,
+            })),
+        )?;
+        Ok(comparison)
+    }
+
+    /// Diffs the requests recorded in two artifacts' receipts (see
+    /// [`diff_receipts`]). `left` and `right` are artifact ids or receipt
+    /// paths.
+    pub fn diff_artifact_receipts(&mut self, left: &str, right: &str) -> Result<ReceiptDiff> {
+        let left_path = self.resolve_receipt(left)?;
+        let right_path = self.resolve_receipt(right)?;
+        let diff = diff_receipts(&load_receipt(&left_path)?, &load_receipt(&right_path)?);
+        let mut payload = map_object(diff.to_value());
+        payload.insert("left".to_string(), Value::String(left.to_string()));
+        payload.insert("right".to_string(), Value::String(right.to_string()));
+        payload.insert(
+            "left_receipt".to_string(),
+            Value::String(left_path.to_string_lossy().to_string()),
+        );
+        payload.insert(
+            "right_receipt".to_string(),
+            Value::String(right_path.to_string_lossy().to_string()),
+        );
+        self.events.emit("receipts_diffed", payload)?;
+        Ok(diff)
+    }
+
+    fn resolve_receipt(&self, reference: &str) -> Result<PathBuf> {
+        if let Some((_, artifact)) = self.thread.find_artifact(reference) {
+            let Some(path) = artifact.get("receipt_path").and_then(Value::as_str) else {
+                bail!("artifact '{reference}' has no receipt");
+            };
+            return Ok(PathBuf::from(path));
+        }
+        let path = Path::new(reference);
+        if path.is_file() {
+            return Ok(path.to_path_buf());
+        }
+        bail!("'{reference}' is neither an artifact id nor a receipt path")
+    }
+
+    fn resolve_comparison_image(&self, reference: &str) -> Result<PathBuf> {
+        if let Some((_, artifact)) = self.thread.find_artifact(reference) {
+            let Some(path) = artifact.get("image_path").and_then(Value::as_str) else {
+                bail!("artifact '{reference}' has no image");
+            };
+            return Ok(PathBuf::from(path));
+        }
+        let path = Path::new(reference);
+        if path.is_file() {
+            return Ok(path.to_path_buf());
+        }
+        bail!("'{reference}' is neither an artifact id nor an image path")
+    }
+}
+
+fn file_label(reference: &str) -> String {
+    let stem = Path::new(reference)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(reference);
+    stem.chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .take(40)
+        .collect()
+}
+
+fn side_by_side(panels: &[&RgbImage]) -> RgbImage {
+    let height = panels.iter().map(|panel| panel.height()).max().unwrap_or(0);
+    let width = panels.iter().map(|panel| panel.width()).sum::<u32>()
+        + PANEL_GAP * panels.len().saturating_sub(1) as u32;
+    let mut out = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+    let mut x = 0;
+    for panel in panels {
+        image::imageops::overlay(&mut out, *panel, i64::from(x), 0);
+        x += panel.width() + PANEL_GAP;
+    }
+    out
+}
+
+/// Per-pixel difference mapped from black (same) through red to yellow.
+fn diff_heatmap(left: &RgbImage, right: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(left.width(), left.height(), |x, y| {
+        let a = left.get_pixel(x, y).0;
+        let b = right.get_pixel(x, y).0;
+        let delta = a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| u32::from(a.abs_diff(*b)))
+            .max()
+            .unwrap_or(0);
+        let red = (delta * 2).min(255) as u8;
+        let green = delta.saturating_sub(128).saturating_mul(2).min(255) as u8;
+        Rgb([red, green, 0])
+    })
+}
+
+pub(crate) fn mean_abs_diff(left: &RgbImage, right: &RgbImage) -> f64 {
+    let samples = left.as_raw().len();
+    if samples == 0 {
+        return 0.0;
+    }
+    let total: u64 = left
+        .as_raw()
+        .iter()
+        .zip(right.as_raw())
+        .map(|(a, b)| u64::from(a.abs_diff(*b)))
+        .sum();
+    total as f64 / samples as f64 / 255.0
+}
+
+/// Mean SSIM over non-overlapping windows (the whole image when smaller
+/// than one window).
+pub(crate) fn mean_ssim(left: &GrayImage, right: &GrayImage) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (width, height) = left.dimensions();
+    let window_w = SSIM_WINDOW.min(width).max(1);
+    let window_h = SSIM_WINDOW.min(height).max(1);
+    let mut total = 0.0;
+    let mut windows = 0u32;
+    for top in (0..=height.saturating_sub(window_h)).step_by(window_h as usize) {
+        for left_x in (0..=width.saturating_sub(window_w)).step_by(window_w as usize) {
+            let mut a = Vec::with_capacity((window_w * window_h) as usize);
+            let mut b = Vec::with_capacity(a.capacity());
+            for y in top..top + window_h {
+                for x in left_x..left_x + window_w {
+                    a.push(f64::from(left.get_pixel(x, y).0[0]));
+                    b.push(f64::from(right.get_pixel(x, y).0[0]));
+                }
+            }
+            let count = a.len() as f64;
+            let mean_a = a.iter().sum::<f64>() / count;
+            let mean_b = b.iter().sum::<f64>() / count;
+            let (mut var_a, mut var_b, mut cov) = (0.0, 0.0, 0.0);
+            for (a, b) in a.iter().zip(&b) {
+                var_a += (a - mean_a) * (a - mean_a);
+                var_b += (b - mean_b) * (b - mean_b);
+                cov += (a - mean_a) * (b - mean_b);
+            }
+            var_a /= count;
+            var_b /= count;
+            cov /= count;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        return 1.0;
+    }
+    total / f64::from(windows)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use super::{diff_heatmap, mean_abs_diff, mean_ssim, side_by_side};
+
+    #[test]
+    fn identical_images_score_one_and_noise_scores_lower() {
+        let gradient = GrayImage::from_fn(32, 32, |x, y| Luma([((x * 7 + y * 3) % 256) as u8]));
+        assert!((mean_ssim(&gradient, &gradient) - 1.0).abs() < 1e-9);
+        let inverted =
+            GrayImage::from_fn(32, 32, |x, y| Luma([255 - gradient.get_pixel(x, y).0[0]]));
+        assert!(mean_ssim(&gradient, &inverted) < 0.5);
+
+        let black = RgbImage::from_pixel(4, 4, Rgb([0, 0, 0]));
+        let white = RgbImage::from_pixel(4, 4, Rgb([255, 255, 255]));
+        assert!((mean_abs_diff(&black, &white) - 1.0).abs() < 1e-9);
+        assert_eq!(mean_abs_diff(&black, &black), 0.0);
+        assert_eq!(
+            diff_heatmap(&black, &black).get_pixel(0, 0),
+            &Rgb([0, 0, 0])
+        );
+        assert_eq!(
+            diff_heatmap(&black, &white).get_pixel(0, 0),
+            &Rgb([255, 254, 0])
+        );
+        let composite = side_by_side(&[&black, &white, &black]);
+        assert_eq!(composite.dimensions(), (4 * 3 + 8 * 2, 4));
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{bail, Result};
+use brood_contracts::models::ModelSpec;
+use brood_contracts::runs::session::ContextTurn;
+use serde_json::json;
+
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{map_object, NativeEngine, ProviderConfig, TokenEstimator};
+
+/// Instruction sent ahead of the transcript being compacted.
+const COMPACT_INSTRUCTIONS: &str = "Summarize this image-generation session transcript for the \
+assistant that continues it. Keep every subject, style, constraint and preference the user \
+asked for, and which requests were already generated; drop pleasantries and repetition. Reply \
+with the summary only.";
+
+/// Most recent turns compaction keeps verbatim.
+pub const CONTEXT_KEEP_RECENT_TURNS: usize = 4;
+
+const CONTEXT_SUMMARY_MAX_CHARS: usize = 4000;
+/// Words per turn the dryrun text model keeps in its summary.
+const DRYRUN_SUMMARY_WORDS: usize = 12;
+
+/// What [`NativeEngine::compact_context`] did, also emitted as
+/// `context_compacted`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextCompaction {
+    pub model: String,
+    /// `openai`, `gemini`, `openrouter` or `dryrun`.
+    pub transport: String,
+    pub turns_summarized: usize,
+    pub tokens_before: u64,
+    pub tokens_after: u64,
+    pub cost_usd: f64,
+}
+
+/// Summarizes older turns with the engine's text model.
+#[derive(Debug, Clone)]
+pub(crate) struct ContextCompactor {
+    client: TextModelClient,
+}
+
+impl ContextCompactor {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    fn summarize(
+        &self,
+        model: &ModelSpec,
+        turns: &[ContextTurn],
+    ) -> Result<(String, &'static str, TokenUsage)> {
+        if !model.supports("text") {
+            bail!("model '{}' is not a text model", model.name);
+        }
+        let transcript = turns
+            .iter()
+            .map(|turn| format!("{}: {}", turn.role, turn.text.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let (summary, transport, usage) = if model.provider == "dryrun" {
+            let summary = dryrun_summary(turns);
+            let usage = TokenUsage::estimate(model, COMPACT_INSTRUCTIONS, &transcript, &summary);
+            (summary, "dryrun", usage)
+        } else {
+            let reply = self
+                .client
+                .complete(model, COMPACT_INSTRUCTIONS, &transcript, None)?;
+            (reply.text, reply.transport, reply.usage)
+        };
+        let summary: String = summary
+            .trim()
+            .chars()
+            .take(CONTEXT_SUMMARY_MAX_CHARS)
+            .collect();
+        if summary.is_empty() {
+            bail!("{transport} text model returned an empty summary");
+        }
+        Ok((summary, transport, usage))
+    }
+}
+
+/// The opening words of each turn, so tests can see what was folded in.
+fn dryrun_summary(turns: &[ContextTurn]) -> String {
+    let lines: Vec<String> = turns
+        .iter()
+        .map(|turn| {
+            let words: Vec<&str> = turn
+                .text
+                .split_whitespace()
+                .take(DRYRUN_SUMMARY_WORDS)
+                .collect();
+            format!("{}: {}", turn.role, words.join(" "))
+        })
+        .collect();
+    format!(
+        "Summary of {} earlier turns. {}",
+        turns.len(),
+        lines.join("; ")
+    )
+}
+
+impl NativeEngine {
+    /// Turns recorded by [`NativeEngine::track_context`], oldest first;
+    /// persisted in `session.json` so a resumed chat keeps them.
+    pub fn context_turns(&self) -> &[ContextTurn] {
+        &self.context_turns
+    }
+
+    pub(crate) fn save_context_turns(&self) -> Result<()> {
+        let conversation = self.context_turns.clone();
+        self.update_session(|session| session.conversation = conversation)
+    }
+
+    pub(crate) fn context_estimator(&self) -> TokenEstimator {
+        TokenEstimator::for_model(
+            self.text_model
+                .as_deref()
+                .and_then(|model| self.model_selector.registry.get(model)),
+        )
+    }
+
+    pub(crate) fn context_tokens(&self) -> u64 {
+        let estimator = self.context_estimator();
+        self.context_turns
+            .iter()
+            .map(|turn| estimator.count(&turn.text))
+            .sum()
+    }
+
+    /// Summarizes all but the last [`CONTEXT_KEEP_RECENT_TURNS`] turns into
+    /// one `summary` turn with the text model. `None` when there is nothing
+    /// older to fold in.
+    pub fn compact_context(&mut self) -> Result<Option<ContextCompaction>> {
+        if self.context_turns.len() <= CONTEXT_KEEP_RECENT_TURNS {
+            return Ok(None);
+        }
+        let Some(model) = self
+            .text_model
+            .as_deref()
+            .and_then(|name| self.model_selector.registry.get(name))
+            .cloned()
+        else {
+            bail!(
+                "text model '{}' is not registered",
+                self.text_model.as_deref().unwrap_or("none")
+            );
+        };
+        let tokens_before = self.context_tokens();
+        let split = self.context_turns.len() - CONTEXT_KEEP_RECENT_TURNS;
+        let (summary, transport, usage) = self
+            .context_compactor
+            .summarize(&model, &self.context_turns[..split])?;
+        let cost_usd = self.record_text_call(&model, usage)?;
+        self.context_turns.splice(
+            ..split,
+            [ContextTurn {
+                role: "summary".to_string(),
+                text: summary,
+            }],
+        );
+        let compaction = ContextCompaction {
+            model: model.name.clone(),
+            transport: transport.to_string(),
+            turns_summarized: split,
+            tokens_before,
+            tokens_after: self.context_tokens(),
+            cost_usd,
+        };
+        self.events.emit(
+            "context_compacted",
+            map_object(json!({
+                "model": compaction.model,
+                "transport": compaction.transport,
+                "turns_summarized": compaction.turns_summarized,
+                "turns_kept": CONTEXT_KEEP_RECENT_TURNS,
+                "tokens_before": compaction.tokens_before,
+                "tokens_after": compaction.tokens_after,
+                "cost_usd": compaction.cost_usd,
+            })),
+        )?;
+        Ok(Some(compaction))
+    }
+}
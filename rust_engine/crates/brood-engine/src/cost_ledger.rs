@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+
+use super::non_empty_env;
+
+/// Ledger file path; `0`/`off` disables the ledger and `1`/`on` (or unset)
+/// uses `~/.brood/cost_ledger.jsonl`.
+pub const COST_LEDGER_ENV: &str = "BROOD_COST_LEDGER";
+pub const COST_LEDGER_FILENAME: &str = "cost_ledger.jsonl";
+
+/// One priced `cost_latency_update`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostLedgerEntry {
+    pub ts: String,
+    pub run_id: String,
+    pub provider: String,
+    pub model: String,
+    pub cost_usd: f64,
+    pub latency_per_image_s: f64,
+}
+
+impl CostLedgerEntry {
+    fn to_json(&self) -> Value {
+        json!({
+            "ts": self.ts,
+            "run_id": self.run_id,
+            "provider": self.provider,
+            "model": self.model,
+            "cost_usd": self.cost_usd,
+            "latency_per_image_s": self.latency_per_image_s,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let text = |key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+        Some(Self {
+            ts: text("ts")?,
+            run_id: text("run_id").unwrap_or_default(),
+            provider: text("provider")?,
+            model: text("model")?,
+            cost_usd: value.get("cost_usd").and_then(Value::as_f64)?,
+            latency_per_image_s: value
+                .get("latency_per_image_s")
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0),
+        })
+    }
+
+    /// UTC day of `ts` (`YYYY-MM-DD`).
+    fn day(&self) -> &str {
+        self.ts.get(..10).unwrap_or(&self.ts)
+    }
+}
+
+/// Append-only JSONL of spend across every run on this machine, so spend
+/// can be reported without walking run dirs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostLedger {
+    path: PathBuf,
+}
+
+impl CostLedger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// `~/.brood/cost_ledger.jsonl`.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .map(|home| home.join(".brood").join(COST_LEDGER_FILENAME))
+    }
+
+    /// The ledger [`COST_LEDGER_ENV`] selects; on by default.
+    pub fn from_env() -> Option<Self> {
+        match non_empty_env(COST_LEDGER_ENV) {
+            None => Self::default_path().map(Self::new),
+            Some(raw) => match raw.to_ascii_lowercase().as_str() {
+                "0" | "false" | "off" | "no" => None,
+                "1" | "true" | "on" | "yes" => Self::default_path().map(Self::new),
+                _ => Some(Self::new(raw)),
+            },
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// One line per entry in append mode, so concurrent runs can share the
+    /// file.
+    pub(crate) fn append(&self, entry: &CostLedgerEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let mut line = serde_json::to_string(&entry.to_json())?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("failed to append to {}", self.path.display()))
+    }
+
+    /// Every readable entry; a missing ledger is empty and malformed lines
+    /// (say, a torn final write) are skipped.
+    pub fn entries(&self) -> Result<Vec<CostLedgerEntry>> {
+        let raw = match fs::read_to_string(&self.path) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", self.path.display()))
+            }
+        };
+        Ok(raw
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .filter_map(|value| CostLedgerEntry::from_json(&value))
+            .collect())
+    }
+}
+
+/// How `brood-rs costs` buckets ledger entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum CostGroupBy {
+    Provider,
+    Model,
+    Day,
+}
+
+impl CostGroupBy {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Provider => "provider",
+            Self::Model => "model",
+            Self::Day => "day",
+        }
+    }
+}
+
+/// Spend of one group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostReportRow {
+    pub key: String,
+    pub generations: u64,
+    pub cost_usd: f64,
+}
+
+/// `YYYY-MM-DD`, as taken by `--since`.
+pub fn parse_ledger_date(raw: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(raw.trim(), "%Y-%m-%d")
+        .with_context(|| format!("invalid date '{raw}' (expected YYYY-MM-DD)"))
+}
+
+/// Totals per group, sorted by key, for entries on or after `since` (UTC).
+pub fn summarize_costs(
+    entries: &[CostLedgerEntry],
+    since: Option<NaiveDate>,
+    group_by: CostGroupBy,
+) -> Vec<CostReportRow> {
+    let since = since.map(|date| date.format("%Y-%m-%d").to_string());
+    let mut groups: BTreeMap<String, (u64, f64)> = BTreeMap::new();
+    for entry in entries {
+        if since.as_deref().is_some_and(|since| entry.day() < since) {
+            continue;
+        }
+        let key = match group_by {
+            CostGroupBy::Provider => entry.provider.clone(),
+            CostGroupBy::Model => entry.model.clone(),
+            CostGroupBy::Day => entry.day().to_string(),
+        };
+        let group = groups.entry(key).or_default();
+        group.0 += 1;
+        group.1 += entry.cost_usd;
+    }
+    groups
+        .into_iter()
+        .map(|(key, (generations, cost_usd))| CostReportRow {
+            key,
+            generations,
+            cost_usd,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{parse_ledger_date, summarize_costs, CostGroupBy, CostLedger, CostLedgerEntry};
+
+    fn entry(ts: &str, provider: &str, model: &str, cost_usd: f64) -> CostLedgerEntry {
+        CostLedgerEntry {
+            ts: ts.to_string(),
+            run_id: "run-1".to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            cost_usd,
+            latency_per_image_s: 3.0,
+        }
+    }
+
+    #[test]
+    fn appends_reads_and_groups_ledger_entries() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let ledger = CostLedger::new(temp.path().join("nested").join("ledger.jsonl"));
+        assert!(ledger.entries()?.is_empty());
+        for row in [
+            entry(
+                "2024-05-31T23:59:00.000000+00:00",
+                "openai",
+                "gpt-image-1",
+                0.04,
+            ),
+            entry(
+                "2024-06-01T08:00:00.000000+00:00",
+                "openai",
+                "gpt-image-1",
+                0.04,
+            ),
+            entry(
+                "2024-06-02T09:30:00.000000+00:00",
+                "replicate",
+                "flux-dev",
+                0.025,
+            ),
+            entry(
+                "2024-06-02T10:00:00.000000+00:00",
+                "openai",
+                "dall-e-3",
+                0.08,
+            ),
+        ] {
+            ledger.append(&row)?;
+        }
+        let mut raw = fs::read_to_string(ledger.path())?;
+        raw.push_str("{\"ts\": \"torn");
+        fs::write(ledger.path(), raw)?;
+        let entries = ledger.entries()?;
+        assert_eq!(entries.len(), 4);
+
+        let since = Some(parse_ledger_date("2024-06-01")?);
+        let by_provider = summarize_costs(&entries, since, CostGroupBy::Provider);
+        assert_eq!(by_provider.len(), 2);
+        assert_eq!(by_provider[0].key, "openai");
+        assert_eq!(by_provider[0].generations, 2);
+        assert!((by_provider[0].cost_usd - 0.12).abs() < 1e-9);
+        let by_day = summarize_costs(&entries, None, CostGroupBy::Day);
+        let days: Vec<&str> = by_day.iter().map(|row| row.key.as_str()).collect();
+        assert_eq!(days, ["2024-05-31", "2024-06-01", "2024-06-02"]);
+        assert_eq!(
+            summarize_costs(&entries, since, CostGroupBy::Model).len(),
+            3
+        );
+
+        assert!(parse_ledger_date("06/01/2024").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+
+use super::keychain::cached_keychain_key;
+use super::non_empty_env;
+
+/// Path of the keyring file [`KeyringCredentials::from_env`] loads.
+pub const KEYRING_ENV: &str = "BROOD_KEYRING";
+
+/// What a provider is asking a key for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CredentialRequest<'a> {
+    /// Provider name (`openai`, `flux`, a custom endpoint's name, ...).
+    pub provider: &'a str,
+    /// `request_metadata.tenant` of the request being served, if any.
+    pub tenant: Option<&'a str>,
+}
+
+/// Supplies provider API keys ahead of the environment, so one process can
+/// serve several tenants. Returning `None` falls through to the provider's
+/// `*_API_KEY` variables.
+///
+/// Any `Fn(&CredentialRequest) -> Option<String>` closure is a provider.
+pub trait CredentialsProvider: Send + Sync {
+    fn api_key(&self, request: &CredentialRequest) -> Option<String>;
+}
+
+impl<F> CredentialsProvider for F
+where
+    F: Fn(&CredentialRequest) -> Option<String> + Send + Sync,
+{
+    fn api_key(&self, request: &CredentialRequest) -> Option<String> {
+        self(request)
+    }
+}
+
+/// Keys per tenant from a JSON file:
+/// `{"tenants": {"acme": {"openai": "sk-..."}}, "default": {"flux": "..."}}`.
+/// `default` serves requests without a tenant and tenants missing a key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyringCredentials {
+    tenants: BTreeMap<String, BTreeMap<String, String>>,
+    default: BTreeMap<String, String>,
+}
+
+impl KeyringCredentials {
+    /// The keyring [`KEYRING_ENV`] names, or `None` when it is unset.
+    pub fn from_env() -> Result<Option<Self>> {
+        non_empty_env(KEYRING_ENV)
+            .map(|path| Self::load(PathBuf::from(path)))
+            .transpose()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read keyring {}", path.display()))?;
+        Self::parse(&raw).with_context(|| format!("Invalid keyring {}", path.display()))
+    }
+
+    pub fn parse(raw: &str) -> Result<Self> {
+        let payload: Value = serde_json::from_str(raw)?;
+        let Some(payload) = payload.as_object() else {
+            bail!("expected a JSON object");
+        };
+        let mut keyring = Self::default();
+        if let Some(keys) = payload.get("default") {
+            keyring.default = provider_keys("default", keys)?;
+        }
+        match payload.get("tenants") {
+            None => {}
+            Some(Value::Object(tenants)) => {
+                for (tenant, keys) in tenants {
+                    let keys = provider_keys(&format!("tenants.{tenant}"), keys)?;
+                    keyring.tenants.insert(tenant.trim().to_string(), keys);
+                }
+            }
+            Some(_) => bail!("\"tenants\" must be an object of tenant -> provider keys"),
+        }
+        Ok(keyring)
+    }
+}
+
+fn provider_keys(label: &str, keys: &Value) -> Result<BTreeMap<String, String>> {
+    let Some(keys) = keys.as_object() else {
+        bail!("\"{label}\" must be an object of provider -> API key");
+    };
+    keys.iter()
+        .map(|(provider, key)| match key.as_str().map(str::trim) {
+            Some(key) if !key.is_empty() => {
+                Ok((provider.trim().to_ascii_lowercase(), key.to_string()))
+            }
+            _ => bail!("\"{label}.{provider}\" must be a non-empty string"),
+        })
+        .collect()
+}
+
+impl CredentialsProvider for KeyringCredentials {
+    fn api_key(&self, request: &CredentialRequest) -> Option<String> {
+        request
+            .tenant
+            .and_then(|tenant| self.tenants.get(tenant))
+            .and_then(|keys| keys.get(request.provider))
+            .or_else(|| self.default.get(request.provider))
+            .cloned()
+    }
+}
+
+/// Keys a request carries for itself, taken out of its settings before
+/// anything is hashed or recorded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct RequestCredentials {
+    /// `provider_options.api_key`: the key of whichever provider serves it.
+    api_key: Option<String>,
+    /// `provider_options.api_keys`: keys by provider name.
+    api_keys: BTreeMap<String, String>,
+    tenant: Option<String>,
+}
+
+impl RequestCredentials {
+    /// Removes `api_key` and `api_keys` from `settings.provider_options` and
+    /// reads the tenant from `intent.request_metadata.tenant`.
+    pub(crate) fn take(
+        settings: &mut Map<String, Value>,
+        intent: &Map<String, Value>,
+    ) -> Result<Self> {
+        let mut credentials = Self {
+            tenant: intent
+                .get("request_metadata")
+                .and_then(|metadata| metadata.get("tenant"))
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|tenant| !tenant.is_empty())
+                .map(str::to_string),
+            ..Self::default()
+        };
+        let Some(options) = settings
+            .get_mut("provider_options")
+            .and_then(Value::as_object_mut)
+        else {
+            return Ok(credentials);
+        };
+        match options.remove("api_key") {
+            None | Some(Value::Null) => {}
+            Some(Value::String(key)) if !key.trim().is_empty() => {
+                credentials.api_key = Some(key.trim().to_string());
+            }
+            Some(_) => bail!("provider_options.api_key must be a non-empty string"),
+        }
+        match options.remove("api_keys") {
+            None | Some(Value::Null) => {}
+            Some(keys) => credentials.api_keys = provider_keys("provider_options.api_keys", &keys)?,
+        }
+        Ok(credentials)
+    }
+
+    /// Whether the request brought keys of its own.
+    fn has_keys(&self) -> bool {
+        self.api_key.is_some() || !self.api_keys.is_empty()
+    }
+}
+
+struct ScopeState {
+    /// Provider serving the request, which `api_key` belongs to.
+    provider: String,
+    request: RequestCredentials,
+    credentials: Option<Arc<dyn CredentialsProvider>>,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Option<ScopeState>> = const { RefCell::new(None) };
+}
+
+/// Makes a request's keys and the engine's [`CredentialsProvider`] visible
+/// to provider calls on this thread until dropped, like the timeout scope:
+/// providers are shared, so per-request keys travel with the call. Nested
+/// scopes restore the outer one.
+pub(crate) struct CredentialScope(Option<ScopeState>);
+
+impl CredentialScope {
+    pub(crate) fn begin(
+        provider: &str,
+        request: RequestCredentials,
+        credentials: Option<Arc<dyn CredentialsProvider>>,
+    ) -> Self {
+        let state = ScopeState {
+            provider: provider.to_string(),
+            request,
+            credentials,
+        };
+        Self(SCOPE.with(|cell| cell.borrow_mut().replace(state)))
+    }
+}
+
+impl Drop for CredentialScope {
+    fn drop(&mut self) {
+        let outer = self.0.take();
+        SCOPE.with(|cell| *cell.borrow_mut() = outer);
+    }
+}
+
+/// Whether the request in scope on this thread brought keys of its own.
+/// Such requests skip the run and global caches, so a result paid for with
+/// one caller's key is never handed to a caller with another.
+pub(crate) fn request_keys_in_scope() -> bool {
+    SCOPE.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .is_some_and(|state| state.request.has_keys())
+    })
+}
+
+/// `provider`'s key: the request's own key, then the scoped
+/// [`CredentialsProvider`], then the OS keychain, then the first non-empty
+/// of `envs`.
+pub(crate) fn resolve_api_key(provider: &str, envs: &[String]) -> Option<String> {
+    resolve_api_key_with(provider, envs, non_empty_env)
+}
+
+/// [`resolve_api_key`] with `env` standing in for the process environment.
+pub(crate) fn resolve_api_key_with(
+    provider: &str,
+    envs: &[String],
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    let scoped = SCOPE.with(|cell| {
+        let scope = cell.borrow();
+        let state = scope.as_ref()?;
+        if let Some(key) = state.request.api_keys.get(provider) {
+            return Some(key.clone());
+        }
+        if let Some(key) = state.request.api_key.as_ref() {
+            if state.provider == provider {
+                return Some(key.clone());
+            }
+        }
+        state.credentials.as_ref()?.api_key(&CredentialRequest {
+            provider,
+            tenant: state.request.tenant.as_deref(),
+        })
+    });
+    scoped
+        .filter(|key| !key.trim().is_empty())
+        .or_else(|| cached_keychain_key(provider))
+        .or_else(|| envs.iter().find_map(|key| env(key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::{
+        request_keys_in_scope, resolve_api_key, CredentialRequest, CredentialScope,
+        CredentialsProvider, KeyringCredentials, RequestCredentials,
+    };
+    use crate::map_object;
+
+    #[test]
+    fn request_keys_then_provider_then_env() -> anyhow::Result<()> {
+        std::env::set_var("BROOD_TEST_CREDENTIALS_KEY", "from-env");
+        let envs = vec!["BROOD_TEST_CREDENTIALS_KEY".to_string()];
+        let keyring = KeyringCredentials::parse(
+            r#"{"tenants": {"acme": {"openai": "acme-openai"}}, "default": {"openai": "shared-openai", "gemini": "shared-gemini"}}"#,
+        )?;
+        let lookup = |provider: &str, tenant: Option<&str>| {
+            keyring.api_key(&CredentialRequest { provider, tenant })
+        };
+        assert_eq!(
+            lookup("openai", Some("acme")).as_deref(),
+            Some("acme-openai")
+        );
+        assert_eq!(
+            lookup("gemini", Some("acme")).as_deref(),
+            Some("shared-gemini")
+        );
+        assert_eq!(lookup("openai", None).as_deref(), Some("shared-openai"));
+        assert_eq!(lookup("flux", Some("acme")), None);
+        assert!(KeyringCredentials::parse(r#"{"tenants": {"acme": {"openai": 1}}}"#).is_err());
+
+        let mut settings = map_object(json!({
+            "size": "1024x1024",
+            "provider_options": {"api_key": "req-flux", "api_keys": {"OpenRouter": "req-or"}, "steps": 30},
+        }));
+        let intent = map_object(json!({"request_metadata": {"tenant": "acme"}}));
+        let request = RequestCredentials::take(&mut settings, &intent)?;
+        assert_eq!(settings["provider_options"], json!({"steps": 30}));
+
+        assert_eq!(resolve_api_key("flux", &envs).as_deref(), Some("from-env"));
+        assert!(!request_keys_in_scope());
+        {
+            let _scope = CredentialScope::begin("flux", request, Some(Arc::new(keyring)));
+            assert!(request_keys_in_scope());
+            assert_eq!(resolve_api_key("flux", &envs).as_deref(), Some("req-flux"));
+            assert_eq!(
+                resolve_api_key("openrouter", &envs).as_deref(),
+                Some("req-or")
+            );
+            assert_eq!(
+                resolve_api_key("openai", &envs).as_deref(),
+                Some("acme-openai")
+            );
+            {
+                let callback = |request: &CredentialRequest| {
+                    (request.provider == "recraft").then(|| "cb-recraft".to_string())
+                };
+                let _inner = CredentialScope::begin(
+                    "recraft",
+                    RequestCredentials::default(),
+                    Some(Arc::new(callback)),
+                );
+                assert_eq!(
+                    resolve_api_key("recraft", &envs).as_deref(),
+                    Some("cb-recraft")
+                );
+                assert!(!request_keys_in_scope());
+                assert_eq!(resolve_api_key("flux", &envs).as_deref(), Some("from-env"));
+            }
+            assert_eq!(resolve_api_key("flux", &envs).as_deref(), Some("req-flux"));
+        }
+        assert_eq!(resolve_api_key("flux", &envs).as_deref(), Some("from-env"));
+
+        let mut bad = map_object(json!({"provider_options": {"api_key": 7}}));
+        assert!(RequestCredentials::take(&mut bad, &intent).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+use anyhow::{bail, Context, Result};
+use brood_contracts::models::ModelSpec;
+use serde_json::{json, Map, Value};
+
+use super::scoring::ScoringCandidate;
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{error_chain_text, map_object, NativeEngine, ProviderConfig};
+
+/// Instruction sent with each artifact and its prompt.
+const CRITIC_INSTRUCTIONS: &str = "You review images made by an image generation model. \
+Compare the image with the prompt it was generated from and reply with JSON only: \
+{\"score\": <0-100 prompt adherence and quality>, \"notes\": \"<one or two sentences on what \
+is missing or wrong, phrased as instructions for the next attempt>\"}.";
+
+/// Score the dryrun critic gives every artifact.
+pub const DRYRUN_CRITIC_SCORE: u32 = 72;
+pub const CRITIC_MAX_RETRIES: u32 = 5;
+pub const CRITIC_DEFAULT_THRESHOLD: u32 = 70;
+
+const CRITIC_NOTES_MAX_CHARS: usize = 600;
+
+/// `settings.critic`: `true` for defaults, or
+/// `{"model": "gpt-4o-mini", "auto_retry": 2, "threshold": 80}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CriticSpec {
+    /// Vision model to use; defaults to the engine's text model.
+    pub model: Option<String>,
+    /// Regenerations allowed while the best score stays below `threshold`.
+    pub auto_retry: u32,
+    pub threshold: u32,
+}
+
+impl CriticSpec {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let raw = match settings.get("critic") {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => return Ok(None),
+            Some(Value::Bool(true)) => Map::new(),
+            Some(Value::Object(raw)) => raw.clone(),
+            Some(other) => bail!("critic must be true or an object (got {other})"),
+        };
+        let model = match raw.get("model") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(model)) if !model.trim().is_empty() => {
+                Some(model.trim().to_string())
+            }
+            Some(other) => bail!("critic.model must be a model name (got {other})"),
+        };
+        let auto_retry = match raw.get("auto_retry") {
+            None | Some(Value::Null) => 0,
+            Some(value) => value
+                .as_u64()
+                .filter(|retries| *retries <= u64::from(CRITIC_MAX_RETRIES))
+                .with_context(|| {
+                    format!("critic.auto_retry must be 0..={CRITIC_MAX_RETRIES} (got {value})")
+                })? as u32,
+        };
+        let threshold = match raw.get("threshold") {
+            None | Some(Value::Null) => CRITIC_DEFAULT_THRESHOLD,
+            Some(value) => value
+                .as_u64()
+                .filter(|threshold| *threshold <= 100)
+                .with_context(|| format!("critic.threshold must be 0..=100 (got {value})"))?
+                as u32,
+        };
+        Ok(Some(Self {
+            model,
+            auto_retry,
+            threshold,
+        }))
+    }
+}
+
+/// A vision model's verdict on one artifact.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactCritique {
+    pub artifact_id: String,
+    /// Prompt adherence and quality, 0..=100.
+    pub score: u32,
+    pub notes: String,
+    pub model: String,
+    pub usage: TokenUsage,
+    /// Priced by the engine from the critic model's pricing row.
+    pub cost_usd: f64,
+}
+
+impl ArtifactCritique {
+    pub fn to_map(&self) -> Map<String, Value> {
+        map_object(json!({
+            "score": self.score,
+            "notes": self.notes,
+            "model": self.model,
+            "input_tokens": self.usage.input_tokens,
+            "output_tokens": self.usage.output_tokens,
+            "cost_usd": self.cost_usd,
+        }))
+    }
+}
+
+/// Asks a vision-capable model how well an artifact matches its prompt.
+#[derive(Debug, Clone)]
+pub struct ArtifactCritic {
+    client: TextModelClient,
+}
+
+impl ArtifactCritic {
+    pub fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    fn critique(
+        &self,
+        model: &ModelSpec,
+        candidate: &ScoringCandidate,
+    ) -> Result<ArtifactCritique> {
+        if !model.supports("vision") {
+            bail!("critic model '{}' is not vision-capable", model.name);
+        }
+        let (score, notes, usage) = if model.provider == "dryrun" {
+            (
+                DRYRUN_CRITIC_SCORE,
+                "dryrun critic: adherence not checked".to_string(),
+                TokenUsage::default(),
+            )
+        } else {
+            let prompt = format!("Prompt: {}", candidate.prompt);
+            let reply = self.client.complete(
+                model,
+                CRITIC_INSTRUCTIONS,
+                &prompt,
+                Some(&candidate.image_path),
+            )?;
+            let (score, notes) = parse_critique(&reply.text)
+                .with_context(|| format!("{} critic reply had no score", reply.transport))?;
+            (score, notes, reply.usage)
+        };
+        Ok(ArtifactCritique {
+            artifact_id: candidate.artifact_id.clone(),
+            score,
+            notes,
+            model: model.name.clone(),
+            usage,
+            cost_usd: 0.0,
+        })
+    }
+}
+
+impl NativeEngine {
+    /// Critiques every raster artifact of a version with a vision model
+    /// (`model`, else the engine's text model) and stores the result under
+    /// `metrics.critic` on each artifact row.
+    pub fn critique_version(
+        &mut self,
+        version_id: &str,
+        model: Option<&str>,
+    ) -> Result<Vec<ArtifactCritique>> {
+        let Some(model_name) = model
+            .map(str::to_string)
+            .or_else(|| self.text_model.clone())
+        else {
+            bail!("no critic model configured");
+        };
+        let Some(model) = self.model_selector.registry.get(&model_name).cloned() else {
+            bail!("critic model '{model_name}' is not registered");
+        };
+        let Some(version) = self
+            .thread
+            .live_versions()
+            .find(|version| version.version_id == version_id)
+        else {
+            bail!("version '{version_id}' not found");
+        };
+        let candidates: Vec<ScoringCandidate> = version
+            .artifacts
+            .iter()
+            .filter_map(|artifact| ScoringCandidate::from_artifact(artifact, &version.prompt))
+            .collect();
+        if candidates.is_empty() {
+            bail!("version '{version_id}' has no raster artifacts to critique");
+        }
+        let mut critiques = Vec::with_capacity(candidates.len());
+        for candidate in &candidates {
+            let mut critique = self.critic.critique(&model, candidate)?;
+            critique.cost_usd = self.record_text_call(&model, critique.usage)?;
+            critiques.push(critique);
+        }
+
+        if let Some(version) = self
+            .thread
+            .versions
+            .iter_mut()
+            .find(|version| version.version_id == version_id)
+        {
+            for artifact in &mut version.artifacts {
+                let artifact_id = artifact.get("artifact_id").and_then(Value::as_str);
+                let Some(critique) = critiques
+                    .iter()
+                    .find(|critique| Some(critique.artifact_id.as_str()) == artifact_id)
+                else {
+                    continue;
+                };
+                let metrics = artifact
+                    .entry("metrics".to_string())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Some(metrics) = metrics.as_object_mut() {
+                    metrics.insert("critic".to_string(), Value::Object(critique.to_map()));
+                }
+            }
+        }
+        self.thread.save()?;
+        for critique in &critiques {
+            let mut payload = critique.to_map();
+            payload.insert("version_id".to_string(), json!(version_id));
+            payload.insert("artifact_id".to_string(), json!(critique.artifact_id));
+            self.events.emit("artifact_critiqued", payload)?;
+        }
+        Ok(critiques)
+    }
+
+    /// Critiques the versions created since `versions_before` and, while the
+    /// best score stays under the threshold, regenerates each one with the
+    /// critic's notes appended to its prompt, up to `auto_retry` times.
+    /// Returns the artifacts of the retries.
+    pub(crate) fn run_critic_loop(
+        &mut self,
+        critic: &CriticSpec,
+        versions_before: usize,
+    ) -> Result<Vec<Map<String, Value>>> {
+        let version_ids: Vec<String> = self.thread.versions[versions_before..]
+            .iter()
+            .map(|version| version.version_id.clone())
+            .collect();
+        let mut retried = Vec::new();
+        for version_id in version_ids {
+            let Some(base_prompt) = self
+                .thread
+                .versions
+                .iter()
+                .find(|version| version.version_id == version_id)
+                .map(|version| version.prompt.clone())
+            else {
+                continue;
+            };
+            let mut current = version_id;
+            for attempt in 0..=critic.auto_retry {
+                // The critic is advisory; a failed call must not fail a
+                // generation that has already been paid for.
+                let critiques = match self.critique_version(&current, critic.model.as_deref()) {
+                    Ok(critiques) => critiques,
+                    Err(err) => {
+                        self.events.emit(
+                            "critic_failed",
+                            map_object(json!({
+                                "version_id": current,
+                                "error": error_chain_text(&err, 512),
+                            })),
+                        )?;
+                        break;
+                    }
+                };
+                let Some(best) =
+                    critiques
+                        .into_iter()
+                        .reduce(|best, next| if next.score > best.score { next } else { best })
+                else {
+                    break;
+                };
+                if best.score >= critic.threshold || attempt == critic.auto_retry {
+                    break;
+                }
+                let Some(settings) = self
+                    .thread
+                    .versions
+                    .iter()
+                    .find(|version| version.version_id == current)
+                    .map(|version| version.settings.clone())
+                else {
+                    break;
+                };
+                let prompt = critic_retry_prompt(&base_prompt, &best.notes);
+                self.events.emit(
+                    "critic_retry",
+                    map_object(json!({
+                        "version_id": current,
+                        "attempt": attempt + 1,
+                        "max_attempts": critic.auto_retry,
+                        "score": best.score,
+                        "threshold": critic.threshold,
+                        "prompt": prompt,
+                    })),
+                )?;
+                let intent = map_object(json!({
+                    "action": "critic_retry",
+                    "parent_version_id": current,
+                    "critic_attempt": attempt + 1,
+                    "critic_feedback": best.notes,
+                }));
+                retried.extend(self.generate_rendered(&prompt, settings, intent)?);
+                let Some(next) = self.thread.versions.last() else {
+                    break;
+                };
+                current = next.version_id.clone();
+            }
+        }
+        Ok(retried)
+    }
+}
+
+/// The original prompt with the critic's notes appended; notes from earlier
+/// attempts are not carried over.
+fn critic_retry_prompt(prompt: &str, notes: &str) -> String {
+    format!("{}\n\nFix from review: {}", prompt.trim(), notes.trim())
+}
+
+/// Reads `{"score": .., "notes": ..}` from a model reply, tolerating prose
+/// or code fences around the JSON.
+fn parse_critique(reply: &str) -> Option<(u32, String)> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let payload: Value = serde_json::from_str(reply.get(start..=end)?).ok()?;
+    let score = match payload.get("score")? {
+        Value::Number(number) => number.as_f64()?,
+        Value::String(text) => text.trim().parse::<f64>().ok()?,
+        _ => return None,
+    };
+    let notes = payload
+        .get("notes")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(CRITIC_NOTES_MAX_CHARS)
+        .collect();
+    Some((score.clamp(0.0, 100.0).round() as u32, notes))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::{critic_retry_prompt, parse_critique, CriticSpec, CRITIC_DEFAULT_THRESHOLD};
+
+    #[test]
+    fn parses_critic_settings_and_replies() -> anyhow::Result<()> {
+        let mut settings = Map::new();
+        assert_eq!(CriticSpec::from_settings(&settings)?, None);
+        settings.insert("critic".to_string(), json!(true));
+        assert_eq!(
+            CriticSpec::from_settings(&settings)?,
+            Some(CriticSpec {
+                model: None,
+                auto_retry: 0,
+                threshold: CRITIC_DEFAULT_THRESHOLD,
+            })
+        );
+        settings.insert(
+            "critic".to_string(),
+            json!({"model": "gpt-4o-mini", "auto_retry": 2, "threshold": 85}),
+        );
+        let spec = CriticSpec::from_settings(&settings)?;
+        assert_eq!(spec.map(|spec| spec.auto_retry), Some(2));
+        settings.insert("critic".to_string(), json!({"auto_retry": 9}));
+        assert!(CriticSpec::from_settings(&settings).is_err());
+        settings.insert("critic".to_string(), json!({"threshold": 101}));
+        assert!(CriticSpec::from_settings(&settings).is_err());
+
+        assert_eq!(
+            parse_critique("```json\n{\"score\": 64.6, \"notes\": \"Add  the moon.\"}\n```"),
+            Some((65, "Add the moon.".to_string()))
+        );
+        assert_eq!(
+            parse_critique("{\"score\": \"140\"}"),
+            Some((100, String::new()))
+        );
+        assert_eq!(parse_critique("looks great"), None);
+        assert_eq!(
+            critic_retry_prompt("a fox ", "Show the tail."),
+            "a fox\n\nFix from review: Show the tail."
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use serde_json::{Map, Value};
+
+use brood_contracts::runs::thread_manifest::ThreadManifest;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+
+/// Default `settings.dedup_max_distance`: dHash bit differences (out of 64)
+/// at or below which two images count as near-duplicates.
+pub const DEDUP_DEFAULT_MAX_DISTANCE: u32 = 5;
+
+/// What to do with an artifact that is a near-duplicate of an earlier one
+/// in the run (`settings.dedup`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    Off,
+    /// Keep the artifact but attach a `near_duplicate` warning (default).
+    Warn,
+    /// Delete the image and leave it out of the version.
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DedupPolicy {
+    pub(crate) mode: DedupMode,
+    pub(crate) max_distance: u32,
+}
+
+impl DedupPolicy {
+    pub(crate) fn from_settings(settings: &Map<String, Value>) -> Result<Self> {
+        let mode = match settings.get("dedup") {
+            None | Some(Value::Null) => DedupMode::Warn,
+            Some(Value::Bool(false)) => DedupMode::Off,
+            Some(Value::Bool(true)) => DedupMode::Warn,
+            Some(Value::String(value)) => match value.trim().to_ascii_lowercase().as_str() {
+                "off" | "none" => DedupMode::Off,
+                "warn" => DedupMode::Warn,
+                "skip" => DedupMode::Skip,
+                other => bail!("unknown dedup mode '{other}' (expected off, warn or skip)"),
+            },
+            Some(other) => bail!("dedup must be off, warn or skip, got {other}"),
+        };
+        let max_distance = match settings.get("dedup_max_distance") {
+            None | Some(Value::Null) => DEDUP_DEFAULT_MAX_DISTANCE,
+            Some(value) => match value.as_u64().filter(|distance| *distance <= 64) {
+                Some(distance) => distance as u32,
+                None => bail!("dedup_max_distance must be an integer in 0..=64"),
+            },
+        };
+        Ok(Self { mode, max_distance })
+    }
+}
+
+/// An earlier artifact that a new image is perceptually close to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NearDuplicate {
+    pub(crate) artifact_id: String,
+    pub(crate) distance: u32,
+}
+
+impl NearDuplicate {
+    pub(crate) fn warning(&self) -> Warning {
+        Warning::new(
+            WarningCode::NearDuplicate,
+            format!(
+                "Near-duplicate of {} (dHash distance {}).",
+                self.artifact_id, self.distance
+            ),
+        )
+    }
+}
+
+/// 64-bit difference hash: the image is reduced to 9x8 luma and each bit
+/// records whether a pixel is brighter than its right neighbour.
+pub fn image_dhash(path: &Path) -> Result<u64> {
+    let image = image::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+pub(crate) fn dhash_hex(hash: u64) -> String {
+    format!("{hash:016x}")
+}
+
+/// Closest artifact in the run (any version, including soft-deleted ones)
+/// whose stored `dhash` is within `max_distance` bits of `hash`.
+pub(crate) fn find_near_duplicate(
+    thread: &ThreadManifest,
+    hash: u64,
+    max_distance: u32,
+) -> Option<NearDuplicate> {
+    thread
+        .versions
+        .iter()
+        .flat_map(|version| version.artifacts.iter())
+        .filter_map(|artifact| {
+            let existing = artifact
+                .get("dhash")
+                .and_then(Value::as_str)
+                .and_then(|text| u64::from_str_radix(text, 16).ok())?;
+            Some(NearDuplicate {
+                artifact_id: artifact.get("artifact_id")?.as_str()?.to_string(),
+                distance: (existing ^ hash).count_ones(),
+            })
+        })
+        .filter(|candidate| candidate.distance <= max_distance)
+        .min_by_key(|candidate| candidate.distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+    use serde_json::{json, Map, Value};
+
+    use super::{image_dhash, DedupMode, DedupPolicy, DEDUP_DEFAULT_MAX_DISTANCE};
+    use crate::{map_object, NativeEngine};
+
+    fn settings(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn dhash_matches_rescaled_copies_and_separates_different_images() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let gradient =
+            |size: u32| GrayImage::from_fn(size, size, |x, _| Luma([(x * 255 / size) as u8]));
+        let small = temp.path().join("small.png");
+        let large = temp.path().join("large.png");
+        let flipped = temp.path().join("flipped.png");
+        gradient(32).save(&small)?;
+        gradient(96).save(&large)?;
+        image::imageops::flip_horizontal(&gradient(32)).save(&flipped)?;
+
+        let base = image_dhash(&small)?;
+        assert!((base ^ image_dhash(&large)?).count_ones() <= DEDUP_DEFAULT_MAX_DISTANCE);
+        assert!((base ^ image_dhash(&flipped)?).count_ones() > 32);
+        Ok(())
+    }
+
+    #[test]
+    fn dedup_policy_parses_settings() -> anyhow::Result<()> {
+        assert_eq!(
+            DedupPolicy::from_settings(&Map::new())?,
+            DedupPolicy {
+                mode: DedupMode::Warn,
+                max_distance: DEDUP_DEFAULT_MAX_DISTANCE
+            }
+        );
+        let skip = DedupPolicy::from_settings(&settings(
+            json!({"dedup": "skip", "dedup_max_distance": 0}),
+        ))?;
+        assert_eq!((skip.mode, skip.max_distance), (DedupMode::Skip, 0));
+        assert_eq!(
+            DedupPolicy::from_settings(&settings(json!({"dedup": false})))?.mode,
+            DedupMode::Off
+        );
+        assert!(DedupPolicy::from_settings(&settings(json!({"dedup": "drop"}))).is_err());
+        assert!(DedupPolicy::from_settings(&settings(json!({"dedup_max_distance": 65}))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn near_duplicates_are_flagged_or_skipped() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let events_path = run_dir.join("events.jsonl");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            &events_path,
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        // Dryrun renders identical images for the same prompt and seed.
+        let mut settings = settings(json!({"size": "32x32", "n": 2, "seed": 7}));
+        let intent = map_object(json!({"action": "generate"}));
+        let kept = engine.generate("a lighthouse", settings.clone(), intent.clone())?;
+        assert_eq!(kept.len(), 2);
+        assert!(kept[0]["dhash"].is_string());
+        let receipt: Value = serde_json::from_str(&std::fs::read_to_string(
+            kept[1]["receipt_path"].as_str().unwrap_or_default(),
+        )?)?;
+        assert_eq!(receipt["artifacts"]["image_dhash"], kept[1]["dhash"]);
+        assert_eq!(
+            receipt["warning_details"][0]["code"],
+            json!("near_duplicate")
+        );
+
+        settings.insert("dedup".to_string(), json!("skip"));
+        settings.insert("n".to_string(), json!(1));
+        let skipped = engine.generate("a lighthouse", settings, intent)?;
+        assert!(skipped.is_empty());
+        let events = std::fs::read_to_string(&events_path)?;
+        assert!(events.contains("\"action\":\"skipped\""));
+        assert_eq!(
+            std::fs::read_dir(&run_dir)?
+                .flatten()
+                .filter(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    name.starts_with("artifact-") && !name.ends_with("-thumb.webp")
+                })
+                .count(),
+            2
+        );
+        Ok(())
+    }
+}
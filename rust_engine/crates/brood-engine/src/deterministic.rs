@@ -0,0 +1,190 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_json_atomic;
+use brood_contracts::runs::warnings::Warning;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::capabilities::ProviderCapabilities;
+use super::{map_object, now_utc_iso, ImageProvider, NativeEngine, ProviderGenerateRequest};
+
+/// Run-dir file listing every deterministic generation and the providers
+/// that could not guarantee identical output.
+pub const REPRODUCIBILITY_FILENAME: &str = "reproducibility.json";
+
+/// `settings.deterministic`: `true` or `false` (default).
+pub(crate) fn deterministic_from_settings(settings: &Map<String, Value>) -> Result<bool> {
+    match settings.get("deterministic") {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(enabled)) => Ok(*enabled),
+        Some(other) => bail!("settings.deterministic must be true or false, got {other}"),
+    }
+}
+
+/// Seed used when a deterministic run names none: stable per prompt, so
+/// running the same prompt again lands on the same seed.
+pub(crate) fn derived_seed(prompt: &str) -> i64 {
+    let digest = Sha256::digest(prompt.as_bytes());
+    i64::from(u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) & 0x7fff_ffff)
+}
+
+/// Settings a deterministic run must not leave to the provider.
+pub(crate) fn check_deterministic_settings(
+    size: &str,
+    fallback_reason: Option<&str>,
+) -> Result<()> {
+    if size.trim().eq_ignore_ascii_case("auto") {
+        bail!("deterministic runs need an explicit size, not auto");
+    }
+    if let Some(reason) = fallback_reason {
+        bail!("deterministic runs cannot fall back to another model ({reason})");
+    }
+    Ok(())
+}
+
+/// Why `provider` cannot promise the same image for the same request.
+pub(crate) fn nondeterminism_reasons(
+    provider: &str,
+    capabilities: &ProviderCapabilities,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if !capabilities.supports_seed {
+        reasons.push(format!("{provider} does not accept seeds."));
+    }
+    if !capabilities.deterministic {
+        reasons.push(format!(
+            "{provider} does not guarantee identical images for a fixed seed."
+        ));
+    }
+    reasons
+}
+
+/// What a deterministic generation pinned, for [`REPRODUCIBILITY_FILENAME`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ReproducibilityEntry {
+    pub(crate) version_id: String,
+    pub(crate) provider: String,
+    pub(crate) model: String,
+    pub(crate) seeds: Vec<Option<i64>>,
+    pub(crate) pinned_options: Map<String, Value>,
+    pub(crate) reasons: Vec<String>,
+}
+
+impl NativeEngine {
+    /// Fails when `provider` would change the request on its own (snapped
+    /// size, clamped n) or the engine already changed it (`request_changes`,
+    /// each a warning about a dropped or replaced setting), then returns the
+    /// options pinning its model version.
+    pub(crate) fn deterministic_preflight(
+        &self,
+        provider: &dyn ImageProvider,
+        request: &ProviderGenerateRequest,
+        settings: &Map<String, Value>,
+        request_changes: &[Warning],
+    ) -> Result<Map<String, Value>> {
+        // Seedless providers are reported, not refused.
+        let mut checked = settings.clone();
+        checked.remove("seed");
+        checked.remove("seed_sweep");
+        let mut problems = provider.capabilities().unmet_settings(
+            provider.name(),
+            &checked,
+            &request.size,
+            request.n,
+        );
+        problems.extend(request_changes.iter().map(|change| change.message.clone()));
+        if !problems.is_empty() {
+            bail!(
+                "deterministic run refused: {}",
+                problems.join(" ").trim_end()
+            );
+        }
+        provider
+            .pinned_options(request)
+            .with_context(|| format!("failed to pin the {} model version", provider.name()))
+    }
+
+    /// Appends `entry` to [`REPRODUCIBILITY_FILENAME`] and emits
+    /// `reproducibility_report`.
+    pub(crate) fn record_reproducibility(&self, entry: ReproducibilityEntry) -> Result<()> {
+        let path = self.run_dir.join(REPRODUCIBILITY_FILENAME);
+        let mut report = fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<Value>(&raw).ok())
+            .and_then(|value| value.as_object().cloned())
+            .unwrap_or_default();
+        let generation = json!({
+            "version_id": entry.version_id,
+            "provider": entry.provider,
+            "model": entry.model,
+            "seeds": entry.seeds,
+            "pinned_options": entry.pinned_options,
+            "deterministic": entry.reasons.is_empty(),
+            "reasons": entry.reasons,
+        });
+        let mut generations = report
+            .get("generations")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        generations.push(generation.clone());
+        let mut nondeterministic: Vec<Value> = Vec::new();
+        for row in &generations {
+            if row["deterministic"] == json!(false)
+                && !nondeterministic
+                    .iter()
+                    .any(|seen| seen["provider"] == row["provider"])
+            {
+                nondeterministic.push(json!({
+                    "provider": row["provider"],
+                    "reasons": row["reasons"],
+                }));
+            }
+        }
+        report.insert("updated_at".to_string(), Value::String(now_utc_iso()));
+        report.insert(
+            "deterministic".to_string(),
+            Value::Bool(nondeterministic.is_empty()),
+        );
+        report.insert(
+            "nondeterministic_providers".to_string(),
+            Value::Array(nondeterministic.clone()),
+        );
+        report.insert("generations".to_string(), Value::Array(generations));
+        write_json_atomic(&path, &Value::Object(report))?;
+        let mut payload = map_object(generation);
+        payload.insert(
+            "report_path".to_string(),
+            Value::String(path.to_string_lossy().to_string()),
+        );
+        payload.insert(
+            "nondeterministic_providers".to_string(),
+            Value::Array(nondeterministic),
+        );
+        self.events.emit("reproducibility_report", payload)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{derived_seed, deterministic_from_settings};
+    use crate::map_object;
+
+    #[test]
+    fn deterministic_settings_and_seeds() -> anyhow::Result<()> {
+        assert!(deterministic_from_settings(&map_object(
+            json!({"deterministic": true})
+        ))?);
+        assert!(!deterministic_from_settings(&map_object(json!({})))?);
+        assert!(deterministic_from_settings(&map_object(json!({"deterministic": "yes"}))).is_err());
+
+        assert_eq!(derived_seed("a red chair"), derived_seed("a red chair"));
+        assert_ne!(derived_seed("a red chair"), derived_seed("a blue chair"));
+        assert!(derived_seed("a red chair") >= 0);
+        Ok(())
+    }
+}
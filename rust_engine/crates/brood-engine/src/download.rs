@@ -0,0 +1,209 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use reqwest::blocking::Response as HttpResponse;
+use sha2::{Digest, Sha256};
+
+use super::{non_empty_env, truncate_text};
+
+/// Largest provider download Brood accepts, in MB; larger bodies fail the
+/// call instead of filling the disk.
+pub const MAX_DOWNLOAD_ENV: &str = "BROOD_MAX_DOWNLOAD_MB";
+const DEFAULT_MAX_DOWNLOAD_MB: u64 = 1024;
+
+fn max_download_bytes() -> u64 {
+    non_empty_env(MAX_DOWNLOAD_ENV)
+        .and_then(|value| value.parse::<u64>().ok())
+        .filter(|mb| *mb > 0)
+        .unwrap_or(DEFAULT_MAX_DOWNLOAD_MB)
+        .saturating_mul(1024 * 1024)
+}
+
+/// A successful provider response whose body has not been read yet; it is
+/// streamed to disk by [`Download::save`] instead of held in memory.
+pub(crate) struct Download {
+    reader: BufReader<HttpResponse>,
+    label: String,
+    limit: u64,
+}
+
+impl std::fmt::Debug for Download {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Download")
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+/// What [`Download::save`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Saved {
+    pub(crate) size: u64,
+    pub(crate) sha256: String,
+}
+
+impl Download {
+    /// Fails on an error status, with the start of its body, or on a
+    /// `Content-Length` over the limit. `label` names the download in
+    /// errors ("Fal image").
+    pub(crate) fn start(response: HttpResponse, label: &str) -> Result<Self> {
+        if !response.status().is_success() {
+            let code = response.status().as_u16();
+            let body = response.text().unwrap_or_default();
+            bail!(
+                "{label} download failed ({code}): {}",
+                truncate_text(&body, 512)
+            );
+        }
+        let limit = max_download_bytes();
+        if let Some(length) = response.content_length().filter(|length| *length > limit) {
+            bail!("{label} download is {length} bytes, over the {limit}-byte limit ({MAX_DOWNLOAD_ENV})");
+        }
+        Ok(Self {
+            reader: BufReader::with_capacity(64 * 1024, response),
+            label: label.to_string(),
+            limit,
+        })
+    }
+
+    pub(crate) fn mime_type(&self) -> Option<String> {
+        self.reader
+            .get_ref()
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    }
+
+    /// The first bytes of the body, for sniffing the format before saving.
+    pub(crate) fn head(&mut self) -> Result<&[u8]> {
+        let label = &self.label;
+        self.reader
+            .fill_buf()
+            .with_context(|| format!("failed reading {label} bytes"))
+    }
+
+    /// Streams the body to `path`, hashing it on the way for the artifact's
+    /// receipt. The body goes to a `.part` file renamed into place once
+    /// complete, so a failed download leaves nothing at `path`.
+    pub(crate) fn save(mut self, path: &Path) -> Result<Saved> {
+        let partial = partial_path(path);
+        let outcome = self.copy_to(&partial);
+        let saved = match outcome {
+            Ok(saved) => saved,
+            Err(err) => {
+                let _ = fs::remove_file(&partial);
+                return Err(err);
+            }
+        };
+        fs::rename(&partial, path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(saved)
+    }
+
+    fn copy_to(&mut self, partial: &Path) -> Result<Saved> {
+        let file = File::create(partial)
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        let mut writer = BufWriter::new(file);
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let (label, limit) = (&self.label, self.limit);
+        loop {
+            let chunk = self
+                .reader
+                .fill_buf()
+                .with_context(|| format!("failed reading {label} bytes"))?;
+            if chunk.is_empty() {
+                break;
+            }
+            let len = chunk.len();
+            size += len as u64;
+            if size > limit {
+                bail!("{label} download exceeded the {limit}-byte limit ({MAX_DOWNLOAD_ENV})");
+            }
+            hasher.update(chunk);
+            writer
+                .write_all(chunk)
+                .with_context(|| format!("failed to write {}", partial.display()))?;
+            self.reader.consume(len);
+        }
+        // Synced before the rename in `save`, so a crash never publishes a
+        // short file under the final name.
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        Ok(Saved {
+            size,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    }
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Writes in-memory bytes the way [`Download::save`] writes a stream, so
+/// both return the receipt digest.
+pub(crate) fn save_bytes(bytes: &[u8], path: &Path) -> Result<Saved> {
+    write_atomic(path, bytes)?;
+    Ok(Saved {
+        size: bytes.len() as u64,
+        sha256: hex::encode(Sha256::digest(bytes)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use brood_contracts::runs::receipts::file_sha256;
+    use sha2::{Digest, Sha256};
+
+    use super::{Download, MAX_DOWNLOAD_ENV};
+    use crate::test_support::{MockResponse, MockServer};
+
+    #[test]
+    fn downloads_stream_to_disk_with_digest_and_size_limit() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        let body = vec![7u8; 3 * 1024 * 1024];
+        server
+            .mock(
+                "GET",
+                "/big.png",
+                MockResponse::bytes(200, "image/png", body.clone()),
+            )
+            .mock(
+                "GET",
+                "/missing.png",
+                MockResponse::json(404, serde_json::json!({"error": "gone"})),
+            );
+        let dir = tempfile::tempdir()?;
+        let http = reqwest::blocking::Client::new();
+
+        let mut download = Download::start(http.get(server.url_for("big.png")).send()?, "test")?;
+        assert_eq!(download.mime_type().as_deref(), Some("image/png"));
+        assert_eq!(download.head()?[0], 7);
+        let path = dir.path().join("artifact.png");
+        let saved = download.save(&path)?;
+        let digest = hex::encode(Sha256::digest(&body));
+        assert_eq!(saved.size, body.len() as u64);
+        assert_eq!(saved.sha256, digest);
+        assert_eq!(file_sha256(&path), Some(digest));
+
+        let err = Download::start(http.get(server.url_for("missing.png")).send()?, "test")
+            .expect_err("404 fails");
+        assert!(format!("{err:#}").contains("test download failed (404)"));
+
+        std::env::set_var(MAX_DOWNLOAD_ENV, "2");
+        let outcome = Download::start(http.get(server.url_for("big.png")).send()?, "test");
+        std::env::remove_var(MAX_DOWNLOAD_ENV);
+        assert!(format!("{:#}", outcome.expect_err("over limit")).contains("limit"));
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use serde_json::{Map, Value};
+
+use super::FLUX_FILL_ENDPOINT;
+
+/// Area of the init image an edit is allowed to touch.
+///
+/// Masks are rendered as 8-bit grayscale PNGs where white marks the editable
+/// region; providers that expect a different encoding convert from this.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditRegion {
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Ellipse {
+        cx: f64,
+        cy: f64,
+        rx: f64,
+        ry: f64,
+    },
+    Polygon(Vec<(f64, f64)>),
+    Mask(PathBuf),
+    Outpaint {
+        left: u32,
+        top: u32,
+        right: u32,
+        bottom: u32,
+    },
+}
+
+impl EditRegion {
+    pub fn mode(&self) -> &'static str {
+        match self {
+            Self::Outpaint { .. } => "outpaint",
+            _ => "inpaint",
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut row = Map::new();
+        match self {
+            Self::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                row.insert("shape".to_string(), Value::String("rect".to_string()));
+                row.insert("x".to_string(), Value::Number((*x).into()));
+                row.insert("y".to_string(), Value::Number((*y).into()));
+                row.insert("width".to_string(), Value::Number((*width).into()));
+                row.insert("height".to_string(), Value::Number((*height).into()));
+            }
+            Self::Ellipse { cx, cy, rx, ry } => {
+                row.insert("shape".to_string(), Value::String("ellipse".to_string()));
+                row.insert("cx".to_string(), Value::from(*cx));
+                row.insert("cy".to_string(), Value::from(*cy));
+                row.insert("rx".to_string(), Value::from(*rx));
+                row.insert("ry".to_string(), Value::from(*ry));
+            }
+            Self::Polygon(points) => {
+                row.insert("shape".to_string(), Value::String("polygon".to_string()));
+                row.insert(
+                    "points".to_string(),
+                    Value::Array(
+                        points
+                            .iter()
+                            .map(|(x, y)| Value::Array(vec![Value::from(*x), Value::from(*y)]))
+                            .collect(),
+                    ),
+                );
+            }
+            Self::Mask(path) => {
+                row.insert("shape".to_string(), Value::String("mask".to_string()));
+                row.insert(
+                    "path".to_string(),
+                    Value::String(path.to_string_lossy().to_string()),
+                );
+            }
+            Self::Outpaint {
+                left,
+                top,
+                right,
+                bottom,
+            } => {
+                row.insert("shape".to_string(), Value::String("outpaint".to_string()));
+                row.insert("left".to_string(), Value::Number((*left).into()));
+                row.insert("top".to_string(), Value::Number((*top).into()));
+                row.insert("right".to_string(), Value::Number((*right).into()));
+                row.insert("bottom".to_string(), Value::Number((*bottom).into()));
+            }
+        }
+        Value::Object(row)
+    }
+
+    fn contains(&self, px: f64, py: f64) -> bool {
+        match self {
+            Self::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                px >= *x as f64
+                    && py >= *y as f64
+                    && px < (*x + *width) as f64
+                    && py < (*y + *height) as f64
+            }
+            Self::Ellipse { cx, cy, rx, ry } => {
+                if *rx <= 0.0 || *ry <= 0.0 {
+                    return false;
+                }
+                let dx = (px - cx) / rx;
+                let dy = (py - cy) / ry;
+                dx * dx + dy * dy <= 1.0
+            }
+            Self::Polygon(points) => point_in_polygon(points, px, py),
+            Self::Mask(_) | Self::Outpaint { .. } => false,
+        }
+    }
+}
+
+/// Renders a shape region into a white-on-black grayscale mask.
+pub fn render_region_mask(region: &EditRegion, width: u32, height: u32) -> Result<GrayImage> {
+    if let EditRegion::Mask(path) = region {
+        let mask = image::open(path)
+            .with_context(|| format!("failed to open mask {}", path.display()))?
+            .to_luma8();
+        if mask.dimensions() != (width, height) {
+            return Ok(image::imageops::resize(
+                &mask,
+                width,
+                height,
+                image::imageops::FilterType::Nearest,
+            ));
+        }
+        return Ok(mask);
+    }
+    if let EditRegion::Outpaint {
+        left,
+        top,
+        right,
+        bottom,
+    } = region
+    {
+        let inner_w = width.saturating_sub(left + right);
+        let inner_h = height.saturating_sub(top + bottom);
+        return Ok(GrayImage::from_fn(width, height, |x, y| {
+            let inside = x >= *left && y >= *top && x < left + inner_w && y < top + inner_h;
+            Luma([if inside { 0 } else { 255 }])
+        }));
+    }
+    let mask = GrayImage::from_fn(width, height, |x, y| {
+        let covered = region.contains(x as f64 + 0.5, y as f64 + 0.5);
+        Luma([if covered { 255 } else { 0 }])
+    });
+    if mask.pixels().all(|pixel| pixel[0] == 0) {
+        bail!("edit region does not cover any pixels of a {width}x{height} image");
+    }
+    Ok(mask)
+}
+
+/// Converts a white-on-black mask to the RGBA form OpenAI edits expect,
+/// where fully transparent pixels mark the editable area.
+pub fn alpha_mask_from_gray(mask: &GrayImage) -> RgbaImage {
+    RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let value = mask.get_pixel(x, y)[0];
+        Rgba([0, 0, 0, 255 - value])
+    })
+}
+
+/// Pads the init image with a transparent border for outpainting on
+/// providers without a dedicated outpaint endpoint.
+pub(crate) fn pad_for_outpaint(
+    init_image: &Path,
+    out_path: &Path,
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+) -> Result<(u32, u32)> {
+    let source = image::open(init_image)
+        .with_context(|| format!("failed to open {}", init_image.display()))?
+        .to_rgba8();
+    let width = source.width() + left + right;
+    let height = source.height() + top + bottom;
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+    image::imageops::overlay(&mut canvas, &source, left as i64, top as i64);
+    canvas
+        .save(out_path)
+        .with_context(|| format!("failed to write {}", out_path.display()))?;
+    Ok((width, height))
+}
+
+/// Provider option overrides that point a generation at the provider's
+/// inpaint/outpaint surface.
+pub(crate) fn edit_route_options(
+    provider: &str,
+    region: &EditRegion,
+) -> Result<Map<String, Value>> {
+    let mut options = Map::new();
+    match provider {
+        // Replicate takes the mask as an input; outpaint pads the init image.
+        "openai" | "dryrun" | "replicate" => {}
+        "stability" => {
+            let endpoint = if let EditRegion::Outpaint {
+                left,
+                top,
+                right,
+                bottom,
+            } = region
+            {
+                options.insert("left".to_string(), Value::Number((*left).into()));
+                options.insert("up".to_string(), Value::Number((*top).into()));
+                options.insert("right".to_string(), Value::Number((*right).into()));
+                options.insert("down".to_string(), Value::Number((*bottom).into()));
+                "v2beta/stable-image/edit/outpaint"
+            } else {
+                "v2beta/stable-image/edit/inpaint"
+            };
+            options.insert(
+                "stability_endpoint".to_string(),
+                Value::String(endpoint.to_string()),
+            );
+        }
+        "flux" => {
+            options.insert(
+                "endpoint".to_string(),
+                Value::String(FLUX_FILL_ENDPOINT.to_string()),
+            );
+        }
+        other => bail!("provider '{other}' does not support inpaint/outpaint edits"),
+    }
+    Ok(options)
+}
+
+fn point_in_polygon(points: &[(f64, f64)], px: f64, py: f64) -> bool {
+    if points.len() < 3 {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+        if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{alpha_mask_from_gray, edit_route_options, render_region_mask, EditRegion};
+
+    #[test]
+    fn region_masks_cover_expected_pixels() -> anyhow::Result<()> {
+        let rect = render_region_mask(
+            &EditRegion::Rect {
+                x: 2,
+                y: 2,
+                width: 4,
+                height: 3,
+            },
+            10,
+            10,
+        )?;
+        assert_eq!(rect.get_pixel(2, 2)[0], 255);
+        assert_eq!(rect.get_pixel(5, 4)[0], 255);
+        assert_eq!(rect.get_pixel(6, 4)[0], 0);
+
+        let ellipse = render_region_mask(
+            &EditRegion::Ellipse {
+                cx: 5.0,
+                cy: 5.0,
+                rx: 3.0,
+                ry: 2.0,
+            },
+            10,
+            10,
+        )?;
+        assert_eq!(ellipse.get_pixel(5, 5)[0], 255);
+        assert_eq!(ellipse.get_pixel(0, 0)[0], 0);
+
+        let triangle = render_region_mask(
+            &EditRegion::Polygon(vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)]),
+            10,
+            10,
+        )?;
+        assert_eq!(triangle.get_pixel(1, 1)[0], 255);
+        assert_eq!(triangle.get_pixel(9, 9)[0], 0);
+
+        let alpha = alpha_mask_from_gray(&rect);
+        assert_eq!(alpha.get_pixel(3, 3)[3], 0);
+        assert_eq!(alpha.get_pixel(0, 0)[3], 255);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_region_is_rejected() {
+        let region = EditRegion::Rect {
+            x: 50,
+            y: 50,
+            width: 4,
+            height: 4,
+        };
+        assert!(render_region_mask(&region, 10, 10).is_err());
+    }
+
+    #[test]
+    fn edit_routes_select_provider_endpoints() -> anyhow::Result<()> {
+        let inpaint = EditRegion::Rect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let outpaint = EditRegion::Outpaint {
+            left: 64,
+            top: 0,
+            right: 64,
+            bottom: 0,
+        };
+        assert_eq!(
+            edit_route_options("stability", &inpaint)?["stability_endpoint"],
+            "v2beta/stable-image/edit/inpaint"
+        );
+        let stability_outpaint = edit_route_options("stability", &outpaint)?;
+        assert_eq!(
+            stability_outpaint["stability_endpoint"],
+            "v2beta/stable-image/edit/outpaint"
+        );
+        assert_eq!(stability_outpaint["left"], 64);
+        assert_eq!(
+            edit_route_options("flux", &inpaint)?["endpoint"],
+            "flux-pro-1.0-fill"
+        );
+        assert!(edit_route_options("openai", &inpaint)?.is_empty());
+        assert!(edit_route_options("replicate", &outpaint)?.is_empty());
+        assert!(edit_route_options("imagen", &inpaint).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use brood_contracts::runs::atomic::write_json_atomic;
+use serde_json::{json, Map, Value};
+
+use super::scoring::{score_artifacts, ScoringCandidate};
+use super::{error_chain_text, map_object, now_utc_iso, NativeEngine};
+
+/// One arm of a prompt A/B experiment. `settings` override the experiment's
+/// shared settings for this variant only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptVariant {
+    pub label: String,
+    pub prompt: String,
+    pub settings: Map<String, Value>,
+}
+
+impl PromptVariant {
+    pub fn new(label: impl Into<String>, prompt: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            prompt: prompt.into(),
+            settings: Map::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentVariantOutcome {
+    pub label: String,
+    pub prompt: String,
+    pub status: String,
+    pub version_ids: Vec<String>,
+    pub artifact_ids: Vec<String>,
+    pub cost_usd: f64,
+    /// Provider latency per image from the cost/latency metrics.
+    pub latency_per_image_s: Option<f64>,
+    /// Wall-clock time for the whole variant, including cache hits.
+    pub elapsed_s: f64,
+    /// Best artifact quality score, scored across all variants together so
+    /// the values are comparable between them.
+    pub quality_score: Option<f64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentSummary {
+    pub experiment_id: String,
+    pub variants: Vec<ExperimentVariantOutcome>,
+    pub summary_path: PathBuf,
+}
+
+impl ExperimentSummary {
+    pub fn cheapest(&self) -> Option<&ExperimentVariantOutcome> {
+        self.succeeded()
+            .min_by(|left, right| left.cost_usd.total_cmp(&right.cost_usd))
+    }
+
+    pub fn fastest(&self) -> Option<&ExperimentVariantOutcome> {
+        self.succeeded()
+            .filter(|variant| variant.latency_per_image_s.is_some())
+            .min_by(|left, right| {
+                left.latency_per_image_s
+                    .unwrap_or_default()
+                    .total_cmp(&right.latency_per_image_s.unwrap_or_default())
+            })
+    }
+
+    pub fn best_quality(&self) -> Option<&ExperimentVariantOutcome> {
+        self.succeeded()
+            .filter(|variant| variant.quality_score.is_some())
+            .max_by(|left, right| {
+                left.quality_score
+                    .unwrap_or_default()
+                    .total_cmp(&right.quality_score.unwrap_or_default())
+            })
+    }
+
+    fn succeeded(&self) -> impl Iterator<Item = &ExperimentVariantOutcome> {
+        self.variants
+            .iter()
+            .filter(|variant| variant.status == "ok")
+    }
+}
+
+impl NativeEngine {
+    /// Generates every variant in this run under one experiment id and writes
+    /// `experiment-<id>.json` comparing their cost and latency. A failing
+    /// variant (provider error, budget) is recorded and the rest still run.
+    pub fn experiment(
+        &mut self,
+        variants: Vec<PromptVariant>,
+        settings: Map<String, Value>,
+    ) -> Result<ExperimentSummary> {
+        if variants.is_empty() {
+            bail!("an experiment needs at least one variant");
+        }
+        for (idx, variant) in variants.iter().enumerate() {
+            if variant.label.trim().is_empty() {
+                bail!("experiment variant {} has no label", idx + 1);
+            }
+            if variants[..idx]
+                .iter()
+                .any(|other| other.label == variant.label)
+            {
+                bail!("duplicate experiment variant label '{}'", variant.label);
+            }
+        }
+
+        let experiment_id = self.next_experiment_id();
+        let started_at = now_utc_iso();
+        self.events.emit(
+            "experiment_started",
+            map_object(json!({
+                "experiment_id": experiment_id,
+                "variants": variants
+                    .iter()
+                    .map(|variant| json!({ "label": variant.label, "prompt": variant.prompt }))
+                    .collect::<Vec<_>>(),
+                "settings": settings,
+            })),
+        )?;
+
+        let mut outcomes = Vec::with_capacity(variants.len());
+        for variant in &variants {
+            let outcome = self.run_experiment_variant(&experiment_id, variant, &settings)?;
+            self.events.emit(
+                "experiment_variant_completed",
+                map_object(json!({
+                    "experiment_id": experiment_id,
+                    "label": outcome.label,
+                    "status": outcome.status,
+                    "version_ids": outcome.version_ids,
+                    "artifact_ids": outcome.artifact_ids,
+                    "cost_usd": outcome.cost_usd,
+                    "latency_per_image_s": outcome.latency_per_image_s,
+                    "elapsed_s": outcome.elapsed_s,
+                    "error": outcome.error,
+                })),
+            )?;
+            outcomes.push(outcome);
+        }
+        self.score_experiment_variants(&experiment_id, &mut outcomes)?;
+
+        let summary = ExperimentSummary {
+            summary_path: self
+                .run_dir
+                .join(format!("experiment-{experiment_id}.json")),
+            experiment_id,
+            variants: outcomes,
+        };
+        write_experiment_summary(&summary, &settings, &started_at)?;
+        self.events.emit(
+            "experiment_finished",
+            map_object(json!({
+                "experiment_id": summary.experiment_id,
+                "summary_path": summary.summary_path.to_string_lossy().to_string(),
+                "best_quality": summary.best_quality().map(|variant| &variant.label),
+            })),
+        )?;
+        Ok(summary)
+    }
+
+    fn next_experiment_id(&self) -> String {
+        let existing = fs::read_dir(&self.run_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| {
+                entry.file_name().to_str().is_some_and(|name| {
+                    name.starts_with("experiment-exp") && name.ends_with(".json")
+                })
+            })
+            .count();
+        format!("exp{}", existing + 1)
+    }
+
+    fn score_experiment_variants(
+        &mut self,
+        experiment_id: &str,
+        outcomes: &mut [ExperimentVariantOutcome],
+    ) -> Result<()> {
+        let mut candidates = Vec::new();
+        let mut owners = Vec::new();
+        for (idx, outcome) in outcomes.iter().enumerate() {
+            for version in self
+                .thread
+                .versions
+                .iter()
+                .filter(|version| outcome.version_ids.contains(&version.version_id))
+            {
+                for artifact in &version.artifacts {
+                    if let Some(candidate) =
+                        ScoringCandidate::from_artifact(artifact, &version.prompt)
+                    {
+                        candidates.push(candidate);
+                        owners.push(idx);
+                    }
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        match score_artifacts(&candidates, self.clip_scorer.as_deref()) {
+            Ok(scores) => {
+                for (score, idx) in scores.iter().zip(owners) {
+                    let best = &mut outcomes[idx].quality_score;
+                    *best = Some(best.map_or(score.score, |value| value.max(score.score)));
+                }
+            }
+            Err(err) => {
+                // Quality is informational; the comparison still has cost and latency.
+                self.events.emit(
+                    "experiment_scoring_failed",
+                    map_object(json!({
+                        "experiment_id": experiment_id,
+                        "error": error_chain_text(&err, 512),
+                    })),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_experiment_variant(
+        &mut self,
+        experiment_id: &str,
+        variant: &PromptVariant,
+        shared_settings: &Map<String, Value>,
+    ) -> Result<ExperimentVariantOutcome> {
+        let mut settings = shared_settings.clone();
+        for (key, value) in &variant.settings {
+            settings.insert(key.clone(), value.clone());
+        }
+        let versions_before = self.thread.versions.len();
+        let cost_before = self.run_cost_usd;
+        self.last_cost_latency = None;
+        let started = Instant::now();
+        // The experiment id is stamped on the versions afterwards rather than
+        // passed in the intent, so identical prompts keep sharing cache entries.
+        let generated = self.generate(
+            &variant.prompt,
+            settings,
+            map_object(json!({ "action": "generate" })),
+        );
+        let elapsed_s = started.elapsed().as_secs_f64();
+
+        let version_ids: Vec<String> = self.thread.versions[versions_before..]
+            .iter()
+            .map(|version| version.version_id.clone())
+            .collect();
+        if !version_ids.is_empty() {
+            for version in &mut self.thread.versions[versions_before..] {
+                version.intent.insert(
+                    "experiment".to_string(),
+                    json!({ "id": experiment_id, "variant": variant.label }),
+                );
+            }
+            self.thread.save()?;
+        }
+
+        let mut outcome = ExperimentVariantOutcome {
+            label: variant.label.clone(),
+            prompt: variant.prompt.clone(),
+            status: "ok".to_string(),
+            version_ids,
+            artifact_ids: Vec::new(),
+            cost_usd: (self.run_cost_usd - cost_before).max(0.0),
+            latency_per_image_s: self
+                .last_cost_latency
+                .as_ref()
+                .map(|metrics| metrics.latency_per_image_s),
+            elapsed_s,
+            quality_score: None,
+            error: None,
+        };
+        match generated {
+            Ok(artifacts) => {
+                outcome.artifact_ids = artifacts
+                    .iter()
+                    .filter_map(|artifact| artifact.get("artifact_id").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect();
+            }
+            Err(err) => {
+                outcome.status = "failed".to_string();
+                outcome.error = Some(error_chain_text(&err, 1024));
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+fn write_experiment_summary(
+    summary: &ExperimentSummary,
+    settings: &Map<String, Value>,
+    started_at: &str,
+) -> Result<()> {
+    let variants: Vec<Value> = summary
+        .variants
+        .iter()
+        .map(|variant| {
+            json!({
+                "label": variant.label,
+                "prompt": variant.prompt,
+                "status": variant.status,
+                "version_ids": variant.version_ids,
+                "artifact_ids": variant.artifact_ids,
+                "cost_usd": variant.cost_usd,
+                "latency_per_image_s": variant.latency_per_image_s,
+                "elapsed_s": variant.elapsed_s,
+                "quality_score": variant.quality_score,
+                "error": variant.error,
+            })
+        })
+        .collect();
+    let payload = json!({
+        "experiment_id": summary.experiment_id,
+        "started_at": started_at,
+        "finished_at": now_utc_iso(),
+        "settings": settings,
+        "variants": variants,
+        "comparison": {
+            "cheapest": summary.cheapest().map(|variant| &variant.label),
+            "fastest": summary.fastest().map(|variant| &variant.label),
+            "best_quality": summary.best_quality().map(|variant| &variant.label),
+            "cost_total_usd": summary.variants.iter().map(|variant| variant.cost_usd).sum::<f64>(),
+        },
+    });
+    write_json_atomic(&summary.summary_path, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::PromptVariant;
+    use crate::NativeEngine;
+
+    #[test]
+    fn experiment_generates_variants_and_writes_comparison() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let events_path = run_dir.join("events.jsonl");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            &events_path,
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let mut wide = PromptVariant::new("B", "a wide shot of a lighthouse");
+        wide.settings.insert("n".to_string(), json!(2));
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("32x32"));
+        let summary = engine.experiment(
+            vec![
+                PromptVariant::new("A", "a lighthouse"),
+                wide,
+                PromptVariant::new("C", "a {{missing}} lighthouse"),
+            ],
+            settings.clone(),
+        )?;
+        assert_eq!(summary.experiment_id, "exp1");
+        assert_eq!(summary.variants[0].artifact_ids.len(), 1);
+        assert_eq!(summary.variants[1].artifact_ids.len(), 2);
+        assert_eq!(summary.variants[2].status, "failed");
+        assert!(summary.summary_path.ends_with("experiment-exp1.json"));
+
+        let written: Value =
+            serde_json::from_str(&std::fs::read_to_string(&summary.summary_path)?)?;
+        assert_eq!(written["variants"][1]["version_ids"], json!(["v2"]));
+        assert!(written["comparison"]["cheapest"].is_string());
+        assert!(written["comparison"]["best_quality"].is_string());
+        assert!(summary.variants[1].quality_score.is_some());
+        assert!(summary.variants[2].quality_score.is_none());
+        let thread: Value =
+            serde_json::from_str(&std::fs::read_to_string(run_dir.join("thread.json"))?)?;
+        assert_eq!(
+            thread["versions"][1]["intent"]["experiment"],
+            json!({ "id": "exp1", "variant": "B" })
+        );
+        let events = std::fs::read_to_string(&events_path)?;
+        assert!(events.contains("\"type\":\"experiment_started\""));
+        assert_eq!(
+            events
+                .matches("\"type\":\"experiment_variant_completed\"")
+                .count(),
+            3
+        );
+
+        let again = engine.experiment(vec![PromptVariant::new("A", "a lighthouse")], settings)?;
+        assert_eq!(again.experiment_id, "exp2");
+        assert!(engine
+            .experiment(
+                vec![PromptVariant::new("A", "x"), PromptVariant::new("A", "y")],
+                Map::new()
+            )
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+
+/// Output preset for exported artifacts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportProfile {
+    pub name: &'static str,
+    pub max_edge: Option<u32>,
+    /// `Some(quality)` encodes JPEG; `None` keeps a lossless PNG.
+    pub jpeg_quality: Option<u8>,
+}
+
+pub const EXPORT_PROFILES: &[ExportProfile] = &[
+    ExportProfile {
+        name: "web",
+        max_edge: Some(2048),
+        jpeg_quality: Some(85),
+    },
+    ExportProfile {
+        name: "thumb",
+        max_edge: Some(512),
+        jpeg_quality: Some(80),
+    },
+    ExportProfile {
+        name: "print",
+        max_edge: None,
+        jpeg_quality: None,
+    },
+];
+
+impl ExportProfile {
+    pub fn named(name: &str) -> Result<Self> {
+        let lowered = name.trim().to_ascii_lowercase();
+        EXPORT_PROFILES
+            .iter()
+            .find(|profile| profile.name == lowered)
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown export profile '{name}' (expected one of: {})",
+                    EXPORT_PROFILES
+                        .iter()
+                        .map(|profile| profile.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+
+    fn extension(&self) -> &'static str {
+        if self.jpeg_quality.is_some() {
+            "jpg"
+        } else {
+            "png"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedFile {
+    pub artifact_id: String,
+    pub source_path: PathBuf,
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+pub(crate) fn export_image(
+    artifact_id: &str,
+    source: &Path,
+    out_dir: &Path,
+    profile: &ExportProfile,
+) -> Result<ExportedFile> {
+    if !source.is_file() {
+        bail!(
+            "artifact '{artifact_id}' image missing ({})",
+            source.display()
+        );
+    }
+    let mut image =
+        image::open(source).with_context(|| format!("failed to open {}", source.display()))?;
+    if let Some(max_edge) = profile.max_edge {
+        if image.width().max(image.height()) > max_edge {
+            image = image.resize(max_edge, max_edge, FilterType::Lanczos3);
+        }
+    }
+    let path = out_dir.join(format!("{artifact_id}.{}", profile.extension()));
+    match profile.jpeg_quality {
+        Some(quality) => {
+            let file = fs::File::create(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(file), quality);
+            encoder.encode_image(&image.to_rgb8())?;
+        }
+        None => image
+            .save(&path)
+            .with_context(|| format!("failed to write {}", path.display()))?,
+    }
+    Ok(ExportedFile {
+        artifact_id: artifact_id.to_string(),
+        source_path: source.to_path_buf(),
+        bytes: fs::metadata(&path)?.len(),
+        width: image.width(),
+        height: image.height(),
+        path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::{export_image, ExportProfile};
+
+    #[test]
+    fn web_profile_downscales_to_jpeg() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let source = temp.path().join("source.png");
+        RgbImage::from_pixel(600, 300, Rgb([200, 10, 10])).save(&source)?;
+        let profile = ExportProfile::named("thumb")?;
+        let exported = export_image("a1", &source, temp.path(), &profile)?;
+        assert_eq!((exported.width, exported.height), (512, 256));
+        assert!(exported.path.ends_with("a1.jpg"));
+        assert!(exported.bytes > 0);
+        assert!(ExportProfile::named("poster").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::models::ModelSpec;
+use image::{GrayImage, Luma};
+use serde_json::{json, Map, Value};
+
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{map_object, timestamp_millis, NativeEngine, ProviderConfig};
+
+/// Instruction sent with the init image when no local detector is set.
+const FACE_INSTRUCTIONS: &str = "You find human faces in images for an editing tool that must \
+keep them unchanged. Reply with JSON only: {\"faces\": [[x, y, width, height], ...]}, one box \
+per face from hairline to chin, as fractions of the image width and height (0 is left/top). \
+Reply {\"faces\": []} when there are no faces.";
+
+/// Face the dryrun detector reports: upper middle of the image.
+const DRYRUN_FACE: [f64; 4] = [0.375, 0.2, 0.25, 0.3];
+
+pub const PRESERVE_FACES_DEFAULT_PADDING: f64 = 0.25;
+const FACES_MAX: usize = 32;
+
+/// A face in pixels of the image it was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FaceBox {
+    pub fn to_value(self) -> Value {
+        json!({ "x": self.x, "y": self.y, "width": self.width, "height": self.height })
+    }
+}
+
+/// Local face detection (e.g. an ONNX or dlib model) used instead of the
+/// vision model. Configure with [`crate::NativeEngine::set_face_detector`].
+pub trait FaceDetector: Send + Sync {
+    fn detect_faces(&self, image_path: &Path) -> Result<Vec<FaceBox>>;
+}
+
+/// `settings.preserve_faces`: `true` for defaults, or
+/// `{"padding": 0.4, "model": "gpt-4o-mini"}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreserveFacesSpec {
+    /// Vision model that finds faces when no [`FaceDetector`] is set;
+    /// defaults to the engine's text model.
+    pub model: Option<String>,
+    /// Margin kept around each face, as a fraction of its size.
+    pub padding: f64,
+}
+
+impl PreserveFacesSpec {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let raw = match settings.get("preserve_faces") {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => return Ok(None),
+            Some(Value::Bool(true)) => Map::new(),
+            Some(Value::Object(raw)) => raw.clone(),
+            Some(other) => bail!("preserve_faces must be true or an object (got {other})"),
+        };
+        let model = match raw.get("model") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(model)) if !model.trim().is_empty() => {
+                Some(model.trim().to_string())
+            }
+            Some(other) => bail!("preserve_faces.model must be a model name (got {other})"),
+        };
+        let padding = match raw.get("padding") {
+            None | Some(Value::Null) => PRESERVE_FACES_DEFAULT_PADDING,
+            Some(value) => value
+                .as_f64()
+                .filter(|padding| (0.0..=1.0).contains(padding))
+                .with_context(|| {
+                    format!("preserve_faces.padding must be 0.0..=1.0 (got {value})")
+                })?,
+        };
+        Ok(Some(Self { model, padding }))
+    }
+}
+
+/// Finds faces with a vision-capable model.
+#[derive(Debug, Clone)]
+pub(crate) struct VisionFaceDetector {
+    client: TextModelClient,
+}
+
+impl VisionFaceDetector {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    /// Face boxes as `[x, y, width, height]` image fractions.
+    fn detect(&self, model: &ModelSpec, image_path: &Path) -> Result<(Vec<[f64; 4]>, TokenUsage)> {
+        if !model.supports("vision") {
+            bail!(
+                "face detection model '{}' is not vision-capable",
+                model.name
+            );
+        }
+        if model.provider == "dryrun" {
+            return Ok((vec![DRYRUN_FACE], TokenUsage::default()));
+        }
+        let reply = self.client.complete(
+            model,
+            FACE_INSTRUCTIONS,
+            "Find every face.",
+            Some(image_path),
+        )?;
+        let faces = parse_faces(&reply.text)
+            .with_context(|| format!("{} face reply had no faces list", reply.transport))?;
+        Ok((faces, reply.usage))
+    }
+}
+
+/// White-on-black edit mask that keeps an ellipse around each face (grown
+/// by `padding`) black, so only the rest of the image is edited.
+pub fn render_face_preserving_mask(
+    faces: &[FaceBox],
+    padding: f64,
+    width: u32,
+    height: u32,
+) -> GrayImage {
+    let ellipses: Vec<(f64, f64, f64, f64)> = faces
+        .iter()
+        .map(|face| {
+            let rx = face.width as f64 * (0.5 + padding);
+            let ry = face.height as f64 * (0.5 + padding);
+            let cx = face.x as f64 + face.width as f64 / 2.0;
+            let cy = face.y as f64 + face.height as f64 / 2.0;
+            (cx, cy, rx.max(0.5), ry.max(0.5))
+        })
+        .collect();
+    GrayImage::from_fn(width, height, |x, y| {
+        let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+        let on_face = ellipses.iter().any(|(cx, cy, rx, ry)| {
+            let dx = (px - cx) / rx;
+            let dy = (py - cy) / ry;
+            dx * dx + dy * dy <= 1.0
+        });
+        Luma([if on_face { 0 } else { 255 }])
+    })
+}
+
+impl NativeEngine {
+    /// Detects faces with `detector` instead of the vision model.
+    pub fn set_face_detector(&mut self, detector: Option<Box<dyn FaceDetector>>) {
+        self.face_detector = detector;
+    }
+
+    /// Faces in `image_path`, from the [`FaceDetector`] when one is set,
+    /// else from a vision model (`model`, else the engine's text model).
+    pub fn detect_faces(&mut self, image_path: &Path, model: Option<&str>) -> Result<Vec<FaceBox>> {
+        let (width, height) = image::image_dimensions(image_path)
+            .with_context(|| format!("failed to read {}", image_path.display()))?;
+        let mut faces = if let Some(detector) = &self.face_detector {
+            detector.detect_faces(image_path)?
+        } else {
+            let Some(model_name) = model
+                .map(str::to_string)
+                .or_else(|| self.text_model.clone())
+            else {
+                bail!("no face detection model configured");
+            };
+            let Some(model) = self.model_selector.registry.get(&model_name).cloned() else {
+                bail!("face detection model '{model_name}' is not registered");
+            };
+            let (fractions, usage) = self.vision_face_detector.detect(&model, image_path)?;
+            self.record_text_call(&model, usage)?;
+            fractions
+                .into_iter()
+                .map(|[x, y, w, h]| FaceBox {
+                    x: (x * width as f64).round() as u32,
+                    y: (y * height as f64).round() as u32,
+                    width: (w * width as f64).round() as u32,
+                    height: (h * height as f64).round() as u32,
+                })
+                .collect()
+        };
+        faces.retain(|face| face.width > 0 && face.height > 0 && face.x < width && face.y < height);
+        faces.truncate(FACES_MAX);
+        Ok(faces)
+    }
+
+    /// Writes the inverse face mask for `settings.init_image` under
+    /// `masks/`. `None` when there is no init image, a mask is already set or
+    /// no face was found.
+    pub(crate) fn face_preserving_mask(
+        &mut self,
+        spec: &PreserveFacesSpec,
+        settings: &Map<String, Value>,
+    ) -> Result<Option<(PathBuf, Vec<FaceBox>)>> {
+        let Some(init_image) = settings
+            .get("init_image")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+        else {
+            return Ok(None);
+        };
+        if settings.contains_key("mask") {
+            return Ok(None);
+        }
+        let detector = if self.face_detector.is_some() {
+            "local"
+        } else {
+            "vision"
+        };
+        let faces = self.detect_faces(&init_image, spec.model.as_deref())?;
+        if faces.is_empty() {
+            self.events.emit(
+                "faces_not_found",
+                map_object(json!({
+                    "init_image": init_image.to_string_lossy(),
+                    "detector": detector,
+                })),
+            )?;
+            return Ok(None);
+        }
+        let (width, height) = image::image_dimensions(&init_image)
+            .with_context(|| format!("failed to read {}", init_image.display()))?;
+        let mask = render_face_preserving_mask(&faces, spec.padding, width, height);
+        let masks_dir = self.run_dir.join("masks");
+        fs::create_dir_all(&masks_dir)
+            .with_context(|| format!("failed to create {}", masks_dir.display()))?;
+        let mask_path = masks_dir.join(format!("faces-{}.png", timestamp_millis()));
+        mask.save(&mask_path)
+            .with_context(|| format!("failed to write {}", mask_path.display()))?;
+        self.events.emit(
+            "faces_detected",
+            map_object(json!({
+                "init_image": init_image.to_string_lossy(),
+                "detector": detector,
+                "faces": faces.iter().map(|face| face.to_value()).collect::<Vec<_>>(),
+                "padding": spec.padding,
+                "mask_path": mask_path.to_string_lossy(),
+            })),
+        )?;
+        Ok(Some((mask_path, faces)))
+    }
+}
+
+/// Reads `{"faces": [[x, y, w, h], ...]}` from a model reply, tolerating
+/// prose or code fences around the JSON. Boxes are clipped to the image.
+fn parse_faces(reply: &str) -> Option<Vec<[f64; 4]>> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let payload: Value = serde_json::from_str(reply.get(start..=end)?).ok()?;
+    payload
+        .get("faces")?
+        .as_array()?
+        .iter()
+        .map(|face| {
+            let values: Vec<f64> = face
+                .as_array()?
+                .iter()
+                .map(Value::as_f64)
+                .collect::<Option<_>>()?;
+            let [x, y, w, h] = <[f64; 4]>::try_from(values).ok()?;
+            if ![x, y, w, h].iter().all(|value| value.is_finite()) {
+                return None;
+            }
+            let (x, y) = (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0));
+            Some([x, y, w.clamp(0.0, 1.0 - x), h.clamp(0.0, 1.0 - y)])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::{
+        parse_faces, render_face_preserving_mask, FaceBox, PreserveFacesSpec,
+        PRESERVE_FACES_DEFAULT_PADDING,
+    };
+
+    #[test]
+    fn parses_face_settings_replies_and_renders_inverse_masks() -> anyhow::Result<()> {
+        let mut settings = Map::new();
+        assert_eq!(PreserveFacesSpec::from_settings(&settings)?, None);
+        settings.insert("preserve_faces".to_string(), json!(true));
+        assert_eq!(
+            PreserveFacesSpec::from_settings(&settings)?,
+            Some(PreserveFacesSpec {
+                model: None,
+                padding: PRESERVE_FACES_DEFAULT_PADDING,
+            })
+        );
+        settings.insert("preserve_faces".to_string(), json!({"padding": 2.0}));
+        assert!(PreserveFacesSpec::from_settings(&settings).is_err());
+
+        assert_eq!(
+            parse_faces("```json\n{\"faces\": [[0.75, 0.1, 0.5, 0.2]]}\n```"),
+            Some(vec![[0.75, 0.1, 0.25, 0.2]])
+        );
+        assert_eq!(parse_faces("{\"faces\": []}"), Some(Vec::new()));
+        assert_eq!(parse_faces("{\"faces\": [[0.1, 0.2]]}"), None);
+
+        let face = FaceBox {
+            x: 8,
+            y: 8,
+            width: 16,
+            height: 16,
+        };
+        let mask = render_face_preserving_mask(&[face], 0.0, 64, 32);
+        assert_eq!(mask.get_pixel(16, 16)[0], 0);
+        assert_eq!(mask.get_pixel(40, 16)[0], 255);
+        assert_eq!(mask.get_pixel(8, 8)[0], 255);
+        let padded = render_face_preserving_mask(&[face], 0.5, 64, 32);
+        assert_eq!(padded.get_pixel(8, 8)[0], 0);
+        Ok(())
+    }
+}
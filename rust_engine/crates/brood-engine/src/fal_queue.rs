@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use reqwest::header::AUTHORIZATION;
+use serde_json::{Map, Value};
+
+use super::progress::{percent_from_logs, report_generation_progress};
+use super::telemetry::PollSpan;
+use super::timeouts::{scoped_http, ScopedTimeout, TimeoutKind};
+use super::{response_json_or_error, FalProvider, ReplaySend, ReplicateProvider};
+
+/// Endpoint fragments of Fal models slow enough to outlast a synchronous
+/// `fal.run` request; these go through `queue.fal.run` unless
+/// `queue: false` is set.
+pub const FAL_QUEUE_MODEL_HINTS: &[&str] =
+    &["flux-pro", "flux-2", "ultra", "video", "upscale", "kling"];
+
+/// Provider options that steer the queue flow and are not sent to Fal.
+pub(crate) const FAL_QUEUE_OPTION_KEYS: &[&str] = &["queue", "poll_interval", "poll_timeout"];
+
+/// Webhook URL registered with queued Fal requests, plus the requests still
+/// waiting on one. Process-wide: `serve` receives callbacks on connection
+/// threads while the engine polls on a job thread.
+#[derive(Default)]
+struct FalWebhooks {
+    url: Option<String>,
+    /// Request id -> delivered callback body, `None` until it arrives.
+    pending: HashMap<String, Option<Value>>,
+}
+
+fn webhooks() -> &'static Mutex<FalWebhooks> {
+    static WEBHOOKS: OnceLock<Mutex<FalWebhooks>> = OnceLock::new();
+    WEBHOOKS.get_or_init(Mutex::default)
+}
+
+/// Registers `url` as the `fal_webhook` of every queued Fal request made by
+/// this process (`None` stops registering). Callbacks must be passed to
+/// [`deliver_fal_webhook`].
+pub fn set_fal_webhook_url(url: Option<String>) {
+    webhooks()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .url = url
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty());
+}
+
+/// Hands a Fal webhook body (`{"request_id", "status", "payload", "error"}`)
+/// to the generation waiting on it. Returns `false` when no queued request
+/// in this process has that id.
+pub fn deliver_fal_webhook(body: &Value) -> Result<bool> {
+    let Some(request_id) = body.get("request_id").and_then(Value::as_str) else {
+        bail!("Fal webhook body has no request_id");
+    };
+    let mut webhooks = webhooks().lock().unwrap_or_else(PoisonError::into_inner);
+    match webhooks.pending.get_mut(request_id) {
+        Some(slot) => {
+            *slot = Some(body.clone());
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Unregisters a pending request when its poll loop ends, however it ends.
+struct PendingWebhook(String);
+
+impl PendingWebhook {
+    fn take_delivered(&self) -> Option<Value> {
+        webhooks()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pending
+            .get_mut(&self.0)
+            .and_then(Option::take)
+    }
+}
+
+impl Drop for PendingWebhook {
+    fn drop(&mut self) {
+        webhooks()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pending
+            .remove(&self.0);
+    }
+}
+
+/// `queue: true|false` wins; otherwise slow models (see
+/// [`FAL_QUEUE_MODEL_HINTS`]) are queued.
+pub(crate) fn use_fal_queue(endpoint: &str, provider_options: &Map<String, Value>) -> bool {
+    if let Some(queue) = provider_options.get("queue").and_then(Value::as_bool) {
+        return queue;
+    }
+    let endpoint = endpoint.to_ascii_lowercase();
+    FAL_QUEUE_MODEL_HINTS
+        .iter()
+        .any(|hint| endpoint.contains(hint))
+}
+
+/// `https://fal.run/<model>` -> `https://queue.fal.run/<model>`. Other
+/// hosts (proxies, mocks) are expected to serve the queue paths themselves.
+pub(crate) fn queue_endpoint(endpoint: &str) -> String {
+    match endpoint.strip_prefix("https://fal.run/") {
+        Some(model) => format!("https://queue.fal.run/{model}"),
+        None => endpoint.to_string(),
+    }
+}
+
+/// A submitted queue request: where to poll and where to fetch the result.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueuedRequest {
+    pub(crate) request_id: String,
+    pub(crate) status_url: String,
+    pub(crate) response_url: String,
+    pub(crate) webhook: bool,
+}
+
+impl FalProvider {
+    /// Submits to `queue.fal.run`, polls the request's status until it
+    /// completes (or its webhook arrives) and returns the result payload.
+    pub(crate) fn run_queued(
+        &self,
+        endpoint: &str,
+        api_key: &str,
+        payload: &Map<String, Value>,
+        provider_options: &Map<String, Value>,
+    ) -> Result<(QueuedRequest, Value)> {
+        let queued = self.submit_queued(endpoint, api_key, payload)?;
+        let pending = PendingWebhook(queued.request_id.clone());
+        if queued.webhook {
+            webhooks()
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pending
+                .insert(queued.request_id.clone(), None);
+        }
+        let result = self.poll_queued(
+            &queued,
+            api_key,
+            &pending,
+            ReplicateProvider::poll_interval_seconds(provider_options),
+            ReplicateProvider::poll_timeout_seconds(provider_options),
+        )?;
+        Ok((queued, result))
+    }
+
+    fn submit_queued(
+        &self,
+        endpoint: &str,
+        api_key: &str,
+        payload: &Map<String, Value>,
+    ) -> Result<QueuedRequest> {
+        let submit_url = queue_endpoint(endpoint);
+        let webhook_url = webhooks()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .url
+            .clone();
+        let mut request = scoped_http(&self.http)
+            .post(&submit_url)
+            .header(AUTHORIZATION, format!("Key {api_key}"));
+        if let Some(webhook_url) = &webhook_url {
+            request = request.query(&[("fal_webhook", webhook_url)]);
+        }
+        let response = request
+            .json(&Value::Object(payload.clone()))
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("Fal queue submit failed ({submit_url})"))?;
+        let submitted = response_json_or_error("Fal queue", response)?;
+        let Some(request_id) = submitted
+            .get("request_id")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+        else {
+            bail!("Fal queue response has no request_id");
+        };
+        let url = |key: &str, fallback: String| {
+            submitted
+                .get(key)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map_or(fallback, str::to_string)
+        };
+        let request_url = format!("{submit_url}/requests/{request_id}");
+        Ok(QueuedRequest {
+            status_url: url("status_url", format!("{request_url}/status")),
+            response_url: url("response_url", request_url),
+            request_id,
+            webhook: webhook_url.is_some(),
+        })
+    }
+
+    fn poll_queued(
+        &self,
+        queued: &QueuedRequest,
+        api_key: &str,
+        pending: &PendingWebhook,
+        poll_interval_s: f64,
+        poll_timeout_s: f64,
+    ) -> Result<Value> {
+        let started = Instant::now();
+        let mut poll_span = PollSpan::enter("fal");
+        loop {
+            if let Some(delivered) = pending.take_delivered() {
+                poll_span.status("webhook");
+                return webhook_result(&delivered);
+            }
+            poll_span.poll();
+            let response = scoped_http(&self.http)
+                .get(&queued.status_url)
+                .query(&[("logs", "1")])
+                .header(AUTHORIZATION, format!("Key {api_key}"))
+                .scoped_timeout(TimeoutKind::Request)
+                .send_replayable()
+                .with_context(|| format!("Fal status request failed ({})", queued.status_url))?;
+            let payload = response_json_or_error("Fal status", response)?;
+            let status = payload
+                .get("status")
+                .and_then(Value::as_str)
+                .map(|value| value.to_ascii_uppercase())
+                .unwrap_or_default();
+            poll_span.status(status.as_str());
+            match status.as_str() {
+                "COMPLETED" => {
+                    if let Some(error) = payload.get("error").filter(|error| !error.is_null()) {
+                        bail!("Fal request {} failed: {error}", queued.request_id);
+                    }
+                    return self.fetch_queued_result(queued, api_key);
+                }
+                "IN_QUEUE" | "IN_PROGRESS" => {}
+                _ => bail!("Fal request {} failed: {payload}", queued.request_id),
+            }
+            if started.elapsed().as_secs_f64() >= poll_timeout_s {
+                bail!("Fal queue polling timed out after {:.1}s", poll_timeout_s);
+            }
+            let logs = queue_logs(&payload);
+            report_generation_progress(
+                &status.to_ascii_lowercase(),
+                started.elapsed(),
+                logs.as_deref().and_then(percent_from_logs),
+                logs.as_deref(),
+            );
+            thread::sleep(Duration::from_secs_f64(poll_interval_s));
+        }
+    }
+
+    fn fetch_queued_result(&self, queued: &QueuedRequest, api_key: &str) -> Result<Value> {
+        let response = scoped_http(&self.http)
+            .get(&queued.response_url)
+            .header(AUTHORIZATION, format!("Key {api_key}"))
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("Fal result request failed ({})", queued.response_url))?;
+        response_json_or_error("Fal result", response)
+    }
+}
+
+/// The result carried by a webhook callback, or its error.
+fn webhook_result(body: &Value) -> Result<Value> {
+    let status = body.get("status").and_then(Value::as_str).unwrap_or("OK");
+    if !status.eq_ignore_ascii_case("OK") {
+        let error = body
+            .get("error")
+            .filter(|error| !error.is_null())
+            .map(Value::to_string)
+            .unwrap_or_else(|| status.to_string());
+        bail!("Fal webhook reported failure: {error}");
+    }
+    match body.get("payload") {
+        Some(payload) if !payload.is_null() => Ok(payload.clone()),
+        _ => bail!("Fal webhook has no payload"),
+    }
+}
+
+/// Status `logs` (`[{"message": ...}]`) joined into one text, or `None`.
+fn queue_logs(payload: &Value) -> Option<String> {
+    let lines: Vec<&str> = payload
+        .get("logs")?
+        .as_array()?
+        .iter()
+        .filter_map(|row| row.get("message").and_then(Value::as_str))
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{deliver_fal_webhook, queue_endpoint, queue_logs, use_fal_queue, webhook_result};
+    use crate::map_object;
+
+    #[test]
+    fn queue_selection_endpoints_and_webhook_bodies() -> anyhow::Result<()> {
+        let none = map_object(json!({}));
+        assert!(use_fal_queue(
+            "https://fal.run/fal-ai/flux-pro/v1.1-ultra",
+            &none
+        ));
+        assert!(!use_fal_queue("https://fal.run/fal-ai/fast-sdxl", &none));
+        assert!(use_fal_queue(
+            "https://fal.run/fal-ai/fast-sdxl",
+            &map_object(json!({"queue": true}))
+        ));
+        assert!(!use_fal_queue(
+            "https://fal.run/fal-ai/flux-pro",
+            &map_object(json!({"queue": false}))
+        ));
+
+        assert_eq!(
+            queue_endpoint("https://fal.run/fal-ai/flux-pro"),
+            "https://queue.fal.run/fal-ai/flux-pro"
+        );
+        assert_eq!(
+            queue_endpoint("http://127.0.0.1:9/fal-ai/flux-pro"),
+            "http://127.0.0.1:9/fal-ai/flux-pro"
+        );
+
+        assert_eq!(
+            queue_logs(&json!({"logs": [{"message": "step 1"}, {"message": " 45%|████▌ | 9/20"}]}))
+                .as_deref(),
+            Some("step 1\n 45%|████▌ | 9/20")
+        );
+        assert_eq!(queue_logs(&json!({"logs": null})), None);
+
+        let ok = json!({"request_id": "r1", "status": "OK", "payload": {"images": []}});
+        assert_eq!(webhook_result(&ok)?, json!({"images": []}));
+        let failed = json!({"request_id": "r1", "status": "ERROR", "error": "nsfw"});
+        assert!(webhook_result(&failed).is_err());
+        assert!(!deliver_fal_webhook(&json!({"request_id": "unknown"}))?);
+        assert!(deliver_fal_webhook(&json!({"status": "OK"})).is_err());
+        Ok(())
+    }
+}
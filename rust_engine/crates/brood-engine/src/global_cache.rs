@@ -0,0 +1,346 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use brood_contracts::runs::atomic::{write_atomic, write_json_atomic};
+use brood_contracts::runs::cache::CacheStore;
+use brood_contracts::runs::lock::FileLock;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::output_format::write_thumbnail;
+use super::{map_object, now_utc_iso};
+
+pub const GLOBAL_CACHE_INDEX_FILENAME: &str = "index.json";
+/// Locked around each index write, which re-reads `index.json` and merges
+/// into it, so concurrent runs sharing the cache do not drop each other's
+/// entries.
+const GLOBAL_CACHE_INDEX_LOCK_FILENAME: &str = "index.lock";
+
+/// Cross-run generation cache. Entries are keyed by the same stable hash as
+/// the per-run `cache.json`; artifact files live once under
+/// `objects/<sha256[..2]>/<sha256>.<ext>` and are hard-linked (or copied)
+/// into the run dir on a hit.
+#[derive(Debug, Clone)]
+pub struct GlobalCache {
+    root: PathBuf,
+    index: CacheStore,
+}
+
+impl GlobalCache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            index: CacheStore::new(root.join(GLOBAL_CACHE_INDEX_FILENAME)),
+            root,
+        }
+    }
+
+    /// `~/.brood/cache`.
+    pub fn default_root() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .map(|home| home.join(".brood").join("cache"))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn contains(&mut self, key: &str) -> bool {
+        self.index.get(key).is_some()
+    }
+
+    /// Copies each artifact's image and receipt into the object store and
+    /// records them under `key`.
+    pub(crate) fn store(&mut self, key: &str, artifacts: &[Map<String, Value>]) -> Result<()> {
+        let mut rows = Vec::new();
+        for artifact in artifacts {
+            let Some(image_path) = artifact.get("image_path").and_then(Value::as_str) else {
+                continue;
+            };
+            let image_sha256 = self.put_object(Path::new(image_path))?;
+            let receipt_sha256 = match artifact.get("receipt_path").and_then(Value::as_str) {
+                Some(path) if Path::new(path).is_file() => Some(self.put_object(Path::new(path))?),
+                _ => None,
+            };
+            rows.push(json!({
+                "image_sha256": image_sha256,
+                "image_ext": file_extension(Path::new(image_path)),
+                "receipt_sha256": receipt_sha256,
+                "metrics": artifact.get("metrics").cloned().unwrap_or(Value::Object(Map::new())),
+                "dhash": artifact.get("dhash"),
+                "mime": artifact.get("mime"),
+            }));
+        }
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let _lock = FileLock::acquire(&self.root.join(GLOBAL_CACHE_INDEX_LOCK_FILENAME))?;
+        self.index.set(
+            key,
+            map_object(json!({ "artifacts": rows, "stored_at": now_utc_iso() })),
+        )
+    }
+
+    /// Materializes the artifacts stored under `key` into `run_dir` for
+    /// `version_id`, with thumbnails as for a fresh generation. Returns
+    /// `None` when the entry or any object is missing.
+    pub(crate) fn restore(
+        &mut self,
+        key: &str,
+        run_dir: &Path,
+        version_id: &str,
+    ) -> Result<Option<Vec<Map<String, Value>>>> {
+        let Some(entry) = self.index.get(key) else {
+            return Ok(None);
+        };
+        let rows: Vec<Map<String, Value>> = entry
+            .get("artifacts")
+            .and_then(Value::as_array)
+            .map(|rows| rows.iter().filter_map(Value::as_object).cloned().collect())
+            .unwrap_or_default();
+        let mut objects = Vec::new();
+        for row in &rows {
+            let Some(sha256) = row.get("image_sha256").and_then(Value::as_str) else {
+                return Ok(None);
+            };
+            let ext = row
+                .get("image_ext")
+                .and_then(Value::as_str)
+                .unwrap_or("png");
+            let object = self.object_path(sha256, ext);
+            if !object.is_file() {
+                return Ok(None);
+            }
+            objects.push((row, sha256, ext, object));
+        }
+        if objects.is_empty() {
+            return Ok(None);
+        }
+
+        let stamp = chrono::Utc::now().timestamp_millis();
+        let mut artifacts = Vec::new();
+        for (idx, (row, sha256, ext, object)) in objects.into_iter().enumerate() {
+            let artifact_id = format!("{version_id}-{:02}-{}", idx + 1, &sha256[..8]);
+            let image_path = run_dir.join(format!("artifact-{stamp}-{idx:02}.{ext}"));
+            link_or_copy(&object, &image_path)?;
+            // Vector and AVIF images cannot be decoded here and never had one.
+            let thumbnail_path = write_thumbnail(&image_path).ok();
+            let receipt_path = run_dir.join(format!("receipt-{artifact_id}.json"));
+            let receipt_object = row
+                .get("receipt_sha256")
+                .and_then(Value::as_str)
+                .map(|sha256| self.object_path(sha256, "json"))
+                .filter(|path| path.is_file());
+            let mut receipt = match receipt_object {
+                Some(path) => serde_json::from_str::<Value>(&fs::read_to_string(&path)?)
+                    .ok()
+                    .and_then(|value| value.as_object().cloned())
+                    .unwrap_or_default(),
+                None => Map::new(),
+            };
+            let dhash = row.get("dhash").filter(|value| value.is_string());
+            let mime = row.get("mime").filter(|value| value.is_string());
+            let mut receipt_artifacts = map_object(json!({
+                "image_path": image_path.to_string_lossy(),
+                "receipt_path": receipt_path.to_string_lossy(),
+            }));
+            if let Some(path) = &thumbnail_path {
+                receipt_artifacts
+                    .insert("thumbnail_path".to_string(), json!(path.to_string_lossy()));
+            }
+            if let Some(dhash) = dhash {
+                receipt_artifacts.insert("image_dhash".to_string(), dhash.clone());
+            }
+            if let Some(mime) = mime {
+                receipt_artifacts.insert("image_mime".to_string(), mime.clone());
+            }
+            receipt.insert("artifacts".to_string(), Value::Object(receipt_artifacts));
+            receipt.insert(
+                "cache".to_string(),
+                json!({ "source": "global", "key": key, "image_sha256": sha256 }),
+            );
+            write_json_atomic(&receipt_path, &Value::Object(receipt))?;
+            let mut artifact = map_object(json!({
+                "artifact_id": artifact_id,
+                "image_path": image_path.to_string_lossy(),
+                "receipt_path": receipt_path.to_string_lossy(),
+                "metrics": row.get("metrics").cloned().unwrap_or(Value::Object(Map::new())),
+            }));
+            if let Some(path) = &thumbnail_path {
+                artifact.insert("thumbnail_path".to_string(), json!(path.to_string_lossy()));
+            }
+            if let Some(dhash) = dhash {
+                artifact.insert("dhash".to_string(), dhash.clone());
+            }
+            if let Some(mime) = mime {
+                artifact.insert("mime".to_string(), mime.clone());
+            }
+            artifacts.push(artifact);
+        }
+        Ok(Some(artifacts))
+    }
+
+    fn object_path(&self, sha256: &str, ext: &str) -> PathBuf {
+        self.root
+            .join("objects")
+            .join(&sha256[..2.min(sha256.len())])
+            .join(format!("{sha256}.{ext}"))
+    }
+
+    fn put_object(&self, source: &Path) -> Result<String> {
+        let bytes =
+            fs::read(source).with_context(|| format!("failed to read {}", source.display()))?;
+        let sha256 = hex::encode(Sha256::digest(&bytes));
+        let object = self.object_path(&sha256, &file_extension(source));
+        if object.is_file() {
+            return Ok(sha256);
+        }
+        // Staged and renamed so concurrent runs never see a partial object.
+        write_atomic(&object, &bytes)?;
+        Ok(sha256)
+    }
+}
+
+fn file_extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_else(|| "bin".to_string())
+}
+
+fn link_or_copy(source: &Path, dest: &Path) -> Result<()> {
+    if fs::hard_link(source, dest).is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, dest).with_context(|| {
+        format!(
+            "failed to copy cached artifact {} to {}",
+            source.display(),
+            dest.display()
+        )
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Map, Value};
+
+    use super::GlobalCache;
+    use crate::{map_object, parse_pricing_table_rows, CostBudget, NativeEngine};
+
+    #[test]
+    fn global_cache_hit_links_artifacts_into_new_run() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let cache_root = temp.path().join("cache");
+        let mut runs = Vec::new();
+        for name in ["run-a", "run-b"] {
+            let run_dir = temp.path().join(name);
+            let mut engine = NativeEngine::new(
+                &run_dir,
+                run_dir.join("events.jsonl"),
+                Some("dryrun-text-1".to_string()),
+                Some("dryrun-image-1".to_string()),
+            )?;
+            engine.set_global_cache(Some(GlobalCache::new(&cache_root)));
+            let artifacts = engine.generate("lighthouse", Map::new(), Map::new())?;
+            engine.finish()?;
+            runs.push((run_dir, artifacts));
+        }
+
+        let (run_b, artifacts) = &runs[1];
+        let image_path = artifacts[0]["image_path"].as_str().unwrap_or("");
+        assert!(image_path.starts_with(run_b.to_string_lossy().as_ref()));
+        assert_eq!(
+            fs::read(image_path)?,
+            fs::read(runs[0].1[0]["image_path"].as_str().unwrap_or(""))?
+        );
+        let receipt: Value = serde_json::from_str(&fs::read_to_string(
+            artifacts[0]["receipt_path"].as_str().unwrap_or(""),
+        )?)?;
+        assert_eq!(receipt["cache"]["source"], "global");
+        assert_eq!(receipt["artifacts"]["image_path"], image_path);
+        let thumbnail = artifacts[0]["thumbnail_path"].as_str().unwrap_or("");
+        assert!(thumbnail.starts_with(run_b.to_string_lossy().as_ref()));
+        assert!(std::path::Path::new(thumbnail).is_file());
+        assert_eq!(receipt["artifacts"]["thumbnail_path"], thumbnail);
+
+        let events = fs::read_to_string(run_b.join("events.jsonl"))?;
+        assert!(events.contains("\"cache_source\":\"global\""));
+        assert!(run_b.join("cache.json").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_stores_keep_every_index_entry() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let cache_root = temp.path().join("cache");
+        let image = temp.path().join("image.png");
+        fs::write(&image, b"not really a png")?;
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let cache_root = cache_root.clone();
+                let artifact = map_object(json!({ "image_path": image.to_string_lossy() }));
+                std::thread::spawn(move || -> anyhow::Result<()> {
+                    let mut cache = GlobalCache::new(cache_root);
+                    for idx in 0..10 {
+                        cache.store(
+                            &format!("key-{worker}-{idx}"),
+                            std::slice::from_ref(&artifact),
+                        )?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().expect("store worker")?;
+        }
+        let mut cache = GlobalCache::new(&cache_root);
+        for worker in 0..4 {
+            for idx in 0..10 {
+                assert!(cache.contains(&format!("key-{worker}-{idx}")));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn failed_restore_checks_the_budget_before_adding_a_version() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let cache_root = temp.path().join("cache");
+        let mut engines = Vec::new();
+        for name in ["run-a", "run-b"] {
+            let run_dir = temp.path().join(name);
+            let mut engine = NativeEngine::new(
+                &run_dir,
+                run_dir.join("events.jsonl"),
+                Some("dryrun-text-1".to_string()),
+                Some("dryrun-image-1".to_string()),
+            )?;
+            engine.set_global_cache(Some(GlobalCache::new(&cache_root)));
+            engine.pricing_tables =
+                parse_pricing_table_rows(r#"{"dryrun-image": {"cost_per_image_usd": 0.5}}"#);
+            engines.push(engine);
+        }
+        engines[0].generate("lighthouse", Map::new(), Map::new())?;
+        // The index still lists the entry, but its objects are gone.
+        fs::remove_dir_all(cache_root.join("objects"))?;
+
+        let engine = &mut engines[1];
+        engine.set_cost_budget(CostBudget {
+            run_usd: Some(0.1),
+            session_usd: None,
+        })?;
+        let err = engine
+            .generate("lighthouse", Map::new(), Map::new())
+            .expect_err("the provider call should exceed the run cap");
+        assert!(err.to_string().contains("run budget exceeded"), "{err}");
+        assert!(engine.thread.versions.is_empty());
+        Ok(())
+    }
+}
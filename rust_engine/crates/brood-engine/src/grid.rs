@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::receipts::{
+    build_receipt, write_receipt, ImageInputs, ImageRequest, ResolvedRequest,
+};
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use serde_json::{json, Map, Value};
+
+use super::output_format::write_thumbnail;
+use super::watermark::render_text;
+use super::{map_object, short_id, timestamp_millis, NativeEngine};
+
+/// Backend name recorded in grid receipts.
+pub const GRID_BACKEND: &str = "local-grid";
+pub const GRID_COLS_MAX: u32 = 12;
+pub const GRID_CELL_SIZE_MIN: u32 = 64;
+pub const GRID_CELL_SIZE_MAX: u32 = 1024;
+
+/// Padding around and between cells, in pixels.
+const GRID_GAP: u32 = 8;
+/// Height of the caption strip under each cell.
+const LABEL_HEIGHT: u32 = 20;
+
+impl NativeEngine {
+    /// Tiles the given artifacts into one labeled contact-sheet PNG, `cols`
+    /// per row, each fitted into a `cell_size` square. The sheet becomes an
+    /// artifact of a new version with a receipt listing the sources.
+    pub fn compose_grid(
+        &mut self,
+        artifact_ids: &[String],
+        cols: u32,
+        cell_size: u32,
+    ) -> Result<Map<String, Value>> {
+        if artifact_ids.is_empty() {
+            bail!("grid needs at least one artifact");
+        }
+        if !(1..=GRID_COLS_MAX).contains(&cols) {
+            bail!("grid columns must be between 1 and {GRID_COLS_MAX} (got {cols})");
+        }
+        if !(GRID_CELL_SIZE_MIN..=GRID_CELL_SIZE_MAX).contains(&cell_size) {
+            bail!(
+                "grid cell size must be between {GRID_CELL_SIZE_MIN} and {GRID_CELL_SIZE_MAX} (got {cell_size})"
+            );
+        }
+        let started = Instant::now();
+        let mut sources = Vec::new();
+        for artifact_id in artifact_ids {
+            let Some((version, artifact)) = self.thread.find_artifact(artifact_id) else {
+                bail!("artifact '{artifact_id}' not found in thread");
+            };
+            let Some(path) = artifact.get("image_path").and_then(Value::as_str) else {
+                bail!("artifact '{artifact_id}' has no image_path");
+            };
+            sources.push((
+                artifact_id.clone(),
+                version.version_id.clone(),
+                PathBuf::from(path),
+            ));
+        }
+
+        let cols = cols.min(sources.len() as u32);
+        let rows = (sources.len() as u32).div_ceil(cols);
+        let width = cols * cell_size + (cols + 1) * GRID_GAP;
+        let height = rows * (cell_size + LABEL_HEIGHT) + (rows + 1) * GRID_GAP;
+        let mut sheet = RgbaImage::from_pixel(width, height, Rgba([24, 24, 24, 255]));
+        for (idx, (artifact_id, _, path)) in sources.iter().enumerate() {
+            let col = idx as u32 % cols;
+            let row = idx as u32 / cols;
+            let x = GRID_GAP + col * (cell_size + GRID_GAP);
+            let y = GRID_GAP + row * (cell_size + LABEL_HEIGHT + GRID_GAP);
+            let image =
+                image::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+            let thumb = image
+                .resize(cell_size, cell_size, FilterType::Triangle)
+                .to_rgba8();
+            let offset_x = (cell_size - thumb.width()) / 2;
+            let offset_y = (cell_size - thumb.height()) / 2;
+            image::imageops::overlay(
+                &mut sheet,
+                &thumb,
+                i64::from(x + offset_x),
+                i64::from(y + offset_y),
+            );
+            let max_chars = (cell_size / 12).max(1) as usize;
+            let label: String = artifact_id.chars().take(max_chars).collect();
+            image::imageops::overlay(
+                &mut sheet,
+                &render_text(&label, 2),
+                i64::from(x),
+                i64::from(y + cell_size + 3),
+            );
+        }
+
+        let source_version_id = sources[0].1.clone();
+        let prompt = format!("Grid of {} artifacts", sources.len());
+        let mut intent = map_object(json!({
+            "action": "grid",
+            "source_artifact_ids": artifact_ids,
+        }));
+        intent.insert(
+            "parent_version_id".to_string(),
+            Value::String(source_version_id.clone()),
+        );
+        let settings = map_object(json!({ "grid_cols": cols, "grid_cell_size": cell_size }));
+        let version = self.thread.add_version(
+            intent,
+            settings.clone(),
+            prompt.clone(),
+            Some(source_version_id.clone()),
+        );
+        self.thread.save()?;
+        self.events.emit(
+            "version_created",
+            map_object(json!({
+                "version_id": version.version_id,
+                "parent_version_id": source_version_id,
+                "settings": settings,
+                "prompt": prompt,
+            })),
+        )?;
+
+        let version_dir = self.version_dir(&version.version_id)?;
+        let image_path = version_dir.join(format!("artifact-{}-00-grid.png", timestamp_millis()));
+        sheet
+            .save(&image_path)
+            .with_context(|| format!("failed to write {}", image_path.display()))?;
+        let artifact_id = format!(
+            "{}-01-{}",
+            version.version_id,
+            short_id(&artifact_ids.join(","), 0)
+        );
+        let receipt_path = version_dir.join(format!("receipt-{artifact_id}.json"));
+        let size = format!("{width}x{height}");
+        let source_paths: Vec<String> = sources
+            .iter()
+            .map(|(_, _, path)| path.to_string_lossy().to_string())
+            .collect();
+        let inputs = ImageInputs {
+            init_image: None,
+            mask: None,
+            reference_images: source_paths,
+            control: None,
+        };
+        let provider_params = map_object(json!({
+            "cols": cols,
+            "rows": rows,
+            "cell_size": cell_size,
+        }));
+        let request = ImageRequest {
+            prompt: prompt.clone(),
+            mode: "grid".to_string(),
+            size: size.clone(),
+            n: 1,
+            seed: None,
+            output_format: Some("png".to_string()),
+            background: None,
+            inputs: inputs.clone(),
+            provider: Some(GRID_BACKEND.to_string()),
+            provider_options: provider_params.clone(),
+            user: None,
+            out_dir: Some(version_dir.to_string_lossy().to_string()),
+            stream: false,
+            partial_images: None,
+            model: Some(GRID_BACKEND.to_string()),
+            metadata: map_object(json!({ "source_artifact_ids": artifact_ids })),
+        };
+        let resolved = ResolvedRequest {
+            provider: GRID_BACKEND.to_string(),
+            model: Some(GRID_BACKEND.to_string()),
+            size,
+            width: Some(u64::from(width)),
+            height: Some(u64::from(height)),
+            output_format: "png".to_string(),
+            background: None,
+            seed: None,
+            n: 1,
+            user: None,
+            prompt: prompt.clone(),
+            inputs,
+            stream: false,
+            partial_images: None,
+            provider_params,
+            warnings: Vec::new(),
+            safety: Map::new(),
+        };
+        let result_metadata = map_object(json!({
+            "latency_per_image_s": started.elapsed().as_secs_f64(),
+        }));
+        let provider_request = map_object(json!({
+            "endpoint": GRID_BACKEND,
+            "payload": {
+                "source_artifact_ids": artifact_ids,
+                "cols": cols,
+                "cell_size": cell_size,
+            }
+        }));
+        let provider_response = map_object(json!({
+            "status": "ok",
+            "width": width,
+            "height": height,
+        }));
+        let receipt = build_receipt(
+            &request,
+            &resolved,
+            &provider_request,
+            &provider_response,
+            &[],
+            &image_path,
+            None,
+            &receipt_path,
+            &result_metadata,
+        );
+        write_receipt(&receipt_path, &receipt)?;
+        self.upload_artifact(&artifact_id, &image_path, &receipt_path)?;
+
+        let mut artifact = map_object(json!({
+            "artifact_id": artifact_id,
+            "image_path": image_path.to_string_lossy().to_string(),
+            "receipt_path": receipt_path.to_string_lossy().to_string(),
+            "source_artifact_ids": artifact_ids,
+            "metrics": result_metadata,
+        }));
+        if let Ok(path) = write_thumbnail(&image_path) {
+            artifact.insert("thumbnail_path".to_string(), json!(path.to_string_lossy()));
+        }
+        self.thread
+            .add_artifact(&version.version_id, artifact.clone());
+        self.thread.save()?;
+        self.events.emit(
+            "artifact_created",
+            map_object(json!({
+                "version_id": version.version_id,
+                "artifact_id": artifact_id,
+                "image_path": artifact.get("image_path"),
+                "receipt_path": artifact.get("receipt_path"),
+                "thumbnail_path": artifact.get("thumbnail_path"),
+                "source_artifact_ids": artifact_ids,
+                "metrics": artifact.get("metrics").cloned().unwrap_or(Value::Object(Map::new())),
+            })),
+        )?;
+        Ok(artifact)
+    }
+}
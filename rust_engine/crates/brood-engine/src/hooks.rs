@@ -0,0 +1,701 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::receipts::write_receipt;
+use serde_json::{json, Map, Value};
+
+use super::{map_object, non_empty_env, truncate_text, NativeEngine};
+
+/// JSON file listing the hooks every engine opened by the CLI runs.
+pub const HOOKS_ENV: &str = "BROOD_HOOKS";
+/// Lifecycle events hooks can be attached to.
+pub const HOOK_EVENTS: &[&str] = &["artifact_created", "run_finished"];
+
+const HOOK_TIMEOUT_DEFAULT: Duration = Duration::from_secs(60);
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Hook output kept as the rejection reason or failure detail.
+const HOOK_OUTPUT_MAX_CHARS: usize = 512;
+/// How long to wait for a hook's pipes to close once it has exited; a
+/// process it left behind may hold them open.
+const HOOK_PIPE_GRACE: Duration = Duration::from_millis(200);
+
+/// What a hook runs, with the event JSON on stdin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookRunner {
+    /// A program and its arguments.
+    Command(Vec<String>),
+    /// A WASI module, run in the embedded wasmtime with no files, network,
+    /// arguments or environment: it only sees the event on stdin.
+    Wasm(PathBuf),
+}
+
+/// One configured hook: `{"on": "artifact_created", "command": [...]}` or
+/// `{"on": ..., "wasm": "plugin.wasm"}`, plus optional `name`, `reject` and
+/// `timeout_s`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub name: String,
+    pub event: String,
+    pub runner: HookRunner,
+    /// A non-zero exit marks the artifact as rejected instead of being
+    /// reported as a hook failure. Only meaningful for `artifact_created`.
+    pub reject: bool,
+    pub timeout: Duration,
+}
+
+/// Hooks loaded from a `{"hooks": [...]}` file. Relative `command` programs
+/// and `wasm` paths are resolved against the file's directory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn new(hooks: Vec<Hook>) -> Self {
+        Self { hooks }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("failed to read hooks file {}", path.display()))?;
+        let parsed: Value = serde_json::from_str(&raw)
+            .with_context(|| format!("invalid JSON in hooks file {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let Some(entries) = parsed.get("hooks").and_then(Value::as_array) else {
+            bail!("hooks file {} has no `hooks` list", path.display());
+        };
+        let hooks = entries
+            .iter()
+            .enumerate()
+            .map(|(idx, entry)| {
+                Hook::parse(entry, base).with_context(|| format!("hook #{}", idx + 1))
+            })
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("invalid hooks file {}", path.display()))?;
+        Ok(Self { hooks })
+    }
+
+    /// The hooks [`HOOKS_ENV`] names, if any.
+    pub fn from_env() -> Result<Option<Self>> {
+        non_empty_env(HOOKS_ENV)
+            .map(|path| Self::load(Path::new(&path)))
+            .transpose()
+    }
+
+    pub fn hooks(&self) -> &[Hook] {
+        &self.hooks
+    }
+
+    fn for_event<'a>(&'a self, event_type: &'a str) -> impl Iterator<Item = &'a Hook> + 'a {
+        self.hooks
+            .iter()
+            .filter(move |hook| hook.event == event_type)
+    }
+}
+
+impl Hook {
+    fn parse(entry: &Value, base: &Path) -> Result<Self> {
+        let text = |key: &str| entry.get(key).and_then(Value::as_str).map(str::trim);
+        let event = text("on").unwrap_or_default().to_string();
+        if !HOOK_EVENTS.contains(&event.as_str()) {
+            bail!(
+                "`on` must be one of {}, got '{event}'",
+                HOOK_EVENTS.join(", ")
+            );
+        }
+        let resolve = |path: &str| {
+            let path = Path::new(path);
+            if path.is_relative() && path.components().count() > 1 {
+                base.join(path)
+            } else {
+                path.to_path_buf()
+            }
+        };
+        let runner = match (entry.get("command"), text("wasm")) {
+            (Some(_), Some(_)) => bail!("set either `command` or `wasm`, not both"),
+            (Some(Value::String(line)), None) => {
+                HookRunner::Command(vec!["sh".to_string(), "-c".to_string(), line.clone()])
+            }
+            (Some(Value::Array(parts)), None) => {
+                let mut argv = parts
+                    .iter()
+                    .map(|part| part.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|argv| !argv.is_empty())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("`command` must be a non-empty list of strings")
+                    })?;
+                argv[0] = resolve(&argv[0]).to_string_lossy().to_string();
+                HookRunner::Command(argv)
+            }
+            (Some(_), None) => bail!("`command` must be a string or a list of strings"),
+            #[cfg(feature = "wasm")]
+            (None, Some(module)) if !module.is_empty() => HookRunner::Wasm(base.join(module)),
+            #[cfg(not(feature = "wasm"))]
+            (None, Some(module)) if !module.is_empty() => {
+                bail!("`wasm` hooks need brood built with the `wasm` feature")
+            }
+            (None, _) => bail!("set `command` or `wasm`"),
+        };
+        let timeout = match entry.get("timeout_s") {
+            None => HOOK_TIMEOUT_DEFAULT,
+            Some(value) => value
+                .as_f64()
+                .filter(|secs| *secs > 0.0)
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| anyhow::anyhow!("`timeout_s` must be a positive number"))?,
+        };
+        let name = text("name")
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| match &runner {
+                HookRunner::Command(argv) => argv.last().cloned().unwrap_or_default(),
+                HookRunner::Wasm(module) => module.to_string_lossy().to_string(),
+            });
+        Ok(Self {
+            name,
+            event,
+            runner,
+            reject: entry
+                .get("reject")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            timeout,
+        })
+    }
+
+    /// Runs the hook with `event` on stdin. `Ok(None)` when it exited 0,
+    /// `Ok(Some(detail))` when it exited non-zero or timed out, and an error
+    /// when it could not be run at all.
+    fn run(&self, event: &Value, run_dir: &Path) -> Result<Option<String>> {
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        match &self.runner {
+            HookRunner::Command(argv) => self.run_command(argv, &line, run_dir),
+            #[cfg(feature = "wasm")]
+            HookRunner::Wasm(module) => wasi::run(module, &line, self.timeout)
+                .with_context(|| format!("failed to run hook '{}'", self.name)),
+            #[cfg(not(feature = "wasm"))]
+            HookRunner::Wasm(_) => bail!("`wasm` hooks need brood built with the `wasm` feature"),
+        }
+    }
+
+    fn run_command(&self, argv: &[String], line: &str, run_dir: &Path) -> Result<Option<String>> {
+        let mut command = Command::new(&argv[0]);
+        command
+            .args(&argv[1..])
+            .env("BROOD_HOOK_EVENT", &self.event)
+            .env("BROOD_RUN_DIR", run_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Its own process group, so a timeout also kills whatever it spawned.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("failed to start hook '{}'", self.name))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that exits without reading stdin is not a failure.
+            let _ = stdin.write_all(line.as_bytes());
+        }
+        // Drained on their own threads so a chatty hook cannot fill a pipe
+        // and block before it exits.
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+        let status = wait_with_timeout(&mut child, self.timeout)?;
+        let stdout = stdout.recv_timeout(HOOK_PIPE_GRACE).unwrap_or_default();
+        let stderr = stderr.recv_timeout(HOOK_PIPE_GRACE).unwrap_or_default();
+        let Some(status) = status else {
+            return Ok(Some(format!("timed out after {:?}", self.timeout)));
+        };
+        if status.success() {
+            return Ok(None);
+        }
+        Ok(Some(failure_detail(
+            &format!("exited with {status}"),
+            &stdout,
+            &stderr,
+        )))
+    }
+}
+
+/// `summary`, plus the hook's stdout or else its stderr.
+fn failure_detail(summary: &str, stdout: &str, stderr: &str) -> String {
+    match [stdout.trim(), stderr.trim()]
+        .into_iter()
+        .find(|text| !text.is_empty())
+    {
+        Some(output) => format!(
+            "{summary}: {}",
+            truncate_text(output, HOOK_OUTPUT_MAX_CHARS)
+        ),
+        None => summary.to_string(),
+    }
+}
+
+/// Reads `pipe` to the end on a detached thread. The text arrives once the
+/// pipe closes; a grandchild holding it open must not block the engine.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut text);
+        }
+        let _ = sender.send(text);
+    });
+    receiver
+}
+
+/// `None` when the hook was killed for running past `timeout`.
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<Option<std::process::ExitStatus>> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            kill_process_group(child);
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    }
+}
+
+#[cfg(unix)]
+fn kill_process_group(child: &Child) {
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: signals the group the hook leads; no memory is involved.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_child: &Child) {}
+
+/// WASM hooks, run in-process with WASI preview 1 and nothing but stdin,
+/// stdout and stderr.
+#[cfg(feature = "wasm")]
+mod wasi {
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::{anyhow, Context, Result};
+    use wasmtime::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+    use wasmtime_wasi::pipe::{MemoryInputPipe, MemoryOutputPipe};
+    use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+    use wasmtime_wasi::{I32Exit, WasiCtxBuilder};
+
+    use super::failure_detail;
+
+    const HOOK_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+    /// Output past this makes the module's writes fail.
+    const HOOK_OUTPUT_LIMIT: usize = 64 * 1024;
+
+    struct HookState {
+        wasi: WasiP1Ctx,
+        limits: StoreLimits,
+    }
+
+    pub(super) fn run(module: &Path, stdin: &str, timeout: Duration) -> Result<Option<String>> {
+        // An engine of its own, so its timeout interrupts no other module.
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine =
+            Engine::new(&config).map_err(|err| anyhow!("failed to start wasmtime: {err}"))?;
+        let module = Module::from_file(&engine, module)
+            .with_context(|| format!("failed to load {}", module.display()))?;
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |state: &mut HookState| &mut state.wasi)?;
+        let stdout = MemoryOutputPipe::new(HOOK_OUTPUT_LIMIT);
+        let stderr = MemoryOutputPipe::new(HOOK_OUTPUT_LIMIT);
+        let wasi = WasiCtxBuilder::new()
+            .stdin(MemoryInputPipe::new(stdin.to_string()))
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .build_p1();
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(HOOK_MEMORY_LIMIT)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&engine, HookState { wasi, limits });
+        store.limiter(|state| &mut state.limits);
+        store.set_epoch_deadline(1);
+        let instance = linker.instantiate(&mut store, &module)?;
+        let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog_engine = engine.clone();
+        let watchdog = thread::spawn(move || {
+            if finished.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout) {
+                watchdog_engine.increment_epoch();
+            }
+        });
+        let result = start.call(&mut store, ());
+        drop(done);
+        let _ = watchdog.join();
+
+        let text = |pipe: &MemoryOutputPipe| String::from_utf8_lossy(&pipe.contents()).to_string();
+        let summary = match result {
+            Ok(()) => return Ok(None),
+            Err(err) => match err.downcast_ref::<I32Exit>() {
+                Some(I32Exit(0)) => return Ok(None),
+                Some(I32Exit(code)) => format!("exited with code {code}"),
+                None if err.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => {
+                    return Ok(Some(format!("timed out after {timeout:?}")));
+                }
+                None => format!("trapped: {}", err.root_cause()),
+            },
+        };
+        Ok(Some(failure_detail(
+            &summary,
+            &text(&stdout),
+            &text(&stderr),
+        )))
+    }
+}
+
+impl NativeEngine {
+    /// Runs `hooks` after each lifecycle event they are attached to.
+    pub fn set_hooks(&mut self, hooks: Option<Hooks>) {
+        self.hooks = hooks;
+    }
+
+    /// Runs the hooks for a just-emitted `event` and returns whether one
+    /// rejected the artifact. A rejecting hook that exits non-zero after
+    /// `artifact_created` marks the artifact rejected in the thread and its
+    /// receipt and emits `artifact_rejected`; other failures, including a
+    /// rejecting hook that could not be started, emit `hook_failed`. Hooks
+    /// run after the work is paid for and saved, so nothing here fails it.
+    pub(crate) fn run_hooks(&mut self, event: &Value) -> bool {
+        let Some(hooks) = self.hooks.clone() else {
+            return false;
+        };
+        let event_type = event.get("type").and_then(Value::as_str).unwrap_or("");
+        let artifact_id = event.get("artifact_id").and_then(Value::as_str);
+        for hook in hooks.for_event(event_type) {
+            let failure = match (hook.run(event, &self.run_dir), artifact_id) {
+                (Ok(None), _) => continue,
+                (Ok(Some(reason)), Some(artifact_id)) if hook.reject => {
+                    if let Err(err) = self.reject_artifact(artifact_id, &hook.name, &reason) {
+                        tracing::warn!(
+                            hook = %hook.name,
+                            artifact_id,
+                            error = %format_args!("{err:#}"),
+                            "failed to record a hook rejection"
+                        );
+                    }
+                    // Later hooks have nothing left to judge.
+                    return true;
+                }
+                (Ok(Some(detail)), _) => detail,
+                (Err(err), _) => format!("{err:#}"),
+            };
+            let emitted = self.events.emit(
+                "hook_failed",
+                map_object(json!({
+                    "hook": hook.name,
+                    "event": event_type,
+                    "artifact_id": artifact_id,
+                    "error": failure,
+                })),
+            );
+            if let Err(err) = emitted {
+                tracing::warn!(
+                    hook = %hook.name,
+                    event_type,
+                    failure = %failure,
+                    error = %format_args!("{err:#}"),
+                    "hook failed and hook_failed could not be emitted"
+                );
+            }
+        }
+        false
+    }
+
+    fn reject_artifact(&mut self, artifact_id: &str, hook: &str, reason: &str) -> Result<()> {
+        let rejection = json!({ "hook": hook, "reason": reason });
+        let mut receipt_path = None;
+        if let Some(artifact) = self
+            .thread
+            .versions
+            .iter_mut()
+            .flat_map(|version| version.artifacts.iter_mut())
+            .find(|artifact| {
+                artifact.get("artifact_id").and_then(Value::as_str) == Some(artifact_id)
+            })
+        {
+            artifact.insert("rejected".to_string(), rejection.clone());
+            receipt_path = artifact
+                .get("receipt_path")
+                .and_then(Value::as_str)
+                .map(PathBuf::from);
+        }
+        self.thread.save()?;
+        if let Some(receipt_path) = receipt_path {
+            store_rejection(&receipt_path, &rejection)?;
+        }
+        self.events.emit(
+            "artifact_rejected",
+            map_object(json!({
+                "artifact_id": artifact_id,
+                "hook": hook,
+                "reason": reason,
+            })),
+        )?;
+        Ok(())
+    }
+}
+
+/// Records the rejection under `result_metadata.rejected` in the receipt.
+fn store_rejection(receipt_path: &Path, rejection: &Value) -> Result<()> {
+    let Some(mut payload) = fs::read_to_string(receipt_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+    else {
+        return Ok(());
+    };
+    let Some(root) = payload.as_object_mut() else {
+        return Ok(());
+    };
+    let metadata = root
+        .entry("result_metadata".to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Map::new());
+    }
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert("rejected".to_string(), rejection.clone());
+    }
+    write_receipt(receipt_path, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use serde_json::{json, Map, Value};
+
+    use super::{HookRunner, Hooks};
+    use crate::{map_object, NativeEngine};
+
+    #[test]
+    fn hooks_file_resolves_runners() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("hooks.json");
+        fs::write(
+            &path,
+            serde_json::to_string(&json!({"hooks": [
+                {"on": "artifact_created", "command": ["./score.sh", "--min", "0.5"], "reject": true},
+                {"on": "run_finished", "command": "echo done", "name": "echo", "timeout_s": 5},
+            ]}))?,
+        )?;
+        let hooks = Hooks::load(&path)?;
+        let hooks = hooks.hooks();
+        assert_eq!(
+            hooks[0].runner,
+            HookRunner::Command(vec![
+                temp.path().join("./score.sh").to_string_lossy().to_string(),
+                "--min".to_string(),
+                "0.5".to_string(),
+            ])
+        );
+        assert!(hooks[0].reject);
+        assert_eq!(hooks[1].timeout.as_secs(), 5);
+        assert_eq!(hooks[1].name, "echo");
+
+        fs::write(
+            &path,
+            r#"{"hooks": [{"on": "run_finished", "wasm": "upload.wasm"}]}"#,
+        )?;
+        #[cfg(feature = "wasm")]
+        assert_eq!(
+            Hooks::load(&path)?.hooks()[0].runner,
+            HookRunner::Wasm(temp.path().join("upload.wasm"))
+        );
+        #[cfg(not(feature = "wasm"))]
+        assert!(Hooks::load(&path).is_err());
+
+        fs::write(
+            &path,
+            r#"{"hooks": [{"on": "version_created", "command": "true"}]}"#,
+        )?;
+        assert!(Hooks::load(&path).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_hooks_reject_artifacts_and_see_the_event() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let seen = temp.path().join("seen.jsonl");
+        let hooks_path = temp.path().join("hooks.json");
+        fs::write(
+            &hooks_path,
+            serde_json::to_string(&json!({"hooks": [
+                {"on": "artifact_created", "command": format!("cat >> '{}'", seen.display())},
+                {"on": "artifact_created", "name": "too-dark", "reject": true,
+                 "command": "echo 'mean luma 0.02' && exit 3"},
+                {"on": "run_finished", "command": "exit 1"},
+            ]}))?,
+        )?;
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        engine.set_hooks(Some(Hooks::load(&hooks_path)?));
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("32x32"));
+        let artifacts = engine.generate(
+            "a lighthouse",
+            settings,
+            map_object(json!({"action": "generate"})),
+        )?;
+        engine.finish()?;
+
+        let event: Value = serde_json::from_str(fs::read_to_string(&seen)?.trim())?;
+        assert_eq!(event["type"], json!("artifact_created"));
+        assert_eq!(event["artifact_id"], artifacts[0]["artifact_id"]);
+        assert_eq!(artifacts[0]["rejected"]["hook"], json!("too-dark"));
+        let receipt: Value = serde_json::from_str(&fs::read_to_string(
+            artifacts[0]["receipt_path"].as_str().unwrap_or_default(),
+        )?)?;
+        assert!(receipt["result_metadata"]["rejected"]["reason"]
+            .as_str()
+            .is_some_and(|reason| reason.contains("mean luma 0.02")));
+        let thread = fs::read_to_string(run_dir.join("thread.json"))?;
+        assert!(thread.contains("\"rejected\""));
+        let events = fs::read_to_string(run_dir.join("events.jsonl"))?;
+        assert!(events.contains("\"type\":\"artifact_rejected\""));
+        assert!(events.contains("\"type\":\"hook_failed\""));
+        Ok(())
+    }
+
+    fn engine_with_hooks(temp: &Path, hooks: Value) -> anyhow::Result<NativeEngine> {
+        let run_dir = temp.join("run");
+        let hooks_path = temp.join("hooks.json");
+        fs::write(&hooks_path, serde_json::to_string(&hooks)?)?;
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        engine.set_hooks(Some(Hooks::load(&hooks_path)?));
+        Ok(engine)
+    }
+
+    fn generate(engine: &mut NativeEngine, prompt: &str) -> anyhow::Result<Map<String, Value>> {
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("32x32"));
+        let mut artifacts =
+            engine.generate(prompt, settings, map_object(json!({"action": "generate"})))?;
+        Ok(artifacts.remove(0))
+    }
+
+    fn hook_failures(temp: &Path) -> anyhow::Result<Vec<String>> {
+        let events = fs::read_to_string(temp.join("run").join("events.jsonl"))?;
+        let mut failures = Vec::new();
+        for line in events.lines() {
+            let event: Value = serde_json::from_str(line)?;
+            if event["type"] == "hook_failed" {
+                failures.push(event["error"].as_str().unwrap_or_default().to_string());
+            }
+        }
+        Ok(failures)
+    }
+
+    #[test]
+    fn rejected_artifacts_stay_out_of_the_cache() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let mut engine = engine_with_hooks(
+            temp.path(),
+            json!({"hooks": [{"on": "artifact_created", "reject": true, "command": ["false"]}]}),
+        )?;
+        let first = generate(&mut engine, "a lighthouse")?;
+        assert!(first.contains_key("rejected"));
+        let second = generate(&mut engine, "a lighthouse")?;
+        assert_ne!(first["artifact_id"], second["artifact_id"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_hooks_do_not_reject_or_hold_up_the_run() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let mut engine = engine_with_hooks(
+            temp.path(),
+            json!({"hooks": [
+                {"on": "artifact_created", "reject": true, "command": ["./no-such-hook"]},
+                // Leaves a child holding stdout open after it exits.
+                {"on": "artifact_created", "command": "sleep 30 & echo started"},
+                {"on": "artifact_created", "command": "sleep 30; sleep 30", "timeout_s": 0.5},
+            ]}),
+        )?;
+        let started = std::time::Instant::now();
+        let artifact = generate(&mut engine, "a lighthouse")?;
+        assert!(started.elapsed().as_secs() < 10, "{:?}", started.elapsed());
+        assert!(!artifact.contains_key("rejected"));
+        let failures = hook_failures(temp.path())?;
+        assert_eq!(failures.len(), 2, "{failures:?}");
+        assert!(failures[0].contains("failed to start hook"), "{failures:?}");
+        assert!(failures[1].contains("timed out"), "{failures:?}");
+        Ok(())
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn wasm_hooks_run_embedded_with_only_stdin() -> anyhow::Result<()> {
+        // Echoes the first bytes of stdin to stdout, then exits 3.
+        const ECHO_AND_EXIT: &str = r#"(module
+            (import "wasi_snapshot_preview1" "fd_read" (func $read (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "fd_write" (func $write (param i32 i32 i32 i32) (result i32)))
+            (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+            (memory (export "memory") 1)
+            (func (export "_start")
+                (i32.store (i32.const 0) (i32.const 256))
+                (i32.store (i32.const 4) (i32.const 9))
+                (drop (call $read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 16)))
+                (i32.store (i32.const 4) (i32.load (i32.const 16)))
+                (drop (call $write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20)))
+                (call $exit (i32.const 3))))"#;
+        const SPIN: &str = r#"(module (func (export "_start") (loop (br 0))))"#;
+        let temp = tempfile::tempdir()?;
+        fs::write(temp.path().join("judge.wat"), ECHO_AND_EXIT)?;
+        fs::write(temp.path().join("spin.wat"), SPIN)?;
+        let mut engine = engine_with_hooks(
+            temp.path(),
+            json!({"hooks": [
+                {"on": "artifact_created", "wasm": "spin.wat", "timeout_s": 0.2},
+                {"on": "artifact_created", "wasm": "judge.wat", "reject": true},
+            ]}),
+        )?;
+        let artifact = generate(&mut engine, "a lighthouse")?;
+        let reason = artifact["rejected"]["reason"].as_str().unwrap_or_default();
+        assert!(reason.starts_with("exited with code 3: {\""), "{reason}");
+        let failures = hook_failures(temp.path())?;
+        assert_eq!(failures.len(), 1, "{failures:?}");
+        assert!(failures[0].contains("timed out"), "{failures:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client as HttpClient, ClientBuilder};
+use reqwest::{Certificate, Identity, NoProxy, Proxy};
+
+use super::non_empty_env;
+
+/// PEM bundle of extra root CAs to trust, e.g. a corporate MITM proxy's.
+/// `SSL_CERT_FILE` is used when unset.
+pub const CA_BUNDLE_ENV: &str = "BROOD_CA_BUNDLE";
+
+/// PEM client certificate presented to servers that ask for one. It may
+/// hold the private key too; otherwise [`CLIENT_KEY_ENV`] names it.
+pub const CLIENT_CERT_ENV: &str = "BROOD_CLIENT_CERT";
+
+/// PEM private key for [`CLIENT_CERT_ENV`].
+pub const CLIENT_KEY_ENV: &str = "BROOD_CLIENT_KEY";
+
+/// Proxy URL for every Brood request, ahead of `HTTPS_PROXY`/`HTTP_PROXY`.
+/// `NO_PROXY` still applies.
+pub const PROXY_ENV: &str = "BROOD_PROXY";
+
+/// Network settings every HTTP client Brood builds starts from. Without a
+/// [`PROXY_ENV`] proxy, reqwest reads `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY`
+/// and `NO_PROXY` itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HttpConfig {
+    pub ca_bundle: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub proxy: Option<String>,
+}
+
+impl HttpConfig {
+    pub fn from_env() -> Self {
+        Self {
+            ca_bundle: non_empty_env(CA_BUNDLE_ENV)
+                .or_else(|| non_empty_env("SSL_CERT_FILE"))
+                .map(PathBuf::from),
+            client_cert: non_empty_env(CLIENT_CERT_ENV).map(PathBuf::from),
+            client_key: non_empty_env(CLIENT_KEY_ENV).map(PathBuf::from),
+            proxy: non_empty_env(PROXY_ENV),
+        }
+    }
+
+    /// Reads the certificate files and parses the proxy once, so clients
+    /// built later cannot fail on them.
+    fn load(&self) -> Result<LoadedConfig> {
+        let mut loaded = LoadedConfig::default();
+        if let Some(path) = &self.ca_bundle {
+            let pem = fs::read(path)
+                .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+            loaded.roots = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+            if loaded.roots.is_empty() {
+                anyhow::bail!("CA bundle {} holds no certificates", path.display());
+            }
+        }
+        if let Some(path) = &self.client_cert {
+            let mut pem = fs::read(path)
+                .with_context(|| format!("Failed to read client certificate {}", path.display()))?;
+            if let Some(key_path) = &self.client_key {
+                pem.push(b'\n');
+                pem.extend(fs::read(key_path).with_context(|| {
+                    format!("Failed to read client key {}", key_path.display())
+                })?);
+            }
+            loaded.identity = Some(
+                Identity::from_pem(&pem)
+                    .with_context(|| format!("Invalid client certificate {}", path.display()))?,
+            );
+        } else if self.client_key.is_some() {
+            anyhow::bail!("{CLIENT_KEY_ENV} is set without {CLIENT_CERT_ENV}");
+        }
+        if let Some(url) = &self.proxy {
+            loaded.proxy = Some(
+                Proxy::all(url)
+                    .with_context(|| format!("Invalid proxy URL '{url}'"))?
+                    .no_proxy(NoProxy::from_env()),
+            );
+        }
+        Ok(loaded)
+    }
+
+    /// A client builder with these settings applied.
+    pub fn client_builder(&self) -> Result<ClientBuilder> {
+        Ok(self.load()?.apply(HttpClient::builder()))
+    }
+}
+
+#[derive(Default)]
+struct LoadedConfig {
+    roots: Vec<Certificate>,
+    identity: Option<Identity>,
+    proxy: Option<Proxy>,
+}
+
+impl LoadedConfig {
+    fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        for root in &self.roots {
+            builder = builder.add_root_certificate(root.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        builder
+    }
+}
+
+fn process_config() -> &'static Result<LoadedConfig, String> {
+    static CONFIG: OnceLock<Result<LoadedConfig, String>> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        HttpConfig::from_env()
+            .load()
+            .map_err(|err| format!("{err:#}"))
+    })
+}
+
+/// Fails when the process's [`HttpConfig`] names unreadable certificates
+/// or a bad proxy, which clients otherwise only log before going on
+/// without them.
+pub fn check_http_config() -> Result<()> {
+    process_config()
+        .as_ref()
+        .map(|_| ())
+        .map_err(|err| anyhow::anyhow!("{err}"))
+}
+
+/// A client builder with the process's [`HttpConfig`] applied. Fails when
+/// those settings are unusable, rather than going on without the proxy or
+/// CA bundle they name.
+pub fn http_client_builder() -> Result<ClientBuilder> {
+    process_config()
+        .as_ref()
+        .map(|loaded| loaded.apply(HttpClient::builder()))
+        .map_err(|err| anyhow::anyhow!("{err}"))
+}
+
+/// The process-wide client for `timeout_s`, built once and shared by every
+/// provider with that timeout, so they share its connection pool.
+pub(crate) fn shared_http_client(timeout_s: Option<f64>) -> Result<HttpClient> {
+    static CLIENTS: OnceLock<Mutex<HashMap<Option<u64>, HttpClient>>> = OnceLock::new();
+    // The map only ever gains fully built clients, so a poisoned lock
+    // still guards a usable cache.
+    let mut clients = CLIENTS
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let key = timeout_s.map(f64::to_bits);
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+    let mut builder = http_client_builder()?;
+    if let Some(timeout_s) = timeout_s {
+        builder = builder.timeout(Duration::from_secs_f64(timeout_s));
+    }
+    let client = builder.build().context("failed to build HTTP client")?;
+    clients.insert(key, client.clone());
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::HttpConfig;
+    use crate::test_support::{MockResponse, MockServer};
+
+    #[test]
+    fn clients_route_through_the_proxy_and_reject_bad_certificates() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server.mock(
+            "GET",
+            "http://provider.invalid/models",
+            MockResponse::json(200, json!({"data": []})),
+        );
+        let config = HttpConfig {
+            proxy: Some(server.url().to_string()),
+            ..HttpConfig::default()
+        };
+        let client = config.client_builder()?.build()?;
+        let response = client.get("http://provider.invalid/models").send()?;
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(server.requests()[0].path, "http://provider.invalid/models");
+
+        let dir = tempfile::tempdir()?;
+        let empty = dir.path().join("empty.pem");
+        fs::write(&empty, "not a certificate\n")?;
+        for bad in [
+            HttpConfig {
+                ca_bundle: Some(dir.path().join("missing.pem")),
+                ..HttpConfig::default()
+            },
+            HttpConfig {
+                ca_bundle: Some(empty.clone()),
+                ..HttpConfig::default()
+            },
+            HttpConfig {
+                client_cert: Some(empty),
+                ..HttpConfig::default()
+            },
+            HttpConfig {
+                client_key: Some(dir.path().join("key.pem")),
+                ..HttpConfig::default()
+            },
+            HttpConfig {
+                proxy: Some("not a url".to_string()),
+                ..HttpConfig::default()
+            },
+        ] {
+            assert!(bad.client_builder().is_err(), "{bad:?}");
+        }
+        Ok(())
+    }
+}
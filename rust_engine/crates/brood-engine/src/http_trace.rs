@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use brood_contracts::redaction::{redact_secrets_capped, redact_url};
+use brood_contracts::runs::atomic::write_json_atomic;
+use serde_json::{json, Map, Value};
+
+use super::non_empty_env;
+
+/// Set to `1` (or `true`) to trace every provider call in every run.
+pub const HTTP_TRACE_ENV: &str = "BROOD_HTTP_TRACE";
+/// Run-dir subdirectory holding one JSON file per traced provider call.
+pub const HTTP_TRACE_DIR: &str = "http_trace";
+
+/// Strings longer than this are cut in trace files.
+const TRACE_MAX_STRING_CHARS: usize = 512;
+
+thread_local! {
+    static EXCHANGES: RefCell<Option<Vec<Value>>> = const { RefCell::new(None) };
+}
+
+/// `settings.http_trace` wins over [`HTTP_TRACE_ENV`].
+pub(crate) fn http_trace_enabled(settings: &Map<String, Value>) -> bool {
+    if let Some(enabled) = settings.get("http_trace").and_then(Value::as_bool) {
+        return enabled;
+    }
+    non_empty_env(HTTP_TRACE_ENV).is_some_and(|value| {
+        matches!(
+            value.to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        )
+    })
+}
+
+/// Collects the responses read on this thread until [`HttpTraceCapture::finish`]
+/// (or drop).
+pub(crate) struct HttpTraceCapture;
+
+impl HttpTraceCapture {
+    pub(crate) fn begin() -> Self {
+        EXCHANGES.with(|cell| *cell.borrow_mut() = Some(Vec::new()));
+        Self
+    }
+
+    pub(crate) fn finish(self) -> Vec<Value> {
+        EXCHANGES.with(|cell| cell.borrow_mut().take().unwrap_or_default())
+    }
+}
+
+impl Drop for HttpTraceCapture {
+    fn drop(&mut self) {
+        EXCHANGES.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
+/// Records one response body while a capture is active on this thread.
+pub(crate) fn record_http_response(provider: &str, url: &str, status: u16, body: &str) {
+    EXCHANGES.with(|cell| {
+        let mut exchanges = cell.borrow_mut();
+        let Some(exchanges) = exchanges.as_mut() else {
+            return;
+        };
+        let body =
+            serde_json::from_str::<Value>(body).unwrap_or_else(|_| Value::String(body.to_string()));
+        exchanges.push(json!({
+            "provider": provider,
+            "url": redact_url(url),
+            "status": status,
+            "body": sanitize_trace_value(&body),
+        }));
+    });
+}
+
+/// Writes `<run_dir>/http_trace/<name>.json` with secrets redacted.
+pub(crate) fn write_http_trace(run_dir: &Path, name: &str, trace: &Value) -> Result<PathBuf> {
+    let dir = run_dir.join(HTTP_TRACE_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{name}.json"));
+    write_json_atomic(&path, &sanitize_trace_value(trace))?;
+    Ok(path)
+}
+
+/// Receipt redaction with a tighter string cap, so response bodies stay
+/// readable.
+pub(crate) fn sanitize_trace_value(value: &Value) -> Value {
+    redact_secrets_capped(value, TRACE_MAX_STRING_CHARS)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{record_http_response, sanitize_trace_value, HttpTraceCapture};
+
+    #[test]
+    fn traces_redact_secrets_and_elide_image_payloads() {
+        let sanitized = sanitize_trace_value(&json!({
+            "headers": { "Authorization": "Bearer sk-live" },
+            "api_key": "sk-live",
+            "endpoint": "https://api.example.com/v1/images",
+            "image_url": "https://storage.example.com/out.png?token=abc&size=2",
+            "data": [{ "b64_json": "A".repeat(4096) }],
+            "seed": 7,
+        }));
+        assert_eq!(sanitized["headers"]["Authorization"], json!("<redacted>"));
+        assert_eq!(sanitized["api_key"], json!("<redacted>"));
+        assert_eq!(sanitized["seed"], json!(7));
+        assert_eq!(
+            sanitized["image_url"],
+            json!("https://storage.example.com/out.png?token=<redacted>&size=2")
+        );
+        assert_eq!(
+            sanitized["data"][0]["b64_json"],
+            json!("<base64 omitted, 4096 chars>")
+        );
+        let long_text = sanitize_trace_value(&json!("error: ".repeat(100)));
+        assert!(long_text
+            .as_str()
+            .unwrap_or_default()
+            .ends_with("<188 more chars cut>"));
+
+        record_http_response("OpenAI", "https://api.example.com", 200, "{}");
+        let capture = HttpTraceCapture::begin();
+        record_http_response(
+            "Gemini",
+            "https://g.example.com/models/x:generateContent?key=AIza",
+            200,
+            r#"{"candidates": []}"#,
+        );
+        let exchanges = capture.finish();
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(
+            exchanges[0]["url"],
+            json!("https://g.example.com/models/x:generateContent?key=<redacted>")
+        );
+        assert_eq!(exchanges[0]["body"], json!({ "candidates": [] }));
+    }
+}
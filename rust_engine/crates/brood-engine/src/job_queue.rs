@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use brood_contracts::runs::lock::RunLock;
+use brood_contracts::runs::run_dir::slugify;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::batch::run_batch_row;
+use super::{non_empty_env, now_utc_iso, BatchConfig, BatchRow};
+
+/// Queue directory; defaults to `~/.brood/queue`.
+pub const JOB_QUEUE_DIR_ENV: &str = "BROOD_QUEUE_DIR";
+pub const JOB_QUEUE_FILENAME: &str = "jobs.jsonl";
+
+/// Locked around every read-modify-append of `jobs.jsonl`, so `queue add`,
+/// `cancel` and `retry` cannot race each other or the worker.
+const JOB_QUEUE_LOCK_FILENAME: &str = "jobs.lock";
+
+/// Times a job may be left `running` by a worker that died before it is
+/// marked failed instead of being run again.
+pub const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// `jobs.jsonl` is rewritten with one line per job once it holds this many
+/// superseded lines.
+const COMPACT_AFTER_STALE_LINES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// Its deadline passed before a worker got to it.
+    Expired,
+}
+
+impl JobStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// Which queued job a worker takes next. Ties fall back to the order jobs
+/// were added.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum QueuePolicy {
+    /// Highest priority first, then earliest deadline.
+    #[default]
+    Priority,
+    /// Earliest deadline first (jobs without one last), then priority.
+    Deadline,
+    /// In the order they were added.
+    Fifo,
+}
+
+impl QueuePolicy {
+    pub fn parse(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "priority" => Ok(Self::Priority),
+            "deadline" => Ok(Self::Deadline),
+            "fifo" => Ok(Self::Fifo),
+            other => bail!("unknown queue policy '{other}' (expected priority, deadline or fifo)"),
+        }
+    }
+
+    fn compare(self, left: &QueueJob, right: &QueueJob) -> Ordering {
+        // Earlier deadlines first; no deadline sorts after any deadline.
+        let deadline = match (&left.deadline, &right.deadline) {
+            (Some(left), Some(right)) => left.cmp(right),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+        let priority = right.priority.cmp(&left.priority);
+        let order = match self {
+            Self::Priority => priority.then(deadline),
+            Self::Deadline => deadline.then(priority),
+            Self::Fifo => Ordering::Equal,
+        };
+        order.then(left.seq.cmp(&right.seq))
+    }
+}
+
+/// One generation request in the queue and what became of it, as recorded
+/// on each line of `jobs.jsonl`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueJob {
+    pub job_id: String,
+    pub seq: u64,
+    pub prompt: String,
+    pub image_model: Option<String>,
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+    #[serde(default)]
+    pub priority: i64,
+    pub deadline: Option<DateTime<Utc>>,
+    pub status: JobStatus,
+    /// Times a worker has started it.
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub enqueued_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub run_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+    #[serde(default)]
+    pub cost_usd: f64,
+    pub error: Option<String>,
+}
+
+/// `jobs.jsonl` as read so far: the latest record per job, plus where
+/// reading stopped, so a worker only parses what was appended since.
+#[derive(Debug, Default)]
+struct JobLog {
+    offset: u64,
+    lines: usize,
+    latest: BTreeMap<u64, QueueJob>,
+}
+
+impl JobLog {
+    /// Reads the complete lines appended since the last call. Malformed
+    /// lines (say, a torn write) are skipped; a final line without its
+    /// newline is left for the next call.
+    fn refresh(&mut self, path: &Path) -> Result<()> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        let mut raw = Vec::new();
+        file.seek(SeekFrom::Start(self.offset))
+            .and_then(|_| file.read_to_end(&mut raw))
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let Some(end) = raw.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(());
+        };
+        for line in raw[..end].split(|byte| *byte == b'\n') {
+            self.lines += 1;
+            if let Ok(job) = serde_json::from_slice::<QueueJob>(line) {
+                self.latest.insert(job.seq, job);
+            }
+        }
+        self.offset += end as u64 + 1;
+        Ok(())
+    }
+
+    /// Rewrites `path` with one line per job once enough lines are
+    /// superseded. Only under the queue's file lock.
+    fn compact(&mut self, path: &Path) -> Result<()> {
+        if self.lines < self.latest.len() + COMPACT_AFTER_STALE_LINES {
+            return Ok(());
+        }
+        let mut raw = String::new();
+        for job in self.latest.values() {
+            raw.push_str(&serde_json::to_string(job)?);
+            raw.push('\n');
+        }
+        write_atomic(path, raw.as_bytes())?;
+        self.offset = raw.len() as u64;
+        self.lines = self.latest.len();
+        Ok(())
+    }
+}
+
+/// Persistent generation queue: `jobs.jsonl` in the queue dir, with each job
+/// run in its own run dir under `runs/`. Every change appends the job's whole
+/// record, and the last line for a job wins, so the file survives crashes and
+/// can be appended to while a worker drains it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobQueue {
+    dir: PathBuf,
+}
+
+impl JobQueue {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The queue [`JOB_QUEUE_DIR_ENV`] selects, or `~/.brood/queue`.
+    pub fn from_env() -> Option<Self> {
+        non_empty_env(JOB_QUEUE_DIR_ENV)
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(PathBuf::from)
+                    .map(|home| home.join(".brood").join("queue"))
+            })
+            .map(Self::new)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn jobs_path(&self) -> PathBuf {
+        self.dir.join(JOB_QUEUE_FILENAME)
+    }
+
+    /// Every job, in the order they were added. Malformed lines (say, a
+    /// torn final write) are skipped.
+    pub fn jobs(&self) -> Result<Vec<QueueJob>> {
+        let mut log = JobLog::default();
+        log.refresh(&self.jobs_path())?;
+        Ok(log.latest.into_values().collect())
+    }
+
+    /// Runs `change` holding the queue's file lock, blocking until any other
+    /// process's change is done.
+    fn locked<T>(&self, change: impl FnOnce() -> Result<T>) -> Result<T> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(JOB_QUEUE_LOCK_FILENAME);
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.lock().map(|()| file))
+            .with_context(|| format!("failed to lock {}", path.display()))?;
+        let result = change();
+        drop(lock);
+        result
+    }
+
+    pub fn job(&self, job_id: &str) -> Result<QueueJob> {
+        self.jobs()?
+            .into_iter()
+            .find(|job| job.job_id == job_id)
+            .ok_or_else(|| anyhow::anyhow!("no job '{job_id}' in {}", self.jobs_path().display()))
+    }
+
+    /// Adds `row` to the queue. Its `id` is not kept; the job gets its own.
+    pub fn enqueue(
+        &self,
+        row: BatchRow,
+        priority: i64,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Result<QueueJob> {
+        self.locked(|| {
+            let seq = self.jobs()?.last().map_or(1, |job| job.seq + 1);
+            let job = QueueJob {
+                job_id: format!("job-{seq:04}"),
+                seq,
+                prompt: row.prompt,
+                image_model: row.image_model,
+                settings: row.settings,
+                priority,
+                deadline,
+                status: JobStatus::Queued,
+                attempts: 0,
+                enqueued_at: now_utc_iso(),
+                started_at: None,
+                finished_at: None,
+                run_dir: None,
+                artifacts: Vec::new(),
+                cost_usd: 0.0,
+                error: None,
+            };
+            self.append(&job)?;
+            Ok(job)
+        })
+    }
+
+    /// Cancels a job that has not started yet.
+    pub fn cancel(&self, job_id: &str) -> Result<QueueJob> {
+        self.locked(|| {
+            let mut job = self.job(job_id)?;
+            if job.status != JobStatus::Queued {
+                bail!(
+                    "job {job_id} is {}; only queued jobs can be cancelled",
+                    job.status.label()
+                );
+            }
+            job.status = JobStatus::Cancelled;
+            job.finished_at = Some(now_utc_iso());
+            self.append(&job)?;
+            Ok(job)
+        })
+    }
+
+    /// Puts a failed, cancelled or expired job back in the queue with a
+    /// fresh attempt count. An expired job loses its deadline, or it would
+    /// expire again straight away.
+    pub fn retry(&self, job_id: &str) -> Result<QueueJob> {
+        self.locked(|| {
+            let mut job = self.job(job_id)?;
+            match job.status {
+                JobStatus::Failed | JobStatus::Cancelled => {}
+                JobStatus::Expired => job.deadline = None,
+                status => bail!(
+                    "job {job_id} is {}; only failed, cancelled or expired jobs can be retried",
+                    status.label()
+                ),
+            }
+            job.status = JobStatus::Queued;
+            job.attempts = 0;
+            job.finished_at = None;
+            job.error = None;
+            self.append(&job)?;
+            Ok(job)
+        })
+    }
+
+    /// Drains the queue: runs queued jobs one at a time in `policy` order
+    /// until none is left, calling `on_done` as each finishes. Holds a lock on
+    /// the queue dir so only one worker drains it. Jobs a crashed worker left
+    /// `running` are queued again first, or marked failed once they have
+    /// been started [`MAX_JOB_ATTEMPTS`] times. Jobs added meanwhile are
+    /// picked up.
+    pub fn work(
+        &self,
+        config: &BatchConfig,
+        policy: QueuePolicy,
+        mut on_done: impl FnMut(&QueueJob),
+    ) -> Result<Vec<QueueJob>> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let _lock = RunLock::acquire(&self.dir)
+            .with_context(|| format!("queue {} already has a worker", self.dir.display()))?;
+        let mut log = JobLog::default();
+        self.locked(|| {
+            log.refresh(&self.jobs_path())?;
+            let crashed: Vec<QueueJob> = log
+                .latest
+                .values()
+                .filter(|job| job.status == JobStatus::Running)
+                .cloned()
+                .collect();
+            for mut job in crashed {
+                if job.attempts >= MAX_JOB_ATTEMPTS {
+                    job.status = JobStatus::Failed;
+                    job.finished_at = Some(now_utc_iso());
+                    job.error = Some(format!(
+                        "worker died while running it {} times",
+                        job.attempts
+                    ));
+                } else {
+                    job.status = JobStatus::Queued;
+                }
+                self.append(&job)?;
+            }
+            Ok(())
+        })?;
+        let cache_path = self.dir.join("cache.json");
+        let mut done = Vec::new();
+        loop {
+            let started = self.locked(|| {
+                let Some(mut job) = self.next(&mut log, policy)? else {
+                    return Ok(None);
+                };
+                let run_dir = job.run_dir.clone().unwrap_or_else(|| {
+                    self.dir
+                        .join("runs")
+                        .join(format!("{}-{}", job.job_id, slugify(&job.prompt)))
+                });
+                job.status = JobStatus::Running;
+                job.attempts += 1;
+                job.started_at = Some(now_utc_iso());
+                job.run_dir = Some(run_dir.clone());
+                self.append(&job)?;
+                Ok(Some((job, run_dir)))
+            })?;
+            let Some((mut job, run_dir)) = started else {
+                break;
+            };
+
+            let row = BatchRow {
+                id: job.job_id.clone(),
+                prompt: job.prompt.clone(),
+                image_model: job.image_model.clone(),
+                settings: job.settings.clone(),
+            };
+            let outcome = run_batch_row(&row, &run_dir, &cache_path, config, config.budget_usd);
+            job.status = if outcome.error.is_none() {
+                JobStatus::Succeeded
+            } else {
+                JobStatus::Failed
+            };
+            job.finished_at = Some(now_utc_iso());
+            job.artifacts = outcome.artifacts;
+            job.cost_usd += outcome.cost_usd;
+            job.error = outcome.error;
+            self.locked(|| self.append(&job))?;
+            on_done(&job);
+            done.push(job);
+        }
+        Ok(done)
+    }
+
+    /// The queued job `policy` picks, after catching `log` up with the
+    /// file. Queued jobs past their deadline are marked expired on the way.
+    /// Only under the queue's file lock.
+    fn next(&self, log: &mut JobLog, policy: QueuePolicy) -> Result<Option<QueueJob>> {
+        let path = self.jobs_path();
+        log.refresh(&path)?;
+        log.compact(&path)?;
+        let now = Utc::now();
+        let mut queued = Vec::new();
+        for job in log.latest.values() {
+            if job.status != JobStatus::Queued {
+                continue;
+            }
+            if job.deadline.is_some_and(|deadline| deadline <= now) {
+                let mut job = job.clone();
+                job.status = JobStatus::Expired;
+                job.finished_at = Some(now_utc_iso());
+                self.append(&job)?;
+                continue;
+            }
+            queued.push(job);
+        }
+        Ok(queued
+            .into_iter()
+            .min_by(|left, right| policy.compare(left, right))
+            .cloned())
+    }
+
+    /// One line per change in append mode. Only under the queue's file lock.
+    /// A torn final line is ended first so it does not swallow the new
+    /// record.
+    fn append(&self, job: &QueueJob) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let path = self.jobs_path();
+        let mut line = serde_json::to_string(job)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| {
+                let len = file.metadata()?.len();
+                if len > 0 {
+                    let mut last = [0u8];
+                    file.seek(SeekFrom::Start(len - 1))?;
+                    file.read_exact(&mut last)?;
+                    if last[0] != b'\n' {
+                        line.insert(0, '\n');
+                    }
+                }
+                file.write_all(line.as_bytes())?;
+                file.sync_data()
+            })
+            .with_context(|| format!("failed to append to {}", path.display()))
+    }
+}
+
+/// Parses a `--deadline`: an RFC 3339 time, or a delay from `now` such as
+/// `90s`, `30m`, `2h` or `1d`.
+pub fn parse_deadline(raw: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let raw = raw.trim();
+    if let Ok(deadline) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(deadline.with_timezone(&Utc));
+    }
+    let split = raw.len().saturating_sub(1);
+    let delay = raw
+        .get(..split)
+        .and_then(|amount| amount.parse::<i64>().ok())
+        .filter(|amount| *amount >= 0)
+        .and_then(|amount| match &raw[split..] {
+            "s" => Some(Duration::seconds(amount)),
+            "m" => Some(Duration::minutes(amount)),
+            "h" => Some(Duration::hours(amount)),
+            "d" => Some(Duration::days(amount)),
+            _ => None,
+        });
+    match delay {
+        Some(delay) => Ok(now + delay),
+        None => bail!("invalid deadline '{raw}' (expected an RFC 3339 time or e.g. 30m, 2h, 1d)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+    use serde_json::{json, Map};
+
+    use super::{
+        parse_deadline, JobLog, JobQueue, JobStatus, QueuePolicy, JOB_QUEUE_FILENAME,
+        MAX_JOB_ATTEMPTS,
+    };
+    use crate::{BatchConfig, BatchRow};
+
+    fn row(prompt: &str) -> BatchRow {
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("32x32"));
+        BatchRow {
+            id: String::new(),
+            prompt: prompt.to_string(),
+            image_model: None,
+            settings,
+        }
+    }
+
+    fn config(out_dir: &std::path::Path) -> BatchConfig {
+        BatchConfig {
+            out_dir: out_dir.to_path_buf(),
+            concurrency: 1,
+            budget_usd: None,
+            text_model: Some("dryrun-text-1".to_string()),
+            image_model: Some("dryrun-image-1".to_string()),
+            base_settings: Map::new(),
+            global_cache_dir: None,
+            cost_ledger: None,
+            artifact_store: None,
+            keyring: None,
+            hooks: None,
+        }
+    }
+
+    #[test]
+    fn queue_runs_jobs_by_policy_and_survives_restart() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let queue = JobQueue::new(temp.path().join("queue"));
+        let soon = Utc::now() + Duration::hours(1);
+        let low = queue.enqueue(row("low"), 0, None)?;
+        let urgent = queue.enqueue(row("urgent"), 0, Some(soon))?;
+        let high = queue.enqueue(row("high"), 5, None)?;
+        let late = queue.enqueue(row("late"), 9, Some(Utc::now() - Duration::minutes(1)))?;
+        let dropped = queue.enqueue(row("dropped"), 1, None)?;
+        assert_eq!(low.job_id, "job-0001");
+        queue.cancel(&dropped.job_id)?;
+        assert!(queue.cancel(&dropped.job_id).is_err());
+
+        // A worker that died mid-job left it running; the next one redoes it.
+        let mut crashed = queue.job(&low.job_id)?;
+        crashed.status = JobStatus::Running;
+        queue.append(&crashed)?;
+        // So did a torn write.
+        let jobs_path = queue.dir().join(JOB_QUEUE_FILENAME);
+        let mut raw = std::fs::read_to_string(&jobs_path)?;
+        raw.push_str("{\"job_id\": \"job-00");
+        std::fs::write(&jobs_path, raw)?;
+
+        let mut order = Vec::new();
+        let done = queue.work(&config(temp.path()), QueuePolicy::Priority, |job| {
+            order.push(job.prompt.clone())
+        })?;
+        assert_eq!(order, ["high", "urgent", "low"]);
+        assert!(done.iter().all(|job| job.status == JobStatus::Succeeded
+            && job.attempts == 1
+            && !job.artifacts.is_empty()));
+        assert_eq!(queue.job(&late.job_id)?.status, JobStatus::Expired);
+        assert_eq!(queue.job(&urgent.job_id)?.status, JobStatus::Succeeded);
+        assert!(queue.retry(&high.job_id).is_err());
+
+        // Retried jobs run again; expired ones without their deadline.
+        assert_eq!(queue.retry(&late.job_id)?.deadline, None);
+        queue.retry(&dropped.job_id)?;
+        let mut order = Vec::new();
+        queue.work(&config(temp.path()), QueuePolicy::Fifo, |job| {
+            order.push(job.job_id.clone())
+        })?;
+        assert_eq!(order, [late.job_id, dropped.job_id]);
+        assert_eq!(queue.jobs()?.len(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_policy_and_parsing() -> anyhow::Result<()> {
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(parse_deadline("30m", now)?, now + Duration::minutes(30));
+        assert_eq!(parse_deadline("1d", now)?, now + Duration::days(1));
+        assert_eq!(
+            parse_deadline("2026-03-02T00:00:00Z", now)?,
+            Utc.with_ymd_and_hms(2026, 3, 2, 0, 0, 0).unwrap()
+        );
+        assert!(parse_deadline("soon", now).is_err());
+        assert!(QueuePolicy::parse("lifo").is_err());
+
+        let temp = tempfile::tempdir()?;
+        let queue = JobQueue::new(temp.path());
+        queue.enqueue(row("important"), 9, None)?;
+        queue.enqueue(row("later"), 0, Some(Utc::now() + Duration::hours(2)))?;
+        queue.enqueue(row("sooner"), 0, Some(Utc::now() + Duration::hours(1)))?;
+        let mut log = JobLog::default();
+        let mut next = |policy| {
+            queue
+                .next(&mut log, policy)
+                .map(|job| job.map(|job| job.prompt))
+        };
+        assert_eq!(next(QueuePolicy::Deadline)?.as_deref(), Some("sooner"));
+        assert_eq!(next(QueuePolicy::Priority)?.as_deref(), Some("important"));
+        assert_eq!(next(QueuePolicy::Fifo)?.as_deref(), Some("important"));
+        Ok(())
+    }
+
+    #[test]
+    fn crashed_jobs_give_up_after_max_attempts_and_the_log_is_compacted() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let queue = JobQueue::new(temp.path().join("queue"));
+        let flaky = queue.enqueue(row("flaky"), 0, None)?;
+        let mut crashed = queue.job(&flaky.job_id)?;
+        crashed.status = JobStatus::Running;
+        crashed.attempts = MAX_JOB_ATTEMPTS;
+        queue.append(&crashed)?;
+        // Enough churn to trigger compaction.
+        let churn = queue.enqueue(row("churn"), 0, None)?;
+        for _ in 0..300 {
+            queue.cancel(&churn.job_id)?;
+            queue.retry(&churn.job_id)?;
+        }
+        queue.cancel(&churn.job_id)?;
+
+        let done = queue.work(&config(temp.path()), QueuePolicy::Fifo, |_| {})?;
+        assert!(done.is_empty());
+        let flaky = queue.job(&flaky.job_id)?;
+        assert_eq!(flaky.status, JobStatus::Failed);
+        assert!(flaky.error.unwrap_or_default().contains("worker died"));
+        let raw = std::fs::read_to_string(queue.dir().join(JOB_QUEUE_FILENAME))?;
+        assert_eq!(raw.lines().count(), 2);
+
+        // A retry starts the attempt count over.
+        assert_eq!(queue.retry(&flaky.job_id)?.attempts, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+use anyhow::{Context, Result};
+use keyring::Entry;
+
+use super::http_client::shared_http_client;
+use super::{non_empty_env, response_json_or_error, ProviderConfig, ProviderSettings};
+
+/// Keychain service provider keys are stored under; the account is the
+/// provider name (`openai`, `flux`, a custom endpoint's name, ...).
+pub const KEYCHAIN_SERVICE: &str = "brood";
+
+/// `0`/`off` stops providers reading keys from the OS keychain (CI, or
+/// machines where a locked keychain would prompt).
+pub const KEYCHAIN_ENV: &str = "BROOD_KEYCHAIN";
+
+/// Keys already read this process, `None` for providers with no entry, so a
+/// provider call does not go to the keychain every time. Every write leaves
+/// a complete entry, so a lock poisoned by a panicking thread is still good.
+fn cache() -> MutexGuard<'static, HashMap<String, Option<String>>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(not(test))]
+fn entry(provider: &str) -> Result<Entry> {
+    Entry::new(KEYCHAIN_SERVICE, provider)
+        .with_context(|| format!("no keychain entry possible for '{provider}'"))
+}
+
+/// Tests never touch the real keychain, which may hold the developer's keys.
+#[cfg(test)]
+fn entry(provider: &str) -> Result<Entry> {
+    Ok(Entry::new_with_credential(Box::new(
+        tests::MemoryCredential(provider.to_string()),
+    )))
+}
+
+/// Stores `key` for `provider` in the OS keychain.
+pub fn store_keychain_key(provider: &str, key: &str) -> Result<()> {
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("refusing to store an empty key for '{provider}'");
+    }
+    entry(provider)?
+        .set_password(key)
+        .with_context(|| format!("failed to store the '{provider}' key in the keychain"))?;
+    cache().insert(provider.to_string(), Some(key.to_string()));
+    Ok(())
+}
+
+/// `provider`'s key from the OS keychain, `None` when none is stored.
+pub fn keychain_key(provider: &str) -> Result<Option<String>> {
+    match entry(provider)?.get_password() {
+        Ok(key) => Ok(Some(key).filter(|key| !key.trim().is_empty())),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err)
+            .with_context(|| format!("failed to read the '{provider}' key from the keychain")),
+    }
+}
+
+/// Deletes `provider`'s key; `false` when there was none.
+pub fn remove_keychain_key(provider: &str) -> Result<bool> {
+    let removed = match entry(provider)?.delete_credential() {
+        Ok(()) => true,
+        Err(keyring::Error::NoEntry) => false,
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!("failed to remove the '{provider}' key from the keychain")
+            })
+        }
+    };
+    cache().insert(provider.to_string(), None);
+    Ok(removed)
+}
+
+/// The cached keychain lookup providers make before their env vars. An
+/// unreachable keychain counts as no key.
+pub(crate) fn cached_keychain_key(provider: &str) -> Option<String> {
+    if non_empty_env(KEYCHAIN_ENV)
+        .is_some_and(|value| matches!(value.to_ascii_lowercase().as_str(), "0" | "off" | "false"))
+    {
+        return None;
+    }
+    if let Some(cached) = cache().get(provider) {
+        return cached.clone();
+    }
+    let key = keychain_key(provider).unwrap_or_else(|err| {
+        tracing::debug!("keychain lookup for {provider} failed: {err:#}");
+        None
+    });
+    cache().insert(provider.to_string(), key.clone());
+    key
+}
+
+/// Where `settings.provider`'s key comes from outside a request: `keychain`
+/// or the env var holding it, `None` when there is none.
+pub fn api_key_source(settings: &ProviderSettings) -> Option<String> {
+    if cached_keychain_key(&settings.provider).is_some() {
+        return Some("keychain".to_string());
+    }
+    settings
+        .api_key_envs
+        .iter()
+        .find(|key| non_empty_env(key).is_some())
+        .cloned()
+}
+
+/// Checks `settings.provider`'s key with one authenticated read-only
+/// request. `Ok(false)` when Brood knows no such request for the provider;
+/// a rejected key is an error.
+pub fn verify_api_key(settings: &ProviderSettings) -> Result<bool> {
+    let Some(api_key) = settings.api_key() else {
+        anyhow::bail!(
+            "no key for '{}' (keychain or {})",
+            settings.provider,
+            settings.api_key_envs.join(", ")
+        );
+    };
+    let base = settings.base_url.trim_end_matches('/');
+    let http = shared_http_client(None)?;
+    let bearer = |path: &str| http.get(format!("{base}/{path}")).bearer_auth(&api_key);
+    let request = match settings.provider.as_str() {
+        "openai" => bearer("models"),
+        "openrouter" => bearer("key"),
+        "replicate" => bearer("account"),
+        "stability" => bearer("v1/user/account"),
+        "recraft" => bearer("users/me"),
+        "gemini" | "imagen" => http
+            .get(format!("{base}/models"))
+            .header("x-goog-api-key", &api_key),
+        name if ProviderConfig::is_builtin(name) => return Ok(false),
+        // Custom endpoints speak the OpenAI API.
+        _ => bearer("models"),
+    };
+    let response = request
+        .timeout(std::time::Duration::from_secs(30))
+        .send()
+        .map_err(reqwest::Error::without_url)
+        .with_context(|| format!("'{}' key check request failed", settings.provider))?;
+    response_json_or_error(&settings.provider, response)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+
+    use super::{
+        api_key_source, cached_keychain_key, keychain_key, remove_keychain_key, store_keychain_key,
+        verify_api_key,
+    };
+    use crate::credentials::resolve_api_key_with;
+    use crate::test_support::{MockResponse, MockServer};
+
+    fn store() -> &'static Mutex<HashMap<String, Vec<u8>>> {
+        static STORE: OnceLock<Mutex<HashMap<String, Vec<u8>>>> = OnceLock::new();
+        STORE.get_or_init(Mutex::default)
+    }
+
+    /// A keychain entry kept in process memory.
+    #[derive(Debug)]
+    pub(crate) struct MemoryCredential(pub(crate) String);
+
+    impl keyring::credential::CredentialApi for MemoryCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            store()
+                .lock()
+                .expect("memory keychain lock")
+                .insert(self.0.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            store()
+                .lock()
+                .expect("memory keychain lock")
+                .get(&self.0)
+                .cloned()
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            store()
+                .lock()
+                .expect("memory keychain lock")
+                .remove(&self.0)
+                .map(|_| ())
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn stored_keys_beat_env_until_removed() -> anyhow::Result<()> {
+        let envs = vec!["BROOD_TEST_KEYCHAIN_KEY".to_string()];
+        let env = |key: &str| (key == "BROOD_TEST_KEYCHAIN_KEY").then(|| "from-env".to_string());
+        assert_eq!(keychain_key("keychain-test")?, None);
+        assert_eq!(
+            resolve_api_key_with("keychain-test", &envs, env).as_deref(),
+            Some("from-env")
+        );
+
+        store_keychain_key("keychain-test", " from-keychain \n")?;
+        assert_eq!(
+            keychain_key("keychain-test")?.as_deref(),
+            Some("from-keychain")
+        );
+        assert_eq!(
+            resolve_api_key_with("keychain-test", &envs, env).as_deref(),
+            Some("from-keychain")
+        );
+        assert!(store_keychain_key("keychain-test", "  ").is_err());
+
+        assert!(remove_keychain_key("keychain-test")?);
+        assert!(!remove_keychain_key("keychain-test")?);
+        assert_eq!(cached_keychain_key("keychain-test"), None);
+        assert_eq!(
+            resolve_api_key_with("keychain-test", &envs, env).as_deref(),
+            Some("from-env")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn key_checks_hit_each_providers_read_only_endpoint() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server
+            .mock(
+                "GET",
+                "/models",
+                MockResponse::json(200, serde_json::json!({"data": []})),
+            )
+            .mock(
+                "GET",
+                "/key",
+                MockResponse::json(401, serde_json::json!({"error": {"message": "bad key"}})),
+            );
+        let config = server.provider_config(&["openai", "openrouter", "fal"])?;
+        let openai = config.settings("openai");
+        assert_eq!(
+            api_key_source(&openai).as_deref(),
+            Some("BROOD_MOCK_API_KEY")
+        );
+        assert!(verify_api_key(&openai)?);
+        assert!(verify_api_key(&config.settings("openrouter")).is_err());
+        assert!(!verify_api_key(&config.settings("fal"))?);
+        let sent = server.requests();
+        assert_eq!(sent[0].header("authorization"), Some("Bearer mock-key"));
+        assert_eq!(sent.len(), 2);
+        Ok(())
+    }
+}
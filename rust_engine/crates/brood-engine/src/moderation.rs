@@ -0,0 +1,350 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use image::imageops::FilterType;
+use serde_json::{json, Map, Value};
+
+use super::{
+    error_chain_text, mime_for_path, response_json_or_error, NativeEngine, ProviderConfig,
+    ProviderSettings, BASE64,
+};
+
+/// Run-dir subdirectory flagged artifacts are moved to.
+pub const QUARANTINE_DIR: &str = "quarantine";
+pub const SAFETY_CHECK_DEFAULT_THRESHOLD: f64 = 0.5;
+
+const OPENAI_MODERATION_MODEL: &str = "omni-moderation-latest";
+const MODERATION_TIMEOUT_S: f64 = 30.0;
+/// Images are reduced to this edge before the local classifier runs.
+const LOCAL_MAX_EDGE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafetyBackend {
+    /// Local. Images are only sent to OpenAI when asked for by name, even
+    /// when a key is set.
+    Auto,
+    OpenAi,
+    /// Skin-tone coverage heuristic; coarse, but needs no network.
+    Local,
+}
+
+impl SafetyBackend {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "auto" | "" => Some(Self::Auto),
+            "openai" | "provider" => Some(Self::OpenAi),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+
+    /// The backend that actually runs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::OpenAi => "openai",
+            Self::Auto | Self::Local => "local",
+        }
+    }
+}
+
+/// `settings.safety_check`: `true`, a backend name, or
+/// `{"backend": "local", "quarantine": true, "threshold": 0.5}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetyCheck {
+    pub backend: SafetyBackend,
+    /// Move flagged files under [`QUARANTINE_DIR`].
+    pub quarantine: bool,
+    /// Scores at or above this flag the artifact (0.0..=1.0).
+    pub threshold: f64,
+}
+
+impl SafetyCheck {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let mut check = Self {
+            backend: SafetyBackend::Auto,
+            quarantine: false,
+            threshold: SAFETY_CHECK_DEFAULT_THRESHOLD,
+        };
+        let raw = match settings.get("safety_check") {
+            None | Some(Value::Null) | Some(Value::Bool(false)) => return Ok(None),
+            Some(Value::Bool(true)) => return Ok(Some(check)),
+            Some(Value::String(backend)) => {
+                check.backend = SafetyBackend::parse(backend)
+                    .with_context(|| format!("unknown safety_check backend '{backend}'"))?;
+                return Ok(Some(check));
+            }
+            Some(Value::Object(raw)) => raw,
+            Some(other) => bail!("safety_check must be true, a backend or an object (got {other})"),
+        };
+        if let Some(backend) = raw.get("backend").and_then(Value::as_str) {
+            check.backend = SafetyBackend::parse(backend)
+                .with_context(|| format!("unknown safety_check backend '{backend}'"))?;
+        }
+        check.quarantine = raw
+            .get("quarantine")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if let Some(value) = raw.get("threshold") {
+            check.threshold = value
+                .as_f64()
+                .filter(|threshold| (0.0..=1.0).contains(threshold))
+                .with_context(|| format!("safety_check.threshold must be 0..=1 (got {value})"))?;
+        }
+        Ok(Some(check))
+    }
+}
+
+/// Outcome of a safety check, stored under `metrics.safety`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetyVerdict {
+    pub flagged: bool,
+    /// `openai` or `local`.
+    pub backend: String,
+    /// Highest category score, 0.0..=1.0.
+    pub score: f64,
+    /// Categories at or above the threshold (or flagged by the provider).
+    pub categories: Vec<String>,
+    /// Why the image could not be classified. Such an image counts as
+    /// flagged, in the `unclassified` category.
+    pub error: Option<String>,
+}
+
+impl SafetyVerdict {
+    /// The verdict for an image the backend failed on: flagged, so a check
+    /// that breaks never lets an image through as clean.
+    fn unclassified(backend: SafetyBackend, err: &anyhow::Error) -> Self {
+        Self {
+            flagged: true,
+            backend: backend.as_str().to_string(),
+            score: 0.0,
+            categories: vec!["unclassified".to_string()],
+            error: Some(error_chain_text(err, 512)),
+        }
+    }
+
+    pub fn to_map(&self) -> Map<String, Value> {
+        let mut out = Map::new();
+        out.insert("flagged".to_string(), json!(self.flagged));
+        out.insert("backend".to_string(), json!(self.backend));
+        out.insert("score".to_string(), json!(self.score));
+        out.insert("categories".to_string(), json!(self.categories));
+        if let Some(error) = &self.error {
+            out.insert("error".to_string(), json!(error));
+        }
+        out
+    }
+}
+
+/// Calls OpenAI's moderation endpoint for generated images.
+#[derive(Debug, Clone)]
+pub(crate) struct ModerationClient {
+    openai: ProviderSettings,
+}
+
+impl ModerationClient {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            openai: config.settings("openai"),
+        }
+    }
+
+    fn moderate_openai(&self, path: &Path, threshold: f64) -> Result<SafetyVerdict> {
+        let Some(api_key) = self.openai.api_key() else {
+            bail!("safety_check backend openai needs OPENAI_API_KEY");
+        };
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed reading {}", path.display()))?;
+        let mime = mime_for_path(path).unwrap_or("image/png");
+        let payload = json!({
+            "model": OPENAI_MODERATION_MODEL,
+            "input": [{
+                "type": "image_url",
+                "image_url": { "url": format!("data:{mime};base64,{}", BASE64.encode(bytes)) },
+            }],
+        });
+        let endpoint = format!("{}/moderations", self.openai.base_url);
+        let response = self
+            .openai
+            .http_client()?
+            .post(&endpoint)
+            .bearer_auth(api_key)
+            .timeout(Duration::from_secs_f64(MODERATION_TIMEOUT_S))
+            .json(&payload)
+            .send()
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("OpenAI moderation request failed ({endpoint})"))?;
+        let payload = response_json_or_error("OpenAI", response)?;
+        openai_verdict(&payload, threshold).context("OpenAI moderation response had no results")
+    }
+}
+
+impl NativeEngine {
+    /// Classifies one generated image with the backend `check` selects. A
+    /// failed check is an [`SafetyVerdict::unclassified`] verdict.
+    pub(crate) fn check_output_safety(&self, check: &SafetyCheck, path: &Path) -> SafetyVerdict {
+        let verdict = match check.backend {
+            SafetyBackend::OpenAi => self.moderation.moderate_openai(path, check.threshold),
+            SafetyBackend::Auto | SafetyBackend::Local => local_verdict(path, check.threshold),
+        };
+        verdict.unwrap_or_else(|err| SafetyVerdict::unclassified(check.backend, &err))
+    }
+
+    /// Moves a flagged file under [`QUARANTINE_DIR`] and returns its new path.
+    pub(crate) fn quarantine_file(&self, path: &Path) -> Result<PathBuf> {
+        let dir = self.run_dir.join(QUARANTINE_DIR);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let Some(name) = path.file_name() else {
+            bail!("cannot quarantine {}", path.display());
+        };
+        let target = dir.join(name);
+        std::fs::rename(path, &target).with_context(|| {
+            format!("failed to move {} to {}", path.display(), target.display())
+        })?;
+        Ok(target)
+    }
+}
+
+fn local_verdict(path: &Path, threshold: f64) -> Result<SafetyVerdict> {
+    let score = local_skin_score(path)?;
+    let flagged = score >= threshold;
+    Ok(SafetyVerdict {
+        flagged,
+        backend: "local".to_string(),
+        score,
+        categories: if flagged {
+            vec!["skin_exposure".to_string()]
+        } else {
+            Vec::new()
+        },
+        error: None,
+    })
+}
+
+fn openai_verdict(payload: &Value, threshold: f64) -> Option<SafetyVerdict> {
+    let result = payload.pointer("/results/0")?;
+    let scores = result
+        .get("category_scores")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    let flagged_by_provider = result
+        .get("categories")
+        .and_then(Value::as_object)
+        .map(|categories| {
+            categories
+                .iter()
+                .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let mut categories = flagged_by_provider;
+    for (name, score) in &scores {
+        if score.as_f64().is_some_and(|score| score >= threshold) && !categories.contains(name) {
+            categories.push(name.clone());
+        }
+    }
+    categories.sort();
+    let score = scores
+        .values()
+        .filter_map(Value::as_f64)
+        .fold(0.0f64, f64::max);
+    let flagged = result
+        .get("flagged")
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+        || !categories.is_empty();
+    Some(SafetyVerdict {
+        flagged,
+        backend: "openai".to_string(),
+        score,
+        categories,
+        error: None,
+    })
+}
+
+/// Fraction of pixels in the YCbCr skin-tone range. Large skin coverage is
+/// a coarse proxy for explicit content; it will flag close-up portraits too.
+pub fn local_skin_score(path: &Path) -> Result<f64> {
+    let image = image::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let image = if image.width().max(image.height()) > LOCAL_MAX_EDGE {
+        image.resize(LOCAL_MAX_EDGE, LOCAL_MAX_EDGE, FilterType::Triangle)
+    } else {
+        image
+    };
+    let rgb = image.to_rgb8();
+    let total = rgb.pixels().len();
+    if total == 0 {
+        return Ok(0.0);
+    }
+    let skin = rgb
+        .pixels()
+        .filter(|pixel| {
+            let [r, g, b] = pixel.0.map(f64::from);
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+            let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+            y > 40.0 && (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+        })
+        .count();
+    Ok(skin as f64 / total as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use serde_json::{json, Map};
+
+    use super::{
+        local_skin_score, local_verdict, openai_verdict, SafetyBackend, SafetyCheck, SafetyVerdict,
+    };
+
+    #[test]
+    fn parses_checks_and_scores_verdicts() -> anyhow::Result<()> {
+        let mut settings = Map::new();
+        assert_eq!(SafetyCheck::from_settings(&settings)?, None);
+        settings.insert("safety_check".to_string(), json!("local"));
+        let check = SafetyCheck::from_settings(&settings)?;
+        assert_eq!(check.map(|check| check.backend), Some(SafetyBackend::Local));
+        settings.insert(
+            "safety_check".to_string(),
+            json!({"backend": "openai", "quarantine": true, "threshold": 0.8}),
+        );
+        let check = SafetyCheck::from_settings(&settings)?;
+        assert!(check.is_some_and(|check| check.quarantine && check.threshold == 0.8));
+        settings.insert("safety_check".to_string(), json!({"threshold": 2}));
+        assert!(SafetyCheck::from_settings(&settings).is_err());
+
+        let payload = json!({"results": [{
+            "flagged": false,
+            "categories": {"sexual": false, "violence": false},
+            "category_scores": {"sexual": 0.62, "violence": 0.01},
+        }]});
+        let verdict = openai_verdict(&payload, 0.5).expect("verdict");
+        assert!(verdict.flagged);
+        assert_eq!(verdict.categories, vec!["sexual".to_string()]);
+        assert!((verdict.score - 0.62).abs() < 1e-9);
+        assert!(openai_verdict(&payload, 0.9).is_some_and(|verdict| !verdict.flagged));
+
+        let temp = tempfile::tempdir()?;
+        let skin = temp.path().join("skin.png");
+        RgbImage::from_pixel(8, 8, Rgb([224, 172, 150])).save(&skin)?;
+        let sky = temp.path().join("sky.png");
+        RgbImage::from_pixel(8, 8, Rgb([40, 90, 220])).save(&sky)?;
+        assert_eq!(local_skin_score(&skin)?, 1.0);
+        assert_eq!(local_skin_score(&sky)?, 0.0);
+
+        let unreadable = temp.path().join("out.avif");
+        std::fs::write(&unreadable, b"not an image")?;
+        let verdict = local_verdict(&unreadable, 0.5)
+            .unwrap_or_else(|err| SafetyVerdict::unclassified(SafetyBackend::Auto, &err));
+        assert!(verdict.flagged);
+        assert_eq!(verdict.backend, "local");
+        assert_eq!(verdict.categories, vec!["unclassified".to_string()]);
+        assert!(verdict.to_map().contains_key("error"));
+        Ok(())
+    }
+}
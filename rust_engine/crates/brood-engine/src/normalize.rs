@@ -0,0 +1,329 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use image::metadata::Orientation;
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageReader, ImageResult};
+use moxcms::{ColorProfile, DataColorSpace, Layout, TransformOptions};
+use serde_json::{json, Map, Value};
+
+use super::output_format::{encode_image, is_svg, OutputFormat, LOCAL_JPEG_QUALITY};
+
+/// How close, per XYZ component, an embedded profile's primaries must be to
+/// sRGB's for the image to count as sRGB already.
+const SRGB_COLORANT_TOLERANCE: f64 = 0.005;
+
+/// `settings.normalize`, applied to each provider file before anything else
+/// touches it. `false` turns it off; an object picks steps:
+/// `{orientation?, srgb?, strip_metadata?}`. By default EXIF rotation is
+/// baked into the pixels and wide-gamut ICC profiles are converted to sRGB;
+/// files needing neither are left byte-for-byte alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalizeSpec {
+    pub orientation: bool,
+    pub srgb: bool,
+    /// Rewrite files that carry EXIF, XMP or ICC metadata even when nothing
+    /// else changes. Any rewrite drops metadata.
+    pub strip_metadata: bool,
+}
+
+impl Default for NormalizeSpec {
+    fn default() -> Self {
+        Self {
+            orientation: true,
+            srgb: true,
+            strip_metadata: false,
+        }
+    }
+}
+
+/// What [`NormalizeSpec::apply`] changed, for `result_metadata.normalize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizeOutcome {
+    pub metadata: Map<String, Value>,
+    pub dims: (u32, u32),
+    /// Set when the rewrite was lossy, as for JPEG.
+    pub warnings: Vec<Warning>,
+}
+
+impl NormalizeSpec {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Self> {
+        let raw = match settings.get("normalize") {
+            None | Some(Value::Null) | Some(Value::Bool(true)) => return Ok(Self::default()),
+            Some(Value::Bool(false)) => {
+                return Ok(Self {
+                    orientation: false,
+                    srgb: false,
+                    strip_metadata: false,
+                })
+            }
+            Some(Value::Object(raw)) => raw,
+            Some(other) => bail!("normalize must be a boolean or an object, got {other}"),
+        };
+        let mut spec = Self::default();
+        for (key, value) in raw {
+            let Some(enabled) = value.as_bool() else {
+                bail!("normalize.{key} must be a boolean, got {value}");
+            };
+            match key.as_str() {
+                "orientation" => spec.orientation = enabled,
+                "srgb" => spec.srgb = enabled,
+                "strip_metadata" => spec.strip_metadata = enabled,
+                other => bail!(
+                    "unknown normalize option '{other}' (expected orientation, srgb or strip_metadata)"
+                ),
+            }
+        }
+        Ok(spec)
+    }
+
+    fn is_off(self) -> bool {
+        !(self.orientation || self.srgb || self.strip_metadata)
+    }
+
+    /// Normalizes the image at `path` in place, keeping its format. `None`
+    /// when the file needed nothing, is a vector or AVIF, or is disabled.
+    pub fn apply(self, path: &Path) -> Result<Option<NormalizeOutcome>> {
+        if self.is_off() || is_svg(path) {
+            return Ok(None);
+        }
+        let Some(format) = OutputFormat::sniff(path).filter(|format| *format != OutputFormat::Avif)
+        else {
+            return Ok(None);
+        };
+        let mut decoder = ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .with_context(|| format!("failed to open {}", path.display()))?
+            .into_decoder()
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let icc = decoder.icc_profile().ok().flatten();
+        let has_metadata = icc.is_some() || carries_metadata(&mut decoder);
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        let mut image = DynamicImage::from_decoder(decoder)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+
+        let mut metadata = Map::new();
+        if self.orientation && orientation != Orientation::NoTransforms {
+            image.apply_orientation(orientation);
+            metadata.insert("exif_orientation".to_string(), json!(orientation.to_exif()));
+        }
+        if self.srgb {
+            if let Some(profile) = icc.as_deref().and_then(wide_gamut_profile) {
+                image = to_srgb(&image, &profile)
+                    .with_context(|| format!("failed to convert {} to sRGB", path.display()))?;
+                metadata.insert("converted_to_srgb".to_string(), json!(true));
+            }
+        }
+        if metadata.is_empty() && !(self.strip_metadata && has_metadata) {
+            return Ok(None);
+        }
+        let bytes = encode_image(&image, format, LOCAL_JPEG_QUALITY)?;
+        let kept_metadata = ImageReader::new(Cursor::new(&bytes))
+            .with_guessed_format()
+            .map_err(image::ImageError::IoError)
+            .and_then(|reader| reader.into_decoder())
+            .is_ok_and(|mut decoder| {
+                decoder.icc_profile().ok().flatten().is_some() || carries_metadata(&mut decoder)
+            });
+        if has_metadata && !kept_metadata {
+            metadata.insert("metadata_stripped".to_string(), json!(true));
+        }
+        let mut warnings = Vec::new();
+        if format == OutputFormat::Jpeg {
+            warnings.push(Warning::new(
+                WarningCode::FormatConverted,
+                format!(
+                    "Normalized JPEG converted to JPEG locally (lossy re-encode at quality {LOCAL_JPEG_QUALITY})."
+                ),
+            ));
+        }
+        write_atomic(path, &bytes)?;
+        Ok(Some(NormalizeOutcome {
+            metadata,
+            dims: image.dimensions(),
+            warnings,
+        }))
+    }
+}
+
+/// Whether the file carries EXIF or XMP metadata.
+fn carries_metadata(decoder: &mut impl ImageDecoder) -> bool {
+    let present = |metadata: ImageResult<Option<Vec<u8>>>| metadata.ok().flatten().is_some();
+    present(decoder.exif_metadata()) || present(decoder.xmp_metadata())
+}
+
+/// The embedded RGB profile when its primaries are not sRGB's. Unreadable,
+/// grey and CMYK profiles are left alone.
+fn wide_gamut_profile(icc: &[u8]) -> Option<ColorProfile> {
+    let profile = ColorProfile::new_from_slice(icc).ok()?;
+    if profile.color_space != DataColorSpace::Rgb {
+        return None;
+    }
+    let srgb = ColorProfile::new_srgb();
+    let close = |a: moxcms::Xyzd, b: moxcms::Xyzd| {
+        (a.x - b.x).abs() <= SRGB_COLORANT_TOLERANCE
+            && (a.y - b.y).abs() <= SRGB_COLORANT_TOLERANCE
+            && (a.z - b.z).abs() <= SRGB_COLORANT_TOLERANCE
+    };
+    let is_srgb = close(profile.red_colorant, srgb.red_colorant)
+        && close(profile.green_colorant, srgb.green_colorant)
+        && close(profile.blue_colorant, srgb.blue_colorant);
+    (!is_srgb).then_some(profile)
+}
+
+fn to_srgb(image: &DynamicImage, profile: &ColorProfile) -> Result<DynamicImage> {
+    let srgb = ColorProfile::new_srgb();
+    let (width, height) = image.dimensions();
+    let layout = if image.color().has_alpha() {
+        Layout::Rgba
+    } else {
+        Layout::Rgb
+    };
+    let transform = profile
+        .create_transform_8bit(layout, &srgb, layout, TransformOptions::default())
+        .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+    let converted = match layout {
+        Layout::Rgba => {
+            let source = image.to_rgba8();
+            let mut target = vec![0u8; source.len()];
+            transform
+                .transform(&source, &mut target)
+                .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+            image::RgbaImage::from_raw(width, height, target).map(DynamicImage::ImageRgba8)
+        }
+        _ => {
+            let source = image.to_rgb8();
+            let mut target = vec![0u8; source.len()];
+            transform
+                .transform(&source, &mut target)
+                .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+            image::RgbImage::from_raw(width, height, target).map(DynamicImage::ImageRgb8)
+        }
+    };
+    converted.context("color transform returned a short buffer")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use image::codecs::jpeg::JpegEncoder;
+    use image::codecs::png::PngEncoder;
+    use image::{GenericImageView, ImageEncoder, Rgb, RgbImage};
+    use moxcms::ColorProfile;
+    use serde_json::{json, Map};
+
+    use super::NormalizeSpec;
+    use crate::map_object;
+    use crate::test_support::canned;
+
+    fn png_with_profile(image: &RgbImage, icc: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut encoder = PngEncoder::new(Cursor::new(&mut bytes));
+        encoder.set_icc_profile(icc)?;
+        encoder.write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            image::ExtendedColorType::Rgb8,
+        )?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn wide_gamut_profiles_convert_and_plain_files_stay_untouched() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let spec = NormalizeSpec::from_settings(&Map::new())?;
+
+        let plain = dir.path().join("plain.png");
+        let bytes = canned::png(4, 3);
+        fs::write(&plain, &bytes)?;
+        assert_eq!(spec.apply(&plain)?, None);
+        assert_eq!(fs::read(&plain)?, bytes);
+
+        // Pure P3 green lies outside sRGB, so it lands on a clipped, less
+        // saturated sRGB green.
+        let green = RgbImage::from_pixel(4, 3, Rgb([0, 255, 0]));
+        let p3 = dir.path().join("p3.png");
+        let p3_icc = ColorProfile::new_display_p3()
+            .encode()
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        fs::write(&p3, png_with_profile(&green, p3_icc)?)?;
+        let outcome = spec.apply(&p3)?.expect("P3 converts");
+        assert_eq!(
+            outcome.metadata,
+            map_object(json!({"converted_to_srgb": true, "metadata_stripped": true}))
+        );
+        let converted = image::open(&p3)?;
+        assert_eq!(converted.dimensions(), (4, 3));
+        let pixel = converted.to_rgb8().get_pixel(0, 0).0;
+        assert!(
+            pixel[0] < 10 && pixel[1] == 255 && pixel[2] < 10,
+            "{pixel:?}"
+        );
+
+        let srgb = dir.path().join("srgb.png");
+        let srgb_icc = ColorProfile::new_srgb()
+            .encode()
+            .map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        fs::write(&srgb, png_with_profile(&green, srgb_icc.clone())?)?;
+        assert_eq!(spec.apply(&srgb)?, None);
+        let strip = NormalizeSpec::from_settings(&map_object(
+            json!({"normalize": {"strip_metadata": true}}),
+        ))?;
+        let outcome = strip.apply(&srgb)?.expect("metadata stripped");
+        assert_eq!(
+            outcome.metadata,
+            map_object(json!({"metadata_stripped": true}))
+        );
+
+        let off = NormalizeSpec::from_settings(&map_object(json!({"normalize": false})))?;
+        fs::write(&p3, png_with_profile(&green, srgb_icc)?)?;
+        assert_eq!(off.apply(&p3)?, None);
+        assert!(
+            NormalizeSpec::from_settings(&map_object(json!({"normalize": {"gamma": true}})))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exif_rotation_is_baked_into_the_pixels() -> anyhow::Result<()> {
+        // A little-endian TIFF header with one IFD entry: Orientation = 6,
+        // "rotate 90 degrees clockwise to display".
+        let exif = [
+            b'I', b'I', 42, 0, 8, 0, 0, 0, 1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0,
+            0,
+        ];
+        let mut image = RgbImage::from_pixel(6, 4, Rgb([200, 200, 200]));
+        image.put_pixel(0, 0, Rgb([0, 0, 0]));
+        let mut bytes = Vec::new();
+        let mut encoder = JpegEncoder::new_with_quality(Cursor::new(&mut bytes), 100);
+        encoder.set_exif_metadata(exif.to_vec())?;
+        encoder.write_image(image.as_raw(), 6, 4, image::ExtendedColorType::Rgb8)?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rotated.jpg");
+        fs::write(&path, bytes)?;
+
+        let outcome = NormalizeSpec::default().apply(&path)?.expect("rotated");
+        assert_eq!(outcome.dims, (4, 6));
+        assert_eq!(
+            outcome.metadata,
+            map_object(json!({"exif_orientation": 6, "metadata_stripped": true}))
+        );
+        assert_eq!(outcome.warnings.len(), 1, "{:?}", outcome.warnings);
+        assert_eq!(
+            outcome.warnings[0].code,
+            brood_contracts::runs::warnings::WarningCode::FormatConverted
+        );
+        // The dark top-left corner moves to the top-right once rotated.
+        let rotated = image::open(&path)?.to_rgb8();
+        assert_eq!(rotated.dimensions(), (4, 6));
+        assert!(rotated.get_pixel(3, 0).0[0] < 100);
+        assert_eq!(NormalizeSpec::default().apply(&path)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,983 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use brood_contracts::models::OPENROUTER_MODEL_PREFIX;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use reqwest::blocking::Client as HttpClient;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Map, Value};
+
+use super::capabilities::{strings, ProviderCapabilities};
+use super::credentials::resolve_api_key;
+use super::download::Download;
+use super::replay::ReplaySend;
+use super::timeouts::scoped_http;
+use super::{
+    error_chain_text, is_retryable_transport_error, map_object, mime_for_path, non_empty_env,
+    normalize_openrouter_model_for_image_transport, openrouter_image_model_aliases,
+    output_extension_from_mime_or_format, parse_dims, push_unique_warning, response_json_or_error,
+    timestamp_millis, truncate_text, value_as_f64, FluxProvider, GeminiProvider, ImageBytes,
+    ImageProvider, ProviderGenerateRequest, ProviderGenerateResponse, ProviderImageResult,
+    ProviderSettings, BASE64,
+};
+
+/// OpenRouter's image generation, as a provider of its own and as the
+/// transport other providers fall back to when their key is missing.
+#[derive(Clone)]
+pub(crate) struct OpenRouterProvider {
+    api_base: String,
+    api_key_envs: Vec<String>,
+    http: HttpClient,
+}
+
+impl OpenRouterProvider {
+    pub(crate) fn new(settings: &ProviderSettings) -> Result<Self> {
+        // A bare host (`https://openrouter.ai`) gets the API path appended.
+        let mut api_base = settings.base_url.trim().trim_end_matches('/').to_string();
+        if let Ok(parsed) = reqwest::Url::parse(&api_base) {
+            if parsed.path().trim().is_empty() || parsed.path() == "/" {
+                api_base = format!("{api_base}/api/v1");
+            }
+        }
+        Ok(Self {
+            api_base,
+            api_key_envs: settings.api_key_envs.clone(),
+            http: settings.http_client()?,
+        })
+    }
+
+    pub(crate) fn api_key(&self) -> Option<String> {
+        resolve_api_key("openrouter", &self.api_key_envs)
+    }
+
+    pub(crate) fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
+    /// Why `label`'s requests go through `fallback`, or `None` when there is
+    /// no fallback or it has no key. Callers check their own key first.
+    pub(crate) fn fallback_reason(fallback: Option<&Self>, label: &str) -> Option<String> {
+        fallback?.api_key()?;
+        Some(format!(
+            "{label} API key missing; routed through OpenRouter."
+        ))
+    }
+
+    /// Serves `request` for `label` (whose own key is missing) through
+    /// OpenRouter, with its model mapped to an OpenRouter slug when
+    /// `default_model` is given. `None` without an OpenRouter key.
+    pub(crate) fn fallback_for(
+        &self,
+        label: &str,
+        request: &ProviderGenerateRequest,
+        default_model: Option<&str>,
+    ) -> Option<Result<ProviderGenerateResponse>> {
+        let api_key = self.api_key()?;
+        let mut routed = request.clone();
+        if let Some(default_model) = default_model {
+            routed.model =
+                normalize_openrouter_model_for_image_transport(&routed.model, default_model);
+        }
+        Some(
+            self.generate_with_key(&routed, &api_key)
+                .with_context(|| format!("{label} OpenRouter fallback failed"))
+                .map(|mut response| {
+                    response.warnings.insert(
+                        0,
+                        Warning::new(
+                            WarningCode::TransportFallback,
+                            format!("{label} API key missing; used OpenRouter image transport."),
+                        ),
+                    );
+                    response
+                }),
+        )
+    }
+
+    /// `provider` routing preferences and usage reporting for each payload.
+    /// `provider_options.openrouter_provider` is sent as given;
+    /// `provider_order` and `allow_fallbacks` fill in its `order` and
+    /// `allow_fallbacks`.
+    fn routing_fields(request: &ProviderGenerateRequest) -> Result<Map<String, Value>> {
+        let options = &request.provider_options;
+        let mut preferences = match options.get("openrouter_provider") {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(preferences)) => preferences.clone(),
+            Some(_) => bail!("provider_options.openrouter_provider must be an object"),
+        };
+        match options.get("provider_order") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(order)) if order.iter().all(Value::is_string) => {
+                preferences.insert("order".to_string(), Value::Array(order.clone()));
+            }
+            Some(_) => bail!("provider_options.provider_order must be a list of provider names"),
+        }
+        match options.get("allow_fallbacks") {
+            None | Some(Value::Null) => {}
+            Some(Value::Bool(allow)) => {
+                preferences.insert("allow_fallbacks".to_string(), Value::Bool(*allow));
+            }
+            Some(_) => bail!("provider_options.allow_fallbacks must be true or false"),
+        }
+        let mut fields = map_object(json!({ "usage": { "include": true } }));
+        if !preferences.is_empty() {
+            fields.insert("provider".to_string(), Value::Object(preferences));
+        }
+        Ok(fields)
+    }
+
+    pub(crate) fn map_flux_model(model: &str) -> Option<&'static str> {
+        match model.trim().to_ascii_lowercase().as_str() {
+            "flux-2" | "flux-2-flex" | "flux-2-pro" | "flux-2-max" | "flux-klein"
+            | "flux-klein-pro" | "flux-klein-max" => Some("black-forest-labs/flux-1.1-pro"),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn model_candidates(
+        request: &ProviderGenerateRequest,
+        warnings: &mut Vec<Warning>,
+    ) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::new();
+        let push_model = |value: &str, out: &mut Vec<String>| {
+            let trimmed = value.trim();
+            if trimmed.is_empty() {
+                return;
+            }
+            if out.iter().any(|existing| existing == trimmed) {
+                return;
+            }
+            out.push(trimmed.to_string());
+        };
+        if let Some(explicit) = request
+            .provider_options
+            .get("openrouter_model")
+            .or_else(|| request.provider_options.get("responses_model"))
+            .or_else(|| request.provider_options.get("openai_responses_model"))
+            .and_then(Value::as_str)
+        {
+            let normalized = normalize_openrouter_model_for_image_transport(explicit, explicit);
+            push_model(&normalized, &mut candidates);
+            for alias in openrouter_image_model_aliases(&normalized) {
+                push_model(&alias, &mut candidates);
+            }
+            push_model(explicit, &mut candidates);
+            if normalized != explicit.trim() {
+                push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ModelRemapped,
+                        format!(
+                            "OpenRouter model '{}' normalized to '{}'.",
+                            explicit.trim(),
+                            normalized
+                        ),
+                    )
+                    .parameter("model")
+                    .requested(explicit.trim())
+                    .applied(&normalized),
+                );
+            }
+        }
+        // `openrouter/<slug>` pins the slug; its aliases only back it up.
+        if let Some(slug) = request.model.trim().strip_prefix(OPENROUTER_MODEL_PREFIX) {
+            push_model(slug, &mut candidates);
+            for alias in openrouter_image_model_aliases(slug) {
+                push_model(&alias, &mut candidates);
+            }
+            return candidates;
+        }
+        let normalized_request_model =
+            normalize_openrouter_model_for_image_transport(&request.model, "openai/gpt-image-1");
+        if normalized_request_model != request.model.trim() {
+            push_unique_warning(
+                warnings,
+                Warning::new(
+                    WarningCode::ModelRemapped,
+                    format!(
+                        "Model '{}' normalized to '{}' for OpenRouter transport.",
+                        request.model.trim(),
+                        normalized_request_model
+                    ),
+                )
+                .parameter("model")
+                .requested(request.model.trim())
+                .applied(&normalized_request_model),
+            );
+        }
+        push_model(&normalized_request_model, &mut candidates);
+        for alias in openrouter_image_model_aliases(&normalized_request_model) {
+            push_model(&alias, &mut candidates);
+        }
+        push_model(&request.model, &mut candidates);
+        if let Some(mapped) = Self::map_flux_model(&request.model) {
+            if !candidates.iter().any(|existing| existing == mapped) {
+                push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ModelRemapped,
+                        format!(
+                        "Flux model '{}' mapped to OpenRouter model '{}' for OpenRouter transport.",
+                        request.model, mapped
+                    ),
+                    )
+                    .parameter("model")
+                    .requested(&request.model)
+                    .applied(mapped),
+                );
+                candidates.push(mapped.to_string());
+            }
+        }
+        if candidates.is_empty() {
+            candidates.push("black-forest-labs/flux-1.1-pro".to_string());
+        }
+        candidates
+    }
+
+    fn aspect_ratio(size: &str) -> String {
+        let (width, height) = parse_dims(size);
+        if width == 0 || height == 0 {
+            return "1:1".to_string();
+        }
+        let ratio = width as f64 / height as f64;
+        let candidates = [
+            ("1:1", 1.0),
+            ("16:9", 16.0 / 9.0),
+            ("9:16", 9.0 / 16.0),
+            ("4:3", 4.0 / 3.0),
+            ("3:4", 3.0 / 4.0),
+            ("3:2", 3.0 / 2.0),
+            ("2:3", 2.0 / 3.0),
+            ("5:4", 5.0 / 4.0),
+            ("4:5", 4.0 / 5.0),
+            ("21:9", 21.0 / 9.0),
+        ];
+        let mut best = "1:1";
+        let mut best_delta = f64::MAX;
+        for (label, value) in candidates {
+            let delta = (ratio - value).abs();
+            if delta < best_delta {
+                best_delta = delta;
+                best = label;
+            }
+        }
+        best.to_string()
+    }
+
+    fn supports_image_size(model: &str) -> bool {
+        let normalized = model.trim().to_ascii_lowercase();
+        normalized.contains("gemini") || normalized.contains("imagen")
+    }
+
+    fn image_size_hint(request: &ProviderGenerateRequest) -> String {
+        let from_options = request
+            .provider_options
+            .get("image_size")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_ascii_uppercase);
+        if let Some(value) = from_options {
+            if value == "1K" || value == "2K" || value == "4K" {
+                return value;
+            }
+        }
+        GeminiProvider::resolve_image_size_hint(&request.size)
+    }
+
+    fn input_image_url(value: &str) -> Result<String> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            bail!("OpenRouter image input value is empty");
+        }
+        let lowered = trimmed.to_ascii_lowercase();
+        if lowered.starts_with("http://")
+            || lowered.starts_with("https://")
+            || lowered.starts_with("data:image/")
+        {
+            return Ok(trimmed.to_string());
+        }
+        let path = PathBuf::from(trimmed);
+        if path.exists() && path.is_file() {
+            let bytes =
+                fs::read(&path).with_context(|| format!("failed reading {}", path.display()))?;
+            let mime = mime_for_path(&path).unwrap_or("image/png");
+            return Ok(format!("data:{mime};base64,{}", BASE64.encode(bytes)));
+        }
+        if BASE64.decode(trimmed.as_bytes()).is_ok() {
+            return Ok(format!("data:image/png;base64,{trimmed}"));
+        }
+        bail!(
+            "OpenRouter image input '{}' must be a URL, data URL, local file path, or base64 image bytes",
+            truncate_text(trimmed, 80)
+        );
+    }
+
+    fn build_input_content(
+        request: &ProviderGenerateRequest,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<Vec<Value>> {
+        let mut content = vec![json!({
+            "type": "input_text",
+            "text": request.prompt,
+        })];
+        if let Some(init_image) = request.inputs.init_image.as_ref() {
+            match Self::input_image_url(init_image) {
+                Ok(image_url) => {
+                    content.push(json!({
+                        "type": "input_image",
+                        "image_url": image_url,
+                    }));
+                }
+                Err(err) => push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ValueDropped,
+                        format!(
+                            "OpenRouter dropped init_image input: {}",
+                            truncate_text(&err.to_string(), 220)
+                        ),
+                    )
+                    .parameter("init_image"),
+                ),
+            }
+        }
+        for (idx, reference) in request.inputs.reference_images.iter().enumerate() {
+            match Self::input_image_url(reference) {
+                Ok(image_url) => {
+                    content.push(json!({
+                        "type": "input_image",
+                        "image_url": image_url,
+                    }));
+                }
+                Err(err) => push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ValueDropped,
+                        format!(
+                            "OpenRouter dropped reference_images[{}]: {}",
+                            idx,
+                            truncate_text(&err.to_string(), 220)
+                        ),
+                    )
+                    .parameter(format!("reference_images[{idx}]")),
+                ),
+            }
+        }
+        if request.inputs.mask.is_some() {
+            push_unique_warning(
+                warnings,
+                Warning::new(
+                    WarningCode::ValueDropped,
+                    "OpenRouter image generation ignores mask input.",
+                )
+                .parameter("mask"),
+            );
+        }
+        Ok(content)
+    }
+
+    pub(crate) fn apply_request_headers(
+        mut request: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        if let Some(referer) = non_empty_env("OPENROUTER_HTTP_REFERER")
+            .or_else(|| non_empty_env("BROOD_OPENROUTER_HTTP_REFERER"))
+        {
+            request = request.header("HTTP-Referer", referer);
+        }
+        if let Some(title) = non_empty_env("OPENROUTER_X_TITLE")
+            .or_else(|| non_empty_env("BROOD_OPENROUTER_X_TITLE"))
+        {
+            request = request.header("X-Title", title);
+        }
+        request
+    }
+
+    fn should_fallback_to_chat(status_code: u16, body: &str) -> bool {
+        if matches!(status_code, 404 | 405 | 415 | 501) {
+            return true;
+        }
+        if matches!(status_code, 400 | 422) {
+            let lowered = body.to_ascii_lowercase();
+            return lowered.contains("response")
+                && (lowered.contains("unsupported")
+                    || lowered.contains("not supported")
+                    || lowered.contains("not found")
+                    || lowered.contains("unknown")
+                    || lowered.contains("does not exist")
+                    || lowered.contains("unavailable"));
+        }
+        false
+    }
+
+    pub(crate) fn should_fallback_to_chat_after_decode_error(err: &anyhow::Error) -> bool {
+        if is_retryable_transport_error(err) {
+            return true;
+        }
+        let lowered = error_chain_text(err, 480).to_ascii_lowercase();
+        lowered.contains("response body read failed")
+            || lowered.contains("returned invalid json payload")
+    }
+
+    fn transport_retry_count(request: &ProviderGenerateRequest) -> usize {
+        let retries_value = request
+            .provider_options
+            .get("transport_retries")
+            .or_else(|| request.provider_options.get("request_retries"));
+        value_as_f64(retries_value, 2.0, 0.0, 4.0).round() as usize
+    }
+
+    fn retry_backoff_seconds(request: &ProviderGenerateRequest) -> f64 {
+        value_as_f64(
+            request.provider_options.get("retry_backoff"),
+            1.0,
+            0.1,
+            10.0,
+        )
+    }
+
+    fn extract_chat_finish_reason(payload: &Value) -> Option<String> {
+        payload
+            .get("choices")
+            .and_then(Value::as_array)
+            .and_then(|rows| rows.first())
+            .and_then(Value::as_object)
+            .and_then(|row| row.get("finish_reason").and_then(Value::as_str))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    }
+
+    pub(crate) fn extract_generated_images(
+        &self,
+        payload: &Value,
+        download_timeout_s: f64,
+    ) -> Result<Vec<ImageBytes>> {
+        fn collect(value: &Value, key_hint: Option<&str>, out: &mut Vec<String>) {
+            match value {
+                Value::Object(obj) => {
+                    for (key, nested) in obj {
+                        collect(nested, Some(key), out);
+                    }
+                }
+                Value::Array(items) => {
+                    for item in items {
+                        collect(item, key_hint, out);
+                    }
+                }
+                Value::String(raw) => {
+                    let trimmed = raw.trim();
+                    if trimmed.is_empty() {
+                        return;
+                    }
+                    let key = key_hint
+                        .map(|value| value.trim().to_ascii_lowercase())
+                        .unwrap_or_default();
+                    let looks_http =
+                        trimmed.starts_with("http://") || trimmed.starts_with("https://");
+                    let looks_data_url = trimmed.starts_with("data:image/");
+                    let looks_b64_key =
+                        key.contains("b64") || key.contains("base64") || key == "result";
+                    let looks_url_key = key == "url"
+                        || key.ends_with("_url")
+                        || key.ends_with("url")
+                        || key.contains("image_url");
+                    if (looks_data_url || (looks_http && looks_url_key) || looks_b64_key)
+                        && !out.iter().any(|existing| existing == trimmed)
+                    {
+                        out.push(trimmed.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        fn decode_data_url(value: &str) -> Result<ImageBytes> {
+            let (meta, payload) = value
+                .split_once(',')
+                .ok_or_else(|| anyhow::anyhow!("invalid data URL image payload"))?;
+            let mime = meta
+                .trim()
+                .strip_prefix("data:")
+                .and_then(|rest| rest.split(';').next())
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .unwrap_or("image/png")
+                .to_string();
+            let bytes = BASE64
+                .decode(payload.trim().as_bytes())
+                .context("OpenRouter image data URL base64 decode failed")?;
+            Ok(ImageBytes::Inline {
+                bytes,
+                mime_type: Some(mime),
+            })
+        }
+
+        let mut candidates: Vec<String> = Vec::new();
+        collect(payload, None, &mut candidates);
+        let mut out: Vec<ImageBytes> = Vec::new();
+        for candidate in candidates {
+            let trimmed = candidate.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with("data:image/") {
+                if let Ok(image) = decode_data_url(trimmed) {
+                    out.push(image);
+                }
+                continue;
+            }
+            if trimmed.starts_with("http://") || trimmed.starts_with("https://") {
+                if let Ok(image) = self.download_image(trimmed, download_timeout_s) {
+                    out.push(image);
+                }
+                continue;
+            }
+            if let Ok(bytes) = BASE64.decode(trimmed.as_bytes()) {
+                out.push(ImageBytes::Inline {
+                    bytes,
+                    mime_type: None,
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    fn download_image(&self, url: &str, timeout_s: f64) -> Result<ImageBytes> {
+        let response = scoped_http(&self.http)
+            .get(url)
+            .timeout(Duration::from_secs_f64(timeout_s))
+            .send_replayable()
+            .with_context(|| format!("OpenRouter image download failed ({url})"))?;
+        Ok(ImageBytes::Download(Download::start(
+            response,
+            "OpenRouter image",
+        )?))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn request_image_generation(
+        &self,
+        request: &ProviderGenerateRequest,
+        model: &str,
+        input_content: &[Value],
+        seed: Option<i64>,
+        aspect_ratio: &str,
+        api_key: &str,
+        request_timeout: f64,
+        download_timeout: f64,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(String, Value, Value, Vec<ImageBytes>)> {
+        let max_retries = Self::transport_retry_count(request);
+        let retry_backoff_s = Self::retry_backoff_seconds(request);
+        let base = &self.api_base;
+        let routing = Self::routing_fields(request)?;
+        let responses_endpoint = format!("{base}/responses");
+        let responses_payload = {
+            let mut image_config = map_object(json!({
+                "aspect_ratio": aspect_ratio,
+            }));
+            if Self::supports_image_size(model) {
+                image_config.insert(
+                    "image_size".to_string(),
+                    Value::String(Self::image_size_hint(request)),
+                );
+            }
+            let mut payload = map_object(json!({
+                "model": model,
+                "input": [{
+                    "role": "user",
+                    "content": input_content,
+                }],
+                "modalities": ["text", "image"],
+                "stream": false,
+                "image_config": image_config,
+            }));
+            if let Some(seed_value) = seed {
+                payload.insert("seed".to_string(), Value::Number(seed_value.into()));
+            }
+            payload.extend(routing.clone());
+            Value::Object(payload)
+        };
+        for attempt in 0..=max_retries {
+            let responses_request = scoped_http(&self.http)
+                .post(&responses_endpoint)
+                .bearer_auth(api_key)
+                .header("accept", "application/json")
+                .header(CONTENT_TYPE, "application/json")
+                .timeout(Duration::from_secs_f64(request_timeout));
+            let responses_response = match Self::apply_request_headers(responses_request)
+                .json(&responses_payload)
+                .send_replayable()
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    let err = err.context(format!(
+                        "OpenRouter responses request failed ({responses_endpoint})"
+                    ));
+                    if !is_retryable_transport_error(&err) {
+                        return Err(err);
+                    }
+                    if attempt < max_retries {
+                        push_unique_warning(
+                            warnings, Warning::new(WarningCode::TransportRetry, format!(
+                                "OpenRouter responses transport retry {}/{} after transient request failure.",
+                                attempt + 1,
+                                max_retries
+                            )),
+                        );
+                        let delay_s = retry_backoff_s * (attempt as f64 + 1.0);
+                        thread::sleep(Duration::from_secs_f64(delay_s));
+                        continue;
+                    }
+                    push_unique_warning(
+                        warnings, Warning::new(WarningCode::TransportFallback, format!(
+                            "OpenRouter responses transport failed after retries; falling back to chat/completions ({})",
+                            truncate_text(&error_chain_text(&err, 220), 220)
+                        )),
+                    );
+                    break;
+                }
+            };
+            if responses_response.status().is_success() {
+                match response_json_or_error("OpenRouter responses", responses_response) {
+                    Ok(response_payload) => {
+                        let images =
+                            self.extract_generated_images(&response_payload, download_timeout)?;
+                        if !images.is_empty() {
+                            return Ok((
+                                "openrouter_responses".to_string(),
+                                responses_payload,
+                                response_payload,
+                                images,
+                            ));
+                        }
+                        break;
+                    }
+                    Err(err) => {
+                        if !Self::should_fallback_to_chat_after_decode_error(&err) {
+                            return Err(err);
+                        }
+                        if is_retryable_transport_error(&err) && attempt < max_retries {
+                            push_unique_warning(
+                                warnings, Warning::new(WarningCode::TransportRetry, format!(
+                                    "OpenRouter responses decode retry {}/{} after transient body failure.",
+                                    attempt + 1,
+                                    max_retries
+                                )),
+                            );
+                            let delay_s = retry_backoff_s * (attempt as f64 + 1.0);
+                            thread::sleep(Duration::from_secs_f64(delay_s));
+                            continue;
+                        }
+                        push_unique_warning(
+                            warnings, Warning::new(WarningCode::TransportFallback, format!(
+                                "OpenRouter responses payload decode failed; falling back to chat/completions ({})",
+                                truncate_text(&error_chain_text(&err, 220), 220)
+                            )),
+                        );
+                        break;
+                    }
+                }
+            } else {
+                let code = responses_response.status().as_u16();
+                let body = responses_response.text().unwrap_or_default();
+                if !Self::should_fallback_to_chat(code, &body) {
+                    bail!(
+                        "OpenRouter responses request failed ({code}): {}",
+                        truncate_text(&body, 512)
+                    );
+                }
+                break;
+            }
+        }
+
+        let chat_endpoint = format!("{base}/chat/completions");
+        let mut chat_content = Vec::new();
+        for item in input_content {
+            let Some(obj) = item.as_object() else {
+                continue;
+            };
+            let kind = obj
+                .get("type")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            if kind == "input_text" {
+                if let Some(text) = obj
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                {
+                    chat_content.push(json!({
+                        "type": "text",
+                        "text": text,
+                    }));
+                }
+            } else if kind == "input_image" {
+                let maybe_url = obj
+                    .get("image_url")
+                    .and_then(Value::as_str)
+                    .or_else(|| {
+                        obj.get("image_url")
+                            .and_then(Value::as_object)
+                            .and_then(|row| row.get("url"))
+                            .and_then(Value::as_str)
+                    })
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty());
+                if let Some(url) = maybe_url {
+                    chat_content.push(json!({
+                        "type": "image_url",
+                        "image_url": { "url": url }
+                    }));
+                }
+            }
+        }
+        let chat_payload = {
+            let mut image_config = map_object(json!({
+                "aspect_ratio": aspect_ratio,
+            }));
+            if Self::supports_image_size(model) {
+                image_config.insert(
+                    "image_size".to_string(),
+                    Value::String(Self::image_size_hint(request)),
+                );
+            }
+            let mut payload = map_object(json!({
+                "model": model,
+                "messages": [{
+                    "role": "user",
+                    "content": chat_content,
+                }],
+                "modalities": ["text", "image"],
+                "stream": false,
+                "image_config": image_config,
+            }));
+            if let Some(seed_value) = seed {
+                payload.insert("seed".to_string(), Value::Number(seed_value.into()));
+            }
+            payload.extend(routing);
+            Value::Object(payload)
+        };
+        for attempt in 0..=max_retries {
+            let chat_request = scoped_http(&self.http)
+                .post(&chat_endpoint)
+                .bearer_auth(api_key)
+                .header("accept", "application/json")
+                .header(CONTENT_TYPE, "application/json")
+                .timeout(Duration::from_secs_f64(request_timeout));
+            let chat_response = match Self::apply_request_headers(chat_request)
+                .json(&chat_payload)
+                .send_replayable()
+            {
+                Ok(response) => response,
+                Err(err) => {
+                    let err =
+                        err.context(format!("OpenRouter chat request failed ({chat_endpoint})"));
+                    if is_retryable_transport_error(&err) && attempt < max_retries {
+                        push_unique_warning(
+                            warnings, Warning::new(WarningCode::TransportRetry, format!(
+                                "OpenRouter chat transport retry {}/{} after transient request failure.",
+                                attempt + 1,
+                                max_retries
+                            )),
+                        );
+                        let delay_s = retry_backoff_s * (attempt as f64 + 1.0);
+                        thread::sleep(Duration::from_secs_f64(delay_s));
+                        continue;
+                    }
+                    return Err(err);
+                }
+            };
+            let chat_payload_response =
+                match response_json_or_error("OpenRouter chat", chat_response) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        if Self::should_fallback_to_chat_after_decode_error(&err)
+                            && attempt < max_retries
+                        {
+                            push_unique_warning(
+                                warnings,
+                                Warning::new(
+                                    WarningCode::TransportRetry,
+                                    format!(
+                                "OpenRouter chat decode retry {}/{} after transient body failure.",
+                                attempt + 1,
+                                max_retries
+                            ),
+                                ),
+                            );
+                            let delay_s = retry_backoff_s * (attempt as f64 + 1.0);
+                            thread::sleep(Duration::from_secs_f64(delay_s));
+                            continue;
+                        }
+                        return Err(err);
+                    }
+                };
+            let images = self.extract_generated_images(&chat_payload_response, download_timeout)?;
+            if images.is_empty() {
+                let finish = Self::extract_chat_finish_reason(&chat_payload_response)
+                    .unwrap_or_else(|| "unknown".to_string());
+                bail!(
+                    "OpenRouter chat image response returned no image payload (finish_reason={finish})"
+                );
+            }
+            return Ok((
+                "openrouter_chat_completions".to_string(),
+                chat_payload,
+                chat_payload_response,
+                images,
+            ));
+        }
+        unreachable!("OpenRouter chat retry loop should always return a response or error")
+    }
+
+    pub(crate) fn generate_with_key(
+        &self,
+        request: &ProviderGenerateRequest,
+        api_key: &str,
+    ) -> Result<ProviderGenerateResponse> {
+        let (_poll_interval, _poll_timeout, request_timeout, download_timeout) =
+            FluxProvider::request_timeouts(request);
+        let mut warnings = Vec::new();
+        let candidates = Self::model_candidates(request, &mut warnings);
+        let (width, height) = parse_dims(&request.size);
+        let stamp = timestamp_millis();
+        let aspect_ratio = Self::aspect_ratio(&request.size);
+        let input_content = Self::build_input_content(request, &mut warnings)?;
+
+        let mut request_manifests: Vec<Value> = Vec::new();
+        let mut response_manifests: Vec<Value> = Vec::new();
+        let mut results = Vec::new();
+
+        for idx in 0..request.n.max(1) {
+            let seed = request.seed.map(|value| value.saturating_add(idx as i64));
+            let mut last_error: Option<anyhow::Error> = None;
+            let mut generated: Option<(String, Value, Value, Vec<ImageBytes>)> = None;
+            for model in &candidates {
+                match self.request_image_generation(
+                    request,
+                    model,
+                    &input_content,
+                    seed,
+                    &aspect_ratio,
+                    api_key,
+                    request_timeout,
+                    download_timeout,
+                    &mut warnings,
+                ) {
+                    Ok(tuple) => {
+                        generated = Some(tuple);
+                        break;
+                    }
+                    Err(err) => {
+                        last_error = Some(err);
+                    }
+                }
+            }
+            let Some((transport, request_payload, response_payload, images)) = generated else {
+                let message = last_error
+                    .as_ref()
+                    .map(|err| err.to_string())
+                    .unwrap_or_else(|| "OpenRouter request failed".to_string());
+                bail!("OpenRouter image fallback failed: {message}");
+            };
+            let first = images
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("OpenRouter returned no image bytes"))?;
+            let ext = output_extension_from_mime_or_format(
+                first.mime_type().as_deref(),
+                &request.output_format,
+            );
+            let image_path = request
+                .run_dir
+                .join(format!("artifact-{}-{:02}.{}", stamp, idx, ext));
+            let saved = first.save(&image_path)?;
+            results.push(ProviderImageResult {
+                image_path,
+                width,
+                height,
+                seed,
+                sha256: Some(saved.sha256),
+            });
+            request_manifests.push(json!({
+                "transport": transport,
+                "payload": request_payload,
+            }));
+            response_manifests.push(json!({
+                "transport": transport,
+                "response_id": response_payload.get("id").cloned().unwrap_or(Value::Null),
+                "status": response_payload.get("status").cloned().unwrap_or(Value::Null),
+                "usage": response_payload.get("usage").cloned().unwrap_or(Value::Null),
+            }));
+        }
+
+        Ok(ProviderGenerateResponse {
+            provider_request: map_object(json!({
+                "endpoint": format!("{}/responses", self.api_base),
+                "payload": if request_manifests.len() == 1 {
+                    request_manifests.first().cloned().unwrap_or(Value::Null)
+                } else {
+                    Value::Array(request_manifests)
+                },
+            })),
+            provider_response: map_object(json!({
+                "usage": total_usage(&response_manifests),
+                "responses": response_manifests,
+            })),
+            warnings,
+            results,
+        })
+    }
+}
+
+/// Token counts and billed `cost` (USD) summed over `responses`; `cost` is
+/// omitted unless every response reported one.
+fn total_usage(responses: &[Value]) -> Value {
+    let mut total = Map::new();
+    for key in ["prompt_tokens", "completion_tokens", "total_tokens"] {
+        let sum: u64 = responses
+            .iter()
+            .filter_map(|response| response["usage"][key].as_u64())
+            .sum();
+        total.insert(key.to_string(), json!(sum));
+    }
+    let cost: Option<f64> = responses
+        .iter()
+        .map(|response| response["usage"]["cost"].as_f64())
+        .sum();
+    if let Some(cost) = cost.filter(|_| !responses.is_empty()) {
+        total.insert("cost".to_string(), json!(cost));
+    }
+    Value::Object(total)
+}
+
+impl ImageProvider for OpenRouterProvider {
+    fn name(&self) -> &str {
+        "openrouter"
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            supports_mask: false,
+            deterministic: false,
+            supported_output_formats: strings(&["png", "jpg", "webp"]),
+            ..ProviderCapabilities::default()
+        }
+    }
+
+    fn generate(&self, request: &ProviderGenerateRequest) -> Result<ProviderGenerateResponse> {
+        let Some(api_key) = self.api_key() else {
+            bail!("OPENROUTER_API_KEY not set");
+        };
+        self.generate_with_key(request, &api_key)
+    }
+}
@@ -0,0 +1,409 @@
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat};
+use serde_json::{json, Map, Value};
+
+/// MIME type recorded for vector (SVG) artifacts.
+pub const SVG_MIME: &str = "image/svg+xml";
+/// JPEG quality for local re-encodes.
+pub const LOCAL_JPEG_QUALITY: u8 = 92;
+/// AVIF quality (0-100) for local re-encodes.
+pub const LOCAL_AVIF_QUALITY: u8 = 80;
+/// ravif speed (1 slowest/best .. 10 fastest).
+const LOCAL_AVIF_SPEED: u8 = 6;
+/// Longest edge of the WebP thumbnail written next to each image artifact.
+pub const THUMBNAIL_EDGE: u32 = 256;
+
+/// Artifact file formats the engine can guarantee by re-encoding locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    /// Accepts `png`, `jpg`/`jpeg`, `webp`, `avif` and `image/<format>`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let lowered = text.trim().to_ascii_lowercase();
+        match lowered.strip_prefix("image/").unwrap_or(&lowered) {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        Self::parse(path.extension()?.to_str()?)
+    }
+
+    /// Format of the encoded bytes, regardless of the file extension.
+    pub(crate) fn sniff(path: &Path) -> Option<Self> {
+        let mut head = Vec::with_capacity(64);
+        fs::File::open(path)
+            .ok()?
+            .take(64)
+            .read_to_end(&mut head)
+            .ok()?;
+        match image::guess_format(&head).ok()? {
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::WebP => Some(Self::Webp),
+            ImageFormat::Avif => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// Whether encoding to this format discards detail. The local WebP
+    /// encoder is lossless.
+    pub fn is_lossy(self) -> bool {
+        matches!(self, Self::Jpeg | Self::Avif)
+    }
+
+    /// What to ask providers for. None of them emit AVIF, so they are asked
+    /// for lossless PNG and the engine encodes the AVIF itself.
+    pub(crate) fn provider_request_format(self) -> Self {
+        match self {
+            Self::Avif => Self::Png,
+            other => other,
+        }
+    }
+}
+
+/// Whether `bytes` start an SVG document, optionally behind an XML prolog
+/// or comments.
+pub(crate) fn is_svg_document(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    head.starts_with('<') && head.contains("<svg")
+}
+
+/// Whether the file at `path` is an SVG document. Vector artifacts skip
+/// every step that decodes pixels.
+pub(crate) fn is_svg(path: &Path) -> bool {
+    let mut head = Vec::with_capacity(1024);
+    fs::File::open(path)
+        .and_then(|file| file.take(1024).read_to_end(&mut head))
+        .is_ok_and(|_| is_svg_document(&head))
+}
+
+/// MIME type of the artifact at `path`, by content.
+pub(crate) fn artifact_mime(path: &Path) -> Option<&'static str> {
+    match OutputFormat::sniff(path) {
+        Some(format) => Some(format.mime()),
+        None => is_svg(path).then_some(SVG_MIME),
+    }
+}
+
+/// Intrinsic size of an SVG document from the root element's
+/// `width`/`height` (unitless or `px`), falling back to its `viewBox`.
+pub(crate) fn svg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let text = String::from_utf8_lossy(bytes);
+    let start = text.find("<svg")?;
+    let root = &text[start..start + text[start..].find('>')?];
+    let length = |name: &str| {
+        svg_attribute(root, name)?
+            .trim()
+            .trim_end_matches("px")
+            .parse::<f64>()
+            .ok()
+            .filter(|value| *value >= 1.0)
+            .map(|value| value.round() as u32)
+    };
+    if let (Some(width), Some(height)) = (length("width"), length("height")) {
+        return Some((width, height));
+    }
+    let view_box: Vec<f64> = svg_attribute(root, "viewBox")?
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    match view_box.as_slice() {
+        [_, _, width, height] if *width >= 1.0 && *height >= 1.0 => {
+            Some((width.round() as u32, height.round() as u32))
+        }
+        _ => None,
+    }
+}
+
+fn svg_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(idx) = rest.find(name) {
+        let preceded_by_space = rest[..idx]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        let after = rest[idx + name.len()..].trim_start();
+        if let (true, Some(value)) = (preceded_by_space, after.strip_prefix('=')) {
+            let value = value.trim_start();
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value = &value[1..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+        rest = &rest[idx + name.len()..];
+    }
+    None
+}
+
+/// Decodes by content rather than extension; providers sometimes write
+/// bytes under the wrong extension.
+pub(crate) fn open_image(path: &Path) -> Result<DynamicImage> {
+    image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .decode()
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+pub(crate) fn encode_image(
+    image: &DynamicImage,
+    format: OutputFormat,
+    jpeg_quality: u8,
+) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match format {
+        OutputFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut buffer, jpeg_quality)
+                .encode_image(&image.to_rgb8())?;
+        }
+        OutputFormat::Png => image.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)?,
+        // The WebP and AVIF encoders only take 8-bit RGB(A).
+        OutputFormat::Webp => {
+            rgb8_or_rgba8(image).write_to(&mut Cursor::new(&mut buffer), ImageFormat::WebP)?
+        }
+        OutputFormat::Avif => rgb8_or_rgba8(image).write_with_encoder(
+            AvifEncoder::new_with_speed_quality(&mut buffer, LOCAL_AVIF_SPEED, LOCAL_AVIF_QUALITY),
+        )?,
+    }
+    Ok(buffer)
+}
+
+/// Writes `<stem>-thumb.webp` next to `path`, at most [`THUMBNAIL_EDGE`]
+/// on its longest side, so grid views need not decode the full image.
+pub(crate) fn write_thumbnail(path: &Path) -> Result<PathBuf> {
+    let image = open_image(path)?;
+    let thumbnail = if image.width().max(image.height()) > THUMBNAIL_EDGE {
+        image.thumbnail(THUMBNAIL_EDGE, THUMBNAIL_EDGE)
+    } else {
+        image
+    };
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let out_path = path.with_file_name(format!("{stem}-thumb.webp"));
+    write_atomic(
+        &out_path,
+        &encode_image(&thumbnail, OutputFormat::Webp, LOCAL_JPEG_QUALITY)?,
+    )?;
+    Ok(out_path)
+}
+
+fn rgb8_or_rgba8(image: &DynamicImage) -> DynamicImage {
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    }
+}
+
+/// Result of making an artifact match the requested `output_format`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FormatConformance {
+    pub(crate) image_path: PathBuf,
+    /// `{from, to, lossy}` when the image was re-encoded.
+    pub(crate) conversion: Option<Map<String, Value>>,
+    pub(crate) warning: Option<Warning>,
+}
+
+/// Re-encodes `path` to `target` when its bytes are in another format and
+/// fixes the extension when only the name is wrong. Files whose format
+/// cannot be sniffed are left alone.
+pub(crate) fn conform_output_format(
+    path: &Path,
+    target: OutputFormat,
+) -> Result<FormatConformance> {
+    let out_path = path.with_extension(target.extension());
+    let unchanged = FormatConformance {
+        image_path: path.to_path_buf(),
+        conversion: None,
+        warning: None,
+    };
+    let Some(actual) = OutputFormat::sniff(path) else {
+        return Ok(unchanged);
+    };
+    if actual == target {
+        if OutputFormat::from_path(path) != Some(target) {
+            fs::rename(path, &out_path)
+                .with_context(|| format!("failed to rename {}", path.display()))?;
+            return Ok(FormatConformance {
+                image_path: out_path,
+                ..unchanged
+            });
+        }
+        return Ok(unchanged);
+    }
+
+    let image = open_image(path)?;
+    let encoded = encode_image(&image, target, LOCAL_JPEG_QUALITY)?;
+    write_atomic(&out_path, &encoded)?;
+    if out_path != path {
+        fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    let lossy = target.is_lossy();
+    Ok(FormatConformance {
+        image_path: out_path,
+        conversion: Some(
+            json!({ "from": actual.extension(), "to": target.extension(), "lossy": lossy })
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+        ),
+        warning: lossy.then(|| {
+            Warning::new(
+                WarningCode::FormatConverted,
+                format!(
+                    "Output format {} converted to {} locally (lossy re-encode).",
+                    actual.extension(),
+                    target.extension()
+                ),
+            )
+            .parameter("output_format")
+            .applied(target.extension())
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use serde_json::{json, Value};
+
+    use super::{artifact_mime, conform_output_format, svg_dimensions, OutputFormat, SVG_MIME};
+    use crate::{map_object, NativeEngine};
+
+    #[test]
+    fn conformance_converts_renames_and_flags_lossy_targets() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        assert_eq!(OutputFormat::parse("image/AVIF"), Some(OutputFormat::Avif));
+        assert_eq!(OutputFormat::parse("gif"), None);
+
+        // PNG bytes behind a .webp name, as some providers write them.
+        let mislabeled = temp.path().join("a.webp");
+        RgbImage::from_pixel(16, 16, Rgb([9, 9, 9]))
+            .save_with_format(&mislabeled, image::ImageFormat::Png)?;
+        let webp = conform_output_format(&mislabeled, OutputFormat::Webp)?;
+        assert!(webp.warning.is_none());
+        assert_eq!(
+            OutputFormat::sniff(&webp.image_path),
+            Some(OutputFormat::Webp)
+        );
+
+        let png = temp.path().join("b.png");
+        RgbImage::from_pixel(16, 16, Rgb([9, 9, 9])).save(&png)?;
+        let avif = conform_output_format(&png, OutputFormat::Avif)?;
+        assert!(avif.image_path.ends_with("b.avif"));
+        assert!(!png.exists());
+        assert_eq!(
+            OutputFormat::sniff(&avif.image_path),
+            Some(OutputFormat::Avif)
+        );
+        assert!(avif.warning.is_some());
+        assert_eq!(
+            avif.conversion.and_then(|row| row.get("from").cloned()),
+            Some(serde_json::json!("png"))
+        );
+
+        let jpeg_named_png = temp.path().join("c.png");
+        RgbImage::from_pixel(16, 16, Rgb([9, 9, 9]))
+            .save_with_format(&jpeg_named_png, image::ImageFormat::Jpeg)?;
+        let renamed = conform_output_format(&jpeg_named_png, OutputFormat::Jpeg)?;
+        assert!(renamed.image_path.ends_with("c.jpg"));
+        assert!(renamed.conversion.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn svg_documents_are_sniffed_and_sized() -> anyhow::Result<()> {
+        let sized = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" width="640px" height='480'></svg>"#;
+        assert_eq!(svg_dimensions(sized), Some((640, 480)));
+        let boxed = br#"<svg stroke-width="2" viewBox="0 0 1024 768"><path d="M0 0"/></svg>"#;
+        assert_eq!(svg_dimensions(boxed), Some((1024, 768)));
+        assert_eq!(svg_dimensions(b"<svg width=\"100%\"></svg>"), None);
+
+        let temp = tempfile::tempdir()?;
+        let svg = temp.path().join("logo.svg");
+        std::fs::write(&svg, boxed)?;
+        assert_eq!(artifact_mime(&svg), Some(SVG_MIME));
+        // Vector files are never re-encoded, whatever format was asked for.
+        let kept = conform_output_format(&svg, OutputFormat::Png)?;
+        assert_eq!(kept.image_path, svg);
+        assert!(kept.conversion.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn avif_output_is_encoded_locally_and_receipted() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let artifacts = engine.generate(
+            "a lighthouse",
+            map_object(json!({"size": "32x32", "output_format": "avif"})),
+            map_object(json!({"action": "generate"})),
+        )?;
+        let image_path = artifacts[0]["image_path"].as_str().unwrap_or_default();
+        assert!(image_path.ends_with(".avif"));
+        assert_eq!(
+            OutputFormat::sniff(std::path::Path::new(image_path)),
+            Some(OutputFormat::Avif)
+        );
+        let receipt: Value = serde_json::from_str(&std::fs::read_to_string(
+            artifacts[0]["receipt_path"].as_str().unwrap_or_default(),
+        )?)?;
+        assert_eq!(
+            receipt["result_metadata"]["format_conversion"],
+            json!({"from": "png", "to": "avif", "lossy": true})
+        );
+        assert_eq!(receipt["request"]["output_format"], json!("avif"));
+        assert_eq!(
+            receipt["warning_details"][0]["code"],
+            json!("format_converted")
+        );
+        assert!(receipt["artifacts"]["image_sha256"].is_string());
+        Ok(())
+    }
+}
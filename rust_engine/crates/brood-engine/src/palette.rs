@@ -0,0 +1,354 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::chat::normalize_hex_color;
+use brood_contracts::runs::receipts::write_receipt;
+use image::imageops::FilterType;
+use image::RgbImage;
+use serde_json::{json, Map, Value};
+
+use super::{map_object, NativeEngine};
+
+/// Colours `/palette` reports by default.
+pub const PALETTE_DEFAULT_COLORS: usize = 6;
+const PALETTE_MAX_COLORS: usize = 16;
+/// Buckets of [`color_bucket`]: 4 levels per channel.
+pub(crate) const COLOR_BUCKETS: usize = 64;
+/// Edge images are reduced to before their colours are counted.
+const PALETTE_EDGE: u32 = 64;
+/// Distance between black and white in RGB, the largest deviation possible.
+const RGB_DIAGONAL: f64 = 441.672_955_930_063_7;
+
+/// One dominant colour of an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteColor {
+    /// `#rrggbb`, the mean of the pixels in its bucket.
+    pub hex: String,
+    /// Share of the image in this colour, 0..=1.
+    pub share: f64,
+}
+
+impl PaletteColor {
+    pub fn to_value(&self) -> Value {
+        json!({ "hex": self.hex, "name": color_name(&self.hex), "share": self.share })
+    }
+}
+
+/// `settings.palette`: `["#1428a0", "#ffffff"]` or
+/// `{"colors": ["#1428a0", "#ffffff"]}`. The colours are written into the
+/// prompt and each output is checked against them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteSpec {
+    /// Lowercase `#rrggbb`.
+    pub colors: Vec<String>,
+}
+
+impl PaletteSpec {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let raw = match settings.get("palette") {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::Array(colors)) => colors,
+            Some(Value::Object(spec)) => match spec.get("colors") {
+                Some(Value::Array(colors)) => colors,
+                _ => bail!("palette.colors must be a list of #rrggbb colors"),
+            },
+            Some(other) => bail!("palette must be a list of colors or an object (got {other})"),
+        };
+        if raw.is_empty() || raw.len() > PALETTE_MAX_COLORS {
+            bail!(
+                "palette needs 1 to {PALETTE_MAX_COLORS} colors (got {})",
+                raw.len()
+            );
+        }
+        let colors = raw
+            .iter()
+            .map(|color| {
+                color
+                    .as_str()
+                    .and_then(normalize_hex_color)
+                    .with_context(|| format!("palette color must be #rgb or #rrggbb (got {color})"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(Self { colors }))
+    }
+
+    /// Sentence appended to the provider prompt.
+    pub fn prompt_clause(&self) -> String {
+        let colors: Vec<String> = self
+            .colors
+            .iter()
+            .map(|hex| match color_name(hex) {
+                Some(name) => format!("{name} ({hex})"),
+                None => hex.clone(),
+            })
+            .collect();
+        format!("Use only this color palette: {}.", colors.join(", "))
+    }
+
+    /// Measures how far `image_path` strays from the palette.
+    pub fn check(&self, image_path: &Path) -> Result<PaletteCheck> {
+        let image = load_small(image_path)?;
+        let targets: Vec<[f64; 3]> = self.colors.iter().filter_map(|hex| hex_rgb(hex)).collect();
+        let total = f64::from(image.width() * image.height()).max(1.0);
+        let deviation = image
+            .pixels()
+            .map(|pixel| {
+                let rgb = pixel.0.map(f64::from);
+                targets
+                    .iter()
+                    .map(|target| {
+                        (0..3)
+                            .map(|channel| (rgb[channel] - target[channel]).powi(2))
+                            .sum::<f64>()
+                            .sqrt()
+                    })
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .sum::<f64>()
+            / total
+            / RGB_DIAGONAL;
+        Ok(PaletteCheck {
+            target: self.colors.clone(),
+            extracted: dominant_colors(&image, self.colors.len().max(PALETTE_DEFAULT_COLORS)),
+            deviation: deviation.clamp(0.0, 1.0),
+        })
+    }
+}
+
+/// An output measured against a [`PaletteSpec`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteCheck {
+    pub target: Vec<String>,
+    pub extracted: Vec<PaletteColor>,
+    /// Mean RGB distance from each pixel to its nearest palette colour,
+    /// scaled to 0..=1; 0 means every pixel is a palette colour.
+    pub deviation: f64,
+}
+
+impl PaletteCheck {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "target": self.target,
+            "extracted": self.extracted.iter().map(PaletteColor::to_value).collect::<Vec<_>>(),
+            "deviation": self.deviation,
+        })
+    }
+}
+
+impl NativeEngine {
+    /// The `count` dominant colours of `image_path`. When the image is an
+    /// artifact of this run, they are also stored under
+    /// `result_metadata.dominant_palette` in its receipt. Emits
+    /// `palette_extracted`.
+    pub fn extract_palette(
+        &mut self,
+        image_path: &Path,
+        count: usize,
+    ) -> Result<Vec<PaletteColor>> {
+        let colors = dominant_colors(&load_small(image_path)?, count.clamp(1, PALETTE_MAX_COLORS));
+        let image_text = image_path.to_string_lossy().to_string();
+        let artifact = self.thread.versions.iter().rev().find_map(|version| {
+            version
+                .artifacts
+                .iter()
+                .find(|artifact| {
+                    artifact.get("image_path").and_then(Value::as_str) == Some(&image_text)
+                })
+                .cloned()
+        });
+        let values: Vec<Value> = colors.iter().map(PaletteColor::to_value).collect();
+        if let Some(receipt_path) = artifact
+            .as_ref()
+            .and_then(|artifact| artifact.get("receipt_path"))
+            .and_then(Value::as_str)
+        {
+            store_in_receipt(Path::new(receipt_path), &values)?;
+        }
+        self.events.emit(
+            "palette_extracted",
+            map_object(json!({
+                "image_path": image_text,
+                "artifact_id": artifact.as_ref().and_then(|artifact| artifact.get("artifact_id")),
+                "colors": values,
+            })),
+        )?;
+        Ok(colors)
+    }
+}
+
+/// Bucket of a pixel with 4 levels per channel.
+pub(crate) fn color_bucket(rgb: [u8; 3]) -> usize {
+    (usize::from(rgb[0] >> 6) << 4) | (usize::from(rgb[1] >> 6) << 2) | usize::from(rgb[2] >> 6)
+}
+
+/// The `count` most common colour buckets of `image`, most common first,
+/// each as the mean of its pixels.
+pub(crate) fn dominant_colors(image: &RgbImage, count: usize) -> Vec<PaletteColor> {
+    let mut buckets = [(0u64, [0u64; 3]); COLOR_BUCKETS];
+    for pixel in image.pixels() {
+        let bucket = &mut buckets[color_bucket(pixel.0)];
+        bucket.0 += 1;
+        for channel in 0..3 {
+            bucket.1[channel] += u64::from(pixel.0[channel]);
+        }
+    }
+    let total = buckets.iter().map(|(pixels, _)| pixels).sum::<u64>().max(1) as f64;
+    let mut ranked: Vec<(usize, (u64, [u64; 3]))> = buckets
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (pixels, _))| *pixels > 0)
+        .collect();
+    ranked.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(&b.0)));
+    ranked
+        .into_iter()
+        .take(count)
+        .map(|(_, (pixels, sums))| PaletteColor {
+            hex: format!(
+                "#{:02x}{:02x}{:02x}",
+                sums[0] / pixels,
+                sums[1] / pixels,
+                sums[2] / pixels
+            ),
+            share: pixels as f64 / total,
+        })
+        .collect()
+}
+
+/// A plain colour word for `#rrggbb`, so prompts read "deep blue" rather
+/// than only a hex code.
+pub fn color_name(hex: &str) -> Option<String> {
+    let value = u32::from_str_radix(hex.strip_prefix('#')?, 16).ok()?;
+    let [r, g, b] = [(value >> 16) & 0xff, (value >> 8) & 0xff, value & 0xff]
+        .map(|channel| channel as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma < 0.12 {
+        return Some(
+            match lightness {
+                l if l < 0.15 => "black",
+                l if l < 0.4 => "dark gray",
+                l if l < 0.75 => "gray",
+                l if l < 0.92 => "light gray",
+                _ => "white",
+            }
+            .to_string(),
+        );
+    }
+    let hue = if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+    let name = match hue {
+        h if !(15.0..345.0).contains(&h) => "red",
+        h if h < 40.0 => "orange",
+        h if h < 65.0 => "yellow",
+        h if h < 160.0 => "green",
+        h if h < 195.0 => "teal",
+        h if h < 255.0 => "blue",
+        h if h < 290.0 => "purple",
+        _ => "pink",
+    };
+    let shade = if lightness < 0.3 {
+        "deep "
+    } else if lightness > 0.75 {
+        "pale "
+    } else {
+        ""
+    };
+    Some(format!("{shade}{name}"))
+}
+
+fn hex_rgb(hex: &str) -> Option<[f64; 3]> {
+    let value = u32::from_str_radix(hex.strip_prefix('#')?, 16).ok()?;
+    Some([(value >> 16) & 0xff, (value >> 8) & 0xff, value & 0xff].map(f64::from))
+}
+
+fn load_small(image_path: &Path) -> Result<RgbImage> {
+    Ok(image::open(image_path)
+        .with_context(|| format!("failed to read {}", image_path.display()))?
+        .resize_exact(PALETTE_EDGE, PALETTE_EDGE, FilterType::Triangle)
+        .to_rgb8())
+}
+
+fn store_in_receipt(receipt_path: &Path, colors: &[Value]) -> Result<()> {
+    let Some(mut payload) = fs::read_to_string(receipt_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+    else {
+        return Ok(());
+    };
+    let Some(root) = payload.as_object_mut() else {
+        return Ok(());
+    };
+    let metadata = root
+        .entry("result_metadata".to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Map::new());
+    }
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert(
+            "dominant_palette".to_string(),
+            Value::Array(colors.to_vec()),
+        );
+    }
+    write_receipt(receipt_path, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use serde_json::{json, Map};
+
+    use super::{color_name, dominant_colors, PaletteSpec};
+
+    #[test]
+    fn extracts_dominant_colors_and_scores_palette_deviation() -> anyhow::Result<()> {
+        let image = RgbImage::from_fn(64, 64, |x, _| {
+            if x < 48 {
+                Rgb([20, 40, 160])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        let colors = dominant_colors(&image, 4);
+        assert_eq!(colors.len(), 2);
+        assert_eq!(colors[0].hex, "#1428a0");
+        assert!((colors[0].share - 0.75).abs() < 1e-9);
+        assert_eq!(color_name("#1428a0").as_deref(), Some("blue"));
+        assert_eq!(color_name("#0a0a0a").as_deref(), Some("black"));
+        assert_eq!(color_name("#f0a0e0").as_deref(), Some("pale pink"));
+
+        let mut settings = Map::new();
+        assert_eq!(PaletteSpec::from_settings(&settings)?, None);
+        settings.insert(
+            "palette".to_string(),
+            json!({"colors": ["#1428A0", "#fff"]}),
+        );
+        let spec =
+            PaletteSpec::from_settings(&settings)?.unwrap_or(PaletteSpec { colors: Vec::new() });
+        assert_eq!(spec.colors, vec!["#1428a0", "#ffffff"]);
+        assert_eq!(
+            spec.prompt_clause(),
+            "Use only this color palette: blue (#1428a0), white (#ffffff)."
+        );
+        settings.insert("palette".to_string(), json!(["red"]));
+        assert!(PaletteSpec::from_settings(&settings).is_err());
+
+        let temp = tempfile::tempdir()?;
+        let on_palette = temp.path().join("on.png");
+        let off_palette = temp.path().join("off.png");
+        image.save(&on_palette)?;
+        RgbImage::from_pixel(64, 64, Rgb([200, 30, 30])).save(&off_palette)?;
+        let on = spec.check(&on_palette)?;
+        assert!(on.deviation < 0.01, "{}", on.deviation);
+        assert!(spec.check(&off_palette)?.deviation > 0.3);
+        Ok(())
+    }
+}
@@ -0,0 +1,391 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use brood_contracts::runs::receipts::file_sha256;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde_json::{json, Map, Value};
+
+use super::output_format::{encode_image, open_image, OutputFormat, LOCAL_JPEG_QUALITY};
+
+/// Largest edge `resize` accepts.
+pub const POST_PROCESS_MAX_EDGE: u32 = 8192;
+/// Qualities tried, in order, by `compress`.
+const COMPRESS_QUALITIES: [u8; 7] = [90, 82, 74, 66, 58, 50, 40];
+
+/// One step of `settings.post_process`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PostProcessOp {
+    /// Scale to fit within the given bounds, keeping the aspect ratio.
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    /// Center-crop to an aspect ratio such as `16:9`.
+    Crop {
+        aspect_w: u32,
+        aspect_h: u32,
+    },
+    Convert {
+        format: OutputFormat,
+    },
+    /// Flatten transparency onto white.
+    StripAlpha,
+    /// Re-encode as JPEG at the highest quality that fits in `max_kb`.
+    Compress {
+        max_kb: u64,
+    },
+}
+
+impl PostProcessOp {
+    fn parse(raw: &Value) -> Result<Self> {
+        let Some(raw) = raw.as_object() else {
+            bail!("post_process steps must be objects, got {raw}");
+        };
+        let op = raw.get("op").and_then(Value::as_str).unwrap_or_default();
+        let dimension = |key: &str| -> Result<Option<u32>> {
+            match raw.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(value) => match value
+                    .as_u64()
+                    .filter(|edge| (1..=u64::from(POST_PROCESS_MAX_EDGE)).contains(edge))
+                {
+                    Some(edge) => Ok(Some(edge as u32)),
+                    None => bail!(
+                        "post_process {op}.{key} must be an integer in 1..={POST_PROCESS_MAX_EDGE}"
+                    ),
+                },
+            }
+        };
+        match op {
+            "resize" => {
+                let (width, height) = (dimension("width")?, dimension("height")?);
+                if width.is_none() && height.is_none() {
+                    bail!("post_process resize needs width and/or height");
+                }
+                Ok(Self::Resize { width, height })
+            }
+            "crop" => {
+                let aspect = raw.get("aspect").and_then(Value::as_str).unwrap_or_default();
+                let parsed = aspect.split_once(':').and_then(|(w, h)| {
+                    Some((w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?))
+                });
+                match parsed {
+                    Some((aspect_w, aspect_h)) if aspect_w > 0 && aspect_h > 0 => {
+                        Ok(Self::Crop { aspect_w, aspect_h })
+                    }
+                    _ => bail!("post_process crop needs an aspect like \"16:9\", got '{aspect}'"),
+                }
+            }
+            "convert" => {
+                let format = raw.get("format").and_then(Value::as_str).unwrap_or_default();
+                match OutputFormat::parse(format) {
+                    Some(format) => Ok(Self::Convert { format }),
+                    None => bail!(
+                        "post_process convert format must be png, jpeg, webp or avif, got '{format}'"
+                    ),
+                }
+            }
+            "strip_alpha" => Ok(Self::StripAlpha),
+            "compress" => match raw.get("max_kb").and_then(Value::as_u64) {
+                Some(max_kb) if max_kb > 0 => Ok(Self::Compress { max_kb }),
+                _ => bail!("post_process compress needs a positive max_kb"),
+            },
+            "" => bail!("post_process step is missing 'op'"),
+            other => bail!(
+                "unknown post_process op '{other}' (expected resize, crop, convert, strip_alpha or compress)"
+            ),
+        }
+    }
+}
+
+/// `settings.post_process`: steps applied in order to each provider result
+/// before it is watermarked, hashed and receipted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessChain {
+    pub steps: Vec<PostProcessOp>,
+}
+
+/// The processed file (its extension may have changed) and what each step
+/// did, for `result_metadata.post_process`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessOutcome {
+    pub image_path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub metadata: Map<String, Value>,
+    pub warnings: Vec<Warning>,
+}
+
+impl PostProcessChain {
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let steps = match settings.get("post_process") {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::Array(steps)) if steps.is_empty() => return Ok(None),
+            Some(Value::Array(steps)) => steps
+                .iter()
+                .enumerate()
+                .map(|(idx, step)| {
+                    PostProcessOp::parse(step)
+                        .with_context(|| format!("invalid post_process step {}", idx + 1))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(other) => bail!("post_process must be a list of steps, got {other}"),
+        };
+        Ok(Some(Self { steps }))
+    }
+
+    /// Whether the chain picks the output encoding itself, overriding
+    /// `output_format`.
+    pub fn sets_format(&self) -> bool {
+        self.steps.iter().any(|step| {
+            matches!(
+                step,
+                PostProcessOp::Convert { .. } | PostProcessOp::Compress { .. }
+            )
+        })
+    }
+
+    /// Runs the chain on the image at `path`. When the output format
+    /// changes, the result is written next to it with the new extension and
+    /// the provider's original file is removed.
+    pub fn apply(&self, path: &Path) -> Result<PostProcessOutcome> {
+        let source_sha256 = file_sha256(path);
+        let mut image = open_image(path)?;
+        let source_dims = (image.width(), image.height());
+        let mut format = OutputFormat::sniff(path)
+            .or_else(|| OutputFormat::from_path(path))
+            .unwrap_or(OutputFormat::Png);
+        let mut jpeg_quality = LOCAL_JPEG_QUALITY;
+        let mut steps = Vec::with_capacity(self.steps.len());
+        let mut warnings = Vec::new();
+
+        for step in &self.steps {
+            let record = match step {
+                PostProcessOp::Resize { width, height } => {
+                    image = image.resize(
+                        width.unwrap_or(u32::MAX),
+                        height.unwrap_or(u32::MAX),
+                        FilterType::Lanczos3,
+                    );
+                    json!({ "op": "resize", "width": image.width(), "height": image.height() })
+                }
+                PostProcessOp::Crop { aspect_w, aspect_h } => {
+                    image = crop_to_aspect(&image, *aspect_w, *aspect_h);
+                    json!({
+                        "op": "crop",
+                        "aspect": format!("{aspect_w}:{aspect_h}"),
+                        "width": image.width(),
+                        "height": image.height(),
+                    })
+                }
+                PostProcessOp::Convert { format: target } => {
+                    format = *target;
+                    json!({ "op": "convert", "format": target.extension() })
+                }
+                PostProcessOp::StripAlpha => {
+                    let had_alpha = image.color().has_alpha();
+                    image = strip_alpha(&image);
+                    json!({ "op": "strip_alpha", "had_alpha": had_alpha })
+                }
+                PostProcessOp::Compress { max_kb } => {
+                    format = OutputFormat::Jpeg;
+                    let (quality, bytes) = compress_quality(&image, *max_kb)?;
+                    jpeg_quality = quality;
+                    if bytes > max_kb * 1024 {
+                        warnings.push(
+                            Warning::new(
+                                WarningCode::ValueReplaced,
+                                format!(
+                                    "Post-process compress target {max_kb} KB not reached; using quality {quality} ({} KB).",
+                                    bytes.div_ceil(1024)
+                                ),
+                            )
+                            .parameter("max_kb")
+                            .requested(max_kb)
+                            .applied(bytes.div_ceil(1024)),
+                        );
+                    }
+                    json!({ "op": "compress", "max_kb": max_kb, "quality": quality, "bytes": bytes })
+                }
+            };
+            steps.push(record);
+        }
+
+        let out_path = path.with_extension(format.extension());
+        let encoded = encode_image(&image, format, jpeg_quality)?;
+        write_atomic(&out_path, &encoded)?;
+        if out_path != path {
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+
+        let mut metadata = Map::new();
+        metadata.insert("steps".to_string(), Value::Array(steps));
+        metadata.insert(
+            "source".to_string(),
+            json!({
+                "width": source_dims.0,
+                "height": source_dims.1,
+                "sha256": source_sha256,
+            }),
+        );
+        metadata.insert("bytes".to_string(), json!(encoded.len()));
+        Ok(PostProcessOutcome {
+            image_path: out_path,
+            width: image.width(),
+            height: image.height(),
+            metadata,
+            warnings,
+        })
+    }
+}
+
+fn crop_to_aspect(image: &DynamicImage, aspect_w: u32, aspect_h: u32) -> DynamicImage {
+    let (width, height) = (u64::from(image.width()), u64::from(image.height()));
+    let (aspect_w, aspect_h) = (u64::from(aspect_w), u64::from(aspect_h));
+    let (crop_w, crop_h) = if width * aspect_h > height * aspect_w {
+        ((height * aspect_w / aspect_h).max(1), height)
+    } else {
+        (width, (width * aspect_h / aspect_w).max(1))
+    };
+    image.crop_imm(
+        ((width - crop_w) / 2) as u32,
+        ((height - crop_h) / 2) as u32,
+        crop_w as u32,
+        crop_h as u32,
+    )
+}
+
+fn strip_alpha(image: &DynamicImage) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image.clone();
+    }
+    let mut flattened = RgbaImage::from_pixel(image.width(), image.height(), Rgba([255; 4]));
+    image::imageops::overlay(&mut flattened, &image.to_rgba8(), 0, 0);
+    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(flattened).to_rgb8())
+}
+
+/// Highest quality from [`COMPRESS_QUALITIES`] whose JPEG fits in `max_kb`,
+/// or the lowest one when none does, with the encoded size.
+fn compress_quality(image: &DynamicImage, max_kb: u64) -> Result<(u8, u64)> {
+    let mut last = (0, 0);
+    for quality in COMPRESS_QUALITIES {
+        let bytes = encode_image(image, OutputFormat::Jpeg, quality)?.len() as u64;
+        last = (quality, bytes);
+        if bytes <= max_kb * 1024 {
+            break;
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use serde_json::{json, Map, Value};
+
+    use super::{PostProcessChain, PostProcessOp};
+    use crate::{map_object, NativeEngine};
+
+    fn settings(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn chain_parses_and_rejects_bad_steps() -> anyhow::Result<()> {
+        assert!(PostProcessChain::from_settings(&Map::new())?.is_none());
+        let chain = PostProcessChain::from_settings(&settings(json!({
+            "post_process": [{"op": "resize", "width": 1200}, {"op": "crop", "aspect": "16:9"}]
+        })))?
+        .ok_or_else(|| anyhow::anyhow!("expected a chain"))?;
+        assert_eq!(
+            chain.steps,
+            vec![
+                PostProcessOp::Resize {
+                    width: Some(1200),
+                    height: None
+                },
+                PostProcessOp::Crop {
+                    aspect_w: 16,
+                    aspect_h: 9
+                },
+            ]
+        );
+        for bad in [
+            json!([{"op": "resize"}]),
+            json!([{"op": "crop", "aspect": "wide"}]),
+            json!([{"op": "convert", "format": "gif"}]),
+            json!([{"op": "compress", "max_kb": 0}]),
+            json!([{"op": "blur"}]),
+            json!({"op": "resize"}),
+        ] {
+            assert!(
+                PostProcessChain::from_settings(&settings(json!({ "post_process": bad }))).is_err()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn chain_resizes_crops_flattens_and_converts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("artifact.png");
+        RgbaImage::from_pixel(400, 200, Rgba([0, 0, 0, 0])).save(&path)?;
+        let chain = PostProcessChain::from_settings(&settings(json!({
+            "post_process": [
+                {"op": "resize", "width": 200},
+                {"op": "crop", "aspect": "1:1"},
+                {"op": "strip_alpha"},
+                {"op": "compress", "max_kb": 50},
+            ]
+        })))?
+        .ok_or_else(|| anyhow::anyhow!("expected a chain"))?;
+        let outcome = chain.apply(&path)?;
+        assert_eq!((outcome.width, outcome.height), (100, 100));
+        assert!(outcome.image_path.ends_with("artifact.jpg"));
+        assert!(!path.exists());
+        let written = image::open(&outcome.image_path)?.to_rgb8();
+        assert_eq!(written.get_pixel(50, 50)[0], 255);
+        assert_eq!(outcome.metadata["steps"][3]["quality"], json!(90));
+        assert_eq!(outcome.metadata["source"]["width"], json!(400));
+        assert!(outcome.warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_artifacts_are_post_processed_before_receipts() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let artifacts = engine.generate(
+            "a lighthouse",
+            settings(json!({
+                "size": "64x64",
+                "post_process": [{"op": "resize", "width": 32}, {"op": "convert", "format": "jpeg"}],
+            })),
+            map_object(json!({"action": "generate"})),
+        )?;
+        let image_path = artifacts[0]["image_path"].as_str().unwrap_or_default();
+        assert!(image_path.ends_with(".jpg"));
+        assert_eq!(image::image_dimensions(image_path)?, (32, 32));
+        let receipt: Value = serde_json::from_str(&std::fs::read_to_string(
+            artifacts[0]["receipt_path"].as_str().unwrap_or_default(),
+        )?)?;
+        assert_eq!(receipt["artifacts"]["image_path"], json!(image_path));
+        assert_eq!(
+            receipt["result_metadata"]["post_process"]["steps"][0]["width"],
+            json!(32)
+        );
+        assert!(receipt["result_metadata"]["post_process"]["source"]["sha256"].is_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_json_atomic;
+use serde_json::{Map, Value};
+
+use super::{file_stem_name, non_empty_env};
+
+/// Directory holding `<name>.json` settings presets; defaults to
+/// `~/.brood/presets`.
+pub const SETTINGS_PRESETS_DIR_ENV: &str = "BROOD_PRESETS_DIR";
+
+/// Generation settings a preset carries.
+pub const PRESET_SETTINGS_KEYS: &[&str] = &["size", "n", "provider_options", "quality_preset"];
+
+/// A saved bundle of generation settings and the image model, recalled with
+/// `/preset use <name>` or `run --preset <name>`:
+///
+/// ```json
+/// {"image_model": "flux-2-pro", "size": "1536x1024", "n": 2,
+///  "quality_preset": "quality", "provider_options": {"safety_tolerance": 2}}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingsPreset {
+    pub name: String,
+    pub image_model: Option<String>,
+    /// Only [`PRESET_SETTINGS_KEYS`].
+    pub settings: Map<String, Value>,
+}
+
+impl SettingsPreset {
+    /// `$BROOD_PRESETS_DIR`, else `~/.brood/presets`.
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = non_empty_env(SETTINGS_PRESETS_DIR_ENV) {
+            return Some(PathBuf::from(dir));
+        }
+        env::var_os("HOME")
+            .map(PathBuf::from)
+            .map(|home| home.join(".brood").join("presets"))
+    }
+
+    /// Snapshots the preset keys of `settings`.
+    pub fn capture(
+        name: &str,
+        image_model: Option<&str>,
+        settings: &Map<String, Value>,
+    ) -> Result<Self> {
+        let name = file_stem_name("preset", name)?;
+        let settings = settings
+            .iter()
+            .filter(|(key, _)| PRESET_SETTINGS_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(Self {
+            name: name.to_string(),
+            image_model: image_model.map(str::to_string),
+            settings,
+        })
+    }
+
+    /// The preset `name` from [`SettingsPreset::default_dir`].
+    pub fn load(name: &str) -> Result<Self> {
+        let Some(dir) = Self::default_dir() else {
+            bail!("no presets directory (HOME is not set)");
+        };
+        Self::load_from(&dir, name)
+    }
+
+    pub fn load_from(dir: &Path, name: &str) -> Result<Self> {
+        let name = file_stem_name("preset", name)?;
+        let path = dir.join(format!("{name}.json"));
+        if !path.is_file() {
+            let available = Self::available(dir);
+            bail!(
+                "no preset '{name}' in {} (available: {})",
+                dir.display(),
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            );
+        }
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read preset {}", path.display()))?;
+        Self::parse(name, &raw).with_context(|| format!("invalid preset {}", path.display()))
+    }
+
+    pub fn parse(name: &str, raw: &str) -> Result<Self> {
+        let payload: Value = serde_json::from_str(raw)?;
+        let Some(fields) = payload.as_object() else {
+            bail!("expected a JSON object");
+        };
+        let mut preset = Self {
+            name: name.to_string(),
+            image_model: None,
+            settings: Map::new(),
+        };
+        for (key, value) in fields {
+            match (key.as_str(), value) {
+                (_, Value::Null) => {}
+                ("image_model", Value::String(model)) if !model.trim().is_empty() => {
+                    preset.image_model = Some(model.trim().to_string());
+                }
+                ("size" | "quality_preset", Value::String(text)) if !text.trim().is_empty() => {
+                    preset
+                        .settings
+                        .insert(key.clone(), Value::String(text.trim().to_string()));
+                }
+                ("n", value) if value.as_u64().is_some_and(|n| n > 0) => {
+                    preset.settings.insert(key.clone(), value.clone());
+                }
+                ("provider_options", Value::Object(_)) => {
+                    preset.settings.insert(key.clone(), value.clone());
+                }
+                ("image_model" | "size" | "quality_preset" | "n" | "provider_options", _) => {
+                    bail!("invalid preset {key}: {value}");
+                }
+                _ => bail!("unknown preset key '{key}'"),
+            }
+        }
+        Ok(preset)
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut payload = Map::new();
+        if let Some(model) = &self.image_model {
+            payload.insert("image_model".to_string(), Value::String(model.clone()));
+        }
+        payload.extend(self.settings.clone());
+        Value::Object(payload)
+    }
+
+    /// Writes `<dir>/<name>.json`, replacing an older preset of that name.
+    pub fn save_to(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", self.name));
+        write_json_atomic(&path, &self.to_value())?;
+        Ok(path)
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let Some(dir) = Self::default_dir() else {
+            bail!("no presets directory (HOME is not set)");
+        };
+        self.save_to(&dir)
+    }
+
+    /// Preset names in `dir`, sorted.
+    pub fn available(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                (path.extension().and_then(|value| value.to_str()) == Some("json"))
+                    .then(|| path.file_stem()?.to_str().map(str::to_string))
+                    .flatten()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Overwrites the preset's keys in `settings`.
+    pub fn apply(&self, settings: &mut Map<String, Value>) {
+        settings.extend(self.settings.clone());
+    }
+
+    pub fn quality_preset(&self) -> Option<&str> {
+        self.settings.get("quality_preset").and_then(Value::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::SettingsPreset;
+
+    #[test]
+    fn presets_round_trip_and_reject_unknown_keys() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("1536x1024"));
+        settings.insert("n".to_string(), json!(2));
+        settings.insert("provider_options".to_string(), json!({"style": "vivid"}));
+        settings.insert("seed".to_string(), json!(7));
+        let preset = SettingsPreset::capture("hero", Some("gpt-image-1"), &settings)?;
+        assert!(!preset.settings.contains_key("seed"));
+        let path = preset.save_to(temp.path())?;
+        assert_eq!(path, temp.path().join("hero.json"));
+        assert_eq!(SettingsPreset::load_from(temp.path(), "hero")?, preset);
+        assert_eq!(SettingsPreset::available(temp.path()), vec!["hero"]);
+        assert!(SettingsPreset::load_from(temp.path(), "missing").is_err());
+        assert!(SettingsPreset::capture("../hero", None, &settings).is_err());
+
+        assert!(SettingsPreset::parse("bad", r#"{"n": 0}"#).is_err());
+        assert!(SettingsPreset::parse("bad", r#"{"seed": 3}"#).is_err());
+        let mut target = Map::new();
+        target.insert("size".to_string(), json!("1024x1024"));
+        preset.apply(&mut target);
+        assert_eq!(target["size"], json!("1536x1024"));
+        assert_eq!(target["n"], json!(2));
+        Ok(())
+    }
+}
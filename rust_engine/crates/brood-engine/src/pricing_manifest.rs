@@ -0,0 +1,413 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use brood_contracts::redaction::redact_url;
+use brood_contracts::runs::atomic::write_json_atomic;
+use chrono::{DateTime, Utc};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde_json::{json, Map, Value};
+
+use super::http_client::http_client_builder;
+use super::{non_empty_env, now_utc_iso};
+
+/// URL of the signed pricing manifest; refresh is off while unset.
+pub const PRICING_URL_ENV: &str = "BROOD_PRICING_URL";
+/// Ed25519 public key (hex or base64) the manifest must be signed with.
+pub const PRICING_PUBLIC_KEY_ENV: &str = "BROOD_PRICING_PUBLIC_KEY";
+/// Hours a fetched manifest stays fresh before an engine opening refetches
+/// it in the background; `0` turns auto-refresh off. Defaults to 24.
+pub const PRICING_TTL_ENV: &str = "BROOD_PRICING_TTL_HOURS";
+/// Verified manifest cache under `~/.brood`.
+pub const REMOTE_PRICING_FILENAME: &str = "pricing_remote.json";
+
+const DEFAULT_TTL_HOURS: f64 = 24.0;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_MANIFEST_BYTES: usize = 1024 * 1024;
+
+/// Where the pricing manifest comes from and who must have signed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingSource {
+    pub url: String,
+    public_key: Vec<u8>,
+}
+
+impl PricingSource {
+    pub fn new(url: &str, public_key: &str) -> Result<Self> {
+        let raw = public_key.trim();
+        let decoded = match hex::decode(raw) {
+            Ok(bytes) => bytes,
+            Err(_) => BASE64
+                .decode(raw)
+                .context("pricing public key must be hex or base64")?,
+        };
+        if decoded.len() != 32 {
+            bail!(
+                "pricing public key must be a 32-byte Ed25519 key, got {} bytes",
+                decoded.len()
+            );
+        }
+        Ok(Self {
+            url: url.trim().to_string(),
+            public_key: decoded,
+        })
+    }
+
+    /// [`PRICING_URL_ENV`] and [`PRICING_PUBLIC_KEY_ENV`]; `None` without a
+    /// URL, an error with a URL but no key, since unsigned prices are never
+    /// merged.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(url) = non_empty_env(PRICING_URL_ENV) else {
+            return Ok(None);
+        };
+        let Some(key) = non_empty_env(PRICING_PUBLIC_KEY_ENV) else {
+            bail!("{PRICING_URL_ENV} is set but {PRICING_PUBLIC_KEY_ENV} is not");
+        };
+        Self::new(&url, &key).map(Some)
+    }
+}
+
+/// Outcome of [`update_pricing`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingUpdate {
+    pub version: u64,
+    pub issued_at: Option<String>,
+    pub models: usize,
+    pub path: PathBuf,
+    /// False when the fetched manifest was the version already cached.
+    pub changed: bool,
+}
+
+/// `~/.brood/pricing_remote.json`.
+pub fn remote_pricing_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .map(|home| home.join(".brood").join(REMOTE_PRICING_FILENAME))
+}
+
+struct VerifiedManifest {
+    version: u64,
+    issued_at: Option<String>,
+    pricing: Map<String, Value>,
+}
+
+/// A manifest is `{"payload": <base64>, "signature": <base64>}`: the
+/// signature covers the exact payload bytes, so no JSON canonicalization is
+/// needed. The payload is `{"version", "issued_at", "pricing"}`, where
+/// `pricing` has the shape of `default_pricing.json`.
+fn verify_manifest(raw: &[u8], public_key: &[u8]) -> Result<VerifiedManifest> {
+    let manifest: Value =
+        serde_json::from_slice(raw).context("pricing manifest is not valid JSON")?;
+    let field = |key: &str| -> Result<Vec<u8>> {
+        let encoded = manifest
+            .get(key)
+            .and_then(Value::as_str)
+            .with_context(|| format!("pricing manifest is missing {key}"))?;
+        BASE64
+            .decode(encoded)
+            .with_context(|| format!("pricing manifest {key} is not valid base64"))
+    };
+    let payload = field("payload")?;
+    let signature = field("signature")?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(&payload, &signature)
+        .map_err(|_| anyhow::anyhow!("pricing manifest signature does not verify"))?;
+
+    let payload: Value =
+        serde_json::from_slice(&payload).context("pricing manifest payload is not valid JSON")?;
+    let Some(version) = payload.get("version").and_then(Value::as_u64) else {
+        bail!("pricing manifest payload is missing its version");
+    };
+    let Some(pricing) = payload.get("pricing").and_then(Value::as_object) else {
+        bail!("pricing manifest payload is missing its pricing table");
+    };
+    validate_pricing_table(pricing)?;
+    Ok(VerifiedManifest {
+        version,
+        issued_at: payload
+            .get("issued_at")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        pricing: pricing.clone(),
+    })
+}
+
+/// Rows are objects whose values are null, non-negative numbers, or maps of
+/// non-negative multipliers; anything else would poison every estimate.
+fn validate_pricing_table(pricing: &Map<String, Value>) -> Result<()> {
+    if pricing.is_empty() {
+        bail!("pricing manifest has no rows");
+    }
+    let valid_number = |value: &Value| {
+        value
+            .as_f64()
+            .is_some_and(|number| number.is_finite() && number >= 0.0)
+    };
+    for (key, row) in pricing {
+        let Some(row) = row.as_object() else {
+            bail!("pricing row '{key}' must be an object");
+        };
+        for (field, value) in row {
+            let ok = match value {
+                Value::Null => true,
+                Value::Number(_) => valid_number(value),
+                Value::Object(nested) => nested.values().all(valid_number),
+                _ => false,
+            };
+            if !ok {
+                bail!("pricing row '{key}' has an invalid {field}: {value}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The cached manifest's pricing table, if one was ever fetched.
+pub(crate) fn cached_remote_pricing(path: &Path) -> Option<Value> {
+    read_cache(path).and_then(|cache| cache.get("pricing").cloned())
+}
+
+fn read_cache(path: &Path) -> Option<Value> {
+    let raw = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+/// Fetches and verifies the manifest at `source`, then caches it at
+/// `cache_path`. A manifest older than the cached one is refused, so a
+/// replayed old manifest cannot roll prices back.
+pub fn update_pricing(source: &PricingSource, cache_path: &Path) -> Result<PricingUpdate> {
+    let http = http_client_builder()?
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .context("failed to build pricing client")?;
+    let shown_url = redact_url(&source.url);
+    let response = http
+        .get(&source.url)
+        .send()
+        .map_err(reqwest::Error::without_url)
+        .with_context(|| format!("pricing manifest request failed ({shown_url})"))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!(
+            "pricing manifest request failed ({}): {shown_url}",
+            status.as_u16()
+        );
+    }
+    // Read at most one byte past the cap, so an oversized body is refused
+    // without buffering it.
+    let too_large = || anyhow::anyhow!("pricing manifest exceeds {MAX_MANIFEST_BYTES} bytes");
+    if response
+        .content_length()
+        .is_some_and(|len| len > MAX_MANIFEST_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    let mut raw = Vec::new();
+    response
+        .take(MAX_MANIFEST_BYTES as u64 + 1)
+        .read_to_end(&mut raw)
+        .with_context(|| format!("pricing manifest read failed ({shown_url})"))?;
+    if raw.len() > MAX_MANIFEST_BYTES {
+        return Err(too_large());
+    }
+    let manifest = verify_manifest(&raw, &source.public_key)?;
+
+    let cached_version =
+        read_cache(cache_path).and_then(|cache| cache.get("version").and_then(Value::as_u64));
+    if let Some(cached) = cached_version.filter(|cached| *cached > manifest.version) {
+        bail!(
+            "pricing manifest version {} is older than the cached version {cached}",
+            manifest.version
+        );
+    }
+    let cache = json!({
+        "version": manifest.version,
+        "issued_at": manifest.issued_at,
+        "fetched_at": now_utc_iso(),
+        "source": shown_url,
+        "pricing": manifest.pricing,
+    });
+    write_json_atomic(cache_path, &cache)?;
+    Ok(PricingUpdate {
+        version: manifest.version,
+        issued_at: manifest.issued_at,
+        models: manifest.pricing.len(),
+        path: cache_path.to_path_buf(),
+        changed: cached_version != Some(manifest.version),
+    })
+}
+
+/// Starts a background refetch when [`PRICING_URL_ENV`] is set and the
+/// cache is older than the TTL, at most once per process. Engine opens never
+/// wait on the network: they price with the cached (or built-in) table, and
+/// a refreshed manifest applies from the next engine opened. Failures are
+/// logged as warnings; `pricing update` refetches on demand.
+pub(crate) fn spawn_refresh_if_stale(cache_path: &Path) {
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    let source = match PricingSource::from_env() {
+        Ok(Some(source)) => source,
+        Ok(None) => return,
+        Err(err) => {
+            tracing::warn!(
+                error = %format_args!("{err:#}"),
+                "pricing manifest refresh skipped"
+            );
+            return;
+        }
+    };
+    let ttl_hours = non_empty_env(PRICING_TTL_ENV)
+        .and_then(|raw| raw.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_TTL_HOURS);
+    if ttl_hours <= 0.0
+        || !is_stale(cache_path, ttl_hours, Utc::now())
+        || STARTED.swap(true, Ordering::SeqCst)
+    {
+        return;
+    }
+    let cache_path = cache_path.to_path_buf();
+    let spawned = thread::Builder::new()
+        .name("brood-pricing-refresh".to_string())
+        .spawn(move || {
+            if let Err(err) = update_pricing(&source, &cache_path) {
+                tracing::warn!(
+                    cache_path = %cache_path.display(),
+                    error = %format_args!("{err:#}"),
+                    "pricing manifest refresh failed"
+                );
+            }
+        });
+    if let Err(err) = spawned {
+        tracing::warn!(error = %err, "failed to start the pricing manifest refresh");
+    }
+}
+
+fn is_stale(cache_path: &Path, ttl_hours: f64, now: DateTime<Utc>) -> bool {
+    let fetched_at = read_cache(cache_path)
+        .and_then(|cache| {
+            cache
+                .get("fetched_at")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .and_then(|raw| DateTime::parse_from_rfc3339(&raw).ok());
+    match fetched_at {
+        Some(fetched_at) => {
+            let age_hours = (now - fetched_at.with_timezone(&Utc)).num_seconds() as f64 / 3600.0;
+            age_hours >= ttl_hours
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use base64::Engine as _;
+    use chrono::{Duration as ChronoDuration, Utc};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::{json, Value};
+
+    use super::{
+        cached_remote_pricing, is_stale, update_pricing, validate_pricing_table, PricingSource,
+        BASE64,
+    };
+    use crate::test_support::{MockResponse, MockServer};
+
+    fn signed_manifest(key: &Ed25519KeyPair, payload: &Value) -> Value {
+        let payload = serde_json::to_vec(payload).unwrap_or_default();
+        json!({
+            "payload": BASE64.encode(&payload),
+            "signature": BASE64.encode(key.sign(&payload)),
+        })
+    }
+
+    #[test]
+    fn verifies_caches_and_refuses_tampered_or_older_manifests() -> anyhow::Result<()> {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[7; 32])
+            .map_err(|err| anyhow::anyhow!("{err}"))?;
+        let public_key = hex::encode(key.public_key().as_ref());
+        let pricing = json!({"openai-gpt-image-1": {"cost_per_image_usd": 0.05}});
+        let v2 = signed_manifest(
+            &key,
+            &json!({"version": 2, "issued_at": "2024-06-01", "pricing": pricing}),
+        );
+        let mut tampered = v2.clone();
+        tampered["payload"] = json!(BASE64.encode(serde_json::to_vec(
+            &json!({"version": 2, "pricing": {"openai-gpt-image-1": {"cost_per_image_usd": 0.0}}})
+        )?));
+        let v1 = signed_manifest(&key, &json!({"version": 1, "pricing": pricing}));
+        let server = MockServer::start()?;
+        for manifest in [&v2, &v2, &tampered, &v1] {
+            server.mock(
+                "GET",
+                "/pricing.json",
+                MockResponse::json(200, manifest.clone()),
+            );
+        }
+
+        let temp = tempfile::tempdir()?;
+        let cache = temp.path().join("pricing_remote.json");
+        let source = PricingSource::new(&server.url_for("pricing.json"), &public_key)?;
+        let update = update_pricing(&source, &cache)?;
+        assert_eq!(
+            (update.version, update.models, update.changed),
+            (2, 1, true)
+        );
+        assert_eq!(
+            cached_remote_pricing(&cache),
+            Some(json!({"openai-gpt-image-1": {"cost_per_image_usd": 0.05}}))
+        );
+        assert!(!update_pricing(&source, &cache)?.changed);
+        let err = update_pricing(&source, &cache).expect_err("tampered payload");
+        assert!(err.to_string().contains("signature does not verify"));
+        let err = update_pricing(&source, &cache).expect_err("rollback");
+        assert!(err.to_string().contains("older than the cached version 2"));
+
+        let written: Value = serde_json::from_str(&fs::read_to_string(&cache)?)?;
+        assert!(!is_stale(&cache, 24.0, Utc::now()));
+        assert!(is_stale(
+            &cache,
+            24.0,
+            Utc::now() + ChronoDuration::hours(25)
+        ));
+        assert!(is_stale(
+            &temp.path().join("missing.json"),
+            24.0,
+            Utc::now()
+        ));
+        assert_eq!(written["issued_at"], json!("2024-06-01"));
+
+        let rows = |value: Value| value.as_object().cloned().unwrap_or_default();
+        assert!(validate_pricing_table(&rows(json!({"m": {"cost_per_image_usd": -1}}))).is_err());
+        assert!(
+            validate_pricing_table(&rows(json!({"m": {"cost_per_image_usd": "cheap"}}))).is_err()
+        );
+        assert!(validate_pricing_table(&rows(json!({}))).is_err());
+        assert!(PricingSource::new("https://x", "abcd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_manifests_are_refused() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server.mock(
+            "GET",
+            "/pricing.json",
+            MockResponse::bytes(200, "application/json", vec![b' '; 2 * 1024 * 1024]),
+        );
+        let temp = tempfile::tempdir()?;
+        let cache = temp.path().join("pricing_remote.json");
+        let source = PricingSource::new(&server.url_for("pricing.json"), &hex::encode([7; 32]))?;
+        let err = update_pricing(&source, &cache).expect_err("oversized manifest");
+        assert!(err.to_string().contains("exceeds 1048576 bytes"));
+        assert!(!cache.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use brood_contracts::events::EventWriter;
+use serde_json::{json, Map, Value};
+
+/// Provider log excerpts longer than this keep only their tail.
+const PROGRESS_LOG_MAX_CHARS: usize = 400;
+/// Log lines carried by one `generation_progress` event.
+const PROGRESS_LOG_MAX_LINES: usize = 3;
+
+struct ProgressTarget {
+    events: EventWriter,
+    version_id: String,
+    provider: String,
+    model: String,
+}
+
+thread_local! {
+    static TARGET: RefCell<Option<ProgressTarget>> = const { RefCell::new(None) };
+}
+
+/// Turns [`report_generation_progress`] calls made on this thread into
+/// `generation_progress` events until dropped. Providers poll on the
+/// engine's thread, so they can report without holding the writer.
+pub(crate) struct ProgressScope;
+
+impl ProgressScope {
+    pub(crate) fn begin(
+        events: &EventWriter,
+        version_id: &str,
+        provider: &str,
+        model: &str,
+    ) -> Self {
+        TARGET.with(|cell| {
+            *cell.borrow_mut() = Some(ProgressTarget {
+                events: events.clone(),
+                version_id: version_id.to_string(),
+                provider: provider.to_string(),
+                model: model.to_string(),
+            })
+        });
+        Self
+    }
+}
+
+impl Drop for ProgressScope {
+    fn drop(&mut self) {
+        TARGET.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
+/// One poll of a pending provider job. `percent` is 0–100 when the provider
+/// reports it; `logs` is the provider's log text so far.
+pub(crate) fn report_generation_progress(
+    status: &str,
+    elapsed: Duration,
+    percent: Option<f64>,
+    logs: Option<&str>,
+) {
+    TARGET.with(|cell| {
+        let target = cell.borrow();
+        let Some(target) = target.as_ref() else {
+            return;
+        };
+        let mut payload = Map::new();
+        payload.insert("version_id".to_string(), json!(target.version_id));
+        payload.insert("provider".to_string(), json!(target.provider));
+        payload.insert("model".to_string(), json!(target.model));
+        payload.insert("status".to_string(), json!(status));
+        payload.insert(
+            "elapsed_s".to_string(),
+            json!((elapsed.as_secs_f64() * 10.0).round() / 10.0),
+        );
+        payload.insert(
+            "percent".to_string(),
+            percent.map_or(Value::Null, |value| json!(value.clamp(0.0, 100.0))),
+        );
+        if let Some(excerpt) = logs.and_then(log_excerpt) {
+            payload.insert("logs".to_string(), json!(excerpt));
+        }
+        // Progress is advisory; a failed write must not fail the generation.
+        let _ = target.events.emit("generation_progress", payload);
+    });
+}
+
+/// Last few non-empty log lines, tail-capped. Carriage returns split lines
+/// too, so tqdm redraws count as separate lines.
+fn log_excerpt(logs: &str) -> Option<String> {
+    let lines: Vec<&str> = logs
+        .split(['\n', '\r'])
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let tail = lines[lines.len().saturating_sub(PROGRESS_LOG_MAX_LINES)..].join("\n");
+    if tail.is_empty() {
+        return None;
+    }
+    let skip = tail.chars().count().saturating_sub(PROGRESS_LOG_MAX_CHARS);
+    Some(tail.chars().skip(skip).collect())
+}
+
+/// Latest tqdm-style percentage (` 45%|████▌ | 9/20`) in Replicate logs.
+pub(crate) fn percent_from_logs(logs: &str) -> Option<f64> {
+    logs.split(['\n', '\r']).rev().find_map(|line| {
+        let end = line.find("%|")?;
+        let start = line[..end]
+            .rfind(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+            .map_or(0, |index| index + 1);
+        line[start..end].parse::<f64>().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use brood_contracts::events::EventWriter;
+    use serde_json::{json, Value};
+
+    use super::{percent_from_logs, report_generation_progress, ProgressScope};
+
+    #[test]
+    fn reports_only_inside_a_scope() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("events.jsonl");
+        let events = EventWriter::new(&path, "run-progress");
+        report_generation_progress("starting", Duration::from_secs(1), None, None);
+        {
+            let _scope = ProgressScope::begin(&events, "v1", "replicate", "flux-dev");
+            report_generation_progress(
+                "processing",
+                Duration::from_millis(2340),
+                percent_from_logs("Using seed 7\n 10%|█ | 2/20\r 45%|████▌ | 9/20"),
+                Some("Using seed 7\n 10%|█ | 2/20\r 45%|████▌ | 9/20\n"),
+            );
+        }
+        report_generation_progress("processing", Duration::from_secs(3), None, None);
+
+        let rows: Vec<Value> = fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["type"], json!("generation_progress"));
+        assert_eq!(rows[0]["version_id"], json!("v1"));
+        assert_eq!(rows[0]["status"], json!("processing"));
+        assert_eq!(rows[0]["elapsed_s"], json!(2.3));
+        assert_eq!(rows[0]["percent"], json!(45.0));
+        assert_eq!(
+            rows[0]["logs"],
+            json!("Using seed 7\n 10%|█ | 2/20\n 45%|████▌ | 9/20")
+        );
+        assert_eq!(percent_from_logs("no progress bar here"), None);
+        Ok(())
+    }
+}
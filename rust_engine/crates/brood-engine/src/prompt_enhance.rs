@@ -0,0 +1,185 @@
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use brood_contracts::models::ModelSpec;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use serde_json::{json, Map, Value};
+
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{error_chain_text, map_object, NativeEngine, ProviderConfig};
+
+/// Instruction sent ahead of the user prompt.
+const ENHANCE_INSTRUCTIONS: &str = "Rewrite the user's image prompt into a single richer prompt \
+for an image generation model. Keep the subject and intent, add concrete detail about \
+composition, lighting, style and materials, and do not add text overlays. Reply with the \
+prompt only.";
+
+/// Suffix the dryrun text model appends, so tests can tell an enhanced prompt apart.
+pub const DRYRUN_ENHANCE_SUFFIX: &str = "detailed composition, soft natural lighting";
+
+const ENHANCE_MAX_CHARS: usize = 2000;
+
+/// Result of [`PromptEnhancer::enhance`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptEnhancement {
+    pub original: String,
+    pub enhanced: String,
+    pub model: String,
+    /// `openai`, `gemini`, `openrouter` or `dryrun`.
+    pub transport: String,
+    pub latency_s: f64,
+    pub usage: TokenUsage,
+    /// Priced from the text model's pricing row by the engine; zero from
+    /// [`PromptEnhancer::enhance`] itself.
+    pub cost_usd: f64,
+}
+
+/// Rewrites prompts with the engine's text model before image generation.
+#[derive(Debug, Clone)]
+pub struct PromptEnhancer {
+    client: TextModelClient,
+}
+
+impl PromptEnhancer {
+    pub fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    pub fn enhance(&self, model: &ModelSpec, prompt: &str) -> Result<PromptEnhancement> {
+        if !model.supports("text") {
+            bail!("model '{}' is not a text model", model.name);
+        }
+        let started = Instant::now();
+        let (transport, raw, usage) = if model.provider == "dryrun" {
+            let raw = format!("{}, {DRYRUN_ENHANCE_SUFFIX}", prompt.trim());
+            let usage = TokenUsage::estimate(model, ENHANCE_INSTRUCTIONS, prompt, &raw);
+            ("dryrun", raw, usage)
+        } else {
+            let reply = self
+                .client
+                .complete(model, ENHANCE_INSTRUCTIONS, prompt, None)?;
+            (reply.transport, reply.text, reply.usage)
+        };
+        let enhanced = clean_enhanced_prompt(&raw);
+        if enhanced.is_empty() {
+            bail!("{transport} text model returned an empty prompt");
+        }
+        Ok(PromptEnhancement {
+            original: prompt.to_string(),
+            enhanced,
+            model: model.name.clone(),
+            transport: transport.to_string(),
+            latency_s: started.elapsed().as_secs_f64(),
+            usage,
+            cost_usd: 0.0,
+        })
+    }
+}
+
+impl NativeEngine {
+    /// Rewrites `prompt` with the text model when `settings.enhance_prompt`
+    /// is set. Enhancement is best effort: without a usable text model, or
+    /// when the call fails, generation keeps the original prompt and a
+    /// warning is added instead.
+    pub(crate) fn enhance_prompt(
+        &mut self,
+        prompt: &str,
+        settings: &Map<String, Value>,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<Option<PromptEnhancement>> {
+        if !settings
+            .get("enhance_prompt")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+        {
+            return Ok(None);
+        }
+        let model = self
+            .text_model
+            .as_deref()
+            .and_then(|name| self.model_selector.registry.get(name))
+            .cloned();
+        let outcome = match &model {
+            Some(model) => {
+                self.prompt_enhancer
+                    .enhance(model, prompt)
+                    .and_then(|mut enhancement| {
+                        enhancement.cost_usd = self.record_text_call(model, enhancement.usage)?;
+                        Ok(enhancement)
+                    })
+            }
+            None => Err(anyhow::anyhow!(
+                "text model '{}' is not registered",
+                self.text_model.as_deref().unwrap_or("none")
+            )),
+        };
+        match outcome {
+            Ok(enhancement) => {
+                self.events.emit(
+                    "prompt_enhanced",
+                    map_object(json!({
+                        "original_prompt": enhancement.original,
+                        "enhanced_prompt": enhancement.enhanced,
+                        "model": enhancement.model,
+                        "transport": enhancement.transport,
+                        "latency_s": enhancement.latency_s,
+                        "input_tokens": enhancement.usage.input_tokens,
+                        "output_tokens": enhancement.usage.output_tokens,
+                        "cost_usd": enhancement.cost_usd,
+                    })),
+                )?;
+                Ok(Some(enhancement))
+            }
+            Err(err) => {
+                let error = error_chain_text(&err, 512);
+                self.events.emit(
+                    "prompt_enhance_failed",
+                    map_object(json!({
+                        "prompt": prompt,
+                        "model": self.text_model,
+                        "error": error,
+                    })),
+                )?;
+                warnings.push(Warning::new(
+                    WarningCode::BackendFallback,
+                    format!("Prompt enhancement failed; using the original prompt ({error})."),
+                ));
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Strips wrapping quotes and a leading `Prompt:` label, collapses
+/// whitespace and caps the length.
+fn clean_enhanced_prompt(raw: &str) -> String {
+    let mut text = raw.trim();
+    if let Some((label, rest)) = text.split_once(':') {
+        if label.trim().eq_ignore_ascii_case("prompt") {
+            text = rest.trim();
+        }
+    }
+    let text = text.trim_matches(|ch| matches!(ch, '"' | '\'' | '`' | '“' | '”'));
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(ENHANCE_MAX_CHARS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clean_enhanced_prompt;
+
+    #[test]
+    fn cleans_labels_quotes_and_whitespace() {
+        assert_eq!(
+            clean_enhanced_prompt("Prompt: \"a red fox,\n  golden hour\"\n"),
+            "a red fox, golden hour"
+        );
+        assert_eq!(clean_enhanced_prompt("  plain  "), "plain");
+    }
+}
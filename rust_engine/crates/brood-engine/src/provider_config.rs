@@ -0,0 +1,477 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::models::ModelSpec;
+use reqwest::blocking::Client as HttpClient;
+use serde_json::{Map, Value};
+
+use super::credentials::resolve_api_key;
+use super::http_client::shared_http_client;
+use super::non_empty_env;
+
+/// Overrides the location of the provider config file.
+pub const PROVIDER_CONFIG_ENV: &str = "BROOD_PROVIDERS_CONFIG";
+
+/// `type` value that declares an extra OpenAI-compatible image endpoint.
+const OPENAI_COMPATIBLE: &str = "openai_compatible";
+
+/// `type` value that declares a provider implemented by a WASM module.
+const WASM: &str = "wasm";
+
+struct BuiltinProvider {
+    name: &'static str,
+    /// Environment variables that override the base URL, in order.
+    base_envs: &'static [&'static str],
+    base_url: &'static str,
+    api_key_envs: &'static [&'static str],
+}
+
+const BUILTIN_PROVIDERS: &[BuiltinProvider] = &[
+    BuiltinProvider {
+        name: "openai",
+        base_envs: &["OPENAI_API_BASE"],
+        base_url: "https://api.openai.com/v1",
+        api_key_envs: &["OPENAI_API_KEY", "OPENAI_API_KEY_BACKUP"],
+    },
+    BuiltinProvider {
+        name: "replicate",
+        base_envs: &["REPLICATE_API_BASE"],
+        base_url: "https://api.replicate.com/v1",
+        api_key_envs: &["REPLICATE_API_TOKEN", "REPLICATE_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "stability",
+        base_envs: &["STABILITY_API_BASE"],
+        base_url: "https://api.stability.ai",
+        api_key_envs: &["STABILITY_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "fal",
+        base_envs: &["FAL_API_BASE"],
+        base_url: "https://fal.run",
+        api_key_envs: &["FAL_KEY", "FAL_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "gemini",
+        base_envs: &["GEMINI_API_BASE"],
+        base_url: "https://generativelanguage.googleapis.com/v1beta",
+        api_key_envs: &["GEMINI_API_KEY", "GOOGLE_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "imagen",
+        base_envs: &["IMAGEN_API_BASE", "GEMINI_API_BASE"],
+        base_url: "https://generativelanguage.googleapis.com/v1beta",
+        api_key_envs: &["IMAGEN_API_KEY", "GEMINI_API_KEY", "GOOGLE_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "flux",
+        base_envs: &["FLUX_API_BASE"],
+        base_url: "https://api.bfl.ai/v1",
+        api_key_envs: &["BFL_API_KEY", "FLUX_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "recraft",
+        base_envs: &["RECRAFT_API_BASE"],
+        base_url: "https://external.api.recraft.ai/v1",
+        api_key_envs: &["RECRAFT_API_KEY", "RECRAFT_API_TOKEN"],
+    },
+    BuiltinProvider {
+        name: "openrouter",
+        base_envs: &["OPENROUTER_API_BASE", "OPENROUTER_BASE_URL"],
+        base_url: "https://openrouter.ai/api/v1",
+        api_key_envs: &["OPENROUTER_API_KEY"],
+    },
+    BuiltinProvider {
+        name: "runway",
+        base_envs: &["RUNWAY_API_BASE"],
+        base_url: "https://api.dev.runwayml.com/v1",
+        api_key_envs: &["RUNWAYML_API_SECRET", "RUNWAY_API_KEY"],
+    },
+];
+
+/// Resolved connection settings for one provider.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProviderSettings {
+    /// Provider name, which a [`CredentialsProvider`] is asked keys for.
+    ///
+    /// [`CredentialsProvider`]: crate::CredentialsProvider
+    pub provider: String,
+    pub base_url: String,
+    /// Environment variables holding the API key; the first non-empty wins.
+    pub api_key_envs: Vec<String>,
+    pub default_model: Option<String>,
+    pub timeout_s: Option<f64>,
+}
+
+impl ProviderSettings {
+    pub fn api_key(&self) -> Option<String> {
+        resolve_api_key(&self.provider, &self.api_key_envs)
+    }
+
+    /// The shared client for this provider's timeout.
+    pub(crate) fn http_client(&self) -> Result<HttpClient> {
+        shared_http_client(self.timeout_s)
+    }
+}
+
+/// An extra provider: an endpoint that speaks the OpenAI images API, or a
+/// WASM module when `wasm` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomEndpoint {
+    pub name: String,
+    pub settings: ProviderSettings,
+    pub models: Vec<String>,
+    pub wasm: Option<WasmSettings>,
+}
+
+/// How a `"type": "wasm"` provider is sandboxed and authenticated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmSettings {
+    /// The compiled module; relative paths resolve against the config file.
+    pub module: PathBuf,
+    /// Hosts besides the `base_url` host the module may send requests to or
+    /// download images from.
+    pub allowed_hosts: Vec<String>,
+    /// Header the host puts the API key in: `Authorization` carries
+    /// `Bearer <key>`, any other header the bare key.
+    pub auth_header: String,
+}
+
+/// Declarative provider settings from `~/.brood/providers.json`.
+///
+/// Built-in providers may override `base_url`, `api_key_env`,
+/// `default_model` and `timeout_s`; entries with
+/// `"type": "openai_compatible"` or `"type": "wasm"` add new providers. A
+/// `*_API_BASE` environment variable still beats the file's `base_url`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProviderConfig {
+    overrides: BTreeMap<String, ProviderSettings>,
+    custom: Vec<CustomEndpoint>,
+}
+
+impl ProviderConfig {
+    /// `$BROOD_PROVIDERS_CONFIG`, else `~/.brood/providers.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = non_empty_env(PROVIDER_CONFIG_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        env::var_os("HOME")
+            .map(PathBuf::from)
+            .map(|home| home.join(".brood").join("providers.json"))
+    }
+
+    /// Loads the default config file; a missing file means no overrides.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::default_path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read provider config {}", path.display()))?;
+        let mut config = Self::parse(&raw)
+            .with_context(|| format!("Invalid provider config {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_modules(dir);
+        }
+        Ok(config)
+    }
+
+    /// Makes relative WASM module paths relative to `dir`.
+    pub fn resolve_modules(&mut self, dir: &Path) {
+        for wasm in self.custom.iter_mut().filter_map(|e| e.wasm.as_mut()) {
+            if wasm.module.is_relative() {
+                wasm.module = dir.join(&wasm.module);
+            }
+        }
+    }
+
+    pub fn parse(raw: &str) -> Result<Self> {
+        let payload: Value = serde_json::from_str(raw)?;
+        let Some(entries) = payload.get("providers").and_then(Value::as_object) else {
+            bail!("expected a top-level \"providers\" object");
+        };
+        let mut config = Self::default();
+        for (raw_name, entry) in entries {
+            let name = raw_name.trim().to_ascii_lowercase();
+            let Some(entry) = entry.as_object() else {
+                bail!("provider '{name}' must be an object");
+            };
+            let builtin = builtin(&name);
+            let kind = entry.get("type").and_then(Value::as_str).map(str::trim);
+            let is_wasm = kind == Some(WASM);
+            let is_custom = match kind {
+                Some(OPENAI_COMPATIBLE | WASM) => true,
+                Some(other) => bail!("provider '{name}' has unknown type '{other}'"),
+                None => false,
+            };
+            if is_custom && (builtin.is_some() || name == "dryrun") {
+                bail!("provider '{name}' is built in; drop its \"type\" to override it");
+            }
+            if !is_custom && builtin.is_none() {
+                bail!(
+                    "unknown provider '{name}'; set \"type\": \"{OPENAI_COMPATIBLE}\" to add an endpoint"
+                );
+            }
+
+            let mut settings = ProviderSettings {
+                provider: name.clone(),
+                ..ProviderSettings::default()
+            };
+            if let Some(value) = entry.get("base_url") {
+                let Some(base_url) = value.as_str().map(str::trim).filter(|v| !v.is_empty()) else {
+                    bail!("provider '{name}' base_url must be a non-empty string");
+                };
+                settings.base_url = base_url.trim_end_matches('/').to_string();
+            }
+            settings.api_key_envs = match entry.get("api_key_env") {
+                None => Vec::new(),
+                Some(Value::String(key)) => vec![key.trim().to_string()],
+                Some(Value::Array(keys)) => keys
+                    .iter()
+                    .map(|key| key.as_str().map(|key| key.trim().to_string()))
+                    .collect::<Option<Vec<_>>>()
+                    .with_context(|| format!("provider '{name}' api_key_env must be strings"))?,
+                Some(_) => bail!("provider '{name}' api_key_env must be a string or list"),
+            };
+            settings.api_key_envs.retain(|key| !key.is_empty());
+            settings.default_model = entry
+                .get("default_model")
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string);
+            if let Some(value) = entry.get("timeout_s") {
+                let Some(timeout_s) = value.as_f64().filter(|value| *value > 0.0) else {
+                    bail!("provider '{name}' timeout_s must be a positive number");
+                };
+                settings.timeout_s = Some(timeout_s);
+            }
+
+            if !is_custom {
+                config.overrides.insert(name, settings);
+                continue;
+            }
+            if settings.base_url.is_empty() {
+                bail!("provider '{name}' needs a base_url");
+            }
+            // A WASM provider may talk to a keyless endpoint.
+            if settings.api_key_envs.is_empty() && !is_wasm {
+                bail!("provider '{name}' needs an api_key_env");
+            }
+            let wasm = if is_wasm {
+                Some(parse_wasm_settings(&name, entry)?)
+            } else {
+                None
+            };
+            let mut models = match entry.get("models") {
+                None => Vec::new(),
+                Some(Value::Array(models)) => models
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::trim)
+                    .filter(|model| !model.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                Some(_) => bail!("provider '{name}' models must be a list"),
+            };
+            if let Some(default_model) = &settings.default_model {
+                if !models.contains(default_model) {
+                    models.insert(0, default_model.clone());
+                }
+            }
+            if models.is_empty() {
+                bail!("provider '{name}' needs at least one model");
+            }
+            config.custom.push(CustomEndpoint {
+                name,
+                settings,
+                models,
+                wasm,
+            });
+        }
+        Ok(config)
+    }
+
+    /// Settings for a built-in provider: environment, then file, then the
+    /// built-in default.
+    pub fn settings(&self, provider: &str) -> ProviderSettings {
+        let mut settings = self.overrides.get(provider).cloned().unwrap_or_default();
+        settings.provider = provider.to_string();
+        let Some(builtin) = builtin(provider) else {
+            return settings;
+        };
+        if let Some(base_url) = builtin.base_envs.iter().find_map(|key| non_empty_env(key)) {
+            settings.base_url = base_url.trim_end_matches('/').to_string();
+        } else if settings.base_url.is_empty() {
+            settings.base_url = builtin.base_url.to_string();
+        }
+        if settings.api_key_envs.is_empty() {
+            settings.api_key_envs = builtin
+                .api_key_envs
+                .iter()
+                .map(|key| key.to_string())
+                .collect();
+        }
+        settings
+    }
+
+    /// Built-in providers, then custom endpoints.
+    pub fn provider_names(&self) -> Vec<String> {
+        BUILTIN_PROVIDERS
+            .iter()
+            .map(|provider| provider.name.to_string())
+            .chain(self.custom.iter().map(|endpoint| endpoint.name.clone()))
+            .collect()
+    }
+
+    pub fn is_builtin(name: &str) -> bool {
+        builtin(name).is_some()
+    }
+
+    pub fn custom_endpoints(&self) -> &[CustomEndpoint] {
+        &self.custom
+    }
+
+    /// `(provider, model)` pairs to prefer when a provider is picked without
+    /// an explicit model.
+    pub fn default_models(&self) -> Vec<(String, String)> {
+        let overrides = self.overrides.iter();
+        let custom = self
+            .custom
+            .iter()
+            .map(|endpoint| (&endpoint.name, &endpoint.settings));
+        overrides
+            .chain(custom)
+            .filter_map(|(name, settings)| {
+                settings
+                    .default_model
+                    .clone()
+                    .map(|model| (name.clone(), model))
+            })
+            .collect()
+    }
+
+    /// Registry entries for the models of custom endpoints.
+    pub fn model_specs(&self) -> Vec<ModelSpec> {
+        self.custom
+            .iter()
+            .flat_map(|endpoint| {
+                endpoint.models.iter().map(|model| ModelSpec {
+                    name: model.clone(),
+                    provider: endpoint.name.clone(),
+                    capabilities: vec!["image".to_string(), "edit".to_string()],
+                    context_window: None,
+                    pricing_key: None,
+                    latency_key: None,
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_wasm_settings(name: &str, entry: &Map<String, Value>) -> Result<WasmSettings> {
+    if !cfg!(feature = "wasm") {
+        bail!("provider '{name}' needs brood built with the \"wasm\" feature");
+    }
+    let Some(module) = entry
+        .get("module")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|module| !module.is_empty())
+    else {
+        bail!("provider '{name}' needs a module path");
+    };
+    let allowed_hosts = match entry.get("allowed_hosts") {
+        None => Vec::new(),
+        Some(Value::Array(hosts)) => hosts
+            .iter()
+            .map(|host| host.as_str().map(|host| host.trim().to_ascii_lowercase()))
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("provider '{name}' allowed_hosts must be strings"))?,
+        Some(_) => bail!("provider '{name}' allowed_hosts must be a list"),
+    };
+    let auth_header = match entry.get("auth_header") {
+        None => "Authorization".to_string(),
+        Some(Value::String(header)) if !header.trim().is_empty() => header.trim().to_string(),
+        Some(_) => bail!("provider '{name}' auth_header must be a non-empty string"),
+    };
+    Ok(WasmSettings {
+        module: PathBuf::from(module),
+        allowed_hosts,
+        auth_header,
+    })
+}
+
+fn builtin(name: &str) -> Option<&'static BuiltinProvider> {
+    BUILTIN_PROVIDERS
+        .iter()
+        .find(|provider| provider.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProviderConfig;
+
+    #[test]
+    fn parses_overrides_and_custom_endpoints() -> anyhow::Result<()> {
+        let config = ProviderConfig::parse(
+            r#"{
+                "providers": {
+                    "Recraft": {
+                        "base_url": "https://recraft.proxy/v1/",
+                        "api_key_env": "TEAM_RECRAFT_KEY",
+                        "default_model": "recraft-v3-svg",
+                        "timeout_s": 90
+                    },
+                    "local-sd": {
+                        "type": "openai_compatible",
+                        "base_url": "http://localhost:8080/v1",
+                        "api_key_env": ["LOCAL_SD_KEY"],
+                        "models": ["sdxl-turbo"],
+                        "default_model": "sd3-medium"
+                    }
+                }
+            }"#,
+        )?;
+
+        let recraft = config.settings("recraft");
+        assert_eq!(recraft.base_url, "https://recraft.proxy/v1");
+        assert_eq!(recraft.api_key_envs, vec!["TEAM_RECRAFT_KEY".to_string()]);
+        assert_eq!(recraft.timeout_s, Some(90.0));
+
+        let fal = config.settings("fal");
+        assert_eq!(fal.base_url, "https://fal.run");
+        assert_eq!(fal.api_key_envs, vec!["FAL_KEY", "FAL_API_KEY"]);
+
+        let endpoint = &config.custom_endpoints()[0];
+        assert_eq!(endpoint.name, "local-sd");
+        assert_eq!(endpoint.models, vec!["sd3-medium", "sdxl-turbo"]);
+        assert_eq!(
+            config.default_models(),
+            vec![
+                ("recraft".to_string(), "recraft-v3-svg".to_string()),
+                ("local-sd".to_string(), "sd3-medium".to_string()),
+            ]
+        );
+        let specs = config.model_specs();
+        assert_eq!(specs.len(), 2);
+        assert!(specs.iter().all(|spec| spec.provider == "local-sd"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_or_incomplete_entries() {
+        for raw in [
+            r#"{"providers": {"midjourney": {"base_url": "https://mj"}}}"#,
+            r#"{"providers": {"openai": {"type": "openai_compatible"}}}"#,
+            r#"{"providers": {"proxy": {"type": "openai_compatible", "base_url": "http://x", "models": ["m"]}}}"#,
+            r#"{"providers": {"acme": {"type": "wasm", "base_url": "http://x", "models": ["m"]}}}"#,
+            r#"{"providers": {"flux": {"timeout_s": 0}}}"#,
+            r#"{"flux": {}}"#,
+        ] {
+            assert!(ProviderConfig::parse(raw).is_err(), "{raw}");
+        }
+    }
+}
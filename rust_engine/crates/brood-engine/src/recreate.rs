@@ -0,0 +1,757 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::models::ModelSpec;
+use brood_contracts::runs::receipts::write_receipt;
+use image::imageops::FilterType;
+use image::RgbImage;
+use serde_json::{json, Map, Value};
+
+use super::compare::mean_ssim;
+use super::dedup::image_dhash;
+use super::palette::{color_bucket, color_name, dominant_colors, COLOR_BUCKETS};
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{error_chain_text, map_object, NativeEngine, ProviderConfig};
+
+/// Instruction sent with the reference image.
+const ANALYSIS_INSTRUCTIONS: &str = "You describe reference images so an image generation model \
+can recreate them. Reply with JSON only: {\"subject\": \"<what is shown, specific>\", \
+\"style\": \"<art style or photographic style>\", \"medium\": \"<photo, oil painting, 3D render, \
+...>\", \"composition\": \"<framing, camera angle, subject placement>\", \"lighting\": \
+\"<light direction, quality and mood>\"}.";
+
+/// Similarity at which the loop stops early.
+pub const RECREATE_DEFAULT_TARGET: f64 = 0.8;
+pub const RECREATE_DEFAULT_ITERATIONS: u32 = 3;
+
+/// Dominant colours kept in a [`RecreateSpec`] palette.
+const PALETTE_COLORS: usize = 5;
+/// Edge the reference and candidates are reduced to before measuring.
+const ANALYSIS_EDGE: u32 = 64;
+const FIELD_MAX_CHARS: usize = 300;
+
+/// Providers whose models follow comma-separated keyword prompts better
+/// than prose.
+const KEYWORD_PROMPT_PROVIDERS: &[&str] = &["flux", "stability", "replicate", "fal"];
+
+/// What a reference image shows, as extracted by [`NativeEngine::analyze_reference`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecreateSpec {
+    pub subject: String,
+    pub style: String,
+    pub medium: String,
+    pub composition: String,
+    pub lighting: String,
+    /// Dominant colours, most common first, as `#rrggbb`.
+    pub palette: Vec<String>,
+    /// `vision` (described by a vision model), `receipt` (the prompt a
+    /// Brood receipt recorded for the image) or `local` (pixels only).
+    pub source: String,
+    /// Vision model, or the model named in the receipt.
+    pub model: Option<String>,
+}
+
+impl RecreateSpec {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "subject": self.subject,
+            "style": self.style,
+            "medium": self.medium,
+            "composition": self.composition,
+            "lighting": self.lighting,
+            "palette": self.palette,
+            "source": self.source,
+            "model": self.model,
+        })
+    }
+}
+
+/// How close a candidate is to the reference, each part in `0.0..=1.0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecreateScore {
+    /// Difference-hash agreement: layout of light and dark areas.
+    pub structure: f64,
+    /// Mean SSIM of the luma channels at 64 px.
+    pub ssim: f64,
+    /// Colour histogram intersection.
+    pub palette: f64,
+    pub overall: f64,
+}
+
+impl RecreateScore {
+    pub fn to_map(&self) -> Map<String, Value> {
+        map_object(json!({
+            "structure": self.structure,
+            "ssim": self.ssim,
+            "palette": self.palette,
+            "overall": self.overall,
+        }))
+    }
+}
+
+/// Knobs for [`NativeEngine::recreate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecreateOptions {
+    /// Generation settings for every candidate (`n` is set per iteration).
+    pub settings: Map<String, Value>,
+    pub images_per_iteration: u64,
+    pub max_iterations: u32,
+    pub target_score: f64,
+    /// Vision model for the analysis; defaults to the engine's text model.
+    pub analysis_model: Option<String>,
+}
+
+impl Default for RecreateOptions {
+    fn default() -> Self {
+        Self {
+            settings: Map::new(),
+            images_per_iteration: 2,
+            max_iterations: RECREATE_DEFAULT_ITERATIONS,
+            target_score: RECREATE_DEFAULT_TARGET,
+            analysis_model: None,
+        }
+    }
+}
+
+/// Result of [`NativeEngine::recreate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecreateOutcome {
+    pub spec: RecreateSpec,
+    /// Prompt compiled for the first iteration.
+    pub prompt: String,
+    /// Best artifact row, with its score under `similarity`.
+    pub best: Option<Map<String, Value>>,
+    pub best_score: f64,
+    pub iterations: u32,
+}
+
+/// Asks a vision-capable model to describe a reference image.
+#[derive(Debug, Clone)]
+pub(crate) struct ReferenceAnalyzer {
+    client: TextModelClient,
+}
+
+impl ReferenceAnalyzer {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    fn describe(
+        &self,
+        model: &ModelSpec,
+        reference: &Path,
+    ) -> Result<(Map<String, Value>, TokenUsage)> {
+        if !model.supports("vision") {
+            bail!("analysis model '{}' is not vision-capable", model.name);
+        }
+        let reply = self.client.complete(
+            model,
+            ANALYSIS_INSTRUCTIONS,
+            "Describe this reference image.",
+            Some(reference),
+        )?;
+        let fields = parse_analysis(&reply.text)
+            .with_context(|| format!("{} analysis reply had no subject", reply.transport))?;
+        Ok((fields, reply.usage))
+    }
+}
+
+impl NativeEngine {
+    /// Extracts subject, style, palette and composition from `reference`.
+    /// A receipt next to the image supplies the original prompt; otherwise
+    /// a vision model (`model`, else the text model) describes it. The
+    /// palette, and the composition and lighting when no model can, are
+    /// measured from the pixels. Emits `recreate_analysis`.
+    pub fn analyze_reference(
+        &mut self,
+        reference: &Path,
+        model: Option<&str>,
+    ) -> Result<RecreateSpec> {
+        let pixels = image::open(reference)
+            .with_context(|| format!("failed to read {}", reference.display()))?
+            .to_rgb8();
+        let measured = measure_reference(&pixels);
+        let mut spec = RecreateSpec {
+            subject: reference
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.replace(['-', '_'], " "))
+                .filter(|stem| !stem.trim().is_empty())
+                .unwrap_or_else(|| "reference image".to_string()),
+            style: String::new(),
+            medium: String::new(),
+            composition: measured.composition,
+            lighting: measured.lighting,
+            palette: measured.palette,
+            source: "local".to_string(),
+            model: None,
+        };
+        if let Some((prompt, receipt_model)) = prompt_from_receipts(reference) {
+            spec.subject = prompt;
+            spec.source = "receipt".to_string();
+            spec.model = receipt_model;
+        } else if let Some(model) = model
+            .map(str::to_string)
+            .or_else(|| self.text_model.clone())
+            .and_then(|name| self.model_selector.registry.get(&name).cloned())
+            .filter(|model| model.provider != "dryrun")
+        {
+            // Pixels alone still give a usable spec, so a failed call only
+            // downgrades the analysis.
+            match self.reference_analyzer.describe(&model, reference) {
+                Ok((fields, usage)) => {
+                    self.record_text_call(&model, usage)?;
+                    let field = |key: &str| {
+                        fields
+                            .get(key)
+                            .and_then(Value::as_str)
+                            .map(|text| {
+                                text.trim()
+                                    .chars()
+                                    .take(FIELD_MAX_CHARS)
+                                    .collect::<String>()
+                            })
+                            .filter(|text| !text.is_empty())
+                    };
+                    if let Some(subject) = field("subject") {
+                        spec.subject = subject;
+                    }
+                    spec.style = field("style").unwrap_or_default();
+                    spec.medium = field("medium").unwrap_or_default();
+                    if let Some(composition) = field("composition") {
+                        spec.composition = composition;
+                    }
+                    if let Some(lighting) = field("lighting") {
+                        spec.lighting = lighting;
+                    }
+                    spec.source = "vision".to_string();
+                    spec.model = Some(model.name.clone());
+                }
+                Err(err) => {
+                    self.events.emit(
+                        "recreate_analysis_failed",
+                        map_object(json!({
+                            "reference": reference.to_string_lossy(),
+                            "model": model.name,
+                            "error": error_chain_text(&err, 512),
+                        })),
+                    )?;
+                }
+            }
+        }
+        let mut payload = map_object(spec.to_value());
+        payload.insert("reference".to_string(), json!(reference.to_string_lossy()));
+        self.events.emit("recreate_analysis", payload)?;
+        Ok(spec)
+    }
+
+    /// Analyzes `reference`, then generates candidates from prompts compiled
+    /// for the active provider and scores each against the reference,
+    /// refining the prompt toward the weakest score until `target_score` or
+    /// `max_iterations`. Emits `recreate_score` per candidate,
+    /// `recreate_iteration_update` per round and `recreate_done`.
+    pub fn recreate(
+        &mut self,
+        reference: &Path,
+        options: &RecreateOptions,
+    ) -> Result<RecreateOutcome> {
+        if !reference.is_file() {
+            bail!("reference file not found ({})", reference.display());
+        }
+        let reference_text = reference.to_string_lossy().to_string();
+        let spec = self.analyze_reference(reference, options.analysis_model.as_deref())?;
+        let provider = self.resolve_image_selection()?.model.provider;
+        let base_prompt = compile_recreate_prompt(&spec, &provider);
+        self.events.emit(
+            "recreate_prompt_inferred",
+            map_object(json!({
+                "reference": reference_text,
+                "prompt": base_prompt,
+                "source": spec.source,
+                "model": spec.model,
+            })),
+        )?;
+
+        let mut prompt = base_prompt.clone();
+        let mut best: Option<(Map<String, Value>, RecreateScore)> = None;
+        let mut iterations = 0;
+        let mut failure: Option<String> = None;
+        for iteration in 1..=options.max_iterations.max(1) {
+            iterations = iteration;
+            let mut settings = options.settings.clone();
+            settings.insert("n".to_string(), json!(options.images_per_iteration.max(1)));
+            settings.insert("reference_images".to_string(), json!([reference_text]));
+            let intent = map_object(json!({
+                "action": "recreate",
+                "reference": reference_text,
+                "iteration": iteration,
+                "base_prompt": base_prompt,
+                "prompt_source": spec.source,
+                "caption_model": spec.model,
+                "recreate_spec": spec.to_value(),
+            }));
+            let artifacts = match self.generate(&prompt, settings, intent) {
+                Ok(artifacts) => artifacts,
+                Err(err) => {
+                    failure = Some(err.to_string());
+                    break;
+                }
+            };
+            for artifact in artifacts {
+                let Some(image_path) = artifact.get("image_path").and_then(Value::as_str) else {
+                    continue;
+                };
+                let score = match score_recreation(reference, Path::new(image_path)) {
+                    Ok(score) => score,
+                    Err(err) => {
+                        failure = Some(err.to_string());
+                        break;
+                    }
+                };
+                if let Some(receipt_path) = artifact.get("receipt_path").and_then(Value::as_str) {
+                    if let Err(err) = write_similarity_to_receipt(Path::new(receipt_path), &score) {
+                        failure = Some(err.to_string());
+                        break;
+                    }
+                }
+                let mut payload = score.to_map();
+                payload.insert("iteration".to_string(), json!(iteration));
+                payload.insert(
+                    "artifact_id".to_string(),
+                    artifact.get("artifact_id").cloned().unwrap_or(Value::Null),
+                );
+                self.events.emit("recreate_score", payload)?;
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, best_score)| score.overall > best_score.overall)
+                {
+                    let mut enriched = artifact.clone();
+                    enriched.insert("similarity".to_string(), Value::Object(score.to_map()));
+                    best = Some((enriched, score));
+                }
+            }
+            if failure.is_some() {
+                break;
+            }
+            let best_score = best.as_ref().map_or(0.0, |(_, score)| score.overall);
+            self.events.emit(
+                "recreate_iteration_update",
+                map_object(json!({
+                    "iteration": iteration,
+                    "similarity": best_score,
+                    "best_artifact_id": best
+                        .as_ref()
+                        .and_then(|(artifact, _)| artifact.get("artifact_id")),
+                })),
+            )?;
+            if best_score >= options.target_score {
+                break;
+            }
+            if let Some((_, score)) = &best {
+                prompt = refine_recreate_prompt(&base_prompt, &spec, score);
+            }
+        }
+
+        let best_score = best.as_ref().map_or(0.0, |(_, score)| score.overall);
+        let best = best.map(|(artifact, _)| artifact);
+        self.events.emit(
+            "recreate_done",
+            map_object(json!({
+                "reference": reference_text,
+                "best_artifact_id": best.as_ref().and_then(|artifact| artifact.get("artifact_id")),
+                "best_score": best_score,
+                "iterations": iterations,
+                "success": failure.is_none(),
+                "error": failure,
+            })),
+        )?;
+        if let Some(err) = failure {
+            bail!(err);
+        }
+        Ok(RecreateOutcome {
+            spec,
+            prompt: base_prompt,
+            best,
+            best_score,
+            iterations,
+        })
+    }
+}
+
+/// Turns a spec into a prompt for `provider`: prose for instruction-following
+/// models, comma-separated keywords for diffusion endpoints. A prompt
+/// recovered from a receipt is used as is.
+pub fn compile_recreate_prompt(spec: &RecreateSpec, provider: &str) -> String {
+    if spec.source == "receipt" {
+        return spec.subject.clone();
+    }
+    let colors: Vec<String> = spec
+        .palette
+        .iter()
+        .map(|hex| match color_name(hex) {
+            Some(name) => format!("{name} ({hex})"),
+            None => hex.clone(),
+        })
+        .collect();
+    if KEYWORD_PROMPT_PROVIDERS.contains(&provider) {
+        let mut parts: Vec<String> = [
+            &spec.subject,
+            &spec.medium,
+            &spec.style,
+            &spec.composition,
+            &spec.lighting,
+        ]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .cloned()
+        .collect();
+        if !colors.is_empty() {
+            parts.push(format!("color palette {}", colors.join(", ")));
+        }
+        return parts.join(", ");
+    }
+    let mut sentences = vec![format!(
+        "Recreate this image: {}.",
+        spec.subject.trim_end_matches('.')
+    )];
+    for (label, value) in [
+        ("Medium", &spec.medium),
+        ("Style", &spec.style),
+        ("Composition", &spec.composition),
+        ("Lighting", &spec.lighting),
+    ] {
+        if !value.is_empty() {
+            sentences.push(format!("{label}: {}.", value.trim_end_matches('.')));
+        }
+    }
+    if !colors.is_empty() {
+        sentences.push(format!("Palette: {}.", colors.join(", ")));
+    }
+    sentences.join(" ")
+}
+
+/// The base prompt plus an instruction aimed at the weakest part of the
+/// best score so far.
+fn refine_recreate_prompt(base_prompt: &str, spec: &RecreateSpec, score: &RecreateScore) -> String {
+    let hint = if score.palette <= score.structure && score.palette <= score.ssim {
+        format!(
+            "Match the reference colors exactly: {}.",
+            spec.palette.join(", ")
+        )
+    } else if score.structure <= score.ssim {
+        format!(
+            "Match the reference layout closely: {}.",
+            spec.composition.trim_end_matches('.')
+        )
+    } else {
+        format!(
+            "Match the reference detail and lighting: {}.",
+            spec.lighting.trim_end_matches('.')
+        )
+    };
+    format!("{} {hint}", base_prompt.trim())
+}
+
+/// Scores `candidate` against `reference`; both are compared at 64 px.
+pub fn score_recreation(reference: &Path, candidate: &Path) -> Result<RecreateScore> {
+    let structure =
+        1.0 - f64::from((image_dhash(reference)? ^ image_dhash(candidate)?).count_ones()) / 64.0;
+    let load = |path: &Path| -> Result<RgbImage> {
+        Ok(image::open(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .resize_exact(ANALYSIS_EDGE, ANALYSIS_EDGE, FilterType::Triangle)
+            .to_rgb8())
+    };
+    let (left, right) = (load(reference)?, load(candidate)?);
+    let ssim = mean_ssim(
+        &image::DynamicImage::ImageRgb8(left.clone()).to_luma8(),
+        &image::DynamicImage::ImageRgb8(right.clone()).to_luma8(),
+    )
+    .clamp(0.0, 1.0);
+    let (left_hist, right_hist) = (color_histogram(&left), color_histogram(&right));
+    let palette = left_hist
+        .iter()
+        .zip(&right_hist)
+        .map(|(a, b)| a.min(*b))
+        .sum::<f64>()
+        .clamp(0.0, 1.0);
+    let overall = (0.4 * structure + 0.3 * ssim + 0.3 * palette).clamp(0.0, 1.0);
+    Ok(RecreateScore {
+        structure,
+        ssim,
+        palette,
+        overall,
+    })
+}
+
+struct MeasuredReference {
+    palette: Vec<String>,
+    composition: String,
+    lighting: String,
+}
+
+/// Palette, framing and light read from the pixels alone.
+fn measure_reference(image: &RgbImage) -> MeasuredReference {
+    let (width, height) = image.dimensions();
+    let small = image::imageops::resize(image, ANALYSIS_EDGE, ANALYSIS_EDGE, FilterType::Triangle);
+
+    let palette = dominant_colors(&small, PALETTE_COLORS)
+        .into_iter()
+        .map(|color| color.hex)
+        .collect();
+
+    let luma: Vec<f64> = small
+        .pixels()
+        .map(|pixel| {
+            (0.299 * f64::from(pixel.0[0])
+                + 0.587 * f64::from(pixel.0[1])
+                + 0.114 * f64::from(pixel.0[2]))
+                / 255.0
+        })
+        .collect();
+    let mean = luma.iter().sum::<f64>() / luma.len() as f64;
+    let spread =
+        (luma.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / luma.len() as f64).sqrt();
+
+    // Where the detail is: centroid of horizontal and vertical gradients.
+    let edge = ANALYSIS_EDGE as usize;
+    let (mut weight, mut cx, mut cy) = (0.0, 0.0, 0.0);
+    for y in 0..edge - 1 {
+        for x in 0..edge - 1 {
+            let here = luma[y * edge + x];
+            let gradient =
+                (luma[y * edge + x + 1] - here).abs() + (luma[(y + 1) * edge + x] - here).abs();
+            weight += gradient;
+            cx += gradient * x as f64;
+            cy += gradient * y as f64;
+        }
+    }
+    let (fx, fy) = if weight > 0.0 {
+        (cx / weight / edge as f64, cy / weight / edge as f64)
+    } else {
+        (0.5, 0.5)
+    };
+    let orientation = match width.cmp(&height) {
+        std::cmp::Ordering::Greater => "landscape",
+        std::cmp::Ordering::Less => "portrait",
+        std::cmp::Ordering::Equal => "square",
+    };
+    let vertical = if fy < 0.4 {
+        "upper"
+    } else if fy > 0.6 {
+        "lower"
+    } else {
+        "middle"
+    };
+    let horizontal = if fx < 0.4 {
+        "left"
+    } else if fx > 0.6 {
+        "right"
+    } else {
+        "center"
+    };
+    let composition = format!("{orientation} frame, main detail in the {vertical} {horizontal}");
+
+    let key = if mean < 0.35 {
+        "low-key, dark"
+    } else if mean > 0.65 {
+        "high-key, bright"
+    } else {
+        "balanced exposure"
+    };
+    let contrast = if spread > 0.25 {
+        "high contrast"
+    } else {
+        "soft contrast"
+    };
+    let (red, blue) = small.pixels().fold((0u64, 0u64), |(red, blue), pixel| {
+        (red + u64::from(pixel.0[0]), blue + u64::from(pixel.0[2]))
+    });
+    let temperature = if red > blue + blue / 10 {
+        ", warm tones"
+    } else if blue > red + red / 10 {
+        ", cool tones"
+    } else {
+        ""
+    };
+    MeasuredReference {
+        palette,
+        composition,
+        lighting: format!("{key}, {contrast}{temperature}"),
+    }
+}
+
+/// Share of pixels per colour bucket.
+fn color_histogram(image: &RgbImage) -> [f64; COLOR_BUCKETS] {
+    let mut histogram = [0.0; COLOR_BUCKETS];
+    let total = f64::from(image.width() * image.height()).max(1.0);
+    for pixel in image.pixels() {
+        histogram[color_bucket(pixel.0)] += 1.0 / total;
+    }
+    histogram
+}
+
+/// Reads the analysis fields from a model reply, tolerating prose or code
+/// fences around the JSON.
+fn parse_analysis(reply: &str) -> Option<Map<String, Value>> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let payload: Value = serde_json::from_str(reply.get(start..=end)?).ok()?;
+    let fields = payload.as_object()?;
+    fields
+        .get("subject")
+        .and_then(Value::as_str)
+        .filter(|subject| !subject.trim().is_empty())?;
+    Some(fields.clone())
+}
+
+/// The prompt and model recorded by a Brood receipt (`receipt-*.json` in the
+/// same directory) whose artifact is `reference`.
+fn prompt_from_receipts(reference: &Path) -> Option<(String, Option<String>)> {
+    let parent = reference.parent()?;
+    let target = reference.to_string_lossy().to_string();
+    let canonical_target = fs::canonicalize(reference).ok();
+    for entry in fs::read_dir(parent).ok()?.flatten() {
+        let path = entry.path();
+        let is_receipt = path.extension().and_then(|value| value.to_str()) == Some("json")
+            && path
+                .file_name()
+                .and_then(|value| value.to_str())
+                .is_some_and(|name| name.starts_with("receipt-"));
+        if !is_receipt {
+            continue;
+        }
+        let Some(payload) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+        else {
+            continue;
+        };
+        let image_path = payload["artifacts"]["image_path"]
+            .as_str()
+            .unwrap_or_default();
+        let same_path = image_path == target
+            || canonical_target.as_ref().is_some_and(|expected| {
+                fs::canonicalize(image_path).is_ok_and(|actual| actual == *expected)
+            });
+        if !same_path {
+            continue;
+        }
+        let text = |value: &Value| {
+            value
+                .as_str()
+                .map(str::trim)
+                .filter(|text| !text.is_empty())
+                .map(str::to_string)
+        };
+        let prompt = text(&payload["request"]["prompt"])?;
+        return Some((prompt, text(&payload["resolved"]["model"])));
+    }
+    None
+}
+
+/// Records the score under `result_metadata.similarity` in a receipt.
+fn write_similarity_to_receipt(receipt_path: &Path, score: &RecreateScore) -> Result<()> {
+    let Some(mut payload) = fs::read_to_string(receipt_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+    else {
+        return Ok(());
+    };
+    let Some(root) = payload.as_object_mut() else {
+        return Ok(());
+    };
+    let metadata = root
+        .entry("result_metadata".to_string())
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !metadata.is_object() {
+        *metadata = Value::Object(Map::new());
+    }
+    if let Some(metadata) = metadata.as_object_mut() {
+        metadata.insert("similarity".to_string(), Value::Object(score.to_map()));
+    }
+    write_receipt(receipt_path, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use serde_json::json;
+
+    use super::{
+        compile_recreate_prompt, measure_reference, parse_analysis, score_recreation, RecreateSpec,
+    };
+
+    #[test]
+    fn measures_palette_names_colors_and_compiles_per_provider() {
+        let image = RgbImage::from_fn(96, 64, |x, _| {
+            if x < 72 {
+                Rgb([20, 40, 160])
+            } else {
+                Rgb([240, 240, 235])
+            }
+        });
+        let measured = measure_reference(&image);
+        assert_eq!(measured.palette[0], "#1428a0");
+        assert!(measured.composition.starts_with("landscape frame"));
+        assert!(measured.lighting.contains("cool tones"));
+
+        let spec = RecreateSpec {
+            subject: "a lighthouse on a cliff".to_string(),
+            style: "travel poster".to_string(),
+            medium: String::new(),
+            composition: "low angle".to_string(),
+            lighting: "golden hour".to_string(),
+            palette: vec!["#1428a0".to_string()],
+            source: "vision".to_string(),
+            model: Some("gpt-4o-mini".to_string()),
+        };
+        assert_eq!(
+            compile_recreate_prompt(&spec, "flux"),
+            "a lighthouse on a cliff, travel poster, low angle, golden hour, color palette blue (#1428a0)"
+        );
+        assert_eq!(
+            compile_recreate_prompt(&spec, "openai"),
+            "Recreate this image: a lighthouse on a cliff. Style: travel poster. Composition: low angle. \
+             Lighting: golden hour. Palette: blue (#1428a0)."
+        );
+        let receipt = RecreateSpec {
+            source: "receipt".to_string(),
+            ..spec
+        };
+        assert_eq!(
+            compile_recreate_prompt(&receipt, "openai"),
+            "a lighthouse on a cliff"
+        );
+
+        assert_eq!(
+            parse_analysis("```json\n{\"subject\": \"a fox\", \"style\": \"ink\"}\n```")
+                .map(|fields| fields["style"].clone()),
+            Some(json!("ink"))
+        );
+        assert_eq!(parse_analysis("{\"subject\": \"\"}"), None);
+    }
+
+    #[test]
+    fn identical_images_score_higher_than_different_ones() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let reference = temp.path().join("reference.png");
+        let similar = temp.path().join("similar.png");
+        let different = temp.path().join("different.png");
+        RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 90]))
+            .save(&reference)?;
+        RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, 100]))
+            .save(&similar)?;
+        RgbImage::from_fn(64, 64, |x, _| Rgb([250, 250, (x * 4) as u8])).save(&different)?;
+        let same = score_recreation(&reference, &reference)?;
+        assert!((same.overall - 1.0).abs() < 1e-9);
+        let close = score_recreation(&reference, &similar)?;
+        let far = score_recreation(&reference, &different)?;
+        assert!(close.overall > far.overall);
+        assert!(close.palette > far.palette);
+        Ok(())
+    }
+}
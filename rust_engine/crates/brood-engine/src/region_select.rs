@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::models::ModelSpec;
+use serde_json::{json, Value};
+
+use super::edit::{render_region_mask, EditRegion};
+use super::text_model::{TextModelClient, TokenUsage};
+use super::{map_object, timestamp_millis, NativeEngine, ProviderConfig};
+
+/// Instruction sent with the image and the description of the region.
+const SEGMENT_INSTRUCTIONS: &str = "You locate regions in images for an inpainting tool. \
+Outline the region the user describes as one polygon that covers all of it, with coordinates \
+as fractions of the image width and height (0 is left/top, 1 is right/bottom). Reply with JSON \
+only: {\"polygon\": [[x, y], ...]} with at least three points, or {\"polygon\": []} when the \
+region is not in the image.";
+
+/// Outline the dryrun segmenter returns: the middle half of the image.
+const DRYRUN_POLYGON: [(f64, f64); 4] = [(0.25, 0.25), (0.75, 0.25), (0.75, 0.75), (0.25, 0.75)];
+
+const SEGMENT_MAX_POINTS: usize = 256;
+
+/// A mask bound to the image it was drawn on; edits of that image change
+/// only the white area of `mask_path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionSelection {
+    pub image_path: PathBuf,
+    /// White-on-black grayscale PNG the size of the image.
+    pub mask_path: PathBuf,
+    /// The shape that was rendered, as in [`EditRegion::to_value`].
+    pub region: Value,
+    /// What was asked for with `/select "<description>"`; `None` for shapes.
+    pub description: Option<String>,
+    /// Vision model that outlined `description`.
+    pub model: Option<String>,
+    /// Share of the image the mask covers, 0..=1.
+    pub coverage: f64,
+    pub cost_usd: f64,
+}
+
+/// Asks a vision-capable model for the outline of a described region.
+#[derive(Debug, Clone)]
+pub(crate) struct RegionSegmenter {
+    client: TextModelClient,
+}
+
+impl RegionSegmenter {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            client: TextModelClient::new(config),
+        }
+    }
+
+    /// The polygon in image fractions and the call's token usage.
+    fn segment(
+        &self,
+        model: &ModelSpec,
+        image_path: &Path,
+        description: &str,
+    ) -> Result<(Vec<(f64, f64)>, TokenUsage)> {
+        if !model.supports("vision") {
+            bail!("segmentation model '{}' is not vision-capable", model.name);
+        }
+        if model.provider == "dryrun" {
+            return Ok((DRYRUN_POLYGON.to_vec(), TokenUsage::default()));
+        }
+        let prompt = format!("Region: {description}");
+        let reply = self
+            .client
+            .complete(model, SEGMENT_INSTRUCTIONS, &prompt, Some(image_path))?;
+        let polygon = parse_polygon(&reply.text)
+            .with_context(|| format!("{} segmentation reply had no polygon", reply.transport))?;
+        if polygon.is_empty() {
+            bail!("'{description}' was not found in the image");
+        }
+        Ok((polygon, reply.usage))
+    }
+}
+
+impl NativeEngine {
+    /// The selection the next edit of its image is limited to.
+    pub fn active_selection(&self) -> Option<&RegionSelection> {
+        self.active_selection.as_ref()
+    }
+
+    /// Renders `region` as a mask over `image_path`, stores it under
+    /// `masks/` in the run dir and makes it the active selection.
+    pub fn select_region(
+        &mut self,
+        image_path: &Path,
+        region: &EditRegion,
+    ) -> Result<RegionSelection> {
+        self.bind_selection(image_path, region, None, None, 0.0)
+    }
+
+    /// Outlines `description` on `image_path` with a vision model (`model`,
+    /// else the engine's text model) and selects it like
+    /// [`NativeEngine::select_region`].
+    pub fn segment_region(
+        &mut self,
+        image_path: &Path,
+        description: &str,
+        model: Option<&str>,
+    ) -> Result<RegionSelection> {
+        let description = description.trim();
+        if description.is_empty() {
+            bail!("describe the region to select");
+        }
+        let Some(model_name) = model
+            .map(str::to_string)
+            .or_else(|| self.text_model.clone())
+        else {
+            bail!("no segmentation model configured");
+        };
+        let Some(model) = self.model_selector.registry.get(&model_name).cloned() else {
+            bail!("segmentation model '{model_name}' is not registered");
+        };
+        let (width, height) = image::image_dimensions(image_path)
+            .with_context(|| format!("failed to read {}", image_path.display()))?;
+        let (polygon, usage) = self
+            .region_segmenter
+            .segment(&model, image_path, description)?;
+        let cost_usd = self.record_text_call(&model, usage)?;
+        let region = EditRegion::Polygon(
+            polygon
+                .into_iter()
+                .map(|(x, y)| (x * width as f64, y * height as f64))
+                .collect(),
+        );
+        self.bind_selection(
+            image_path,
+            &region,
+            Some(description),
+            Some(&model.name),
+            cost_usd,
+        )
+    }
+
+    /// Drops the active selection; `true` when there was one.
+    pub fn clear_selection(&mut self) -> Result<bool> {
+        let Some(selection) = self.active_selection.take() else {
+            return Ok(false);
+        };
+        self.events.emit(
+            "region_cleared",
+            map_object(json!({
+                "image_path": selection.image_path.to_string_lossy(),
+                "mask_path": selection.mask_path.to_string_lossy(),
+            })),
+        )?;
+        Ok(true)
+    }
+
+    fn bind_selection(
+        &mut self,
+        image_path: &Path,
+        region: &EditRegion,
+        description: Option<&str>,
+        model: Option<&str>,
+        cost_usd: f64,
+    ) -> Result<RegionSelection> {
+        if !image_path.is_file() {
+            bail!("selection image not found ({})", image_path.display());
+        }
+        if matches!(region, EditRegion::Mask(_) | EditRegion::Outpaint { .. }) {
+            bail!("select a rect, ellipse or polygon region");
+        }
+        let (width, height) = image::image_dimensions(image_path)
+            .with_context(|| format!("failed to read {}", image_path.display()))?;
+        let mask = render_region_mask(region, width, height)?;
+        let covered = mask.pixels().filter(|pixel| pixel[0] > 0).count();
+        let masks_dir = self.run_dir.join("masks");
+        fs::create_dir_all(&masks_dir)
+            .with_context(|| format!("failed to create {}", masks_dir.display()))?;
+        let mask_path = masks_dir.join(format!("select-{}.png", timestamp_millis()));
+        mask.save(&mask_path)
+            .with_context(|| format!("failed to write {}", mask_path.display()))?;
+        let selection = RegionSelection {
+            image_path: image_path.to_path_buf(),
+            mask_path,
+            region: region.to_value(),
+            description: description.map(str::to_string),
+            model: model.map(str::to_string),
+            coverage: covered as f64 / (u64::from(width) * u64::from(height)) as f64,
+            cost_usd,
+        };
+        self.events.emit(
+            "region_selected",
+            map_object(json!({
+                "image_path": selection.image_path.to_string_lossy(),
+                "mask_path": selection.mask_path.to_string_lossy(),
+                "region": selection.region,
+                "description": selection.description,
+                "model": selection.model,
+                "coverage": selection.coverage,
+                "cost_usd": selection.cost_usd,
+            })),
+        )?;
+        self.active_selection = Some(selection.clone());
+        Ok(selection)
+    }
+}
+
+/// Reads `{"polygon": [[x, y], ...]}` from a model reply, tolerating prose
+/// or code fences around the JSON. Points are clamped to the image.
+fn parse_polygon(reply: &str) -> Option<Vec<(f64, f64)>> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let payload: Value = serde_json::from_str(reply.get(start..=end)?).ok()?;
+    let points = payload.get("polygon")?.as_array()?;
+    if points.len() > SEGMENT_MAX_POINTS {
+        return None;
+    }
+    let polygon = points
+        .iter()
+        .map(|point| {
+            let x = point.get(0)?.as_f64()?;
+            let y = point.get(1)?.as_f64()?;
+            (x.is_finite() && y.is_finite()).then(|| (x.clamp(0.0, 1.0), y.clamp(0.0, 1.0)))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (polygon.is_empty() || polygon.len() >= 3).then_some(polygon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_polygon;
+
+    #[test]
+    fn parses_polygon_replies() {
+        assert_eq!(
+            parse_polygon("Here:\n```json\n{\"polygon\": [[0, 0], [1.5, 0], [0.5, 0.4]]}\n```"),
+            Some(vec![(0.0, 0.0), (1.0, 0.0), (0.5, 0.4)])
+        );
+        assert_eq!(parse_polygon("{\"polygon\": []}"), Some(Vec::new()));
+        assert_eq!(parse_polygon("{\"polygon\": [[0, 0], [1, 1]]}"), None);
+        assert_eq!(
+            parse_polygon("{\"polygon\": [[0, \"a\"], [1, 1], [0, 1]]}"),
+            None
+        );
+        assert_eq!(parse_polygon("no region"), None);
+    }
+}
@@ -0,0 +1,448 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::receipts::{
+    build_receipt, write_receipt, ImageInputs, ImageRequest, ResolvedRequest,
+};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use serde_json::{json, Map, Value};
+
+use super::faces::FaceBox;
+use super::{map_object, timestamp_millis, NativeEngine};
+
+/// Edge of the grayscale copy the saliency centroid is measured on.
+const SALIENCY_EDGE: u32 = 128;
+
+/// One output of a rendition set: a crop to `width:height`, resized to
+/// exactly that size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenditionTarget {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenditionSet {
+    pub name: &'static str,
+    pub targets: &'static [RenditionTarget],
+}
+
+pub const RENDITION_SETS: &[RenditionSet] = &[
+    RenditionSet {
+        name: "social",
+        targets: &[
+            RenditionTarget {
+                name: "square",
+                width: 1080,
+                height: 1080,
+            },
+            RenditionTarget {
+                name: "portrait",
+                width: 1080,
+                height: 1350,
+            },
+            RenditionTarget {
+                name: "story",
+                width: 1080,
+                height: 1920,
+            },
+            RenditionTarget {
+                name: "landscape",
+                width: 1280,
+                height: 720,
+            },
+        ],
+    },
+    RenditionSet {
+        name: "web",
+        targets: &[
+            RenditionTarget {
+                name: "hero",
+                width: 1920,
+                height: 1080,
+            },
+            RenditionTarget {
+                name: "card",
+                width: 1200,
+                height: 630,
+            },
+            RenditionTarget {
+                name: "thumb",
+                width: 400,
+                height: 400,
+            },
+        ],
+    },
+];
+
+impl RenditionSet {
+    pub fn named(name: &str) -> Result<Self> {
+        let lowered = name.trim().to_ascii_lowercase();
+        RENDITION_SETS
+            .iter()
+            .find(|set| set.name == lowered)
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown rendition set '{name}' (expected one of: {})",
+                    RENDITION_SETS
+                        .iter()
+                        .map(|set| set.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+/// Crop rectangle in source pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CropBox {
+    pub fn to_value(self) -> Value {
+        json!({ "x": self.x, "y": self.y, "width": self.width, "height": self.height })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendition {
+    pub target: RenditionTarget,
+    pub source_artifact_id: String,
+    pub path: PathBuf,
+    pub receipt_path: PathBuf,
+    pub crop: CropBox,
+    /// `"faces"` or `"saliency"`: what the crop was centered on.
+    pub focus: &'static str,
+}
+
+/// The largest `target` aspect crop of a `width`x`height` image, centered
+/// as close to `focus` (pixels) as the image edges allow.
+pub(crate) fn crop_around(
+    width: u32,
+    height: u32,
+    target: RenditionTarget,
+    focus: (f64, f64),
+) -> CropBox {
+    let target_aspect = target.width as f64 / target.height as f64;
+    let (crop_width, crop_height) = if width as f64 / height as f64 > target_aspect {
+        let crop_width = (height as f64 * target_aspect).round() as u32;
+        (crop_width.clamp(1, width), height)
+    } else {
+        let crop_height = (width as f64 / target_aspect).round() as u32;
+        (width, crop_height.clamp(1, height))
+    };
+    let place = |center: f64, extent: u32, limit: u32| -> u32 {
+        let start = (center - extent as f64 / 2.0).round();
+        start.clamp(0.0, (limit - extent) as f64) as u32
+    };
+    CropBox {
+        x: place(focus.0, crop_width, width),
+        y: place(focus.1, crop_height, height),
+        width: crop_width,
+        height: crop_height,
+    }
+}
+
+/// Center of the box around every face.
+pub(crate) fn faces_center(faces: &[FaceBox]) -> Option<(f64, f64)> {
+    let left = faces.iter().map(|face| face.x).min()?;
+    let top = faces.iter().map(|face| face.y).min()?;
+    let right = faces.iter().map(|face| face.x + face.width).max()?;
+    let bottom = faces.iter().map(|face| face.y + face.height).max()?;
+    Some(((left + right) as f64 / 2.0, (top + bottom) as f64 / 2.0))
+}
+
+/// Edge-energy centroid of `image`, in its pixels: detailed regions pull
+/// the crop toward them, flat backgrounds do not. The image center when
+/// the image is flat.
+pub(crate) fn saliency_center(image: &DynamicImage) -> (f64, f64) {
+    let (width, height) = image.dimensions();
+    let small = image
+        .resize(SALIENCY_EDGE, SALIENCY_EDGE, FilterType::Triangle)
+        .to_luma8();
+    let (small_width, small_height) = small.dimensions();
+    let (mut total, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+    for y in 1..small_height.saturating_sub(1) {
+        for x in 1..small_width.saturating_sub(1) {
+            let at = |dx: i32, dy: i32| {
+                small.get_pixel((x as i32 + dx) as u32, (y as i32 + dy) as u32)[0] as f64
+            };
+            let energy = (at(1, 0) - at(-1, 0)).abs() + (at(0, 1) - at(0, -1)).abs();
+            total += energy;
+            sum_x += energy * (x as f64 + 0.5);
+            sum_y += energy * (y as f64 + 0.5);
+        }
+    }
+    if total <= f64::EPSILON {
+        return (width as f64 / 2.0, height as f64 / 2.0);
+    }
+    (
+        sum_x / total * width as f64 / small_width as f64,
+        sum_y / total * height as f64 / small_height as f64,
+    )
+}
+
+impl NativeEngine {
+    /// Writes every target of rendition set `set_name` for `artifact_id`
+    /// (default: the newest selected winner, else the newest artifact) to
+    /// `out_dir` (default `exports/renditions-<stamp>-<set>/`), each with
+    /// its own receipt that references the source artifact. Crops center
+    /// on faces when a local [`crate::FaceDetector`] is set and finds any,
+    /// else on saliency.
+    pub fn export_renditions(
+        &mut self,
+        artifact_id: Option<&str>,
+        set_name: &str,
+        out_dir: Option<&Path>,
+    ) -> Result<Vec<Rendition>> {
+        let set = RenditionSet::named(set_name)?;
+        let Some(artifact_id) = artifact_id
+            .map(str::to_string)
+            .or_else(|| {
+                self.thread
+                    .live_versions()
+                    .filter_map(|version| version.selected_artifact_id.clone())
+                    .last()
+            })
+            .or_else(|| self.thread.latest_artifact_id().map(str::to_string))
+        else {
+            bail!("no artifacts to export renditions of yet");
+        };
+        let Some((source_version, source_artifact)) = self.thread.find_artifact(&artifact_id)
+        else {
+            bail!("artifact '{artifact_id}' not found in thread");
+        };
+        let prompt = source_version.prompt.clone();
+        let source_path = source_artifact
+            .get("image_path")
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("artifact '{artifact_id}' has no image_path"))?;
+        let source_receipt = source_artifact.get("receipt_path").cloned();
+        let image = image::open(&source_path)
+            .with_context(|| format!("failed to open {}", source_path.display()))?;
+        let (width, height) = image.dimensions();
+
+        let faces = match &self.face_detector {
+            Some(detector) => detector.detect_faces(&source_path)?,
+            None => Vec::new(),
+        };
+        let (focus, focus_point) = match faces_center(&faces) {
+            Some(center) => ("faces", center),
+            None => ("saliency", saliency_center(&image)),
+        };
+
+        let out_dir = match out_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => self.run_dir.join("exports").join(format!(
+                "renditions-{}-{}",
+                timestamp_millis(),
+                set.name
+            )),
+        };
+        fs::create_dir_all(&out_dir)
+            .with_context(|| format!("failed to create {}", out_dir.display()))?;
+        let mut renditions = Vec::new();
+        for target in set.targets {
+            let crop = crop_around(width, height, *target, focus_point);
+            let output = image
+                .crop_imm(crop.x, crop.y, crop.width, crop.height)
+                .resize_exact(target.width, target.height, FilterType::Lanczos3);
+            let stem = format!("{artifact_id}-{}", target.name);
+            let path = out_dir.join(format!("{stem}.png"));
+            output
+                .save(&path)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            let receipt_path = out_dir.join(format!("receipt-{stem}.json"));
+            let result_metadata = map_object(json!({
+                "rendition": {
+                    "set": set.name,
+                    "name": target.name,
+                    "crop": crop.to_value(),
+                    "focus": focus,
+                    "source_artifact_id": artifact_id,
+                    "source_receipt_path": source_receipt,
+                },
+            }));
+            write_rendition_receipt(
+                &prompt,
+                &source_path,
+                *target,
+                &path,
+                &receipt_path,
+                &result_metadata,
+            )?;
+            renditions.push(Rendition {
+                target: *target,
+                source_artifact_id: artifact_id.clone(),
+                path,
+                receipt_path,
+                crop,
+                focus,
+            });
+        }
+        self.events.emit(
+            "renditions_exported",
+            map_object(json!({
+                "artifact_id": artifact_id,
+                "set": set.name,
+                "focus": focus,
+                "faces": faces.iter().map(|face| face.to_value()).collect::<Vec<_>>(),
+                "out_dir": out_dir.to_string_lossy(),
+                "renditions": renditions
+                    .iter()
+                    .map(|rendition| json!({
+                        "name": rendition.target.name,
+                        "width": rendition.target.width,
+                        "height": rendition.target.height,
+                        "crop": rendition.crop.to_value(),
+                        "path": rendition.path.to_string_lossy(),
+                        "receipt_path": rendition.receipt_path.to_string_lossy(),
+                    }))
+                    .collect::<Vec<Value>>(),
+            })),
+        )?;
+        Ok(renditions)
+    }
+}
+
+fn write_rendition_receipt(
+    prompt: &str,
+    source_path: &Path,
+    target: RenditionTarget,
+    image_path: &Path,
+    receipt_path: &Path,
+    result_metadata: &Map<String, Value>,
+) -> Result<()> {
+    let size = format!("{}x{}", target.width, target.height);
+    let inputs = ImageInputs {
+        init_image: Some(source_path.to_string_lossy().to_string()),
+        ..ImageInputs::default()
+    };
+    let request = ImageRequest {
+        prompt: prompt.to_string(),
+        mode: "rendition".to_string(),
+        size: size.clone(),
+        n: 1,
+        seed: None,
+        output_format: Some("png".to_string()),
+        background: None,
+        inputs: inputs.clone(),
+        provider: Some("local".to_string()),
+        provider_options: Map::new(),
+        user: None,
+        out_dir: image_path
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string()),
+        stream: false,
+        partial_images: None,
+        model: None,
+        metadata: result_metadata.clone(),
+    };
+    let resolved = ResolvedRequest {
+        provider: "local".to_string(),
+        model: None,
+        size,
+        width: Some(target.width as u64),
+        height: Some(target.height as u64),
+        output_format: "png".to_string(),
+        background: None,
+        seed: None,
+        n: 1,
+        user: None,
+        prompt: prompt.to_string(),
+        inputs,
+        stream: false,
+        partial_images: None,
+        provider_params: Map::new(),
+        warnings: Vec::new(),
+        safety: Map::new(),
+    };
+    let receipt = build_receipt(
+        &request,
+        &resolved,
+        &Map::new(),
+        &Map::new(),
+        &[],
+        image_path,
+        None,
+        receipt_path,
+        result_metadata,
+    );
+    write_receipt(receipt_path, &receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use super::{crop_around, faces_center, saliency_center, CropBox, RenditionSet};
+    use crate::FaceBox;
+
+    #[test]
+    fn crops_follow_faces_or_saliency_within_the_image() -> anyhow::Result<()> {
+        let social = RenditionSet::named("Social")?;
+        assert_eq!(social.targets.len(), 4);
+        assert!(RenditionSet::named("billboard").is_err());
+
+        let story = social.targets[2];
+        assert_eq!(
+            crop_around(1600, 900, story, (1500.0, 450.0)),
+            CropBox {
+                x: 1094,
+                y: 0,
+                width: 506,
+                height: 900,
+            }
+        );
+        let landscape = social.targets[3];
+        assert_eq!(
+            crop_around(900, 1600, landscape, (450.0, 0.0)).y,
+            0,
+            "crops stop at the image edge"
+        );
+
+        let faces = [
+            FaceBox {
+                x: 100,
+                y: 100,
+                width: 50,
+                height: 50,
+            },
+            FaceBox {
+                x: 250,
+                y: 100,
+                width: 50,
+                height: 50,
+            },
+        ];
+        assert_eq!(faces_center(&faces), Some((200.0, 125.0)));
+        assert_eq!(faces_center(&[]), None);
+
+        let mut busy_right = RgbImage::from_pixel(256, 128, Rgb([40, 40, 40]));
+        for y in 0..128 {
+            for x in 192..256 {
+                if (x / 8 + y / 8) % 2 == 0 {
+                    busy_right.put_pixel(x, y, Rgb([255, 255, 255]));
+                }
+            }
+        }
+        let (x, _) = saliency_center(&DynamicImage::ImageRgb8(busy_right));
+        assert!(
+            x > 180.0,
+            "saliency centroid {x} should sit in the busy area"
+        );
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 32, Rgb([9, 9, 9])));
+        assert_eq!(saliency_center(&flat), (32.0, 16.0));
+        Ok(())
+    }
+}
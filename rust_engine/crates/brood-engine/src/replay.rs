@@ -0,0 +1,323 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine as _;
+use brood_contracts::redaction::redact_url;
+use brood_contracts::runs::atomic::write_json_atomic;
+use reqwest::blocking::{Client, Request, RequestBuilder, Response as HttpResponse};
+use reqwest::header::SET_COOKIE;
+use reqwest::{ResponseBuilderExt, Url};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::non_empty_env;
+use super::telemetry::record_span_error;
+
+/// `record` saves provider HTTP exchanges as fixtures; `1` (or `true`)
+/// serves them back instead of touching the network.
+pub const REPLAY_ENV: &str = "BROOD_REPLAY";
+/// Fixture directory to record into or replay from; defaults to
+/// `<run_dir>/replay`.
+pub const REPLAY_DIR_ENV: &str = "BROOD_REPLAY_DIR";
+/// Run-dir subdirectory holding one JSON fixture per provider call.
+pub const REPLAY_DIR: &str = "replay";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplayMode {
+    Record,
+    Replay,
+}
+
+/// `settings.replay` (`"record"`, `"replay"`, or `false`) wins over
+/// [`REPLAY_ENV`].
+pub(crate) fn replay_mode(settings: &Map<String, Value>) -> Result<Option<ReplayMode>> {
+    let raw = match settings.get("replay") {
+        Some(Value::Bool(false)) | Some(Value::Null) => return Ok(None),
+        Some(Value::Bool(true)) => "replay".to_string(),
+        Some(Value::String(value)) => value.trim().to_ascii_lowercase(),
+        Some(other) => {
+            bail!("settings.replay must be \"record\", \"replay\" or false, got {other}")
+        }
+        None => match non_empty_env(REPLAY_ENV) {
+            Some(value) => value.to_ascii_lowercase(),
+            None => return Ok(None),
+        },
+    };
+    Ok(match raw.as_str() {
+        "record" => Some(ReplayMode::Record),
+        "replay" | "1" | "true" | "on" | "yes" => Some(ReplayMode::Replay),
+        "" | "0" | "false" | "off" | "no" => None,
+        other => bail!("unknown replay mode '{other}' (expected record or replay)"),
+    })
+}
+
+/// [`REPLAY_DIR_ENV`] when set, else `<run_dir>/replay`.
+pub(crate) fn replay_dir(run_dir: &Path) -> PathBuf {
+    non_empty_env(REPLAY_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| run_dir.join(REPLAY_DIR))
+}
+
+struct ReplayState {
+    mode: ReplayMode,
+    dir: PathBuf,
+    /// Calls seen so far per request key; polling repeats the same request,
+    /// and each repeat gets its own fixture.
+    seen: BTreeMap<String, usize>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<ReplayState>> = const { RefCell::new(None) };
+}
+
+/// Records or replays provider calls made on this thread until dropped, the
+/// way `http_trace` captures responses.
+pub(crate) struct ReplayScope;
+
+impl ReplayScope {
+    pub(crate) fn begin(mode: ReplayMode, dir: PathBuf) -> Self {
+        STATE.with(|cell| {
+            *cell.borrow_mut() = Some(ReplayState {
+                mode,
+                dir,
+                seen: BTreeMap::new(),
+            })
+        });
+        Self
+    }
+}
+
+impl Drop for ReplayScope {
+    fn drop(&mut self) {
+        STATE.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
+/// Sends a provider request, recording or replaying it when a
+/// [`ReplayScope`] is active; otherwise a plain `send`.
+pub(crate) trait ReplaySend {
+    fn send_replayable(self) -> Result<HttpResponse>;
+}
+
+impl ReplaySend for RequestBuilder {
+    /// Each call is a `provider_http` span carrying the redacted URL and the
+    /// response status.
+    fn send_replayable(self) -> Result<HttpResponse> {
+        let (client, request) = self.build_split();
+        let request = request?;
+        let span = tracing::info_span!(
+            "provider_http",
+            otel.kind = "client",
+            method = %request.method(),
+            url = %redact_url(request.url().as_str()),
+            status = tracing::field::Empty,
+            error = tracing::field::Empty,
+            otel.status_description = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+        let outcome = execute(client, request);
+        match &outcome {
+            Ok(response) => {
+                span.record("status", i64::from(response.status().as_u16()));
+            }
+            Err(err) => record_span_error(&span, &format!("{err:#}")),
+        }
+        outcome
+    }
+}
+
+/// Sends `request`, through the fixtures when a [`ReplayScope`] is active.
+fn execute(client: Client, request: Request) -> Result<HttpResponse> {
+    let Some((mode, dir)) = STATE.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .map(|state| (state.mode, state.dir.clone()))
+    }) else {
+        return Ok(client
+            .execute(request)
+            .map_err(reqwest::Error::without_url)?);
+    };
+    let method = request.method().to_string();
+    let url = request.url().clone();
+    let key = request_key(
+        &method,
+        url.as_str(),
+        request.body().and_then(|body| body.as_bytes()),
+    );
+    let index = STATE.with(|cell| {
+        let mut state = cell.borrow_mut();
+        let seen = state
+            .as_mut()
+            .map(|state| state.seen.entry(key.clone()).or_insert(0));
+        match seen {
+            Some(seen) => {
+                *seen += 1;
+                *seen - 1
+            }
+            None => 0,
+        }
+    });
+    match mode {
+        ReplayMode::Replay => {
+            let Some(fixture) = find_fixture(&dir, &key, index)? else {
+                bail!(
+                    "no replay fixture for {method} {} in {}; record one with {REPLAY_ENV}=record",
+                    redact_url(url.as_str()),
+                    dir.display()
+                );
+            };
+            response_from_fixture(&fixture, url)
+        }
+        ReplayMode::Record => {
+            let response = client
+                .execute(request)
+                .map_err(reqwest::Error::without_url)?;
+            let status = response.status().as_u16();
+            let mut headers = Map::new();
+            for (name, value) in response.headers() {
+                if name == SET_COOKIE {
+                    continue;
+                }
+                if let Ok(value) = value.to_str() {
+                    headers.insert(name.to_string(), json!(value));
+                }
+            }
+            let body = response
+                .bytes()
+                .map_err(reqwest::Error::without_url)?
+                .to_vec();
+            let fixture = fixture_value(&method, url.as_str(), &key, status, headers, &body);
+            write_fixture(&dir, &key, index, &fixture)?;
+            response_from_fixture(&fixture, url)
+        }
+    }
+}
+
+/// Hash of method, redacted URL and body bytes. API keys in query strings
+/// are redacted first so fixtures replay under any key; streamed (multipart)
+/// bodies are not hashed.
+fn request_key(method: &str, url: &str, body: Option<&[u8]>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(redact_url(url).as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body.unwrap_or_default());
+    hex::encode(hasher.finalize())[..16].to_string()
+}
+
+fn fixture_path(dir: &Path, key: &str, index: usize) -> PathBuf {
+    dir.join(format!("{key}-{index}.json"))
+}
+
+/// The `index`th recording of `key`, or its last one when the replayed run
+/// polls more often than the recorded one did.
+fn find_fixture(dir: &Path, key: &str, index: usize) -> Result<Option<Value>> {
+    for candidate in (0..=index).rev() {
+        let path = fixture_path(dir, key, candidate);
+        if !path.exists() {
+            continue;
+        }
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let fixture = serde_json::from_str(&raw)
+            .with_context(|| format!("invalid replay fixture {}", path.display()))?;
+        return Ok(Some(fixture));
+    }
+    Ok(None)
+}
+
+/// UTF-8 bodies are stored as text so JSON payloads stay reviewable;
+/// anything else (images, video) as base64.
+fn fixture_value(
+    method: &str,
+    url: &str,
+    key: &str,
+    status: u16,
+    headers: Map<String, Value>,
+    body: &[u8],
+) -> Value {
+    let mut fixture = json!({
+        "method": method,
+        "url": redact_url(url),
+        "key": key,
+        "status": status,
+        "headers": headers,
+    });
+    match std::str::from_utf8(body) {
+        Ok(text) => fixture["body"] = json!(text),
+        Err(_) => fixture["body_base64"] = json!(BASE64.encode(body)),
+    }
+    fixture
+}
+
+fn write_fixture(dir: &Path, key: &str, index: usize, fixture: &Value) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = fixture_path(dir, key, index);
+    write_json_atomic(&path, fixture)
+}
+
+fn response_from_fixture(fixture: &Value, url: Url) -> Result<HttpResponse> {
+    let status = fixture
+        .get("status")
+        .and_then(Value::as_u64)
+        .and_then(|status| u16::try_from(status).ok())
+        .context("replay fixture is missing its status")?;
+    let body = match (
+        fixture.get("body").and_then(Value::as_str),
+        fixture.get("body_base64").and_then(Value::as_str),
+    ) {
+        (Some(text), _) => text.as_bytes().to_vec(),
+        (None, Some(encoded)) => BASE64
+            .decode(encoded)
+            .context("replay fixture body_base64 is not valid base64")?,
+        (None, None) => Vec::new(),
+    };
+    let mut builder = http::Response::builder().status(status).url(url);
+    if let Some(headers) = fixture.get("headers").and_then(Value::as_object) {
+        for (name, value) in headers {
+            if let Some(value) = value.as_str() {
+                builder = builder.header(name.as_str(), value);
+            }
+        }
+    }
+    let response = builder
+        .body(body)
+        .context("replay fixture has an invalid status or header")?;
+    Ok(HttpResponse::from(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map};
+
+    use super::{replay_mode, request_key, ReplayMode};
+
+    #[test]
+    fn modes_parse_and_keys_ignore_api_keys() -> anyhow::Result<()> {
+        let mut settings = Map::new();
+        settings.insert("replay".to_string(), json!("record"));
+        assert_eq!(replay_mode(&settings)?, Some(ReplayMode::Record));
+        settings.insert("replay".to_string(), json!(true));
+        assert_eq!(replay_mode(&settings)?, Some(ReplayMode::Replay));
+        settings.insert("replay".to_string(), json!(false));
+        assert_eq!(replay_mode(&settings)?, None);
+        settings.insert("replay".to_string(), json!("rewind"));
+        assert!(replay_mode(&settings).is_err());
+
+        let body = Some(br#"{"prompt":"a boat"}"#.as_slice());
+        assert_eq!(
+            request_key("POST", "https://g.example.com/x?key=AIza-one", body),
+            request_key("POST", "https://g.example.com/x?key=AIza-two", body)
+        );
+        assert_ne!(
+            request_key("POST", "https://g.example.com/x", body),
+            request_key("GET", "https://g.example.com/x", body)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::receipt_diff::load_receipt;
+use brood_contracts::runs::receipts::{store_result_metadata, ImageRequest, ResolvedRequest};
+use brood_contracts::runs::warnings::{coded_warnings, warning_messages, Warning, WarningCode};
+use image::imageops::FilterType;
+use serde_json::{json, Map, Value};
+
+use super::compare::mean_abs_diff;
+use super::dedup::image_dhash;
+use super::{error_chain_text, map_object, NativeEngine};
+
+/// How far a reproduced image landed from the original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReproductionDelta {
+    /// Mean absolute per-channel difference in `0.0..=1.0`, with the
+    /// reproduction resized to the original's size when they differ.
+    pub pixel_delta: f64,
+    /// dHash bits (out of 64) that differ.
+    pub dhash_distance: u32,
+}
+
+impl ReproductionDelta {
+    fn between(original: &Path, reproduced: &Path) -> Result<Self> {
+        let left = image::open(original)
+            .with_context(|| format!("failed to read {}", original.display()))?
+            .to_rgb8();
+        let mut right = image::open(reproduced)
+            .with_context(|| format!("failed to read {}", reproduced.display()))?
+            .to_rgb8();
+        if right.dimensions() != left.dimensions() {
+            right =
+                image::imageops::resize(&right, left.width(), left.height(), FilterType::Triangle);
+        }
+        Ok(Self {
+            pixel_delta: mean_abs_diff(&left, &right),
+            dhash_distance: (image_dhash(original)? ^ image_dhash(reproduced)?).count_ones(),
+        })
+    }
+
+    fn to_value(self) -> Value {
+        json!({
+            "pixel_delta": self.pixel_delta,
+            "dhash_distance": self.dhash_distance,
+        })
+    }
+}
+
+/// One artifact written by [`NativeEngine::reproduce_receipt`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReproducedArtifact {
+    pub artifact_id: String,
+    pub image_path: PathBuf,
+    pub receipt_path: PathBuf,
+    /// `None` when the original image is gone (e.g. pruned by `gc`).
+    pub delta: Option<ReproductionDelta>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reproduction {
+    pub version_id: String,
+    pub source_receipt: PathBuf,
+    /// Artifact id of the original, from its `receipt-<id>.json` name.
+    pub reproduction_of: String,
+    pub artifacts: Vec<ReproducedArtifact>,
+}
+
+impl Reproduction {
+    /// The reproduced artifact closest to the original, by pixel delta.
+    pub fn closest(&self) -> Option<&ReproducedArtifact> {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.delta.is_some())
+            .min_by(|a, b| {
+                let delta = |artifact: &ReproducedArtifact| {
+                    artifact.delta.map_or(f64::MAX, |delta| delta.pixel_delta)
+                };
+                delta(a).total_cmp(&delta(b))
+            })
+    }
+}
+
+impl NativeEngine {
+    /// Generates the image recorded in an image receipt again (same
+    /// provider, model, prompt, seed, size and provider params) and records
+    /// the results as a new version whose artifacts carry `reproduction_of`
+    /// and a [`ReproductionDelta`] against the original image.
+    pub fn reproduce_receipt(&mut self, receipt_path: &Path) -> Result<Reproduction> {
+        self.reproduce_receipt_with(receipt_path, Map::new())
+    }
+
+    /// [`NativeEngine::reproduce_receipt`] for a request carrying
+    /// `settings`. Keys in `provider_options.api_key`/`api_keys` reach the
+    /// provider as they would a generation's (receipts never hold keys),
+    /// and the credentials provider is asked for the receipt's tenant.
+    ///
+    /// The original version's settings and intent are replayed through
+    /// [`NativeEngine::generate`] when it is in this run, so post-processing,
+    /// checks, cost and hooks apply as they did; otherwise the settings are
+    /// rebuilt from the receipt.
+    pub fn reproduce_receipt_with(
+        &mut self,
+        receipt_path: &Path,
+        request_settings: Map<String, Value>,
+    ) -> Result<Reproduction> {
+        let receipt = load_receipt(receipt_path)?;
+        if receipt.get("kind").and_then(Value::as_str) == Some("video") {
+            bail!("video receipts cannot be reproduced");
+        }
+        let request: ImageRequest =
+            serde_json::from_value(receipt.get("request").cloned().unwrap_or(Value::Null))
+                .with_context(|| format!("receipt {} has no request", receipt_path.display()))?;
+        let resolved: ResolvedRequest =
+            serde_json::from_value(receipt.get("resolved").cloned().unwrap_or(Value::Null))
+                .with_context(|| {
+                    format!("receipt {} has no resolved request", receipt_path.display())
+                })?;
+        let reproduction_of = receipt_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| stem.strip_prefix("receipt-").unwrap_or(stem).to_string())
+            .unwrap_or_default();
+        let original_image = receipt
+            .get("artifacts")
+            .and_then(|artifacts| artifacts.get("image_path"))
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .filter(|path| path.is_file());
+        if self.providers.get(&resolved.provider).is_none() {
+            bail!(
+                "provider '{}' not registered (available: [{}])",
+                resolved.provider,
+                self.providers.names().join(", ")
+            );
+        }
+        let inputs = &resolved.inputs;
+        for input in inputs
+            .init_image
+            .iter()
+            .chain(&inputs.mask)
+            .chain(&inputs.reference_images)
+            .chain(inputs.control.as_ref().map(|control| &control.image))
+        {
+            if !Path::new(input).is_file() {
+                bail!("receipt input {input} no longer exists");
+            }
+        }
+        let model = resolved
+            .model
+            .clone()
+            .or_else(|| request.model.clone())
+            .unwrap_or_default();
+
+        let source_receipt = receipt_path.to_string_lossy().to_string();
+        let source = self
+            .thread
+            .find_artifact(&reproduction_of)
+            .map(|(version, _)| version.clone());
+        let (mut prompt, mut settings, mut intent) = match &source {
+            Some(version) => (
+                version.prompt.clone(),
+                version.settings.clone(),
+                version.intent.clone(),
+            ),
+            None => (
+                request.prompt.clone(),
+                settings_from_receipt(&resolved),
+                map_object(json!({ "request_metadata": request.metadata })),
+            ),
+        };
+        // The prompt the provider saw is sent again rather than enhanced anew.
+        if let Some(Value::String(enhanced)) = intent.remove("enhanced_prompt") {
+            prompt = enhanced;
+            settings.remove("enhance_prompt");
+        }
+        // One call's worth: the seed and count that produced this artifact.
+        for key in ["seed_sweep", "variables", "deterministic"] {
+            settings.remove(key);
+        }
+        settings.insert("n".to_string(), json!(resolved.n));
+        match resolved.seed.or(request.seed) {
+            Some(seed) => settings.insert("seed".to_string(), json!(seed)),
+            None => settings.remove("seed"),
+        };
+        settings.extend(request_settings);
+        for key in ["model_fallback", "style_profile"] {
+            intent.remove(key);
+        }
+        intent.insert("action".to_string(), json!("reproduce"));
+        intent.insert("reproduction_of".to_string(), json!(reproduction_of));
+        intent.insert("source_receipt".to_string(), json!(source_receipt));
+        match &source {
+            Some(version) => {
+                intent.insert("parent_version_id".to_string(), json!(version.version_id))
+            }
+            None => intent.remove("parent_version_id"),
+        };
+
+        let versions_before = self.thread.versions.len();
+        let previous_model = self.image_model.replace(model);
+        let generated = self.generate(&prompt, settings, intent);
+        self.image_model = previous_model;
+        let generated = generated.context("reproduction failed")?;
+        let version_id = self
+            .thread
+            .versions
+            .get(versions_before)
+            .map(|version| version.version_id.clone())
+            .unwrap_or_default();
+
+        let mut artifacts = Vec::new();
+        for artifact in &generated {
+            let text = |key: &str| artifact.get(key).and_then(Value::as_str).unwrap_or("");
+            let artifact_id = text("artifact_id").to_string();
+            let image_path = PathBuf::from(text("image_path"));
+            let receipt_path = PathBuf::from(text("receipt_path"));
+            let mut warnings = Vec::new();
+            let delta = match &original_image {
+                Some(original) => match ReproductionDelta::between(original, &image_path) {
+                    Ok(delta) => Some(delta),
+                    Err(err) => {
+                        warnings.push(Warning::new(
+                            WarningCode::PartialResult,
+                            format!(
+                                "Reproduction delta skipped: {}",
+                                error_chain_text(&err, 256)
+                            ),
+                        ));
+                        None
+                    }
+                },
+                None => {
+                    warnings.push(Warning::new(
+                        WarningCode::PartialResult,
+                        "Original image is missing; no reproduction delta.",
+                    ));
+                    None
+                }
+            };
+            let reproduction = json!({
+                "of": reproduction_of,
+                "source_receipt_path": source_receipt,
+                "delta": delta.map(ReproductionDelta::to_value),
+                "warnings": warning_messages(&warnings),
+                "warning_details": coded_warnings(&warnings),
+            });
+            store_result_metadata(&receipt_path, "reproduction", reproduction)?;
+            if let Some(stored) = self
+                .thread
+                .versions
+                .iter_mut()
+                .flat_map(|version| version.artifacts.iter_mut())
+                .find(|stored| {
+                    stored.get("artifact_id").and_then(Value::as_str) == Some(&artifact_id)
+                })
+            {
+                stored.insert("reproduction_of".to_string(), json!(reproduction_of));
+            }
+            artifacts.push(ReproducedArtifact {
+                artifact_id,
+                image_path,
+                receipt_path,
+                delta,
+            });
+        }
+        self.thread.save()?;
+
+        let reproduction = Reproduction {
+            version_id,
+            source_receipt: receipt_path.to_path_buf(),
+            reproduction_of,
+            artifacts,
+        };
+        self.events.emit(
+            "reproduction_completed",
+            map_object(json!({
+                "version_id": reproduction.version_id,
+                "reproduction_of": reproduction.reproduction_of,
+                "source_receipt": source_receipt,
+                "artifacts": reproduction
+                    .artifacts
+                    .iter()
+                    .map(|artifact| json!({
+                        "artifact_id": artifact.artifact_id,
+                        "delta": artifact.delta.map(ReproductionDelta::to_value),
+                    }))
+                    .collect::<Vec<Value>>(),
+            })),
+        )?;
+        Ok(reproduction)
+    }
+}
+
+/// Generation settings for a receipt whose version is not in this run.
+fn settings_from_receipt(resolved: &ResolvedRequest) -> Map<String, Value> {
+    let inputs = &resolved.inputs;
+    let mut settings = map_object(json!({
+        "size": resolved.size,
+        "output_format": resolved.output_format,
+        "provider_options": resolved.provider_params,
+        "background": resolved.background,
+        "init_image": inputs.init_image,
+        "mask": inputs.mask,
+        "reference_images": inputs.reference_images,
+        "control": inputs.control,
+    }));
+    settings.retain(|_, value| {
+        !value.is_null() && value.as_array().is_none_or(|rows| !rows.is_empty())
+    });
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::ReproductionDelta;
+
+    #[test]
+    fn delta_is_zero_for_identical_images_and_grows_with_change() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let original = temp.path().join("original.png");
+        let resized = temp.path().join("resized.png");
+        let inverted = temp.path().join("inverted.png");
+        let gradient = |width: u32, height: u32| {
+            RgbImage::from_fn(width, height, |x, _| Rgb([(x * 255 / width) as u8, 64, 64]))
+        };
+        gradient(64, 32).save(&original)?;
+        gradient(128, 64).save(&resized)?;
+        let mut flipped = gradient(64, 32);
+        image::imageops::flip_horizontal_in_place(&mut flipped);
+        flipped.save(&inverted)?;
+
+        let same = ReproductionDelta::between(&original, &original)?;
+        assert_eq!(same.pixel_delta, 0.0);
+        assert_eq!(same.dhash_distance, 0);
+        assert!(ReproductionDelta::between(&original, &resized)?.pixel_delta < 0.02);
+        let changed = ReproductionDelta::between(&original, &inverted)?;
+        assert!(changed.pixel_delta > 0.1);
+        assert!(changed.dhash_distance > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use serde_json::{json, Map, Value};
+
+use super::is_openai_gpt_image_model;
+
+/// Provider-neutral content policy selected with `settings.safety_level`.
+/// `standard` reproduces the engine's historical per-provider defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SafetyLevel {
+    Strict,
+    #[default]
+    Standard,
+    Relaxed,
+}
+
+impl SafetyLevel {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "strict" | "high" => Some(Self::Strict),
+            "standard" | "default" | "medium" => Some(Self::Standard),
+            "relaxed" | "low" | "permissive" => Some(Self::Relaxed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Standard => "standard",
+            Self::Relaxed => "relaxed",
+        }
+    }
+}
+
+const GEMINI_HARM_CATEGORIES: [&str; 4] = [
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+];
+
+/// The mapping table: provider option values for `level`. Providers without
+/// a safety control (dryrun, replicate, fal, stability) map to nothing, as do
+/// levels where the provider's own default already applies.
+pub(crate) fn safety_provider_options(
+    provider: &str,
+    model: &str,
+    level: SafetyLevel,
+) -> Map<String, Value> {
+    let mut out = Map::new();
+    match provider {
+        "openai" if is_openai_gpt_image_model(model) => {
+            let moderation = match level {
+                SafetyLevel::Strict => "auto",
+                SafetyLevel::Standard | SafetyLevel::Relaxed => "low",
+            };
+            out.insert("moderation".to_string(), json!(moderation));
+        }
+        "gemini" => {
+            let threshold = match level {
+                SafetyLevel::Strict => "BLOCK_LOW_AND_ABOVE",
+                SafetyLevel::Standard | SafetyLevel::Relaxed => "OFF",
+            };
+            out.insert(
+                "safety_settings".to_string(),
+                Value::Array(
+                    GEMINI_HARM_CATEGORIES
+                        .iter()
+                        .map(|category| json!({ "category": category, "threshold": threshold }))
+                        .collect(),
+                ),
+            );
+        }
+        "flux" => match level {
+            SafetyLevel::Strict => {
+                out.insert("safety_tolerance".to_string(), json!(0));
+            }
+            SafetyLevel::Relaxed => {
+                out.insert("safety_tolerance".to_string(), json!(5));
+            }
+            SafetyLevel::Standard => {}
+        },
+        "imagen" => match level {
+            SafetyLevel::Strict => {
+                out.insert("person_generation".to_string(), json!("dont_allow"));
+            }
+            SafetyLevel::Relaxed => {
+                out.insert("person_generation".to_string(), json!("allow_all"));
+            }
+            SafetyLevel::Standard => {}
+        },
+        _ => {}
+    }
+    out
+}
+
+/// Merges the mapped values for `settings.safety_level` into
+/// `provider_options` (explicit provider options win) and returns the record
+/// stored under `resolved.safety` in receipts.
+pub(crate) fn apply_safety_level(
+    provider: &str,
+    model: &str,
+    settings: &Map<String, Value>,
+    provider_options: &mut Map<String, Value>,
+    warnings: &mut Vec<Warning>,
+) -> Map<String, Value> {
+    let requested = settings
+        .get("safety_level")
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let level = match requested {
+        Some(raw) => SafetyLevel::parse(raw).unwrap_or_else(|| {
+            warnings.push(
+                Warning::new(
+                    WarningCode::ValueReplaced,
+                    format!(
+                        "Safety level '{raw}' unsupported; using {}.",
+                        SafetyLevel::default().as_str()
+                    ),
+                )
+                .parameter("safety_level")
+                .requested(raw)
+                .applied(SafetyLevel::default().as_str()),
+            );
+            SafetyLevel::default()
+        }),
+        None => SafetyLevel::default(),
+    };
+
+    let mut applied = Map::new();
+    let mut overridden = Vec::new();
+    for (key, value) in safety_provider_options(provider, model, level) {
+        if let Some(explicit) = provider_options.get(&key) {
+            overridden.push(Value::String(key.clone()));
+            applied.insert(key, explicit.clone());
+            continue;
+        }
+        provider_options.insert(key.clone(), value.clone());
+        applied.insert(key, value);
+    }
+    let mut record = Map::new();
+    record.insert("level".to_string(), json!(level.as_str()));
+    record.insert("applied".to_string(), Value::Object(applied));
+    if !overridden.is_empty() {
+        record.insert(
+            "overridden_by_provider_options".to_string(),
+            Value::Array(overridden),
+        );
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::{apply_safety_level, safety_provider_options, SafetyLevel};
+
+    #[test]
+    fn safety_levels_map_per_provider() {
+        let openai = safety_provider_options("openai", "gpt-image-1", SafetyLevel::Strict);
+        assert_eq!(openai["moderation"], json!("auto"));
+        assert!(safety_provider_options("openai", "dall-e-3", SafetyLevel::Strict).is_empty());
+
+        let gemini = safety_provider_options("gemini", "", SafetyLevel::Standard);
+        let rows = gemini["safety_settings"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(rows.len(), 4);
+        assert!(rows.iter().all(|row| row["threshold"] == json!("OFF")));
+
+        assert_eq!(
+            safety_provider_options("flux", "flux-2", SafetyLevel::Relaxed)["safety_tolerance"],
+            json!(5)
+        );
+        assert_eq!(
+            safety_provider_options("imagen", "imagen-4", SafetyLevel::Strict)["person_generation"],
+            json!("dont_allow")
+        );
+        assert!(safety_provider_options("flux", "flux-2", SafetyLevel::Standard).is_empty());
+        assert!(safety_provider_options("dryrun", "", SafetyLevel::Strict).is_empty());
+    }
+
+    #[test]
+    fn explicit_provider_options_override_safety_level() {
+        let mut settings = Map::new();
+        settings.insert("safety_level".to_string(), json!("strict"));
+        let mut options = Map::new();
+        options.insert("safety_tolerance".to_string(), json!(3));
+        let mut warnings = Vec::new();
+        let record = apply_safety_level("flux", "flux-2", &settings, &mut options, &mut warnings);
+        assert_eq!(options["safety_tolerance"], json!(3));
+        assert_eq!(record["applied"]["safety_tolerance"], json!(3));
+        assert_eq!(
+            record["overridden_by_provider_options"],
+            json!(["safety_tolerance"])
+        );
+
+        settings.insert("safety_level".to_string(), json!("yolo"));
+        let record = apply_safety_level(
+            "openai",
+            "gpt-image-1",
+            &settings,
+            &mut Map::new(),
+            &mut warnings,
+        );
+        assert_eq!(record["level"], json!("standard"));
+        assert_eq!(
+            record["applied"]["moderation"],
+            Value::String("low".to_string())
+        );
+        assert!(warnings[0].message.contains("'yolo' unsupported"));
+    }
+}
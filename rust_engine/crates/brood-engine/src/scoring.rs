@@ -0,0 +1,347 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use image::GrayImage;
+use serde_json::{json, Map, Value};
+
+use super::{map_object, NativeEngine, SVG_MIME};
+
+/// Longest edge images are reduced to before measuring; keeps scoring cheap
+/// for 4K outputs without changing which artifact ranks highest.
+const SCORING_MAX_EDGE: u32 = 512;
+
+/// Prompt/image similarity from an external model (e.g. a CLIP endpoint).
+/// Scores are expected in `0.0..=1.0`. Configure with
+/// [`crate::NativeEngine::set_clip_scorer`].
+pub trait ClipScorer: Send + Sync {
+    fn clip_score(&self, image_path: &Path, prompt: &str) -> Result<f64>;
+}
+
+/// Objective quality metrics for one artifact. `score` is only comparable
+/// within the set of artifacts it was computed with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactScore {
+    pub artifact_id: String,
+    /// Variance of the Laplacian of the luma channel (higher = sharper).
+    pub sharpness: f64,
+    /// Shannon entropy of the luma histogram in bits (0..=8).
+    pub entropy: f64,
+    pub clip_score: Option<f64>,
+    pub score: f64,
+}
+
+impl ArtifactScore {
+    pub fn to_map(&self) -> Map<String, Value> {
+        let mut out = Map::new();
+        out.insert("sharpness".to_string(), json!(self.sharpness));
+        out.insert("entropy".to_string(), json!(self.entropy));
+        out.insert("clip_score".to_string(), json!(self.clip_score));
+        out.insert("score".to_string(), json!(self.score));
+        out
+    }
+}
+
+/// An artifact image and the prompt it was generated from.
+#[derive(Debug, Clone)]
+pub(crate) struct ScoringCandidate {
+    pub(crate) artifact_id: String,
+    pub(crate) image_path: PathBuf,
+    pub(crate) prompt: String,
+}
+
+impl ScoringCandidate {
+    /// `None` for rows without paths and for vector (SVG) artifacts, which
+    /// have no pixels to measure.
+    pub(crate) fn from_artifact(artifact: &Map<String, Value>, prompt: &str) -> Option<Self> {
+        if artifact.get("mime").and_then(Value::as_str) == Some(SVG_MIME) {
+            return None;
+        }
+        Some(Self {
+            artifact_id: artifact.get("artifact_id")?.as_str()?.to_string(),
+            image_path: PathBuf::from(artifact.get("image_path")?.as_str()?),
+            prompt: prompt.to_string(),
+        })
+    }
+}
+
+/// `(sharpness, entropy)` for the image at `path`.
+pub fn image_quality_metrics(path: &Path) -> Result<(f64, f64)> {
+    let image = image::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let image = if image.width().max(image.height()) > SCORING_MAX_EDGE {
+        image.resize(SCORING_MAX_EDGE, SCORING_MAX_EDGE, FilterType::Triangle)
+    } else {
+        image
+    };
+    let gray = image.to_luma8();
+    Ok((laplacian_variance(&gray), luma_entropy(&gray)))
+}
+
+/// Scores every candidate. Sharpness is normalized
+/// against the sharpest artifact in the set; with a CLIP scorer the blend is
+/// 50% CLIP, 25% sharpness, 25% entropy, otherwise sharpness and entropy
+/// are weighted equally. A failed CLIP call only drops that component.
+pub(crate) fn score_artifacts(
+    candidates: &[ScoringCandidate],
+    clip: Option<&dyn ClipScorer>,
+) -> Result<Vec<ArtifactScore>> {
+    let mut scores = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let (sharpness, entropy) = image_quality_metrics(&candidate.image_path)?;
+        scores.push(ArtifactScore {
+            artifact_id: candidate.artifact_id.clone(),
+            sharpness,
+            entropy,
+            clip_score: clip
+                .and_then(|scorer| {
+                    scorer
+                        .clip_score(&candidate.image_path, &candidate.prompt)
+                        .ok()
+                })
+                .map(|value| value.clamp(0.0, 1.0)),
+            score: 0.0,
+        });
+    }
+    let max_sharpness = scores
+        .iter()
+        .map(|score| score.sharpness)
+        .fold(0.0f64, f64::max);
+    for score in &mut scores {
+        let sharpness = if max_sharpness > 0.0 {
+            score.sharpness / max_sharpness
+        } else {
+            0.0
+        };
+        let entropy = score.entropy / 8.0;
+        score.score = match score.clip_score {
+            Some(clip) => 0.5 * clip + 0.25 * sharpness + 0.25 * entropy,
+            None => 0.5 * sharpness + 0.5 * entropy,
+        };
+    }
+    Ok(scores)
+}
+
+impl NativeEngine {
+    /// Enables the CLIP component of artifact scores.
+    pub fn set_clip_scorer(&mut self, scorer: Option<Box<dyn ClipScorer>>) {
+        self.clip_scorer = scorer;
+    }
+
+    /// Scores the artifacts of a version against each other and stores the
+    /// metrics under `quality` on each artifact row.
+    pub fn score_version(&mut self, version_id: &str) -> Result<Vec<ArtifactScore>> {
+        let Some(version) = self
+            .thread
+            .live_versions()
+            .find(|version| version.version_id == version_id)
+        else {
+            bail!("version '{version_id}' not found");
+        };
+        let candidates: Vec<ScoringCandidate> = version
+            .artifacts
+            .iter()
+            // Artifacts a hook rejected are not candidates for selection.
+            .filter(|artifact| !artifact.contains_key("rejected"))
+            .filter_map(|artifact| ScoringCandidate::from_artifact(artifact, &version.prompt))
+            .collect();
+        if candidates.is_empty() {
+            bail!("version '{version_id}' has no raster artifacts to score");
+        }
+        let scores = score_artifacts(&candidates, self.clip_scorer.as_deref())?;
+
+        if let Some(version) = self
+            .thread
+            .versions
+            .iter_mut()
+            .find(|version| version.version_id == version_id)
+        {
+            for artifact in &mut version.artifacts {
+                let artifact_id = artifact.get("artifact_id").and_then(Value::as_str);
+                if let Some(score) = scores
+                    .iter()
+                    .find(|score| Some(score.artifact_id.as_str()) == artifact_id)
+                {
+                    artifact.insert("quality".to_string(), Value::Object(score.to_map()));
+                }
+            }
+        }
+        self.thread.save()?;
+        self.events.emit(
+            "version_scored",
+            map_object(json!({
+                "version_id": version_id,
+                "scores": scores
+                    .iter()
+                    .map(|score| {
+                        let mut row = score.to_map();
+                        row.insert("artifact_id".to_string(), json!(score.artifact_id));
+                        Value::Object(row)
+                    })
+                    .collect::<Vec<_>>(),
+            })),
+        )?;
+        Ok(scores)
+    }
+
+    /// Scores a version and selects its best artifact, which then appears in
+    /// the run summary's winners. Ties keep the earlier artifact.
+    pub fn auto_select(&mut self, version_id: &str) -> Result<ArtifactScore> {
+        let scores = self.score_version(version_id)?;
+        let Some(best) =
+            scores
+                .into_iter()
+                .reduce(|best, next| if next.score > best.score { next } else { best })
+        else {
+            bail!("version '{version_id}' has no raster artifacts to score");
+        };
+        let reason = format!("auto_select: quality score {:.3}", best.score);
+        self.thread
+            .select_artifact(version_id, &best.artifact_id, Some(&reason));
+        self.thread.save()?;
+        self.events.emit(
+            "artifact_selected",
+            map_object(json!({
+                "version_id": version_id,
+                "artifact_id": best.artifact_id,
+                "reason": "auto_select",
+                "score": best.score,
+            })),
+        )?;
+        Ok(best)
+    }
+}
+
+fn laplacian_variance(gray: &GrayImage) -> f64 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| f64::from(gray.get_pixel(x, y)[0]);
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut count = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let value = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += value;
+            sum_sq += value * value;
+            count += 1.0;
+        }
+    }
+    let mean = sum / count;
+    (sum_sq / count - mean * mean).max(0.0)
+}
+
+fn luma_entropy(gray: &GrayImage) -> f64 {
+    let mut histogram = [0u64; 256];
+    for pixel in gray.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total = gray.pixels().len() as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+    histogram
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use image::{GrayImage, Luma};
+
+    use serde_json::{json, Map, Value};
+
+    use super::{score_artifacts, ClipScorer, ScoringCandidate};
+    use crate::{map_object, NativeEngine};
+
+    struct FlatPreferringClip;
+
+    impl ClipScorer for FlatPreferringClip {
+        fn clip_score(&self, image_path: &Path, _prompt: &str) -> anyhow::Result<f64> {
+            Ok(if image_path.ends_with("flat.png") {
+                1.0
+            } else {
+                0.0
+            })
+        }
+    }
+
+    #[test]
+    fn sharper_detailed_images_score_higher() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let flat = temp.path().join("flat.png");
+        let checker = temp.path().join("checker.png");
+        GrayImage::from_pixel(32, 32, Luma([128])).save(&flat)?;
+        GrayImage::from_fn(32, 32, |x, y| {
+            Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])
+        })
+        .save(&checker)?;
+        let candidates: Vec<ScoringCandidate> = [("flat", &flat), ("checker", &checker)]
+            .into_iter()
+            .map(|(artifact_id, path)| ScoringCandidate {
+                artifact_id: artifact_id.to_string(),
+                image_path: path.clone(),
+                prompt: "test".to_string(),
+            })
+            .collect();
+
+        let scores = score_artifacts(&candidates, None)?;
+        assert_eq!(scores[0].sharpness, 0.0);
+        assert_eq!(scores[0].entropy, 0.0);
+        assert!((scores[1].entropy - 1.0).abs() < 1e-9);
+        assert!(scores[1].score > scores[0].score);
+
+        let with_clip = score_artifacts(&candidates, Some(&FlatPreferringClip))?;
+        assert_eq!(with_clip[0].clip_score, Some(1.0));
+        assert!(with_clip[0].score > with_clip[1].score);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_select_records_winner_in_summary() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let events_path = run_dir.join("events.jsonl");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            &events_path,
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let mut settings = Map::new();
+        settings.insert("size".to_string(), json!("32x32"));
+        settings.insert("n".to_string(), json!(2));
+        settings.insert("auto_select".to_string(), json!(true));
+        let artifacts = engine.generate(
+            "a lighthouse",
+            settings,
+            map_object(json!({"action": "generate"})),
+        )?;
+        assert_eq!(artifacts.len(), 2);
+        engine.finish()?;
+
+        let thread: Value =
+            serde_json::from_str(&std::fs::read_to_string(run_dir.join("thread.json"))?)?;
+        let version = &thread["versions"][0];
+        assert!(version["settings"].get("auto_select").is_none());
+        assert!(version["artifacts"][0]["quality"]["score"].is_number());
+        let selected = version["selected_artifact_id"].as_str().unwrap_or_default();
+        assert!(!selected.is_empty());
+        let summary: Value =
+            serde_json::from_str(&std::fs::read_to_string(run_dir.join("summary.json"))?)?;
+        assert_eq!(summary["winners"][0]["artifact_id"], json!(selected));
+        let events = std::fs::read_to_string(&events_path)?;
+        assert!(events.contains("\"type\":\"version_scored\""));
+        assert!(events.contains("\"type\":\"artifact_selected\""));
+        assert!(engine.auto_select("v9").is_err());
+        Ok(())
+    }
+}
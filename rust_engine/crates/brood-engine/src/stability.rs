@@ -0,0 +1,590 @@
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use reqwest::blocking::multipart::Form as MultipartForm;
+use serde_json::{json, Map, Value};
+
+use super::download::Download;
+use super::progress::report_generation_progress;
+use super::timeouts::{scoped_http, ScopedTimeout, TimeoutKind};
+use super::{
+    push_unique_warning, truncate_text, ImageBytes, ReplaySend, ReplicateProvider,
+    StabilityProvider, STABILITY_DEFAULT_STRENGTH,
+};
+
+/// A Stability `v2beta/stable-image` endpoint. Picked with the
+/// `stability_operation` provider option, the model name (`sd3*`,
+/// `stable-image-ultra`, `stable-image-core`) or the request's inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StabilityOperation {
+    Core,
+    Ultra,
+    Sd3,
+    Inpaint,
+    Outpaint,
+    Erase,
+    SearchAndReplace,
+    UpscaleFast,
+    UpscaleConservative,
+    UpscaleCreative,
+    Sketch,
+    Structure,
+    Style,
+}
+
+/// Whether an operation sends the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptUse {
+    Required,
+    Optional,
+    Unused,
+}
+
+/// Provider options some operation sends as a form field. Options in this
+/// list that the chosen operation does not take are dropped with a
+/// warning; `strength` only applies to image-to-image.
+const STABILITY_OPTION_FIELDS: &[&str] = &[
+    "negative_prompt",
+    "style_preset",
+    "cfg_scale",
+    "strength",
+    "search_prompt",
+    "grow_mask",
+    "creativity",
+    "left",
+    "right",
+    "up",
+    "down",
+    "fidelity",
+    "control_strength",
+];
+
+impl StabilityOperation {
+    pub const ALL: [Self; 13] = [
+        Self::Core,
+        Self::Ultra,
+        Self::Sd3,
+        Self::Inpaint,
+        Self::Outpaint,
+        Self::Erase,
+        Self::SearchAndReplace,
+        Self::UpscaleFast,
+        Self::UpscaleConservative,
+        Self::UpscaleCreative,
+        Self::Sketch,
+        Self::Structure,
+        Self::Style,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Core => "core",
+            Self::Ultra => "ultra",
+            Self::Sd3 => "sd3",
+            Self::Inpaint => "inpaint",
+            Self::Outpaint => "outpaint",
+            Self::Erase => "erase",
+            Self::SearchAndReplace => "search-and-replace",
+            Self::UpscaleFast => "upscale-fast",
+            Self::UpscaleConservative => "upscale-conservative",
+            Self::UpscaleCreative => "upscale-creative",
+            Self::Sketch => "sketch",
+            Self::Structure => "structure",
+            Self::Style => "style",
+        }
+    }
+
+    /// Path under the API base, e.g. `v2beta/stable-image/generate/core`.
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Core => "v2beta/stable-image/generate/core",
+            Self::Ultra => "v2beta/stable-image/generate/ultra",
+            Self::Sd3 => "v2beta/stable-image/generate/sd3",
+            Self::Inpaint => "v2beta/stable-image/edit/inpaint",
+            Self::Outpaint => "v2beta/stable-image/edit/outpaint",
+            Self::Erase => "v2beta/stable-image/edit/erase",
+            Self::SearchAndReplace => "v2beta/stable-image/edit/search-and-replace",
+            Self::UpscaleFast => "v2beta/stable-image/upscale/fast",
+            Self::UpscaleConservative => "v2beta/stable-image/upscale/conservative",
+            Self::UpscaleCreative => "v2beta/stable-image/upscale/creative",
+            Self::Sketch => "v2beta/stable-image/control/sketch",
+            Self::Structure => "v2beta/stable-image/control/structure",
+            Self::Style => "v2beta/stable-image/control/style",
+        }
+    }
+
+    /// Accepts [`Self::name`] with `_` for `-`.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let normalized = raw.trim().to_ascii_lowercase().replace('_', "-");
+        Self::ALL
+            .into_iter()
+            .find(|operation| operation.name() == normalized)
+            .with_context(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|operation| operation.name()).collect();
+                format!(
+                    "unknown stability_operation '{raw}' (expected one of {})",
+                    names.join(", ")
+                )
+            })
+    }
+
+    /// The generator a model name asks for, if it names one.
+    pub(crate) fn from_model(model: &str) -> Option<Self> {
+        let model = model.trim().to_ascii_lowercase();
+        match model.as_str() {
+            "stable-image-ultra" | "ultra" => Some(Self::Ultra),
+            "stable-image-core" | "core" => Some(Self::Core),
+            _ if model.starts_with("sd3") => Some(Self::Sd3),
+            _ => None,
+        }
+    }
+
+    /// The operation an endpoint URL or path points at.
+    pub(crate) fn from_endpoint(endpoint: &str) -> Option<Self> {
+        let endpoint = endpoint.trim_end_matches('/');
+        Self::ALL
+            .into_iter()
+            .find(|operation| endpoint.ends_with(operation.path()))
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, Self::Sketch | Self::Structure | Self::Style)
+    }
+
+    pub(crate) fn is_upscale(self) -> bool {
+        matches!(
+            self,
+            Self::UpscaleFast | Self::UpscaleConservative | Self::UpscaleCreative
+        )
+    }
+
+    /// Operations that edit or upscale an existing image.
+    pub(crate) fn needs_init_image(self) -> bool {
+        self.is_upscale()
+            || matches!(
+                self,
+                Self::Inpaint | Self::Outpaint | Self::Erase | Self::SearchAndReplace
+            )
+    }
+
+    pub(crate) fn takes_mask(self) -> bool {
+        matches!(self, Self::Inpaint | Self::Erase)
+    }
+
+    fn prompt_use(self) -> PromptUse {
+        match self {
+            Self::Erase | Self::UpscaleFast => PromptUse::Unused,
+            Self::Outpaint => PromptUse::Optional,
+            _ => PromptUse::Required,
+        }
+    }
+
+    /// Image-to-image generators; they also take `strength`.
+    fn takes_init_image(self) -> bool {
+        matches!(self, Self::Ultra | Self::Sd3)
+    }
+
+    fn option_fields(self) -> &'static [&'static str] {
+        match self {
+            Self::Core => &["negative_prompt", "style_preset"],
+            Self::Ultra => &["negative_prompt", "style_preset"],
+            Self::Sd3 => &["negative_prompt", "style_preset", "cfg_scale"],
+            Self::Inpaint => &["negative_prompt", "style_preset", "grow_mask"],
+            Self::Outpaint => &["style_preset", "creativity", "left", "right", "up", "down"],
+            Self::Erase => &["grow_mask"],
+            Self::SearchAndReplace => &[
+                "search_prompt",
+                "negative_prompt",
+                "style_preset",
+                "grow_mask",
+            ],
+            Self::UpscaleFast => &[],
+            Self::UpscaleConservative => &["negative_prompt", "creativity"],
+            Self::UpscaleCreative => &["negative_prompt", "style_preset", "creativity"],
+            Self::Sketch | Self::Structure => {
+                &["negative_prompt", "style_preset", "control_strength"]
+            }
+            Self::Style => &["negative_prompt", "style_preset", "fidelity"],
+        }
+    }
+
+    /// Creative upscale answers with a generation id to poll.
+    pub(crate) fn is_async(self) -> bool {
+        self == Self::UpscaleCreative
+    }
+}
+
+/// What one Stability call sends, independent of where it came from
+/// (a generation or an upscale).
+pub(crate) struct StabilityRequest<'a> {
+    pub(crate) prompt: &'a str,
+    pub(crate) image: Option<&'a str>,
+    pub(crate) mask: Option<&'a str>,
+    pub(crate) control_strength: Option<f64>,
+    pub(crate) aspect_ratio: String,
+    pub(crate) model: &'a str,
+    pub(crate) output_format: &'a str,
+    pub(crate) options: &'a Map<String, Value>,
+}
+
+/// Stability's `output_format` for a normalized extension (`jpg` is sent
+/// as `jpeg`); SD3 cannot return WebP.
+fn stability_output_format(
+    operation: StabilityOperation,
+    ext: &str,
+    warnings: &mut Vec<Warning>,
+) -> &'static str {
+    match ext {
+        "jpg" => "jpeg",
+        "webp" if operation == StabilityOperation::Sd3 => {
+            push_unique_warning(
+                warnings,
+                Warning::new(
+                    WarningCode::ValueReplaced,
+                    "Stability output_format 'webp' unsupported; using png (sd3 endpoint).",
+                )
+                .parameter("output_format")
+                .requested("webp")
+                .applied("png"),
+            );
+            "png"
+        }
+        "webp" => "webp",
+        _ => "png",
+    }
+}
+
+/// Adds a text field to `form` and records it in `manifest`.
+fn form_text(
+    form: MultipartForm,
+    manifest: &mut Map<String, Value>,
+    key: &str,
+    value: Value,
+) -> MultipartForm {
+    let raw = match &value {
+        Value::String(raw) => raw.clone(),
+        other => other.to_string(),
+    };
+    manifest.insert(key.to_string(), value);
+    form.text(key.to_string(), raw)
+}
+
+/// A string (trimmed, non-empty) or number option as a form value.
+fn option_value(options: &Map<String, Value>, key: &str) -> Option<Value> {
+    match options.get(key)? {
+        Value::String(value) => Some(value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| json!(value)),
+        Value::Number(value) => Some(Value::Number(value.clone())),
+        _ => None,
+    }
+}
+
+impl StabilityProvider {
+    /// The multipart form for one call and a JSON manifest of its text
+    /// fields (files as paths) for the receipt.
+    pub(crate) fn operation_form(
+        operation: StabilityOperation,
+        request: &StabilityRequest,
+        seed: Option<i64>,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(MultipartForm, Map<String, Value>)> {
+        let mut form = MultipartForm::new();
+        let mut manifest = Map::new();
+        let name = operation.name();
+
+        let prompt = request.prompt.trim();
+        match operation.prompt_use() {
+            PromptUse::Required if prompt.is_empty() => {
+                bail!("Stability {name} requires a prompt.")
+            }
+            PromptUse::Unused if !prompt.is_empty() => push_unique_warning(
+                warnings,
+                Warning::new(
+                    WarningCode::ValueDropped,
+                    format!("Stability prompt unsupported; ignoring ({name} endpoint)."),
+                )
+                .parameter("prompt"),
+            ),
+            PromptUse::Unused => {}
+            _ if !prompt.is_empty() => {
+                form = form_text(form, &mut manifest, "prompt", json!(request.prompt))
+            }
+            _ => {}
+        }
+
+        let image = request.image.filter(|_| {
+            operation.needs_init_image() || operation.is_control() || operation.takes_init_image()
+        });
+        match image {
+            Some(image) => {
+                form = form.part("image", Self::file_part(Path::new(image))?);
+                manifest.insert("image".to_string(), json!(image));
+            }
+            None if operation.needs_init_image() => {
+                bail!("Stability {name} requires an init image.")
+            }
+            None if operation.is_control() => {
+                bail!("Stability control endpoints require an init or reference image.")
+            }
+            None => {}
+        }
+        let image_to_image = image.is_some() && operation.takes_init_image();
+        if image_to_image {
+            if operation == StabilityOperation::Sd3 {
+                form = form_text(form, &mut manifest, "mode", json!("image-to-image"));
+            }
+            let strength = request
+                .options
+                .get("strength")
+                .and_then(Value::as_f64)
+                .unwrap_or(STABILITY_DEFAULT_STRENGTH)
+                .clamp(0.0, 1.0);
+            form = form_text(form, &mut manifest, "strength", json!(strength));
+        } else if matches!(
+            operation,
+            StabilityOperation::Core | StabilityOperation::Ultra | StabilityOperation::Sd3
+        ) {
+            form = form_text(
+                form,
+                &mut manifest,
+                "aspect_ratio",
+                json!(request.aspect_ratio),
+            );
+        }
+
+        match request.mask {
+            Some(mask) if operation.takes_mask() => {
+                form = form.part("mask", Self::file_part(Path::new(mask))?);
+                manifest.insert("mask".to_string(), json!(mask));
+            }
+            Some(_) => push_unique_warning(
+                warnings,
+                Warning::new(
+                    WarningCode::ValueDropped,
+                    "Stability mask ignored outside the inpaint and erase endpoints.",
+                )
+                .parameter("mask"),
+            ),
+            None if operation == StabilityOperation::Inpaint => {
+                bail!("Stability inpaint requires a mask.")
+            }
+            None => {}
+        }
+        if operation == StabilityOperation::SearchAndReplace
+            && option_value(request.options, "search_prompt").is_none()
+        {
+            bail!("Stability search-and-replace requires a search_prompt option.");
+        }
+        if operation == StabilityOperation::Sd3 && request.model.starts_with("sd3") {
+            form = form_text(form, &mut manifest, "model", json!(request.model));
+        }
+
+        for key in operation.option_fields() {
+            let value =
+                match *key {
+                    "control_strength" => request
+                        .control_strength
+                        .map(|value| json!(value))
+                        .or_else(|| {
+                            request
+                                .options
+                                .get(*key)
+                                .filter(|value| value.is_number())
+                                .cloned()
+                        }),
+                    "left" | "right" | "up" | "down" => request
+                        .options
+                        .get(*key)
+                        .and_then(Value::as_u64)
+                        .map(Value::from),
+                    _ => option_value(request.options, key),
+                };
+            if let Some(value) = value {
+                form = form_text(form, &mut manifest, key, value);
+            }
+        }
+        for key in STABILITY_OPTION_FIELDS {
+            let used =
+                operation.option_fields().contains(key) || (*key == "strength" && image_to_image);
+            if !used
+                && request
+                    .options
+                    .get(*key)
+                    .is_some_and(|value| !value.is_null())
+            {
+                push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ValueDropped,
+                        format!("Stability {key} unsupported; ignoring ({name} endpoint)."),
+                    )
+                    .parameter(*key),
+                );
+            }
+        }
+
+        if let Some(seed) = seed.filter(|_| operation != StabilityOperation::UpscaleFast) {
+            form = form_text(form, &mut manifest, "seed", json!(seed));
+        }
+        let output_format = stability_output_format(operation, request.output_format, warnings);
+        form = form_text(form, &mut manifest, "output_format", json!(output_format));
+        Ok((form, manifest))
+    }
+
+    /// Posts `form` and returns the status code and image. Creative
+    /// upscale answers with an id first; its result is polled.
+    pub(crate) fn send_operation(
+        &self,
+        operation: StabilityOperation,
+        endpoint: &str,
+        api_key: &str,
+        form: MultipartForm,
+        options: &Map<String, Value>,
+    ) -> Result<(u16, ImageBytes)> {
+        if operation.is_async() {
+            return self.run_async_operation(endpoint, api_key, form, options);
+        }
+        let response = scoped_http(&self.http)
+            .post(endpoint)
+            .bearer_auth(api_key)
+            .header("Accept", "image/*")
+            .multipart(form)
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("Stability request failed ({endpoint})"))?;
+        let status_code = response.status().as_u16();
+        if !response.status().is_success() {
+            let body = response.text().unwrap_or_default();
+            bail!(
+                "Stability request failed ({status_code}): {}",
+                truncate_text(&body, 512)
+            );
+        }
+        Ok((status_code, Self::response_image(response)?))
+    }
+
+    /// An `image/*` body, or a JSON body's base64 image.
+    fn response_image(response: reqwest::blocking::Response) -> Result<ImageBytes> {
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_ascii_lowercase())
+            .unwrap_or_default();
+        if content_type.starts_with("image/") {
+            return Ok(ImageBytes::Download(Download::start(
+                response,
+                "Stability image",
+            )?));
+        }
+        let payload: Value = response
+            .json()
+            .context("failed parsing Stability JSON response")?;
+        Self::decode_json_image(&payload)
+    }
+
+    /// Submits a creative upscale and polls `v2beta/results/{id}` until it
+    /// returns the image (202 means still running).
+    fn run_async_operation(
+        &self,
+        endpoint: &str,
+        api_key: &str,
+        form: MultipartForm,
+        options: &Map<String, Value>,
+    ) -> Result<(u16, ImageBytes)> {
+        let response = scoped_http(&self.http)
+            .post(endpoint)
+            .bearer_auth(api_key)
+            .header("Accept", "application/json")
+            .multipart(form)
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("Stability request failed ({endpoint})"))?;
+        let status_code = response.status().as_u16();
+        let body = response.text().unwrap_or_default();
+        if !(200..300).contains(&status_code) {
+            bail!(
+                "Stability request failed ({status_code}): {}",
+                truncate_text(&body, 512)
+            );
+        }
+        let submitted: Value =
+            serde_json::from_str(&body).context("failed parsing Stability JSON response")?;
+        let Some(id) = submitted.get("id").and_then(Value::as_str) else {
+            bail!("Stability response has no generation id");
+        };
+        let poll_url = format!("{}/v2beta/results/{id}", self.api_base);
+        let poll_interval_s = ReplicateProvider::poll_interval_seconds(options);
+        let poll_timeout_s = ReplicateProvider::poll_timeout_seconds(options);
+        let started = Instant::now();
+        loop {
+            let response = scoped_http(&self.http)
+                .get(&poll_url)
+                .bearer_auth(api_key)
+                .header("Accept", "image/*")
+                .scoped_timeout(TimeoutKind::Request)
+                .send_replayable()
+                .with_context(|| format!("Stability result request failed ({poll_url})"))?;
+            let code = response.status().as_u16();
+            if code != 202 {
+                if !response.status().is_success() {
+                    let body = response.text().unwrap_or_default();
+                    bail!(
+                        "Stability result failed ({code}): {}",
+                        truncate_text(&body, 512)
+                    );
+                }
+                return Ok((code, Self::response_image(response)?));
+            }
+            if started.elapsed().as_secs_f64() >= poll_timeout_s {
+                bail!("Stability polling timed out after {:.1}s", poll_timeout_s);
+            }
+            report_generation_progress("in-progress", started.elapsed(), None, None);
+            thread::sleep(Duration::from_secs_f64(poll_interval_s));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StabilityOperation;
+
+    #[test]
+    fn operations_parse_and_resolve_from_models_and_endpoints() {
+        for operation in StabilityOperation::ALL {
+            assert_eq!(
+                StabilityOperation::parse(operation.name()).ok(),
+                Some(operation)
+            );
+            assert_eq!(
+                StabilityOperation::from_endpoint(&format!(
+                    "https://api.stability.ai/{}",
+                    operation.path()
+                )),
+                Some(operation)
+            );
+        }
+        assert_eq!(
+            StabilityOperation::parse(" Search_And_Replace ").ok(),
+            Some(StabilityOperation::SearchAndReplace)
+        );
+        assert!(StabilityOperation::parse("sharpen").is_err());
+        assert_eq!(
+            StabilityOperation::from_model("sd3.5-large"),
+            Some(StabilityOperation::Sd3)
+        );
+        assert_eq!(
+            StabilityOperation::from_model("stable-image-ultra"),
+            Some(StabilityOperation::Ultra)
+        );
+        assert_eq!(StabilityOperation::from_model("gpt-image-1"), None);
+        assert_eq!(
+            StabilityOperation::from_endpoint("https://example.com/v1/generate"),
+            None
+        );
+    }
+}
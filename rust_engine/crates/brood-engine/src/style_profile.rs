@@ -0,0 +1,279 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::chat::normalize_hex_color;
+use serde_json::{json, Map, Value};
+
+use super::{file_stem_name, non_empty_env, stable_hash, NativeEngine};
+
+/// Directory holding `<name>.json` style profiles; defaults to
+/// `~/.brood/profiles`.
+pub const STYLE_PROFILES_DIR_ENV: &str = "BROOD_PROFILES_DIR";
+
+/// Providers that take `provider_options.negative_prompt`; for the rest the
+/// negative prompts are written into the prompt instead.
+const NEGATIVE_PROMPT_PROVIDERS: &[&str] = &["stability", "recraft"];
+
+/// A brand kit applied to every generation while it is active: palette,
+/// logo watermark, tone words, negative prompts and default provider
+/// options. Loaded from `<profiles dir>/<name>.json`:
+///
+/// ```json
+/// {"palette": ["#1428a0", "#ffffff"], "logo": "acme-logo.png",
+///  "tone_words": ["confident", "friendly"],
+///  "negative_prompts": ["clutter", "text"],
+///  "provider_options": {"quality": "high"}}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleProfile {
+    pub name: String,
+    pub path: PathBuf,
+    /// Lowercase `#rrggbb`; becomes `settings.palette`.
+    pub palette: Vec<String>,
+    /// Stamped on outputs as `settings.watermark.logo`. Relative paths are
+    /// resolved against the profile's directory.
+    pub logo: Option<PathBuf>,
+    pub tone_words: Vec<String>,
+    pub negative_prompts: Vec<String>,
+    /// Defaults under any `settings.provider_options`.
+    pub provider_options: Map<String, Value>,
+}
+
+impl StyleProfile {
+    /// `$BROOD_PROFILES_DIR`, else `~/.brood/profiles`.
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = non_empty_env(STYLE_PROFILES_DIR_ENV) {
+            return Some(PathBuf::from(dir));
+        }
+        env::var_os("HOME")
+            .map(PathBuf::from)
+            .map(|home| home.join(".brood").join("profiles"))
+    }
+
+    /// The profile `name` from [`StyleProfile::default_dir`]; `None` when
+    /// there is no such file.
+    pub fn load(name: &str) -> Result<Option<Self>> {
+        match Self::default_dir() {
+            Some(dir) => Self::load_from(&dir, name),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_from(dir: &Path, name: &str) -> Result<Option<Self>> {
+        let name = file_stem_name("profile", name)?;
+        let path = dir.join(format!("{name}.json"));
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read profile {}", path.display()))?;
+        Self::parse(name, &path, &raw)
+            .with_context(|| format!("invalid profile {}", path.display()))
+            .map(Some)
+    }
+
+    /// Profile names in `dir`, sorted.
+    pub fn available(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                (path.extension().and_then(|value| value.to_str()) == Some("json"))
+                    .then(|| path.file_stem()?.to_str().map(str::to_string))
+                    .flatten()
+            })
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub fn parse(name: &str, path: &Path, raw: &str) -> Result<Self> {
+        let payload: Value = serde_json::from_str(raw)?;
+        let Some(fields) = payload.as_object() else {
+            bail!("expected a JSON object");
+        };
+        let words = |key: &str| -> Result<Vec<String>> {
+            match fields.get(key) {
+                None | Some(Value::Null) => Ok(Vec::new()),
+                Some(Value::Array(items)) => items
+                    .iter()
+                    .map(|item| match item.as_str().map(str::trim) {
+                        Some(text) if !text.is_empty() => Ok(text.to_string()),
+                        _ => bail!("{key} must be a list of non-empty strings (got {item})"),
+                    })
+                    .collect(),
+                Some(other) => bail!("{key} must be a list of strings (got {other})"),
+            }
+        };
+        let palette = words("palette")?
+            .iter()
+            .map(|color| {
+                normalize_hex_color(color)
+                    .with_context(|| format!("palette color must be #rgb or #rrggbb (got {color})"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let logo = match fields.get("logo") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(logo)) if !logo.trim().is_empty() => {
+                let logo = PathBuf::from(logo.trim());
+                Some(match path.parent() {
+                    Some(dir) if logo.is_relative() => dir.join(logo),
+                    _ => logo,
+                })
+            }
+            Some(other) => bail!("logo must be a path (got {other})"),
+        };
+        let provider_options = match fields.get("provider_options") {
+            None | Some(Value::Null) => Map::new(),
+            Some(Value::Object(options)) => options.clone(),
+            Some(other) => bail!("provider_options must be an object (got {other})"),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            palette,
+            logo,
+            tone_words: words("tone_words")?,
+            negative_prompts: words("negative_prompts")?,
+            provider_options,
+        })
+    }
+
+    pub fn to_value(&self) -> Value {
+        json!({
+            "name": self.name,
+            "path": self.path.to_string_lossy(),
+            "palette": self.palette,
+            "logo": self.logo.as_ref().map(|logo| logo.to_string_lossy().to_string()),
+            "tone_words": self.tone_words,
+            "negative_prompts": self.negative_prompts,
+            "provider_options": self.provider_options,
+        })
+    }
+
+    /// Changes whenever the profile's contents do, so cached generations
+    /// are not reused across edits of the profile.
+    pub fn fingerprint(&self) -> String {
+        stable_hash(&self.to_value())
+    }
+
+    /// Merges the profile under `settings`: values the request already sets
+    /// win. Returns the settings keys it filled.
+    pub(crate) fn apply(
+        &self,
+        provider: &str,
+        settings: &mut Map<String, Value>,
+    ) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        if !self.palette.is_empty() && !settings.contains_key("palette") {
+            settings.insert("palette".to_string(), json!(self.palette));
+            applied.push("palette");
+        }
+        if let Some(logo) = &self.logo {
+            if !settings.contains_key("watermark") {
+                settings.insert(
+                    "watermark".to_string(),
+                    json!({ "logo": logo.to_string_lossy() }),
+                );
+                applied.push("watermark");
+            }
+        }
+        let mut options = self.provider_options.clone();
+        if !self.negative_prompts.is_empty() && NEGATIVE_PROMPT_PROVIDERS.contains(&provider) {
+            options.insert(
+                "negative_prompt".to_string(),
+                json!(self.negative_prompts.join(", ")),
+            );
+        }
+        if !options.is_empty() {
+            if let Some(explicit) = settings.get("provider_options").and_then(Value::as_object) {
+                options.extend(explicit.clone());
+            }
+            settings.insert("provider_options".to_string(), Value::Object(options));
+            applied.push("provider_options");
+        }
+        applied
+    }
+
+    /// Sentences appended to the provider prompt for `provider`.
+    pub(crate) fn prompt_clause(&self, provider: &str) -> Option<String> {
+        let mut sentences = Vec::new();
+        if !self.tone_words.is_empty() {
+            sentences.push(format!("Tone: {}.", self.tone_words.join(", ")));
+        }
+        if !self.negative_prompts.is_empty() && !NEGATIVE_PROMPT_PROVIDERS.contains(&provider) {
+            sentences.push(format!("Avoid: {}.", self.negative_prompts.join(", ")));
+        }
+        (!sentences.is_empty()).then(|| sentences.join(" "))
+    }
+}
+
+impl NativeEngine {
+    /// Applies `profile` to every generation until replaced or cleared.
+    pub fn set_style_profile(&mut self, profile: Option<StyleProfile>) {
+        self.style_profile = profile;
+    }
+
+    pub fn style_profile(&self) -> Option<&StyleProfile> {
+        self.style_profile.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Map};
+
+    use super::StyleProfile;
+
+    #[test]
+    fn loads_profiles_and_merges_them_under_request_settings() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        fs::write(
+            temp.path().join("acme.json"),
+            r##"{"palette": ["#1428A0"], "logo": "logo.png", "tone_words": ["confident"],
+                "negative_prompts": ["clutter", "text"], "provider_options": {"quality": "high", "style": "vivid"}}"##,
+        )?;
+        fs::write(temp.path().join("broken.json"), r#"{"palette": ["navy"]}"#)?;
+        assert_eq!(StyleProfile::available(temp.path()), vec!["acme", "broken"]);
+        assert_eq!(StyleProfile::load_from(temp.path(), "missing")?, None);
+        assert!(StyleProfile::load_from(temp.path(), "broken").is_err());
+        assert!(StyleProfile::load_from(temp.path(), "../acme").is_err());
+
+        let Some(profile) = StyleProfile::load_from(temp.path(), "acme")? else {
+            anyhow::bail!("acme profile not loaded");
+        };
+        assert_eq!(profile.palette, vec!["#1428a0"]);
+        assert_eq!(profile.logo, Some(temp.path().join("logo.png")));
+
+        let mut settings = Map::new();
+        settings.insert("provider_options".to_string(), json!({"style": "natural"}));
+        let applied = profile.apply("openai", &mut settings);
+        assert_eq!(applied, vec!["palette", "watermark", "provider_options"]);
+        assert_eq!(
+            settings["provider_options"],
+            json!({"quality": "high", "style": "natural"})
+        );
+        assert_eq!(
+            profile.prompt_clause("openai").as_deref(),
+            Some("Tone: confident. Avoid: clutter, text.")
+        );
+
+        let mut settings = Map::new();
+        profile.apply("stability", &mut settings);
+        assert_eq!(
+            settings["provider_options"]["negative_prompt"],
+            json!("clutter, text")
+        );
+        assert_eq!(
+            profile.prompt_clause("stability").as_deref(),
+            Some("Tone: confident.")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use std::io;
+
+use anyhow::Result;
+use tracing::span::EnteredSpan;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// OTLP/HTTP collector base URL; spans go to `<endpoint>/v1/traces`.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+/// Full traces URL; wins over [`OTLP_ENDPOINT_ENV`].
+pub const OTLP_TRACES_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+/// Extra export headers as `key=value,key2=value2` (collector auth).
+pub const OTLP_HEADERS_ENV: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+/// `service.name` resource attribute; defaults to `brood`.
+pub const OTEL_SERVICE_NAME_ENV: &str = "OTEL_SERVICE_NAME";
+/// `tracing` directives for the stderr log, e.g. `brood_engine=debug`;
+/// warnings and errors by default.
+pub const LOG_FILTER_ENV: &str = "RUST_LOG";
+
+/// Only Brood's own spans are exported, not those of its HTTP stack.
+#[cfg(feature = "otlp")]
+const TARGET_PREFIX: &str = "brood";
+#[cfg(feature = "otlp")]
+const EXPORT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Where and how to export spans.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OtlpConfig {
+    pub traces_endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub service_name: String,
+}
+
+impl OtlpConfig {
+    /// Reads the standard `OTEL_*` variables; `None` when no endpoint is set.
+    pub fn from_env() -> Option<Self> {
+        let traces_endpoint = super::non_empty_env(OTLP_TRACES_ENDPOINT_ENV).or_else(|| {
+            super::non_empty_env(OTLP_ENDPOINT_ENV)
+                .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+        })?;
+        let headers = super::non_empty_env(OTLP_HEADERS_ENV)
+            .map(|raw| parse_headers(&raw))
+            .unwrap_or_default();
+        let service_name =
+            super::non_empty_env(OTEL_SERVICE_NAME_ENV).unwrap_or_else(|| "brood".to_string());
+        Some(Self {
+            traces_endpoint,
+            headers,
+            service_name,
+        })
+    }
+}
+
+fn parse_headers(raw: &str) -> Vec<(String, String)> {
+    raw.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+/// The stderr log's filter: [`LOG_FILTER_ENV`], else warnings and errors.
+fn log_filter() -> Targets {
+    let default = Targets::new().with_default(LevelFilter::WARN);
+    let Some(raw) = super::non_empty_env(LOG_FILTER_ENV) else {
+        return default;
+    };
+    raw.parse().unwrap_or_else(|err| {
+        eprintln!("brood: warning: ignoring {LOG_FILTER_ENV}={raw:?}: {err}");
+        default
+    })
+}
+
+/// Installs the process-wide `tracing` subscriber: a `tracing-subscriber`
+/// fmt layer writing to stderr, plus, with the `otlp` feature, an
+/// OpenTelemetry layer exporting Brood's spans when [`OtlpConfig::from_env`]
+/// finds an endpoint. Keep the guard alive for the life of the process;
+/// dropping it flushes the spans still queued.
+pub fn install_tracing_from_env() -> Result<TelemetryGuard> {
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(io::stderr)
+        .with_filter(log_filter());
+    let registry = tracing_subscriber::registry().with(fmt);
+    #[cfg(feature = "otlp")]
+    {
+        let (otlp, guard) = match OtlpConfig::from_env() {
+            Some(config) => {
+                let (layer, guard) = otlp_layer(&config)?;
+                (Some(layer), guard)
+            }
+            None => (None, TelemetryGuard::default()),
+        };
+        registry.with(otlp).try_init()?;
+        Ok(guard)
+    }
+    #[cfg(not(feature = "otlp"))]
+    {
+        if OtlpConfig::from_env().is_some() {
+            eprintln!(
+                "brood: warning: {OTLP_ENDPOINT_ENV} is set, but this build has no `otlp` feature; traces are not exported"
+            );
+        }
+        registry.try_init()?;
+        Ok(TelemetryGuard::default())
+    }
+}
+
+/// A `tracing-opentelemetry` layer exporting Brood's spans to `config` as
+/// OTLP/HTTP JSON from a background batch processor, through the shared
+/// HTTP client settings. Span fields become attributes; `otel.kind` and
+/// `otel.status_*` fields set the span kind and status.
+#[cfg(feature = "otlp")]
+pub fn otlp_layer<S>(config: &OtlpConfig) -> Result<(impl Layer<S>, TelemetryGuard)>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use anyhow::Context;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::InstrumentationScope;
+    use opentelemetry_otlp::{Protocol, WithExportConfig, WithHttpConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+
+    let http = super::http_client::http_client_builder()?
+        .timeout(EXPORT_TIMEOUT)
+        .build()
+        .context("failed to build OTLP export client")?;
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_http_client(http)
+        .with_endpoint(config.traces_endpoint.clone())
+        .with_headers(config.headers.iter().cloned().collect())
+        .with_timeout(EXPORT_TIMEOUT)
+        .build()
+        .context("failed to build OTLP exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .with_batch_exporter(exporter)
+        .build();
+    let tracer = provider.tracer_with_scope(
+        InstrumentationScope::builder("brood-engine")
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build(),
+    );
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(tracing_subscriber::filter::filter_fn(|metadata| {
+            metadata.target().starts_with(TARGET_PREFIX)
+        }));
+    Ok((
+        layer,
+        TelemetryGuard {
+            provider: Some(provider),
+        },
+    ))
+}
+
+/// Flushes queued spans when dropped.
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl TelemetryGuard {
+    /// Exports every span finished so far and waits for the export. Export
+    /// is best effort: a down collector must not fail generations, so
+    /// errors are reported on stderr.
+    pub fn flush(&self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = &self.provider {
+            if let Err(err) = provider.force_flush() {
+                eprintln!("brood: OTLP trace export failed: {err}");
+            }
+        }
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("brood: OTLP trace export failed: {err}");
+            }
+        }
+    }
+}
+
+/// Records `error` on `span`, which must declare `error` and
+/// `otel.status_description`, and marks it failed for export.
+pub(crate) fn record_span_error(span: &tracing::Span, error: &str) {
+    span.record("error", error);
+    span.record("otel.status_description", error);
+}
+
+/// An entered `provider_poll` span. Its poll count and last status are
+/// recorded once, when it closes: OpenTelemetry appends a field recorded
+/// again instead of replacing it.
+pub(crate) struct PollSpan {
+    span: EnteredSpan,
+    polls: i64,
+    status: Option<String>,
+}
+
+impl PollSpan {
+    pub(crate) fn enter(provider: &'static str) -> Self {
+        let span = tracing::info_span!(
+            "provider_poll",
+            provider,
+            polls = tracing::field::Empty,
+            status = tracing::field::Empty,
+        );
+        Self {
+            span: span.entered(),
+            polls: 0,
+            status: None,
+        }
+    }
+
+    /// Counts one poll and returns the count so far.
+    pub(crate) fn poll(&mut self) -> i64 {
+        self.polls += 1;
+        self.polls
+    }
+
+    pub(crate) fn status(&mut self, status: &str) {
+        self.status = Some(status.to_string());
+    }
+}
+
+impl Drop for PollSpan {
+    fn drop(&mut self) {
+        if self.polls > 0 {
+            self.span.record("polls", self.polls);
+        }
+        if let Some(status) = self.status.as_deref() {
+            self.span.record("status", status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_headers;
+
+    #[test]
+    fn parses_otlp_header_lists() {
+        assert_eq!(
+            parse_headers("authorization=Bearer abc, x-team = brood,,bad"),
+            [
+                ("authorization".to_string(), "Bearer abc".to_string()),
+                ("x-team".to_string(), "brood".to_string()),
+            ]
+        );
+    }
+}
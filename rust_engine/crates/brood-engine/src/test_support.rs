@@ -0,0 +1,534 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use super::ProviderConfig;
+
+/// Environment variable [`MockServer::provider_config`] points providers at.
+pub const MOCK_API_KEY_ENV: &str = "BROOD_MOCK_API_KEY";
+/// The key providers send to a [`MockServer`].
+pub const MOCK_API_KEY: &str = "mock-key";
+
+/// One canned HTTP response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: Value) -> Self {
+        Self::bytes(status, "application/json", body.to_string().into_bytes())
+    }
+
+    pub fn bytes(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// `429 Too Many Requests` with `Retry-After` and an OpenAI-style error
+    /// body.
+    pub fn rate_limited(retry_after_s: u64) -> Self {
+        Self::json(
+            429,
+            json!({
+                "error": {
+                    "message": "Rate limit reached for requests",
+                    "type": "rate_limit_error",
+                    "code": "rate_limit_exceeded",
+                }
+            }),
+        )
+        .with_header("Retry-After", &retry_after_s.to_string())
+    }
+
+    /// A `200` that claims JSON but is cut off mid-body.
+    pub fn malformed() -> Self {
+        Self::bytes(
+            200,
+            "application/json",
+            b"{\"data\": [{\"b64_json\": ".to_vec(),
+        )
+    }
+
+    pub fn server_error() -> Self {
+        Self::json(500, json!({"error": {"message": "internal server error"}}))
+    }
+}
+
+/// A request the server received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path plus query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_slice(&self.body).ok()
+    }
+
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    /// Responses per `METHOD path`, served in order; the last one repeats.
+    routes: BTreeMap<String, Vec<MockResponse>>,
+    served: BTreeMap<String, usize>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A local HTTP/1.1 server answering from canned responses, one connection
+/// at a time. Shuts down when dropped.
+pub struct MockServer {
+    base_url: String,
+    state: Arc<Mutex<MockState>>,
+    stopping: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let state = Arc::clone(&state);
+            let stopping = Arc::clone(&stopping);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stopping.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = serve_connection(stream, &state);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            base_url,
+            state,
+            stopping,
+            worker: Some(worker),
+        })
+    }
+
+    /// `http://127.0.0.1:<port>`, without a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn url_for(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    /// Queues `response` for `method` requests to `path` (query ignored).
+    /// Repeated calls queue further responses, e.g. successive polls.
+    pub fn mock(&self, method: &str, path: &str, response: MockResponse) -> &Self {
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .routes
+                .entry(route_key(method, path))
+                .or_default()
+                .push(response);
+        }
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state
+            .lock()
+            .map(|state| state.requests.clone())
+            .unwrap_or_default()
+    }
+
+    /// Provider config pointing each named built-in provider at this server
+    /// and at [`MOCK_API_KEY_ENV`], which is set to [`MOCK_API_KEY`].
+    pub fn provider_config(&self, providers: &[&str]) -> Result<ProviderConfig> {
+        env::set_var(MOCK_API_KEY_ENV, MOCK_API_KEY);
+        let entries: Map<String, Value> = providers
+            .iter()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    json!({"base_url": self.base_url, "api_key_env": MOCK_API_KEY_ENV}),
+                )
+            })
+            .collect();
+        ProviderConfig::parse(&json!({ "providers": entries }).to_string())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wake the blocking accept so the worker sees the flag.
+        let _ = TcpStream::connect(self.base_url.trim_start_matches("http://"));
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn route_key(method: &str, path: &str) -> String {
+    let path = path.split('?').next().unwrap_or_default();
+    format!(
+        "{} /{}",
+        method.to_ascii_uppercase(),
+        path.trim_start_matches('/')
+    )
+}
+
+fn serve_connection(stream: TcpStream, state: &Mutex<MockState>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(());
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+    let body = if header("transfer-encoding").is_some_and(|value| value.contains("chunked")) {
+        read_chunked(&mut reader)?
+    } else {
+        let length = header("content-length")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        body
+    };
+
+    let key = route_key(&method, &path);
+    let response = {
+        let mut state = state
+            .lock()
+            .map_err(|_| io::Error::other("mock server state poisoned"))?;
+        state.requests.push(RecordedRequest {
+            method: method.clone(),
+            path: path.clone(),
+            headers,
+            body,
+        });
+        let index = *state.served.get(&key).unwrap_or(&0);
+        let response = state
+            .routes
+            .get(&key)
+            .and_then(|queue| queue.get(index).or_else(|| queue.last()).cloned());
+        *state.served.entry(key.clone()).or_insert(0) += 1;
+        response
+    };
+    let response = response
+        .unwrap_or_else(|| MockResponse::json(404, json!({"error": format!("no mock for {key}")})));
+
+    let stream = reader.get_mut();
+    write!(stream, "HTTP/1.1 {} Mock\r\n", response.status)?;
+    for (name, value) in &response.headers {
+        write!(stream, "{name}: {value}\r\n")?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    let _ = stream.shutdown(Shutdown::Write);
+    Ok(())
+}
+
+fn read_chunked(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line)?;
+        let size = usize::from_str_radix(size_line.trim().split(';').next().unwrap_or("0"), 16)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if size == 0 {
+            let mut trailer = String::new();
+            while reader.read_line(&mut trailer)? > 0 && trailer != "\r\n" {
+                trailer.clear();
+            }
+            return Ok(body);
+        }
+        let mut chunk = vec![0; size + 2];
+        reader.read_exact(&mut chunk)?;
+        chunk.truncate(size);
+        body.extend(chunk);
+    }
+}
+
+/// Response bodies in each provider's wire format, trimmed to the fields
+/// the engine reads plus the ones providers always send.
+pub mod canned {
+    use std::io::Cursor;
+
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine as _;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use serde_json::{json, Value};
+
+    use super::MockResponse;
+
+    /// A solid-colour PNG of the given size.
+    pub fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let _ = RgbImage::from_pixel(width.max(1), height.max(1), Rgb([40, 120, 200]))
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png);
+        bytes
+    }
+
+    pub fn image_png(bytes: Vec<u8>) -> MockResponse {
+        MockResponse::bytes(200, "image/png", bytes)
+    }
+
+    /// `POST /images/generations` with `b64_json` rows.
+    pub fn openai_images(images: &[Vec<u8>]) -> MockResponse {
+        let data: Vec<Value> = images
+            .iter()
+            .map(|bytes| json!({"b64_json": BASE64.encode(bytes)}))
+            .collect();
+        MockResponse::json(
+            200,
+            json!({
+                "created": 1_700_000_000,
+                "data": data,
+                "usage": {"input_tokens": 12, "output_tokens": 4160, "total_tokens": 4172},
+            }),
+        )
+    }
+
+    /// OpenAI's safety-system rejection.
+    pub fn openai_moderated() -> MockResponse {
+        MockResponse::json(
+            400,
+            json!({
+                "error": {
+                    "message": "Your request was rejected as a result of our safety system.",
+                    "type": "image_generation_user_error",
+                    "param": null,
+                    "code": "moderation_blocked",
+                }
+            }),
+        )
+    }
+
+    /// A prediction in any state; `poll_url` is its `urls.get`.
+    pub fn replicate_prediction(
+        id: &str,
+        status: &str,
+        poll_url: &str,
+        output: Value,
+    ) -> MockResponse {
+        MockResponse::json(
+            if status == "starting" { 201 } else { 200 },
+            json!({
+                "id": id,
+                "status": status,
+                "output": output,
+                "error": null,
+                "logs": "",
+                "urls": {"get": poll_url, "cancel": format!("{poll_url}/cancel")},
+            }),
+        )
+    }
+
+    /// A prediction the safety checker failed.
+    pub fn replicate_moderated(id: &str) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "id": id,
+                "status": "failed",
+                "output": null,
+                "error": "NSFW content detected. Try running it again, or try a different prompt.",
+            }),
+        )
+    }
+
+    /// Stability's `Accept: image/*` success.
+    pub fn stability_image(bytes: Vec<u8>, seed: i64) -> MockResponse {
+        image_png(bytes)
+            .with_header("finish-reason", "SUCCESS")
+            .with_header("seed", &seed.to_string())
+    }
+
+    pub fn stability_moderated() -> MockResponse {
+        MockResponse::json(
+            403,
+            json!({
+                "id": "a1b2c3",
+                "name": "content_moderation",
+                "errors": [
+                    "Your request was flagged by our content moderation system, as a result your request was denied and you were not charged."
+                ],
+            }),
+        )
+    }
+
+    /// A synchronous `fal.run` result.
+    pub fn fal_images(urls: &[String], seed: i64) -> MockResponse {
+        let images: Vec<Value> = urls
+            .iter()
+            .map(|url| json!({"url": url, "width": 1024, "height": 1024, "content_type": "image/png"}))
+            .collect();
+        let flags: Vec<bool> = urls.iter().map(|_| false).collect();
+        MockResponse::json(
+            200,
+            json!({
+                "images": images,
+                "seed": seed,
+                "has_nsfw_concepts": flags,
+                "prompt": "",
+                "timings": {"inference": 1.2},
+            }),
+        )
+    }
+
+    /// Fal's content-policy rejection.
+    pub fn fal_moderated() -> MockResponse {
+        MockResponse::json(
+            422,
+            json!({
+                "detail": [{
+                    "loc": ["body", "prompt"],
+                    "msg": "The content could not be processed because it contained material flagged by a content checker.",
+                    "type": "content_policy_violation",
+                }]
+            }),
+        )
+    }
+
+    /// `queue.fal.run` submit: the request id and where to poll it.
+    pub fn fal_queued(request_id: &str, request_url: &str) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "request_id": request_id,
+                "status_url": format!("{request_url}/status"),
+                "response_url": request_url,
+                "cancel_url": format!("{request_url}/cancel"),
+            }),
+        )
+    }
+
+    /// `IN_QUEUE`, `IN_PROGRESS` or `COMPLETED`, with one log line.
+    pub fn fal_queue_status(status: &str, log: &str) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "status": status,
+                "queue_position": if status == "IN_QUEUE" { json!(0) } else { Value::Null },
+                "logs": [{"message": log, "level": "INFO"}],
+            }),
+        )
+    }
+
+    /// `generateContent` with one inline image.
+    pub fn gemini_image(bytes: &[u8]) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {
+                        "role": "model",
+                        "parts": [{"inlineData": {"mimeType": "image/png", "data": BASE64.encode(bytes)}}],
+                    },
+                    "finishReason": "STOP",
+                    "index": 0,
+                }],
+                "usageMetadata": {"promptTokenCount": 9, "candidatesTokenCount": 1290, "totalTokenCount": 1299},
+            }),
+        )
+    }
+
+    /// A prompt blocked before generation: no candidates at all.
+    pub fn gemini_blocked() -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "promptFeedback": {"blockReason": "SAFETY"},
+                "usageMetadata": {"promptTokenCount": 9, "totalTokenCount": 9},
+            }),
+        )
+    }
+
+    pub fn flux_submitted(id: &str, polling_url: &str) -> MockResponse {
+        MockResponse::json(200, json!({"id": id, "polling_url": polling_url}))
+    }
+
+    /// `progress` is BFL's 0–1 fraction.
+    pub fn flux_pending(progress: f64) -> MockResponse {
+        MockResponse::json(200, json!({"status": "Pending", "progress": progress}))
+    }
+
+    pub fn flux_ready(sample_url: &str) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "status": "Ready",
+                "result": {"sample": sample_url, "prompt": "", "seed": 7},
+                "progress": null,
+            }),
+        )
+    }
+
+    pub fn flux_moderated() -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "status": "Content Moderated",
+                "result": null,
+                "details": {"Moderation Reasons": ["Derivative Works Filter"]},
+            }),
+        )
+    }
+}
@@ -0,0 +1,343 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use brood_contracts::models::ModelSpec;
+use serde_json::{json, Value};
+
+use super::openrouter::OpenRouterProvider;
+use super::{
+    image_part_from_path, mime_for_path, normalize_openrouter_model_for_image_transport,
+    response_json_or_error, ProviderConfig, ProviderSettings, TokenEstimator, BASE64,
+};
+
+const TEXT_MODEL_TIMEOUT_S: f64 = 60.0;
+
+/// One-shot chat calls to the engine's text and vision models.
+///
+/// OpenAI and Gemini models are called directly when their key is set;
+/// otherwise, and for every other provider, the call goes through
+/// OpenRouter's chat completions API. Dryrun models are handled by callers.
+#[derive(Debug, Clone)]
+pub(crate) struct TextModelClient {
+    openai: ProviderSettings,
+    gemini: ProviderSettings,
+    openrouter: ProviderSettings,
+}
+
+/// Reply text and the transport that produced it.
+pub(crate) struct TextModelReply {
+    pub(crate) text: String,
+    /// `openai`, `gemini` or `openrouter`.
+    pub(crate) transport: &'static str,
+    pub(crate) usage: TokenUsage,
+}
+
+/// Tokens billed for one text model call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Counted with `model`'s [`TokenEstimator`], for dryrun models and
+    /// providers that report no usage. Attached images are not counted.
+    pub(crate) fn estimate(
+        model: &ModelSpec,
+        instructions: &str,
+        prompt: &str,
+        reply: &str,
+    ) -> Self {
+        let estimator = TokenEstimator::for_model(Some(model));
+        Self {
+            input_tokens: estimator.count(instructions) + estimator.count(prompt),
+            output_tokens: estimator.count(reply),
+        }
+    }
+}
+
+impl TextModelClient {
+    pub(crate) fn new(config: &ProviderConfig) -> Self {
+        Self {
+            openai: config.settings("openai"),
+            gemini: config.settings("gemini"),
+            openrouter: config.settings("openrouter"),
+        }
+    }
+
+    /// Sends `instructions` as the system message and `prompt` (plus the
+    /// image, when given) as the user message.
+    pub(crate) fn complete(
+        &self,
+        model: &ModelSpec,
+        instructions: &str,
+        prompt: &str,
+        image: Option<&Path>,
+    ) -> Result<TextModelReply> {
+        if image.is_some() && !model.supports("vision") {
+            bail!("model '{}' does not accept images", model.name);
+        }
+        let (transport, (text, usage)) = match model.provider.as_str() {
+            "openai" if self.openai.api_key().is_some() => (
+                "openai",
+                self.complete_openai(&model.name, instructions, prompt, image)?,
+            ),
+            "gemini" if self.gemini.api_key().is_some() => (
+                "gemini",
+                self.complete_gemini(&model.name, instructions, prompt, image)?,
+            ),
+            _ => (
+                "openrouter",
+                self.complete_openrouter(&model.name, instructions, prompt, image)?,
+            ),
+        };
+        let usage =
+            usage.unwrap_or_else(|| TokenUsage::estimate(model, instructions, prompt, &text));
+        Ok(TextModelReply {
+            text,
+            transport,
+            usage,
+        })
+    }
+
+    fn complete_openai(
+        &self,
+        model: &str,
+        instructions: &str,
+        prompt: &str,
+        image: Option<&Path>,
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let api_key = self.openai.api_key().unwrap_or_default();
+        let endpoint = format!("{}/chat/completions", self.openai.base_url);
+        let response = self
+            .openai
+            .http_client()?
+            .post(&endpoint)
+            .bearer_auth(api_key)
+            .timeout(Duration::from_secs_f64(TEXT_MODEL_TIMEOUT_S))
+            .json(&chat_completion_payload(
+                model,
+                instructions,
+                prompt,
+                image,
+            )?)
+            .send()
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("OpenAI request failed ({endpoint})"))?;
+        let payload = response_json_or_error("OpenAI", response)?;
+        let text = chat_completion_text(&payload).context("OpenAI chat response had no text")?;
+        Ok((text, chat_completion_usage(&payload)))
+    }
+
+    fn complete_gemini(
+        &self,
+        model: &str,
+        instructions: &str,
+        prompt: &str,
+        image: Option<&Path>,
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let api_key = self.gemini.api_key().unwrap_or_default();
+        let model_path = if model.starts_with("models/") {
+            model.to_string()
+        } else {
+            format!("models/{model}")
+        };
+        let endpoint = format!("{}/{model_path}:generateContent", self.gemini.base_url);
+        let mut parts = Vec::new();
+        if let Some(image) = image {
+            parts.push(image_part_from_path(image)?);
+        }
+        parts.push(json!({ "text": prompt }));
+        let payload = json!({
+            "systemInstruction": { "parts": [{ "text": instructions }] },
+            "contents": [{ "role": "user", "parts": parts }],
+        });
+        let response = self
+            .gemini
+            .http_client()?
+            .post(&endpoint)
+            .header("x-goog-api-key", api_key.as_str())
+            .timeout(Duration::from_secs_f64(TEXT_MODEL_TIMEOUT_S))
+            .json(&payload)
+            .send()
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("Gemini request failed ({endpoint})"))?;
+        let payload = response_json_or_error("Gemini", response)?;
+        let text = gemini_text(&payload).context("Gemini response had no text")?;
+        Ok((text, gemini_usage(&payload)))
+    }
+
+    fn complete_openrouter(
+        &self,
+        model: &str,
+        instructions: &str,
+        prompt: &str,
+        image: Option<&Path>,
+    ) -> Result<(String, Option<TokenUsage>)> {
+        let openrouter = OpenRouterProvider::new(&self.openrouter)?;
+        let Some(api_key) = openrouter.api_key() else {
+            bail!("no API key for model '{model}' (set its provider key or OPENROUTER_API_KEY)");
+        };
+        let endpoint = format!("{}/chat/completions", openrouter.api_base());
+        let model = normalize_openrouter_model_for_image_transport(model, model);
+        let request = self
+            .openrouter
+            .http_client()?
+            .post(&endpoint)
+            .bearer_auth(api_key)
+            .timeout(Duration::from_secs_f64(TEXT_MODEL_TIMEOUT_S))
+            .json(&chat_completion_payload(
+                &model,
+                instructions,
+                prompt,
+                image,
+            )?);
+        let response = OpenRouterProvider::apply_request_headers(request)
+            .send()
+            .map_err(reqwest::Error::without_url)
+            .with_context(|| format!("OpenRouter request failed ({endpoint})"))?;
+        let payload = response_json_or_error("OpenRouter", response)?;
+        let text =
+            chat_completion_text(&payload).context("OpenRouter chat response had no text")?;
+        Ok((text, chat_completion_usage(&payload)))
+    }
+}
+
+fn chat_completion_payload(
+    model: &str,
+    instructions: &str,
+    prompt: &str,
+    image: Option<&Path>,
+) -> Result<Value> {
+    let content = match image {
+        Some(path) => {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("failed reading {}", path.display()))?;
+            let mime = mime_for_path(path).unwrap_or("image/png");
+            json!([
+                { "type": "text", "text": prompt },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": format!("data:{mime};base64,{}", BASE64.encode(bytes)) },
+                },
+            ])
+        }
+        None => Value::String(prompt.to_string()),
+    };
+    Ok(json!({
+        "model": model,
+        "messages": [
+            { "role": "system", "content": instructions },
+            { "role": "user", "content": content },
+        ],
+    }))
+}
+
+fn chat_completion_text(payload: &Value) -> Option<String> {
+    let content = payload.pointer("/choices/0/message/content")?;
+    match content {
+        Value::String(text) => Some(text.clone()),
+        // Some OpenRouter models answer with content parts.
+        Value::Array(parts) => Some(
+            parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join(""),
+        ),
+        _ => None,
+    }
+}
+
+/// `usage.prompt_tokens` / `usage.completion_tokens`, as OpenAI and
+/// OpenRouter report them.
+fn chat_completion_usage(payload: &Value) -> Option<TokenUsage> {
+    let usage = payload.get("usage")?;
+    Some(TokenUsage {
+        input_tokens: usage.get("prompt_tokens")?.as_u64()?,
+        output_tokens: usage
+            .get("completion_tokens")
+            .and_then(Value::as_u64)
+            .unwrap_or(0),
+    })
+}
+
+fn gemini_usage(payload: &Value) -> Option<TokenUsage> {
+    let usage = payload.get("usageMetadata")?;
+    Some(TokenUsage {
+        input_tokens: usage.get("promptTokenCount")?.as_u64()?,
+        output_tokens: usage
+            .get("candidatesTokenCount")
+            .and_then(Value::as_u64)
+            .unwrap_or(0),
+    })
+}
+
+fn gemini_text(payload: &Value) -> Option<String> {
+    let parts = payload.pointer("/candidates/0/content/parts")?.as_array()?;
+    Some(
+        parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join(""),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use brood_contracts::models::ModelRegistry;
+    use serde_json::json;
+
+    use super::{
+        chat_completion_text, chat_completion_usage, gemini_text, gemini_usage, TokenUsage,
+    };
+
+    #[test]
+    fn extracts_text_from_chat_and_gemini_payloads() {
+        let chat = json!({"choices": [{"message": {"content": "a fox at dusk"}}]});
+        assert_eq!(
+            chat_completion_text(&chat).as_deref(),
+            Some("a fox at dusk")
+        );
+        let parts = json!({"choices": [{"message": {"content": [
+            {"type": "text", "text": "a fox "},
+            {"type": "text", "text": "at dusk"}
+        ]}}]});
+        assert_eq!(
+            chat_completion_text(&parts).as_deref(),
+            Some("a fox at dusk")
+        );
+        assert_eq!(chat_completion_text(&json!({"choices": []})), None);
+
+        let gemini = json!({"candidates": [{"content": {"parts": [{"text": "a fox at dusk"}]}}]});
+        assert_eq!(gemini_text(&gemini).as_deref(), Some("a fox at dusk"));
+
+        let usage = |input_tokens, output_tokens| TokenUsage {
+            input_tokens,
+            output_tokens,
+        };
+        assert_eq!(
+            chat_completion_usage(
+                &json!({"usage": {"prompt_tokens": 120, "completion_tokens": 40}})
+            ),
+            Some(usage(120, 40))
+        );
+        assert_eq!(chat_completion_usage(&chat), None);
+        assert_eq!(
+            gemini_usage(
+                &json!({"usageMetadata": {"promptTokenCount": 90, "candidatesTokenCount": 12}})
+            ),
+            Some(usage(90, 12))
+        );
+        let registry = ModelRegistry::new(None);
+        if let Some(dryrun) = registry.get("dryrun-text-1") {
+            assert_eq!(
+                TokenUsage::estimate(dryrun, "abcd", "abcdefgh", "abcde"),
+                usage(3, 2)
+            );
+        }
+    }
+}
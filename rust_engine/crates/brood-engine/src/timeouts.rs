@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use reqwest::blocking::{Client as HttpClient, RequestBuilder};
+use serde_json::{json, Map, Value};
+
+use super::http_client::http_client_builder;
+
+/// Keys of the `settings.timeouts` block.
+const TIMEOUT_KEYS: [&str; 4] = ["connect", "request", "poll", "download"];
+
+/// Time limits for provider calls, in seconds; `None` keeps the provider's
+/// own default.
+///
+/// - `connect`: establishing the TCP/TLS connection;
+/// - `request`: one API call, start to finish;
+/// - `poll`: waiting on an async job (Replicate, FLUX, Runway) overall;
+/// - `download`: fetching a finished image or video.
+///
+/// Engine defaults come from [`NativeEngine::set_timeouts`]; a request's
+/// `settings.timeouts` overrides them field by field, and the older
+/// provider-specific `provider_options` (`poll_timeout`, `request_timeout`,
+/// `download_timeout`) still win over both.
+///
+/// [`NativeEngine::set_timeouts`]: crate::NativeEngine::set_timeouts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timeouts {
+    pub connect_s: Option<f64>,
+    pub request_s: Option<f64>,
+    pub poll_s: Option<f64>,
+    pub download_s: Option<f64>,
+}
+
+impl Timeouts {
+    /// Parses `settings.timeouts` (`{"connect": 5, "request": 60}`); absent
+    /// means no overrides.
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Self> {
+        let Some(raw) = settings.get("timeouts").filter(|value| !value.is_null()) else {
+            return Ok(Self::default());
+        };
+        let Some(block) = raw.as_object() else {
+            bail!("settings.timeouts must be an object of seconds");
+        };
+        let mut timeouts = Self::default();
+        for (key, value) in block {
+            let Some(seconds) = value.as_f64().filter(|value| *value > 0.0) else {
+                bail!("settings.timeouts.{key} must be a positive number of seconds");
+            };
+            match key.as_str() {
+                "connect" => timeouts.connect_s = Some(seconds),
+                "request" => timeouts.request_s = Some(seconds),
+                "poll" => timeouts.poll_s = Some(seconds),
+                "download" => timeouts.download_s = Some(seconds),
+                other => bail!(
+                    "unknown settings.timeouts key '{other}' (known: {})",
+                    TIMEOUT_KEYS.join(", ")
+                ),
+            }
+        }
+        Ok(timeouts)
+    }
+
+    /// Field-wise `self`, falling back to `defaults`.
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            connect_s: self.connect_s.or(defaults.connect_s),
+            request_s: self.request_s.or(defaults.request_s),
+            poll_s: self.poll_s.or(defaults.poll_s),
+            download_s: self.download_s.or(defaults.download_s),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn get(&self, kind: TimeoutKind) -> Option<f64> {
+        match kind {
+            TimeoutKind::Request => self.request_s,
+            TimeoutKind::Poll => self.poll_s,
+            TimeoutKind::Download => self.download_s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimeoutKind {
+    Request,
+    Poll,
+    Download,
+}
+
+struct ScopeState {
+    timeouts: Timeouts,
+    connect_client: Option<HttpClient>,
+}
+
+thread_local! {
+    static SCOPE: RefCell<Option<ScopeState>> = const { RefCell::new(None) };
+}
+
+/// Applies `timeouts` to provider calls made on this thread until dropped,
+/// the way `http_trace` captures responses: providers are shared and
+/// immutable, so the limits travel with the call rather than the provider.
+pub(crate) struct TimeoutScope;
+
+impl TimeoutScope {
+    pub(crate) fn begin(timeouts: Timeouts) -> Self {
+        SCOPE.with(|cell| {
+            *cell.borrow_mut() = Some(ScopeState {
+                timeouts,
+                connect_client: None,
+            })
+        });
+        Self
+    }
+}
+
+impl Drop for TimeoutScope {
+    fn drop(&mut self) {
+        SCOPE.with(|cell| *cell.borrow_mut() = None);
+    }
+}
+
+fn scoped(kind: TimeoutKind) -> Option<f64> {
+    SCOPE.with(|cell| {
+        cell.borrow()
+            .as_ref()
+            .and_then(|state| state.timeouts.get(kind))
+    })
+}
+
+/// The provider's client, or, when a `connect` limit is in scope, a client
+/// built with it. reqwest only takes connect timeouts per client, so that
+/// client is built once per scope and shared by its calls.
+pub(crate) fn scoped_http(base: &HttpClient) -> HttpClient {
+    SCOPE.with(|cell| {
+        let mut scope = cell.borrow_mut();
+        let Some(state) = scope.as_mut() else {
+            return base.clone();
+        };
+        let Some(connect_s) = state.timeouts.connect_s else {
+            return base.clone();
+        };
+        state
+            .connect_client
+            .get_or_insert_with(|| {
+                let Ok(mut builder) = http_client_builder() else {
+                    return base.clone();
+                };
+                builder = builder.connect_timeout(Duration::from_secs_f64(connect_s));
+                if let Some(request_s) = state.timeouts.request_s {
+                    builder = builder.timeout(Duration::from_secs_f64(request_s));
+                }
+                builder.build().unwrap_or_else(|_| base.clone())
+            })
+            .clone()
+    })
+}
+
+/// Sets the scoped request or download limit on a call that has no
+/// provider-specific one.
+pub(crate) trait ScopedTimeout {
+    fn scoped_timeout(self, kind: TimeoutKind) -> Self;
+}
+
+impl ScopedTimeout for RequestBuilder {
+    fn scoped_timeout(self, kind: TimeoutKind) -> Self {
+        match scoped(kind) {
+            Some(seconds) => self.timeout(Duration::from_secs_f64(seconds)),
+            None => self,
+        }
+    }
+}
+
+/// `provider_options[key]` when set, else the scoped limit of `kind`, for
+/// providers that already parse their own timeout options.
+pub(crate) fn timeout_option(
+    provider_options: &Map<String, Value>,
+    key: &str,
+    kind: TimeoutKind,
+) -> Option<Value> {
+    provider_options
+        .get(key)
+        .cloned()
+        .or_else(|| scoped(kind).map(|seconds| json!(seconds)))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    use super::{timeout_option, TimeoutKind, TimeoutScope, Timeouts};
+
+    fn settings(value: Value) -> Map<String, Value> {
+        let mut settings = Map::new();
+        settings.insert("timeouts".to_string(), value);
+        settings
+    }
+
+    #[test]
+    fn parses_merges_and_scopes_timeouts() -> anyhow::Result<()> {
+        let request = Timeouts::from_settings(&settings(json!({"poll": 300, "request": 45.5})))?;
+        let defaults = Timeouts {
+            connect_s: Some(5.0),
+            request_s: Some(30.0),
+            ..Timeouts::default()
+        };
+        let merged = request.or(defaults);
+        assert_eq!(
+            merged,
+            Timeouts {
+                connect_s: Some(5.0),
+                request_s: Some(45.5),
+                poll_s: Some(300.0),
+                download_s: None,
+            }
+        );
+        assert!(Timeouts::from_settings(&Map::new())?.is_empty());
+        assert!(Timeouts::from_settings(&settings(json!({"poll": 0}))).is_err());
+        assert!(Timeouts::from_settings(&settings(json!({"read": 5}))).is_err());
+
+        let mut options = Map::new();
+        options.insert("poll_timeout".to_string(), json!(90));
+        assert_eq!(
+            timeout_option(&options, "poll_timeout", TimeoutKind::Poll),
+            Some(json!(90))
+        );
+        assert_eq!(
+            timeout_option(&options, "download_timeout", TimeoutKind::Download),
+            None
+        );
+        {
+            let _scope = TimeoutScope::begin(merged);
+            assert_eq!(
+                timeout_option(&options, "poll_timeout", TimeoutKind::Poll),
+                Some(json!(90))
+            );
+            assert_eq!(
+                timeout_option(&options, "request_timeout", TimeoutKind::Request),
+                Some(json!(45.5))
+            );
+        }
+        assert_eq!(
+            timeout_option(&options, "request_timeout", TimeoutKind::Request),
+            None
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use brood_contracts::models::ModelSpec;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+use tiktoken_rs::{cl100k_base_singleton, o200k_base_singleton};
+
+/// How text is turned into a token count for a given text model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenEstimator {
+    /// OpenAI's `o200k_base` encoding (gpt-4o, o-series, gpt-5).
+    O200kBase,
+    /// OpenAI's `cl100k_base` encoding (gpt-4, gpt-3.5).
+    Cl100kBase,
+    /// Gemini's SentencePiece vocabulary, approximated per script.
+    Gemini,
+    /// About four characters per token; used when the model is unknown.
+    CharsPerToken,
+}
+
+impl TokenEstimator {
+    /// The estimator for `model`; unregistered and other-provider models get
+    /// [`TokenEstimator::CharsPerToken`].
+    pub fn for_model(model: Option<&ModelSpec>) -> Self {
+        let Some(model) = model else {
+            return Self::CharsPerToken;
+        };
+        match model.provider.as_str() {
+            "openai" => match get_tokenizer(&model.name) {
+                Some(Tokenizer::Cl100kBase) => Self::Cl100kBase,
+                // Newer models tiktoken does not list yet use o200k_base.
+                _ => Self::O200kBase,
+            },
+            "gemini" => Self::Gemini,
+            _ => Self::CharsPerToken,
+        }
+    }
+
+    /// Recorded as `estimator` on `context_window_update`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::O200kBase => "tiktoken:o200k_base",
+            Self::Cl100kBase => "tiktoken:cl100k_base",
+            Self::Gemini => "gemini",
+            Self::CharsPerToken => "chars_per_token",
+        }
+    }
+
+    pub fn count(self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        match self {
+            Self::O200kBase => o200k_base_singleton()
+                .encode_with_special_tokens(text)
+                .len() as u64,
+            Self::Cl100kBase => cl100k_base_singleton()
+                .encode_with_special_tokens(text)
+                .len() as u64,
+            Self::Gemini => gemini_token_estimate(text),
+            Self::CharsPerToken => ((text.chars().count() as f64) / 4.0).ceil() as u64,
+        }
+    }
+}
+
+/// Gemini does not publish its tokenizer. Its vocabulary spends about a
+/// token per four ASCII characters, one per CJK character and roughly one
+/// per two other non-ASCII characters, which tracks `countTokens` far better
+/// than a flat ratio for CJK and mixed text.
+fn gemini_token_estimate(text: &str) -> u64 {
+    let weight: f64 = text
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii() {
+                0.25
+            } else if is_cjk(ch) {
+                1.0
+            } else {
+                0.5
+            }
+        })
+        .sum();
+    weight.ceil() as u64
+}
+
+/// Han, kana, Hangul and CJK punctuation.
+fn is_cjk(ch: char) -> bool {
+    matches!(
+        ch as u32,
+        0x3000..=0x30FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xAC00..=0xD7AF
+            | 0xF900..=0xFAFF
+            | 0xFF00..=0xFFEF
+            | 0x20000..=0x2FA1F
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use brood_contracts::models::ModelRegistry;
+
+    use super::TokenEstimator;
+
+    #[test]
+    fn selects_estimator_by_model_and_counts_cjk_closer_than_chars() {
+        let registry = ModelRegistry::new(None);
+        let estimator = |name: &str| TokenEstimator::for_model(registry.get(name));
+        assert_eq!(estimator("gpt-4o-mini"), TokenEstimator::O200kBase);
+        assert_eq!(estimator("gpt-5.2"), TokenEstimator::O200kBase);
+        assert_eq!(estimator("gemini-3-pro-preview"), TokenEstimator::Gemini);
+        assert_eq!(estimator("dryrun-text-1"), TokenEstimator::CharsPerToken);
+        assert_eq!(
+            TokenEstimator::for_model(None),
+            TokenEstimator::CharsPerToken
+        );
+
+        assert_eq!(TokenEstimator::O200kBase.count("hello world"), 2);
+        assert_eq!(TokenEstimator::Cl100kBase.count("hello world"), 2);
+        assert_eq!(TokenEstimator::O200kBase.count(""), 0);
+        // Twelve Han characters, which chars/4 counts as three tokens.
+        let cjk = "一只红色的狐狸在黄昏时分";
+        assert_eq!(TokenEstimator::CharsPerToken.count(cjk), 3);
+        assert_eq!(TokenEstimator::Gemini.count(cjk), 12);
+        assert!(TokenEstimator::O200kBase.count(cjk) >= 6);
+        assert_eq!(TokenEstimator::Gemini.count("a red fox"), 3);
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use serde_json::{json, Map, Value};
+
+use super::{
+    map_object, normalize_output_extension, timestamp_millis, ProviderGenerateResponse,
+    ProviderImageResult,
+};
+
+pub const UPSCALE_FACTOR_MIN: u32 = 2;
+pub const UPSCALE_FACTOR_MAX: u32 = 8;
+pub const LOCAL_UPSCALE_BACKEND: &str = "local-lanczos";
+
+#[derive(Debug, Clone)]
+pub struct UpscaleRequest {
+    pub run_dir: PathBuf,
+    pub image_path: PathBuf,
+    pub factor: u32,
+    pub output_format: String,
+    pub provider_options: Map<String, Value>,
+}
+
+impl UpscaleRequest {
+    pub fn new(run_dir: impl Into<PathBuf>, image_path: impl Into<PathBuf>, factor: u32) -> Self {
+        let image_path = image_path.into();
+        let output_format = image_path
+            .extension()
+            .and_then(|value| value.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_else(|| "png".to_string());
+        Self {
+            run_dir: run_dir.into(),
+            image_path,
+            factor,
+            output_format,
+            provider_options: Map::new(),
+        }
+    }
+
+    pub(crate) fn output_path(&self, stamp: u128, idx: usize, ext: &str) -> PathBuf {
+        self.run_dir
+            .join(format!("artifact-{}-{:02}-upscaled.{}", stamp, idx, ext))
+    }
+}
+
+/// The directory an upscale writes into before its version exists. Dropped
+/// while still empty, because the upscale failed, it is removed again.
+pub(crate) struct PendingVersionDir {
+    pub(crate) path: PathBuf,
+    created: bool,
+}
+
+impl PendingVersionDir {
+    pub(crate) fn create(path: PathBuf) -> Result<Self> {
+        let created = !path.exists();
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Self { path, created })
+    }
+}
+
+impl Drop for PendingVersionDir {
+    fn drop(&mut self) {
+        // `remove_dir` refuses non-empty directories, so a directory the
+        // upscale wrote into stays.
+        if self.created {
+            let _ = std::fs::remove_dir(&self.path);
+        }
+    }
+}
+
+pub(crate) fn validate_upscale_factor(factor: u32) -> Result<u32> {
+    if !(UPSCALE_FACTOR_MIN..=UPSCALE_FACTOR_MAX).contains(&factor) {
+        bail!(
+            "upscale factor must be between {} and {} (got {})",
+            UPSCALE_FACTOR_MIN,
+            UPSCALE_FACTOR_MAX,
+            factor
+        );
+    }
+    Ok(factor)
+}
+
+/// Resamples the source image with a Lanczos3 filter when no provider
+/// upscaler is configured (or the configured one fails).
+pub(crate) fn upscale_local(request: &UpscaleRequest) -> Result<ProviderGenerateResponse> {
+    let source = image::open(&request.image_path)
+        .with_context(|| format!("failed to open {}", request.image_path.display()))?;
+    let width = source.width().saturating_mul(request.factor).max(1);
+    let height = source.height().saturating_mul(request.factor).max(1);
+    let resized = source.resize_exact(width, height, FilterType::Lanczos3);
+    let ext = normalize_output_extension(&request.output_format);
+    let image_path = request.output_path(timestamp_millis(), 0, ext);
+    resized
+        .save(&image_path)
+        .with_context(|| format!("failed to write {}", image_path.display()))?;
+
+    Ok(ProviderGenerateResponse {
+        provider_request: map_object(json!({
+            "endpoint": LOCAL_UPSCALE_BACKEND,
+            "payload": {
+                "image_path": request.image_path.to_string_lossy().to_string(),
+                "factor": request.factor,
+                "filter": "lanczos3",
+            }
+        })),
+        provider_response: map_object(json!({
+            "status": "ok",
+            "width": width,
+            "height": height,
+        })),
+        warnings: Vec::new(),
+        results: vec![ProviderImageResult {
+            image_path,
+            width,
+            height,
+            seed: None,
+            sha256: None,
+        }],
+    })
+}
+
+pub(crate) fn image_dims_or(path: &Path, fallback: (u32, u32)) -> (u32, u32) {
+    image::image_dimensions(path).unwrap_or(fallback)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::{upscale_local, validate_upscale_factor, UpscaleRequest};
+
+    #[test]
+    fn local_upscale_multiplies_dimensions() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let source = temp.path().join("source.png");
+        RgbImage::from_pixel(8, 6, Rgb([10, 20, 30])).save(&source)?;
+
+        let request = UpscaleRequest::new(temp.path(), &source, 3);
+        let response = upscale_local(&request)?;
+        let result = &response.results[0];
+        assert_eq!((result.width, result.height), (24, 18));
+        assert_eq!(image::image_dimensions(&result.image_path)?, (24, 18));
+        Ok(())
+    }
+
+    #[test]
+    fn upscale_factor_is_bounded() {
+        assert!(validate_upscale_factor(1).is_err());
+        assert!(validate_upscale_factor(2).is_ok());
+        assert!(validate_upscale_factor(8).is_ok());
+        assert!(validate_upscale_factor(9).is_err());
+    }
+}
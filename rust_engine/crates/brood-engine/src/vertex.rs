@@ -0,0 +1,308 @@
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::{anyhow, Context, Result};
+use google_cloud_auth::credentials::CredentialsFile;
+use google_cloud_auth::project::{create_token_source_from_project, project, Config, Project};
+use google_cloud_auth::token_source::TokenSource;
+use reqwest::blocking::RequestBuilder;
+use tokio::runtime::Runtime;
+
+use super::non_empty_env;
+
+/// Google Cloud project for Vertex AI. Setting it routes Gemini and Imagen
+/// through Vertex, even when an API key is set.
+pub const VERTEX_PROJECT_ENV: &str = "BROOD_VERTEX_PROJECT";
+/// Vertex AI region (default `us-central1`; `global` uses the global host).
+pub const VERTEX_LOCATION_ENV: &str = "BROOD_VERTEX_LOCATION";
+/// Overrides the Vertex AI base URL (`https://<location>-aiplatform.googleapis.com/v1`).
+pub const VERTEX_API_BASE_ENV: &str = "BROOD_VERTEX_API_BASE";
+/// Service account, authorized-user or external-account (workload identity
+/// federation) file, as for every Google client.
+pub const GOOGLE_CREDENTIALS_ENV: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+const DEFAULT_LOCATION: &str = "us-central1";
+const CLOUD_PLATFORM_SCOPES: &[&str] = &["https://www.googleapis.com/auth/cloud-platform"];
+
+/// Vertex AI access for Gemini and Imagen: OAuth tokens from Google's
+/// application default credentials instead of an API key.
+pub(crate) struct VertexAuth {
+    project: String,
+    location: String,
+    api_base: String,
+    /// The credentials file; `None` lets application default credentials
+    /// find one, or fall back to the metadata server on Google Cloud.
+    credentials_path: Option<PathBuf>,
+    /// Drives the async token sources. Only `None` once dropped.
+    runtime: Option<Runtime>,
+    /// Caches the token and refreshes it shortly before it expires.
+    token_source: Mutex<Option<Box<dyn TokenSource>>>,
+}
+
+impl VertexAuth {
+    pub(crate) fn new(
+        project: String,
+        location: &str,
+        api_base: Option<String>,
+        credentials_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let api_base = api_base.unwrap_or_else(|| match location {
+            "global" => "https://aiplatform.googleapis.com/v1".to_string(),
+            region => format!("https://{region}-aiplatform.googleapis.com/v1"),
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("failed to start the Vertex auth runtime")?;
+        Ok(Self {
+            project,
+            location: location.to_string(),
+            api_base: api_base.trim_end_matches('/').to_string(),
+            credentials_path,
+            runtime: Some(runtime),
+            token_source: Mutex::new(None),
+        })
+    }
+
+    /// Vertex settings from the environment: `None` unless
+    /// [`VERTEX_PROJECT_ENV`] is set.
+    pub(crate) fn from_env() -> Result<Option<Self>> {
+        Self::from_vars(non_empty_env)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Option<Self>> {
+        let Some(project) = var(VERTEX_PROJECT_ENV) else {
+            return Ok(None);
+        };
+        let location = var(VERTEX_LOCATION_ENV).unwrap_or_else(|| DEFAULT_LOCATION.to_string());
+        Self::new(
+            project,
+            &location,
+            var(VERTEX_API_BASE_ENV),
+            var(GOOGLE_CREDENTIALS_ENV).map(PathBuf::from),
+        )
+        .map(Some)
+    }
+
+    /// Bearer token for the cloud-platform scope.
+    fn access_token(&self) -> Result<String> {
+        let runtime = self
+            .runtime
+            .as_ref()
+            .ok_or_else(|| anyhow!("Vertex auth runtime already shut down"))?;
+        let mut source = self
+            .token_source
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        runtime.block_on(async {
+            let source = match &mut *source {
+                Some(source) => source,
+                empty => empty.insert(self.token_source().await?),
+            };
+            let token = source
+                .token()
+                .await
+                .context("Google OAuth token request failed")?;
+            Ok(token.access_token)
+        })
+    }
+
+    async fn token_source(&self) -> Result<Box<dyn TokenSource>> {
+        let project = match &self.credentials_path {
+            Some(path) => Project::FromFile(Box::new(
+                CredentialsFile::new_from_file(path.to_string_lossy().into_owned())
+                    .await
+                    .with_context(|| {
+                        format!("failed to read Google credentials {}", path.display())
+                    })?,
+            )),
+            None => project().await.with_context(|| {
+                format!(
+                    "no Google credentials found: set {GOOGLE_CREDENTIALS_ENV}, run `gcloud auth application-default login`, or run on Google Cloud"
+                )
+            })?,
+        };
+        create_token_source_from_project(
+            &project,
+            Config::default().with_scopes(CLOUD_PLATFORM_SCOPES),
+        )
+        .await
+        .context("Google OAuth token request failed")
+    }
+}
+
+impl Drop for VertexAuth {
+    fn drop(&mut self) {
+        // Providers can be dropped on async threads, where dropping a
+        // runtime in place panics.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// How a Gemini or Imagen request authenticates.
+pub(crate) enum GoogleAuth<'a> {
+    ApiKey(String),
+    Vertex(&'a VertexAuth),
+}
+
+impl<'a> GoogleAuth<'a> {
+    /// Vertex when [`VERTEX_PROJECT_ENV`] configured it, else the API key.
+    pub(crate) fn select(vertex: Option<&'a VertexAuth>, api_key: Option<String>) -> Option<Self> {
+        match (vertex, api_key) {
+            (Some(vertex), _) => Some(Self::Vertex(vertex)),
+            (None, Some(api_key)) => Some(Self::ApiKey(api_key)),
+            (None, None) => None,
+        }
+    }
+
+    /// `api_key` or `vertex`, for receipts.
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::ApiKey(_) => "api_key",
+            Self::Vertex(_) => "vertex",
+        }
+    }
+
+    /// `model:method` under `api_base`, or the Vertex publisher model path.
+    pub(crate) fn model_endpoint(&self, api_base: &str, model: &str, method: &str) -> String {
+        let model = model.trim().trim_start_matches("models/");
+        match self {
+            Self::ApiKey(_) => format!("{api_base}/models/{model}:{method}"),
+            Self::Vertex(vertex) => format!(
+                "{}/projects/{}/locations/{}/publishers/google/models/{model}:{method}",
+                vertex.api_base, vertex.project, vertex.location
+            ),
+        }
+    }
+
+    pub(crate) fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        match self {
+            Self::ApiKey(api_key) => Ok(request.header("x-goog-api-key", api_key)),
+            Self::Vertex(vertex) => Ok(request.bearer_auth(vertex.access_token()?)),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine as _;
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use rsa::rand_core::OsRng;
+    use rsa::RsaPrivateKey;
+    use serde_json::{json, Value};
+
+    use super::{GoogleAuth, VertexAuth, GOOGLE_CREDENTIALS_ENV, VERTEX_PROJECT_ENV};
+    use crate::test_support::{MockResponse, MockServer};
+
+    /// A service account key file whose token endpoint is `token_uri`, with
+    /// a key generated for the test.
+    pub(crate) fn service_account_file(dir: &Path, token_uri: &str) -> anyhow::Result<PathBuf> {
+        let key = RsaPrivateKey::new(&mut OsRng, 2048)?;
+        let path = dir.join("service-account.json");
+        std::fs::write(
+            &path,
+            json!({
+                "type": "service_account",
+                "project_id": "studio-prod",
+                "client_email": "brood@studio-prod.iam.gserviceaccount.com",
+                "private_key_id": "test-key",
+                "private_key": key.to_pkcs8_pem(LineEnding::LF)?.as_str(),
+                "token_uri": token_uri,
+            })
+            .to_string(),
+        )?;
+        Ok(path)
+    }
+
+    #[test]
+    fn service_accounts_exchange_a_signed_assertion_once() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server.mock(
+            "POST",
+            "/token",
+            MockResponse::json(
+                200,
+                json!({"access_token": "ya29.test", "token_type": "Bearer", "expires_in": 3599}),
+            ),
+        );
+        let temp = tempfile::tempdir()?;
+        let credentials = service_account_file(temp.path(), &server.url_for("token"))?;
+        let vertex = VertexAuth::new(
+            "studio-prod".to_string(),
+            "europe-west4",
+            None,
+            Some(credentials),
+        )?;
+        // A configured project wins over an API key.
+        let auth = GoogleAuth::select(Some(&vertex), Some("key".to_string()))
+            .ok_or_else(|| anyhow::anyhow!("no auth"))?;
+        assert_eq!(auth.label(), "vertex");
+        assert_eq!(
+            auth.model_endpoint("unused", "models/imagen-4.0-generate-001", "predict"),
+            "https://europe-west4-aiplatform.googleapis.com/v1/projects/studio-prod/locations/europe-west4/publishers/google/models/imagen-4.0-generate-001:predict"
+        );
+        assert_eq!(vertex.access_token()?, "ya29.test");
+        assert_eq!(vertex.access_token()?, "ya29.test");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let form = requests[0].body_text();
+        assert!(form.contains("grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Ajwt-bearer"));
+        let assertion = form
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("assertion="))
+            .unwrap_or_default();
+        let parts: Vec<&str> = assertion.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let claims: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[1])?)?;
+        assert_eq!(
+            claims["iss"],
+            json!("brood@studio-prod.iam.gserviceaccount.com")
+        );
+        assert_eq!(claims["aud"], json!(server.url_for("token")));
+
+        let missing = VertexAuth::new(
+            "other".to_string(),
+            "global",
+            None,
+            Some(temp.path().join("missing.json")),
+        )?;
+        let auth =
+            GoogleAuth::select(Some(&missing), None).ok_or_else(|| anyhow::anyhow!("no auth"))?;
+        assert_eq!(
+            auth.model_endpoint("unused", "gemini-2.5-flash-image", "generateContent"),
+            "https://aiplatform.googleapis.com/v1/projects/other/locations/global/publishers/google/models/gemini-2.5-flash-image:generateContent"
+        );
+        assert!(missing.access_token().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn only_a_vertex_project_turns_vertex_on() -> anyhow::Result<()> {
+        let vars = |pairs: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            move |key: &str| vars.get(key).cloned()
+        };
+        let credentials_only = vars(&[(GOOGLE_CREDENTIALS_ENV, "/keys/sa.json")]);
+        assert!(VertexAuth::from_vars(credentials_only)?.is_none());
+        assert_eq!(
+            GoogleAuth::select(None, Some("key".to_string())).map(|auth| auth.label()),
+            Some("api_key")
+        );
+
+        let project = vars(&[(VERTEX_PROJECT_ENV, "studio-prod")]);
+        let vertex = VertexAuth::from_vars(project)?.ok_or_else(|| anyhow::anyhow!("no vertex"))?;
+        assert_eq!(vertex.project, "studio-prod");
+        assert_eq!(vertex.location, "us-central1");
+        assert_eq!(vertex.credentials_path, None);
+        Ok(())
+    }
+}
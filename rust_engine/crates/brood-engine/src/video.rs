@@ -0,0 +1,645 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use reqwest::blocking::Client as HttpClient;
+use reqwest::header::AUTHORIZATION;
+use serde_json::{json, Map, Value};
+
+use super::credentials::resolve_api_key;
+use super::download::Download;
+use super::replay::ReplaySend;
+use super::telemetry::PollSpan;
+use super::timeouts::{scoped_http, timeout_option, ScopedTimeout, TimeoutKind};
+use super::{
+    map_object, push_unique_warning, response_json_or_error, timestamp_millis, DryrunProvider,
+    FalProvider, ProviderConfig, ProviderSettings, ReplicateProvider,
+};
+
+pub const DEFAULT_VIDEO_DURATION_S: f64 = 5.0;
+
+const RUNWAY_API_VERSION: &str = "2024-11-06";
+
+#[derive(Debug, Clone)]
+pub struct VideoGenerateRequest {
+    pub run_dir: PathBuf,
+    pub prompt: String,
+    pub init_image: Option<PathBuf>,
+    pub duration_s: f64,
+    pub aspect_ratio: String,
+    pub seed: Option<i64>,
+    pub model: Option<String>,
+    pub provider_options: Map<String, Value>,
+}
+
+impl VideoGenerateRequest {
+    pub(crate) fn output_path(&self, stamp: u128, idx: usize) -> PathBuf {
+        self.run_dir.join(format!("video-{}-{:02}.mp4", stamp, idx))
+    }
+
+    fn option_str(&self, key: &str) -> Option<String> {
+        self.provider_options
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    }
+
+    fn poll_timeout_seconds(&self) -> f64 {
+        timeout_option(&self.provider_options, "poll_timeout", TimeoutKind::Poll)
+            .and_then(|value| value.as_f64())
+            .unwrap_or(600.0)
+            .clamp(30.0, 1800.0)
+    }
+
+    /// Videos are large; without a download limit they get the poll budget.
+    fn download_timeout_seconds(&self) -> f64 {
+        timeout_option(
+            &self.provider_options,
+            "download_timeout",
+            TimeoutKind::Download,
+        )
+        .and_then(|value| value.as_f64())
+        .unwrap_or_else(|| self.poll_timeout_seconds())
+    }
+
+    fn poll_interval_seconds(&self) -> f64 {
+        self.provider_options
+            .get("poll_interval")
+            .and_then(Value::as_f64)
+            .unwrap_or(3.0)
+            .clamp(0.5, 30.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderVideoResult {
+    pub video_path: PathBuf,
+    pub duration_s: Option<f64>,
+    /// Hex sha256 of `video_path`, taken while downloading it.
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VideoGenerateResponse {
+    pub provider_request: Map<String, Value>,
+    pub provider_response: Map<String, Value>,
+    pub warnings: Vec<Warning>,
+    pub results: Vec<ProviderVideoResult>,
+}
+
+pub trait VideoProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn generate_video(&self, request: &VideoGenerateRequest) -> Result<VideoGenerateResponse>;
+}
+
+#[derive(Default)]
+pub struct VideoProviderRegistry {
+    providers: BTreeMap<String, Box<dyn VideoProvider>>,
+}
+
+impl VideoProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<P: VideoProvider + 'static>(&mut self, provider: P) {
+        self.providers
+            .insert(provider.name().to_string(), Box::new(provider));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn VideoProvider> {
+        self.providers.get(name).map(|provider| provider.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.providers.keys().cloned().collect()
+    }
+}
+
+pub(crate) fn default_video_provider_registry(
+    config: &ProviderConfig,
+) -> Result<VideoProviderRegistry> {
+    let mut providers = VideoProviderRegistry::new();
+    providers.register(DryrunProvider);
+    providers.register(ReplicateProvider::new(&config.settings("replicate"))?);
+    providers.register(FalProvider::new(&config.settings("fal"))?);
+    providers.register(RunwayProvider::new(&config.settings("runway"))?);
+    Ok(providers)
+}
+
+/// Snaps a requested clip length to the nearest duration a model accepts.
+fn snap_duration(requested: f64, allowed: &[u32], warnings: &mut Vec<Warning>) -> u32 {
+    let snapped = allowed
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            (*a as f64 - requested)
+                .abs()
+                .total_cmp(&(*b as f64 - requested).abs())
+        })
+        .unwrap_or(5);
+    if (snapped as f64 - requested).abs() > f64::EPSILON {
+        push_unique_warning(
+            warnings,
+            Warning::new(
+                WarningCode::ValueReplaced,
+                format!("Video duration {requested}s not supported; using {snapped}s."),
+            )
+            .parameter("duration")
+            .requested(format!("{requested}s"))
+            .applied(format!("{snapped}s")),
+        );
+    }
+    snapped
+}
+
+fn image_data_url(path: &Path) -> Result<String> {
+    FalProvider::path_to_data_url(path)
+}
+
+fn download_video(http: &HttpClient, url: &str, label: &str, timeout_s: f64) -> Result<Download> {
+    let response = http
+        .get(url)
+        .timeout(Duration::from_secs_f64(timeout_s))
+        .send_replayable()
+        .with_context(|| format!("failed downloading {label} video ({url})"))?;
+    Download::start(response, &format!("{label} video"))
+}
+
+/// Downloads each URL into the run dir and returns the written results.
+fn write_video_results(
+    http: &HttpClient,
+    request: &VideoGenerateRequest,
+    urls: &[String],
+    label: &str,
+    duration_s: Option<f64>,
+) -> Result<Vec<ProviderVideoResult>> {
+    let stamp = timestamp_millis();
+    let mut results = Vec::new();
+    for (idx, url) in urls.iter().enumerate() {
+        let download = download_video(http, url, label, request.download_timeout_seconds())?;
+        let video_path = request.output_path(stamp, idx);
+        let saved = download.save(&video_path)?;
+        results.push(ProviderVideoResult {
+            video_path,
+            duration_s,
+            sha256: Some(saved.sha256),
+        });
+    }
+    Ok(results)
+}
+
+/// Replaces inline image data in a payload with the source path so receipts
+/// stay small and readable.
+fn manifest_payload(payload: &Map<String, Value>, key: &str, init_image: &Path) -> Value {
+    let mut manifest = payload.clone();
+    if manifest.contains_key(key) {
+        manifest.insert(
+            key.to_string(),
+            Value::String(init_image.to_string_lossy().to_string()),
+        );
+    }
+    Value::Object(manifest)
+}
+
+impl VideoProvider for DryrunProvider {
+    fn name(&self) -> &str {
+        "dryrun"
+    }
+
+    fn generate_video(&self, request: &VideoGenerateRequest) -> Result<VideoGenerateResponse> {
+        // Header-only MP4 (a lone `ftyp` box): enough for downstream tooling
+        // to recognise the container without shipping an encoder.
+        let mut bytes = vec![0x00, 0x00, 0x00, 0x18];
+        bytes.extend_from_slice(b"ftypisom");
+        bytes.extend_from_slice(&[0x00, 0x00, 0x02, 0x00]);
+        bytes.extend_from_slice(b"isommp41");
+        let video_path = request.output_path(timestamp_millis(), 0);
+        fs::write(&video_path, bytes)
+            .with_context(|| format!("failed to write {}", video_path.display()))?;
+        Ok(VideoGenerateResponse {
+            provider_request: map_object(json!({
+                "endpoint": "dryrun",
+                "payload": {
+                    "prompt": request.prompt,
+                    "duration_s": request.duration_s,
+                },
+            })),
+            provider_response: map_object(json!({ "status": "ok" })),
+            warnings: Vec::new(),
+            results: vec![ProviderVideoResult {
+                video_path,
+                duration_s: Some(request.duration_s),
+                sha256: None,
+            }],
+        })
+    }
+}
+
+impl VideoProvider for ReplicateProvider {
+    fn name(&self) -> &str {
+        "replicate"
+    }
+
+    fn generate_video(&self, request: &VideoGenerateRequest) -> Result<VideoGenerateResponse> {
+        let Some(api_key) = self.api_key() else {
+            bail!("REPLICATE_API_TOKEN not set");
+        };
+        let mut warnings = Vec::new();
+        let model = request
+            .model
+            .clone()
+            .or_else(|| request.option_str("video_model"))
+            .unwrap_or_else(|| {
+                if request.init_image.is_some() {
+                    "wan-video/wan-2.1-i2v-480p".to_string()
+                } else {
+                    "wan-video/wan-2.1-t2v-480p".to_string()
+                }
+            });
+        let is_kling = model.to_ascii_lowercase().contains("kling");
+        let image_key = if is_kling { "start_image" } else { "image" };
+
+        let mut input = map_object(json!({
+            "prompt": request.prompt,
+            "aspect_ratio": request.aspect_ratio,
+        }));
+        let mut duration_s = None;
+        if is_kling {
+            let duration = snap_duration(request.duration_s, &[5, 10], &mut warnings);
+            input.insert("duration".to_string(), Value::Number(duration.into()));
+            duration_s = Some(duration as f64);
+        } else if (request.duration_s - DEFAULT_VIDEO_DURATION_S).abs() > f64::EPSILON {
+            push_unique_warning(
+                &mut warnings,
+                Warning::new(
+                    WarningCode::ValueDropped,
+                    format!(
+                        "Replicate model '{model}' uses a fixed clip length; duration ignored."
+                    ),
+                )
+                .parameter("duration")
+                .requested(format!("{}s", request.duration_s)),
+            );
+        }
+        if let Some(seed) = request.seed {
+            input.insert("seed".to_string(), Value::Number(seed.into()));
+        }
+        if let Some(path) = request.init_image.as_ref() {
+            input.insert(image_key.to_string(), Value::String(image_data_url(path)?));
+        }
+        for (key, value) in &request.provider_options {
+            if matches!(
+                key.as_str(),
+                "video_model" | "poll_interval" | "poll_timeout"
+            ) || input.contains_key(key)
+            {
+                continue;
+            }
+            input.insert(key.clone(), value.clone());
+        }
+
+        let endpoint = self.predictions_endpoint();
+        let payload = map_object(json!({ "model": model, "input": input }));
+        let prediction = self.run_prediction(
+            &endpoint,
+            &api_key,
+            &payload,
+            request.poll_interval_seconds(),
+            request.poll_timeout_seconds(),
+        )?;
+        let mut urls = Vec::new();
+        if let Some(output) = prediction.get("output") {
+            Self::extract_output_urls(output, &mut urls);
+        }
+        if urls.is_empty() {
+            bail!("Replicate video prediction returned no output URLs");
+        }
+        let results = write_video_results(
+            &scoped_http(&self.http),
+            request,
+            &urls,
+            "Replicate",
+            duration_s,
+        )?;
+
+        let manifest_input = match request.init_image.as_ref() {
+            Some(path) => manifest_payload(&input, image_key, path),
+            None => Value::Object(input),
+        };
+        Ok(VideoGenerateResponse {
+            provider_request: map_object(json!({
+                "endpoint": endpoint,
+                "payload": { "model": model, "input": manifest_input },
+            })),
+            provider_response: map_object(json!({
+                "prediction_ids": prediction.get("id").cloned().into_iter().collect::<Vec<Value>>(),
+                "status": prediction.get("status").cloned().unwrap_or(Value::Null),
+            })),
+            warnings,
+            results,
+        })
+    }
+}
+
+impl VideoProvider for FalProvider {
+    fn name(&self) -> &str {
+        "fal"
+    }
+
+    fn generate_video(&self, request: &VideoGenerateRequest) -> Result<VideoGenerateResponse> {
+        let Some(api_key) = self.api_key() else {
+            bail!("FAL_KEY (or FAL_API_KEY) not set");
+        };
+        let mut warnings = Vec::new();
+        let raw_endpoint = request
+            .model
+            .clone()
+            .or_else(|| request.option_str("endpoint"))
+            .unwrap_or_else(|| {
+                if request.init_image.is_some() {
+                    "fal-ai/kling-video/v1.6/standard/image-to-video".to_string()
+                } else {
+                    "fal-ai/kling-video/v1.6/standard/text-to-video".to_string()
+                }
+            });
+        let endpoint =
+            if raw_endpoint.starts_with("http://") || raw_endpoint.starts_with("https://") {
+                raw_endpoint
+            } else {
+                format!("{}/{}", self.api_base, raw_endpoint.trim_start_matches('/'))
+            };
+
+        let duration = snap_duration(request.duration_s, &[5, 10], &mut warnings);
+        let mut payload = map_object(json!({
+            "prompt": request.prompt,
+            "duration": duration.to_string(),
+            "aspect_ratio": request.aspect_ratio,
+        }));
+        if let Some(seed) = request.seed {
+            payload.insert("seed".to_string(), Value::Number(seed.into()));
+        }
+        if let Some(path) = request.init_image.as_ref() {
+            payload.insert(
+                "image_url".to_string(),
+                Value::String(image_data_url(path)?),
+            );
+        }
+        for (key, value) in &request.provider_options {
+            if matches!(key.as_str(), "endpoint" | "poll_interval" | "poll_timeout")
+                || payload.contains_key(key)
+            {
+                continue;
+            }
+            payload.insert(key.clone(), value.clone());
+        }
+
+        let response = scoped_http(&self.http)
+            .post(&endpoint)
+            .timeout(Duration::from_secs_f64(request.poll_timeout_seconds()))
+            .header(AUTHORIZATION, format!("Key {api_key}"))
+            .json(&Value::Object(payload.clone()))
+            .send_replayable()
+            .with_context(|| format!("Fal video request failed ({endpoint})"))?;
+        let response_payload = response_json_or_error("Fal", response)?;
+        let mut urls = Vec::new();
+        Self::extract_urls(&response_payload, &mut urls);
+        if urls.is_empty() {
+            bail!("Fal video response returned no URLs");
+        }
+        let results = write_video_results(
+            &scoped_http(&self.http),
+            request,
+            &urls,
+            "Fal",
+            Some(duration as f64),
+        )?;
+
+        let manifest = match request.init_image.as_ref() {
+            Some(path) => manifest_payload(&payload, "image_url", path),
+            None => Value::Object(payload),
+        };
+        Ok(VideoGenerateResponse {
+            provider_request: map_object(json!({
+                "endpoint": endpoint,
+                "payload": manifest,
+            })),
+            provider_response: map_object(json!({
+                "request_id": response_payload.get("request_id").cloned().unwrap_or(Value::Null),
+                "status": "ok",
+            })),
+            warnings,
+            results,
+        })
+    }
+}
+
+pub(crate) struct RunwayProvider {
+    api_base: String,
+    api_key_envs: Vec<String>,
+    http: HttpClient,
+}
+
+impl RunwayProvider {
+    pub(crate) fn new(settings: &ProviderSettings) -> Result<Self> {
+        Ok(Self {
+            api_base: settings.base_url.clone(),
+            api_key_envs: settings.api_key_envs.clone(),
+            http: settings.http_client()?,
+        })
+    }
+
+    fn api_key(&self) -> Option<String> {
+        resolve_api_key("runway", &self.api_key_envs)
+    }
+
+    fn ratio_for_aspect(aspect_ratio: &str, warnings: &mut Vec<Warning>) -> &'static str {
+        match aspect_ratio.trim() {
+            "16:9" => "1280:720",
+            "9:16" => "720:1280",
+            "1:1" => "960:960",
+            "4:3" => "1104:832",
+            "3:4" => "832:1104",
+            "21:9" => "1584:672",
+            other => {
+                push_unique_warning(
+                    warnings,
+                    Warning::new(
+                        WarningCode::ValueReplaced,
+                        format!("Runway does not support aspect ratio '{other}'; using 16:9."),
+                    )
+                    .parameter("aspect_ratio")
+                    .requested(other)
+                    .applied("16:9"),
+                );
+                "1280:720"
+            }
+        }
+    }
+
+    fn poll_task(
+        &self,
+        task_id: &str,
+        api_key: &str,
+        request: &VideoGenerateRequest,
+    ) -> Result<Value> {
+        let poll_url = format!("{}/tasks/{}", self.api_base, task_id);
+        let started = Instant::now();
+        let mut poll_span = PollSpan::enter("runway");
+        loop {
+            poll_span.poll();
+            let response = scoped_http(&self.http)
+                .get(&poll_url)
+                .bearer_auth(api_key)
+                .header("X-Runway-Version", RUNWAY_API_VERSION)
+                .scoped_timeout(TimeoutKind::Request)
+                .send_replayable()
+                .with_context(|| format!("Runway poll request failed ({poll_url})"))?;
+            let payload = response_json_or_error("Runway poll", response)?;
+            let status = payload
+                .get("status")
+                .and_then(Value::as_str)
+                .map(|value| value.to_ascii_uppercase())
+                .unwrap_or_default();
+            poll_span.status(status.as_str());
+            if status == "SUCCEEDED" {
+                return Ok(payload);
+            }
+            if matches!(status.as_str(), "FAILED" | "CANCELLED") {
+                bail!("Runway task failed: {}", payload);
+            }
+            if started.elapsed().as_secs_f64() >= request.poll_timeout_seconds() {
+                bail!(
+                    "Runway polling timed out after {:.1}s",
+                    request.poll_timeout_seconds()
+                );
+            }
+            thread::sleep(Duration::from_secs_f64(request.poll_interval_seconds()));
+        }
+    }
+}
+
+impl VideoProvider for RunwayProvider {
+    fn name(&self) -> &str {
+        "runway"
+    }
+
+    fn generate_video(&self, request: &VideoGenerateRequest) -> Result<VideoGenerateResponse> {
+        let Some(api_key) = self.api_key() else {
+            bail!("RUNWAYML_API_SECRET not set");
+        };
+        let Some(init_image) = request.init_image.as_ref() else {
+            bail!("Runway video generation requires an init image");
+        };
+        let mut warnings = Vec::new();
+        let duration = snap_duration(request.duration_s, &[5, 10], &mut warnings);
+        let mut payload = map_object(json!({
+            "model": request.model.clone().unwrap_or_else(|| "gen4_turbo".to_string()),
+            "promptImage": image_data_url(init_image)?,
+            "promptText": request.prompt,
+            "ratio": Self::ratio_for_aspect(&request.aspect_ratio, &mut warnings),
+            "duration": duration,
+        }));
+        if let Some(seed) = request.seed {
+            payload.insert("seed".to_string(), Value::Number(seed.into()));
+        }
+
+        let endpoint = format!("{}/image_to_video", self.api_base);
+        let response = scoped_http(&self.http)
+            .post(&endpoint)
+            .bearer_auth(&api_key)
+            .header("X-Runway-Version", RUNWAY_API_VERSION)
+            .json(&Value::Object(payload.clone()))
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("Runway request failed ({endpoint})"))?;
+        let created = response_json_or_error("Runway", response)?;
+        let task_id = created
+            .get("id")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Runway response missing task id"))?
+            .to_string();
+        let task = self.poll_task(&task_id, &api_key, request)?;
+        let urls: Vec<String> = task
+            .get("output")
+            .and_then(Value::as_array)
+            .map(|rows| {
+                rows.iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if urls.is_empty() {
+            bail!("Runway task returned no output URLs");
+        }
+        let results = write_video_results(
+            &scoped_http(&self.http),
+            request,
+            &urls,
+            "Runway",
+            Some(duration as f64),
+        )?;
+
+        Ok(VideoGenerateResponse {
+            provider_request: map_object(json!({
+                "endpoint": endpoint,
+                "payload": manifest_payload(&payload, "promptImage", init_image),
+            })),
+            provider_response: map_object(json!({
+                "task_id": task_id,
+                "status": task.get("status").cloned().unwrap_or(Value::Null),
+            })),
+            warnings,
+            results,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::{json, Map};
+
+    use super::{manifest_payload, snap_duration, RunwayProvider};
+
+    #[test]
+    fn durations_snap_to_supported_lengths_with_warning() {
+        let mut warnings = Vec::new();
+        assert_eq!(snap_duration(5.0, &[5, 10], &mut warnings), 5);
+        assert!(warnings.is_empty());
+        assert_eq!(snap_duration(8.0, &[5, 10], &mut warnings), 10);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn runway_ratio_and_manifest_hide_inline_images() {
+        let mut warnings = Vec::new();
+        assert_eq!(
+            RunwayProvider::ratio_for_aspect("9:16", &mut warnings),
+            "720:1280"
+        );
+        assert_eq!(
+            RunwayProvider::ratio_for_aspect("5:2", &mut warnings),
+            "1280:720"
+        );
+        assert_eq!(warnings.len(), 1);
+
+        let mut payload = Map::new();
+        payload.insert(
+            "promptImage".to_string(),
+            json!("data:image/png;base64,AAAA"),
+        );
+        let manifest = manifest_payload(&payload, "promptImage", Path::new("/tmp/in.png"));
+        assert_eq!(manifest["promptImage"], json!("/tmp/in.png"));
+    }
+}
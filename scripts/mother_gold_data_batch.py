@@ -303,6 +303,10 @@ def run_generation(
         "--events",
         str(events_path),
     ]
+    # A rerun of the same run key continues it; the engine refuses a
+    # non-empty run dir otherwise.
+    if events_path.exists() or (run_dir / "thread.json").exists():
+        cmd.append("--resume")
     if image_model.strip():
         cmd.extend(["--image-model", image_model.strip()])
 